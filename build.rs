@@ -1,4 +1,47 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Runs a command and returns the first line of its output, if it succeeded.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let line = stdout.lines().next()?.trim();
+    (!line.is_empty()).then(|| line.to_owned())
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=targets/x86_64.ld");
-    println!("cargo:rustc-link-arg=-Ttargets/x86_64.ld");
+
+    // Unit tests are linked as regular programs of the host.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        println!("cargo:rustc-link-arg=-Ttargets/x86_64.ld");
+    }
+
+    // The build ID of the kernel. See `src/build_id.rs`.
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let commit = command_output("git", &["rev-parse", "--short=12", "HEAD"]).unwrap_or_default();
+
+    // Reproducible builds set `SOURCE_DATE_EPOCH` to a fixed time.
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs())
+        });
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc_version = command_output(&rustc, &["--version"]).unwrap_or_default();
+
+    println!("cargo:rustc-env=FABRIC_BUILD_COMMIT={commit}");
+    println!("cargo:rustc-env=FABRIC_BUILD_TIMESTAMP={timestamp}");
+    println!("cargo:rustc-env=FABRIC_BUILD_RUSTC={rustc_version}");
 }
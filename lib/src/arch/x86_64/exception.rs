@@ -0,0 +1,261 @@
+//! Decoding of the error codes pushed by the CPU when it raises an exception.
+//!
+//! The kernel uses these types to describe the exceptions it reports, and they are shared with
+//! userspace so that the descriptions of the faults of a process can be interpreted the same way.
+
+use core::fmt;
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// The error code of a page fault.
+    ///
+    /// It is displayed as a description of the fault, such as `user write, not present`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct PageFaultError: u64 {
+        /// The fault was caused by a page-protection violation. Otherwise, the page was not
+        /// present.
+        const PRESENT = 1 << 0;
+        /// The fault was caused by a write. Otherwise, it was caused by a read or an instruction
+        /// fetch.
+        const WRITE = 1 << 1;
+        /// The access was performed in user mode.
+        const USER = 1 << 2;
+        /// A reserved bit was set in one of the paging-structure entries used to translate the
+        /// address.
+        const RESERVED = 1 << 3;
+        /// The fault was caused by an instruction fetch.
+        const INSTRUCTION_FETCH = 1 << 4;
+        /// The access violated the protection key of the page.
+        const PROTECTION_KEY = 1 << 5;
+        /// The fault was caused by a shadow-stack access.
+        const SHADOW_STACK = 1 << 6;
+        /// The fault was caused by a violation of the SGX access-control requirements.
+        const SGX = 1 << 15;
+    }
+}
+
+/// The kind of memory access that caused a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl PageFaultError {
+    /// Returns the kind of access that caused the fault.
+    #[inline]
+    pub const fn access(self) -> Access {
+        if self.contains(Self::INSTRUCTION_FETCH) {
+            Access::Execute
+        } else if self.contains(Self::WRITE) {
+            Access::Write
+        } else {
+            Access::Read
+        }
+    }
+
+    /// Returns whether the fault was caused by a page that is not present, rather than by a
+    /// protection violation.
+    #[inline]
+    pub const fn is_not_present(self) -> bool {
+        !self.contains(Self::PRESENT)
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Execute => "execute",
+        })
+    }
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self.contains(Self::USER) {
+            true => "user",
+            false => "kernel",
+        };
+
+        let cause = if self.contains(Self::RESERVED) {
+            "reserved bit set"
+        } else if self.contains(Self::PROTECTION_KEY) {
+            "protection key violation"
+        } else if self.contains(Self::SHADOW_STACK) {
+            "shadow stack violation"
+        } else if self.is_not_present() {
+            "not present"
+        } else {
+            "protection violation"
+        };
+
+        write!(f, "{} {}, {}", mode, self.access(), cause)
+    }
+}
+
+/// The descriptor table referenced by a [`SelectorError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+impl fmt::Display for DescriptorTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gdt => "GDT",
+            Self::Idt => "IDT",
+            Self::Ldt => "LDT",
+        })
+    }
+}
+
+/// The error code of the exceptions related to a segment selector or a gate: general protection
+/// faults, invalid TSS, segment not present and stack segment faults.
+///
+/// An error code of 0 indicates that the exception is not related to a particular selector.
+/// Otherwise, the error code is displayed as the descriptor it refers to, such as `GDT[5]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SelectorError(pub u64);
+
+impl SelectorError {
+    /// Set when the exception occurred during the delivery of an event external to the program,
+    /// such as an interrupt.
+    const EXTERNAL: u64 = 1 << 0;
+    /// Set when the index refers to a gate of the IDT.
+    const IDT: u64 = 1 << 1;
+    /// Set when the index refers to a descriptor of the LDT rather than the GDT, unless
+    /// [`Self::IDT`] is set.
+    const LDT: u64 = 1 << 2;
+
+    /// Returns whether the error code refers to a selector.
+    #[inline]
+    pub const fn has_selector(self) -> bool {
+        self.0 != 0
+    }
+
+    /// Returns whether the exception occurred during the delivery of an external event.
+    #[inline]
+    pub const fn is_external(self) -> bool {
+        self.0 & Self::EXTERNAL != 0
+    }
+
+    /// Returns the descriptor table that the selector refers to.
+    #[inline]
+    pub const fn table(self) -> DescriptorTable {
+        if self.0 & Self::IDT != 0 {
+            DescriptorTable::Idt
+        } else if self.0 & Self::LDT != 0 {
+            DescriptorTable::Ldt
+        } else {
+            DescriptorTable::Gdt
+        }
+    }
+
+    /// Returns the index of the descriptor within its table.
+    ///
+    /// For the IDT, this is the vector of the gate.
+    #[inline]
+    pub const fn index(self) -> u16 {
+        ((self.0 >> 3) & 0x1FFF) as u16
+    }
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.has_selector() {
+            return f.write_str("no selector");
+        }
+
+        write!(f, "{}[{}]", self.table(), self.index())?;
+
+        if self.is_external() {
+            f.write_str(", external")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn page_fault_access() {
+        let cases = [
+            (0b00000, Access::Read),
+            (0b00010, Access::Write),
+            (0b10000, Access::Execute),
+            // Instruction fetches never write, but the fetch takes precedence anyway.
+            (0b10010, Access::Execute),
+        ];
+
+        for (bits, access) in cases {
+            assert_eq!(
+                PageFaultError::from_bits_retain(bits).access(),
+                access,
+                "{bits:#b}"
+            );
+        }
+    }
+
+    #[test]
+    fn page_fault_display() {
+        let cases = [
+            (0b00000, "kernel read, not present"),
+            (0b00110, "user write, not present"),
+            (0b00101, "user read, protection violation"),
+            (0b10101, "user execute, protection violation"),
+            (0b01001, "kernel read, reserved bit set"),
+            (0b100111, "user write, protection key violation"),
+            (0b1000011, "kernel write, shadow stack violation"),
+        ];
+
+        for (bits, text) in cases {
+            let error = PageFaultError::from_bits_retain(bits);
+            assert_eq!(error.is_not_present(), bits & 1 == 0, "{bits:#b}");
+            assert_eq!(error.to_string(), text, "{bits:#b}");
+        }
+    }
+
+    #[test]
+    fn selector_decoding() {
+        let cases = [
+            (0x28, DescriptorTable::Gdt, 5, false),
+            (0x29, DescriptorTable::Gdt, 5, true),
+            (0x6A, DescriptorTable::Idt, 13, false),
+            // The LDT bit is ignored for gates of the IDT.
+            (0x6E, DescriptorTable::Idt, 13, false),
+            (0x0C, DescriptorTable::Ldt, 1, false),
+            (0xFFF8, DescriptorTable::Gdt, 0x1FFF, false),
+        ];
+
+        for (code, table, index, external) in cases {
+            let error = SelectorError(code);
+            assert!(error.has_selector());
+            assert_eq!(error.table(), table, "{code:#x}");
+            assert_eq!(error.index(), index, "{code:#x}");
+            assert_eq!(error.is_external(), external, "{code:#x}");
+        }
+    }
+
+    #[test]
+    fn selector_display() {
+        assert_eq!(SelectorError(0).to_string(), "no selector");
+        assert_eq!(SelectorError(0x28).to_string(), "GDT[5]");
+        assert_eq!(SelectorError(0x6B).to_string(), "IDT[13], external");
+        assert_eq!(SelectorError(0x0C).to_string(), "LDT[1]");
+    }
+}
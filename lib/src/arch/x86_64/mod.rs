@@ -2,15 +2,57 @@
 
 use bitflags::bitflags;
 
+use crate::ipc::Pod;
+use crate::layout::assert_layout;
+
+#[cfg(feature = "userland")]
+use core::sync::atomic::AtomicU32;
+
+#[cfg(feature = "userland")]
+use crate::event::EventKind;
 #[cfg(feature = "userland")]
-use crate::{ProcessId, SysResult};
+use crate::{PortId, ProcessId, SysResult};
 
 #[cfg(feature = "userland")]
 pub mod raw;
 
+pub mod exception;
 pub mod public;
 
+/// The version of the system call ABI described by this crate.
+///
+/// The kernel publishes the range of versions it implements in the public data area (see
+/// [`PublicData::is_abi_compatible`](public::PublicData::is_abi_compatible)), and the system
+/// calls it supports through [`kernel_info`].
+///
+/// # Stability
+///
+/// The ABI evolves without breaking existing binaries:
+///
+/// - The number of a system call never changes, and is never reused. New system calls are
+///   appended to [`Syscall`], which increments this version.
+///
+/// - The arguments and the behavior of an existing system call never change in incompatible
+///   ways. An incompatible change requires a new system call, whose name is the one of the
+///   original with a version suffix (such as `MapMemoryV2`). The wrapper of the original system
+///   call is then marked as `#[deprecated]` in favor of the new one, but the kernel keeps
+///   implementing it.
+///
+/// - The structures shared with the kernel only grow at their end, and their existing fields
+///   keep their offset.
+///
+/// Deprecated system calls are only removed from the kernel when [`MIN_ABI_VERSION`] is raised
+/// past the version that introduced their replacement.
+pub const ABI_VERSION: u32 = 15;
+
+/// The oldest version of the system call ABI implemented by a kernel built from this crate.
+///
+/// See [`ABI_VERSION`].
+pub const MIN_ABI_VERSION: u32 = 1;
+
 /// An enumeration of all valid system call numbers on **x86_64**.
+///
+/// Variants are never reordered or removed. See [`ABI_VERSION`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(usize)]
 pub enum Syscall {
@@ -19,6 +61,46 @@ pub enum Syscall {
     UnmapMemory,
     AcquireFramebuffer,
     ReleaseFramebuffer,
+    Suspend,
+    Resume,
+    QueryMapping,
+    RemapMemory,
+    Wait,
+    Wake,
+    CreatePort,
+    DestroyPort,
+    Send,
+    Receive,
+    WaitPort,
+    DebugLog,
+    SubscribeEvent,
+    AcquirePciDevice,
+    ReleasePciDevice,
+    MapDeviceMemory,
+    QueryAddressSpace,
+    QueryKernelStats,
+    SetPurgeable,
+    DebugSnapshot,
+    SetLogFilter,
+    InjectFault,
+    KernelInfo,
+    Spawn,
+    SetRestartable,
+    ReclaimEscrow,
+    BindIrq,
+    AckIrq,
+    SetIrqBudget,
+    CreateFramebufferRegion,
+    MapFramebufferRegion,
+    DestroyFramebufferRegion,
+    SetTickMode,
+    CacheControl,
+    WaitTimeout,
+    ReadInputEvents,
+    GetTime,
+    YieldTo,
+    Yield,
+    SleepNs,
 }
 
 bitflags! {
@@ -30,138 +112,512 @@ bitflags! {
         /// When this flag is set, the mapping is read-write. Otherwise, it is read-only.
         const WRITABLE = 1 << 0;
 
-        /// Whether the pages are executable.alloc
+        /// Whether the pages are executable.
         ///
         /// When this flag is set, the mapping can be executed. Otherwise, it is not executable and
         /// attempting to execute code in it will result in a page fault.
         const EXECUTABLE = 1 << 1;
+
+        /// Whether the mapping is a stack that grows on demand.
+        ///
+        /// When this flag is set, the kernel only reserves the requested range of the address
+        /// space. Pages are allocated when the process first accesses them, up to the size of the
+        /// mapping. The lowest page of the mapping is a guard page that is never allocated.
+        const STACK = 1 << 2;
+
+        /// Whether the pages are allocated on demand.
+        ///
+        /// When this flag is set, the kernel only reserves the requested range of the address
+        /// space. Every page is allocated, and zeroed, when the process first accesses it. This
+        /// makes large reservations cheap, at the cost of a page fault on the first access to
+        /// each page. Growing the mapping with [`remap_memory`] does not allocate the new pages
+        /// either.
+        ///
+        /// The pages are not reserved: accessing one of them when the system is out of memory
+        /// terminates the process. When the process passes a buffer that covers such pages to a
+        /// system call, the kernel allocates them before using the buffer, and the system call
+        /// fails with [`SysResult::OUT_OF_MEMORY`] if they cannot be allocated.
+        const LAZY = 1 << 3;
     }
 }
 
-/// Performs the `terminate` system call on the current process.
+bitflags! {
+    /// Flags used to control the behavior of the [`remap_memory`] system call.
+    #[derive(Debug, Clone, Copy)]
+    pub struct RemapFlags: usize {
+        /// Whether the mapping may be moved to another location when it cannot be grown in
+        /// place.
+        const MAY_MOVE = 1 << 0;
+    }
+}
+
+/// The kind of memory that backs a mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum MappingKind {
+    /// The memory was allocated by the kernel using [`map_memory`].
+    Anonymous,
+    /// The memory is the in-memory buffer of a framebuffer acquired with [`acquire_framebuffer`].
+    Framebuffer,
+    /// The memory contains the image of the process, as loaded by the kernel.
+    Image,
+    /// The memory is a stack mapped using [`MapFlags::STACK`].
+    Stack,
+    /// The memory is the memory of a PCI device, mapped using [`map_device_memory`].
+    Device,
+    /// The memory was allocated by the kernel using [`map_memory`], and has been marked as
+    /// purgeable with [`set_purgeable`].
+    Purgeable,
+    /// The memory is the [`Inbox`](public::Inbox) of the process, mapped by the kernel.
+    Inbox,
+    /// The memory is a region of a framebuffer, mapped using [`map_framebuffer_region`].
+    FramebufferRegion,
+}
+
+/// Information about a region of memory mapped in the address space of a process.
 ///
-/// # Returns
+/// This is returned by the [`query_mapping`] system call.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct MappingInfo {
+    /// The first address of the region. This is always aligned to a page boundary.
+    pub start: usize,
+    /// The size of the region, in bytes. This is always a multiple of the page size.
+    pub length: usize,
+    /// The flags that were used to map the region.
+    pub flags: MapFlags,
+    /// The kind of memory that backs the region.
+    pub kind: MappingKind,
+}
+
+assert_layout!(MappingInfo, size = 32, align = 8, {
+    start: 0,
+    length: 8,
+    flags: 16,
+    kind: 24,
+});
+
+bitflags! {
+    /// Flags used to control the behavior of the [`query_address_space`] system call.
+    #[derive(Debug, Clone, Copy)]
+    pub struct QueryAddressSpaceFlags: usize {
+        /// Whether the kernel should write the regions of the address space to its log.
+        const LOG_REGIONS = 1 << 0;
+    }
+}
+
+/// Statistics about the address space of a process.
 ///
-/// This function never returns and may never fail.
-#[cfg(feature = "userland")]
-#[inline(always)]
-pub fn terminate_self() -> ! {
-    unsafe {
-        raw::syscall1(Syscall::Terminate as usize, 0);
-        core::hint::unreachable_unchecked();
+/// This is returned by the [`query_address_space`] system call.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct AddressSpaceStats {
+    /// The number of regions mapped in the address space.
+    pub region_count: usize,
+    /// The total size of the regions, in bytes.
+    ///
+    /// This includes the memory that is reserved but not allocated yet, such as the unused part
+    /// of stacks.
+    pub reserved_bytes: usize,
+    /// The number of 4 KiB pages that are present in the page tables, by access rights.
+    ///
+    /// This array is indexed by the bits of [`MapFlags::WRITABLE`] and [`MapFlags::EXECUTABLE`]:
+    /// `mapped_pages[0]` counts the read-only pages, `mapped_pages[1]` the writable ones,
+    /// `mapped_pages[2]` the executable ones, and `mapped_pages[3]` the pages that are both
+    /// writable and executable.
+    pub mapped_pages: [usize; 4],
+    /// The number of page tables used to map the address space, including the root table.
+    pub page_table_count: usize,
+    /// The first address of the largest range of the address space that is not part of any
+    /// region.
+    pub largest_hole_start: usize,
+    /// The size of the largest range of the address space that is not part of any region, in
+    /// bytes.
+    pub largest_hole_length: usize,
+}
+
+assert_layout!(AddressSpaceStats, size = 72, align = 8, {
+    region_count: 0,
+    reserved_bytes: 8,
+    mapped_pages: 16,
+    page_table_count: 48,
+    largest_hole_start: 56,
+    largest_hole_length: 64,
+});
+
+bitflags! {
+    /// Properties of the CPU that are known to cause problems.
+    ///
+    /// This is part of the [`CpuInfo`] returned by the [`query_kernel_stats`] system call.
+    #[derive(Debug, Clone, Copy)]
+    pub struct CpuQuirks: u32 {
+        /// The kernel runs under a hypervisor.
+        ///
+        /// The reported microcode revision is the one exposed by the hypervisor, which is often
+        /// meaningless.
+        const HYPERVISOR = 1 << 0;
+
+        /// The time-stamp counter does not run at a constant rate. Timestamps may drift when the
+        /// frequency of the CPU changes.
+        const TSC_NOT_INVARIANT = 1 << 1;
+
+        /// The TSC-deadline mode of the local APIC timer is unreliable with the loaded microcode.
+        const TSC_DEADLINE_ERRATUM = 1 << 2;
     }
 }
 
-/// Maps some physical memory into the virtual address space of the specified process.
+/// Identifies the CPU the kernel is running on.
 ///
-/// # Arguments
+/// This is part of the [`KernelStats`] returned by the [`query_kernel_stats`] system call.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct CpuInfo {
+    /// The vendor string of the CPU, such as `GenuineIntel` or `AuthenticAMD`.
+    pub vendor: [u8; 12],
+    /// The family of the CPU, including its extended family.
+    pub family: u32,
+    /// The model of the CPU, including its extended model.
+    pub model: u32,
+    /// The stepping of the CPU.
+    pub stepping: u32,
+    /// The revision of the microcode loaded on the CPU, or 0 if it is not known.
+    pub microcode_revision: u32,
+    /// The known-problematic properties of the CPU.
+    pub quirks: CpuQuirks,
+}
+
+assert_layout!(CpuInfo, size = 32, align = 4, {
+    vendor: 0,
+    family: 12,
+    model: 16,
+    stepping: 20,
+    microcode_revision: 24,
+    quirks: 28,
+});
+
+/// The memory used by the kernel for its own purposes, in bytes.
 ///
-/// - `process_id` is the ID of the process to map to the memory for. 0 indicates the current
-///   process.
+/// This is part of the [`KernelStats`] returned by the [`query_kernel_stats`] system call.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KernelMemoryUsage {
+    /// The page tables of the kernel and of the processes.
+    pub page_tables: u64,
+    /// The stacks of the kernel, including its shadow stacks.
+    pub stacks: u64,
+    /// The bookkeeping structures of the kernel, such as the list of free pages, the public data
+    /// area or the symbol table.
+    pub metadata: u64,
+}
+
+assert_layout!(KernelMemoryUsage, size = 24, align = 8, {
+    page_tables: 0,
+    stacks: 8,
+    metadata: 16,
+});
+
+/// Information about the kernel and the machine it is running on.
 ///
-/// - `virtual_address` is the virtual address to map the memory to. This must be aligned to a
-///   page boundary.
+/// This is returned by the [`query_kernel_stats`] system call, and is meant to be included in
+/// bug reports.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KernelStats {
+    /// The CPU the kernel is running on.
+    pub cpu: CpuInfo,
+    /// The memory used by the kernel.
+    pub memory: KernelMemoryUsage,
+}
+
+assert_layout!(KernelStats, size = 56, align = 8, {
+    cpu: 0,
+    memory: 32,
+});
+
+bitflags! {
+    /// The hardware features that processes may rely on.
+    ///
+    /// This is part of the [`KernelInfo`] returned by the [`kernel_info`] system call.
+    #[derive(Debug, Clone, Copy)]
+    pub struct HardwareFeatures: u64 {
+        /// Mappings that are not [`MapFlags::EXECUTABLE`] cannot be executed.
+        ///
+        /// Without this feature, every readable page is executable.
+        const NO_EXECUTE = 1 << 0;
+
+        /// The `rdfsbase`, `rdgsbase`, `wrfsbase` and `wrgsbase` instructions can be used by
+        /// processes.
+        const FSGSBASE = 1 << 1;
+
+        /// The local APIC of the CPU supports the x2APIC mode.
+        const X2APIC = 1 << 2;
+    }
+}
+
+bitflags! {
+    /// The sizes of pages supported by the hardware.
+    ///
+    /// This is part of the [`KernelInfo`] returned by the [`kernel_info`] system call. The
+    /// mappings created on behalf of processes currently always use 4 KiB pages.
+    #[derive(Debug, Clone, Copy)]
+    pub struct PageSizes: u64 {
+        /// 4 KiB pages.
+        const SIZE_4KIB = 1 << 0;
+        /// 2 MiB pages.
+        const SIZE_2MIB = 1 << 1;
+        /// 1 GiB pages.
+        const SIZE_1GIB = 1 << 2;
+    }
+}
+
+/// Describes the running kernel and what it supports.
 ///
-/// - `length` is the length of the memory region to map. This must be aligned to a page boundary.
+/// This is returned by the [`kernel_info`] system call, and allows programs to adapt to the
+/// kernel they run on instead of assuming that it matches the version of this crate.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KernelInfo {
+    /// The major version of the kernel.
+    pub version_major: u32,
+    /// The minor version of the kernel.
+    pub version_minor: u32,
+    /// The patch version of the kernel.
+    pub version_patch: u32,
+    /// The number of system calls known to the kernel.
+    ///
+    /// System call numbers greater than or equal to this value are always invalid.
+    pub syscall_count: u32,
+    /// A bitmap of the system calls supported by the kernel, indexed by [`Syscall`].
+    ///
+    /// System calls that are known to the kernel but disabled in its build (such as
+    /// [`debug_snapshot`] without the `snapshot` feature) are not part of the bitmap.
+    pub syscalls: [u64; 2],
+    /// The hardware features that processes may rely on.
+    pub features: HardwareFeatures,
+    /// The sizes of pages supported by the hardware.
+    pub page_sizes: PageSizes,
+    /// The last address that may be part of the address space of a process.
+    ///
+    /// It depends on the configuration of the kernel and on the paging mode of the machine.
+    /// Memory can only be mapped below it, and never in the page that ends at it, which the
+    /// kernel keeps as a guard region. System calls reject the addresses and the ranges that
+    /// reach into that page.
+    ///
+    /// Kernels older than version 11 of the ABI do not write this field.
+    pub user_top: u64,
+    /// The time the kernel was built, in seconds since the Unix epoch.
+    ///
+    /// Kernels older than version 14 of the ABI do not write this field.
+    pub build_timestamp: u64,
+    /// The abbreviated hash of the commit the kernel was built from, as ASCII padded with zeros.
+    ///
+    /// It is all zeros if the kernel was not built from a git repository. See
+    /// [`KernelInfo::build_commit`].
+    ///
+    /// Kernels older than version 14 of the ABI do not write this field.
+    pub build_commit: [u8; 16],
+}
+
+assert_layout!(KernelInfo, size = 80, align = 8, {
+    version_major: 0,
+    version_minor: 4,
+    version_patch: 8,
+    syscall_count: 12,
+    syscalls: 16,
+    features: 32,
+    page_sizes: 40,
+    user_top: 48,
+    build_timestamp: 56,
+    build_commit: 64,
+});
+
+impl KernelInfo {
+    /// Returns whether the kernel supports the provided system call.
+    pub fn supports(&self, syscall: Syscall) -> bool {
+        let index = syscall as usize;
+        self.syscalls
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+
+    /// Returns the abbreviated hash of the commit the kernel was built from.
+    ///
+    /// `None` is returned if it is not known.
+    pub fn build_commit(&self) -> Option<&str> {
+        let length = self
+            .build_commit
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.build_commit.len());
+
+        match core::str::from_utf8(&self.build_commit[..length]) {
+            Ok("") | Err(_) => None,
+            Ok(commit) => Some(commit),
+        }
+    }
+}
+
+/// The maximum size of a message sent through a port, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 128;
+
+/// The maximum length of a message logged with [`debug_log`], in bytes.
+pub const MAX_DEBUG_LOG_LENGTH: usize = 1024;
+
+/// The maximum length of the name of a module passed to [`set_log_filter`], in bytes.
+pub const MAX_LOG_FILTER_NAME_LENGTH: usize = 32;
+
+/// The minimum level of the messages of the kernel log that get through a filter.
 ///
-/// - `flags` is a bitfield of flags that control the mapping. The supported flags are defined
-///   in [`MapFlags`].
+/// See [`set_log_filter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(usize)]
+pub enum LogLevelFilter {
+    /// Every message gets through.
+    Trace,
+    /// Trace messages are hidden.
+    Info,
+    /// Trace and info messages are hidden.
+    Warn,
+    /// Only error messages get through.
+    Error,
+    /// No message gets through.
+    Off,
+}
+
+/// An operation of the kernel that can be forced to fail with [`inject_fault`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum FaultPoint {
+    /// The allocation of a physical page by the kernel.
+    Allocate,
+    /// The mapping of a 4 KiB page in an address space, including the allocation of the page
+    /// tables it needs.
+    Map4KiB,
+}
+
+/// Walks the page tables of a process and computes statistics about its address space.
 ///
-/// # Returns
+/// This is meant to help debugging memory managers.
 ///
-/// On success, this system call returns 0.
+/// # Arguments
+///
+/// - `process_id` is the ID of the process whose address space should be inspected. 0 indicates
+///   the current process. Only the init process may inspect other processes.
+///
+/// - `stats` is the location where the statistics will be written.
+///
+/// - `flags` is a bitfield of flags that control the operation. The supported flags are defined
+///   in [`QueryAddressSpaceFlags`].
 ///
 /// # Errors
 ///
 /// [`SysResult::INVALID_VALUE`] is returned if:
 ///
-/// - The target address is not aligned to a page boundary.
-/// - The length is not aligned to a page boundary.
-/// - The target address and length refer to a memory region that overlaps completely or partially
-///   with the higher half.
-/// - Some set flags are not known to the kernel.
+/// - `stats` does not refer to memory that is writable by the current process.
+///
+/// - `flags` contains unknown flags.
 ///
 /// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
 /// process id.
-#[cfg(feature = "userland")]
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if the current process is not allowed to inspect
+/// the address space of the target process.
 #[inline(always)]
-pub fn map_memory(
+#[cfg(feature = "userland")]
+pub fn query_address_space(
     process_id: Option<ProcessId>,
-    virtual_address: usize,
-    length: usize,
-    flags: MapFlags,
+    stats: &mut core::mem::MaybeUninit<AddressSpaceStats>,
+    flags: QueryAddressSpaceFlags,
 ) -> SysResult {
-    SysResult(raw::syscall4(
-        Syscall::MapMemory as usize,
+    SysResult(raw::syscall3(
+        Syscall::QueryAddressSpace as usize,
         process_id.map_or(0, ProcessId::get),
-        virtual_address,
-        length,
+        stats.as_mut_ptr() as usize,
         flags.bits(),
     ))
 }
 
-/// Unmaps a bunch of pages from the virtual address space of the specified process.
+/// Writes information about the kernel and the machine it is running on to `stats`.
 ///
-/// If any of the pages specified in the range are not mapped, the system call will ignore those
-/// pages and only unmap the one that are mapped.
+/// # Errors
 ///
-/// # Arguments
+/// [`SysResult::INVALID_VALUE`] is returned if `stats` does not refer to memory that is writable
+/// by the current process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn query_kernel_stats(stats: &mut core::mem::MaybeUninit<KernelStats>) -> SysResult {
+    SysResult(raw::syscall1(
+        Syscall::QueryKernelStats as usize,
+        stats.as_mut_ptr() as usize,
+    ))
+}
+
+/// Writes a description of the running kernel and of what it supports to `info`.
 ///
-/// - `process_id` is the ID of the process to unmap the memory from. 0 indicates the current
-///   process.
+/// # Errors
 ///
-/// - `virtual_address` is the virtual address to unmap the memory from. This must be aligned to a
-///   page boundary.
+/// [`SysResult::INVALID_VALUE`] is returned if `info` does not refer to memory that is writable
+/// by the current process.
 ///
-/// - `length` is the length of the memory region to unmap. This must be aligned to a page
-///   boundary.
+/// Kernels that predate this system call return [`SysResult::INVALID_VALUE`] as well.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn kernel_info(info: &mut core::mem::MaybeUninit<KernelInfo>) -> SysResult {
+    SysResult(raw::syscall1(
+        Syscall::KernelInfo as usize,
+        info.as_mut_ptr() as usize,
+    ))
+}
+
+/// Starts a new process running the provided image.
 ///
-/// # Notes
+/// The image has the same format as the one of the init process. It is either a statically
+/// linked ELF64 executable, whose loadable segments are copied at the addresses requested by its
+/// program headers, or a flat image that starts with an [`InitHeader`](crate::InitHeader) and is
+/// copied at the address requested by that header. In both cases, the image is loaded in a new
+/// address space. The arguments of the new process are taken from `cmdline`, as described in
+/// [`InitArgs`](crate::InitArgs).
 ///
-/// Attempting to unmap memory pages that are not currently mapped in the target process's virtual
-/// address space has unspecified behavior. It may or may not produce an error, and the pages of the
-/// range that are actually mapped may or may not be unmapped.
+/// The image is copied before this function returns: the buffer does not have to outlive the
+/// new process.
 ///
 /// # Returns
 ///
-/// On success, this function returns 0.
+/// On success, this function returns the ID of the new process.
 ///
 /// # Errors
 ///
-/// [`SysResult::INVALID_VALUE`] is returned if:
-///
-/// - The target address is not aligned to a page boundary.
-/// - The length is not aligned to a page boundary.
+/// - [`SysResult::INVALID_VALUE`] is returned if `image` or `cmdline` do not refer to memory that
+///   is readable by the current process, if the image is invalid, or if the arguments do not fit
+///   in the topmost page of the stack of the new process.
 ///
-/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
-/// process id.
-#[cfg(feature = "userland")]
+/// - [`SysResult::OUT_OF_MEMORY`] is returned if the system does not have enough memory to
+///   create the process, or if the process table is full.
 #[inline(always)]
-pub fn unmap_memory(
-    process_id: Option<ProcessId>,
-    virtual_address: usize,
-    length: usize,
-) -> SysResult {
-    SysResult(raw::syscall3(
-        Syscall::UnmapMemory as usize,
-        process_id.map_or(0, ProcessId::get),
-        virtual_address,
-        length,
+#[cfg(feature = "userland")]
+pub fn spawn(image: &[u8], cmdline: &[u8]) -> SysResult {
+    SysResult(raw::syscall4(
+        Syscall::Spawn as usize,
+        image.as_ptr() as usize,
+        image.len(),
+        cmdline.as_ptr() as usize,
+        cmdline.len(),
     ))
 }
 
-/// Acquires a framebuffer for the provided process.
+/// Declares whether the provided process is restartable.
 ///
-/// # Arguments
+/// When a restartable process crashes, the kernel sends a [`CrashNotice`](crate::CrashNotice)
+/// message to `port`, and holds the framebuffers and PCI devices owned by the process in escrow
+/// until the current process reclaims them with [`reclaim_escrow`].
 ///
-/// - `process_id` is the ID of the process to acquire the framebuffer for. 0 indicates the current
-///  process.
+/// # Arguments
 ///
-/// - `index` is the index of the framebuffer to acquire.
+/// - `process` is the process to supervise. It must have been started by the current process
+///   with [`spawn`].
 ///
-/// - `at` is the virtual address at which the framebuffer's memory should be mapped. This must be
-/// aligned to a page boundary. If the provided memory region is already mapped, the old mappings
-/// will be overwritten.
+/// - `port` is the port that will receive the notice. It must be owned by the current process.
+///   `None` makes the process non-restartable again.
 ///
 /// # Returns
 ///
@@ -169,56 +625,1671 @@ pub fn unmap_memory(
 ///
 /// # Errors
 ///
-/// [`SysResult::INVALID_VALUE`] is returned if:
+/// - [`SysResult::INVALID_PROCESS_ID`] is returned if `process` does not refer to a process
+///   started by the current process.
 ///
-/// - The provided index does not refer to a valid framebuffer.
-/// - The provided virtual address is not aligned to a page boundary.
+/// - [`SysResult::INVALID_VALUE`] is returned if `port` does not refer to a port owned by the
+///   current process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn set_restartable(process: ProcessId, port: Option<PortId>) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::SetRestartable as usize,
+        process.get(),
+        port.map_or(0, PortId::get),
+    ))
+}
+
+/// Hands the resources held in the provided escrow over to another process.
 ///
-/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
-/// process id.
+/// The escrow must have been created when a process supervised by the current process crashed.
+/// See [`CrashNotice`](crate::CrashNotice). Once reclaimed, the escrow no longer exists.
 ///
-/// [`SysResult::CONFICT`] is returned if the requested framebuffer is already acquired by a
-/// process.
+/// # Arguments
+///
+/// - `escrow` is the ID of the escrow, as found in [`CrashNotice::escrow`](crate::CrashNotice).
+///
+/// - `to` is the process that receives the resources. `None` designates the current process.
+///
+/// # Returns
+///
+/// On success, this function returns the number of resources that have been handed over.
+///
+/// # Errors
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `escrow` does not refer to an escrow held on
+///   behalf of the current process.
+///
+/// - [`SysResult::INVALID_PROCESS_ID`] is returned if `to` does not refer to an existing process.
 #[inline(always)]
 #[cfg(feature = "userland")]
-pub fn acquire_framebuffer(process_id: Option<ProcessId>, index: usize, at: *mut u8) -> SysResult {
-    SysResult(raw::syscall3(
-        Syscall::AcquireFramebuffer as usize,
-        process_id.map_or(0, ProcessId::get),
-        index,
-        at as usize,
+pub fn reclaim_escrow(escrow: usize, to: Option<ProcessId>) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::ReclaimEscrow as usize,
+        escrow,
+        to.map_or(0, ProcessId::get),
     ))
 }
 
-/// Releases a framebuffer from the provided process and unmaps its from memory.
+/// The content of the message sent by the kernel when a PCI device bound with [`bind_irq`]
+/// raises an interrupt.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct IrqNotice {
+    /// The ID of the interrupt line, as returned by [`bind_irq`].
+    pub line: usize,
+    /// The index of the device in [`PublicData::pci_devices`](public::PublicData::pci_devices).
+    pub device: usize,
+    /// The time at which the interrupt was raised, in nanoseconds since boot.
+    ///
+    /// This is measured with the [`Clock`](public::Clock) of the public data area.
+    pub timestamp: u64,
+    /// The number of interrupts raised on the line before this one since it has been bound.
+    ///
+    /// Notices that could not be sent are counted as well: a gap between the sequence numbers of
+    /// two consecutive notices indicates that the driver missed some of them.
+    pub sequence: u64,
+}
+
+assert_layout!(IrqNotice, size = 32, align = 8, {
+    line: 0,
+    device: 8,
+    timestamp: 16,
+    sequence: 24,
+});
+
+unsafe impl Pod for IrqNotice {}
+
+/// Delivers the interrupts of a PCI device owned by the current process to one of its ports.
+///
+/// # Protocol
+///
+/// When the device raises an interrupt, the kernel masks its interrupt line and sends an
+/// [`IrqNotice`] to `port`. The line remains masked until the driver has serviced the device and
+/// acknowledged the interrupt with [`ack_irq`]: a device that keeps its line asserted cannot
+/// flood the system with interrupts, and the driver never sees a second notice for an interrupt
+/// it is still handling.
+///
+/// If the notice cannot be sent because `port` is full, the line remains masked all the same.
+/// The driver should check its devices and acknowledge their lines after draining the port.
+///
+/// A driver that takes too long to acknowledge an interrupt eventually loses the device. See
+/// [`set_irq_budget`].
+///
+/// The binding is removed when the device is released, or when the current process exits.
 ///
 /// # Arguments
 ///
-/// - `process_id` is the ID of the process to release the framebuffer from. 0 indicates the current
-/// process.
+/// - `device` is the index of the device in
+///   [`PublicData::pci_devices`](public::PublicData::pci_devices). It must be owned by the
+///   current process.
 ///
-/// - `index` is the index of the framebuffer to release.
+/// - `port` is the port that receives the notices. It must be owned by the current process.
 ///
 /// # Returns
 ///
-/// On success, this function returns 0.
+/// On success, this function returns the ID of the interrupt line. The line starts unmasked.
 ///
 /// # Errors
 ///
-/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
-/// process id.
+/// - [`SysResult::INVALID_VALUE`] is returned if `device` does not refer to a device owned by the
+///   current process, if the device does not use legacy interrupts, or if `port` does not refer
+///   to a port owned by the current process.
 ///
-/// [`SysResult::INVALID_VALUE`] is returned if the provided index does not refer to a valid
-/// framebuffer.
+/// - [`SysResult::CONFLICT`] is returned if the interrupt line of the device is already in use,
+///   either by the kernel or by another binding. Shared interrupt lines are not supported.
 ///
-/// [`SysResult::CONFICT`] is returned if the requested framebuffer is not acquired by the
-/// target process.
+/// - [`SysResult::OUT_OF_MEMORY`] is returned if the kernel cannot bind any more interrupt lines.
 #[inline(always)]
 #[cfg(feature = "userland")]
-pub fn release_framebuffer(process_id: Option<ProcessId>, index: usize) -> SysResult {
-    SysResult(raw::syscall2(
-        Syscall::ReleaseFramebuffer as usize,
-        process_id.map_or(0, ProcessId::get),
-        index,
+pub fn bind_irq(device: usize, port: PortId) -> SysResult {
+    SysResult(raw::syscall2(Syscall::BindIrq as usize, device, port.get()))
+}
+
+/// Acknowledges the last interrupt delivered on the provided line, and unmasks it.
+///
+/// See [`bind_irq`].
+///
+/// # Arguments
+///
+/// - `line` is the ID of the interrupt line, as returned by [`bind_irq`].
+///
+/// # Returns
+///
+/// On success, this function returns 0. Acknowledging a line that is not masked does nothing.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `line` does not refer to an interrupt line bound
+/// by the current process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn ack_irq(line: usize) -> SysResult {
+    SysResult(raw::syscall1(Syscall::AckIrq as usize, line))
+}
+
+/// The budget of an interrupt line that a driver exceeded.
+///
+/// See [`set_irq_budget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum IrqViolation {
+    /// The driver did not acknowledge the interrupt before its deadline.
+    AckDeadline,
+    /// The driver consumed more CPU time than its budget while handling the interrupt.
+    CpuBudget,
+}
+
+impl IrqViolation {
+    /// Converts the provided raw value into an [`IrqViolation`].
+    #[inline]
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::AckDeadline),
+            1 => Some(Self::CpuBudget),
+            _ => None,
+        }
+    }
+}
+
+/// The content of the message sent by the kernel to the supervisor of a driver that exceeded
+/// the budget of one of its interrupt lines.
+///
+/// The notice is sent to the port registered with [`set_restartable`], along with the
+/// [`CrashNotice`](crate::CrashNotice) messages. It is shorter than a crash notice, which lets
+/// the supervisor tell them apart by their length.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct IrqViolationNotice {
+    /// The ID of the driver.
+    pub process: usize,
+    /// The index of the device in [`PublicData::pci_devices`](public::PublicData::pci_devices).
+    pub device: usize,
+    /// The raw [`IrqViolation`].
+    pub violation: u32,
+    /// The ID of the interrupt line.
+    pub line: u32,
+}
+
+assert_layout!(IrqViolationNotice, size = 24, align = 8, {
+    process: 0,
+    device: 8,
+    violation: 16,
+    line: 20,
+});
+
+unsafe impl Pod for IrqViolationNotice {}
+
+impl IrqViolationNotice {
+    /// Returns the budget that was exceeded, if it is known.
+    #[inline(always)]
+    pub const fn violation(&self) -> Option<IrqViolation> {
+        IrqViolation::from_raw(self.violation)
+    }
+}
+
+/// Sets the budgets of an interrupt line bound with [`bind_irq`].
+///
+/// # Budgets
+///
+/// Once an interrupt has been delivered on a line, its driver must acknowledge it with
+/// [`ack_irq`] before the ack deadline, and without consuming more CPU time than its CPU budget
+/// in the meantime. Both are enforced with the granularity of a timer tick, and default to the
+/// values selected with the `irq.ack_deadline` and `irq.cpu_budget` options of the kernel command
+/// line.
+///
+/// When a driver exceeds one of its budgets, the kernel escalates, waiting for one more ack
+/// deadline between each step:
+///
+/// 1. The device is masked: its interrupts are disabled in its PCI command register.
+///
+/// 2. The supervisor of the driver receives an [`IrqViolationNotice`].
+///
+/// 3. The line is unbound, and the device is revoked: its memory is unmapped from the address
+///    space of the driver, and it is released.
+///
+/// Acknowledging the interrupt before the last step unmasks the device and stops the
+/// escalation.
+///
+/// # Arguments
+///
+/// - `line` is the ID of the interrupt line.
+///
+/// - `ack_deadline_ms` is the new ack deadline, in milliseconds.
+///
+/// - `cpu_budget_ms` is the new CPU budget, in milliseconds.
+///
+/// A value of 0 selects the default of the kernel.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `line` does not refer to a bound interrupt line.
+///
+/// - [`SysResult::PERMISSION_DENIED`] is returned if the current process is neither the driver
+///   that bound the line nor the process that started it, or if the driver attempts to raise
+///   one of its own budgets.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn set_irq_budget(line: usize, ack_deadline_ms: usize, cpu_budget_ms: usize) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::SetIrqBudget as usize,
+        line,
+        ack_deadline_ms,
+        cpu_budget_ms,
+    ))
+}
+
+/// The maximum number of timer ticks between two timer interrupts, as passed to
+/// [`set_tick_mode`].
+///
+/// This is one second with the default tick rate of the kernel.
+pub const MAX_TICK_PERIOD: usize = 100;
+
+/// How the kernel programs the timer that drives the scheduler.
+///
+/// Regardless of the mode, timeouts are always expressed in timer ticks, and the tick counter of
+/// the kernel keeps advancing at the same rate. The mode only changes how often the CPU is
+/// interrupted to account for the ticks that elapsed.
+///
+/// See [`set_tick_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum TickMode {
+    /// The timer interrupts the CPU periodically, once every `period` ticks.
+    ///
+    /// Processes are preempted and their timeouts expire with a granularity of `period` ticks.
+    Periodic,
+    /// The timer interrupts the CPU when the earliest timeout of a sleeping process expires, or
+    /// when another process is ready to run and the current one must be preempted.
+    ///
+    /// The CPU is interrupted at least once every `period` ticks, which bounds the delay of the
+    /// other timeouts of the kernel.
+    Deadline,
+}
+
+impl TickMode {
+    /// Converts the provided raw value into a [`TickMode`].
+    #[inline]
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Periodic),
+            1 => Some(Self::Deadline),
+            _ => None,
+        }
+    }
+}
+
+/// Changes how the kernel programs the timer that drives the scheduler.
+///
+/// This is meant for power managers, which may reduce the number of timer interrupts when the
+/// system runs on battery. This is only allowed to the init process.
+///
+/// # Arguments
+///
+/// - `mode` is the new [`TickMode`].
+///
+/// - `period` is the number of ticks between two timer interrupts in [`TickMode::Periodic`], or
+///   the maximum number of ticks between two timer interrupts in [`TickMode::Deadline`]. It must
+///   be between 1 and [`MAX_TICK_PERIOD`]. The default configuration of the kernel is a periodic
+///   timer with a period of 1.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// - [`SysResult::PERMISSION_DENIED`] is returned if the current process is not the init
+///   process.
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `mode` or `period` is invalid, or if the timer
+///   cannot count `period` ticks.
+///
+/// - [`SysResult::NOT_SUPPORTED`] is returned if the tick source is not the local APIC timer
+///   and the requested configuration is not the default one.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn set_tick_mode(mode: TickMode, period: usize) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::SetTickMode as usize,
+        mode as usize,
+        period,
+    ))
+}
+
+/// A cache maintenance operation, performed with [`cache_control`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum CacheOp {
+    /// Writes the modified cache lines of the range back to memory, and invalidates them.
+    ///
+    /// This is used before a device that does not snoop the caches reads the range, or after it
+    /// has written to it.
+    Flush,
+    /// Writes the modified cache lines of the range back to memory. The lines may be kept in the
+    /// cache.
+    ///
+    /// This is used before a device that does not snoop the caches reads the range. When the CPU
+    /// cannot keep the lines, this is the same as [`CacheOp::Flush`].
+    Writeback,
+    /// Makes the code written to the range visible to the instruction stream of the process.
+    ///
+    /// This is used by JIT compilers once they have written new code, before jumping to it.
+    SyncInstructions,
+    /// Writes every modified line of the caches back to memory, and invalidates all of them.
+    ///
+    /// The range is ignored. This is slow and delays the whole system: it is only allowed to the
+    /// init process and to the processes that own a PCI device.
+    FlushAll,
+}
+
+impl CacheOp {
+    /// Converts the provided raw value into a [`CacheOp`].
+    #[inline]
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Flush),
+            1 => Some(Self::Writeback),
+            2 => Some(Self::SyncInstructions),
+            3 => Some(Self::FlushAll),
+            _ => None,
+        }
+    }
+}
+
+/// Performs a cache maintenance operation on behalf of the current process.
+///
+/// # Arguments
+///
+/// - `op` is the [`CacheOp`] to perform.
+///
+/// - `address` and `length` describe the range of the address space of the current process that
+///   the operation applies to. The range must be part of memory mapped by the process. Pages that
+///   have not been accessed yet are skipped.
+///
+/// # Returns
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `op` is not a valid [`CacheOp`], or if the
+///   range is not mapped by the current process.
+///
+/// - [`SysResult::PERMISSION_DENIED`] is returned if `op` is [`CacheOp::FlushAll`] and the
+///   current process is neither the init process nor the owner of a PCI device.
+///
+/// - [`SysResult::NOT_SUPPORTED`] is returned if the CPU cannot flush individual cache lines.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn cache_control(op: CacheOp, address: usize, length: usize) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::CacheControl as usize,
+        op as usize,
+        address,
+        length,
+    ))
+}
+
+bitflags! {
+    /// Describes a [`KeyEvent`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct KeyFlags: u16 {
+        /// The key was released. Otherwise, it was pressed, or is repeating.
+        const RELEASED = 1 << 0;
+        /// The scancode of the key was preceded by the `0xE0` prefix.
+        ///
+        /// This distinguishes, for example, the keys of the numeric keypad from the arrow keys.
+        const EXTENDED = 1 << 1;
+    }
+}
+
+/// A key pressed or released on the PS/2 keyboard, as returned by [`read_input_events`].
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KeyEvent {
+    /// The time at which the key was pressed or released, in nanoseconds since boot.
+    ///
+    /// This is measured with the [`Clock`](public::Clock) of the public data area.
+    pub timestamp: u64,
+    /// The number of key events received by the kernel before this one, wrapping around.
+    ///
+    /// The kernel only keeps a limited number of events: a gap between the sequence numbers of
+    /// two consecutive events indicates that some of them were lost.
+    pub sequence: u32,
+    /// The scancode of the key, in scancode set 1, without its release bit.
+    pub scancode: u8,
+    /// Reserved for future use. Always 0.
+    pub reserved: u8,
+    /// Describes the event.
+    pub flags: KeyFlags,
+}
+
+unsafe impl Pod for KeyEvent {}
+
+assert_layout!(KeyEvent, size = 16, align = 8, {
+    timestamp: 0,
+    sequence: 8,
+    scancode: 12,
+    reserved: 13,
+    flags: 14,
+});
+
+/// Reads the key events received from the PS/2 keyboard since the last call.
+///
+/// The events are removed from the queue of the kernel, which is shared by every process allowed
+/// to read them.
+///
+/// # Arguments
+///
+/// - `events` is the buffer that receives the events.
+///
+/// - `timeout` is the maximum number of timer ticks to wait for when no event is available. 0
+///   makes the function return [`SysResult::WOULD_BLOCK`] right away, and `usize::MAX` waits
+///   forever.
+///
+/// # Returns
+///
+/// On success, this function returns the number of events written to `events`.
+///
+/// When no event is available and `timeout` is not 0, the process sleeps until an event is
+/// received or until the timeout expires, and the function returns 0. It may also return 0
+/// spuriously: callers must call it again to read the events.
+///
+/// # Errors
+///
+/// - [`SysResult::WOULD_BLOCK`] is returned if no event is available and `timeout` is 0.
+///
+/// - [`SysResult::PERMISSION_DENIED`] is returned if the current process is neither the init
+///   process nor the owner of a framebuffer.
+///
+/// - [`SysResult::NOT_SUPPORTED`] is returned if the system has no PS/2 keyboard.
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `events` is not mapped writable in the address
+///   space of the current process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn read_input_events(
+    events: &mut [core::mem::MaybeUninit<KeyEvent>],
+    timeout: usize,
+) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::ReadInputEvents as usize,
+        events.as_mut_ptr() as usize,
+        events.len(),
+        timeout,
+    ))
+}
+
+/// Returns the number of nanoseconds elapsed since boot, as measured by the monotonic clock of
+/// the kernel.
+///
+/// The same clock can be read without a system call through
+/// [`Clock::uptime_ns`](public::Clock::uptime_ns), which should be preferred when the TSC has
+/// been calibrated. This function works in every case: when the TSC could not be calibrated, the
+/// time is derived from the scheduler tick, with its granularity.
+///
+/// # Returns
+///
+/// This function always succeeds, and returns the number of nanoseconds elapsed since boot.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn get_time() -> SysResult {
+    SysResult(raw::syscall0(Syscall::GetTime as usize))
+}
+
+/// Gives the rest of the time slice of the current process to another process.
+///
+/// The target runs right away, until the next tick of the scheduler, and the current process is
+/// moved to the back of the run queue. This reduces the latency of processes that work in pairs,
+/// such as a client that sends a request to a server and waits for its response.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process that receives the time slice.
+///
+/// # Returns
+///
+/// On success, this function returns 1 once the current process runs again.
+///
+/// When the target is not ready to run (for example because it is waiting for a message, or
+/// because it is the current process), nothing happens and the function returns 0 right away.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn yield_to(process_id: ProcessId) -> SysResult {
+    SysResult(raw::syscall1(Syscall::YieldTo as usize, process_id.get()))
+}
+
+/// Gives the rest of the time slice of the current process to the other processes that are ready
+/// to run.
+///
+/// The current process is moved to the back of the run queue. When no other process is ready to
+/// run, it keeps running right away.
+///
+/// # Returns
+///
+/// This function always succeeds, and returns 0 once the current process runs again.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn yield_now() -> SysResult {
+    SysResult(raw::syscall0(Syscall::Yield as usize))
+}
+
+/// Puts the current process to sleep for at least `duration` nanoseconds.
+///
+/// The process is removed from the run queue and does not use the CPU while it sleeps. It is
+/// woken up by the first tick of the scheduler that follows the end of its sleep, according to
+/// the monotonic clock (see [`get_time`]). In [`TickMode::Deadline`], the timer is programmed to
+/// interrupt the CPU at that tick.
+///
+/// A `duration` of 0 behaves like [`yield_now`].
+///
+/// # Returns
+///
+/// On success, this function returns 0 once the process has been woken up.
+///
+/// # Errors
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if the kernel cannot arm any more timeouts.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn sleep_ns(duration: u64) -> SysResult {
+    SysResult(raw::syscall1(Syscall::SleepNs as usize, duration as usize))
+}
+
+/// Information about a message received with [`receive`].
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct MessageInfo {
+    /// The ID of the process that sent the message.
+    pub sender: usize,
+    /// The ID of the port attached to the message, or 0 if no port was attached.
+    ///
+    /// The port was owned by the sender when the message was sent.
+    pub attachment: usize,
+}
+
+assert_layout!(MessageInfo, size = 16, align = 8, {
+    sender: 0,
+    attachment: 8,
+});
+
+/// Performs the `terminate` system call on the current process.
+///
+/// # Returns
+///
+/// This function never returns and may never fail.
+#[cfg(feature = "userland")]
+#[inline(always)]
+pub fn terminate_self() -> ! {
+    unsafe {
+        raw::syscall1(Syscall::Terminate as usize, 0);
+        core::hint::unreachable_unchecked();
+    }
+}
+
+/// Terminates another process, releasing all the resources it owns.
+///
+/// # Permissions
+///
+/// - The init process cannot be terminated by another process.
+/// - The init process may terminate any other process, and so may the memory manager of the
+///   system, subscribed to [`EventKind::OutOfMemory`] events, to reclaim memory.
+/// - Other processes may terminate the processes they started with [`spawn`], directly or
+///   through processes that are still running.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// - [`SysResult::INVALID_PROCESS_ID`] is returned if `process_id` does not refer to an
+///   existing process.
+///
+/// - [`SysResult::PERMISSION_DENIED`] is returned if the current process is not allowed to
+///   terminate it.
+#[cfg(feature = "userland")]
+#[inline(always)]
+pub fn terminate(process_id: ProcessId) -> SysResult {
+    SysResult(raw::syscall1(Syscall::Terminate as usize, process_id.get()))
+}
+
+/// Maps some physical memory into the virtual address space of the specified process.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to map to the memory for. 0 indicates the current
+///   process.
+///
+/// - `virtual_address` is the virtual address to map the memory to. This must be aligned to a
+///   page boundary. When 0, the kernel picks a free range of the address space that is large
+///   enough to hold the mapping.
+///
+/// - `length` is the length of the memory region to map. This must be aligned to a page boundary.
+///
+/// - `flags` is a bitfield of flags that control the mapping. The supported flags are defined
+///   in [`MapFlags`].
+///
+/// # Returns
+///
+/// On success, this system call returns the address at which the memory was mapped.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if:
+///
+/// - The target address is not aligned to a page boundary.
+/// - The length is not aligned to a page boundary.
+/// - The target address and length refer to a memory region that overlaps completely or partially
+///   with the higher half.
+/// - Some set flags are not known to the kernel.
+/// - [`MapFlags::STACK`] is set and the length is less than two pages.
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+///
+/// [`SysResult::CONFLICT`] is returned if the target memory region overlaps with an existing
+/// mapping.
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if the system does not have enough memory to
+/// complete the operation, or if `virtual_address` is 0 and no free range of the address space
+/// is large enough to hold the mapping.
+#[cfg(feature = "userland")]
+#[inline(always)]
+pub fn map_memory(
+    process_id: Option<ProcessId>,
+    virtual_address: usize,
+    length: usize,
+    flags: MapFlags,
+) -> SysResult {
+    SysResult(raw::syscall4(
+        Syscall::MapMemory as usize,
+        process_id.map_or(0, ProcessId::get),
+        virtual_address,
+        length,
+        flags.bits(),
+    ))
+}
+
+/// Unmaps a bunch of pages from the virtual address space of the specified process.
+///
+/// If any of the pages specified in the range are not mapped, the system call will ignore those
+/// pages and only unmap the one that are mapped. The memory allocated by the kernel on behalf of
+/// the process is freed, while the memory of framebuffers and devices is only unmapped.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to unmap the memory from. 0 indicates the current
+///   process.
+///
+/// - `virtual_address` is the virtual address to unmap the memory from. This must be aligned to a
+///   page boundary.
+///
+/// - `length` is the length of the memory region to unmap. This must be aligned to a page
+///   boundary.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if:
+///
+/// - The target address is not aligned to a page boundary.
+/// - The length is not aligned to a page boundary.
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if unmapping the range would split a mapping in two
+/// and the kernel cannot track any more mappings for the process. Nothing is unmapped then.
+#[cfg(feature = "userland")]
+#[inline(always)]
+pub fn unmap_memory(
+    process_id: Option<ProcessId>,
+    virtual_address: usize,
+    length: usize,
+) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::UnmapMemory as usize,
+        process_id.map_or(0, ProcessId::get),
+        virtual_address,
+        length,
+    ))
+}
+
+/// Acquires a framebuffer for the provided process.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to acquire the framebuffer for. 0 indicates the current
+///   process.
+///
+/// - `index` is the index of the framebuffer to acquire.
+///
+/// - `at` is the virtual address at which the framebuffer's memory should be mapped. This must be
+///   aligned to a page boundary. The range must not overlap with any existing mapping.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if:
+///
+/// - The provided index does not refer to a valid framebuffer. This is always the case on
+///   headless systems (see [`PublicData::is_headless`](public::PublicData::is_headless)).
+/// - The provided virtual address is not aligned to a page boundary.
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+///
+/// [`SysResult::CONFLICT`] is returned if the requested framebuffer is already acquired by a
+/// process, or if the target memory region overlaps with an existing mapping.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn acquire_framebuffer(process_id: Option<ProcessId>, index: usize, at: *mut u8) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::AcquireFramebuffer as usize,
+        process_id.map_or(0, ProcessId::get),
+        index,
+        at as usize,
+    ))
+}
+
+/// Releases a framebuffer from the provided process and unmaps its from memory.
+///
+/// The regions created from the framebuffer with [`create_framebuffer_region`] are destroyed.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to release the framebuffer from. 0 indicates the current
+///   process.
+///
+/// - `index` is the index of the framebuffer to release.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+///
+/// [`SysResult::INVALID_VALUE`] is returned if the provided index does not refer to a valid
+/// framebuffer.
+///
+/// [`SysResult::CONFICT`] is returned if the requested framebuffer is not acquired by the
+/// target process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn release_framebuffer(process_id: Option<ProcessId>, index: usize) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::ReleaseFramebuffer as usize,
+        process_id.map_or(0, ProcessId::get),
+        index,
+    ))
+}
+
+/// Creates a region of a framebuffer owned by the current process, and grants it to another
+/// process.
+///
+/// This lets a display server give its clients direct access to the pixels of their windows,
+/// without copying them. The grantee maps the region with [`map_framebuffer_region`].
+///
+/// # Granularity
+///
+/// Memory is mapped with the granularity of a page. To ensure that the grantee cannot reach the
+/// rows above and below the rectangle, `y * pitch` and `height * pitch` must be multiples of
+/// the page size (4 KiB), where `pitch` is the one of the framebuffer.
+///
+/// The grantee may access every column of the rows of the rectangle: its horizontal bounds are
+/// not enforced.
+///
+/// # Arguments
+///
+/// - `index` is the index of the framebuffer.
+///
+/// - `x`, `y`, `width` and `height` describe the rectangle, in pixels.
+///
+/// - `grantee` is the process that may map the region.
+///
+/// # Returns
+///
+/// On success, this function returns the ID of the region.
+///
+/// The region is destroyed, and unmapped from the address space of the grantee, when it is
+/// destroyed with [`destroy_framebuffer_region`], when the framebuffer is released, or when
+/// the current process or the grantee exits.
+///
+/// # Errors
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `index` does not refer to a valid
+///   framebuffer, or if the rectangle is empty, exceeds the framebuffer, or does not meet the
+///   alignment requirements above.
+///
+/// - [`SysResult::INVALID_PROCESS_ID`] is returned if `grantee` is not a valid process ID.
+///
+/// - [`SysResult::CONFLICT`] is returned if the framebuffer is not owned by the current
+///   process.
+///
+/// - [`SysResult::OUT_OF_MEMORY`] is returned if the kernel cannot hold more regions.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn create_framebuffer_region(
+    index: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    grantee: ProcessId,
+) -> SysResult {
+    SysResult(raw::syscall6(
+        Syscall::CreateFramebufferRegion as usize,
+        index,
+        x,
+        y,
+        width,
+        height,
+        grantee.get(),
+    ))
+}
+
+/// Maps a region of a framebuffer granted to the current process.
+///
+/// The memory is writable and not executable. Page tables cannot express write-only mappings on
+/// **x86_64**: the pixels of the region can be read back as well.
+///
+/// # Arguments
+///
+/// - `region` is the ID of the region, as returned by [`create_framebuffer_region`].
+///
+/// - `at` is the virtual address at which the region should be mapped. It must be aligned to a
+///   page boundary. When null, the kernel picks an address.
+///
+/// # Returns
+///
+/// On success, this function returns the address at which the region has been mapped.
+///
+/// # Errors
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `region` does not refer to a region granted to
+///   the current process, or if `at` is not aligned to a page boundary.
+///
+/// - [`SysResult::CONFLICT`] is returned if the requested range is already mapped.
+///
+/// - [`SysResult::OUT_OF_MEMORY`] is returned if the system runs out of memory.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn map_framebuffer_region(region: usize, at: *mut u8) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::MapFramebufferRegion as usize,
+        region,
+        at as usize,
+    ))
+}
+
+/// Destroys a region of a framebuffer created by the current process, and unmaps it from the
+/// address space of its grantee.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `region` does not refer to a region created by
+/// the current process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn destroy_framebuffer_region(region: usize) -> SysResult {
+    SysResult(raw::syscall1(
+        Syscall::DestroyFramebufferRegion as usize,
+        region,
+    ))
+}
+
+/// Suspends the provided process.
+///
+/// A suspended process is not executed until it is resumed with [`resume`]. When a process
+/// suspends itself, this function returns after another process has resumed it.
+///
+/// Suspending a process that is already suspended has no effect.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to suspend. 0 indicates the current process.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn suspend(process_id: Option<ProcessId>) -> SysResult {
+    SysResult(raw::syscall1(
+        Syscall::Suspend as usize,
+        process_id.map_or(0, ProcessId::get),
+    ))
+}
+
+/// Resumes a process previously suspended with [`suspend`].
+///
+/// Resuming a process that is not suspended has no effect.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to resume.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn resume(process_id: ProcessId) -> SysResult {
+    SysResult(raw::syscall1(Syscall::Resume as usize, process_id.get()))
+}
+
+/// Queries information about the region mapped at the provided address.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process whose address space should be queried. 0 indicates
+///   the current process.
+///
+/// - `address` is the address to query. It does not need to be aligned to a page boundary.
+///
+/// - `info` is the location where the information about the region will be written.
+///
+/// # Returns
+///
+/// On success, this function returns 1 if a region contains `address`, in which case `info` is
+/// written. If no region contains `address`, 0 is returned and `info` is left untouched.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `info` does not refer to memory that is writable by
+/// the current process.
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn query_mapping(
+    process_id: Option<ProcessId>,
+    address: usize,
+    info: &mut core::mem::MaybeUninit<MappingInfo>,
+) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::QueryMapping as usize,
+        process_id.map_or(0, ProcessId::get),
+        address,
+        info.as_mut_ptr() as usize,
+    ))
+}
+
+/// Grows, shrinks or moves a region of memory previously mapped with [`map_memory`] in the
+/// address space of the current process.
+///
+/// When the mapping is grown, the kernel first attempts to extend it in place. If the memory
+/// directly following the mapping is already in use, the mapping is moved to `new_address_hint`
+/// when [`RemapFlags::MAY_MOVE`] is set. Moving a mapping does not copy its content: the physical
+/// memory that backs it is simply mapped at the new location.
+///
+/// When the mapping is shrunk, the memory past the new length is unmapped and freed. The mapping
+/// is never moved in that case.
+///
+/// # Arguments
+///
+/// - `old_address` is the address of the mapping. This must be aligned to a page boundary.
+///
+/// - `old_length` is the current length of the mapping. This must be aligned to a page boundary.
+///
+/// - `new_length` is the requested length of the mapping. This must be a non-zero multiple of
+///   the page size.
+///
+/// - `flags` is a bitfield of flags that control the operation. The supported flags are defined
+///   in [`RemapFlags`].
+///
+/// - `new_address_hint` is the address to which the mapping is moved if it cannot be grown in
+///   place. This is ignored when [`RemapFlags::MAY_MOVE`] is not set. Otherwise, it must be
+///   aligned to a page boundary. When 0, the kernel picks a free range of the address space.
+///
+/// # Returns
+///
+/// On success, this function returns the new address of the mapping.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if:
+///
+/// - One of the addresses or lengths is not aligned to a page boundary.
+/// - One of the lengths is zero.
+/// - The `old_address..old_address + old_length` range is not part of a single region mapped
+///   with [`map_memory`].
+/// - The new region would overlap with the higher half.
+/// - Some set flags are not known to the kernel.
+///
+/// [`SysResult::CONFLICT`] is returned if the mapping cannot be grown in place and
+/// [`RemapFlags::MAY_MOVE`] is not set, or if the memory at `new_address_hint` is already in use.
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if the system does not have enough memory to
+/// complete the operation. In that case, the original mapping is left untouched.
+#[cfg(feature = "userland")]
+#[inline(always)]
+pub fn remap_memory(
+    old_address: usize,
+    old_length: usize,
+    new_length: usize,
+    flags: RemapFlags,
+    new_address_hint: usize,
+) -> SysResult {
+    SysResult(raw::syscall5(
+        Syscall::RemapMemory as usize,
+        old_address,
+        old_length,
+        new_length,
+        flags.bits(),
+        new_address_hint,
+    ))
+}
+
+/// Marks a range of memory mapped with [`map_memory`] as purgeable, or makes it regular memory
+/// again.
+///
+/// The kernel may reclaim the physical memory of purgeable ranges when the system runs low on
+/// memory, discarding their content. This is meant for data that the process is able to
+/// regenerate, such as caches.
+///
+/// Before using the content of a purgeable range, the process must make it regular memory again.
+/// The returned value then tells whether the content has been discarded and must be regenerated.
+/// Accessing a range that has been purged maps zeroed memory.
+///
+/// # Arguments
+///
+/// - `address` is the address of the range. This must be aligned to a page boundary.
+///
+/// - `length` is the length of the range. This must be a non-zero multiple of the page size.
+///
+/// - `purgeable` is whether the range becomes purgeable.
+///
+/// # Returns
+///
+/// On success, this function returns 1 if the memory of the range had been purged, and 0
+/// otherwise.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if:
+///
+/// - The address or the length is not aligned to a page boundary.
+/// - The length is zero.
+/// - The `address..address + length` range is not part of a single region mapped with
+///   [`map_memory`].
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if the kernel cannot keep track of the new region
+/// created by the operation.
+#[cfg(feature = "userland")]
+#[inline(always)]
+pub fn set_purgeable(address: usize, length: usize, purgeable: bool) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::SetPurgeable as usize,
+        address,
+        length,
+        purgeable as usize,
+    ))
+}
+
+/// Puts the current process to sleep until another process wakes it up with [`wake`], provided
+/// that `word` still holds the `expected` value.
+///
+/// The comparison and the transition to the sleeping state happen atomically with respect to
+/// [`wake`]. This is the basic building block of the primitives of the [`sync`](crate::sync)
+/// module.
+///
+/// Processes wait on the physical memory that backs `word`, meaning that processes sharing memory
+/// may use this function to synchronize with each other.
+///
+/// This function may return spuriously, for example when the process is suspended and resumed
+/// while it is waiting. Callers must check the condition they are waiting for again.
+///
+/// # Returns
+///
+/// On success, this function returns 0, regardless of whether the process actually slept.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `word` is not mapped in the address space of the
+/// current process.
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if `word` is part of a stack page that has never
+/// been accessed, and the system does not have enough memory to allocate it.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn wait(word: &AtomicU32, expected: u32) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::Wait as usize,
+        word as *const AtomicU32 as usize,
+        expected as usize,
+    ))
+}
+
+/// Like [`wait`], but gives up once `timeout` timer ticks have elapsed.
+///
+/// A `timeout` of 0 makes the function return immediately, and `usize::MAX` waits forever, like
+/// [`wait`].
+///
+/// The function does not indicate whether the timeout expired: callers must check the condition
+/// they are waiting for again, and the current time if they need to.
+///
+/// # Returns
+///
+/// On success, this function returns 0, regardless of whether the process actually slept.
+///
+/// # Errors
+///
+/// See [`wait`].
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn wait_timeout(word: &AtomicU32, expected: u32, timeout: usize) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::WaitTimeout as usize,
+        word as *const AtomicU32 as usize,
+        expected as usize,
+        timeout,
+    ))
+}
+
+/// Wakes up to `count` processes waiting on `word` with [`wait`].
+///
+/// # Returns
+///
+/// On success, this function returns the number of processes that have been woken up.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `word` is not mapped in the address space of the
+/// current process.
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if `word` is part of a stack page that has never
+/// been accessed, and the system does not have enough memory to allocate it.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn wake(word: &AtomicU32, count: usize) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::Wake as usize,
+        word as *const AtomicU32 as usize,
+        count,
+    ))
+}
+
+/// Creates a new port owned by the current process.
+///
+/// Only the current process may receive messages from the port. Other processes may send
+/// messages to it once they know its ID.
+///
+/// # Returns
+///
+/// On success, this function returns the ID of the new port.
+///
+/// # Errors
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if the system cannot hold any more ports.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn create_port() -> SysResult {
+    SysResult(raw::syscall0(Syscall::CreatePort as usize))
+}
+
+/// Destroys a port owned by the current process, discarding its pending messages.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `port` does not refer to a port owned by the
+/// current process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn destroy_port(port: PortId) -> SysResult {
+    SysResult(raw::syscall1(Syscall::DestroyPort as usize, port.get()))
+}
+
+/// Sends a message to a port.
+///
+/// This function never blocks. The message is copied into the queue of the port, and the owner
+/// of the port is woken up if it was waiting for a message with [`wait_port`].
+///
+/// # Arguments
+///
+/// - `port` is the ID of the port to send the message to.
+///
+/// - `data` is the content of the message. It must not be larger than [`MAX_MESSAGE_SIZE`].
+///
+/// - `attachment` is the ID of a port owned by the current process to attach to the message, if
+///   any. This is typically used to let the receiver reply to the message. The receiver knows
+///   that the attached port belongs to the sender of the message.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `port` does not refer to an existing port, if
+/// `attachment` does not refer to a port owned by the current process, or if `data` is too
+/// large.
+///
+/// [`SysResult::WOULD_BLOCK`] is returned if the queue of the port is full.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn send(port: PortId, data: &[u8], attachment: Option<PortId>) -> SysResult {
+    SysResult(raw::syscall4(
+        Syscall::Send as usize,
+        port.get(),
+        data.as_ptr() as usize,
+        data.len(),
+        attachment.map_or(0, PortId::get),
+    ))
+}
+
+/// Removes the oldest message of a port owned by the current process and copies it into
+/// `buffer`.
+///
+/// This function never blocks. Use [`wait_port`] to wait for a message to arrive.
+///
+/// # Arguments
+///
+/// - `port` is the ID of the port to receive the message from.
+///
+/// - `buffer` is where the content of the message is written.
+///
+/// - `info`, when provided, receives information about the sender of the message and the port
+///   attached to it.
+///
+/// # Returns
+///
+/// On success, this function returns the size of the received message.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `port` does not refer to a port owned by the
+/// current process, or if `buffer` is too small to hold the message. In the latter case, the
+/// message is left in the queue.
+///
+/// [`SysResult::WOULD_BLOCK`] is returned if the port has no pending message.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn receive(
+    port: PortId,
+    buffer: &mut [u8],
+    info: Option<&mut core::mem::MaybeUninit<MessageInfo>>,
+) -> SysResult {
+    SysResult(raw::syscall4(
+        Syscall::Receive as usize,
+        port.get(),
+        buffer.as_mut_ptr() as usize,
+        buffer.len(),
+        info.map_or(0, |info| info.as_mut_ptr() as usize),
+    ))
+}
+
+/// Puts the current process to sleep until a message is sent to a port it owns, or until the
+/// timeout expires.
+///
+/// This function returns immediately if the port already has pending messages. It may also
+/// return spuriously, before any message is available.
+///
+/// # Arguments
+///
+/// - `port` is the ID of the port to wait on.
+///
+/// - `timeout` is the maximum number of timer ticks to wait for. 0 makes the function return
+///   immediately, and `usize::MAX` waits forever.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if `port` does not refer to a port owned by the
+/// current process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn wait_port(port: PortId, timeout: usize) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::WaitPort as usize,
+        port.get(),
+        timeout,
+    ))
+}
+
+/// Writes a message to the log of the kernel.
+///
+/// This is meant to help debugging userspace programs. The message is tagged with the ID of the
+/// current process.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if the message is longer than
+/// [`MAX_DEBUG_LOG_LENGTH`].
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn debug_log(message: &str) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::DebugLog as usize,
+        message.as_ptr() as usize,
+        message.len(),
+    ))
+}
+
+/// Writes a snapshot of the state of the kernel to its serial port.
+///
+/// The snapshot holds the process table, the memory map of every process and a summary of the
+/// memory tracker, in a binary format meant to be analysed offline when investigating hangs.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::NOT_SUPPORTED`] is returned if the kernel has been built without the `snapshot`
+/// feature.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn debug_snapshot() -> SysResult {
+    SysResult(raw::syscall0(Syscall::DebugSnapshot as usize))
+}
+
+/// Sets the filter applied to the messages that a module of the kernel writes to its log.
+///
+/// This is only allowed to the init process.
+///
+/// # Arguments
+///
+/// - `module` is the name of the module the filter applies to, such as `cpu::paging` or
+///   `paging`. The filter applies to every module whose path contains it, as well as to their
+///   submodules. When several filters apply to a module, the one with the longest name is used.
+///   An empty name sets the filter of the modules that have no filter of their own.
+///
+/// - `level` is the minimum level of the messages that get through the filter.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// - [`SysResult::PERMISSION_DENIED`] is returned if the current process is not the init
+///   process.
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `module` is longer than
+///   [`MAX_LOG_FILTER_NAME_LENGTH`].
+///
+/// - [`SysResult::OUT_OF_MEMORY`] is returned if the kernel cannot hold any more filters.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn set_log_filter(module: &str, level: LogLevelFilter) -> SysResult {
+    SysResult(raw::syscall3(
+        Syscall::SetLogFilter as usize,
+        module.as_ptr() as usize,
+        module.len(),
+        level as usize,
+    ))
+}
+
+/// Forces an operation of the kernel to fail, in order to test how failures are handled.
+///
+/// This is meant to verify that the error paths of the kernel, such as the rollback of a
+/// partially mapped region, release everything they acquired. Failures are reported as if the
+/// system had run out of memory.
+///
+/// The same faults can be injected from boot with the `fault.allocate=<n>` and `fault.map=<n>`
+/// options of the kernel command line.
+///
+/// # Arguments
+///
+/// - `point` is the operation that should fail.
+///
+/// - `nth` is the number of the call to the operation that should fail, starting at 1 for the
+///   next one. Only that call fails. 0 disarms the fault.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// - [`SysResult::NOT_SUPPORTED`] is returned if the kernel has been built without the
+///   `fault-injection` feature.
+///
+/// - [`SysResult::PERMISSION_DENIED`] is returned if the current process is not the init
+///   process.
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `point` is not a valid [`FaultPoint`].
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn inject_fault(point: FaultPoint, nth: usize) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::InjectFault as usize,
+        point as usize,
+        nth,
+    ))
+}
+
+/// Subscribes a port to the events of the provided kind.
+///
+/// Every time such an event occurs, the kernel sends an [`Event`](crate::event::Event) message
+/// to the port. Only one port may be subscribed to a given kind of event at a time.
+///
+/// # Arguments
+///
+/// - `kind` is the kind of events to subscribe to.
+///
+/// - `port` is the port that will receive the events. It must be owned by the current process.
+///   `None` removes the subscription of the current process, if any.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// - [`SysResult::INVALID_VALUE`] is returned if `port` does not refer to a port owned by the
+///   current process.
+///
+/// - [`SysResult::CONFLICT`] is returned if another process is already subscribed to the
+///   events of that kind.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn subscribe_event(kind: EventKind, port: Option<PortId>) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::SubscribeEvent as usize,
+        kind as usize,
+        port.map_or(0, PortId::get),
+    ))
+}
+
+/// Acquires a PCI device for the provided process.
+///
+/// Only the owner of a device may map its memory with [`map_device_memory`].
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to acquire the device for. 0 indicates the current
+///   process.
+///
+/// - `index` is the index of the device to acquire in
+///   [`PublicData::pci_devices`](public::PublicData::pci_devices).
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if the provided index does not refer to a valid
+/// device.
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+///
+/// [`SysResult::CONFLICT`] is returned if the requested device is already acquired by a process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn acquire_pci_device(process_id: Option<ProcessId>, index: usize) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::AcquirePciDevice as usize,
+        process_id.map_or(0, ProcessId::get),
+        index,
+    ))
+}
+
+/// Releases a PCI device from the provided process and unmaps its memory.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to release the device from. 0 indicates the current
+///   process.
+///
+/// - `index` is the index of the device to release.
+///
+/// # Returns
+///
+/// On success, this function returns 0.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+///
+/// [`SysResult::INVALID_VALUE`] is returned if the provided index does not refer to a valid
+/// device.
+///
+/// [`SysResult::CONFLICT`] is returned if the requested device is not acquired by the target
+/// process.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn release_pci_device(process_id: Option<ProcessId>, index: usize) -> SysResult {
+    SysResult(raw::syscall2(
+        Syscall::ReleasePciDevice as usize,
+        process_id.map_or(0, ProcessId::get),
+        index,
+    ))
+}
+
+/// Maps the memory of a base address register of a PCI device into the address space of the
+/// provided process.
+///
+/// The memory is mapped as uncacheable, writable and non-executable.
+///
+/// # Arguments
+///
+/// - `process_id` is the ID of the process to map the memory for. 0 indicates the current
+///   process.
+///
+/// - `physical_address` is the address of the memory of the register, as reported by
+///   [`PciBar::address`](public::PciBar::address).
+///
+/// - `length` is the size of the memory of the register, rounded up to a page boundary.
+///
+/// - `virtual_address` is the virtual address to map the memory to. This must be aligned to a
+///   page boundary. When 0, the kernel picks a free range of the address space that is large
+///   enough to hold the mapping.
+///
+/// # Returns
+///
+/// On success, this function returns the address at which the memory was mapped.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if:
+///
+/// - `physical_address` and `length` do not refer exactly to a register marked as
+///   [`PciBarFlags::MAPPABLE`](public::PciBarFlags::MAPPABLE) of a device owned by the target
+///   process.
+/// - The target address is not aligned to a page boundary.
+/// - The target address and length refer to a memory region that overlaps completely or partially
+///   with the higher half.
+///
+/// [`SysResult::INVALID_PROCESS_ID`] is returned if the provided `process_id` is not a valid
+/// process id.
+///
+/// [`SysResult::PERMISSION_DENIED`] is returned if `process_id` designates another process that
+/// the current process is not allowed to [`terminate`].
+///
+/// [`SysResult::CONFLICT`] is returned if the target memory region is already in use.
+///
+/// [`SysResult::OUT_OF_MEMORY`] is returned if the system does not have enough memory to
+/// complete the operation, or if `virtual_address` is 0 and no free range of the address space
+/// is large enough to hold the mapping.
+#[inline(always)]
+#[cfg(feature = "userland")]
+pub fn map_device_memory(
+    process_id: Option<ProcessId>,
+    physical_address: u64,
+    length: usize,
+    virtual_address: usize,
+) -> SysResult {
+    SysResult(raw::syscall4(
+        Syscall::MapDeviceMemory as usize,
+        process_id.map_or(0, ProcessId::get),
+        physical_address as usize,
+        length,
+        virtual_address,
     ))
 }
@@ -0,0 +1,95 @@
+use bitflags::bitflags;
+
+use crate::layout::assert_layout;
+
+bitflags! {
+    /// The ACPI tables that the kernel found and parsed during boot.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct AcpiFlags: u32 {
+        /// A valid RSDP was provided by the bootloader.
+        const RSDP = 1 << 0;
+        /// The MADT was found. It describes the I/O APICs of the system.
+        const MADT = 1 << 1;
+        /// The FADT was found. It describes the fixed power management hardware.
+        const FADT = 1 << 2;
+        /// The HPET table was found. See [`AcpiSummary::hpet`].
+        const HPET = 1 << 3;
+        /// The sleep type of the soft-off state was found in the DSDT, and the kernel is able to
+        /// shut the machine down.
+        const SOFT_OFF = 1 << 4;
+    }
+}
+
+bitflags! {
+    /// The capabilities of a [`HpetDescription`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct HpetFlags: u8 {
+        /// The main counter is 64 bits wide. Otherwise, it is 32 bits wide.
+        const COUNTER_64BIT = 1 << 0;
+        /// The HPET can replace the PIT and the RTC through the legacy replacement route.
+        const LEGACY_REPLACEMENT = 1 << 1;
+    }
+}
+
+/// The High Precision Event Timer described by the ACPI HPET table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HpetDescription {
+    /// The physical address of the registers of the HPET, or 0 if the system has none.
+    pub address: u64,
+    /// The minimum number of ticks of the main counter that a periodic comparator can be
+    /// programmed with without losing interrupts.
+    pub minimum_tick: u16,
+    /// The number of comparators of the HPET.
+    pub comparator_count: u8,
+    /// The capabilities of the HPET.
+    pub flags: HpetFlags,
+
+    pub _reserved: u32,
+}
+
+assert_layout!(HpetDescription, size = 16, align = 8, {
+    address: 0,
+    minimum_tick: 8,
+    comparator_count: 10,
+    flags: 11,
+    _reserved: 12,
+});
+
+/// A read-only copy of the parts of the ACPI tables that the kernel parsed during boot.
+///
+/// The kernel keeps the hardware described by those tables for itself, but userspace drivers
+/// may need to know about it. Tables that the kernel does not parse are not described here.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AcpiSummary {
+    /// The tables that were found.
+    pub flags: AcpiFlags,
+    /// The number of I/O APICs described by the MADT.
+    pub io_apic_count: u32,
+    /// The HPET of the system. Its address is 0 unless [`AcpiFlags::HPET`] is set.
+    pub hpet: HpetDescription,
+}
+
+assert_layout!(AcpiSummary, size = 24, align = 8, {
+    flags: 0,
+    io_apic_count: 4,
+    hpet: 8,
+});
+
+impl AcpiSummary {
+    /// The summary of a system without ACPI tables.
+    pub const EMPTY: Self = Self {
+        flags: AcpiFlags::empty(),
+        io_apic_count: 0,
+        hpet: HpetDescription {
+            address: 0,
+            minimum_tick: 0,
+            comparator_count: 0,
+            flags: HpetFlags::empty(),
+            _reserved: 0,
+        },
+    };
+}
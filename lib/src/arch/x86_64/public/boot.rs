@@ -0,0 +1,113 @@
+use bitflags::bitflags;
+
+use crate::layout::assert_layout;
+
+/// What the kernel does when the init process exits, as selected by the `init.on_exit` option
+/// of the kernel command line.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitExitPolicy {
+    /// The machine is halted.
+    Halt,
+    /// The machine is reset.
+    Reboot,
+    /// A new init process is started from the same image.
+    Restart,
+}
+
+/// The tick source requested with the `timer` option of the kernel command line.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerPreference {
+    /// The local APIC timer is used when it is reliable, and the PIT otherwise.
+    Auto,
+    /// The local APIC timer is used unless it does not work at all.
+    LocalApic,
+    /// The PIT is always used.
+    Pit,
+}
+
+bitflags! {
+    /// The information written before every record of the kernel log, as selected by the
+    /// `log.prefix` option of the kernel command line.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct LogPrefix: u8 {
+        /// The time elapsed since boot.
+        const TIMESTAMP = 1 << 0;
+        /// The ID of the CPU that emitted the record.
+        const CPU = 1 << 1;
+    }
+}
+
+bitflags! {
+    /// The features that the kernel decided to use during boot, depending on the kernel command
+    /// line and on the capabilities of the machine.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct BootFlags: u32 {
+        /// The kernel runs with supervisor shadow stacks.
+        ///
+        /// They can be disabled with the `cet=off` option.
+        const SHADOW_STACKS = 1 << 0;
+
+        /// The kernel uses page table isolation.
+        ///
+        /// It is enabled with the `pti=on` option.
+        const PAGE_TABLE_ISOLATION = 1 << 1;
+
+        /// The system has no usable framebuffer, and is meant to be driven through the serial
+        /// port.
+        ///
+        /// This is the case when the bootloader found no supported framebuffer, or when the
+        /// `headless` option is passed.
+        /// [`PublicData::framebuffers`](super::PublicData::framebuffers) is then empty. See
+        /// [`PublicData::is_headless`](super::PublicData::is_headless).
+        const HEADLESS = 1 << 2;
+
+        /// The initial stack and the base of the mappings placed by the kernel are randomized
+        /// for every process.
+        ///
+        /// It is enabled with the `aslr=on` option.
+        const ASLR = 1 << 3;
+
+        /// The kernel removed the sources of nondeterminism from its boot process, so that its
+        /// output is reproducible.
+        ///
+        /// It is enabled with the `deterministic` option.
+        const DETERMINISTIC = 1 << 4;
+    }
+}
+
+/// The configuration of the kernel, once its command line has been parsed.
+///
+/// Options that are not known to the kernel are ignored by it, but remain available in the raw
+/// command line (see [`PublicData::cmdline`](super::PublicData::cmdline)).
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootConfig {
+    /// The amount of physical memory that the kernel may use, in bytes.
+    ///
+    /// This takes the `max_memory` option into account.
+    pub max_physical_memory: u64,
+    /// What the kernel does when the init process exits.
+    pub init_exit_policy: InitExitPolicy,
+    /// The requested tick source.
+    pub timer_preference: TimerPreference,
+    /// The information written before every record of the kernel log.
+    pub log_prefix: LogPrefix,
+
+    pub _reserved: u8,
+
+    /// The features that the kernel decided to use during boot.
+    pub flags: BootFlags,
+}
+
+assert_layout!(BootConfig, size = 16, align = 8, {
+    max_physical_memory: 0,
+    init_exit_policy: 8,
+    timer_preference: 9,
+    log_prefix: 10,
+    _reserved: 11,
+    flags: 12,
+});
@@ -0,0 +1,202 @@
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::sync::atomic::{fence, AtomicU32, AtomicU64};
+
+use crate::layout::assert_layout;
+
+/// The parameters used to convert readings of the time-stamp counter (TSC) into the time elapsed
+/// since boot, in nanoseconds.
+///
+/// The conversion is the one used by the monotonic clock of the kernel:
+///
+/// ```text
+/// ns = offset_ns + ((tsc - tsc_base) * multiplier) >> shift
+/// ```
+///
+/// The multiplication is performed on 128 bits, and cannot overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockParams {
+    /// The frequency of the TSC, in hertz, or 0 if the TSC has not been calibrated.
+    pub tsc_frequency: u64,
+    /// The value of the TSC at `offset_ns`.
+    pub tsc_base: u64,
+    /// The number of nanoseconds elapsed since boot when the TSC was equal to `tsc_base`.
+    pub offset_ns: u64,
+    /// The number of nanoseconds per TSC cycle, as a fixed point number with `shift` fractional
+    /// bits.
+    pub multiplier: u32,
+    /// The number of fractional bits of `multiplier`.
+    pub shift: u32,
+}
+
+impl ClockParams {
+    /// The parameters of a clock whose TSC has not been calibrated.
+    ///
+    /// [`tsc_to_ns`](Self::tsc_to_ns) always returns `offset_ns` with those parameters.
+    pub const UNCALIBRATED: Self = Self {
+        tsc_frequency: 0,
+        tsc_base: 0,
+        offset_ns: 0,
+        multiplier: 0,
+        shift: 0,
+    };
+
+    /// Computes the parameters of a clock whose TSC runs at `tsc_frequency` hertz, and was equal
+    /// to `tsc_base` after `offset_ns` nanoseconds.
+    ///
+    /// The multiplier is given as many fractional bits as possible, up to 32.
+    pub fn new(tsc_base: u64, tsc_frequency: u64, offset_ns: u64) -> Self {
+        if tsc_frequency == 0 {
+            return Self {
+                tsc_base,
+                offset_ns,
+                ..Self::UNCALIBRATED
+            };
+        }
+
+        let mut shift = 32;
+        let mut multiplier = (1_000_000_000u128 << shift) / tsc_frequency as u128;
+        while multiplier > u32::MAX as u128 && shift > 0 {
+            shift -= 1;
+            multiplier = (1_000_000_000u128 << shift) / tsc_frequency as u128;
+        }
+
+        Self {
+            tsc_frequency,
+            tsc_base,
+            offset_ns,
+            multiplier: multiplier.min(u32::MAX as u128) as u32,
+            shift,
+        }
+    }
+
+    /// Returns whether the TSC has been calibrated.
+    #[inline(always)]
+    pub fn is_calibrated(&self) -> bool {
+        self.tsc_frequency != 0
+    }
+
+    /// Converts a reading of the TSC into the number of nanoseconds elapsed since boot.
+    #[inline]
+    pub fn tsc_to_ns(&self, tsc: u64) -> u64 {
+        let cycles = tsc.wrapping_sub(self.tsc_base) as u128;
+        let ns = (cycles * self.multiplier as u128) >> self.shift;
+        self.offset_ns.wrapping_add(ns as u64)
+    }
+}
+
+/// The [`ClockParams`] of the kernel, shared with userspace processes.
+///
+/// # Protocol
+///
+/// The parameters may be updated by the kernel at any time, for example when the calibration of
+/// the TSC is refined. Updates are protected by a sequence lock:
+///
+/// - The kernel increments `sequence` before modifying the parameters, and increments it again
+///   once it is done. The sequence is odd while an update is in progress.
+///
+/// - Readers load `sequence`, then the parameters, then `sequence` again. The parameters are only
+///   consistent if the sequence was even and did not change in the meantime. Otherwise, they must
+///   be read again.
+///
+/// [`Clock::load`] implements the reading side of the protocol.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Clock {
+    /// The sequence number of the parameters.
+    pub sequence: AtomicU32,
+    /// See [`ClockParams::shift`].
+    pub shift: AtomicU32,
+    /// See [`ClockParams::multiplier`].
+    pub multiplier: AtomicU32,
+
+    pub _reserved: u32,
+
+    /// See [`ClockParams::tsc_frequency`].
+    pub tsc_frequency: AtomicU64,
+    /// See [`ClockParams::tsc_base`].
+    pub tsc_base: AtomicU64,
+    /// See [`ClockParams::offset_ns`].
+    pub offset_ns: AtomicU64,
+}
+
+assert_layout!(Clock, size = 40, align = 8, {
+    sequence: 0,
+    shift: 4,
+    multiplier: 8,
+    _reserved: 12,
+    tsc_frequency: 16,
+    tsc_base: 24,
+    offset_ns: 32,
+});
+
+impl Clock {
+    /// Creates a new [`Clock`] holding the provided parameters.
+    pub const fn new(params: ClockParams) -> Self {
+        Self {
+            sequence: AtomicU32::new(0),
+            shift: AtomicU32::new(params.shift),
+            multiplier: AtomicU32::new(params.multiplier),
+            _reserved: 0,
+            tsc_frequency: AtomicU64::new(params.tsc_frequency),
+            tsc_base: AtomicU64::new(params.tsc_base),
+            offset_ns: AtomicU64::new(params.offset_ns),
+        }
+    }
+
+    /// Reads a consistent copy of the parameters.
+    pub fn load(&self) -> ClockParams {
+        loop {
+            let sequence = self.sequence.load(Acquire);
+            if sequence & 1 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let params = ClockParams {
+                tsc_frequency: self.tsc_frequency.load(Relaxed),
+                tsc_base: self.tsc_base.load(Relaxed),
+                offset_ns: self.offset_ns.load(Relaxed),
+                multiplier: self.multiplier.load(Relaxed),
+                shift: self.shift.load(Relaxed),
+            };
+
+            fence(Acquire);
+            if self.sequence.load(Relaxed) == sequence {
+                return params;
+            }
+        }
+    }
+
+    /// Replaces the parameters.
+    ///
+    /// This is the writing side of the protocol, which is only used by the kernel. Concurrent
+    /// calls to this function are not allowed.
+    pub fn store(&self, params: ClockParams) {
+        let sequence = self.sequence.load(Relaxed);
+        self.sequence.store(sequence.wrapping_add(1), Relaxed);
+        fence(Release);
+
+        self.tsc_frequency.store(params.tsc_frequency, Relaxed);
+        self.tsc_base.store(params.tsc_base, Relaxed);
+        self.offset_ns.store(params.offset_ns, Relaxed);
+        self.multiplier.store(params.multiplier, Relaxed);
+        self.shift.store(params.shift, Relaxed);
+
+        self.sequence.store(sequence.wrapping_add(2), Release);
+    }
+
+    /// Returns the number of nanoseconds elapsed since boot, or `None` if the TSC has not been
+    /// calibrated by the kernel.
+    #[inline]
+    pub fn uptime_ns(&self) -> Option<u64> {
+        let params = self.load();
+        if !params.is_calibrated() {
+            return None;
+        }
+
+        // SAFETY:
+        //  The `rdtsc` instruction is available on every x86_64 CPU.
+        let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+        Some(params.tsc_to_ns(tsc))
+    }
+}
@@ -1,4 +1,6 @@
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::AtomicU64;
+
+use crate::layout::assert_layout;
 
 /// A color mode available for framebuffers.
 #[repr(u8)]
@@ -22,13 +24,13 @@ pub enum ColorMode {
 #[derive(Debug)]
 pub struct Framebuffer {
     /// The physical address of the framebuffer's in-memory buffer.
-    pub physical_address: usize,
+    pub physical_address: u64,
     /// The width of the framebuffer, in pixels.
-    pub width: usize,
+    pub width: u64,
     /// The height of the framebuffer, in pixels.
-    pub height: usize,
+    pub height: u64,
     /// The number of bytes taken by each row of the frame buffer.
-    pub pitch: usize,
+    pub pitch: u64,
     /// The color mode of the framebuffer.
     pub color_mode: ColorMode,
 
@@ -38,13 +40,26 @@ pub struct Framebuffer {
     ///
     /// When non zero, the framebuffer is in use by the process with the given ID. When `0`,
     /// the framebuffer is not being used.
-    pub owned_by: AtomicUsize,
+    ///
+    /// Values with the [`OWNED_BY_ESCROW`](super::OWNED_BY_ESCROW) bit set indicate that the
+    /// framebuffer is held in escrow by the kernel.
+    pub owned_by: AtomicU64,
 }
 
+assert_layout!(Framebuffer, size = 48, align = 8, {
+    physical_address: 0,
+    width: 8,
+    height: 16,
+    pitch: 24,
+    color_mode: 32,
+    _reserved: 33,
+    owned_by: 40,
+});
+
 impl Framebuffer {
     /// Returns the size of the framebuffer's in-memory buffer, in bytes.
     #[inline(always)]
-    pub fn size_in_bytes(&self) -> usize {
+    pub fn size_in_bytes(&self) -> u64 {
         self.pitch * self.height
     }
 }
@@ -0,0 +1,149 @@
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicU32, AtomicU64};
+
+use bitflags::bitflags;
+
+use crate::layout::assert_layout;
+
+bitflags! {
+    /// The announcements currently posted to an [`Inbox`].
+    ///
+    /// An announcement remains set for as long as the condition it describes holds. The kernel
+    /// clears it on its own once the condition is over.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct Announcements: u32 {
+        /// The system is low on memory. The process is asked to release the memory it does not
+        /// need, for example by unmapping its caches or marking them as purgeable.
+        const RELEASE_MEMORY = 1 << 0;
+        /// The process has been selected to be terminated to reclaim memory. Unless the
+        /// shortage is resolved in the meantime, it will be terminated once the deadline
+        /// returned by [`Inbox::termination_deadline_ns`] is reached.
+        const TERMINATION_WARNING = 1 << 1;
+    }
+}
+
+/// A page of memory where the kernel posts announcements to a single process.
+///
+/// Every process has its own inbox, mapped read-only at the address found in
+/// [`PublicData::inbox`](super::PublicData::inbox). Polling it between two system calls is much
+/// cheaper than receiving the equivalent events from a port, and requires no setup at all.
+///
+/// # Protocol
+///
+/// The kernel increments `sequence` after each update of the inbox. A process that saves the
+/// sequence number it last observed can check whether anything changed with a single load, as
+/// done by [`Inbox::has_changed`]. The fields are updated independently from each other: reading
+/// two of them does not give a consistent snapshot.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Inbox {
+    /// The number of updates performed by the kernel.
+    pub sequence: AtomicU32,
+    /// The [`Announcements`] currently posted.
+    pub announcements: AtomicU32,
+    /// The total number of messages pending in the ports owned by the process.
+    pub pending_messages: AtomicU32,
+
+    pub _reserved: u32,
+
+    /// The time at which the process will be terminated, in nanoseconds since boot.
+    ///
+    /// This is only meaningful while [`Announcements::TERMINATION_WARNING`] is set.
+    pub termination_deadline_ns: AtomicU64,
+}
+
+assert_layout!(Inbox, size = 24, align = 8, {
+    sequence: 0,
+    announcements: 4,
+    pending_messages: 8,
+    _reserved: 12,
+    termination_deadline_ns: 16,
+});
+
+impl Inbox {
+    /// Returns the number of updates performed by the kernel so far.
+    #[inline(always)]
+    pub fn sequence(&self) -> u32 {
+        self.sequence.load(Acquire)
+    }
+
+    /// Returns whether the inbox has been updated since the sequence number `last` was
+    /// observed, and stores the current sequence number in `last`.
+    #[inline]
+    pub fn has_changed(&self, last: &mut u32) -> bool {
+        let sequence = self.sequence();
+        let changed = sequence != *last;
+        *last = sequence;
+        changed
+    }
+
+    /// Returns the announcements currently posted.
+    #[inline(always)]
+    pub fn announcements(&self) -> Announcements {
+        Announcements::from_bits_retain(self.announcements.load(Acquire))
+    }
+
+    /// Returns the total number of messages pending in the ports owned by the process.
+    #[inline(always)]
+    pub fn pending_messages(&self) -> u32 {
+        self.pending_messages.load(Acquire)
+    }
+
+    /// Returns the time at which the process will be terminated, in nanoseconds since boot, or
+    /// `None` if no termination warning is posted.
+    #[inline]
+    pub fn termination_deadline_ns(&self) -> Option<u64> {
+        if !self
+            .announcements()
+            .contains(Announcements::TERMINATION_WARNING)
+        {
+            return None;
+        }
+
+        Some(self.termination_deadline_ns.load(Relaxed))
+    }
+
+    /// Posts `set` and withdraws `clear`.
+    ///
+    /// This is the writing side of the protocol, which is only used by the kernel.
+    pub fn post(&self, set: Announcements, clear: Announcements) {
+        let old = self.announcements.load(Relaxed);
+        let new = (old & !clear.bits()) | set.bits();
+
+        if old != new {
+            self.announcements.store(new, Release);
+            self.sequence.fetch_add(1, Release);
+        }
+    }
+
+    /// Posts a [`Announcements::TERMINATION_WARNING`] with the provided deadline.
+    ///
+    /// This is only used by the kernel.
+    pub fn warn_termination(&self, deadline_ns: u64) {
+        self.termination_deadline_ns.store(deadline_ns, Relaxed);
+        self.announcements
+            .fetch_or(Announcements::TERMINATION_WARNING.bits(), Release);
+        self.sequence.fetch_add(1, Release);
+    }
+
+    /// Updates the number of pending messages.
+    ///
+    /// This is only used by the kernel.
+    pub fn set_pending_messages(&self, count: u32) {
+        if self.pending_messages.swap(count, Release) != count {
+            self.sequence.fetch_add(1, Release);
+        }
+    }
+}
+
+/// Returns the [`Inbox`] of the current process.
+///
+/// The process must not unmap its inbox.
+#[cfg(feature = "userland")]
+#[inline(always)]
+pub fn inbox() -> &'static Inbox {
+    // SAFETY:
+    //  The inbox is mapped in every process at this address.
+    unsafe { &*(super::get().inbox as *const Inbox) }
+}
@@ -3,25 +3,164 @@
 //!
 //! Specifically, an instance of [`PublicData`] is mapped in every userspace process and is
 //! freely accessible.
+//!
+//! # Headless Systems
+//!
+//! Servers often have no display at all, and the kernel may be asked to ignore the framebuffers
+//! with the `headless` option of its command line. In both cases, the list of framebuffers is
+//! empty and [`BootFlags::HEADLESS`] is set.
+//!
+//! To work the same way on every system, the init process should be serial-first:
+//!
+//! 1. Its diagnostics are always written with [`debug_log`](super::debug_log), which reaches the
+//!    serial port (or the sink selected with the `log` option) whether a display exists or not.
+//!
+//! 2. It only acquires a framebuffer when [`PublicData::primary_framebuffer`] returns one, and
+//!    treats the display as an additional output rather than the main one.
+
+use crate::layout::assert_layout;
 
+mod acpi;
+mod boot;
+mod clock;
 mod framebuffer;
+mod inbox;
+mod pci;
 
+pub use self::acpi::*;
+pub use self::boot::*;
+pub use self::clock::*;
 pub use self::framebuffer::*;
+pub use self::inbox::*;
+pub use self::pci::*;
+
+/// The bit set in the `owned_by` field of the framebuffers and PCI devices that the kernel holds
+/// in escrow after their owner crashed.
+///
+/// Such resources cannot be acquired until the escrow is reclaimed or released. See
+/// [`CrashNotice`](crate::CrashNotice).
+pub const OWNED_BY_ESCROW: u64 = 1 << 63;
 
 /// An instance of this structure is mapped in the address space of all processes.
+///
+/// # Layout
+///
+/// The structures shared with userspace only use fixed-width fields. Instead of pointers, the
+/// other parts of the public data area are referenced by their offset, in bytes, from the start
+/// of this structure. This way, the layout does not depend on the pointer width of the process
+/// that reads it.
 #[repr(C)]
 pub struct PublicData {
-    /// The framebuffers available to the system.
-    pub framebuffers: *const Framebuffer,
+    /// The offset of the framebuffers available to the system.
+    pub framebuffers: u64,
     /// The total number of available framebuffers.
-    pub framebuffer_count: usize,
+    pub framebuffer_count: u64,
+    /// The offset of the PCI devices connected to the system.
+    pub pci_devices: u64,
+    /// The total number of available PCI devices.
+    pub pci_device_count: u64,
+    /// The offset of the command line passed to the kernel by the bootloader.
+    pub cmdline: u64,
+    /// The length of the command line, in bytes.
+    pub cmdline_length: u64,
+    /// The configuration of the kernel.
+    pub boot_config: BootConfig,
+    /// The parameters of the monotonic clock of the kernel.
+    ///
+    /// This allows processes to read the time elapsed since boot without performing a system
+    /// call.
+    pub clock: Clock,
+    /// The newest version of the system call ABI implemented by the kernel.
+    ///
+    /// See [`ABI_VERSION`](super::ABI_VERSION).
+    pub abi_version: u32,
+    /// The oldest version of the system call ABI implemented by the kernel.
+    pub min_abi_version: u32,
+    /// The virtual address of the [`Inbox`] of the running process.
+    ///
+    /// Each process has its own inbox, but all of them are mapped at this address.
+    pub inbox: u64,
+    /// The parts of the ACPI tables that the kernel parsed during boot.
+    pub acpi: AcpiSummary,
 }
 
+assert_layout!(PublicData, size = 144, align = 8, {
+    framebuffers: 0,
+    framebuffer_count: 8,
+    pci_devices: 16,
+    pci_device_count: 24,
+    cmdline: 32,
+    cmdline_length: 40,
+    boot_config: 48,
+    clock: 64,
+    abi_version: 104,
+    min_abi_version: 108,
+    inbox: 112,
+    acpi: 120,
+});
+
 impl PublicData {
+    /// Returns the `count` instances of `T` stored at `offset` bytes from the start of the
+    /// public data.
+    ///
+    /// # Safety
+    ///
+    /// `offset` and `count` must describe a part of the public data area holding instances of
+    /// `T`.
+    #[inline(always)]
+    unsafe fn slice_at<T>(&self, offset: u64, count: u64) -> &[T] {
+        unsafe {
+            let base = (self as *const Self as *const u8).add(offset as usize) as *const T;
+            core::slice::from_raw_parts(base, count as usize)
+        }
+    }
+
     /// Returns the list of all framebuffers.
     #[inline(always)]
     pub fn framebuffers(&self) -> &[Framebuffer] {
-        unsafe { core::slice::from_raw_parts(self.framebuffers, self.framebuffer_count) }
+        unsafe { self.slice_at(self.framebuffers, self.framebuffer_count) }
+    }
+
+    /// Returns whether the system runs without a framebuffer.
+    ///
+    /// See the [module-level documentation](self) for more information.
+    #[inline(always)]
+    pub fn is_headless(&self) -> bool {
+        self.boot_config.flags.contains(BootFlags::HEADLESS) || self.framebuffer_count == 0
+    }
+
+    /// Returns the framebuffer that should be used as the main display, if any.
+    ///
+    /// `None` is returned on headless systems.
+    #[inline(always)]
+    pub fn primary_framebuffer(&self) -> Option<&Framebuffer> {
+        self.framebuffers().first()
+    }
+
+    /// Returns the list of all PCI devices.
+    #[inline(always)]
+    pub fn pci_devices(&self) -> &[PciDevice] {
+        unsafe { self.slice_at(self.pci_devices, self.pci_device_count) }
+    }
+
+    /// Returns whether the kernel implements the version of the system call ABI that this crate
+    /// was built for.
+    ///
+    /// Programs should check this at startup. When it returns `false`, they may still use the
+    /// system calls reported as supported by [`kernel_info`](super::kernel_info), which is part
+    /// of every version of the ABI.
+    #[inline(always)]
+    pub fn is_abi_compatible(&self) -> bool {
+        (self.min_abi_version..=self.abi_version).contains(&super::ABI_VERSION)
+    }
+
+    /// Returns the command line passed to the kernel by the bootloader.
+    ///
+    /// The command line is a list of options separated by whitespaces, including the ones that
+    /// are not known to the kernel.
+    #[inline(always)]
+    pub fn cmdline(&self) -> &[u8] {
+        unsafe { self.slice_at(self.cmdline, self.cmdline_length) }
     }
 }
 
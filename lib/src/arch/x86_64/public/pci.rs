@@ -0,0 +1,119 @@
+use core::sync::atomic::AtomicU64;
+
+use bitflags::bitflags;
+
+use crate::layout::assert_layout;
+
+/// The maximum number of base address registers of a PCI device.
+pub const PCI_BAR_COUNT: usize = 6;
+
+bitflags! {
+    /// Describes a [`PciBar`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct PciBarFlags: u32 {
+        /// Whether the base address register is implemented by the device.
+        ///
+        /// When this flag is not set, the other fields of the [`PciBar`] are meaningless. This
+        /// is notably the case for the register that holds the upper half of a 64-bit base
+        /// address register.
+        const PRESENT = 1 << 0;
+
+        /// Whether the register refers to I/O ports rather than to memory.
+        const IO = 1 << 1;
+
+        /// Whether reading the memory of the register has no side effects.
+        const PREFETCHABLE = 1 << 2;
+
+        /// Whether the register is a 64-bit register, spanning two slots.
+        const IS_64BIT = 1 << 3;
+
+        /// Whether the memory of the register may be mapped by the owner of the device.
+        ///
+        /// Memory registers that are not assigned an address, that are not aligned to a page
+        /// boundary, or that share a page with other resources cannot be mapped.
+        const MAPPABLE = 1 << 4;
+    }
+}
+
+/// Information about a base address register of a [`PciDevice`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PciBar {
+    /// The physical address of the memory of the register, or the first I/O port it refers to.
+    pub address: u64,
+    /// The size of the resource, in bytes.
+    pub size: u64,
+    /// Describes the register.
+    pub flags: PciBarFlags,
+
+    pub _reserved: [u8; 4],
+}
+
+assert_layout!(PciBar, size = 24, align = 8, {
+    address: 0,
+    size: 8,
+    flags: 16,
+    _reserved: 20,
+});
+
+/// Information about a PCI device function.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PciDevice {
+    /// The bus the device is connected to.
+    pub bus: u8,
+    /// The number of the device on its bus.
+    pub device: u8,
+    /// The function number of the device.
+    pub function: u8,
+    /// The class code of the device.
+    pub class: u8,
+    /// The subclass code of the device.
+    pub subclass: u8,
+    /// The programming interface of the device.
+    pub prog_if: u8,
+    /// The ID of the vendor of the device.
+    pub vendor_id: u16,
+    /// The ID of the device, as assigned by its vendor.
+    pub device_id: u16,
+    /// The legacy interrupt line assigned to the device by the firmware, or `0xFF` if none was
+    /// assigned.
+    ///
+    /// The owner of the device may receive its interrupts with
+    /// [`bind_irq`](crate::x86_64::bind_irq).
+    pub interrupt_line: u8,
+    /// The interrupt pin used by the device (1 for INTA# to 4 for INTD#), or 0 if the device does
+    /// not use legacy interrupts.
+    pub interrupt_pin: u8,
+
+    pub _reserved: [u8; 4],
+
+    /// The base address registers of the device.
+    pub bars: [PciBar; PCI_BAR_COUNT],
+
+    /// The ID of the process that owns the device, if any.
+    ///
+    /// When non zero, the device is in use by the process with the given ID. When `0`, the
+    /// device is not being used.
+    ///
+    /// Values with the [`OWNED_BY_ESCROW`](super::OWNED_BY_ESCROW) bit set indicate that the
+    /// device is held in escrow by the kernel.
+    pub owned_by: AtomicU64,
+}
+
+assert_layout!(PciDevice, size = 168, align = 8, {
+    bus: 0,
+    device: 1,
+    function: 2,
+    class: 3,
+    subclass: 4,
+    prog_if: 5,
+    vendor_id: 6,
+    device_id: 8,
+    interrupt_line: 10,
+    interrupt_pin: 11,
+    _reserved: 12,
+    bars: 16,
+    owned_by: 160,
+});
@@ -0,0 +1,157 @@
+//! Backtraces for userspace programs, enabled by the `backtrace` feature.
+//!
+//! # Frame Pointers
+//!
+//! Backtraces are computed by following the chain of frame pointers saved on the stack. Programs
+//! must therefore be compiled with frame pointers enabled, for example by adding
+//! `-C force-frame-pointers=yes` to their `RUSTFLAGS`. Functions compiled without frame pointers
+//! are skipped or end the backtrace early.
+//!
+//! # Symbols
+//!
+//! Return addresses are printed as raw addresses unless the program registers a symbol table
+//! with [`set_symbols`]. Such a table is usually generated at build time from the symbols of the
+//! program.
+
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering::*;
+use core::sync::atomic::{AtomicPtr, AtomicUsize};
+
+use crate::fmt_buffer::Buffer;
+use crate::x86_64::{debug_log, query_mapping, MappingInfo};
+
+/// The maximum number of frames reported by [`walk`].
+pub const MAX_FRAMES: usize = 64;
+
+/// A symbol of the program, used to resolve return addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    /// The address of the first instruction of the symbol.
+    pub address: usize,
+    /// The name of the symbol.
+    pub name: &'static str,
+}
+
+/// The start of the symbol table registered with [`set_symbols`].
+static SYMBOLS_PTR: AtomicPtr<Symbol> = AtomicPtr::new(core::ptr::null_mut());
+/// The length of the symbol table registered with [`set_symbols`].
+static SYMBOLS_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers the symbol table used to resolve return addresses.
+///
+/// The symbols must be sorted by address.
+pub fn set_symbols(symbols: &'static [Symbol]) {
+    SYMBOLS_LEN.store(0, Release);
+    SYMBOLS_PTR.store(symbols.as_ptr() as *mut Symbol, Release);
+    SYMBOLS_LEN.store(symbols.len(), Release);
+}
+
+/// Returns the symbol table registered with [`set_symbols`].
+fn symbols() -> &'static [Symbol] {
+    let len = SYMBOLS_LEN.load(Acquire);
+    let ptr = SYMBOLS_PTR.load(Acquire);
+
+    if len == 0 {
+        return &[];
+    }
+
+    // SAFETY:
+    //  The pointer and the length come from a `&'static [Symbol]`.
+    unsafe { core::slice::from_raw_parts(ptr, len) }
+}
+
+/// Returns the symbol that contains the provided address, along with the offset of the address
+/// within it.
+pub fn resolve(address: usize) -> Option<(&'static Symbol, usize)> {
+    let symbols = symbols();
+    let index = symbols.partition_point(|s| s.address <= address);
+    let symbol = symbols.get(index.checked_sub(1)?)?;
+    Some((symbol, address - symbol.address))
+}
+
+/// Returns whether the two words at `address` can be read.
+fn is_readable_frame(address: usize) -> bool {
+    if !address.is_multiple_of(core::mem::align_of::<usize>()) {
+        return false;
+    }
+
+    let mut info = MaybeUninit::<MappingInfo>::uninit();
+    if query_mapping(None, address, &mut info).0 != 1 {
+        return false;
+    }
+
+    // SAFETY:
+    //  The kernel initialized the structure when it returned 1.
+    let info = unsafe { info.assume_init() };
+    address + 2 * core::mem::size_of::<usize>() <= info.start + info.length
+}
+
+/// Calls `f` with the return address of each frame of the current call stack, starting with the
+/// caller of this function.
+///
+/// At most [`MAX_FRAMES`] frames are reported.
+#[inline(never)]
+pub fn walk(mut f: impl FnMut(usize)) {
+    let mut frame: usize;
+
+    // SAFETY:
+    //  Reading the frame pointer has no side effects.
+    unsafe {
+        core::arch::asm!(
+            "mov {}, rbp",
+            out(reg) frame,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+
+    for _ in 0..MAX_FRAMES {
+        if frame == 0 || !is_readable_frame(frame) {
+            break;
+        }
+
+        // SAFETY:
+        //  We checked that the frame is mapped. A frame starts with the saved frame pointer of
+        //  the caller, followed by the return address.
+        let (next, return_address) = unsafe {
+            let words = frame as *const usize;
+            (*words, *words.add(1))
+        };
+
+        if return_address == 0 {
+            break;
+        }
+
+        f(return_address);
+
+        // The stack grows downwards, so the frames of callers are always at higher addresses.
+        if next <= frame {
+            break;
+        }
+
+        frame = next;
+    }
+}
+
+/// Writes a backtrace of the current call stack to the log of the kernel.
+#[inline(never)]
+pub fn print() {
+    let _ = debug_log("backtrace:");
+
+    let mut index = 0;
+    walk(|address| {
+        let mut buffer = Buffer::new();
+
+        let _ = match resolve(address) {
+            Some((symbol, offset)) => write!(
+                buffer,
+                "  #{index:<2} {address:#018x} {}+{offset:#x}",
+                symbol.name
+            ),
+            None => write!(buffer, "  #{index:<2} {address:#018x} <unknown>"),
+        };
+
+        let _ = debug_log(buffer.as_str());
+        index += 1;
+    });
+}
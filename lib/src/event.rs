@@ -0,0 +1,99 @@
+//! Events delivered by the kernel to userspace processes.
+//!
+//! A process subscribes to a kind of event by providing one of its ports to the
+//! [`subscribe_event`](crate::x86_64::subscribe_event) system call. When the event occurs, the
+//! kernel sends an [`Event`] message to that port. Messages sent by the kernel have a sender ID
+//! of 0.
+//!
+//! Events, like the notices of the interrupts delivered with
+//! [`bind_irq`](crate::x86_64::bind_irq), carry the time at which they occurred and a sequence
+//! number, which lets the subscriber measure its latency and detect the events it missed.
+
+use crate::ipc::Pod;
+use crate::layout::assert_layout;
+
+/// A kind of event that the kernel may deliver to a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum EventKind {
+    /// The power button of the machine has been pressed.
+    ///
+    /// When no process is subscribed to this event, the kernel shuts the machine down.
+    PowerButton,
+
+    /// The kernel failed to allocate physical memory, even after reclaiming purgeable memory.
+    ///
+    /// [`Event::data`] holds the number of pages that were missing. The subscriber, usually the
+    /// memory manager of the system, is given a grace period to free memory before the kernel
+    /// applies its last-resort policy, which by default terminates the process that owns the
+    /// most memory.
+    ///
+    /// While a shortage is going on, the kernel does not deliver this event again.
+    OutOfMemory,
+
+    /// The number of free pages fell below the low watermark of the kernel, and reclaiming
+    /// purgeable memory was not enough to climb back above it.
+    ///
+    /// [`Event::data`] holds the number of free pages. The subscriber is expected to release
+    /// memory before allocations start failing.
+    ///
+    /// The event is not delivered again until the number of free pages has climbed back above
+    /// the high watermark.
+    LowMemory,
+}
+
+impl EventKind {
+    /// The number of distinct event kinds.
+    pub const COUNT: usize = 3;
+
+    /// Converts the provided raw value into an [`EventKind`].
+    #[inline]
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(Self::PowerButton),
+            1 => Some(Self::OutOfMemory),
+            2 => Some(Self::LowMemory),
+            _ => None,
+        }
+    }
+}
+
+/// The content of a message sent by the kernel to notify a process of an event.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Event {
+    /// The raw [`EventKind`] of the event.
+    pub kind: usize,
+    /// Additional information about the event, whose meaning depends on its kind.
+    ///
+    /// This is 0 for events that carry no information.
+    pub data: usize,
+    /// The time at which the event occurred, in nanoseconds since boot.
+    ///
+    /// This is measured with the clock published in the public data area (see
+    /// [`Clock`](crate::x86_64::public::Clock)), which lets the subscriber measure how long the
+    /// event took to be handled.
+    pub timestamp: u64,
+    /// The number of events of the same kind that occurred before this one.
+    ///
+    /// Events that could not be delivered are counted as well: a gap between the sequence numbers
+    /// of two consecutive events indicates that the subscriber missed some of them.
+    pub sequence: u64,
+}
+
+assert_layout!(Event, size = 32, align = 8, {
+    kind: 0,
+    data: 8,
+    timestamp: 16,
+    sequence: 24,
+});
+
+unsafe impl Pod for Event {}
+
+impl Event {
+    /// Returns the kind of the event, if it is known.
+    #[inline(always)]
+    pub const fn kind(&self) -> Option<EventKind> {
+        EventKind::from_raw(self.kind)
+    }
+}
@@ -0,0 +1,42 @@
+//! A small formatting buffer used to build messages for [`debug_log`](crate::x86_64::debug_log)
+//! without allocating.
+
+use core::fmt;
+
+use crate::x86_64::MAX_DEBUG_LOG_LENGTH;
+
+/// A fixed-size buffer that silently truncates what is written past its capacity.
+pub struct Buffer {
+    data: [u8; MAX_DEBUG_LOG_LENGTH],
+    len: usize,
+}
+
+impl Buffer {
+    /// Creates a new empty [`Buffer`].
+    pub fn new() -> Self {
+        Self {
+            data: [0; MAX_DEBUG_LOG_LENGTH],
+            len: 0,
+        }
+    }
+
+    /// Returns the content of the buffer.
+    pub fn as_str(&self) -> &str {
+        // SAFETY:
+        //  Only complete UTF-8 sequences are ever written to the buffer.
+        unsafe { core::str::from_utf8_unchecked(&self.data[..self.len]) }
+    }
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(self.data.len() - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.data[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+//! Types used to exchange messages between processes.
+//!
+//! The raw system calls are defined in the architecture-specific module. This module provides
+//! typed wrappers over them: a [`Port<T>`] receives messages of type `T`, and a [`Sender<T>`]
+//! sends them. Messages are plain `#[repr(C)]` values copied byte-for-byte, which is why their
+//! type must implement [`Pod`].
+
+use core::num::NonZeroUsize;
+
+mod pod;
+
+#[cfg(feature = "userland")]
+mod port;
+
+pub use self::pod::*;
+
+#[cfg(feature = "userland")]
+pub use self::port::*;
+
+/// The ID of a port.
+///
+/// No port can have the ID zero, which is why this type simply is a [`NonZeroUsize`].
+///
+/// The ID of a destroyed port is not given to the next ports: messages sent to a stale ID are
+/// rejected instead of reaching an unrelated port.
+pub type PortId = NonZeroUsize;
@@ -0,0 +1,34 @@
+/// Types that can be safely sent to another process by copying their bytes.
+///
+/// # Safety
+///
+/// Implementors must not contain any padding or pointer, and every bit pattern must be a valid
+/// value of the type. In practice, this means that the type is `#[repr(C)]` (or
+/// `#[repr(transparent)]`) and only contains other [`Pod`] types.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($t:ty),* $(,)?) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+
+impl_pod!(
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    ()
+);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
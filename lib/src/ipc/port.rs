@@ -0,0 +1,211 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+use crate::x86_64::{self, MessageInfo, MAX_MESSAGE_SIZE};
+use crate::{PortId, ProcessId, SysError};
+
+use super::Pod;
+
+/// Fails to compile when `T` does not fit in a single message.
+struct AssertFits<T>(PhantomData<T>);
+
+impl<T> AssertFits<T> {
+    const OK: () = assert!(
+        size_of::<T>() <= MAX_MESSAGE_SIZE,
+        "the message type is too large"
+    );
+}
+
+/// Returns the bytes of the provided value.
+#[inline(always)]
+fn bytes_of<T: Pod>(value: &T) -> &[u8] {
+    // SAFETY:
+    //  `Pod` types have no padding, so all their bytes are initialized.
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+/// A message received from a [`Port`].
+#[derive(Debug, Clone, Copy)]
+pub struct Received<T> {
+    /// The content of the message.
+    pub message: T,
+    /// The ID of the process that sent the message.
+    pub sender: ProcessId,
+    /// The port attached to the message, if any.
+    pub attachment: Option<PortId>,
+}
+
+/// A port owned by the current process, receiving messages of type `T`.
+///
+/// The port is destroyed when this value is dropped.
+pub struct Port<T: Pod> {
+    id: PortId,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Pod> Port<T> {
+    /// Creates a new port.
+    ///
+    /// # Errors
+    ///
+    /// See [`create_port`](x86_64::create_port).
+    pub fn create() -> Result<Self, SysError> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertFits::<T>::OK;
+
+        let id = x86_64::create_port().into_result()?;
+
+        Ok(Self {
+            // SAFETY:
+            //  The kernel never returns the ID zero for a port.
+            id: unsafe { PortId::new_unchecked(id) },
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns the ID of the port.
+    #[inline(always)]
+    pub fn id(&self) -> PortId {
+        self.id
+    }
+
+    /// Returns a [`Sender`] that sends messages to this port.
+    #[inline(always)]
+    pub fn sender(&self) -> Sender<T> {
+        Sender::from_id(self.id)
+    }
+
+    /// Receives a message without waiting.
+    ///
+    /// # Errors
+    ///
+    /// [`SysError::WouldBlock`] is returned if no message is available. Messages whose size
+    /// does not match the size of `T` are discarded and reported as [`SysError::InvalidValue`].
+    pub fn try_recv(&self) -> Result<Received<T>, SysError> {
+        let mut message = MaybeUninit::<T>::uninit();
+        let mut info = MaybeUninit::<MessageInfo>::uninit();
+
+        // SAFETY:
+        //  The kernel only writes to the buffer, so it is fine to pass uninitialized memory.
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(message.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+
+        let length = x86_64::receive(self.id, buffer, Some(&mut info)).into_result()?;
+
+        if length != size_of::<T>() {
+            return Err(SysError::InvalidValue);
+        }
+
+        // SAFETY:
+        //  The kernel wrote the whole message and the information structure. Any bit pattern is
+        //  valid for `Pod` types, and the sender is never zero.
+        unsafe {
+            let info = info.assume_init();
+            Ok(Received {
+                message: message.assume_init(),
+                sender: ProcessId::new_unchecked(info.sender),
+                attachment: PortId::new(info.attachment),
+            })
+        }
+    }
+
+    /// Receives a message, sleeping until one is available.
+    pub fn recv(&self) -> Result<Received<T>, SysError> {
+        loop {
+            match self.try_recv() {
+                Err(SysError::WouldBlock) => (),
+                result => return result,
+            }
+
+            x86_64::wait_port(self.id, usize::MAX).into_result()?;
+        }
+    }
+
+    /// Receives a message, sleeping for at most `timeout` timer ticks until one is available.
+    ///
+    /// # Errors
+    ///
+    /// [`SysError::TimedOut`] is returned if no message was available before the timeout
+    /// expired.
+    pub fn recv_timeout(&self, timeout: usize) -> Result<Received<T>, SysError> {
+        match self.try_recv() {
+            Err(SysError::WouldBlock) => (),
+            result => return result,
+        }
+
+        x86_64::wait_port(self.id, timeout).into_result()?;
+
+        match self.try_recv() {
+            Err(SysError::WouldBlock) => Err(SysError::TimedOut),
+            result => result,
+        }
+    }
+}
+
+impl<T: Pod> Drop for Port<T> {
+    fn drop(&mut self) {
+        let _ = x86_64::destroy_port(self.id);
+    }
+}
+
+impl<T: Pod> fmt::Debug for Port<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Port").field(&self.id).finish()
+    }
+}
+
+/// Sends messages of type `T` to a port, possibly owned by another process.
+pub struct Sender<T: Pod> {
+    id: PortId,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Pod> Sender<T> {
+    /// Creates a [`Sender`] for the port with the provided ID.
+    ///
+    /// Nothing checks that the port actually expects messages of type `T`.
+    #[inline(always)]
+    pub const fn from_id(id: PortId) -> Self {
+        Self {
+            id,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the ID of the port.
+    #[inline(always)]
+    pub fn id(&self) -> PortId {
+        self.id
+    }
+
+    /// Sends a message to the port, optionally attaching the ID of a port owned by the current
+    /// process to it.
+    ///
+    /// # Errors
+    ///
+    /// See [`send`](x86_64::send).
+    pub fn send(&self, message: &T, attachment: Option<PortId>) -> Result<(), SysError> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertFits::<T>::OK;
+
+        x86_64::send(self.id, bytes_of(message), attachment).into_result()?;
+        Ok(())
+    }
+}
+
+impl<T: Pod> Clone for Sender<T> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Pod> Copy for Sender<T> {}
+
+impl<T: Pod> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Sender").field(&self.id).finish()
+    }
+}
@@ -0,0 +1,106 @@
+//! Compile-time checks of the layout of the structures shared between the kernel and userspace.
+//!
+//! Every `#[repr(C)]` structure that crosses the system call boundary, or that lives in memory
+//! shared with userspace, states its expected layout with [`assert_layout!`]. Changing the
+//! layout of one of those structures is a breaking change of the ABI of the kernel, and must be
+//! done on purpose: the assertion has to be updated along with the structure.
+//!
+//! The declared layouts are also summarized by [`checksum`], which the self-tests of the kernel
+//! compare against a known value.
+
+/// Asserts at compile time that a type has the provided size, alignment and field offsets.
+///
+/// ```ignore
+/// assert_layout!(MessageInfo, size = 16, align = 8, {
+///     sender: 0,
+///     attachment: 8,
+/// });
+/// ```
+macro_rules! assert_layout {
+    ($ty:ty, size = $size:expr, align = $align:expr, { $($field:ident: $offset:expr),* $(,)? }) => {
+        const _: () = {
+            assert!(::core::mem::size_of::<$ty>() == $size, "unexpected size");
+            assert!(::core::mem::align_of::<$ty>() == $align, "unexpected alignment");
+            $(
+                assert!(
+                    ::core::mem::offset_of!($ty, $field) == $offset,
+                    concat!("unexpected offset of `", stringify!($field), "`"),
+                );
+            )*
+        };
+
+        impl $crate::layout::SharedLayout for $ty {
+            const LAYOUT: &'static [usize] = &[$size, $align, $($offset),*];
+        }
+    };
+}
+
+pub(crate) use assert_layout;
+
+/// A structure whose layout is stated with [`assert_layout!`].
+pub(crate) trait SharedLayout {
+    /// The size, the alignment and the field offsets of the structure, in this order.
+    const LAYOUT: &'static [usize];
+}
+
+/// Returns a checksum of the layout of every structure shared between the kernel and userspace.
+///
+/// The checksum changes when the size, the alignment or the offset of a field of one of those
+/// structures changes. Structures whose layout is stated with [`assert_layout!`] must be listed
+/// here as well.
+#[cfg(target_arch = "x86_64")]
+pub const fn checksum() -> u64 {
+    use crate::x86_64::public;
+
+    const LAYOUTS: &[&[usize]] = &[
+        public::AcpiSummary::LAYOUT,
+        public::BootConfig::LAYOUT,
+        public::Clock::LAYOUT,
+        public::Framebuffer::LAYOUT,
+        public::HpetDescription::LAYOUT,
+        public::Inbox::LAYOUT,
+        public::PciBar::LAYOUT,
+        public::PciDevice::LAYOUT,
+        public::PublicData::LAYOUT,
+        crate::x86_64::AddressSpaceStats::LAYOUT,
+        crate::x86_64::CpuInfo::LAYOUT,
+        crate::x86_64::IrqNotice::LAYOUT,
+        crate::x86_64::IrqViolationNotice::LAYOUT,
+        crate::x86_64::KernelInfo::LAYOUT,
+        crate::x86_64::KernelMemoryUsage::LAYOUT,
+        crate::x86_64::KernelStats::LAYOUT,
+        crate::x86_64::KeyEvent::LAYOUT,
+        crate::x86_64::MappingInfo::LAYOUT,
+        crate::x86_64::MessageInfo::LAYOUT,
+        crate::event::Event::LAYOUT,
+        crate::Arg::LAYOUT,
+        crate::CrashNotice::LAYOUT,
+        crate::InitArgs::LAYOUT,
+        crate::InitHeader::LAYOUT,
+    ];
+
+    // 64-bit FNV-1a, over the little-endian bytes of the number of values of each layout
+    // followed by the values themselves.
+    const fn feed(mut hash: u64, value: usize) -> u64 {
+        let bytes = (value as u64).to_le_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            hash = (hash ^ bytes[i] as u64).wrapping_mul(0x0000_0100_0000_01b3);
+            i += 1;
+        }
+        hash
+    }
+
+    let mut hash = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < LAYOUTS.len() {
+        hash = feed(hash, LAYOUTS[i].len());
+        let mut j = 0;
+        while j < LAYOUTS[i].len() {
+            hash = feed(hash, LAYOUTS[i][j]);
+            j += 1;
+        }
+        i += 1;
+    }
+    hash
+}
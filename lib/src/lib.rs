@@ -11,8 +11,25 @@
 #[path = "arch/x86_64/mod.rs"]
 pub mod x86_64;
 
+#[cfg(feature = "userland")]
+pub mod sync;
+
+pub mod event;
+pub mod ipc;
+
+#[cfg(feature = "backtrace")]
+pub mod backtrace;
+
+#[cfg(feature = "panic-handler")]
+mod panic;
+
+#[cfg(any(feature = "panic-handler", feature = "backtrace"))]
+mod fmt_buffer;
+
+pub mod layout;
 mod process;
 mod sys_result;
 
+pub use self::ipc::PortId;
 pub use self::process::*;
 pub use self::sys_result::*;
@@ -0,0 +1,23 @@
+//! A panic handler for userspace programs, enabled by the `panic-handler` feature.
+//!
+//! The panic message is written to the log of the kernel with
+//! [`debug_log`](crate::x86_64::debug_log), and the current process is terminated. When the
+//! `backtrace` feature is enabled, a backtrace is logged as well.
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use crate::fmt_buffer::Buffer;
+use crate::x86_64::{debug_log, terminate_self};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut buffer = Buffer::new();
+    let _ = write!(buffer, "{info}");
+    let _ = debug_log(buffer.as_str());
+
+    #[cfg(feature = "backtrace")]
+    crate::backtrace::print();
+
+    terminate_self();
+}
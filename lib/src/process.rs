@@ -1,5 +1,8 @@
 use core::num::NonZeroUsize;
 
+use crate::ipc::Pod;
+use crate::layout::assert_layout;
+
 /// The header of the initial process.
 #[repr(C)]
 pub struct InitHeader {
@@ -8,11 +11,32 @@ pub struct InitHeader {
     pub magic: u64,
     /// A pointer to the first byte of the initial process' image. This is the virtual address at
     /// which the process will be loaded.
+    ///
+    /// This must be aligned to a page boundary.
     pub image_start: *const (),
-    /// The entry point of the initial process.
+    /// The end of the code segment of the image, which starts at `image_start`.
+    ///
+    /// The code segment is mapped readable and executable. This must be aligned to a page
+    /// boundary.
+    pub text_end: *const (),
+    /// The start of the writable data segment of the image, which extends to the end of the
+    /// image.
+    ///
+    /// The `text_end..data_start` range contains read-only data. Neither of those segments is
+    /// executable. This must be aligned to a page boundary.
+    pub data_start: *const (),
+    /// The entry point of the initial process. This must be part of the code segment.
     pub entry_point: *const (),
 }
 
+assert_layout!(InitHeader, size = 40, align = 8, {
+    magic: 0,
+    image_start: 8,
+    text_end: 16,
+    data_start: 24,
+    entry_point: 32,
+});
+
 unsafe impl Send for InitHeader {}
 unsafe impl Sync for InitHeader {}
 
@@ -20,18 +44,178 @@ impl InitHeader {
     /// The magic number of the initial process.
     pub const MAGIC: u64 = u64::from_ne_bytes(*b"<limine>");
 
-    /// Creates a new [`InitHeader`] from the provided entry point.
+    /// Creates a new [`InitHeader`] from the provided segment bounds and entry point.
     #[inline(always)]
-    pub const fn new(image_start: *const (), entry_point: unsafe extern "C" fn() -> !) -> Self {
+    pub const fn new(
+        image_start: *const (),
+        text_end: *const (),
+        data_start: *const (),
+        entry_point: unsafe extern "C" fn() -> !,
+    ) -> Self {
         Self {
             magic: Self::MAGIC,
             image_start,
+            text_end,
+            data_start,
             entry_point: entry_point as *const (),
         }
     }
 }
 
+/// The size of the stack allocated by the kernel for a process started from an image, in bytes.
+///
+/// The stack is mapped at the top of the lower half of the address space, with the same
+/// semantics as a mapping created with [`MapFlags::STACK`](crate::x86_64::MapFlags::STACK).
+pub const INIT_STACK_SIZE: usize = 256 * 1024;
+
+/// The arguments passed by the kernel to a process started from an image.
+///
+/// The arguments are taken from the command line of the module the image was loaded from, or
+/// from the one passed to [`spawn`](crate::x86_64::spawn), split on ASCII whitespaces.
+///
+/// # Initial Stack Layout
+///
+/// When the entry point of the process starts executing, the `rsp` register points to an
+/// [`InitArgs`] instance, and `rdi` holds the same address. This makes it possible to declare the
+/// entry point as `extern "C" fn(&InitArgs) -> !`. The stack pointer is aligned as if the entry
+/// point had been called: `rsp + 8` is a multiple of 16.
+///
+/// Above the [`InitArgs`] instance lie the `argc` elements of the `argv` array, followed by the
+/// bytes of the arguments themselves. All of this fits in the topmost page of the stack.
+#[derive(Debug)]
+#[repr(C)]
+pub struct InitArgs {
+    /// The number of arguments.
+    pub argc: usize,
+    /// A pointer to the first element of an array of `argc` arguments.
+    pub argv: *const Arg,
+}
+
+assert_layout!(InitArgs, size = 16, align = 8, {
+    argc: 0,
+    argv: 8,
+});
+
+impl InitArgs {
+    /// Returns the arguments as a slice of [`Arg`].
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[Arg] {
+        // SAFETY:
+        //  The kernel guarantees that `argv` points to `argc` valid arguments.
+        unsafe { core::slice::from_raw_parts(self.argv, self.argc) }
+    }
+
+    /// Returns an iterator over the arguments.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        self.as_slice().iter().map(Arg::as_bytes)
+    }
+}
+
+/// An argument passed to a process, as part of its [`InitArgs`].
+#[derive(Debug)]
+#[repr(C)]
+pub struct Arg {
+    /// A pointer to the first byte of the argument.
+    pub data: *const u8,
+    /// The length of the argument, in bytes.
+    pub len: usize,
+}
+
+assert_layout!(Arg, size = 16, align = 8, {
+    data: 0,
+    len: 8,
+});
+
+impl Arg {
+    /// Returns the bytes of the argument.
+    #[inline(always)]
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY:
+        //  The kernel guarantees that `data` points to `len` valid bytes.
+        unsafe { core::slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
 /// The ID of a process.
 ///
 /// No process can have the ID zero, which is why this type simply is a [`NonZeroUsize`].
+///
+/// The ID of a process that has exited is not given to the next processes: a stale ID is
+/// rejected with [`SysResult::INVALID_PROCESS_ID`](crate::SysResult::INVALID_PROCESS_ID) instead
+/// of designating an unrelated process.
 pub type ProcessId = NonZeroUsize;
+
+/// The reason why a restartable process crashed.
+///
+/// See [`CrashNotice`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum CrashReason {
+    /// The process caused a CPU exception. [`CrashNotice::vector`] holds its vector.
+    Exception,
+    /// The process was terminated by the kernel to reclaim memory.
+    OutOfMemory,
+    /// The process was terminated by another process.
+    Killed,
+}
+
+impl CrashReason {
+    /// Converts the provided raw value into a [`CrashReason`].
+    #[inline]
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Exception),
+            1 => Some(Self::OutOfMemory),
+            2 => Some(Self::Killed),
+            _ => None,
+        }
+    }
+}
+
+/// The content of the message sent by the kernel to the supervisor of a restartable process
+/// when that process crashes.
+///
+/// A process that terminates itself does not crash: its supervisor is not notified, and the
+/// resources it owned are released as usual.
+///
+/// # Escrow
+///
+/// The framebuffers and PCI devices owned by the process when it crashed are not released.
+/// Instead, the kernel holds them in escrow on behalf of the supervisor, which may hand them
+/// over to a replacement process with
+/// [`reclaim_escrow`](crate::x86_64::reclaim_escrow). No other process can acquire them in the
+/// meantime. The escrow is released if the supervisor exits before reclaiming it.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct CrashNotice {
+    /// The ID of the process that crashed.
+    ///
+    /// The ID may be reused by a new process as soon as the notice is sent.
+    pub process: usize,
+    /// The raw [`CrashReason`] of the crash.
+    pub reason: usize,
+    /// The vector of the CPU exception that caused the crash, when `reason` is
+    /// [`CrashReason::Exception`].
+    pub vector: usize,
+    /// The ID of the escrow that holds the resources of the process, or 0 if the process did
+    /// not own any resource.
+    pub escrow: usize,
+}
+
+assert_layout!(CrashNotice, size = 32, align = 8, {
+    process: 0,
+    reason: 8,
+    vector: 16,
+    escrow: 24,
+});
+
+unsafe impl Pod for CrashNotice {}
+
+impl CrashNotice {
+    /// Returns the reason of the crash, if it is known.
+    #[inline(always)]
+    pub const fn reason(&self) -> Option<CrashReason> {
+        CrashReason::from_raw(self.reason)
+    }
+}
@@ -0,0 +1,72 @@
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::*;
+
+use super::{futex_wait, futex_wake, MutexGuard};
+
+/// A condition variable, used to wait for an event while holding a [`Mutex`](super::Mutex).
+pub struct Condvar {
+    /// A counter incremented every time the condition variable is notified.
+    ///
+    /// Waiters sleep until this value changes, which ensures that a notification sent between
+    /// the moment the mutex is released and the moment the waiter goes to sleep is not lost.
+    sequence: AtomicU32,
+}
+
+impl Condvar {
+    /// Creates a new [`Condvar`].
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicU32::new(0),
+        }
+    }
+
+    /// Releases the provided guard and puts the current process to sleep until the condition
+    /// variable is notified. The mutex is locked again before this function returns.
+    ///
+    /// This function may return spuriously. Callers must check the condition they are waiting
+    /// for again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let sequence = self.sequence.load(Relaxed);
+
+        let mutex = guard.mutex();
+        drop(guard);
+
+        futex_wait(&self.sequence, sequence);
+
+        mutex.lock()
+    }
+
+    /// Calls [`Condvar::wait`] until `condition` returns `false`.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes up one process waiting on this condition variable.
+    #[inline]
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Relaxed);
+        futex_wake(&self.sequence, 1);
+    }
+
+    /// Wakes up all the processes waiting on this condition variable.
+    #[inline]
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Relaxed);
+        futex_wake(&self.sequence, usize::MAX);
+    }
+}
+
+impl Default for Condvar {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,36 @@
+//! Synchronization primitives built on top of the [`wait`] and [`wake`] system calls.
+//!
+//! Those primitives put the current process to sleep instead of spinning when they cannot make
+//! progress. Because the kernel identifies waiters by the physical address of the word they wait
+//! on, they may be placed in memory shared by multiple processes.
+//!
+//! [`wait`]: crate::x86_64::wait
+//! [`wake`]: crate::x86_64::wake
+
+use core::sync::atomic::AtomicU32;
+
+mod condvar;
+mod mutex;
+mod once;
+mod rwlock;
+
+pub use self::condvar::*;
+pub use self::mutex::*;
+pub use self::once::*;
+pub use self::rwlock::*;
+
+/// Waits until `word` is no longer equal to `expected`, or until the process is woken up.
+///
+/// This function may return spuriously.
+#[inline(always)]
+fn futex_wait(word: &AtomicU32, expected: u32) {
+    // Errors can only be caused by `word` not being mapped, which cannot happen since we hold a
+    // reference to it. Running out of memory is treated as a spurious wake-up.
+    let _ = crate::x86_64::wait(word, expected);
+}
+
+/// Wakes up to `count` processes waiting on `word`.
+#[inline(always)]
+fn futex_wake(word: &AtomicU32, count: usize) {
+    let _ = crate::x86_64::wake(word, count);
+}
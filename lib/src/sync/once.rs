@@ -0,0 +1,100 @@
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::*;
+
+use super::{futex_wait, futex_wake};
+
+/// The initialization function has not been called yet.
+const INCOMPLETE: u32 = 0;
+/// The initialization function is running.
+const RUNNING: u32 = 1;
+/// The initialization function is running, and other processes are waiting for it to complete.
+const RUNNING_CONTENDED: u32 = 2;
+/// The initialization function has completed.
+const COMPLETE: u32 = 3;
+
+/// A synchronization primitive that runs a function exactly once.
+///
+/// # Panics
+///
+/// If the initialization function panics, the [`Once`] never completes and any process waiting
+/// on it sleeps forever.
+pub struct Once {
+    /// One of [`INCOMPLETE`], [`RUNNING`], [`RUNNING_CONTENDED`] or [`COMPLETE`].
+    state: AtomicU32,
+}
+
+impl Once {
+    /// Creates a new [`Once`].
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+        }
+    }
+
+    /// Returns whether the initialization function has completed.
+    #[inline(always)]
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Acquire) == COMPLETE
+    }
+
+    /// Calls `f` if no other call to this function has been made on this [`Once`].
+    ///
+    /// When this function returns, the initialization function is guaranteed to have completed,
+    /// possibly in another process.
+    #[inline]
+    pub fn call_once(&self, f: impl FnOnce()) {
+        if !self.is_completed() {
+            self.call_once_slow(f);
+        }
+    }
+
+    /// The slow path of [`Once::call_once`].
+    #[cold]
+    fn call_once_slow(&self, f: impl FnOnce()) {
+        let mut state = self.state.load(Acquire);
+
+        loop {
+            match state {
+                COMPLETE => return,
+                INCOMPLETE => {
+                    if let Err(cur) = self
+                        .state
+                        .compare_exchange(INCOMPLETE, RUNNING, Acquire, Acquire)
+                    {
+                        state = cur;
+                        continue;
+                    }
+
+                    f();
+
+                    if self.state.swap(COMPLETE, Release) == RUNNING_CONTENDED {
+                        futex_wake(&self.state, usize::MAX);
+                    }
+
+                    return;
+                }
+                RUNNING => {
+                    if let Err(cur) =
+                        self.state
+                            .compare_exchange(RUNNING, RUNNING_CONTENDED, Acquire, Acquire)
+                    {
+                        state = cur;
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+
+            futex_wait(&self.state, RUNNING_CONTENDED);
+            state = self.state.load(Acquire);
+        }
+    }
+}
+
+impl Default for Once {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,164 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::*;
+
+use super::{futex_wait, futex_wake};
+
+/// The value of [`RwLock::state`] when the lock is held by a writer.
+const WRITE_LOCKED: u32 = u32::MAX;
+
+/// A reader-writer lock that puts the current process to sleep while it cannot be acquired.
+///
+/// # Fairness
+///
+/// This lock does not prevent writers from starving when readers keep acquiring it.
+pub struct RwLock<T: ?Sized> {
+    /// The number of readers holding the lock, or [`WRITE_LOCKED`] if it is held by a writer.
+    state: AtomicU32,
+    /// The number of processes sleeping until the lock is released.
+    waiters: AtomicU32,
+    /// The protected value.
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new unlocked [`RwLock`].
+    #[inline(always)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the lock and returns the protected value.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Locks the value for reading, sleeping while a writer holds the lock.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            let state = self.state.load(Relaxed);
+
+            // `WRITE_LOCKED - 1` readers would make the next reader look like a writer.
+            if state < WRITE_LOCKED - 1 {
+                if self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Acquire, Relaxed)
+                    .is_ok()
+                {
+                    return RwLockReadGuard { lock: self };
+                }
+            } else {
+                self.sleep(state);
+            }
+        }
+    }
+
+    /// Locks the value for writing, sleeping while anyone else holds the lock.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            match self
+                .state
+                .compare_exchange(0, WRITE_LOCKED, Acquire, Relaxed)
+            {
+                Ok(_) => return RwLockWriteGuard { lock: self },
+                Err(state) => self.sleep(state),
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the protected value.
+    ///
+    /// No locking is needed since the lock is borrowed mutably.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Sleeps until the state of the lock changes from `state`.
+    #[cold]
+    fn sleep(&self, state: u32) {
+        // The waiter count must be visible to the process releasing the lock before we check the
+        // state of the lock again in the `wait` system call.
+        self.waiters.fetch_add(1, SeqCst);
+        futex_wait(&self.state, state);
+        self.waiters.fetch_sub(1, SeqCst);
+    }
+
+    /// Wakes up the processes waiting for the lock, if any.
+    #[inline]
+    fn wake_waiters(&self) {
+        if self.waiters.load(SeqCst) != 0 {
+            futex_wake(&self.state, usize::MAX);
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// A guard that releases the read lock of a [`RwLock`] when dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.lock.state.fetch_sub(1, SeqCst) == 1 {
+            self.lock.wake_waiters();
+        }
+    }
+}
+
+/// A guard that releases the write lock of a [`RwLock`] when dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.state.store(0, SeqCst);
+        self.lock.wake_waiters();
+    }
+}
@@ -8,7 +8,7 @@ use core::fmt;
 /// integer, but the last values are special, as they are used to represent errors.
 ///
 /// Specifically, any value above [`SysResult::FIRST_ERROR`] is an error.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 #[must_use = "this value represents a system call result, and might represent an error"]
 pub struct SysResult(pub usize);
@@ -69,13 +69,41 @@ impl SysResult {
     pub const fn unwrap(self) -> usize {
         self.expect("called `SysResult::unwrap()` on an error value")
     }
+
+    /// Converts this [`SysResult`] into a [`Result`], making it possible to propagate errors
+    /// with the `?` operator.
+    #[inline]
+    pub const fn into_result(self) -> Result<usize, SysError> {
+        if self.is_success() {
+            Ok(self.0)
+        } else {
+            Err(SysError::from_code(self.0 - Self::FIRST_ERROR))
+        }
+    }
+}
+
+impl From<SysError> for SysResult {
+    #[inline]
+    fn from(error: SysError) -> Self {
+        SysResult(Self::FIRST_ERROR + error.code())
+    }
+}
+
+impl From<Result<usize, SysError>> for SysResult {
+    #[inline]
+    fn from(result: Result<usize, SysError>) -> Self {
+        match result {
+            Ok(value) => SysResult::success(value),
+            Err(error) => error.into(),
+        }
+    }
 }
 
 macro_rules! define_error_codes {
     (
         $(
             $(#[$($doc:meta)*])*
-            const $name:ident = $value:literal;
+            $variant:ident($name:ident) = $value:literal, $message:literal;
         )*
     ) => {
         impl SysResult {
@@ -85,21 +113,59 @@ macro_rules! define_error_codes {
             )*
         }
 
+        /// An error returned by a system call.
+        ///
+        /// Each variant corresponds to one of the error values of [`SysResult`]. This is the error
+        /// type of [`SysResult::into_result`].
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum SysError {
+            $(
+                $(#[$($doc)*])*
+                $variant,
+            )*
+            /// An error that this version of the crate does not know about, along with its code.
+            ///
+            /// Newer kernels may return errors that did not exist when the program was built.
+            Unknown(usize),
+        }
+
+        impl SysError {
+            /// Returns the [`SysError`] with the provided code, which is the offset of its value
+            /// from [`SysResult::FIRST_ERROR`].
+            pub const fn from_code(code: usize) -> Self {
+                match code {
+                    $($value => Self::$variant,)*
+                    code => Self::Unknown(code),
+                }
+            }
+
+            /// Returns the code of this error, which is the offset of its value from
+            /// [`SysResult::FIRST_ERROR`].
+            pub const fn code(self) -> usize {
+                match self {
+                    $(Self::$variant => $value,)*
+                    Self::Unknown(code) => code,
+                }
+            }
+        }
+
+        impl fmt::Display for SysError {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    $(Self::$variant => f.write_str($message),)*
+                    Self::Unknown(code) => write!(f, "unknown error {}", code),
+                }
+            }
+        }
+
         impl fmt::Debug for SysResult {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                if self.is_success() {
-                    f.debug_tuple("SysResult")
-                        .field(&self.0)
-                        .finish()
-                } else {
-                    match self.0 {
-                        $(
-                            $value => f.write_str(stringify!($name)),
-                        )*
-                        _ => f.debug_tuple("SysResult")
-                            .field(&format_args!("<unknown error>"))
-                            .finish(),
-                    }
+                match self.into_result() {
+                    Ok(value) => f.debug_tuple("SysResult").field(&value).finish(),
+                    $(Err(SysError::$variant) => f.write_str(stringify!($name)),)*
+                    Err(SysError::Unknown(_)) => f.debug_tuple("SysResult")
+                        .field(&format_args!("<unknown error>"))
+                        .finish(),
                 }
             }
         }
@@ -110,11 +176,26 @@ define_error_codes! {
     /// This error is returned when a system call is called with an invalid value.
     ///
     /// Every system call checks all of its input values.
-    const INVALID_VALUE = 0;
+    InvalidValue(INVALID_VALUE) = 0, "invalid value";
     /// A process ID passed to a system call was invalid.
-    const INVALID_PROCESS_ID = 1;
+    InvalidProcessId(INVALID_PROCESS_ID) = 1, "invalid process ID";
     /// The system is out of memory and cannot complete the requested operation because of it.
-    const OUT_OF_MEMORY = 2;
+    OutOfMemory(OUT_OF_MEMORY) = 2, "out of memory";
     /// The requested resource is already used by another process.
-    const CONFLICT = 3;
+    Conflict(CONFLICT) = 3, "resource already in use";
+    /// The operation cannot be completed without waiting.
+    WouldBlock(WOULD_BLOCK) = 4, "operation would block";
+    /// The operation did not complete before the provided timeout expired.
+    TimedOut(TIMED_OUT) = 5, "operation timed out";
+    /// The calling process is not allowed to perform the requested operation.
+    PermissionDenied(PERMISSION_DENIED) = 6, "permission denied";
+    /// The requested operation is not supported by the kernel, usually because it has been
+    /// built without the feature that provides it.
+    NotSupported(NOT_SUPPORTED) = 7, "operation not supported";
+    /// The requested object, such as a port or a device, does not exist.
+    NotFound(NOT_FOUND) = 8, "not found";
+    /// The operation was interrupted before it could complete, and may be retried.
+    Interrupted(INTERRUPTED) = 9, "operation interrupted";
+    /// The buffer provided to the system call is too small to hold its output.
+    BufferTooSmall(BUFFER_TOO_SMALL) = 10, "buffer too small";
 }
@@ -0,0 +1,541 @@
+//! Parsing of the ACPI tables provided by the firmware.
+//!
+//! # Lifetime of the tables
+//!
+//! The ACPI tables usually live in ACPI reclaimable memory, which is handed to the memory tracker
+//! during boot. The tables are therefore parsed once by [`init`], before the memory tracker is
+//! initialized, and the information needed by the kernel is kept in an [`AcpiInfo`] instance.
+//!
+//! A summary of that information is copied to the public data area, for userspace drivers (see
+//! [`summary`]).
+
+use core::mem::size_of;
+
+use fabric_sys::x86_64::public::{AcpiFlags, AcpiSummary, HpetDescription, HpetFlags};
+
+use crate::log;
+
+use super::cpu::ioapic::{Polarity, TriggerMode};
+use super::mem::HHDM_OFFSET;
+
+mod power;
+mod tables;
+
+pub use self::power::*;
+
+use self::tables::*;
+
+/// The maximum number of I/O APICs that the kernel can handle.
+pub const MAX_IO_APICS: usize = 8;
+
+/// The maximum number of interrupt source overrides that the kernel can handle.
+pub const MAX_INTERRUPT_OVERRIDES: usize = 16;
+
+/// An I/O APIC described by the MADT.
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    /// The ID of the I/O APIC.
+    pub id: u8,
+    /// The physical address of the registers of the I/O APIC.
+    pub address: usize,
+    /// The first global system interrupt handled by the I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// Describes how an ISA interrupt is connected to the I/O APICs, when it differs from the
+/// identity mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    /// The ISA interrupt number.
+    pub source: u8,
+    /// The global system interrupt that the ISA interrupt is connected to.
+    pub gsi: u32,
+    /// The MPS INTI flags of the interrupt, describing its polarity and trigger mode.
+    pub flags: u16,
+}
+
+/// The values to write in the PM1 control registers to enter a sleep state.
+#[derive(Debug, Clone, Copy)]
+pub struct SleepType {
+    /// The value for the PM1a control register.
+    pub a: u16,
+    /// The value for the PM1b control register.
+    pub b: u16,
+}
+
+/// The fixed power management hardware described by the FADT.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerManagement {
+    /// The ISA interrupt used to signal system control interrupts (SCIs).
+    pub sci_irq: u8,
+    /// The I/O port used to transfer the control of the hardware from the firmware to the OS.
+    pub smi_command: u16,
+    /// The value to write to `smi_command` to enable ACPI mode.
+    pub acpi_enable: u8,
+    /// The I/O port of the PM1a event register block.
+    pub pm1a_event: u16,
+    /// The I/O port of the PM1b event register block, or 0 if it does not exist.
+    pub pm1b_event: u16,
+    /// The I/O port of the PM1a control register block.
+    pub pm1a_control: u16,
+    /// The I/O port of the PM1b control register block, or 0 if it does not exist.
+    pub pm1b_control: u16,
+    /// The size of the PM1 event register blocks, in bytes.
+    pub pm1_event_length: u8,
+    /// Whether the power button is a fixed feature (as opposed to a control method device).
+    pub fixed_power_button: bool,
+    /// The sleep type of the soft-off (S5) state, if found in the DSDT.
+    pub s5: Option<SleepType>,
+}
+
+/// The High Precision Event Timer described by the HPET table.
+#[derive(Debug, Clone, Copy)]
+pub struct HpetInfo {
+    /// The physical address of the registers of the HPET.
+    pub address: usize,
+    /// The minimum number of ticks of the main counter that a periodic comparator can be
+    /// programmed with.
+    pub minimum_tick: u16,
+    /// The number of comparators of the HPET.
+    pub comparator_count: u8,
+    /// Whether the main counter is 64 bits wide.
+    pub counter_64bit: bool,
+    /// Whether the HPET supports the legacy replacement route.
+    pub legacy_replacement: bool,
+}
+
+/// The information extracted from the ACPI tables.
+pub struct AcpiInfo {
+    /// The tables that were found.
+    pub tables: AcpiFlags,
+    /// The I/O APICs of the system.
+    pub io_apics: [Option<IoApicInfo>; MAX_IO_APICS],
+    /// The interrupt source overrides of the system.
+    pub overrides: [Option<InterruptOverride>; MAX_INTERRUPT_OVERRIDES],
+    /// The fixed power management hardware, if a FADT was found.
+    pub power: Option<PowerManagement>,
+    /// The HPET of the system, if the HPET table was found.
+    pub hpet: Option<HpetInfo>,
+}
+
+/// The global [`AcpiInfo`] instance, initialized by [`init`].
+static mut INFO: AcpiInfo = AcpiInfo {
+    tables: AcpiFlags::empty(),
+    io_apics: [None; MAX_IO_APICS],
+    overrides: [None; MAX_INTERRUPT_OVERRIDES],
+    power: None,
+    hpet: None,
+};
+
+/// Returns the information extracted from the ACPI tables.
+///
+/// If [`init`] has not been called, or if no valid table could be found, the returned instance
+/// is empty.
+#[inline(always)]
+pub fn info() -> &'static AcpiInfo {
+    // SAFETY:
+    //  The information is only modified once, by `init`, before any other access.
+    unsafe { &*core::ptr::addr_of!(INFO) }
+}
+
+/// Parses the ACPI tables referenced by the RSDP at the provided physical address.
+///
+/// # Safety
+///
+/// This function must only be called once, before the memory containing the ACPI tables is
+/// reclaimed. The tables must be accessible through the direct map.
+pub unsafe fn init(rsdp: usize) {
+    let Some(root) = (unsafe { root_table(rsdp) }) else {
+        log::warn!("The ACPI RSDP is invalid. ACPI tables will be ignored.");
+        return;
+    };
+
+    // SAFETY:
+    //  This function is only called once, before any other access to the information.
+    let info = unsafe { &mut *core::ptr::addr_of_mut!(INFO) };
+
+    info.tables.insert(AcpiFlags::RSDP);
+
+    for table in unsafe { root.tables() } {
+        let header = unsafe { read::<SdtHeader>(table) };
+
+        match header.signature {
+            MADT_SIGNATURE => {
+                info.tables.insert(AcpiFlags::MADT);
+                unsafe { parse_madt(info, table, header.length as usize) };
+            }
+            FADT_SIGNATURE => {
+                info.tables.insert(AcpiFlags::FADT);
+                info.power = unsafe { parse_fadt(table, header.length as usize) };
+            }
+            HPET_SIGNATURE => {
+                info.hpet = unsafe { parse_hpet(table, header.length as usize) };
+                info.tables.set(AcpiFlags::HPET, info.hpet.is_some());
+            }
+            _ => (),
+        }
+    }
+
+    if info.power.is_some_and(|power| power.s5.is_some()) {
+        info.tables.insert(AcpiFlags::SOFT_OFF);
+    }
+
+    let io_apic_count = info.io_apics.iter().flatten().count();
+    log::trace!("Found {} I/O APIC(s) in the ACPI tables.", io_apic_count);
+
+    if let Some(hpet) = info.hpet {
+        log::trace!(
+            "Found an HPET at {:#x} with {} comparator(s).",
+            hpet.address,
+            hpet.comparator_count,
+        );
+    }
+}
+
+/// Returns the summary of the ACPI tables that is published to userspace.
+pub fn summary() -> AcpiSummary {
+    let info = info();
+
+    let hpet = match info.hpet {
+        Some(hpet) => {
+            let mut flags = HpetFlags::empty();
+            flags.set(HpetFlags::COUNTER_64BIT, hpet.counter_64bit);
+            flags.set(HpetFlags::LEGACY_REPLACEMENT, hpet.legacy_replacement);
+
+            HpetDescription {
+                address: hpet.address as u64,
+                minimum_tick: hpet.minimum_tick,
+                comparator_count: hpet.comparator_count,
+                flags,
+                _reserved: 0,
+            }
+        }
+        None => AcpiSummary::EMPTY.hpet,
+    };
+
+    AcpiSummary {
+        flags: info.tables,
+        io_apic_count: info.io_apics.iter().flatten().count() as u32,
+        hpet,
+    }
+}
+
+/// Returns the global system interrupt, polarity, and trigger mode of the provided ISA
+/// interrupt.
+///
+/// When the interrupt is not overridden in the MADT, or when the override conforms to the
+/// specification of the bus, the provided defaults are used.
+pub fn resolve_isa_irq(
+    irq: u8,
+    mut polarity: Polarity,
+    mut trigger: TriggerMode,
+) -> (u32, Polarity, TriggerMode) {
+    let Some(ovr) = info()
+        .overrides
+        .iter()
+        .flatten()
+        .find(|ovr| ovr.source == irq)
+    else {
+        return (irq as u32, polarity, trigger);
+    };
+
+    match ovr.flags & 0b11 {
+        0b01 => polarity = Polarity::ActiveHigh,
+        0b11 => polarity = Polarity::ActiveLow,
+        _ => (),
+    }
+
+    match (ovr.flags >> 2) & 0b11 {
+        0b01 => trigger = TriggerMode::Edge,
+        0b11 => trigger = TriggerMode::Level,
+        _ => (),
+    }
+
+    (ovr.gsi, polarity, trigger)
+}
+
+/// Reads a value of type `T` at the provided physical address.
+///
+/// # Safety
+///
+/// The memory must be accessible through the direct map.
+#[inline(always)]
+unsafe fn read<T: Copy>(phys: usize) -> T {
+    unsafe { core::ptr::read_unaligned((phys + HHDM_OFFSET) as *const T) }
+}
+
+/// Returns whether the `length` bytes at the provided physical address sum to zero.
+///
+/// # Safety
+///
+/// The memory must be accessible through the direct map.
+unsafe fn checksum_ok(phys: usize, length: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts((phys + HHDM_OFFSET) as *const u8, length) };
+    bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)) == 0
+}
+
+/// Validates the header of the table at the provided physical address.
+///
+/// # Safety
+///
+/// The memory must be accessible through the direct map.
+unsafe fn is_valid_table(phys: usize, signature: [u8; 4]) -> bool {
+    let header = unsafe { read::<SdtHeader>(phys) };
+    header.signature == signature
+        && header.length as usize >= size_of::<SdtHeader>()
+        && unsafe { checksum_ok(phys, header.length as usize) }
+}
+
+/// The root table of the ACPI tables, either the RSDT or the XSDT.
+struct RootTable {
+    /// The physical address of the table.
+    address: usize,
+    /// The size of the pointers stored in the table.
+    entry_size: usize,
+}
+
+impl RootTable {
+    /// Returns an iterator over the physical addresses of the valid tables referenced by the root
+    /// table.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be accessible through the direct map.
+    unsafe fn tables(&self) -> impl Iterator<Item = usize> {
+        let length = unsafe { read::<SdtHeader>(self.address).length } as usize;
+        let count = (length - size_of::<SdtHeader>()) / self.entry_size;
+        let first = self.address + size_of::<SdtHeader>();
+        let entry_size = self.entry_size;
+
+        (0..count)
+            .map(move |i| unsafe {
+                let entry = first + i * entry_size;
+                match entry_size {
+                    4 => read::<u32>(entry) as usize,
+                    _ => read::<u64>(entry) as usize,
+                }
+            })
+            .filter(|&table| {
+                let header = unsafe { read::<SdtHeader>(table) };
+                unsafe { is_valid_table(table, header.signature) }
+            })
+    }
+}
+
+/// Validates the RSDP and returns the root table it references.
+///
+/// # Safety
+///
+/// The memory must be accessible through the direct map.
+unsafe fn root_table(rsdp: usize) -> Option<RootTable> {
+    let table = unsafe { read::<Rsdp>(rsdp) };
+
+    if table.signature != RSDP_SIGNATURE || !unsafe { checksum_ok(rsdp, RSDP_V1_SIZE) } {
+        return None;
+    }
+
+    if table.revision >= 2
+        && table.xsdt_address != 0
+        && unsafe { checksum_ok(rsdp, table.length as usize) }
+        && unsafe { is_valid_table(table.xsdt_address as usize, XSDT_SIGNATURE) }
+    {
+        return Some(RootTable {
+            address: table.xsdt_address as usize,
+            entry_size: 8,
+        });
+    }
+
+    let rsdt = table.rsdt_address as usize;
+    unsafe { is_valid_table(rsdt, RSDT_SIGNATURE) }.then_some(RootTable {
+        address: rsdt,
+        entry_size: 4,
+    })
+}
+
+/// Parses the MADT at the provided physical address.
+///
+/// # Safety
+///
+/// The memory must be accessible through the direct map.
+unsafe fn parse_madt(info: &mut AcpiInfo, madt: usize, length: usize) {
+    let mut io_apics = info.io_apics.iter_mut();
+    let mut overrides = info.overrides.iter_mut();
+
+    let mut offset = size_of::<Madt>();
+    while offset + size_of::<MadtEntryHeader>() <= length {
+        let entry = madt + offset;
+        let header = unsafe { read::<MadtEntryHeader>(entry) };
+
+        if header.length < 2 || offset + header.length as usize > length {
+            log::warn!("The ACPI MADT is malformed.");
+            break;
+        }
+
+        match header.kind {
+            MADT_IO_APIC if header.length as usize >= size_of::<MadtIoApic>() => {
+                let io_apic = unsafe { read::<MadtIoApic>(entry) };
+                match io_apics.next() {
+                    Some(slot) => {
+                        *slot = Some(IoApicInfo {
+                            id: io_apic.id,
+                            address: io_apic.address as usize,
+                            gsi_base: io_apic.gsi_base,
+                        })
+                    }
+                    None => log::warn!("Too many I/O APICs. Some will be ignored."),
+                }
+            }
+            MADT_INTERRUPT_SOURCE_OVERRIDE
+                if header.length as usize >= size_of::<MadtInterruptSourceOverride>() =>
+            {
+                let ovr = unsafe { read::<MadtInterruptSourceOverride>(entry) };
+                match overrides.next() {
+                    Some(slot) => {
+                        *slot = Some(InterruptOverride {
+                            source: ovr.source,
+                            gsi: ovr.gsi,
+                            flags: ovr.flags,
+                        })
+                    }
+                    None => {
+                        log::warn!("Too many interrupt source overrides. Some will be ignored.")
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        offset += header.length as usize;
+    }
+}
+
+/// Parses the FADT at the provided physical address.
+///
+/// # Safety
+///
+/// The memory must be accessible through the direct map.
+unsafe fn parse_fadt(fadt: usize, length: usize) -> Option<PowerManagement> {
+    if length < size_of::<Fadt>() {
+        log::warn!("The ACPI FADT is too small.");
+        return None;
+    }
+
+    let table = unsafe { read::<Fadt>(fadt) };
+
+    let mut dsdt = table.dsdt as usize;
+    if length >= FADT_X_DSDT_OFFSET + 8 {
+        let x_dsdt = unsafe { read::<u64>(fadt + FADT_X_DSDT_OFFSET) } as usize;
+        if x_dsdt != 0 {
+            dsdt = x_dsdt;
+        }
+    }
+
+    let s5 = if dsdt != 0 && unsafe { is_valid_table(dsdt, DSDT_SIGNATURE) } {
+        let length = unsafe { read::<SdtHeader>(dsdt).length } as usize;
+        let aml = unsafe {
+            core::slice::from_raw_parts(
+                (dsdt + HHDM_OFFSET + size_of::<SdtHeader>()) as *const u8,
+                length - size_of::<SdtHeader>(),
+            )
+        };
+        find_s5(aml)
+    } else {
+        None
+    };
+
+    if s5.is_none() {
+        log::warn!("The ACPI \\_S5 object could not be found. Shutdown will not be available.");
+    }
+
+    Some(PowerManagement {
+        sci_irq: table.sci_int as u8,
+        smi_command: table.smi_cmd as u16,
+        acpi_enable: table.acpi_enable,
+        pm1a_event: table.pm1a_evt_blk as u16,
+        pm1b_event: table.pm1b_evt_blk as u16,
+        pm1a_control: table.pm1a_cnt_blk as u16,
+        pm1b_control: table.pm1b_cnt_blk as u16,
+        pm1_event_length: table.pm1_evt_len,
+        fixed_power_button: table.flags & FADT_PWR_BUTTON == 0,
+        s5,
+    })
+}
+
+/// Parses the HPET table at the provided physical address.
+///
+/// # Safety
+///
+/// The memory must be accessible through the direct map.
+unsafe fn parse_hpet(hpet: usize, length: usize) -> Option<HpetInfo> {
+    if length < size_of::<Hpet>() {
+        log::warn!("The ACPI HPET table is too small.");
+        return None;
+    }
+
+    let table = unsafe { read::<Hpet>(hpet) };
+    let base = table.base_address;
+
+    if base.address_space != ADDRESS_SPACE_MEMORY || base.address == 0 {
+        log::warn!("The HPET is not memory-mapped. It will be ignored.");
+        return None;
+    }
+
+    let id = table.event_timer_block_id;
+    let last_comparator = (id >> HPET_LAST_COMPARATOR_SHIFT) & HPET_LAST_COMPARATOR_MASK;
+
+    Some(HpetInfo {
+        address: base.address as usize,
+        minimum_tick: table.minimum_tick,
+        comparator_count: last_comparator as u8 + 1,
+        counter_64bit: id & HPET_COUNTER_64BIT != 0,
+        legacy_replacement: id & HPET_LEGACY_REPLACEMENT != 0,
+    })
+}
+
+/// Looks for the definition of the `\_S5` package in the provided AML code, and returns the
+/// sleep type values it contains.
+///
+/// This is not a complete AML interpreter. Only the common encoding of the package is
+/// recognized: `Name(_S5, Package() { a, b, ... })`.
+fn find_s5(aml: &[u8]) -> Option<SleepType> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0A;
+    const ROOT_CHAR: u8 = b'\\';
+
+    let position = aml.windows(4).enumerate().position(|(i, w)| {
+        w == b"_S5_"
+            && ((i >= 1 && aml[i - 1] == NAME_OP)
+                || (i >= 2 && aml[i - 2] == NAME_OP && aml[i - 1] == ROOT_CHAR))
+    })?;
+
+    let mut rest = aml.get(position + 4..)?;
+    if *rest.first()? != PACKAGE_OP {
+        return None;
+    }
+
+    // Skip the package length, whose size is encoded in the two upper bits of its first byte,
+    // and the number of elements.
+    let pkg_length_size = 1 + (*rest.get(1)? >> 6) as usize;
+    rest = rest.get(1 + pkg_length_size + 1..)?;
+
+    let mut read_value = || -> Option<u16> {
+        let value = match *rest.first()? {
+            BYTE_PREFIX => {
+                let value = *rest.get(1)?;
+                rest = &rest[2..];
+                value
+            }
+            value => {
+                rest = &rest[1..];
+                value
+            }
+        };
+        Some(value as u16)
+    };
+
+    let a = read_value()?;
+    let b = read_value()?;
+
+    Some(SleepType { a, b })
+}
@@ -0,0 +1,174 @@
+//! The fixed power management features of ACPI: the power button and soft-off.
+//!
+//! # Power Button
+//!
+//! When the power button is pressed, the chipset raises a system control interrupt (SCI). The
+//! kernel acknowledges it and delivers an [`EventKind::PowerButton`] event to the subscribed
+//! process. When no process is subscribed, the machine is shut down.
+
+use fabric_sys::event::EventKind;
+
+use crate::log;
+use crate::x86_64::cpu::ioapic::{self, Polarity, TriggerMode};
+use crate::x86_64::cpu::{apic, idt};
+use crate::x86_64::event::{self, DeliverError};
+use crate::x86_64::instr;
+use crate::x86_64::instr::{inw, outb, outw};
+use crate::x86_64::raw::StackFrame;
+
+use super::tables::*;
+use super::{info, PowerManagement};
+
+/// The number of times the PM1 control register is polled while waiting for the firmware to
+/// enable ACPI mode.
+const ACPI_ENABLE_POLL_COUNT: usize = 1_000_000;
+
+/// Returns the fixed power management hardware of the system, if any.
+#[inline(always)]
+fn power_management() -> Option<&'static PowerManagement> {
+    info().power.as_ref()
+}
+
+/// Transfers the control of the power management hardware from the firmware to the kernel, and
+/// enables the power button event.
+///
+/// # Safety
+///
+/// This function must only be called once, after [`super::init`] and once the I/O APICs have
+/// been initialized.
+pub unsafe fn init_power_button() {
+    let Some(pm) = power_management() else {
+        log::warn!("No ACPI FADT found. The power button will not be handled.");
+        return;
+    };
+
+    if !pm.fixed_power_button {
+        log::warn!("The power button is a control method device, which is not supported.");
+        return;
+    }
+
+    unsafe {
+        if inw(pm.pm1a_control) & PM1_CNT_SCI_EN == 0 {
+            if pm.smi_command == 0 || pm.acpi_enable == 0 {
+                log::warn!("ACPI mode cannot be enabled. The power button will not be handled.");
+                return;
+            }
+
+            log::trace!("Enabling ACPI mode...");
+            outb(pm.smi_command, pm.acpi_enable);
+
+            let enabled =
+                (0..ACPI_ENABLE_POLL_COUNT).any(|_| inw(pm.pm1a_control) & PM1_CNT_SCI_EN != 0);
+            if !enabled {
+                log::warn!("The firmware did not enable ACPI mode.");
+                return;
+            }
+        }
+
+        // Clear any pending status before enabling the event.
+        for block in [pm.pm1a_event, pm.pm1b_event] {
+            if block != 0 {
+                outw(block, PM1_STS_PWRBTN);
+                outw(enable_register(pm, block), PM1_EN_PWRBTN);
+            }
+        }
+    }
+
+    // The SCI is a sharable, level-triggered, active-low interrupt unless the MADT says
+    // otherwise.
+    let (gsi, polarity, trigger) =
+        super::resolve_isa_irq(pm.sci_irq, Polarity::ActiveLow, TriggerMode::Level);
+
+    let routed = ioapic::route(
+        gsi,
+        idt::ACPI_SCI_VECTOR as u8,
+        polarity,
+        trigger,
+        apic::local_apic_id(),
+    );
+
+    if routed.is_err() {
+        log::warn!("The ACPI SCI (GSI {}) is not handled by any I/O APIC.", gsi);
+        return;
+    }
+
+    log::trace!("Power button enabled (SCI on GSI {}).", gsi);
+}
+
+/// Returns the I/O port of the enable register of the provided PM1 event block.
+#[inline(always)]
+fn enable_register(pm: &PowerManagement, block: u16) -> u16 {
+    block + pm.pm1_event_length as u16 / 2
+}
+
+/// Puts the machine in the soft-off (S5) state.
+///
+/// If the machine cannot be shut down, it is halted instead.
+pub fn shutdown() -> ! {
+    instr::cli();
+
+    if let Some((pm, s5)) = power_management().and_then(|pm| Some((pm, pm.s5?))) {
+        // SAFETY:
+        //  We are about to turn the machine off. Nothing that happens to memory matters anymore.
+        unsafe {
+            outw(
+                pm.pm1a_control,
+                s5.a << PM1_CNT_SLP_TYP_SHIFT | PM1_CNT_SLP_EN,
+            );
+            if pm.pm1b_control != 0 {
+                outw(
+                    pm.pm1b_control,
+                    s5.b << PM1_CNT_SLP_TYP_SHIFT | PM1_CNT_SLP_EN,
+                );
+            }
+        }
+    }
+
+    log::error!("Failed to shut the machine down. Halting instead.");
+    crate::die();
+}
+
+/// The handler of the ACPI system control interrupt.
+pub extern "x86-interrupt" fn sci_interrupt(_: StackFrame) {
+    let Some(pm) = power_management() else {
+        apic::send_eoi();
+        return;
+    };
+
+    let mut pressed = false;
+
+    for block in [pm.pm1a_event, pm.pm1b_event] {
+        if block == 0 {
+            continue;
+        }
+
+        // SAFETY:
+        //  The PM1 event blocks are described by the FADT.
+        unsafe {
+            if inw(block) & PM1_STS_PWRBTN != 0 {
+                // The status bits are cleared by writing a one to them.
+                outw(block, PM1_STS_PWRBTN);
+                pressed = true;
+            }
+        }
+    }
+
+    apic::send_eoi();
+
+    if !pressed {
+        return;
+    }
+
+    match event::deliver(EventKind::PowerButton, 0) {
+        Ok(()) => (),
+        Err(DeliverError::PortFull) => {
+            log::warn_ratelimited!(
+                "The power button event could not be delivered: the port is full."
+            );
+        }
+        Err(DeliverError::NoSubscriber) => {
+            log::info!("The power button has been pressed. Shutting down...");
+            shutdown();
+        }
+    }
+}
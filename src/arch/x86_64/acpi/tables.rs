@@ -0,0 +1,196 @@
+//! The layout of the ACPI tables used by the kernel.
+//!
+//! See the [ACPI specification][1] for a detailed description of those structures.
+//!
+//! [1]: https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html
+
+/// The Root System Description Pointer.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    // The following fields are only available when `revision` is at least 2.
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    pub reserved: [u8; 3],
+}
+
+/// The signature of the [`Rsdp`].
+pub const RSDP_SIGNATURE: [u8; 8] = *b"RSD PTR ";
+
+/// The size of the part of the [`Rsdp`] that is available in ACPI 1.0.
+pub const RSDP_V1_SIZE: usize = 20;
+
+/// The header shared by all System Description Tables.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+pub const RSDT_SIGNATURE: [u8; 4] = *b"RSDT";
+pub const XSDT_SIGNATURE: [u8; 4] = *b"XSDT";
+pub const FADT_SIGNATURE: [u8; 4] = *b"FACP";
+pub const MADT_SIGNATURE: [u8; 4] = *b"APIC";
+pub const DSDT_SIGNATURE: [u8; 4] = *b"DSDT";
+pub const HPET_SIGNATURE: [u8; 4] = *b"HPET";
+
+/// The Fixed ACPI Description Table, as defined in ACPI 1.0.
+///
+/// Later revisions of the table append fields after `flags`.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    pub reserved0: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_req: u8,
+    pub pstate_cnt: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm2_cnt_blk: u32,
+    pub pm_tmr_blk: u32,
+    pub gpe0_blk: u32,
+    pub gpe1_blk: u32,
+    pub pm1_evt_len: u8,
+    pub pm1_cnt_len: u8,
+    pub pm2_cnt_len: u8,
+    pub pm_tmr_len: u8,
+    pub gpe0_blk_len: u8,
+    pub gpe1_blk_len: u8,
+    pub gpe1_base: u8,
+    pub cst_cnt: u8,
+    pub p_lvl2_lat: u16,
+    pub p_lvl3_lat: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alrm: u8,
+    pub mon_alrm: u8,
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    pub reserved1: u8,
+    pub flags: u32,
+}
+
+/// The offset of the `X_DSDT` field of the [`Fadt`], available since ACPI 2.0.
+pub const FADT_X_DSDT_OFFSET: usize = 140;
+
+/// Set in [`Fadt::flags`] when the power button is a control method device rather than a fixed
+/// feature.
+pub const FADT_PWR_BUTTON: u32 = 1 << 4;
+
+/// The header of the Multiple APIC Description Table.
+///
+/// The header is followed by a list of variable-length entries, each starting with a
+/// [`MadtEntryHeader`].
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct Madt {
+    pub header: SdtHeader,
+    pub local_apic_address: u32,
+    pub flags: u32,
+}
+
+/// The header of an entry of the [`Madt`].
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct MadtEntryHeader {
+    pub kind: u8,
+    pub length: u8,
+}
+
+pub const MADT_IO_APIC: u8 = 1;
+pub const MADT_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+
+/// An I/O APIC entry of the [`Madt`].
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct MadtIoApic {
+    pub header: MadtEntryHeader,
+    pub id: u8,
+    pub reserved: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// An interrupt source override entry of the [`Madt`].
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct MadtInterruptSourceOverride {
+    pub header: MadtEntryHeader,
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+/// A Generic Address Structure, describing the location of a register.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+/// The [`GenericAddress::address_space`] of registers mapped in memory.
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+/// The High Precision Event Timer Description Table.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+/// The shift of the number of the last comparator in [`Hpet::event_timer_block_id`].
+pub const HPET_LAST_COMPARATOR_SHIFT: u32 = 8;
+/// The mask of the number of the last comparator, once shifted.
+pub const HPET_LAST_COMPARATOR_MASK: u32 = 0x1F;
+/// Set in [`Hpet::event_timer_block_id`] when the main counter is 64 bits wide.
+pub const HPET_COUNTER_64BIT: u32 = 1 << 13;
+/// Set in [`Hpet::event_timer_block_id`] when the HPET supports the legacy replacement route.
+pub const HPET_LEGACY_REPLACEMENT: u32 = 1 << 15;
+
+// PM1 registers.
+
+/// The power button status bit of the PM1 status register.
+pub const PM1_STS_PWRBTN: u16 = 1 << 8;
+/// The power button enable bit of the PM1 enable register.
+pub const PM1_EN_PWRBTN: u16 = 1 << 8;
+/// The SCI enable bit of the PM1 control register.
+pub const PM1_CNT_SCI_EN: u16 = 1 << 0;
+/// The offset of the sleep type field of the PM1 control register.
+pub const PM1_CNT_SLP_TYP_SHIFT: u16 = 10;
+/// The sleep enable bit of the PM1 control register.
+pub const PM1_CNT_SLP_EN: u16 = 1 << 13;
@@ -0,0 +1,180 @@
+//! Randomization of the layout of the address space of processes.
+//!
+//! When the `aslr=on` option is passed on the kernel command line, the kernel picks a random
+//! layout for every process it loads:
+//!
+//! - The initial stack is moved below the inbox of the process, at a random distance of up to
+//!   [`STACK_RANGE`] bytes. The inbox itself stays at [`INBOX_ADDRESS`], as its address is
+//!   published in the public data area.
+//!
+//! - The lowest address that the kernel picks when it chooses where to map memory on behalf of
+//!   the process is moved up from [`USER_MAP_BASE`] by a random amount of up to
+//!   [`MAP_BASE_RANGE`] bytes.
+//!
+//! The public data area is mapped by the kernel in the upper half, at the same address for every
+//! process, and is not randomized.
+//!
+//! Random numbers are read from the **RDRAND** instruction when the CPU supports it. Otherwise,
+//! they are derived from the time-stamp counter, which only protects against attackers that
+//! cannot observe the timing of the boot process.
+//!
+//! In the [deterministic boot mode](super::deterministic), **RDRAND** and the time-stamp counter
+//! are not used. Random numbers are generated from the seed of the mode, so that every boot picks
+//! the same layouts.
+//!
+//! [`INBOX_ADDRESS`]: super::process::INBOX_ADDRESS
+
+use fabric_sys::INIT_STACK_SIZE;
+
+use crate::log;
+use crate::utility::Cmdline;
+
+use super::mem::{PAGE_SIZE, USER_MAP_BASE};
+use super::process::INBOX_ADDRESS;
+use super::{deterministic, instr};
+
+/// The size of the range in which the top of the initial stack of a process is picked.
+pub const STACK_RANGE: usize = 1 << 34;
+
+/// The size of the range, starting at [`USER_MAP_BASE`], in which the lowest address picked by
+/// the kernel to map memory on behalf of a process is chosen.
+pub const MAP_BASE_RANGE: usize = 1 << 40;
+
+/// Set in the `ecx` register of the first **CPUID** leaf when the CPU supports **RDRAND**.
+const CPUID_RDRAND: u32 = 1 << 30;
+
+/// The number of times **RDRAND** is retried before falling back to the time-stamp counter.
+const RDRAND_RETRIES: usize = 10;
+
+/// Whether the layout of processes is randomized.
+static mut ENABLED: bool = false;
+
+/// The state of the fallback generator, used when **RDRAND** is not available.
+static mut FALLBACK_STATE: u64 = 0;
+
+/// Reads the `aslr` option of the kernel command line.
+///
+/// # Safety
+///
+/// This function must only be called during boot, before any process is loaded.
+pub unsafe fn init(cmdline: Cmdline) {
+    let enabled = match cmdline.get(b"aslr") {
+        Some(b"on") => true,
+        Some(b"off") | None => false,
+        Some(value) => {
+            log::warn!(
+                "Invalid `aslr` value: `{}`.",
+                core::str::from_utf8(value).unwrap_or("<invalid UTF-8>")
+            );
+            false
+        }
+    };
+
+    if enabled && !deterministic::enabled() && instr::cpuid(1, 0)[2] & CPUID_RDRAND == 0 {
+        log::warn!("RDRAND is not supported. The layout of processes is weakly randomized.");
+    }
+
+    // SAFETY:
+    //  The caller guarantees that this function is called during boot.
+    unsafe {
+        ENABLED = enabled;
+        FALLBACK_STATE = match deterministic::enabled() {
+            true => deterministic::seed(),
+            false => instr::rdtsc(),
+        };
+    }
+}
+
+/// Returns whether the layout of processes is randomized.
+#[inline]
+pub fn enabled() -> bool {
+    // SAFETY:
+    //  This is only modified during boot.
+    unsafe { ENABLED }
+}
+
+/// Returns a random number.
+fn random() -> u64 {
+    let deterministic = deterministic::enabled();
+
+    if !deterministic && instr::cpuid(1, 0)[2] & CPUID_RDRAND != 0 {
+        for _ in 0..RDRAND_RETRIES {
+            // SAFETY:
+            //  The CPU supports RDRAND.
+            if let Some(value) = unsafe { instr::rdrand() } {
+                return value;
+            }
+        }
+    }
+
+    // SAFETY:
+    //  Processes are only loaded with interrupts disabled, on the bootstrap CPU.
+    let state = unsafe { &mut *core::ptr::addr_of_mut!(FALLBACK_STATE) };
+
+    // SplitMix64, with the time-stamp counter mixed in outside of the deterministic mode.
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    if !deterministic {
+        *state ^= instr::rdtsc();
+    }
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Returns a random multiple of the page size that is less than `range`.
+fn random_offset(range: usize) -> usize {
+    let pages = range / PAGE_SIZE;
+    if pages == 0 {
+        return 0;
+    }
+
+    (random() % pages as u64) as usize * PAGE_SIZE
+}
+
+/// The layout of the address space of a process.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    /// The first address past the end of the initial stack.
+    pub stack_end: usize,
+    /// The lowest address that the kernel picks when it chooses where to map memory on behalf of
+    /// the process.
+    pub map_base: usize,
+}
+
+impl Layout {
+    /// The layout used when randomization is disabled.
+    ///
+    /// The initial stack ends at the top of the lower half, right above the inbox.
+    pub const DEFAULT: Self = Self {
+        stack_end: INBOX_ADDRESS + PAGE_SIZE + INIT_STACK_SIZE,
+        map_base: USER_MAP_BASE,
+    };
+
+    /// Picks the layout of a process whose image ends at `image_end`.
+    ///
+    /// The initial stack never overlaps with the image. When randomization is disabled, or when
+    /// the image leaves no room for the stack below the inbox, the stack is placed at its default
+    /// location.
+    pub fn pick(image_end: usize) -> Self {
+        if !enabled() {
+            return Self::DEFAULT;
+        }
+
+        // The stack is separated from the inbox by a guard page.
+        let highest_end = INBOX_ADDRESS - PAGE_SIZE;
+        let room = highest_end
+            .saturating_sub(INIT_STACK_SIZE)
+            .saturating_sub(image_end);
+
+        let stack_end = match room {
+            0 => Self::DEFAULT.stack_end,
+            room => highest_end - random_offset(room.min(STACK_RANGE)),
+        };
+
+        Self {
+            stack_end,
+            map_base: USER_MAP_BASE + random_offset(MAP_BASE_RANGE),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Backtraces of the kernel, printed when the kernel panics.
+//!
+//! Backtraces are computed by following the chain of frame pointers saved on the kernel stack,
+//! which requires the kernel to be compiled with frame pointers enabled (see the `frame-pointer`
+//! option of `targets/x86_64.json`). Return addresses are resolved with the
+//! [`symbols`](super::symbols) of the kernel.
+
+use crate::log;
+use crate::x86_64::kernel_stack::{KERNEL_STACK_BOTTOM, KERNEL_STACK_TOP};
+use crate::x86_64::symbols::Symbolized;
+
+/// The maximum number of frames reported by [`walk`].
+const MAX_FRAMES: usize = 32;
+
+/// Calls `f` with the return address of each frame of the current call stack, starting with the
+/// caller of this function.
+///
+/// The walk stops as soon as a frame pointer leaves the kernel stack, which happens when the
+/// bottom of the stack is reached, or when the kernel is running on another stack (such as the
+/// one used to handle double faults).
+#[inline(never)]
+fn walk(mut f: impl FnMut(usize)) {
+    let mut frame: usize;
+
+    // SAFETY:
+    //  Reading the frame pointer has no side effects.
+    unsafe {
+        core::arch::asm!(
+            "mov {}, rbp",
+            out(reg) frame,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+
+    for _ in 0..MAX_FRAMES {
+        if frame < KERNEL_STACK_BOTTOM
+            || frame + 2 * core::mem::size_of::<usize>() > KERNEL_STACK_TOP
+            || frame % core::mem::align_of::<usize>() != 0
+        {
+            break;
+        }
+
+        // SAFETY:
+        //  We checked that the frame is within the kernel stack. A frame starts with the saved
+        //  frame pointer of the caller, followed by the return address.
+        let (next, return_address) = unsafe {
+            let words = frame as *const usize;
+            (*words, *words.add(1))
+        };
+
+        if return_address == 0 {
+            break;
+        }
+
+        f(return_address);
+
+        // The stack grows downwards, so the frames of callers are always at higher addresses.
+        if next <= frame {
+            break;
+        }
+
+        frame = next;
+    }
+}
+
+/// Writes a backtrace of the current call stack to the log.
+#[inline(never)]
+pub fn print() {
+    log::error!("   > Backtrace:");
+
+    let mut index = 0;
+    walk(|address| {
+        log::error!("       #{:<2} {}", index, Symbolized(address));
+        index += 1;
+    });
+
+    if index == 0 {
+        log::error!("       <no frames>");
+    }
+}
+
+/// Saves the return addresses of the current call stack, starting with the caller of this
+/// function, into `frames`.
+///
+/// # Returns
+///
+/// The number of frames that have been saved. Frames that do not fit in `frames` are dropped.
+#[inline(never)]
+pub fn capture(frames: &mut [usize]) -> usize {
+    let mut count = 0;
+    walk(|address| {
+        if let Some(slot) = frames.get_mut(count) {
+            *slot = address;
+            count += 1;
+        }
+    });
+    count
+}
+
+/// Writes a backtrace previously saved by [`capture`] to the log.
+pub fn print_captured(frames: &[usize]) {
+    for (index, &address) in frames.iter().enumerate() {
+        log::error!("       #{:<2} {}", index, Symbolized(address));
+    }
+
+    if frames.is_empty() {
+        log::error!("       <no frames>");
+    }
+}
@@ -7,16 +7,30 @@
 //! [1]: https://github.com/limine-bootloader/limine/blob/v4.x-branch/PROTOCOL.md
 
 use core::arch::asm;
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::{Relaxed, Release};
 
-use fabric_sys::x86_64::public::{ColorMode, Framebuffer, PublicData};
-use fabric_sys::InitHeader;
+use fabric_sys::x86_64::public::{
+    AcpiSummary, BootConfig, BootFlags, Clock, ColorMode, Framebuffer, LogPrefix, PciDevice,
+    PublicData,
+};
+use fabric_sys::x86_64::{ABI_VERSION, MIN_ABI_VERSION};
 
 use crate::log;
-use crate::x86_64::mem::{
-    BootAllocator, MemoryTrackerTok, HHDM_OFFSET, MAX_PHYSICAL_MEMORY, PAGE_SIZE,
-};
+use crate::utility::num;
+use crate::x86_64::boot_stage::{self, BootStage};
+use crate::x86_64::build_id::BUILD_ID;
+use crate::x86_64::config;
+use crate::x86_64::debugcon;
+use crate::x86_64::kernel_stack::KERNEL_STACK_TOP;
+use crate::x86_64::mem::usage::{self, Category};
+use crate::x86_64::mem::{BootAllocator, Frame, MemoryTrackerTok, PhysAddr, PAGE_SIZE};
+use crate::x86_64::process::{self, Image};
 use crate::x86_64::public::PublicDataLayout;
+use crate::x86_64::smp;
+use crate::x86_64::supervisor::{self, InitExitPolicy};
+use crate::x86_64::symbols::{self, SymbolTable};
+use crate::x86_64::timer::{self, TimerPreference};
 
 use super::cpu::paging::UpperHalfAddressSpaceTok;
 
@@ -28,6 +42,9 @@ mod req;
 /// 16 is very pessimistic. There will usually be at most 4 to 6 segments.
 const MAX_SEGMENTS: usize = 16;
 
+/// The maximum number of modules, not including `fabric_init`, that can be started at boot.
+const MAX_MODULES: usize = 16;
+
 /// Represents a physical memory segment.
 #[derive(Clone, Copy)]
 struct MemorySegment {
@@ -47,9 +64,13 @@ struct Transfer {
     boot_allocator: BootAllocator,
     segments: [MemorySegment; MAX_SEGMENTS],
     boot_allocator_start_address: usize,
-    fabric_init_start_address: usize,
-    fabric_init_size: usize,
+    fabric_init: Image,
+    modules: [Option<Image>; MAX_MODULES],
     upper_half_address_space: UpperHalfAddressSpaceTok,
+    init_exit_policy: InitExitPolicy,
+    timer_preference: TimerPreference,
+    rsdp: Option<usize>,
+    symbols: Option<SymbolTable>,
 }
 
 /// The entry point of the kernel, when loaded by a Limine-complient bootloader.
@@ -86,24 +107,48 @@ unsafe extern "C" fn entry_point() -> ! {
     let serial = unsafe { crate::x86_64::serial::SerialTok::init() };
     crate::log::set_global_log_fn(serial.log_fn());
     log::trace!("Logger initialized.");
+    log::info!("{}", BUILD_ID);
+
+    // SAFETY:
+    //  This function is only called once, and nothing else uses the PIT yet.
+    unsafe { timer::init_clock() };
 
     req::validate_entry_point(limine);
     req::log_bootloader_info(limine);
 
     let current_hhdm = req::hhdm_offset(limine);
 
-    let fabric_init = req::fabric_init(limine);
+    // The command line lives in bootloader reclaimable memory. The options that the kernel
+    // cares about must be parsed before switching address spaces.
+    let cmdline = crate::utility::Cmdline::new(req::kernel_cmdline(limine));
+    select_log_sink(cmdline);
+    crate::x86_64::serial::configure_from_cmdline(cmdline);
+    log::set_prefix(log::Prefix::from_cmdline(cmdline));
+    log::set_filters_from_cmdline(cmdline);
+    // SAFETY:
+    //  The log sink and the clock are initialized, and the options that depend on the mode
+    //  are read afterwards.
+    unsafe { crate::x86_64::deterministic::init(cmdline) };
+    let init_exit_policy = InitExitPolicy::from_cmdline(cmdline);
+    let timer_preference = TimerPreference::from_cmdline(cmdline);
+    // SAFETY:
+    //  This is the only place where the out-of-memory handler is initialized.
+    unsafe { crate::x86_64::oom::init(cmdline) };
+    // SAFETY:
+    //  This is the only place where the interrupt budgets are initialized.
+    unsafe { crate::x86_64::irq::init(cmdline) };
+    crate::x86_64::fault_injection::init(cmdline);
+    // SAFETY:
+    //  No process has been loaded yet.
+    unsafe { crate::x86_64::aslr::init(cmdline) };
 
-    // Find the physical address of the `fabric_init` module.
-    // Note that we can't simply use the virtual address provided by the bootloader because it
-    // points to the higher half direct map that we will replace soon with our own. Instead, we
-    // save the physical address of the module which won't depend on the current address space.
-    let fabric_init_start_address = fabric_init.as_ptr() as usize - current_hhdm;
-    let fabric_init_size = fabric_init.len();
+    let rsdp = req::rsdp(limine, current_hhdm);
+
+    let fabric_init = req::fabric_init(limine);
 
     log::trace!(
         "Found `fabric_init` module of size {}.",
-        crate::utility::HumanByteCount(fabric_init.len() as u64)
+        crate::utility::HumanByteCount(fabric_init.data.len() as u64)
     );
 
     let memmap = req::memory_map(limine);
@@ -189,7 +234,10 @@ unsafe extern "C" fn entry_point() -> ! {
     let largest_segment = unsafe { *segments.get_unchecked(largest_segment) };
 
     let boot_allocator_start_address = largest_segment.base;
-    boot_allocator = BootAllocator::new(boot_allocator_start_address, largest_segment.length);
+    boot_allocator = BootAllocator::new(
+        PhysAddr::new(boot_allocator_start_address),
+        largest_segment.length,
+    );
 
     log::trace!(
         "Boot allocator initialized with a contiguous block of {}.",
@@ -208,14 +256,16 @@ unsafe extern "C" fn entry_point() -> ! {
         .max()
         .unwrap_or(0);
 
-    if direct_map_size > MAX_PHYSICAL_MEMORY {
+    let max_physical_memory = config::max_physical_memory(cmdline);
+
+    if direct_map_size > max_physical_memory {
         log::warn!(
             "Detected {} of physical memory.",
             crate::utility::HumanByteCount(direct_map_size as u64)
         );
         log::warn!(
             "Only up to {} of physical memory are supported.",
-            crate::utility::HumanByteCount(MAX_PHYSICAL_MEMORY as u64),
+            crate::utility::HumanByteCount(max_physical_memory as u64),
         );
         log::warn!("");
         log::warn!("This is due to the laziness of the Fabric developers.");
@@ -223,7 +273,7 @@ unsafe extern "C" fn entry_point() -> ! {
         log::warn!("");
         log::warn!("    https://github.com/nils-mathieu/fabric/issues/new");
 
-        direct_map_size = MAX_PHYSICAL_MEMORY;
+        direct_map_size = max_physical_memory;
     } else {
         log::info!(
             "Available physical memory: {}.",
@@ -231,7 +281,15 @@ unsafe extern "C" fn entry_point() -> ! {
         );
     }
 
-    let framebuffers = req::framebuffers(limine);
+    // The `headless` option hides the framebuffers from userland, even if the bootloader found
+    // some: the system is then driven through the serial port only.
+    let force_headless = cmdline.get(b"headless").is_some();
+    let framebuffers = if force_headless {
+        log::info!("Running headless: the framebuffers are ignored.");
+        &[]
+    } else {
+        req::framebuffers(limine)
+    };
 
     // Print a warning if a framebuffer is not supported.
     let mut first_unsupported_framebuffer = true;
@@ -257,13 +315,67 @@ unsafe extern "C" fn entry_point() -> ! {
         supported_framebuffer_count += 1;
     }
 
-    log::trace!(
-        "Found {} supported framebuffer(s).",
-        supported_framebuffer_count
-    );
+    let headless = supported_framebuffer_count == 0;
+    if headless && !force_headless {
+        log::info!("No supported framebuffer found, running headless.");
+    } else if !headless {
+        log::trace!(
+            "Found {} supported framebuffer(s).",
+            supported_framebuffer_count
+        );
+    }
+
+    let fabric_init = retain_module(fabric_init, current_hhdm, &mut boot_allocator);
+
+    // Every other module is started as an independent process.
+    let mut modules = [None; MAX_MODULES];
+    let mut module_count = 0;
+    for module in req::modules(limine) {
+        if module.name == b"fabric_init" {
+            continue;
+        }
+
+        if module_count >= MAX_MODULES {
+            log::warn!("Too many modules provided by the bootloader.");
+            log::warn!("Only the first {} will be started.", MAX_MODULES);
+            break;
+        }
+
+        log::trace!(
+            "Found module `{}` of size {}.",
+            core::str::from_utf8(module.name).unwrap_or("<invalid UTF-8>"),
+            crate::utility::HumanByteCount(module.data.len() as u64)
+        );
+
+        modules[module_count] = Some(retain_module(module, current_hhdm, &mut boot_allocator));
+        module_count += 1;
+    }
+
+    log::trace!("Enumerating PCI devices...");
+    // SAFETY:
+    //  This function is only called once, and nothing else accesses the PCI configuration space.
+    unsafe { crate::x86_64::pci::init() };
+    let pci_device_count = crate::x86_64::pci::devices().count();
+
+    log::trace!("Found {} PCI device(s).", pci_device_count);
+
+    // The kernel file lives in bootloader reclaimable memory, so its symbols must be copied now.
+    let symbols = req::kernel_file(limine)
+        .and_then(|elf| symbols::build(elf, &mut boot_allocator, current_hhdm));
+
+    match symbols {
+        Some(_) => log::trace!("Loaded the kernel symbol table."),
+        None => log::warn!(
+            "The kernel symbol table is not available. Crash dumps will not be symbolized."
+        ),
+    }
 
     // Compute that amount of memory that we need to allocate for the public data area.
-    let public_data_layout = PublicDataLayout::compute(supported_framebuffer_count);
+    let public_data_layout = PublicDataLayout::compute(
+        supported_framebuffer_count,
+        pci_device_count,
+        cmdline.as_bytes().len(),
+    );
 
     // Initialize the parts of the public data area that we can initialize now.
     // We kinda need to do this now because after we switch address spaces, we won't be able to
@@ -271,24 +383,13 @@ unsafe extern "C" fn entry_point() -> ! {
     let public_data_phys = boot_allocator
         .allocate(public_data_layout.size, PAGE_SIZE) // we need to be aligned to the page size to map the memory at a specific position later.
         .unwrap_or_else(|_| oom());
+    usage::record(Category::Metadata, public_data_layout.size);
 
-    // Initialize the root of the public data area. That's an instance of [`PublicData`] which
-    // references the other parts of the public data area.
-    //
-    // Note that we're not initializing all parts of the public area just yet.
+    // The root of the public data area is only written once the kernel has decided which of its
+    // features to use. See below.
     unsafe {
-        core::ptr::write(
-            (public_data_phys + public_data_layout.root + current_hhdm) as *mut PublicData,
-            PublicData {
-                framebuffers: (crate::x86_64::public_data_address()
-                    + public_data_layout.framebuffers)
-                    as *const Framebuffer,
-                framebuffer_count: framebuffers.len(),
-            },
-        );
-
-        let mut cur =
-            (public_data_phys + public_data_layout.framebuffers + current_hhdm) as *mut Framebuffer;
+        let mut cur = (public_data_phys.get() + public_data_layout.framebuffers + current_hhdm)
+            as *mut Framebuffer;
         for framebuffer in framebuffers {
             match try_convert_framebuffer(framebuffer, current_hhdm) {
                 Some(framebuffer) => core::ptr::write(cur, framebuffer),
@@ -296,6 +397,13 @@ unsafe extern "C" fn entry_point() -> ! {
             }
             cur = cur.add(1);
         }
+
+        let mut cur = (public_data_phys.get() + public_data_layout.pci_devices + current_hhdm)
+            as *mut PciDevice;
+        for device in crate::x86_64::pci::devices() {
+            core::ptr::write(cur, device.to_public());
+            cur = cur.add(1);
+        }
     }
 
     let l4_table = unsafe {
@@ -312,24 +420,79 @@ unsafe extern "C" fn entry_point() -> ! {
 
     let upper_half_address_space = unsafe { UpperHalfAddressSpaceTok::init(l4_table) };
 
+    // The application processors wait in bootloader reclaimable memory. They must leave it
+    // before it is reclaimed.
+    launch_application_processors(limine, l4_table);
+
     // We're currently running on the stack provided by the bootloader, which resides in bootloader
     // reclaimable memory. When a proper memory allocator is initialized, this memory will be
     // overwritten, so we need to switch to a stack that is guaranteed to be safe.
-    let mut stack =
-        unsafe { crate::x86_64::kernel_stack::init(&mut boot_allocator).unwrap_or_else(|_| oom()) };
+    let stack_phys = unsafe {
+        crate::x86_64::kernel_stack::init(&mut boot_allocator, l4_table, current_hhdm)
+            .unwrap_or_else(|_| oom())
+    };
+
+    unsafe {
+        crate::x86_64::cpu::cet::init(&mut boot_allocator, l4_table, current_hhdm, cmdline)
+            .unwrap_or_else(|_| oom());
+        crate::x86_64::cpu::info::init();
+        crate::x86_64::cpu::mitigations::init(cmdline);
+    }
+
+    // Initialize the root of the public data area. That's an instance of [`PublicData`] which
+    // references the other parts of the public data area.
+    //
+    // The command line is copied along with it, so that processes can read the options that the
+    // kernel does not know about.
+    unsafe {
+        let cmdline = cmdline.as_bytes();
+        core::ptr::copy_nonoverlapping(
+            cmdline.as_ptr(),
+            (public_data_phys.get() + public_data_layout.cmdline + current_hhdm) as *mut u8,
+            cmdline.len(),
+        );
+
+        core::ptr::write(
+            (public_data_phys.get() + public_data_layout.root + current_hhdm) as *mut PublicData,
+            PublicData {
+                framebuffers: (public_data_layout.framebuffers - public_data_layout.root) as u64,
+                framebuffer_count: supported_framebuffer_count as u64,
+                pci_devices: (public_data_layout.pci_devices - public_data_layout.root) as u64,
+                pci_device_count: pci_device_count as u64,
+                cmdline: (public_data_layout.cmdline - public_data_layout.root) as u64,
+                cmdline_length: cmdline.len() as u64,
+                boot_config: boot_config(
+                    max_physical_memory,
+                    init_exit_policy,
+                    timer_preference,
+                    headless,
+                ),
+                clock: Clock::new(timer::clock_params()),
+                abi_version: ABI_VERSION,
+                min_abi_version: MIN_ABI_VERSION,
+                inbox: process::INBOX_ADDRESS as u64,
+                // The ACPI tables are only parsed once the kernel address space is loaded.
+                acpi: AcpiSummary::EMPTY,
+            },
+        );
+    }
 
     // Copy the Transfer structure to the new stack.
-    stack -= core::mem::size_of::<Transfer>();
+    let transfer_offset = core::mem::size_of::<Transfer>();
     unsafe {
         core::ptr::write(
-            (stack + current_hhdm) as *mut Transfer,
+            (stack_phys.get() - transfer_offset + current_hhdm) as *mut Transfer,
             Transfer {
                 segments,
                 boot_allocator_start_address,
                 boot_allocator,
-                fabric_init_start_address,
-                fabric_init_size,
+                fabric_init,
+                modules,
                 upper_half_address_space,
+                init_exit_policy,
+                timer_preference,
+                rsdp,
+                symbols,
             },
         );
     }
@@ -360,10 +523,10 @@ unsafe extern "C" fn entry_point() -> ! {
 
             jmp {next}
             "#,
-            l4_table = in(reg) l4_table,
+            l4_table = in(reg) l4_table.get(),
             next = sym entry_point_follow,
 
-            in("rdi") stack + HHDM_OFFSET,
+            in("rdi") KERNEL_STACK_TOP - transfer_offset,
             options(noreturn),
         );
     }
@@ -384,17 +547,60 @@ unsafe extern "C" fn entry_point_follow(transfer: *mut Transfer) -> ! {
         mut boot_allocator,
         segments,
         boot_allocator_start_address,
-        fabric_init_start_address,
-        fabric_init_size,
+        fabric_init,
+        modules,
         upper_half_address_space,
+        init_exit_policy,
+        timer_preference,
+        rsdp,
+        symbols,
     } = unsafe { transfer.read() };
 
+    // SAFETY:
+    //  We are now running in the kernel address space.
+    unsafe { boot_stage::record(BootStage::Paging) };
+
+    // SAFETY:
+    //  No process has been created yet.
+    unsafe { super::cpu::paging::init_user_top() };
+
+    if let Some(symbols) = symbols {
+        // SAFETY:
+        //  The table has been built by `symbols::build`, and we are now running in the kernel
+        //  address space.
+        unsafe { symbols::init(symbols) };
+    }
+
     // SAFETY:
     //  - The function is only called once.
     //  - The kernel stack has been allocated (`gdt::init` can reference it in the TSS).
+    //  - The per-CPU block is installed after the GDT has been loaded, which resets `gs`.
     unsafe {
-        super::cpu::gdt::init(&mut boot_allocator).unwrap_or_else(|_| oom());
+        super::cpu::gdt::init(&mut boot_allocator, upper_half_address_space)
+            .unwrap_or_else(|_| oom());
+        super::percpu::init(0, super::cpu::current_id(), KERNEL_STACK_TOP);
         super::cpu::idt::init();
+        super::cpu::init_umip();
+        super::cpu::pti::init(&mut boot_allocator, upper_half_address_space)
+            .unwrap_or_else(|_| oom());
+        boot_stage::record(BootStage::Idt);
+    }
+
+    // The ACPI tables live in memory that is about to be given to the memory tracker.
+    match rsdp {
+        Some(rsdp) => {
+            log::trace!("Parsing the ACPI tables...");
+            // SAFETY:
+            //  The function is only called once, and the memory tracker is not initialized yet.
+            unsafe { super::acpi::init(rsdp) };
+        }
+        None => log::warn!("No ACPI tables were found."),
+    }
+
+    // SAFETY:
+    //  The public data is mapped in the kernel address space, and no process is running yet.
+    unsafe {
+        (*(super::public_data_address() as *mut PublicData)).acpi = super::acpi::summary();
     }
 
     log::trace!("Initializing the global memory tracker...");
@@ -415,142 +621,175 @@ unsafe extern "C" fn entry_point_follow(transfer: *mut Transfer) -> ! {
         // part that contains the boot allocator, and the part that does not. The part that
         // has already been used must not be passed to the memory tracker.
         if segment.base == boot_allocator_start_address {
-            let diff = boot_allocator.peek() - segment.base;
+            let diff = boot_allocator.peek().get() - segment.base;
 
             debug_assert!(diff <= segment.length);
 
             segment.base += diff;
             segment.length -= diff;
 
-            debug_assert!(segment.base == boot_allocator.peek());
+            debug_assert!(segment.base == boot_allocator.peek().get());
         }
 
-        let mut start = crate::utility::align_page_up(segment.base);
-        let end = crate::utility::align_page_down(segment.base + segment.length);
+        let start = num::page_align_up(segment.base).unwrap_or(usize::MAX);
+        let end = num::page_align_down(segment.base + segment.length);
+
+        let frames = Frame::range(
+            Frame::containing(PhysAddr::new(start)),
+            Frame::containing(PhysAddr::new(end)),
+        );
+
+        // The boot stage record must survive until the next boot.
+        let boot_stage_frame = Frame::containing(PhysAddr::new(boot_stage::BOOT_STAGE_ADDRESS));
 
-        while start != end {
+        for frame in frames.filter(|&frame| frame != boot_stage_frame) {
             // SAFETY:
             //  We allocate enough capacity of the memory tracker to hold as many segments as there
             //  is available pages. That's well enough to hold all segments.
-            memory_tracker.mark_as_unused(start);
-            start += PAGE_SIZE;
+            memory_tracker.mark_as_unused(frame);
         }
     }
 
-    let memory_tracker = unsafe { MemoryTrackerTok::init(memory_tracker) };
+    crate::x86_64::reclaim::init(&mut memory_tracker);
+
+    unsafe { MemoryTrackerTok::init(memory_tracker) };
+    unsafe { boot_stage::record(BootStage::MemoryTracker) };
 
     unsafe { super::syscall::init() };
 
+    log::trace!("Masking the legacy PICs...");
+    unsafe {
+        super::cpu::pic::init();
+    }
+
     log::trace!("Initializing the local APIC...");
     unsafe {
         super::cpu::apic::init_local_apic();
     }
 
+    log::trace!("Initializing the I/O APICs...");
+    unsafe {
+        super::cpu::ioapic::init(upper_half_address_space);
+        super::acpi::init_power_button();
+        super::keyboard::init();
+    }
+
+    log::trace!("Refining the monotonic clock...");
+    unsafe {
+        super::hpet::init(upper_half_address_space);
+        timer::refine_clock();
+    }
+
+    log::trace!("Starting the scheduler tick...");
+    unsafe {
+        timer::init(timer_preference);
+    }
+
     log::trace!("Now accepting interrupts!");
     super::instr::sti();
+    unsafe { boot_stage::record(BootStage::Scheduler) };
 
-    // TODO:
-    //  Initialize the scheduler.
-
-    // TODO:
-    //  Bootstrap other CPUs.
+    log::trace!("Starting the application processors...");
+    unsafe { smp::start(upper_half_address_space) };
 
     log::trace!("Loading the `fabric_init` process...");
 
-    // We converting numbers using the native endianness, as the kernel is not supposed to run
-    // an init process that was compiled for a different endianness.
-    // If a process is compiled for a different endianness, the magic number will be reversed and
-    // we will be able to detect it.
-    let fabric_init = unsafe {
-        core::slice::from_raw_parts(
-            (fabric_init_start_address + HHDM_OFFSET) as *const u8,
-            fabric_init_size,
-        )
-    };
+    // SAFETY:
+    //  The images are retained by `retain_module`.
+    unsafe {
+        supervisor::init(init_exit_policy, fabric_init, upper_half_address_space);
+    }
 
-    if fabric_init.len() < core::mem::size_of::<InitHeader>() {
-        log::error!("`fabric_init` is too small to hold the necessary header.");
+    if supervisor::spawn_init().is_none() {
         crate::die();
     }
 
-    let header = unsafe { &*(fabric_init.as_ptr() as *const InitHeader) };
+    unsafe { boot_stage::record(BootStage::Init) };
 
-    if InitHeader::MAGIC != header.magic {
-        log::error!("The `fabric_init` process does not have a valid header.");
-        if header.magic == InitHeader::MAGIC.swap_bytes() {
-            log::error!("It seems to have been compiled for a different endianness.");
+    for (index, image) in modules.iter().flatten().enumerate() {
+        // SAFETY:
+        //  The images are retained by `retain_module`.
+        match unsafe { process::spawn(*image, upper_half_address_space) } {
+            Ok(id) => log::trace!("Started module #{} as process {}.", index, id),
+            Err(err) => log::error!("Failed to start module #{}: {}.", index, err.message()),
         }
-        crate::die();
     }
 
-    // Perform some sanity checks on the entry point.
-    //
-    // This won't prevent all possible issues (far from it), but it should catch some errors early
-    // on.
-    if header.entry_point.is_null()
-        || (header.entry_point as usize) < header.image_start as usize
-        || (header.entry_point as usize) >= header.image_start as usize + fabric_init.len()
-    {
-        log::error!("The `fabric_init` process does not have a valid entry point.");
-        crate::die();
-    }
+    #[cfg(feature = "ktest")]
+    crate::x86_64::ktest::run();
 
-    // Map the init process in memory at the correct position.
-    // We need to map the kernel in the upper half of the address space.
-    // Luckily, we already have an address space correctly set up for this. We can simply copy
-    // the upper half of the current address space.
-    log::trace!("Creating the address space of the `fabric_init` process...");
-    let new_l4_table;
-    {
-        use crate::x86_64::cpu::paging::{create_direct_map, PageTable};
-        use crate::x86_64::raw;
-
-        let l4_table = upper_half_address_space.get();
-
-        let mut mem_tracker = memory_tracker.lock();
-        new_l4_table = mem_tracker.allocate().unwrap_or_else(|_| oom());
-
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                (l4_table + HHDM_OFFSET) as *mut PageTable,
-                (new_l4_table + HHDM_OFFSET) as *mut PageTable,
-                1,
-            );
+    crate::x86_64::serial::log_stats();
+    log::info!("Passing control to the `fabric_init` process...");
 
-            create_direct_map(
-                &mut *((new_l4_table + HHDM_OFFSET) as *mut PageTable),
-                HHDM_OFFSET,
-                &mut || mem_tracker.allocate(),
-                fabric_init_start_address,
-                header.image_start as usize,
-                fabric_init_size,
-                raw::PageFlags::WRITABLE | raw::PageFlags::USER,
-            )
-            .unwrap_or_else(|_| oom());
+    // SAFETY:
+    //  No process has been started yet.
+    unsafe { crate::x86_64::scheduler::start() }
+}
+
+/// Registers the CPUs started by the bootloader, and sends the application processors to
+/// [`smp::ap_entry`], where they wait to be started by [`smp::start`].
+fn launch_application_processors(limine: req::LimineTok, l4_table: PhysAddr) {
+    let Some((bsp_lapic_id, cpus)) = req::cpus(limine) else {
+        // SAFETY:
+        //  This function is only called once, with the address space of the kernel.
+        unsafe { smp::init(super::cpu::current_id(), l4_table) };
+        return;
+    };
+
+    // SAFETY:
+    //  This function is only called once, with the address space of the kernel.
+    unsafe { smp::init(bsp_lapic_id, l4_table) };
+
+    for cpu in cpus.iter().filter(|cpu| cpu.lapic_id != bsp_lapic_id) {
+        // SAFETY:
+        //  The CPU is still waiting in the bootloader.
+        match unsafe { smp::register(cpu.lapic_id) } {
+            Some(index) => {
+                cpu.extra_argument.store(index as u64, Relaxed);
+                cpu.goto_address.store(smp::ap_entry as usize, Release);
+            }
+            None => {
+                log::warn!("CPU {} does not fit in the CPU table.", cpu.lapic_id);
+                cpu.goto_address.store(smp::ap_park as usize, Release);
+            }
         }
     }
 
-    // FIXME:
-    //  This should actually use the scheduler.
-    //  We don't have a scheduler yet.
+    log::trace!("Found {} CPU(s).", cpus.len());
+}
 
-    log::info!("Passing control to the `fabric_init` process...");
+/// Returns an [`Image`] that references the provided module, and remains valid after the
+/// bootloader reclaimable memory has been reclaimed.
+///
+/// Modules are loaded in memory that is never reclaimed. However, their command line lives in
+/// bootloader reclaimable memory, so it is copied to memory allocated by the boot allocator.
+fn retain_module(
+    module: req::Module,
+    current_hhdm: usize,
+    boot_allocator: &mut BootAllocator,
+) -> Image {
+    // Note that we can't simply use the virtual address provided by the bootloader because it
+    // points to the higher half direct map that we will replace soon with our own. Instead, we
+    // save the physical address of the module which won't depend on the current address space.
+    let cmdline_address = boot_allocator
+        .allocate(module.cmdline.len(), 1)
+        .unwrap_or_else(|_| oom());
+    usage::record(Category::Metadata, module.cmdline.len());
 
     unsafe {
-        // We have to set the current process.
-        crate::x86_64::process::CURRENT_PROCESS.address_space = new_l4_table;
-
-        asm!(
-            r#"
-            mov cr3, {new_l4_table}
-            sysretq
-            "#,
-            in("rcx") header.entry_point,
-            in("r11") 0x202,
-            new_l4_table = in(reg) new_l4_table,
-            options(noreturn),
+        core::ptr::copy_nonoverlapping(
+            module.cmdline.as_ptr(),
+            (cmdline_address.get() + current_hhdm) as *mut u8,
+            module.cmdline.len(),
         );
     }
+
+    Image {
+        physical_address: PhysAddr::new(module.data.as_ptr() as usize - current_hhdm),
+        size: module.data.len(),
+        cmdline_address,
+        cmdline_length: module.cmdline.len(),
+    }
 }
 
 /// Dies with a message indicating that the kernel ran out of memory.
@@ -563,6 +802,71 @@ fn oom() -> ! {
     crate::die();
 }
 
+/// Selects the sink of the kernel log from the `log` option of the provided command line.
+///
+/// The serial port is used by default (`log=serial`). With `log=e9`, the log is written to the
+/// debug console of the emulator instead, if it is present.
+fn select_log_sink(cmdline: crate::utility::Cmdline) {
+    match cmdline.get(b"log") {
+        None | Some(b"serial") => (),
+        Some(b"e9") if debugcon::is_present() => {
+            log::set_global_log_fn(debugcon::Debugcon.log_fn());
+            log::trace!("Logging to the debug console.");
+        }
+        Some(b"e9") => log::warn!("The debug console is not present, logging to the serial port."),
+        Some(other) => {
+            log::warn!(
+                "Unknown `log` sink: `{}`.",
+                core::str::from_utf8(other).unwrap_or("<invalid UTF-8>")
+            );
+            log::warn!("Falling back to `serial`.");
+        }
+    }
+}
+
+/// Builds the [`BootConfig`] exposed in the public data area.
+///
+/// This must be called once the shadow stacks and page table isolation have been configured.
+fn boot_config(
+    max_physical_memory: usize,
+    init_exit_policy: InitExitPolicy,
+    timer_preference: TimerPreference,
+    headless: bool,
+) -> BootConfig {
+    let prefix = log::prefix();
+    let mut log_prefix = LogPrefix::empty();
+    log_prefix.set(
+        LogPrefix::TIMESTAMP,
+        prefix.contains(log::Prefix::TIMESTAMP),
+    );
+    log_prefix.set(LogPrefix::CPU, prefix.contains(log::Prefix::CPU));
+
+    let mut flags = BootFlags::empty();
+    flags.set(
+        BootFlags::SHADOW_STACKS,
+        crate::x86_64::cpu::cet::shadow_stacks_enabled(),
+    );
+    flags.set(
+        BootFlags::PAGE_TABLE_ISOLATION,
+        crate::x86_64::cpu::pti::enabled(),
+    );
+    flags.set(BootFlags::HEADLESS, headless);
+    flags.set(BootFlags::ASLR, crate::x86_64::aslr::enabled());
+    flags.set(
+        BootFlags::DETERMINISTIC,
+        crate::x86_64::deterministic::enabled(),
+    );
+
+    BootConfig {
+        max_physical_memory: max_physical_memory as u64,
+        init_exit_policy: init_exit_policy.to_public(),
+        timer_preference: timer_preference.to_public(),
+        log_prefix,
+        _reserved: 0,
+        flags,
+    }
+}
+
 /// Returns whether the provided framebuffer is supported.
 ///
 /// This function returns `true` if an only if `try_convert_framebuffer` would return `Some`.
@@ -585,12 +889,12 @@ fn try_convert_framebuffer(
     };
 
     Some(Framebuffer {
-        width: framebuffer.width as usize,
-        height: framebuffer.height as usize,
-        pitch: framebuffer.pitch as usize,
+        width: framebuffer.width,
+        height: framebuffer.height,
+        pitch: framebuffer.pitch,
         color_mode,
         _reserved: [0; 7],
-        physical_address: framebuffer.address as usize - hhdm_offset,
-        owned_by: AtomicUsize::new(0),
+        physical_address: (framebuffer.address as usize - hhdm_offset) as u64,
+        owned_by: AtomicU64::new(0),
     })
 }
@@ -262,6 +262,28 @@ pub struct ModuleResponse {
     pub modules: *mut *mut File,
 }
 
+pub const KERNEL_FILE_REQUEST: [u64; 4] = [
+    COMMON_MAGIC[0],
+    COMMON_MAGIC[1],
+    0xad97e90e83f1ed67,
+    0x31eb5d1c5ff23b69,
+];
+
+pub const KERNEL_FILE_REQUEST_REVISION: u64 = 0;
+
+#[repr(C)]
+pub struct KernelFileRequest {
+    pub id: [u64; 4],
+    pub revision: u64,
+    pub response: ResponsePtr<KernelFileResponse>,
+}
+
+#[repr(C)]
+pub struct KernelFileResponse {
+    pub revision: u64,
+    pub kernel_file: *mut File,
+}
+
 pub const KERNEL_ADDRESS_REQUEST: [u64; 4] = [
     COMMON_MAGIC[0],
     COMMON_MAGIC[1],
@@ -284,3 +306,64 @@ pub struct KernelAddressResponse {
     pub physical_base: u64,
     pub virtual_base: u64,
 }
+
+pub const RSDP_REQUEST: [u64; 4] = [
+    COMMON_MAGIC[0],
+    COMMON_MAGIC[1],
+    0xc5e77b6b397e7b43,
+    0x27637845accdcf3c,
+];
+
+pub const RSDP_REQUEST_REVISION: u64 = 0;
+
+#[repr(C)]
+pub struct RsdpRequest {
+    pub id: [u64; 4],
+    pub revision: u64,
+    pub response: ResponsePtr<RsdpResponse>,
+}
+
+#[repr(C)]
+pub struct RsdpResponse {
+    pub revision: u64,
+    pub address: *mut c_void,
+}
+
+pub const SMP_REQUEST: [u64; 4] = [
+    COMMON_MAGIC[0],
+    COMMON_MAGIC[1],
+    0x95a67b819a1b857e,
+    0xa0b61b723b6a73e0,
+];
+
+pub const SMP_REQUEST_REVISION: u64 = 0;
+
+#[repr(C)]
+pub struct SmpRequest {
+    pub id: [u64; 4],
+    pub revision: u64,
+    pub response: ResponsePtr<SmpResponse>,
+    pub flags: u64,
+}
+
+#[repr(C)]
+pub struct SmpResponse {
+    pub revision: u64,
+    pub flags: u32,
+    pub bsp_lapic_id: u32,
+    pub cpu_count: u64,
+    pub cpus: *mut *mut SmpInfo,
+}
+
+/// Describes a CPU started by the bootloader.
+///
+/// The CPU waits until `goto_address` is written, and jumps to it with the address of this
+/// structure in `rdi`.
+#[repr(C)]
+pub struct SmpInfo {
+    pub processor_id: u32,
+    pub lapic_id: u32,
+    pub reserved: u64,
+    pub goto_address: core::sync::atomic::AtomicUsize,
+    pub extra_argument: core::sync::atomic::AtomicU64,
+}
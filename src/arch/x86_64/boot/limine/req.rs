@@ -2,7 +2,7 @@ use core::ffi::c_char;
 use core::ptr::addr_of;
 
 use super::raw;
-use crate::x86_64::mem::PAGE_SIZE;
+use crate::x86_64::mem::{PhysAddr, PAGE_SIZE};
 use crate::{builtins, log};
 
 /// A "token" type that proves that the bootloader reclaimable memory map is still around
@@ -39,7 +39,7 @@ impl<'a> LimineTok<'a> {
 /// used anywhere else in the image.
 #[link_section = ".limine_reqs"]
 #[used]
-static mut LIMINE_REQS: [*const (); 8] = unsafe {
+static mut LIMINE_REQS: [*const (); 11] = unsafe {
     [
         addr_of!(BOOTLOADER_INFO) as *const (),
         addr_of!(HHDM) as *const (),
@@ -48,6 +48,9 @@ static mut LIMINE_REQS: [*const (); 8] = unsafe {
         addr_of!(ENTRY_POINT) as *const (),
         addr_of!(MODULE) as *const (),
         addr_of!(KERNEL_ADDRESS) as *const (),
+        addr_of!(KERNEL_FILE) as *const (),
+        addr_of!(RSDP) as *const (),
+        addr_of!(SMP) as *const (),
         core::ptr::null(),
     ]
 };
@@ -239,13 +242,23 @@ static mut MODULE: raw::ModuleRequest = raw::ModuleRequest {
     .as_ptr() as *mut *mut raw::InternalModule,
 };
 
-/// Returns a slice over the first module named `fabric_init`.
+/// A module loaded by the bootloader.
+#[derive(Clone, Copy)]
+pub struct Module<'a> {
+    /// The name of the module. This is the last component of its path.
+    pub name: &'a [u8],
+    /// The content of the module.
+    pub data: &'a [u8],
+    /// The command line associated with the module.
+    pub cmdline: &'a [u8],
+}
+
+/// Returns an iterator over the modules loaded by the bootloader.
 ///
 /// # Dies
 ///
-/// This function dies if no such module is found or if the request is not answered by the
-/// bootloader.
-pub fn fabric_init(_: LimineTok) -> &[u8] {
+/// This function dies if the request is not answered by the bootloader.
+pub fn modules<'a>(_: LimineTok<'a>) -> impl Iterator<Item = Module<'a>> {
     // SAFETY:
     //  This request is never accessed mutably.
     let response = unsafe { MODULE.response.read() };
@@ -262,45 +275,58 @@ pub fn fabric_init(_: LimineTok) -> &[u8] {
 
     // SAFETY:
     //  This relies on the correctness of the bootloader. We can't really check that.
-    let modules = unsafe {
+    let files = unsafe {
         core::slice::from_raw_parts(
             response.modules as *const &raw::File,
             response.module_count as usize,
         )
     };
 
+    files.iter().map(|file| {
+        // SAFETY:
+        //  The bootloader must provide valid C strings. We can't really check that.
+        let path = unsafe { make_u8_slice(file.path) };
+
+        // Get the position of the last slash.
+        let start = match path.iter().rposition(|&c| c == b'/') {
+            Some(pos) => pos + 1,
+            None => 0,
+        };
+
+        // SAFETY:
+        //  This relies on the correctness of the bootloader. We can't really check that.
+        unsafe {
+            Module {
+                name: path.get_unchecked(start..),
+                data: core::slice::from_raw_parts(file.address as *const u8, file.size as usize),
+                cmdline: if file.cmdline.is_null() {
+                    &[]
+                } else {
+                    make_u8_slice(file.cmdline)
+                },
+            }
+        }
+    })
+}
+
+/// Returns the first module named `fabric_init`.
+///
+/// # Dies
+///
+/// This function dies if no such module is found or if the request is not answered by the
+/// bootloader.
+pub fn fabric_init(limine: LimineTok) -> Module {
     log::trace!("Enumerating kernel modules...");
 
-    let file = modules
-        .iter()
-        .find(|file| {
-            // SAFETY:
-            //  The bootloader must provide valid C strings. We can't really check that.
-            let path = unsafe { make_u8_slice(file.path) };
-
-            // Get the position of the last slash.
-            let start = match path.iter().rposition(|&c| c == b'/') {
-                Some(pos) => pos + 1,
-                None => 0,
-            };
-
-            // SAFETY:
-            //  This is always valid UTF-8.
-            let filename = unsafe { path.get_unchecked(start..) };
-
-            filename == b"fabric_init"
-        })
+    modules(limine)
+        .find(|module| module.name == b"fabric_init")
         .unwrap_or_else(|| {
             log::error!("No module named 'fabric_init' was found.");
             log::error!("This module is required for the kernel to boot.");
             log::error!("");
             log::error!("Check your 'limine.cfg' configration!");
             crate::die();
-        });
-
-    // SAFETY:
-    //  This relies on the correctness of the bootloader. We can't really check that.
-    unsafe { core::slice::from_raw_parts(file.address as *const u8, file.size as usize) }
+        })
 }
 
 static mut KERNEL_ADDRESS: raw::KernelAddressRequest = raw::KernelAddressRequest {
@@ -317,7 +343,7 @@ static mut KERNEL_ADDRESS: raw::KernelAddressRequest = raw::KernelAddressRequest
 /// # Dies
 ///
 /// This function dies if the bootloader did not respond to the kernel address request.
-pub fn kernel_physical_address(_: LimineTok) -> usize {
+pub fn kernel_physical_address(_: LimineTok) -> PhysAddr {
     // SAFETY:
     //  This request is never accessed mutably.
     let response = unsafe { KERNEL_ADDRESS.response.read() };
@@ -336,7 +362,70 @@ pub fn kernel_physical_address(_: LimineTok) -> usize {
         log::warn!("How are we even running?");
     }
 
-    response.physical_base as usize
+    PhysAddr::new(response.physical_base as usize)
+}
+
+static mut KERNEL_FILE: raw::KernelFileRequest = raw::KernelFileRequest {
+    id: raw::KERNEL_FILE_REQUEST,
+    revision: raw::KERNEL_FILE_REQUEST_REVISION,
+    response: raw::ResponsePtr::NULL,
+};
+
+/// Returns the content of the kernel ELF file, as loaded by the bootloader.
+///
+/// If the bootloader did not respond to the kernel file request, `None` is returned.
+pub fn kernel_file(_: LimineTok) -> Option<&[u8]> {
+    // SAFETY:
+    //  This request is never accessed mutably.
+    let response = unsafe { KERNEL_FILE.response.read() };
+    if response.is_null() {
+        return None;
+    }
+
+    // SAFETY:
+    //  The `LimineTok` token that this function requires proves that the bootloader reclaimable
+    // memory is still mapped and initialized.
+    let response = unsafe { &*response };
+
+    // SAFETY:
+    //  This relies on the correctness of the bootloader. We can't really check that.
+    unsafe {
+        let file = &*response.kernel_file;
+        Some(core::slice::from_raw_parts(
+            file.address as *const u8,
+            file.size as usize,
+        ))
+    }
+}
+
+/// Returns the command line that was passed to the kernel.
+///
+/// If the bootloader did not respond to the kernel file request, an empty command line is
+/// returned.
+pub fn kernel_cmdline(_: LimineTok) -> &[u8] {
+    // SAFETY:
+    //  This request is never accessed mutably.
+    let response = unsafe { KERNEL_FILE.response.read() };
+    if response.is_null() {
+        log::warn!("The bootloader did not respond to the kernel file request.");
+        log::warn!("The kernel command line will be ignored.");
+        return &[];
+    }
+
+    // SAFETY:
+    //  The `LimineTok` token that this function requires proves that the bootloader reclaimable
+    // memory is still mapped and initialized.
+    let response = unsafe { &*response };
+
+    // SAFETY:
+    //  This relies on the correctness of the bootloader. We can't really check that.
+    unsafe {
+        let file = &*response.kernel_file;
+        if file.cmdline.is_null() {
+            return &[];
+        }
+        make_u8_slice(file.cmdline)
+    }
 }
 
 /// Creates a new Rust string from a C string.
@@ -349,3 +438,67 @@ pub fn kernel_physical_address(_: LimineTok) -> usize {
 unsafe fn make_u8_slice<'a>(s: *const c_char) -> &'a [u8] {
     unsafe { core::slice::from_raw_parts(s as *const u8, builtins::strlen(s)) }
 }
+
+static mut RSDP: raw::RsdpRequest = raw::RsdpRequest {
+    id: raw::RSDP_REQUEST,
+    revision: raw::RSDP_REQUEST_REVISION,
+    response: raw::ResponsePtr::NULL,
+};
+
+/// Returns the physical address of the ACPI RSDP structure.
+///
+/// If the bootloader did not respond to the RSDP request, `None` is returned.
+pub fn rsdp(_: LimineTok, hhdm_offset: usize) -> Option<usize> {
+    // SAFETY:
+    //  This request is never accessed mutably.
+    let response = unsafe { RSDP.response.read() };
+    if response.is_null() {
+        log::warn!("The bootloader did not respond to the RSDP request.");
+        return None;
+    }
+
+    // SAFETY:
+    //  The `LimineTok` token that this function requires proves that the bootloader reclaimable
+    // memory is still mapped and initialized.
+    let response = unsafe { &*response };
+
+    // The address is provided in the higher half direct map set up by the bootloader.
+    Some(response.address as usize - hhdm_offset)
+}
+
+static mut SMP: raw::SmpRequest = raw::SmpRequest {
+    id: raw::SMP_REQUEST,
+    revision: raw::SMP_REQUEST_REVISION,
+    response: raw::ResponsePtr::NULL,
+    flags: 0,
+};
+
+/// Returns the local APIC ID of the bootstrap CPU, and the list of the CPUs started by the
+/// bootloader. The list includes the bootstrap CPU.
+///
+/// If the bootloader did not respond to the SMP request, `None` is returned.
+pub fn cpus(_: LimineTok) -> Option<(u32, &[&raw::SmpInfo])> {
+    // SAFETY:
+    //  This request is never accessed mutably.
+    let response = unsafe { SMP.response.read() };
+    if response.is_null() {
+        log::warn!("The bootloader did not respond to the SMP request.");
+        return None;
+    }
+
+    // SAFETY:
+    //  The `LimineTok` token that this function requires proves that the bootloader reclaimable
+    // memory is still mapped and initialized.
+    let response = unsafe { &*response };
+
+    // SAFETY:
+    //  This relies on the correctness of the bootloader. We can't really check that.
+    let cpus = unsafe {
+        core::slice::from_raw_parts(
+            response.cpus as *const &raw::SmpInfo,
+            response.cpu_count as usize,
+        )
+    };
+
+    Some((response.bsp_lapic_id, cpus))
+}
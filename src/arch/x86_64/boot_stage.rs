@@ -0,0 +1,75 @@
+//! Reports the progress of the boot process to external tooling.
+//!
+//! When the kernel hangs during boot, the log alone does not always tell where: it may not be
+//! connected, or it may have been lost with a reset. Each major milestone of the boot process is
+//! thus recorded in three places:
+//!
+//! - The log, at the `trace` level.
+//!
+//! - The POST code port (`0x80`), which is displayed by POST cards and can be traced by
+//!   emulators.
+//!
+//! - A fixed location of physical memory, [`BOOT_STAGE_ADDRESS`], which survives warm reboots
+//!   and can be read by debuggers and by the next boot. The frame that contains it is never
+//!   handed out by the memory tracker.
+//!
+//! # Record Format
+//!
+//! The record is a single 64-bit little-endian word. Its low 32 bits are [`MAGIC`], and its high
+//! 32 bits are the last [`BootStage`] reached by the kernel.
+//!
+//! Stages are only recorded once the kernel runs in its own address space, where the location
+//! of the record is known to be mapped. A record that is missing, or left over from a previous
+//! boot, means that the kernel hung before that point.
+
+use crate::log;
+use crate::x86_64::instr;
+use crate::x86_64::mem::PhysAddr;
+
+pub use crate::x86_64::config::BOOT_STAGE_ADDRESS;
+
+/// The low 32 bits of a boot stage record (`FBST` in ASCII).
+pub const MAGIC: u32 = u32::from_le_bytes(*b"FBST");
+
+/// The I/O port of POST codes.
+const POST_CODE_PORT: u16 = 0x80;
+
+/// A milestone of the boot process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BootStage {
+    /// The kernel runs in its own address space.
+    Paging = 1,
+    /// The GDT and the IDT are loaded: CPU exceptions are reported.
+    Idt = 2,
+    /// The global memory tracker is initialized.
+    MemoryTracker = 3,
+    /// Interrupts are enabled and the scheduler tick is running.
+    Scheduler = 4,
+    /// The init process has been loaded.
+    Init = 5,
+}
+
+/// Records that the boot process has reached the provided stage.
+///
+/// See the [module-level documentation](self) for more information.
+///
+/// # Safety
+///
+/// The kernel must run in its own address space, where the higher half direct map covers
+/// [`BOOT_STAGE_ADDRESS`].
+pub unsafe fn record(stage: BootStage) {
+    log::trace!("Reached boot stage {:?}.", stage);
+
+    // SAFETY:
+    //  Writing to the POST code port has no side effects besides external displays.
+    unsafe { instr::outb(POST_CODE_PORT, stage as u8) };
+
+    let record = MAGIC as u64 | (stage as u64) << 32;
+    let addr = PhysAddr::new(BOOT_STAGE_ADDRESS).to_hhdm();
+
+    // SAFETY:
+    //  The caller guarantees that the address is mapped, and the frame that contains it is
+    //  never used for anything else.
+    unsafe { addr.as_ptr::<u64>().write_volatile(record) };
+}
@@ -0,0 +1,116 @@
+//! The build ID of the kernel.
+//!
+//! The build script records the commit the kernel was built from, the time of the build and the
+//! version of the compiler that was used. They are stored in a [`BuildId`] placed in its own
+//! section of the kernel image, `.fabric.build_id`, so that external tooling can read them from
+//! the ELF file without running the kernel.
+//!
+//! The build ID is logged first thing at boot, included in crash reports, and exposed to
+//! processes through the `kernel_info` system call. This makes it possible to tell which build
+//! of the kernel produced a log, and whether a program was built against a different kernel than
+//! the one it runs on.
+//!
+//! Reproducible builds can fix the time of the build through the `SOURCE_DATE_EPOCH` environment
+//! variable.
+
+use core::fmt;
+
+use super::config::parse_usize;
+
+/// The magic number found at the start of the `.fabric.build_id` section.
+pub const MAGIC: [u8; 8] = *b"FABRICID";
+
+/// The maximum length of the commit hash stored in a [`BuildId`], in bytes.
+pub const COMMIT_LENGTH: usize = 16;
+
+/// The maximum length of the version of the compiler stored in a [`BuildId`], in bytes.
+pub const RUSTC_LENGTH: usize = 64;
+
+/// The identity of a build of the kernel.
+///
+/// Strings are stored as ASCII, padded with zeros. Strings that do not fit are truncated.
+#[repr(C)]
+pub struct BuildId {
+    /// Always [`MAGIC`].
+    pub magic: [u8; 8],
+    /// The time of the build, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The abbreviated hash of the commit the kernel was built from.
+    ///
+    /// This is empty if the kernel was not built from a git repository.
+    pub commit: [u8; COMMIT_LENGTH],
+    /// The version of the compiler, as reported by `rustc --version`.
+    pub rustc: [u8; RUSTC_LENGTH],
+}
+
+/// The build ID of the running kernel.
+///
+/// The linker script must **KEEP** this section in the final kernel image, as the symbol is not
+/// necessarily referenced anywhere else.
+#[link_section = ".fabric.build_id"]
+#[used]
+pub static BUILD_ID: BuildId = BuildId {
+    magic: MAGIC,
+    timestamp: match parse_usize(env!("FABRIC_BUILD_TIMESTAMP")) {
+        Some(value) => value as u64,
+        None => panic!("invalid build timestamp"),
+    },
+    commit: padded(env!("FABRIC_BUILD_COMMIT")),
+    rustc: padded(env!("FABRIC_BUILD_RUSTC")),
+};
+
+/// Copies `s` into a zero-padded array, truncating it if it does not fit.
+const fn padded<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut result = [0; N];
+
+    let mut i = 0;
+    while i < N && i < bytes.len() {
+        result[i] = bytes[i];
+        i += 1;
+    }
+
+    result
+}
+
+/// Returns the part of `bytes` before the first zero, as a string.
+fn unpadded(bytes: &[u8]) -> &str {
+    let length = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..length]).unwrap_or("<invalid>")
+}
+
+impl BuildId {
+    /// Returns the abbreviated hash of the commit the kernel was built from, or an empty string if
+    /// it is not known.
+    pub fn commit(&self) -> &str {
+        unpadded(&self.commit)
+    }
+
+    /// Returns the version of the compiler that built the kernel, or an empty string if it is not
+    /// known.
+    pub fn rustc(&self) -> &str {
+        unpadded(&self.rustc)
+    }
+}
+
+impl fmt::Display for BuildId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let commit = match self.commit() {
+            "" => "unknown commit",
+            commit => commit,
+        };
+        let rustc = match self.rustc() {
+            "" => "unknown compiler",
+            rustc => rustc,
+        };
+
+        write!(
+            f,
+            "fabric {} ({}, built at {}, {})",
+            env!("CARGO_PKG_VERSION"),
+            commit,
+            self.timestamp,
+            rustc,
+        )
+    }
+}
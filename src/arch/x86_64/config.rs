@@ -0,0 +1,229 @@
+//! The fundamental constants of the kernel.
+//!
+//! # Build-Time Overrides
+//!
+//! The constants of this module may be overridden when building the kernel by setting
+//! environment variables, with values written in decimal or in hexadecimal (with a `0x` prefix):
+//!
+//! | Constant                    | Environment variable               |
+//! |-----------------------------|------------------------------------|
+//! | [`USER_TOP`]                | `FABRIC_USER_TOP`                  |
+//! | [`KERNEL_STACK_SIZE`]       | `FABRIC_KERNEL_STACK_SIZE`         |
+//! | [`DOUBLE_FAULT_STACK_SIZE`] | `FABRIC_DOUBLE_FAULT_STACK_SIZE`   |
+//! | [`MAX_PHYSICAL_MEMORY`]     | `FABRIC_MAX_PHYSICAL_MEMORY`       |
+//! | [`BOOT_STAGE_ADDRESS`]      | `FABRIC_BOOT_STAGE_ADDRESS`        |
+//!
+//! Invalid values are rejected at compile time.
+//!
+//! # Boot-Time Overrides
+//!
+//! The amount of physical memory used by the kernel can be further reduced with the
+//! `max_memory` option of the kernel command line. See [`max_physical_memory`].
+
+use crate::log;
+use crate::utility::Cmdline;
+use crate::x86_64::mem::PAGE_SIZE;
+
+/// Parses an unsigned integer written in decimal, or in hexadecimal with a `0x` prefix.
+///
+/// Underscores are ignored.
+///
+/// # Returns
+///
+/// `None` is returned if the string is empty, contains invalid digits, or if the value does not
+/// fit in a `usize`.
+pub const fn parse_usize(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+
+    let (radix, mut i) = if bytes.len() > 2 && bytes[0] == b'0' && bytes[1] == b'x' {
+        (16, 2)
+    } else {
+        (10, 0)
+    };
+
+    if i == bytes.len() {
+        return None;
+    }
+
+    let mut value: usize = 0;
+    while i < bytes.len() {
+        let digit = match bytes[i] {
+            b'_' => {
+                i += 1;
+                continue;
+            }
+            b @ b'0'..=b'9' => (b - b'0') as usize,
+            b @ b'a'..=b'f' if radix == 16 => (b - b'a' + 10) as usize,
+            b @ b'A'..=b'F' if radix == 16 => (b - b'A' + 10) as usize,
+            _ => return None,
+        };
+
+        value = match value.checked_mul(radix) {
+            Some(v) => match v.checked_add(digit) {
+                Some(v) => v,
+                None => return None,
+            },
+            None => return None,
+        };
+
+        i += 1;
+    }
+
+    Some(value)
+}
+
+/// Parses a component of the version of the kernel.
+///
+/// # Panics
+///
+/// This function panics if the component is not a valid integer or does not fit in a `u32`.
+const fn version_component(s: &str) -> u32 {
+    match parse_usize(s) {
+        Some(value) if value <= u32::MAX as usize => value as u32,
+        _ => panic!("invalid version component"),
+    }
+}
+
+/// The version of the kernel, as `[major, minor, patch]`.
+pub const VERSION: [u32; 3] = [
+    version_component(env!("CARGO_PKG_VERSION_MAJOR")),
+    version_component(env!("CARGO_PKG_VERSION_MINOR")),
+    version_component(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+/// Returns the value of a build-time override, or `default` if it is not set.
+///
+/// # Panics
+///
+/// This function panics if the override is not a valid integer. As it is only called in
+/// constant contexts, this is reported at compile time.
+const fn or_default(value: Option<&str>, default: usize) -> usize {
+    match value {
+        Some(s) => match parse_usize(s) {
+            Some(v) => v,
+            None => panic!("invalid build-time override of a kernel constant"),
+        },
+        None => default,
+    }
+}
+
+/// The last address of the lower half of the address space with 4-level paging, where virtual
+/// addresses are 48 bits wide.
+pub const LOWER_HALF_TOP_4_LEVEL: usize = 0x00007FFF_FFFFFFFF;
+
+/// The last address of the lower half of the address space with 5-level paging, where virtual
+/// addresses are 57 bits wide.
+pub const LOWER_HALF_TOP_5_LEVEL: usize = 0x00FFFFFF_FFFFFFFF;
+
+/// The last address that may be part of the virtual address space of userland processes.
+///
+/// This must not go past the lower half of the address space with 5-level paging. The actual
+/// bound is only known at runtime, as it also depends on the active paging mode: see
+/// [`user_top`](crate::x86_64::cpu::paging::user_top).
+pub const USER_TOP: usize = or_default(option_env!("FABRIC_USER_TOP"), LOWER_HALF_TOP_4_LEVEL);
+
+/// The last address of the part of the address space of userland processes where the kernel
+/// places its fixed mappings, such as the initial stack and the inbox.
+///
+/// Those mappings must have the same address whatever the active paging mode, so they remain
+/// within the lower half of 4-level paging.
+pub const USER_FIXED_TOP: usize = if USER_TOP < LOWER_HALF_TOP_4_LEVEL {
+    USER_TOP
+} else {
+    LOWER_HALF_TOP_4_LEVEL
+};
+
+/// The size of the kernel stack.
+pub const KERNEL_STACK_SIZE: usize =
+    or_default(option_env!("FABRIC_KERNEL_STACK_SIZE"), PAGE_SIZE * 16);
+
+/// The amount of the stack memory reserved for the double fault handler.
+///
+/// A separate stack is used for this CPU exception because it might be trigged by a stack overflow
+/// within the kernel stack. In that case, the current kernel stack is unusable, and the double
+/// fault will turn in a triple fault, causing the machine to reboot. Unlikely, but highly
+/// undesirable.
+pub const DOUBLE_FAULT_STACK_SIZE: usize =
+    or_default(option_env!("FABRIC_DOUBLE_FAULT_STACK_SIZE"), PAGE_SIZE * 4);
+
+/// The maximum amount of physical memory supported by the kernel.
+///
+/// This is currently (and kinda arbitrarily) set to 1 TiB by default.
+pub const MAX_PHYSICAL_MEMORY: usize = or_default(
+    option_env!("FABRIC_MAX_PHYSICAL_MEMORY"),
+    1024 * 1024 * 1024 * 1024,
+);
+
+/// The physical address at which the progress of the boot process is recorded.
+///
+/// This defaults to the start of the memory that is conventionally left free after the BIOS data
+/// area. See [`boot_stage`](crate::x86_64::boot_stage).
+pub const BOOT_STAGE_ADDRESS: usize = or_default(option_env!("FABRIC_BOOT_STAGE_ADDRESS"), 0x500);
+
+const _: () = {
+    assert!(
+        USER_TOP <= LOWER_HALF_TOP_5_LEVEL,
+        "USER_TOP must be part of the lower half"
+    );
+    assert!(
+        (USER_TOP + 1) % PAGE_SIZE == 0,
+        "USER_TOP must be right before a page boundary"
+    );
+    assert!(
+        KERNEL_STACK_SIZE != 0 && KERNEL_STACK_SIZE % PAGE_SIZE == 0,
+        "KERNEL_STACK_SIZE must be a non-zero multiple of the page size"
+    );
+    assert!(
+        DOUBLE_FAULT_STACK_SIZE != 0 && DOUBLE_FAULT_STACK_SIZE % 16 == 0,
+        "DOUBLE_FAULT_STACK_SIZE must be a non-zero multiple of 16"
+    );
+    assert!(
+        MAX_PHYSICAL_MEMORY != 0 && MAX_PHYSICAL_MEMORY % PAGE_SIZE == 0,
+        "MAX_PHYSICAL_MEMORY must be a non-zero multiple of the page size"
+    );
+    // The direct map spans from the start of the higher half to the vmalloc region.
+    assert!(
+        MAX_PHYSICAL_MEMORY <= 0x00007E80_00000000,
+        "MAX_PHYSICAL_MEMORY does not fit in the direct map"
+    );
+    assert!(
+        BOOT_STAGE_ADDRESS % 8 == 0 && BOOT_STAGE_ADDRESS < MAX_PHYSICAL_MEMORY,
+        "BOOT_STAGE_ADDRESS must be 8-byte aligned and part of the direct map"
+    );
+};
+
+/// Parses a size with an optional `K`, `M`, `G` or `T` binary suffix.
+fn parse_size(s: &[u8]) -> Option<usize> {
+    let (digits, shift) = match s.last()? {
+        b'K' | b'k' => (&s[..s.len() - 1], 10),
+        b'M' | b'm' => (&s[..s.len() - 1], 20),
+        b'G' | b'g' => (&s[..s.len() - 1], 30),
+        b'T' | b't' => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+
+    let value = parse_usize(core::str::from_utf8(digits).ok()?)?;
+    value.checked_mul(1 << shift)
+}
+
+/// Returns the amount of physical memory that the kernel may use.
+///
+/// This is [`MAX_PHYSICAL_MEMORY`], unless a lower value is requested with the `max_memory`
+/// option of the provided command line (such as `max_memory=512M`). Invalid values are reported
+/// and ignored.
+pub fn max_physical_memory(cmdline: Cmdline) -> usize {
+    let Some(value) = cmdline.get(b"max_memory") else {
+        return MAX_PHYSICAL_MEMORY;
+    };
+
+    match parse_size(value) {
+        Some(size) if size >= PAGE_SIZE => (size - size % PAGE_SIZE).min(MAX_PHYSICAL_MEMORY),
+        _ => {
+            log::warn!(
+                "Invalid `max_memory` value: `{}`.",
+                core::str::from_utf8(value).unwrap_or("<invalid UTF-8>")
+            );
+            MAX_PHYSICAL_MEMORY
+        }
+    }
+}
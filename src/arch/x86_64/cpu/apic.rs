@@ -1,36 +1,75 @@
-use core::ptr;
+use core::arch::asm;
 
-use crate::x86_64::cpu::idt;
+use crate::utility::{MmioRegion, PerCpuInitGuard};
+use crate::x86_64::cpu::{idt, pit};
 use crate::x86_64::instr::{rdmsr, wrmsr};
 use crate::x86_64::mem::HHDM_OFFSET;
 use crate::x86_64::raw;
-use crate::x86_64::raw::StackFrame;
+use crate::x86_64::raw::{StackFrame, TrapFrame};
+use crate::x86_64::scheduler::{self, restore_trap_frame};
+use crate::x86_64::timer;
 
-/// Reads the local APIC base address from the IA32_APIC_BASE MSR.
+/// Returns the registers of the local APIC, whose base address is read from the
+/// IA32_APIC_BASE MSR.
 ///
-/// This automatically converts the physical address to a virtual address.
+/// The registers are accessed through the direct map.
 #[inline]
-fn get_local_apic_base() -> *mut u32 {
+fn local_apic() -> MmioRegion {
     let base = unsafe { rdmsr(raw::IA32_APIC_BASE) & 0xFFFFF000 };
-    (base as usize + HHDM_OFFSET) as *mut u32
+
+    // SAFETY:
+    //  The registers of the local APIC are always mapped in the direct map, and accessing them
+    //  only affects interrupts.
+    unsafe {
+        MmioRegion::new(
+            (base as usize + HHDM_OFFSET) as *mut u8,
+            raw::LAPIC_REGISTERS_SIZE,
+        )
+    }
 }
 
 /// Sends an end-of-interrupt (EOI) signal to the local APIC.
 #[inline]
-fn send_eoi() {
-    let base = get_local_apic_base();
+pub fn send_eoi() {
+    local_apic().write(raw::LAPIC_EOI, 0);
+}
 
-    unsafe {
-        ptr::write_volatile(base.byte_add(raw::LAPIC_EOI), 0);
+/// Returns the ID of the local APIC of the current CPU.
+#[inline]
+pub fn local_apic_id() -> u8 {
+    (local_apic().read(raw::LAPIC_ID) >> 24) as u8
+}
+
+/// Sends an inter-processor interrupt with the provided vector to the CPU whose local APIC has
+/// the provided ID.
+///
+/// The function returns once the local APIC has sent the interrupt, which does not mean that the
+/// other CPU has handled it yet.
+pub fn send_ipi(lapic_id: u32, vector: u8) {
+    let apic = local_apic();
+
+    // Writing the low half of the register sends the interrupt.
+    apic.write(raw::LAPIC_ICR_HIGH, lapic_id << 24);
+    apic.write(raw::LAPIC_ICR_LOW, vector as u32 | raw::LAPIC_ICR_ASSERT);
+
+    while apic.read(raw::LAPIC_ICR_LOW) & raw::LAPIC_ICR_SEND_PENDING != 0 {
+        core::hint::spin_loop();
     }
 }
 
 /// Initializes the local APIC of the current CPU.
 ///
+/// The timer of the local APIC is left disabled. See [`start_timer`].
+///
 /// # Safety
 ///
 /// This function may only be called once per CPU core.
 pub unsafe fn init_local_apic() {
+    static GUARD: PerCpuInitGuard = PerCpuInitGuard::new();
+    if !GUARD.enter(super::current_id() as u8, "apic::init_local_apic") {
+        return;
+    }
+
     let base = unsafe { rdmsr(raw::IA32_APIC_BASE) & 0xFFFFF000 };
     debug_assert!(
         base & 0xFFFFFFFF != 0,
@@ -40,33 +79,152 @@ pub unsafe fn init_local_apic() {
     // Writing to the IA32_APIC_BASE MSR will hardware-enable the local APIC.
     unsafe { wrmsr(raw::IA32_APIC_BASE, base) };
 
-    let base = (base as usize + HHDM_OFFSET) as *mut u32;
+    // Set an spuriour interrupt handler to software enable the local APIC. The other bits of the
+    // register are reserved and must be preserved.
+    local_apic().modify(raw::LAPIC_SPURIOUS_INTERRUPT_VECTOR, |value| {
+        (value & !0x1FF) | idt::LAPIC_SPURIOUS_VECTOR as u32 | (1 << 8)
+    });
+}
 
-    unsafe {
-        // Set an spuriour interrupt handler to software enable the local APIC.
-        ptr::write_volatile(
-            base.byte_add(raw::LAPIC_SPURIOUS_INTERRUPT_VECTOR),
-            idt::LAPIC_SPURIOUS_VECTOR as u32 | (1 << 8),
-        );
+/// Measures the number of ticks of the local APIC timer that elapse during `duration_us`
+/// microseconds, using the PIT as a reference.
+///
+/// The timer is left masked and stopped.
+///
+/// # Returns
+///
+/// This function returns `None` if the timer did not count, or if it overflowed during the
+/// measurement.
+///
+/// # Safety
+///
+/// The local APIC must have been initialized, and channel 2 of the PIT must not be used
+/// concurrently. `duration_us` must not be larger than [`pit::MAX_BUSY_WAIT_US`].
+pub unsafe fn calibrate_timer(duration_us: u32) -> Option<u32> {
+    let apic = local_apic();
 
-        // TODO:
-        //  Compute the speed of the current CPU using the TSC or emulated PIC.
+    apic.write(raw::LAPIC_DIVIDE_CONFIG, raw::LAPIC_DIVIDE_BY_16);
+    apic.write(
+        raw::LAPIC_TIMER_INTERRUPT_VECTOR,
+        idt::LAPIC_TIMER_VECTOR as u32 | raw::LAPIC_TIMER_ONE_SHOT | raw::LAPIC_TIMER_MASKED,
+    );
+    apic.write(raw::LAPIC_INITIAL_COUNT, u32::MAX);
 
-        // Configure and enable the timer.
-        ptr::write_volatile(
-            base.byte_add(raw::LAPIC_DIVIDE_CONFIG),
-            raw::LAPIC_DIVIDE_BY_16,
-        );
-        ptr::write_volatile(
-            base.byte_add(raw::LAPIC_TIMER_INTERRUPT_VECTOR),
-            idt::LAPIC_TIMER_VECTOR as u32 | raw::LAPIC_TIMER_PERIODIC,
+    // SAFETY:
+    //  The caller guarantees that channel 2 of the PIT is not used concurrently.
+    unsafe { pit::busy_wait(duration_us) };
+
+    let remaining = apic.read(raw::LAPIC_CURRENT_COUNT);
+    apic.write(raw::LAPIC_INITIAL_COUNT, 0);
+
+    match u32::MAX - remaining {
+        0 | u32::MAX => None,
+        elapsed => Some(elapsed),
+    }
+}
+
+/// Starts the local APIC timer in periodic mode, raising an interrupt every `initial_count`
+/// ticks.
+///
+/// # Safety
+///
+/// The local APIC must have been initialized.
+pub unsafe fn start_timer(initial_count: u32) {
+    unsafe { arm_timer(initial_count, raw::LAPIC_DIVIDE_BY_16, true) };
+}
+
+/// Programs the local APIC timer to raise an interrupt once `initial_count` ticks have elapsed,
+/// with the provided divide configuration.
+///
+/// When `periodic` is set, the count is reloaded every time it reaches zero. Otherwise, the
+/// timer stops after raising the interrupt. Any period that was in progress is abandoned.
+///
+/// # Safety
+///
+/// The local APIC must have been initialized.
+pub unsafe fn arm_timer(initial_count: u32, divide: u32, periodic: bool) {
+    let apic = local_apic();
+
+    let mode = if periodic {
+        raw::LAPIC_TIMER_PERIODIC
+    } else {
+        raw::LAPIC_TIMER_ONE_SHOT
+    };
+
+    apic.write(raw::LAPIC_DIVIDE_CONFIG, divide);
+    apic.write(
+        raw::LAPIC_TIMER_INTERRUPT_VECTOR,
+        idt::LAPIC_TIMER_VECTOR as u32 | mode,
+    );
+    apic.write(raw::LAPIC_INITIAL_COUNT, initial_count);
+}
+
+/// Returns the number of ticks left before the local APIC timer raises its next interrupt.
+///
+/// This is 0 when the timer is stopped.
+#[inline]
+pub fn timer_remaining() -> u32 {
+    local_apic().read(raw::LAPIC_CURRENT_COUNT)
+}
+
+/// The entry point of the timer interrupt, raised either by the local APIC timer or by the PIT.
+///
+/// This function saves the registers of the interrupted context as a [`TrapFrame`] before calling
+/// [`timer_handler`], allowing the scheduler to switch to another process. The `gs` base of the
+/// kernel is loaded when userspace was interrupted.
+#[naked]
+pub extern "C" fn timer() {
+    unsafe {
+        asm!(
+            r#"
+            test byte ptr [rsp + 8], 3
+            jz 2f
+            swapgs
+        2:
+            push r15
+            push r14
+            push r13
+            push r12
+            push r11
+            push r10
+            push r9
+            push r8
+            push rbp
+            push rdi
+            push rsi
+            push rdx
+            push rcx
+            push rbx
+            push rax
+
+            mov rdi, rsp
+            call {timer_handler}
+            jmp {restore_trap_frame}
+            "#,
+            timer_handler = sym timer_handler,
+            restore_trap_frame = sym restore_trap_frame,
+            options(noreturn),
         );
-        ptr::write_volatile(base.byte_add(raw::LAPIC_INITIAL_COUNT), 0x100000);
     }
 }
 
-pub extern "x86-interrupt" fn timer(_: StackFrame) {
+/// Handles the timer interrupt.
+///
+/// When the interrupt occured while userspace was running, the current process is preempted.
+extern "C" fn timer_handler(frame: &mut TrapFrame) {
     send_eoi();
+
+    scheduler::tick(timer::elapsed_ticks());
+
+    if frame.cs & 0b11 == 0b11 {
+        // SAFETY:
+        //  `frame` will be restored when returning from the interrupt, and interrupts are
+        //  disabled within interrupt gates.
+        unsafe { scheduler::schedule(frame) };
+    }
+
+    // The next period is only chosen once the run queue has been updated by the scheduler.
+    timer::rearm();
 }
 
 pub extern "x86-interrupt" fn spurious_interrupt(_: StackFrame) {
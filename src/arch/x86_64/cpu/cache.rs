@@ -0,0 +1,129 @@
+//! Maintenance of the caches of the CPU.
+//!
+//! Caches are coherent with the memory accesses of every CPU, but not necessarily with devices
+//! that do not snoop them, nor with the instruction stream of code that has just been written.
+//! Processes that need to deal with either cannot execute `wbinvd`, which is privileged, and go
+//! through the `cache_control` system call instead.
+//!
+//! Only the bootstrap CPU runs processes (see [`smp`](crate::x86_64::smp)): the operations of
+//! this module only affect the caches of the current CPU and of the levels it shares with the
+//! other CPUs.
+
+use core::arch::asm;
+
+use crate::x86_64::instr;
+
+/// Set in the `edx` register of the `0x1` **CPUID** leaf when the CPU supports `clflush`.
+const CPUID_CLFLUSH: u32 = 1 << 19;
+/// Set in the `ebx` register of the `0x7` **CPUID** leaf when the CPU supports `clflushopt`.
+const CPUID_CLFLUSHOPT: u32 = 1 << 23;
+/// Set in the `ebx` register of the `0x7` **CPUID** leaf when the CPU supports `clwb`.
+const CPUID_CLWB: u32 = 1 << 24;
+
+/// The instruction used to write back the cache lines of a range.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LineOp {
+    /// Writes back and invalidates the line, ordered with respect to every other store.
+    Clflush,
+    /// Writes back and invalidates the line, only ordered by fences.
+    Clflushopt,
+    /// Writes back the line, which may be kept in the cache. Only ordered by fences.
+    Clwb,
+}
+
+/// Returns the instruction used to write back the lines of a range, along with the size of a
+/// cache line.
+///
+/// `keep` indicates whether the lines may be kept in the cache once they have been written back.
+///
+/// # Returns
+///
+/// `None` is returned if the CPU does not support `clflush`.
+fn line_op(keep: bool) -> Option<(LineOp, usize)> {
+    let [_, ebx, _, edx] = instr::cpuid(1, 0);
+    if edx & CPUID_CLFLUSH == 0 {
+        return None;
+    }
+
+    // The size of a line is given in units of 8 bytes.
+    let line_size = ((ebx >> 8) & 0xFF) as usize * 8;
+
+    let extended = match instr::cpuid(0, 0)[0] >= 7 {
+        true => instr::cpuid(7, 0)[1],
+        false => 0,
+    };
+
+    let op = if keep && extended & CPUID_CLWB != 0 {
+        LineOp::Clwb
+    } else if extended & CPUID_CLFLUSHOPT != 0 {
+        LineOp::Clflushopt
+    } else {
+        LineOp::Clflush
+    };
+
+    Some((op, line_size.max(8)))
+}
+
+/// Writes the cache lines that overlap `start..start + length` back to memory.
+///
+/// When `keep` is `false`, the lines are invalidated as well. Otherwise, they may remain in the
+/// cache when the CPU supports doing so.
+///
+/// The function returns once every line has been written back.
+///
+/// # Returns
+///
+/// `false` is returned if the CPU does not support flushing individual cache lines.
+///
+/// # Safety
+///
+/// The range must be mapped in the current address space.
+pub unsafe fn writeback_range(start: usize, length: usize, keep: bool) -> bool {
+    let Some((op, line_size)) = line_op(keep) else {
+        return false;
+    };
+
+    let mut line = start & !(line_size - 1);
+    let end = start + length;
+
+    // SAFETY:
+    //  The caller makes sure that the range is mapped. Flushing a cache line does not modify
+    //  memory.
+    unsafe {
+        while line < end {
+            match op {
+                LineOp::Clflush => asm!("clflush [{}]", in(reg) line, options(nostack)),
+                LineOp::Clflushopt => asm!("clflushopt [{}]", in(reg) line, options(nostack)),
+                LineOp::Clwb => asm!("clwb [{}]", in(reg) line, options(nostack)),
+            }
+
+            line += line_size;
+        }
+
+        // `clflushopt` and `clwb` are only ordered by fences.
+        if op != LineOp::Clflush {
+            asm!("sfence", options(nostack, preserves_flags));
+        }
+    }
+
+    true
+}
+
+/// Writes every modified line of the caches back to memory, and invalidates all of them.
+///
+/// This is slow, and delays interrupts for as long as it takes.
+pub fn writeback_all() {
+    // SAFETY:
+    //  Writing the caches back does not modify the content of memory.
+    unsafe { asm!("wbinvd", options(nostack, preserves_flags)) };
+}
+
+/// Serializes the instruction stream of the current CPU.
+///
+/// Instructions fetched before the call are discarded, and code written to memory before it is
+/// fetched again.
+pub fn serialize() {
+    // `cpuid` is serializing on every x86_64 CPU, unlike `serialize`, which is only available on
+    // recent ones.
+    let _ = instr::cpuid(0, 0);
+}
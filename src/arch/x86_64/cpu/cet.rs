@@ -0,0 +1,227 @@
+//! Support for the control-flow enforcement technology (CET).
+//!
+//! # Supervisor Shadow Stacks
+//!
+//! When the CPU supports them, the kernel runs with a shadow stack: every `call` instruction
+//! pushes the return address to both the regular stack and the shadow stack, which cannot be
+//! written by regular instructions. `ret` instructions compare both return addresses, and raise
+//! a control protection exception if they differ. This defeats return-oriented programming
+//! attacks against the kernel.
+//!
+//! Like the kernel stack, the shadow stack of the kernel is mapped at a fixed virtual address,
+//! surrounded by unmapped guard pages. The double fault handler gets its own shadow stack, which
+//! is referenced by the *Interrupt Shadow Stack Table*.
+//!
+//! Each shadow stack starts with a *supervisor shadow stack token*, which the CPU marks as busy
+//! while the shadow stack is in use:
+//!
+//! - Interrupts raised while userspace is running load the shadow stack pointer from
+//!   **IA32_PL0_SSP**, and **IRETQ** releases the token when returning to userspace.
+//!
+//! - The **SYSCALL** and **SYSRET** instructions do not switch shadow stacks. The system call
+//!   entry point takes the token with **SETSSBSY**, and releases it with **CLRSSBSY** before
+//!   returning with **SYSRETQ**.
+//!
+//! Shadow stacks cannot be enabled in the middle of a call chain, as the functions that are
+//! already running would return to addresses that were never pushed to the shadow stack. They
+//! are prepared during boot by [`init`], and enabled by [`scheduler::start`] right before the
+//! kernel enters userspace for the first time, from a context that never returns.
+//!
+//! Shadow stacks are used when the CPU supports them, unless the `cet=off` option is passed on
+//! the kernel command line.
+//!
+//! # Limitations
+//!
+//! Indirect branch tracking is not enabled, as it requires every indirect branch target of the
+//! kernel, including its assembly stubs, to start with an `endbr64` instruction.
+//!
+//! Userland processes do not get shadow stacks.
+//!
+//! [`scheduler::start`]: crate::x86_64::scheduler::start
+
+use crate::log;
+use crate::utility::Cmdline;
+use crate::x86_64::config::KERNEL_STACK_SIZE;
+use crate::x86_64::cpu::gdt::DOUBLE_FAULT_STACK_INDEX;
+use crate::x86_64::cpu::paging::{self, PageTable};
+use crate::x86_64::instr;
+use crate::x86_64::kernel_stack::KERNEL_STACK_TOP;
+use crate::x86_64::mem::usage::{self, Category};
+use crate::x86_64::mem::{BootAllocator, OutOfMemory, PhysAddr, VirtAddr, PAGE_SIZE};
+use crate::x86_64::raw::{self, Cr0, Cr4, PageFlags};
+
+/// Set in the `ecx` register of the `0x7` **CPUID** leaf when the CPU supports shadow stacks.
+const CPUID_CET_SS: u32 = 1 << 7;
+
+/// The size of the shadow stack of the kernel.
+///
+/// Each call pushes 8 bytes to the shadow stack, and at least 16 bytes to the regular stack (the
+/// return address and the saved frame pointer).
+const KERNEL_SHADOW_STACK_SIZE: usize = (KERNEL_STACK_SIZE / 2 + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+/// The size of the shadow stack of the double fault handler.
+const DOUBLE_FAULT_SHADOW_STACK_SIZE: usize = PAGE_SIZE;
+
+/// The virtual address of the bottom of the shadow stack of the kernel.
+///
+/// The page right below it, between the kernel stack and its shadow stack, is never mapped.
+const KERNEL_SHADOW_STACK_BOTTOM: usize = KERNEL_STACK_TOP + PAGE_SIZE;
+
+/// The virtual address of the supervisor shadow stack token of the kernel shadow stack.
+pub const KERNEL_SHADOW_STACK_TOKEN: usize =
+    KERNEL_SHADOW_STACK_BOTTOM + KERNEL_SHADOW_STACK_SIZE - 8;
+
+/// The virtual address of the bottom of the shadow stack of the double fault handler.
+const DOUBLE_FAULT_SHADOW_STACK_BOTTOM: usize =
+    KERNEL_SHADOW_STACK_BOTTOM + KERNEL_SHADOW_STACK_SIZE + PAGE_SIZE;
+
+/// The virtual address of the supervisor shadow stack token of the double fault shadow stack.
+const DOUBLE_FAULT_SHADOW_STACK_TOKEN: usize =
+    DOUBLE_FAULT_SHADOW_STACK_BOTTOM + DOUBLE_FAULT_SHADOW_STACK_SIZE - 8;
+
+/// Whether supervisor shadow stacks are used.
+///
+/// This is read by the system call entry point, and only modified by [`init`].
+pub static mut SHADOW_STACKS: bool = false;
+
+/// The *Interrupt Shadow Stack Table*.
+///
+/// Entry `n` is the shadow stack used along with the `n`th entry of the *Interrupt Stack Table*.
+/// Entry 0 is unused.
+static mut INTERRUPT_SSP_TABLE: [u64; 8] = [0; 8];
+
+/// Returns whether the CPU supports shadow stacks.
+pub fn supports_shadow_stacks() -> bool {
+    instr::cpuid(0, 0)[0] >= 7 && instr::cpuid(7, 0)[2] & CPUID_CET_SS != 0
+}
+
+/// Returns whether supervisor shadow stacks are used.
+#[inline(always)]
+pub fn shadow_stacks_enabled() -> bool {
+    // SAFETY:
+    //  This is only modified during boot.
+    unsafe { SHADOW_STACKS }
+}
+
+/// Allocates a shadow stack, maps it at `bottom` and writes a supervisor shadow stack token at
+/// its top.
+///
+/// # Safety
+///
+/// `l4_table` must be the physical address of the kernel address space, and `direct_map` must
+/// be the offset of the currently loaded direct map.
+unsafe fn create_shadow_stack(
+    boot_allocator: &mut BootAllocator,
+    l4_table: PhysAddr,
+    direct_map: usize,
+    bottom: usize,
+    size: usize,
+) -> Result<(), OutOfMemory> {
+    let base = boot_allocator.allocate(size, PAGE_SIZE)?;
+    usage::record(Category::Stacks, size);
+
+    unsafe {
+        core::ptr::write_bytes((base.get() + direct_map) as *mut u8, 0x00, size);
+
+        // Shadow stack pages are read-only and dirty. The page tables that lead to them are
+        // shared with the kernel stack, which makes them writable as required by the CPU.
+        paging::create_direct_map(
+            &mut *((l4_table.get() + direct_map) as *mut PageTable),
+            direct_map,
+            &mut || boot_allocator.allocate_page_table(),
+            base,
+            VirtAddr::new(bottom),
+            size,
+            PageFlags::DIRTY | PageFlags::GLOBAL | PageFlags::NO_EXECUTE,
+        )?;
+
+        // A supervisor shadow stack token contains its own address. Its lowest bit is the busy
+        // flag, which is initially clear.
+        let token = bottom + size - 8;
+        core::ptr::write(
+            (base.get() + size - 8 + direct_map) as *mut u64,
+            token as u64,
+        );
+    }
+
+    Ok(())
+}
+
+/// Creates the shadow stacks of the kernel, if the CPU supports them.
+///
+/// Shadow stacks are only enabled later on, by [`scheduler::start`].
+///
+/// # Safety
+///
+/// This function must be called once, after the kernel stack has been mapped.
+///
+/// `l4_table` must be the physical address of the kernel address space, and `direct_map` must
+/// be the offset of the currently loaded direct map.
+///
+/// [`scheduler::start`]: crate::x86_64::scheduler::start
+pub unsafe fn init(
+    boot_allocator: &mut BootAllocator,
+    l4_table: PhysAddr,
+    direct_map: usize,
+    cmdline: Cmdline,
+) -> Result<(), OutOfMemory> {
+    if cmdline.get(b"cet") == Some(b"off") {
+        log::trace!("Shadow stacks are disabled on the command line.");
+        return Ok(());
+    }
+
+    if !supports_shadow_stacks() {
+        log::trace!("The CPU does not support shadow stacks.");
+        return Ok(());
+    }
+
+    unsafe {
+        create_shadow_stack(
+            boot_allocator,
+            l4_table,
+            direct_map,
+            KERNEL_SHADOW_STACK_BOTTOM,
+            KERNEL_SHADOW_STACK_SIZE,
+        )?;
+        create_shadow_stack(
+            boot_allocator,
+            l4_table,
+            direct_map,
+            DOUBLE_FAULT_SHADOW_STACK_BOTTOM,
+            DOUBLE_FAULT_SHADOW_STACK_SIZE,
+        )?;
+
+        INTERRUPT_SSP_TABLE[DOUBLE_FAULT_STACK_INDEX + 1] = DOUBLE_FAULT_SHADOW_STACK_TOKEN as u64;
+        SHADOW_STACKS = true;
+    }
+
+    log::trace!("Supervisor shadow stacks will be enabled.");
+
+    Ok(())
+}
+
+/// Configures the CPU to use the shadow stacks created by [`init`], without enabling them.
+///
+/// This does nothing if shadow stacks are not used.
+///
+/// # Safety
+///
+/// The kernel address space must be loaded.
+pub unsafe fn prepare() {
+    if !shadow_stacks_enabled() {
+        return;
+    }
+
+    // SAFETY:
+    //  The shadow stacks have been mapped by `init`. The kernel never writes to read-only pages,
+    //  which must be guaranteed by the CPU before CET can be enabled.
+    unsafe {
+        instr::set_cr0(instr::cr0() | Cr0::WRITE_PROTECT.bits());
+        instr::set_cr4(instr::cr4() | Cr4::CET.bits());
+        instr::wrmsr(raw::IA32_PL0_SSP, KERNEL_SHADOW_STACK_TOKEN as u64);
+        instr::wrmsr(
+            raw::IA32_INTERRUPT_SSP_TABLE_ADDR,
+            core::ptr::addr_of!(INTERRUPT_SSP_TABLE) as u64,
+        );
+    }
+}
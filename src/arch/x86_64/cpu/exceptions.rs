@@ -1,14 +1,206 @@
 use core::arch::asm;
 
+use fabric_sys::x86_64::exception::{PageFaultError, SelectorError};
+
 use crate::log;
-use crate::x86_64::raw::StackFrame;
+use crate::x86_64::cpu::{paging, registers};
+use crate::x86_64::instr;
+use crate::x86_64::kernel_stack::{KERNEL_STACK_BOTTOM, KERNEL_STACK_GUARD, KERNEL_STACK_TOP};
+use crate::x86_64::percpu;
+use crate::x86_64::process::{self, ExitReason};
+use crate::x86_64::raw::{self, ExceptionFrame, StackFrame};
+use crate::x86_64::scheduler;
+use crate::x86_64::symbols::Symbolized;
+
+/// Defines the naked entry point of an exception that pushes an error code.
+///
+/// The entry point saves the general purpose registers as an [`ExceptionFrame`] and calls
+/// `$handler` with a pointer to it. If the handler returns, the registers are restored and the
+/// faulting instruction is retried.
+///
+/// Exceptions that do not push an error code are marked with `without error code`. Their entry
+/// point pushes a zero in its place, so that every handler receives the same frame.
+///
+/// The entry point is part of the `.entry.text` section, as it loads the user table of the
+/// current process before returning to userspace when page table isolation is enabled. It
+/// exchanges the `gs` base with the one of userspace when entering from and returning to
+/// userspace, so that the handler may access per-CPU data.
+macro_rules! exception_entry {
+    (@entry $(#[$attr:meta])* $name:ident => $handler:ident, $error_code:literal) => {
+        $(#[$attr])*
+        #[naked]
+        #[link_section = ".entry.text"]
+        pub extern "C" fn $name() {
+            unsafe {
+                asm!(
+                    $error_code,
+                    r#"
+                    // The code segment is above the error code and the return address.
+                    test byte ptr [rsp + 16], 3
+                    jz 3f
+                    swapgs
+                3:
+                    push r15
+                    push r14
+                    push r13
+                    push r12
+                    push r11
+                    push r10
+                    push r9
+                    push r8
+                    push rbp
+                    push rdi
+                    push rsi
+                    push rdx
+                    push rcx
+                    push rbx
+                    push rax
+
+                    // The CPU pushed six words, and we pushed fifteen. The stack must be
+                    // re-aligned to 16 bytes before calling the handler.
+                    mov rdi, rsp
+                    sub rsp, 8
+                    call {handler}
+
+                    // The code segment of the interrupted context is above the alignment slot,
+                    // the saved registers, the error code and the return address.
+                    cmp byte ptr [rip + {pti}], 0
+                    je 2f
+                    test byte ptr [rsp + 18 * 8], 3
+                    jz 2f
+                    call {exit_to_user}
+                    mov cr3, rax
+                2:
+                    add rsp, 8
+
+                    pop rax
+                    pop rbx
+                    pop rcx
+                    pop rdx
+                    pop rsi
+                    pop rdi
+                    pop rbp
+                    pop r8
+                    pop r9
+                    pop r10
+                    pop r11
+                    pop r12
+                    pop r13
+                    pop r14
+                    pop r15
 
-pub extern "x86-interrupt" fn division_error(_stack_frame: StackFrame) {
-    panic!("Division Error");
+                    // Skip the error code.
+                    add rsp, 8
+
+                    // The handler may have replaced the interrupted context.
+                    test byte ptr [rsp + 8], 3
+                    jz 4f
+                    swapgs
+                4:
+                    iretq
+                    "#,
+                    handler = sym $handler,
+                    pti = sym $crate::x86_64::cpu::pti::ENABLED,
+                    exit_to_user = sym $crate::x86_64::cpu::pti::exit_to_user,
+                    options(noreturn),
+                );
+            }
+        }
+    };
+    ($(#[$attr:meta])* $name:ident => $handler:ident) => {
+        exception_entry!(@entry $(#[$attr])* $name => $handler, "");
+    };
+    ($(#[$attr:meta])* $name:ident => $handler:ident, without error code) => {
+        exception_entry!(@entry $(#[$attr])* $name => $handler, "push 0");
+    };
 }
 
-pub extern "x86-interrupt" fn debug(_stack_frame: StackFrame) {
-    panic!("Debug Exception");
+/// Writes the registers saved in the provided [`ExceptionFrame`] to the log.
+fn log_registers(frame: &ExceptionFrame) {
+    log::error!("Registers at the time of the exception:");
+    log::error!(
+        "  RAX = {:#018x}  RBX = {:#018x}  RCX = {:#018x}  RDX = {:#018x}",
+        frame.rax,
+        frame.rbx,
+        frame.rcx,
+        frame.rdx,
+    );
+    log::error!(
+        "  RSI = {:#018x}  RDI = {:#018x}  RBP = {:#018x}  RSP = {:#018x}",
+        frame.rsi,
+        frame.rdi,
+        frame.rbp,
+        frame.rsp,
+    );
+    log::error!(
+        "  R8  = {:#018x}  R9  = {:#018x}  R10 = {:#018x}  R11 = {:#018x}",
+        frame.r8,
+        frame.r9,
+        frame.r10,
+        frame.r11,
+    );
+    log::error!(
+        "  R12 = {:#018x}  R13 = {:#018x}  R14 = {:#018x}  R15 = {:#018x}",
+        frame.r12,
+        frame.r13,
+        frame.r14,
+        frame.r15,
+    );
+    log::error!(
+        "  CS  = {:#06x}  SS  = {:#06x}  RFLAGS = {:#010x}  ERROR = {:#x}",
+        frame.cs,
+        frame.ss,
+        frame.rflags,
+        frame.error_code,
+    );
+    log::error!("  RIP = {}", Symbolized(frame.rip as usize));
+    log::error!("Machine state at the time of the report:");
+    registers::print();
+}
+
+/// Terminates the current process if it caused the exception described by `frame`, and panics
+/// otherwise.
+///
+/// This is used for the exceptions that userspace may raise on its own, such as a division by
+/// zero, and that are bugs when they are raised by the kernel.
+fn terminate_or_panic(frame: &mut ExceptionFrame, vector: u8, name: &str) {
+    if is_from_userspace(frame) {
+        log::warn!(
+            "Process {} caused an exception: {} (RIP = {:#x}).",
+            process::current_id(),
+            name,
+            frame.rip,
+        );
+        terminate_faulting_process(frame, vector);
+        return;
+    }
+
+    log_registers(frame);
+    panic!("{} (RIP = {})", name, Symbolized(frame.rip as usize));
+}
+
+exception_entry!(
+    /// The entry point of division errors.
+    division_error => division_error_handler, without error code
+);
+
+/// The vector of division errors.
+const DIVISION_ERROR_VECTOR: u8 = 0;
+
+extern "C" fn division_error_handler(frame: &mut ExceptionFrame) {
+    terminate_or_panic(frame, DIVISION_ERROR_VECTOR, "Division Error");
+}
+
+exception_entry!(
+    /// The entry point of debug exceptions.
+    debug => debug_handler, without error code
+);
+
+/// The vector of debug exceptions.
+const DEBUG_VECTOR: u8 = 1;
+
+extern "C" fn debug_handler(frame: &mut ExceptionFrame) {
+    terminate_or_panic(frame, DEBUG_VECTOR, "Debug Exception");
 }
 
 pub extern "x86-interrupt" fn non_maskable_interrupt(_stack_frame: StackFrame) {
@@ -19,76 +211,271 @@ pub extern "x86-interrupt" fn breakpoint(_stack_frame: StackFrame) {
     log::info!("Breakpoint Exception");
 }
 
-pub extern "x86-interrupt" fn overflow(_stack_frame: StackFrame) {
-    panic!("Overflow");
+exception_entry!(
+    /// The entry point of overflow exceptions.
+    overflow => overflow_handler, without error code
+);
+
+/// The vector of overflow exceptions.
+const OVERFLOW_VECTOR: u8 = 4;
+
+extern "C" fn overflow_handler(frame: &mut ExceptionFrame) {
+    terminate_or_panic(frame, OVERFLOW_VECTOR, "Overflow");
 }
 
-pub extern "x86-interrupt" fn bound_range_exceeded(_stack_frame: StackFrame) {
-    panic!("Bound Range Exceeded");
+exception_entry!(
+    /// The entry point of bound range exceeded exceptions.
+    bound_range_exceeded => bound_range_exceeded_handler, without error code
+);
+
+/// The vector of bound range exceeded exceptions.
+const BOUND_RANGE_EXCEEDED_VECTOR: u8 = 5;
+
+extern "C" fn bound_range_exceeded_handler(frame: &mut ExceptionFrame) {
+    terminate_or_panic(frame, BOUND_RANGE_EXCEEDED_VECTOR, "Bound Range Exceeded");
 }
 
-pub extern "x86-interrupt" fn invalid_opcode(stack_frame: StackFrame) {
-    panic!("Invalid Opcode (RIP = {:#x})", stack_frame.rip);
+exception_entry!(
+    /// The entry point of invalid opcode exceptions.
+    invalid_opcode => invalid_opcode_handler, without error code
+);
+
+/// The vector of invalid opcode exceptions.
+const INVALID_OPCODE_VECTOR: u8 = 6;
+
+extern "C" fn invalid_opcode_handler(frame: &mut ExceptionFrame) {
+    // This notably happens when a process aborts with `ud2`.
+    terminate_or_panic(frame, INVALID_OPCODE_VECTOR, "Invalid Opcode");
 }
 
 pub extern "x86-interrupt" fn device_not_available(_stack_frame: StackFrame) {
     panic!("Device Not Available");
 }
 
-pub extern "x86-interrupt" fn double_fault(_stack_frame: StackFrame, _error_code: u64) -> ! {
-    panic!("Double Fault");
+exception_entry!(
+    /// The entry point of double faults, which run on their own stack.
+    double_fault => double_fault_handler
+);
+
+/// The number of bytes above the bottom of the kernel stack within which the stack pointer is
+/// considered to have overflowed when a double fault occurs.
+///
+/// The faulting instruction may be a `call` or `push` made while the stack pointer is still a few
+/// words above the guard page.
+const STACK_OVERFLOW_SLACK: usize = 256;
+
+/// Returns whether the provided stack pointer indicates that the kernel stack overflowed.
+fn is_kernel_stack_overflow(rsp: usize) -> bool {
+    (KERNEL_STACK_GUARD..KERNEL_STACK_BOTTOM + STACK_OVERFLOW_SLACK).contains(&rsp)
+}
+
+extern "C" fn double_fault_handler(frame: &mut ExceptionFrame) -> ! {
+    log_registers(frame);
+
+    if is_kernel_stack_overflow(frame.rsp as usize) {
+        let context = percpu::current_process();
+
+        log::error!(
+            "The kernel stack ({:#x}..{:#x}) overflowed (RSP = {:#x}).",
+            KERNEL_STACK_BOTTOM,
+            KERNEL_STACK_TOP,
+            frame.rsp,
+        );
+
+        match context {
+            Some(id) => panic!(
+                "kernel stack overflow in a system call or interrupt of process {}, at {}",
+                id,
+                Symbolized(frame.rip as usize),
+            ),
+            None => panic!(
+                "kernel stack overflow outside of any process, at {}",
+                Symbolized(frame.rip as usize),
+            ),
+        }
+    }
+
+    panic!("Double Fault (RIP = {})", Symbolized(frame.rip as usize));
 }
 
-pub extern "x86-interrupt" fn invalid_tss(_stack_frame: StackFrame, _error_code: u64) {
-    panic!("Invalid TSS");
+pub extern "x86-interrupt" fn invalid_tss(_stack_frame: StackFrame, error_code: u64) {
+    panic!("Invalid TSS ({})", SelectorError(error_code));
 }
 
-pub extern "x86-interrupt" fn segment_not_present(_stack_frame: StackFrame, _error_code: u64) {
-    panic!("Segment Not Present");
+pub extern "x86-interrupt" fn segment_not_present(_stack_frame: StackFrame, error_code: u64) {
+    panic!("Segment Not Present ({})", SelectorError(error_code));
 }
 
-pub extern "x86-interrupt" fn stack_segment_fault(_stack_frame: StackFrame, _error_code: u64) {
-    panic!("Stack Segment Fault");
+pub extern "x86-interrupt" fn stack_segment_fault(_stack_frame: StackFrame, error_code: u64) {
+    panic!("Stack Segment Fault ({})", SelectorError(error_code));
 }
 
-pub extern "x86-interrupt" fn general_protection_fault(_stack_frame: StackFrame, _error_code: u64) {
-    panic!("General Protection Fault");
+exception_entry!(
+    /// The entry point of general protection faults.
+    general_protection_fault => general_protection_fault_handler
+);
+
+/// The vector of general protection faults.
+const GENERAL_PROTECTION_FAULT_VECTOR: u8 = 13;
+
+/// Returns whether the exception described by `frame` was caused by userspace.
+#[inline(always)]
+fn is_from_userspace(frame: &ExceptionFrame) -> bool {
+    frame.cs & 0b11 == 0b11
 }
 
-pub extern "x86-interrupt" fn page_fault(stack_frame: StackFrame, error_code: u64) {
-    let addr: usize;
-    unsafe {
-        asm!("mov {}, cr2", out(reg) addr, options(nostack, nomem, preserves_flags));
+/// Terminates the current process, which caused the exception described by `frame`, and replaces
+/// the frame with the context of the next process to run.
+fn terminate_faulting_process(frame: &mut ExceptionFrame, vector: u8) {
+    process::terminate(process::current_id(), ExitReason::Exception { vector });
+
+    let mut trap_frame = frame.trap_frame();
+
+    // SAFETY:
+    //  `frame` is restored when returning from the exception, and interrupts are disabled
+    //  within interrupt gates.
+    unsafe { scheduler::schedule(&mut trap_frame) };
+
+    frame.set_trap_frame(&trap_frame);
+}
+
+extern "C" fn general_protection_fault_handler(frame: &mut ExceptionFrame) {
+    // This notably happens when a process executes a privileged instruction, including the ones
+    // restricted by UMIP.
+    if is_from_userspace(frame) {
+        log::warn!(
+            "Process {} caused a general protection fault (RIP = {:#x}, {}).",
+            process::current_id(),
+            frame.rip,
+            SelectorError(frame.error_code),
+        );
+        terminate_faulting_process(frame, GENERAL_PROTECTION_FAULT_VECTOR);
+        return;
     }
 
+    log_registers(frame);
     panic!(
-        "Page Fault (RIP = {:#x}, RSP = {:#x}, addr = {:#x}, error = {:#b})",
-        stack_frame.rip, stack_frame.rsp, addr, error_code,
+        "General Protection Fault (RIP = {}, {})",
+        Symbolized(frame.rip as usize),
+        SelectorError(frame.error_code),
     );
 }
 
-pub extern "x86-interrupt" fn x87_floating_point(_stack_frame: StackFrame) {
-    panic!("x87 Floating Point");
+/// The vector of page faults.
+const PAGE_FAULT_VECTOR: u8 = 14;
+
+exception_entry!(
+    /// The entry point of page faults.
+    page_fault => page_fault_handler
+);
+
+extern "C" fn page_fault_handler(frame: &mut ExceptionFrame) {
+    let error = PageFaultError::from_bits_retain(frame.error_code);
+    let addr = instr::cr2() as usize;
+
+    // Faults caused by accessing a page of the lower half that is not mapped may be resolved by
+    // growing the stack of the current process, or by mapping memory whose pages have been
+    // purged or are allocated on demand. System calls populate the buffers they are given
+    // beforehand (see `syscall::audit`), so this is mostly reached from userspace.
+    if error.is_not_present() && addr < paging::user_top() {
+        // SAFETY:
+        //  System calls never modify the current process while they access user memory.
+        let process = percpu::current_process().and_then(|id| unsafe { process::get(id) });
+        if process.is_some_and(|p| unsafe { p.populate(addr) }) {
+            return;
+        }
+    }
+
+    // A wild pointer of userspace only takes its own process down. Faults raised by the kernel,
+    // even while accessing user memory on behalf of a system call, are bugs: system calls
+    // validate the memory they are given.
+    if is_from_userspace(frame) {
+        log::warn!(
+            "Process {} caused a page fault at {:#x} ({}, RIP = {:#x}).",
+            process::current_id(),
+            addr,
+            error,
+            frame.rip,
+        );
+        terminate_faulting_process(frame, PAGE_FAULT_VECTOR);
+        return;
+    }
+
+    log_registers(frame);
+    panic!(
+        "Page Fault (RIP = {}, RSP = {:#x}, addr = {:#x}, {})",
+        Symbolized(frame.rip as usize),
+        frame.rsp,
+        addr,
+        error,
+    );
+}
+
+exception_entry!(
+    /// The entry point of x87 floating point exceptions.
+    x87_floating_point => x87_floating_point_handler, without error code
+);
+
+/// The vector of x87 floating point exceptions.
+const X87_FLOATING_POINT_VECTOR: u8 = 16;
+
+extern "C" fn x87_floating_point_handler(frame: &mut ExceptionFrame) {
+    terminate_or_panic(frame, X87_FLOATING_POINT_VECTOR, "x87 Floating Point");
 }
 
-pub extern "x86-interrupt" fn alignment_check(_stack_frame: StackFrame, _error_code: u64) {
-    panic!("Alignment Check");
+exception_entry!(
+    /// The entry point of alignment check exceptions, which are only raised by userspace.
+    alignment_check => alignment_check_handler
+);
+
+/// The vector of alignment check exceptions.
+const ALIGNMENT_CHECK_VECTOR: u8 = 17;
+
+extern "C" fn alignment_check_handler(frame: &mut ExceptionFrame) {
+    terminate_or_panic(frame, ALIGNMENT_CHECK_VECTOR, "Alignment Check");
 }
 
 pub extern "x86-interrupt" fn machine_check(_stack_frame: StackFrame) -> ! {
+    // SAFETY:
+    //  Machine-check exceptions are only raised by CPUs that implement the machine-check
+    //  architecture, which includes this register.
+    let status = unsafe { instr::rdmsr(raw::IA32_MCG_STATUS) };
+    log::error!("IA32_MCG_STATUS = {:#x}", status);
+
     panic!("Machine Check");
 }
 
-pub extern "x86-interrupt" fn simd_floating_point(_stack_frame: StackFrame) {
-    panic!("SIMD Floating Point");
+exception_entry!(
+    /// The entry point of SIMD floating point exceptions.
+    simd_floating_point => simd_floating_point_handler, without error code
+);
+
+/// The vector of SIMD floating point exceptions.
+const SIMD_FLOATING_POINT_VECTOR: u8 = 19;
+
+extern "C" fn simd_floating_point_handler(frame: &mut ExceptionFrame) {
+    terminate_or_panic(frame, SIMD_FLOATING_POINT_VECTOR, "SIMD Floating Point");
 }
 
 pub extern "x86-interrupt" fn virtualization(_stack_frame: StackFrame) {
     panic!("Virtualization");
 }
 
-pub extern "x86-interrupt" fn control_protection(_stack_frame: StackFrame, _error_code: u64) {
-    panic!("Control Protection");
+pub extern "x86-interrupt" fn control_protection(stack_frame: StackFrame, error_code: u64) {
+    let cause = match error_code & 0x7FFF {
+        1 => "mismatched return address",
+        2 => "mismatched far return or interrupt return",
+        3 => "missing end-branch instruction",
+        4 => "invalid shadow stack restore token",
+        5 => "invalid supervisor shadow stack token",
+        _ => "unknown cause",
+    };
+
+    panic!(
+        "Control Protection ({}) at {}",
+        cause,
+        Symbolized(stack_frame.rip as usize),
+    );
 }
 
 pub extern "x86-interrupt" fn hypervisor_injection(_stack_frame: StackFrame) {
@@ -1,10 +1,17 @@
 use core::mem::size_of;
+use core::ops::Range;
 use core::ptr::addr_of;
 
 use crate::log;
-use crate::x86_64::mem::{BootAllocator, OutOfMemory, HHDM_OFFSET, PAGE_SIZE};
+use crate::utility::{InitGuard, PerCpuInitGuard};
+use crate::x86_64::config::DOUBLE_FAULT_STACK_SIZE;
+use crate::x86_64::cpu::paging::UpperHalfAddressSpaceTok;
+use crate::x86_64::mem::usage::{self, Category};
+use crate::x86_64::mem::{
+    vmalloc, BootAllocator, Frame, MemoryTracker, OutOfMemory, PhysAddr, PAGE_SIZE,
+};
 use crate::x86_64::raw;
-use crate::x86_64::raw::SegmentFlags;
+use crate::x86_64::raw::{PageFlags, SegmentFlags};
 
 pub const KERNEL_CODE_SELECTOR: u16 = 8;
 pub const KERNEL_DATA_SELECTOR: u16 = 8 * 2;
@@ -12,22 +19,13 @@ pub const USER_DATA_SELECTOR: u16 = (8 * 3) | 0b11;
 pub const USER_CODE_SELECTOR: u16 = (8 * 4) | 0b11;
 pub const TSS_SELECTOR: u16 = 8 * 5;
 
-/// The amount of the stack memory reserved for the double fault handler.
-///
-/// A separate stack is used for this CPU exception because it might be trigged by a stack overflow
-/// within the kernel stack. In that case, the current kernel stack is unusable, and the double
-/// fault will turn in a triple fault, causing the machine to reboot. Unlikely, but highly
-/// undesirable.
-const DOUBLE_FAULT_STACK_SIZE: usize = PAGE_SIZE * 4;
-
 /// The index of the double fault stack in the *Interrupt Stack Table* of the TSS.
 pub const DOUBLE_FAULT_STACK_INDEX: usize = 0;
 
-/// The global descriptor table of the kernel that will be inserted into the bootstrap CPU.
+/// The descriptors shared by the global descriptor tables of all CPUs.
 ///
-/// This global is initialized by the [`init`] function, and must not be accessed before
-/// initialization.
-static mut GDT: [u64; 7] = [
+/// The task state segment descriptor (the last two entries) is filled in for each CPU.
+const GDT_TEMPLATE: [u64; 7] = [
     // Null Descriptor
     0,
     // Kernel Code Segment
@@ -75,13 +73,8 @@ static mut GDT: [u64; 7] = [
     0,
 ];
 
-static mut GDT_DESC: raw::TableDesc = raw::TableDesc {
-    base: unsafe { addr_of!(GDT) as *const () },
-    limit: size_of::<[u64; 7]>() as u16 - 1,
-};
-
-/// The task state segment that will be inserted into the GDT of the bootstrap CPU.
-static mut TSS: raw::TaskStateSegment = raw::TaskStateSegment {
+/// An empty task state segment.
+const TSS_TEMPLATE: raw::TaskStateSegment = raw::TaskStateSegment {
     reserved0: 0,
     reserved1: 0,
     reserved2: 0,
@@ -91,18 +84,90 @@ static mut TSS: raw::TaskStateSegment = raw::TaskStateSegment {
     iomap_base: 0,
 };
 
+/// The global descriptor table of the kernel that will be inserted into the bootstrap CPU.
+///
+/// This global is initialized by the [`init`] function, and must not be accessed before
+/// initialization.
+#[link_section = ".entry.data"]
+static mut GDT: [u64; 7] = GDT_TEMPLATE;
+
+static mut GDT_DESC: raw::TableDesc = raw::TableDesc {
+    base: unsafe { addr_of!(GDT) as *const () },
+    limit: size_of::<[u64; 7]>() as u16 - 1,
+};
+
+/// The task state segment that will be inserted into the GDT of the bootstrap CPU.
+#[link_section = ".entry.data"]
+static mut TSS: raw::TaskStateSegment = TSS_TEMPLATE;
+
+/// Returns the range of virtual addresses of the stack of the double fault handler of the
+/// bootstrap CPU.
+///
+/// The range is empty before [`init`] has been called.
+pub fn double_fault_stack() -> Range<usize> {
+    // SAFETY:
+    //  The TSS is only modified during boot.
+    let top = unsafe { TSS.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX] } as usize;
+
+    top.saturating_sub(DOUBLE_FAULT_STACK_SIZE)..top
+}
+
+/// The global descriptor table and task state segment of an application processor.
+pub struct CpuTables {
+    gdt: [u64; 7],
+    tss: raw::TaskStateSegment,
+}
+
+impl CpuTables {
+    /// The tables of a CPU that has not been initialized yet.
+    pub const EMPTY: Self = Self {
+        gdt: GDT_TEMPLATE,
+        tss: TSS_TEMPLATE,
+    };
+}
+
+/// Writes the descriptor of the task state segment at `tss_base` into `gdt`.
+fn set_tss_descriptor(gdt: &mut [u64; 7], tss_base: u64) {
+    gdt[5] |= (size_of::<raw::TaskStateSegment>() as u64 - 1) & 0xFFFF; // this is always 0x67
+    gdt[5] |= ((tss_base & 0xFFFFFF) << 16) | ((tss_base & 0xFF000000) << 32);
+    gdt[5] |= (SegmentFlags::PRESENT | SegmentFlags::AVAILABLE_TSS).bits();
+    gdt[6] |= tss_base >> 32;
+}
+
 /// Initializes a Global Descriptor Table for the kernel.
 ///
 /// # Safety
 ///
 /// This function must only be called once.
 ///
-/// The kernel stack must've been initialized before calling this function.
+/// The kernel stack must've been initialized before calling this function, and the kernel must
+/// run in its own address space.
 #[inline] // only called once
-pub unsafe fn init(boot_allocator: &mut BootAllocator) -> Result<(), OutOfMemory> {
-    let double_fault_stack = boot_allocator.allocate(DOUBLE_FAULT_STACK_SIZE, 1)?
-        + HHDM_OFFSET
-        + DOUBLE_FAULT_STACK_SIZE;
+pub unsafe fn init(
+    boot_allocator: &mut BootAllocator,
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<(), OutOfMemory> {
+    static GUARD: InitGuard = InitGuard::new();
+    if !GUARD.enter("gdt::init") {
+        return Ok(());
+    }
+
+    // The double fault stack is mapped in the vmalloc region, below a guard page.
+    let mut frames =
+        [Frame::containing(PhysAddr::ZERO); DOUBLE_FAULT_STACK_SIZE.div_ceil(PAGE_SIZE)];
+    for frame in &mut frames {
+        *frame = Frame::containing(boot_allocator.allocate(PAGE_SIZE, PAGE_SIZE)?);
+    }
+    usage::record(Category::Stacks, frames.len() * PAGE_SIZE);
+
+    let double_fault_stack = unsafe {
+        vmalloc::map_frames(
+            upper_half,
+            &mut || boot_allocator.allocate_page_table(),
+            &frames,
+            PageFlags::WRITABLE | PageFlags::GLOBAL | PageFlags::NO_EXECUTE,
+        )?
+    } + DOUBLE_FAULT_STACK_SIZE;
 
     // SAFETY:
     //  Because this function can only be called once, we can safely assume that the GDT is not
@@ -111,24 +176,90 @@ pub unsafe fn init(boot_allocator: &mut BootAllocator) -> Result<(), OutOfMemory
     //  This function won't be called at a point where multiple CPUs are running concurrently
     //  anyway, as the kernel is not yet fully initialized at that point.
     unsafe {
-        TSS.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX] = double_fault_stack as u64;
-        TSS.privilege_stack_table[0] =
-            (crate::x86_64::kernel_stack::KERNEL_STACK_TOP + HHDM_OFFSET) as u64;
+        TSS.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX] = double_fault_stack.get() as u64;
+        TSS.privilege_stack_table[0] = crate::x86_64::kernel_stack::KERNEL_STACK_TOP as u64;
 
-        let tss_base = addr_of!(TSS) as u64;
-
-        GDT[5] |= (size_of::<raw::TaskStateSegment>() as u64 - 1) & 0xFFFF; // this is always 0x67
-        GDT[5] |= ((tss_base & 0xFFFFFF) << 16) | ((tss_base & 0xFF000000) << 32);
-        GDT[5] |= (SegmentFlags::PRESENT | SegmentFlags::AVAILABLE_TSS).bits();
-        GDT[6] |= tss_base >> 32;
+        set_tss_descriptor(&mut GDT, addr_of!(TSS) as u64);
     }
 
     log::trace!("Switching GDT...");
 
+    unsafe { load(&GDT_DESC) };
+
+    Ok(())
+}
+
+/// Initializes the tables of an application processor and loads them on the current CPU.
+///
+/// `kernel_stack_top` is the stack used when the CPU enters the kernel from userspace, and
+/// `double_fault_stack_top` the one used by the double fault handler. See
+/// [`allocate_ap_double_fault_stack`].
+///
+/// # Safety
+///
+/// This function must be called once per application processor, on that processor, and the
+/// kernel must run in its own address space.
+pub unsafe fn init_ap(
+    tables: &'static mut CpuTables,
+    kernel_stack_top: usize,
+    double_fault_stack_top: usize,
+) {
+    static GUARD: PerCpuInitGuard = PerCpuInitGuard::new();
+    if !GUARD.enter(super::current_id() as u8, "gdt::init_ap") {
+        return;
+    }
+
+    tables.tss.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX] = double_fault_stack_top as u64;
+    tables.tss.privilege_stack_table[0] = kernel_stack_top as u64;
+
+    let tss_base = addr_of!(tables.tss) as u64;
+    set_tss_descriptor(&mut tables.gdt, tss_base);
+
+    let desc = raw::TableDesc {
+        base: tables.gdt.as_ptr() as *const (),
+        limit: size_of::<[u64; 7]>() as u16 - 1,
+    };
+
+    unsafe { load(&desc) };
+}
+
+/// Allocates the double fault stack of an application processor.
+///
+/// # Returns
+///
+/// The virtual address of the top of the stack.
+///
+/// # Safety
+///
+/// The kernel must run in its own address space.
+pub unsafe fn allocate_ap_double_fault_stack(
+    upper_half: UpperHalfAddressSpaceTok,
+    memory_tracker: &mut MemoryTracker,
+) -> Result<usize, OutOfMemory> {
+    let bottom = unsafe {
+        vmalloc::allocate(
+            upper_half,
+            memory_tracker,
+            DOUBLE_FAULT_STACK_SIZE,
+            PageFlags::WRITABLE | PageFlags::GLOBAL | PageFlags::NO_EXECUTE,
+        )?
+    };
+    usage::record(Category::Stacks, DOUBLE_FAULT_STACK_SIZE);
+
+    Ok(bottom.get() + DOUBLE_FAULT_STACK_SIZE)
+}
+
+/// Loads the provided global descriptor table and its task state segment on the current CPU,
+/// and reloads the segment registers.
+///
+/// # Safety
+///
+/// The table must remain valid for as long as it is loaded.
+unsafe fn load(desc: &raw::TableDesc) {
     unsafe {
         core::arch::asm!(
             "lgdt [{}]",
-            in(reg) &GDT_DESC,
+            in(reg) desc,
             options(nostack, readonly, preserves_flags)
         );
 
@@ -166,6 +297,4 @@ pub unsafe fn init(boot_allocator: &mut BootAllocator) -> Result<(), OutOfMemory
             options(preserves_flags, nomem, nostack)
         );
     }
-
-    Ok(())
 }
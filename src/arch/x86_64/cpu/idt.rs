@@ -3,16 +3,41 @@ use core::ptr::addr_of;
 
 use super::gdt;
 use crate::log;
-use crate::x86_64::cpu::apic;
+use crate::utility::InitGuard;
+use crate::x86_64::acpi;
 use crate::x86_64::cpu::gdt::DOUBLE_FAULT_STACK_INDEX;
+use crate::x86_64::cpu::{apic, pic};
+use crate::x86_64::irq;
+use crate::x86_64::keyboard;
 use crate::x86_64::raw;
 use crate::x86_64::raw::GateFlags;
+use crate::x86_64::tlb;
+
+// Legacy PIC interrupt vector offsets.
+
+pub const PIC_VECTOR_BASE: usize = 0x20;
+pub const PIC_MASTER_SPURIOUS_VECTOR: usize = PIC_VECTOR_BASE + 7;
+pub const PIC_SLAVE_SPURIOUS_VECTOR: usize = PIC_VECTOR_BASE + 15;
 
 // LAPIC interrupt vector offsets.
 
 pub const LAPIC_SPURIOUS_VECTOR: usize = 0x64;
 pub const LAPIC_TIMER_VECTOR: usize = 0x65;
 
+// Inter-processor interrupt vector offsets.
+
+pub const TLB_SHOOTDOWN_VECTOR: usize = 0x68;
+
+// I/O APIC interrupt vector offsets.
+
+pub const ACPI_SCI_VECTOR: usize = 0x66;
+pub const PIT_TIMER_VECTOR: usize = 0x67;
+pub const KEYBOARD_VECTOR: usize = 0x69;
+
+// Interrupt vector offsets of the lines bound by userspace drivers.
+
+pub const USER_IRQ_VECTOR_BASE: usize = 0x70;
+
 // CPU exception offsets in the IDT.
 
 pub const DIVISION_ERROR: usize = 0;
@@ -39,6 +64,7 @@ pub const HYPERVISOR_INJECTION: usize = 28;
 pub const VMM_COMMUNICATION: usize = 29;
 pub const SECURITY: usize = 30;
 
+#[link_section = ".entry.data"]
 static mut IDT: [[u64; 2]; 256] = [[0, 0]; 256];
 
 static IDT_DESC: raw::TableDesc = raw::TableDesc {
@@ -86,6 +112,20 @@ fn interrupt_gate(offset: u64) -> [u64; 2] {
     create_gate(true, offset, 0)
 }
 
+/// Replaces the handler of the provided vector, turning its gate into an interrupt gate.
+///
+/// The interrupt stack of the gate is preserved.
+///
+/// # Safety
+///
+/// This function must be called during boot, after [`init`].
+pub unsafe fn redirect(vector: usize, handler: usize) {
+    unsafe {
+        let ist = ((IDT[vector][0] >> 32) & 0b111) as usize;
+        IDT[vector] = create_gate(true, handler as u64, ist);
+    }
+}
+
 /// Initializes an Interrupt Descriptor Table for the kernel.
 ///
 /// # Safety
@@ -93,40 +133,69 @@ fn interrupt_gate(offset: u64) -> [u64; 2] {
 /// This function must only be called once.
 #[inline] // only called once
 pub unsafe fn init() {
+    static GUARD: InitGuard = InitGuard::new();
+    if !GUARD.enter("idt::init") {
+        return;
+    }
+
     #[allow(clippy::fn_to_numeric_cast)]
     unsafe {
         use super::exceptions::*;
 
-        IDT[DIVISION_ERROR] = trap_gate(division_error as u64);
-        IDT[DEBUG] = trap_gate(debug as u64);
+        // The handlers that use interrupt gates may terminate the faulting process and switch to
+        // another one, which must not be interrupted.
+        IDT[DIVISION_ERROR] = interrupt_gate(division_error as u64);
+        IDT[DEBUG] = interrupt_gate(debug as u64);
         IDT[NON_MASKABLE_INTERRUPT] = trap_gate(non_maskable_interrupt as u64);
         IDT[BREAKPOINT] = trap_gate(breakpoint as u64);
-        IDT[OVERFLOW] = trap_gate(overflow as u64);
-        IDT[BOUND_RANGE_EXCEEDED] = trap_gate(bound_range_exceeded as u64);
-        IDT[INVALID_OPCODE] = trap_gate(invalid_opcode as u64);
+        IDT[OVERFLOW] = interrupt_gate(overflow as u64);
+        IDT[BOUND_RANGE_EXCEEDED] = interrupt_gate(bound_range_exceeded as u64);
+        IDT[INVALID_OPCODE] = interrupt_gate(invalid_opcode as u64);
         IDT[DEVICE_NOT_AVAILABLE] = trap_gate(device_not_available as u64);
         IDT[DOUBLE_FAULT] = create_gate(false, double_fault as u64, DOUBLE_FAULT_STACK_INDEX + 1);
         IDT[INVALID_TSS] = trap_gate(invalid_tss as u64);
         IDT[SEGMENT_NOT_PRESENT] = trap_gate(segment_not_present as u64);
         IDT[STACK_SEGMENT_FAULT] = trap_gate(stack_segment_fault as u64);
-        IDT[GENERAL_PROTECTION_FAULT] = trap_gate(general_protection_fault as u64);
-        IDT[PAGE_FAULT] = trap_gate(page_fault as u64);
-        IDT[X87_FLOATING_POINT] = trap_gate(x87_floating_point as u64);
-        IDT[ALIGNMENT_CHECK] = trap_gate(alignment_check as u64);
+        IDT[GENERAL_PROTECTION_FAULT] = interrupt_gate(general_protection_fault as u64);
+        IDT[PAGE_FAULT] = interrupt_gate(page_fault as u64);
+        IDT[X87_FLOATING_POINT] = interrupt_gate(x87_floating_point as u64);
+        IDT[ALIGNMENT_CHECK] = interrupt_gate(alignment_check as u64);
         IDT[MACHINE_CHECK] = trap_gate(machine_check as u64);
-        IDT[SIMD_FLOATING_POINT] = trap_gate(simd_floating_point as u64);
+        IDT[SIMD_FLOATING_POINT] = interrupt_gate(simd_floating_point as u64);
         IDT[VIRTUALIZATION] = trap_gate(virtualization as u64);
         IDT[CONTROL_PROTECTION] = trap_gate(control_protection as u64);
         IDT[HYPERVISOR_INJECTION] = trap_gate(hypervisor_injection as u64);
         IDT[VMM_COMMUNICATION] = trap_gate(vmm_communication as u64);
         IDT[SECURITY] = trap_gate(security as u64);
 
+        IDT[PIC_MASTER_SPURIOUS_VECTOR] = interrupt_gate(pic::spurious_master as u64);
+        IDT[PIC_SLAVE_SPURIOUS_VECTOR] = interrupt_gate(pic::spurious_slave as u64);
+
         IDT[LAPIC_SPURIOUS_VECTOR] = interrupt_gate(apic::spurious_interrupt as u64);
         IDT[LAPIC_TIMER_VECTOR] = interrupt_gate(apic::timer as u64);
+
+        IDT[ACPI_SCI_VECTOR] = interrupt_gate(acpi::sci_interrupt as u64);
+        IDT[PIT_TIMER_VECTOR] = interrupt_gate(apic::timer as u64);
+        IDT[KEYBOARD_VECTOR] = interrupt_gate(keyboard::interrupt as u64);
+
+        IDT[TLB_SHOOTDOWN_VECTOR] = interrupt_gate(tlb::shootdown_interrupt as u64);
+
+        for (index, handler) in irq::HANDLERS.into_iter().enumerate() {
+            IDT[USER_IRQ_VECTOR_BASE + index] = interrupt_gate(handler as u64);
+        }
     }
 
     log::trace!("Switching IDT...");
 
+    unsafe { load() };
+}
+
+/// Loads the Interrupt Descriptor Table of the kernel on the current CPU.
+///
+/// # Safety
+///
+/// The IDT must have been initialized with [`init`].
+pub unsafe fn load() {
     unsafe {
         core::arch::asm!(
             r#"
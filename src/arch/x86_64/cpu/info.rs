@@ -0,0 +1,209 @@
+//! Identification of the CPU.
+//!
+//! During boot, [`init`] reads the vendor, family, model and stepping of the CPU along with the
+//! revision of its microcode, and looks for properties of the CPU that are known to cause
+//! problems. Everything is written to the log, and made available to userspace through the
+//! `query_kernel_stats` system call, so that bug reports include actionable information about
+//! the hardware.
+//!
+//! The features of the CPU that processes may rely on are reported by [`features`] and
+//! [`page_sizes`], through the `kernel_info` system call.
+
+use fabric_sys::x86_64::{CpuInfo, CpuQuirks, HardwareFeatures, PageSizes};
+
+use crate::log;
+use crate::x86_64::raw;
+use crate::x86_64::{instr, timer};
+
+/// Set in the `ecx` register of the `0x1` **CPUID** leaf when the local APIC timer supports the
+/// TSC-deadline mode.
+const CPUID_TSC_DEADLINE: u32 = 1 << 24;
+/// Set in the `ecx` register of the `0x1` **CPUID** leaf when the kernel runs under a hypervisor.
+const CPUID_HYPERVISOR: u32 = 1 << 31;
+/// Set in the `ecx` register of the `0x1` **CPUID** leaf when the local APIC supports the x2APIC
+/// mode.
+const CPUID_X2APIC: u32 = 1 << 21;
+/// Set in the `edx` register of the `0x80000001` **CPUID** leaf when 1 GiB pages are supported.
+const CPUID_PAGE_1GIB: u32 = 1 << 26;
+
+/// The Intel family 6 models whose TSC-deadline mode is unreliable, along with the first
+/// microcode revision that fixes it.
+///
+/// This list is the one used by Linux.
+const TSC_DEADLINE_MICROCODE: [(u32, u32); 9] = [
+    (0x3C, 0x22), // Haswell
+    (0x45, 0x20), // Haswell (ULT)
+    (0x46, 0x17), // Haswell (GT3e)
+    (0x3D, 0x25), // Broadwell
+    (0x47, 0x17), // Broadwell (GT3e)
+    (0x4E, 0xB2), // Skylake (mobile)
+    (0x5E, 0xB2), // Skylake (desktop)
+    (0x8E, 0x52), // Kaby Lake (mobile)
+    (0x9E, 0x52), // Kaby Lake (desktop)
+];
+
+/// The information gathered by [`init`].
+static mut INFO: CpuInfo = CpuInfo {
+    vendor: [0; 12],
+    family: 0,
+    model: 0,
+    stepping: 0,
+    microcode_revision: 0,
+    quirks: CpuQuirks::empty(),
+};
+
+/// Returns whether the kernel runs under a hypervisor.
+pub fn hypervisor_present() -> bool {
+    instr::cpuid(1, 0)[2] & CPUID_HYPERVISOR != 0
+}
+
+/// Returns the vendor string of the CPU.
+pub fn vendor() -> [u8; 12] {
+    let [_, ebx, ecx, edx] = instr::cpuid(0, 0);
+
+    let mut vendor = [0; 12];
+    vendor[0..4].copy_from_slice(&ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&ecx.to_le_bytes());
+    vendor
+}
+
+/// Reads the revision of the microcode loaded on the CPU.
+///
+/// # Returns
+///
+/// 0 is returned when the vendor of the CPU is not known.
+///
+/// # Safety
+///
+/// The kernel must be running during boot, as the state of the **IA32_BIOS_SIGN_ID** register is
+/// modified.
+unsafe fn microcode_revision(vendor: &[u8; 12]) -> u32 {
+    match vendor {
+        // SAFETY:
+        //  Intel CPUs support the **IA32_BIOS_SIGN_ID** register, which must be cleared before
+        //  the `cpuid` instruction writes the revision to it.
+        b"GenuineIntel" => unsafe {
+            instr::wrmsr(raw::IA32_BIOS_SIGN_ID, 0);
+            instr::cpuid(1, 0);
+            (instr::rdmsr(raw::IA32_BIOS_SIGN_ID) >> 32) as u32
+        },
+        // SAFETY:
+        //  AMD CPUs support the **IA32_BIOS_SIGN_ID** register.
+        b"AuthenticAMD" | b"HygonGenuine" => unsafe { instr::rdmsr(raw::IA32_BIOS_SIGN_ID) as u32 },
+        _ => 0,
+    }
+}
+
+/// Identifies the CPU and writes the result to the log.
+///
+/// # Safety
+///
+/// This function must be called once, during boot.
+pub unsafe fn init() {
+    let vendor = vendor();
+    let [eax, _, ecx, _] = instr::cpuid(1, 0);
+
+    let base_family = (eax >> 8) & 0xF;
+    let family = match base_family {
+        0xF => base_family + ((eax >> 20) & 0xFF),
+        _ => base_family,
+    };
+    let model = match base_family {
+        0x6 | 0xF => ((eax >> 4) & 0xF) | ((eax >> 12) & 0xF0),
+        _ => (eax >> 4) & 0xF,
+    };
+    let stepping = eax & 0xF;
+    let microcode_revision = unsafe { microcode_revision(&vendor) };
+
+    let mut quirks = CpuQuirks::empty();
+    if ecx & CPUID_HYPERVISOR != 0 {
+        quirks |= CpuQuirks::HYPERVISOR;
+    }
+    if !timer::tsc_is_invariant() {
+        quirks |= CpuQuirks::TSC_NOT_INVARIANT;
+    }
+    if &vendor == b"GenuineIntel"
+        && family == 6
+        && ecx & CPUID_TSC_DEADLINE != 0
+        && TSC_DEADLINE_MICROCODE
+            .iter()
+            .any(|&(m, fixed_in)| m == model && microcode_revision < fixed_in)
+    {
+        quirks |= CpuQuirks::TSC_DEADLINE_ERRATUM;
+    }
+
+    log::trace!(
+        "CPU: {}, family {:#x}, model {:#x}, stepping {}, microcode revision {:#x}.",
+        core::str::from_utf8(&vendor).unwrap_or("<invalid UTF-8>"),
+        family,
+        model,
+        stepping,
+        microcode_revision,
+    );
+
+    if quirks.contains(CpuQuirks::HYPERVISOR) {
+        log::trace!("The kernel is running under a hypervisor.");
+    }
+    if quirks.contains(CpuQuirks::TSC_DEADLINE_ERRATUM) {
+        log::warn!(
+            "The TSC-deadline timer of this CPU is unreliable with microcode revision {:#x}.",
+            microcode_revision,
+        );
+    }
+
+    // SAFETY:
+    //  This function is only called once, during boot.
+    unsafe {
+        INFO = CpuInfo {
+            vendor,
+            family,
+            model,
+            stepping,
+            microcode_revision,
+            quirks,
+        };
+    }
+}
+
+/// Returns the hardware features that processes may rely on.
+pub fn features() -> HardwareFeatures {
+    let mut features = HardwareFeatures::empty();
+
+    // SAFETY:
+    //  The **IA32_EFER** register exists on every x86_64 CPU.
+    let efer = raw::Efer::from_bits_retain(unsafe { instr::rdmsr(raw::IA32_EFER) });
+    if efer.contains(raw::Efer::NO_EXECUTE_ENABLE) {
+        features |= HardwareFeatures::NO_EXECUTE;
+    }
+    if raw::Cr4::from_bits_retain(instr::cr4()).contains(raw::Cr4::FSGSBASE) {
+        features |= HardwareFeatures::FSGSBASE;
+    }
+    if instr::cpuid(1, 0)[2] & CPUID_X2APIC != 0 {
+        features |= HardwareFeatures::X2APIC;
+    }
+
+    features
+}
+
+/// Returns the sizes of pages supported by the CPU.
+pub fn page_sizes() -> PageSizes {
+    // 2 MiB pages are supported by every x86_64 CPU.
+    let mut sizes = PageSizes::SIZE_4KIB | PageSizes::SIZE_2MIB;
+
+    if instr::cpuid(0x8000_0000, 0)[0] >= 0x8000_0001
+        && instr::cpuid(0x8000_0001, 0)[3] & CPUID_PAGE_1GIB != 0
+    {
+        sizes |= PageSizes::SIZE_1GIB;
+    }
+
+    sizes
+}
+
+/// Returns the information gathered by [`init`].
+#[inline]
+pub fn get() -> CpuInfo {
+    // SAFETY:
+    //  This is only modified during boot.
+    unsafe { INFO }
+}
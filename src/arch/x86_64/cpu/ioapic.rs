@@ -0,0 +1,276 @@
+//! A driver for the I/O APICs, which route external interrupts to the local APICs.
+//!
+//! The I/O APICs of the system are described by the ACPI MADT. Each of them handles a range of
+//! global system interrupts (GSIs), starting at its GSI base.
+//!
+//! # Routing
+//!
+//! Every redirection entry is masked by [`init`]. A GSI only fires once it has been [`route`]d
+//! to a vector, which the drivers of the kernel do for the interrupts they handle:
+//!
+//! - The timer, when the PIT is used to drive the scheduler (see [`timer`]).
+//! - The SCI of the power button (see [`acpi::power`]).
+//! - The PS/2 keyboard (see [`keyboard`]).
+//!
+//! Userspace drivers do not route GSIs themselves. They bind the interrupt line of a PCI device
+//! they own to one of their ports with the `bind_irq` system call, and the kernel routes it to a
+//! vector of its own (see [`irq`]).
+//!
+//! Interrupts are always delivered to the bootstrap CPU: the application processors never run
+//! with interrupts enabled (see [`smp`]).
+//!
+//! [`timer`]: crate::x86_64::timer
+//! [`acpi::power`]: crate::x86_64::acpi::power
+//! [`keyboard`]: crate::x86_64::keyboard
+//! [`irq`]: crate::x86_64::irq
+//! [`smp`]: crate::x86_64::smp
+
+use crate::log;
+use crate::utility::{MmioRegion, Register};
+use crate::x86_64::acpi::{self, MAX_IO_APICS};
+use crate::x86_64::cpu::paging::UpperHalfAddressSpaceTok;
+use crate::x86_64::mem::{vmalloc, MemoryTrackerTok, PhysAddr};
+use crate::x86_64::raw::PageFlags;
+
+/// The register selector of an I/O APIC.
+const IOREGSEL: Register<u32> = Register::new(0x00);
+/// The data window of an I/O APIC.
+const IOWIN: Register<u32> = Register::new(0x10);
+
+/// The size of the registers of an I/O APIC.
+const REGISTERS_SIZE: usize = 0x20;
+
+/// The index of the version register.
+const IOAPICVER: u32 = 0x01;
+/// The index of the first redirection table register.
+const IOREDTBL: u32 = 0x10;
+
+/// Set in a redirection entry when the interrupt is masked.
+const REDIRECTION_MASKED: u64 = 1 << 16;
+/// Set in a redirection entry when the interrupt is level-triggered.
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+/// Set in a redirection entry when the interrupt is active-low.
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+
+/// The polarity of an interrupt line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// The trigger mode of an interrupt line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// Indicates that no I/O APIC handles a global system interrupt.
+#[derive(Debug, Clone, Copy)]
+pub struct UnknownGsi;
+
+/// An initialized I/O APIC.
+#[derive(Clone, Copy)]
+struct IoApic {
+    /// The registers of the I/O APIC.
+    registers: MmioRegion,
+    /// The first global system interrupt handled by the I/O APIC.
+    gsi_base: u32,
+    /// The number of entries in the redirection table of the I/O APIC.
+    entry_count: u32,
+}
+
+impl IoApic {
+    /// Reads the register with the provided index.
+    ///
+    /// # Safety
+    ///
+    /// The index must be valid for the I/O APIC.
+    unsafe fn read(&self, index: u32) -> u32 {
+        self.registers.write(IOREGSEL, index);
+        self.registers.read(IOWIN)
+    }
+
+    /// Writes the register with the provided index.
+    ///
+    /// # Safety
+    ///
+    /// The index must be valid for the I/O APIC.
+    unsafe fn write(&self, index: u32, value: u32) {
+        self.registers.write(IOREGSEL, index);
+        self.registers.write(IOWIN, value);
+    }
+
+    /// Writes the redirection entry with the provided index.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be less than `entry_count`.
+    unsafe fn write_entry(&self, index: u32, entry: u64) {
+        unsafe {
+            // Mask the entry while it is being modified.
+            self.write(IOREDTBL + index * 2, REDIRECTION_MASKED as u32);
+            self.write(IOREDTBL + index * 2 + 1, (entry >> 32) as u32);
+            self.write(IOREDTBL + index * 2, entry as u32);
+        }
+    }
+
+    /// Masks or unmasks the redirection entry with the provided index, leaving the rest of the
+    /// entry untouched.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be less than `entry_count`.
+    unsafe fn set_entry_masked(&self, index: u32, masked: bool) {
+        unsafe {
+            let low = self.read(IOREDTBL + index * 2);
+            let low = if masked {
+                low | REDIRECTION_MASKED as u32
+            } else {
+                low & !(REDIRECTION_MASKED as u32)
+            };
+            self.write(IOREDTBL + index * 2, low);
+        }
+    }
+}
+
+/// The I/O APICs of the system.
+static mut IO_APICS: [Option<IoApic>; MAX_IO_APICS] = [None; MAX_IO_APICS];
+
+/// Initializes the I/O APICs described by the ACPI tables, masking all of their interrupts.
+///
+/// # Safety
+///
+/// This function must only be called once, after the ACPI tables have been parsed and the memory
+/// tracker has been initialized.
+pub unsafe fn init(upper_half: UpperHalfAddressSpaceTok) {
+    let io_apics = acpi::info().io_apics.iter().flatten();
+
+    // SAFETY:
+    //  The caller guarantees that the memory tracker is initialized.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    // SAFETY:
+    //  This function is only called once, before any other access to the I/O APIC table.
+    for (slot, info) in unsafe { IO_APICS.iter_mut() }.zip(io_apics) {
+        // The registers of an I/O APIC are not necessarily covered by the direct map.
+        let base = unsafe {
+            vmalloc::map_physical(
+                upper_half,
+                &mut || memory_tracker.allocate_page_table(),
+                PhysAddr::new(info.address),
+                REGISTERS_SIZE,
+                PageFlags::WRITABLE
+                    | PageFlags::DISABLE_CACHE
+                    | PageFlags::GLOBAL
+                    | PageFlags::NO_EXECUTE,
+            )
+        };
+
+        let Ok(base) = base else {
+            log::error!("Failed to map the registers of the I/O APIC {}.", info.id);
+            continue;
+        };
+
+        let mut io_apic = IoApic {
+            // SAFETY:
+            //  The registers have just been mapped, and are never unmapped.
+            registers: unsafe { MmioRegion::new(base.as_ptr(), REGISTERS_SIZE) },
+            gsi_base: info.gsi_base,
+            entry_count: 0,
+        };
+
+        unsafe {
+            io_apic.entry_count = ((io_apic.read(IOAPICVER) >> 16) & 0xFF) + 1;
+
+            for index in 0..io_apic.entry_count {
+                io_apic.write_entry(index, REDIRECTION_MASKED);
+            }
+        }
+
+        log::trace!(
+            "I/O APIC {} handles GSIs {} to {}.",
+            info.id,
+            io_apic.gsi_base,
+            io_apic.gsi_base + io_apic.entry_count - 1,
+        );
+
+        *slot = Some(io_apic);
+    }
+}
+
+/// Returns the I/O APIC that handles the provided global system interrupt, along with the index
+/// of its redirection entry.
+fn find(gsi: u32) -> Result<(&'static IoApic, u32), UnknownGsi> {
+    // SAFETY:
+    //  The I/O APIC table is never accessed concurrently.
+    unsafe { IO_APICS.iter() }
+        .flatten()
+        .find(|io| io.gsi_base <= gsi && gsi - io.gsi_base < io.entry_count)
+        .map(|io| (io, gsi - io.gsi_base))
+        .ok_or(UnknownGsi)
+}
+
+/// Routes the provided global system interrupt to the interrupt vector `vector` of the local
+/// APIC with the ID `destination`, and unmasks it.
+///
+/// # Errors
+///
+/// If no I/O APIC handles the interrupt, an error is returned.
+pub fn route(
+    gsi: u32,
+    vector: u8,
+    polarity: Polarity,
+    trigger: TriggerMode,
+    destination: u8,
+) -> Result<(), UnknownGsi> {
+    let (io_apic, index) = find(gsi)?;
+
+    let mut entry = vector as u64 | (destination as u64) << 56;
+    if polarity == Polarity::ActiveLow {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if trigger == TriggerMode::Level {
+        entry |= REDIRECTION_LEVEL;
+    }
+
+    // SAFETY:
+    //  We checked that the I/O APIC handles the interrupt.
+    unsafe { io_apic.write_entry(index, entry) };
+
+    Ok(())
+}
+
+/// Masks or unmasks the provided global system interrupt, without modifying its routing.
+///
+/// # Errors
+///
+/// If no I/O APIC handles the interrupt, an error is returned.
+pub fn set_masked(gsi: u32, masked: bool) -> Result<(), UnknownGsi> {
+    let (io_apic, index) = find(gsi)?;
+
+    // SAFETY:
+    //  `find` only returns valid indices.
+    unsafe { io_apic.set_entry_masked(index, masked) };
+
+    Ok(())
+}
+
+/// Returns whether the provided global system interrupt is masked.
+///
+/// Interrupts that have never been routed are masked.
+///
+/// # Errors
+///
+/// If no I/O APIC handles the interrupt, an error is returned.
+pub fn is_masked(gsi: u32) -> Result<bool, UnknownGsi> {
+    let (io_apic, index) = find(gsi)?;
+
+    // SAFETY:
+    //  `find` only returns valid indices.
+    let low = unsafe { io_apic.read(IOREDTBL + index * 2) };
+
+    Ok(low & REDIRECTION_MASKED as u32 != 0)
+}
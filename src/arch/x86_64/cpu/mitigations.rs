@@ -0,0 +1,278 @@
+//! Mitigations against speculative execution vulnerabilities.
+//!
+//! # Enumeration
+//!
+//! During boot, [`init`] determines which vulnerabilities affect the CPU from its vendor, the
+//! speculation control features reported by **CPUID**, and the **IA32_ARCH_CAPABILITIES**
+//! register when it is available. The vulnerabilities and the mitigations in use are written to
+//! the log.
+//!
+//! # Branch Target Injection (Spectre v2)
+//!
+//! - When the CPU supports enhanced IBRS, it is enabled once and for all, which prevents
+//!   userspace from steering the indirect branches of the kernel.
+//!
+//! - Otherwise, the indirect call of the system call dispatcher goes through a *retpoline* (see
+//!   [`call_r11`]), which never lets the CPU speculate to a target picked by the branch
+//!   predictors. Retpolines rely on returning to an address that was not pushed by a `call`,
+//!   which shadow stacks forbid. When shadow stacks are used, IBRS is enabled instead.
+//!
+//! - An *Indirect Branch Prediction Barrier* is issued whenever the CPU switches to another
+//!   process, as processes do not trust each other.
+//!
+//! # Bounds Check Bypass (Spectre v1)
+//!
+//! The system call number is masked once it has been checked against the size of the dispatch
+//! table, so that a mispredicted bounds check cannot load a handler from outside of the table.
+//! The mask is computed from the result of the comparison itself, without any branch: even
+//! speculatively, the number is either within the table or zero.
+//!
+//! With the `spectre_v1=fence` option of the kernel command line, an `lfence` instruction is
+//! also executed between the bounds check and the load of the handler, which prevents the CPU
+//! from running the dispatcher speculatively at all, at the cost of a few cycles per system
+//! call.
+//!
+//! # Rogue Data Cache Load (Meltdown)
+//!
+//! Affected CPUs require page table isolation, which is expensive and must be requested with the
+//! `pti=on` option of the kernel command line. See [`pti`].
+
+use core::arch::asm;
+use core::fmt;
+
+use crate::log;
+use crate::utility::Cmdline;
+use crate::x86_64::instr;
+use crate::x86_64::raw::{self, ArchCapabilities, SpecCtrl};
+
+use super::{cet, info, pti};
+
+/// Set in the `edx` register of the `0x7` **CPUID** leaf when the CPU supports IBRS and IBPB.
+const CPUID_SPEC_CTRL: u32 = 1 << 26;
+/// Set in the `edx` register of the `0x7` **CPUID** leaf when the CPU supports STIBP.
+const CPUID_STIBP: u32 = 1 << 27;
+/// Set in the `edx` register of the `0x7` **CPUID** leaf when the CPU has the
+/// **IA32_ARCH_CAPABILITIES** register.
+const CPUID_ARCH_CAPABILITIES: u32 = 1 << 29;
+/// Set in the `edx` register of the `0x7` **CPUID** leaf when the CPU supports SSBD.
+const CPUID_SSBD: u32 = 1 << 31;
+
+/// Set in the `ebx` register of the `0x80000008` **CPUID** leaf when the CPU supports IBPB.
+const CPUID_AMD_IBPB: u32 = 1 << 12;
+/// Set in the `ebx` register of the `0x80000008` **CPUID** leaf when the CPU supports IBRS.
+const CPUID_AMD_IBRS: u32 = 1 << 14;
+/// Set in the `ebx` register of the `0x80000008` **CPUID** leaf when IBRS may be left enabled
+/// at all times, like enhanced IBRS.
+const CPUID_AMD_IBRS_ALWAYS_ON: u32 = 1 << 16;
+/// Set in the `ebx` register of the `0x80000008` **CPUID** leaf when the CPU supports SSBD.
+const CPUID_AMD_SSBD: u32 = 1 << 24;
+/// Set in the `ebx` register of the `0x80000008` **CPUID** leaf when the CPU is not vulnerable
+/// to speculative store bypass.
+const CPUID_AMD_SSB_NO: u32 = 1 << 26;
+
+/// Whether an *Indirect Branch Prediction Barrier* is issued when switching processes.
+///
+/// This is only modified by [`init`].
+static mut IBPB: bool = false;
+
+/// Whether the system call dispatcher calls its handlers through [`call_r11`].
+///
+/// This is read by the system call entry point, and only modified by [`init`].
+pub static mut RETPOLINE: bool = false;
+
+/// Whether the system call dispatcher executes `lfence` after checking the system call number.
+///
+/// This is read by the system call entry point, and only modified by [`init`].
+pub static mut DISPATCH_FENCE: bool = false;
+
+/// Displays the names of the provided items whose flag is set, separated by commas.
+struct Names<'a>(&'a [(&'static str, bool)]);
+
+impl fmt::Display for Names<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = self.0.iter().filter(|(_, set)| *set).map(|(name, _)| name);
+
+        match names.next() {
+            Some(first) => f.write_str(first)?,
+            None => return f.write_str("none"),
+        }
+
+        names.try_for_each(|name| write!(f, ", {}", name))
+    }
+}
+
+/// Determines which speculative execution vulnerabilities affect the CPU, and enables the
+/// mitigations that are available.
+///
+/// Page table isolation is enabled when requested on the command line, but its address spaces
+/// are only built later on by [`pti::init`].
+///
+/// # Safety
+///
+/// This function must be called once, during boot, after [`cet::init`].
+pub unsafe fn init(cmdline: Cmdline) {
+    let is_intel = &info::vendor() == b"GenuineIntel";
+
+    let leaf_7 = if instr::cpuid(0, 0)[0] >= 7 {
+        instr::cpuid(7, 0)[3]
+    } else {
+        0
+    };
+    let leaf_80000008 = if instr::cpuid(0x8000_0000, 0)[0] >= 0x8000_0008 {
+        instr::cpuid(0x8000_0008, 0)[1]
+    } else {
+        0
+    };
+    let capabilities = if leaf_7 & CPUID_ARCH_CAPABILITIES != 0 {
+        // SAFETY:
+        //  The CPU has the **IA32_ARCH_CAPABILITIES** register.
+        ArchCapabilities::from_bits_truncate(unsafe { instr::rdmsr(raw::IA32_ARCH_CAPABILITIES) })
+    } else {
+        ArchCapabilities::empty()
+    };
+
+    let ibpb = leaf_7 & CPUID_SPEC_CTRL != 0 || leaf_80000008 & CPUID_AMD_IBPB != 0;
+    let ibrs = leaf_7 & CPUID_SPEC_CTRL != 0 || leaf_80000008 & CPUID_AMD_IBRS != 0;
+    let enhanced_ibrs = capabilities.contains(ArchCapabilities::IBRS_ALL)
+        || leaf_80000008 & CPUID_AMD_IBRS_ALWAYS_ON != 0;
+    let stibp = leaf_7 & CPUID_STIBP != 0;
+    let ssbd = leaf_7 & CPUID_SSBD != 0 || leaf_80000008 & CPUID_AMD_SSBD != 0;
+
+    let meltdown = is_intel && !capabilities.contains(ArchCapabilities::RDCL_NO);
+    let mds = is_intel && !capabilities.contains(ArchCapabilities::MDS_NO);
+    let store_bypass =
+        !capabilities.contains(ArchCapabilities::SSB_NO) && leaf_80000008 & CPUID_AMD_SSB_NO == 0;
+
+    log::trace!(
+        "Speculation control features: {}.",
+        Names(&[
+            ("IBPB", ibpb),
+            ("IBRS", ibrs),
+            ("enhanced IBRS", enhanced_ibrs),
+            ("STIBP", stibp),
+            ("SSBD", ssbd),
+        ]),
+    );
+    log::trace!(
+        "Speculative execution vulnerabilities: {}.",
+        Names(&[
+            ("Meltdown", meltdown),
+            ("Spectre v2", !enhanced_ibrs),
+            ("speculative store bypass", store_bypass),
+            ("MDS", mds),
+        ]),
+    );
+
+    // Retpolines cannot be used along with shadow stacks.
+    let use_ibrs = enhanced_ibrs || (ibrs && cet::shadow_stacks_enabled());
+    let use_retpoline = !use_ibrs && !cet::shadow_stacks_enabled();
+
+    if use_ibrs {
+        // SAFETY:
+        //  The CPU supports the **IA32_SPEC_CTRL** register.
+        unsafe {
+            let spec_ctrl = instr::rdmsr(raw::IA32_SPEC_CTRL);
+            instr::wrmsr(raw::IA32_SPEC_CTRL, spec_ctrl | SpecCtrl::IBRS.bits());
+        }
+    } else if !use_retpoline {
+        log::warn!("Branch target injection is not mitigated.");
+    }
+
+    let use_pti = match cmdline.get(b"pti") {
+        Some(b"on") if cet::shadow_stacks_enabled() => {
+            log::warn!("Page table isolation cannot be used along with shadow stacks.");
+            false
+        }
+        Some(b"on") => true,
+        Some(b"off") | None => {
+            if meltdown {
+                log::warn!("The CPU is vulnerable to Meltdown. Pass `pti=on` to mitigate it.");
+            }
+            false
+        }
+        Some(value) => {
+            log::warn!(
+                "Invalid `pti` value: `{}`.",
+                core::str::from_utf8(value).unwrap_or("<invalid UTF-8>")
+            );
+            false
+        }
+    };
+
+    let use_dispatch_fence = match cmdline.get(b"spectre_v1") {
+        Some(b"fence") => true,
+        Some(b"mask") | None => false,
+        Some(value) => {
+            log::warn!(
+                "Invalid `spectre_v1` value: `{}`.",
+                core::str::from_utf8(value).unwrap_or("<invalid UTF-8>")
+            );
+            false
+        }
+    };
+
+    // SAFETY:
+    //  This function is only called once, during boot.
+    unsafe {
+        IBPB = ibpb;
+        RETPOLINE = use_retpoline;
+        DISPATCH_FENCE = use_dispatch_fence;
+        pti::ENABLED = use_pti;
+    }
+
+    log::trace!(
+        "Speculative execution mitigations: {}.",
+        Names(&[
+            ("IBRS", use_ibrs),
+            ("retpoline", use_retpoline),
+            ("IBPB", ibpb),
+            ("dispatch fence", use_dispatch_fence),
+            ("page table isolation", use_pti),
+        ]),
+    );
+}
+
+/// Issues an *Indirect Branch Prediction Barrier*, if the CPU supports it.
+///
+/// The indirect branches executed after the barrier are not influenced by the ones executed
+/// before it.
+#[inline]
+pub fn indirect_branch_barrier() {
+    // SAFETY:
+    //  This is only modified during boot.
+    if unsafe { IBPB } {
+        // SAFETY:
+        //  The CPU supports the **IA32_PRED_CMD** register.
+        unsafe { instr::wrmsr(raw::IA32_PRED_CMD, raw::PRED_CMD_IBPB) };
+    }
+}
+
+/// Jumps to the function whose address is in the `r11` register without letting the CPU
+/// speculatively execute a target picked by the branch predictors.
+///
+/// Calling this function is equivalent to `call r11`. The inner `call` instruction makes the
+/// return stack buffer predict that the `ret` instruction returns to a speculation trap. The
+/// return address is then replaced with the actual target, which the CPU only jumps to once it
+/// has been loaded.
+///
+/// # Safety
+///
+/// This function must be entered with a `call` instruction. Shadow stacks must not be enabled.
+#[naked]
+pub unsafe extern "C" fn call_r11() {
+    unsafe {
+        asm!(
+            r#"
+            call 2f
+        1:
+            pause
+            lfence
+            jmp 1b
+        2:
+            mov [rsp], r11
+            ret
+            "#,
+            options(noreturn),
+        );
+    }
+}
@@ -3,6 +3,52 @@
 mod exceptions;
 
 pub mod apic;
+pub mod cache;
+pub mod cet;
 pub mod gdt;
 pub mod idt;
+pub mod info;
+pub mod ioapic;
+pub mod mitigations;
 pub mod paging;
+pub mod pic;
+pub mod pit;
+pub mod pti;
+pub mod registers;
+
+/// Set in the `ecx` register of the `0x7` **CPUID** leaf when the CPU supports UMIP.
+const CPUID_UMIP: u32 = 1 << 2;
+
+/// Enables *User-Mode Instruction Prevention* if the CPU supports it.
+///
+/// Userspace processes are then unable to execute the `sgdt`, `sidt`, `sldt`, `smsw` and `str`
+/// instructions, which would reveal the addresses of the structures of the kernel. Attempting to
+/// do so raises a general protection fault.
+///
+/// # Safety
+///
+/// This function must be called during boot.
+pub unsafe fn init_umip() {
+    use super::instr;
+    use super::raw::Cr4;
+
+    if instr::cpuid(0, 0)[0] < 7 || instr::cpuid(7, 0)[2] & CPUID_UMIP == 0 {
+        crate::log::trace!("The CPU does not support UMIP.");
+        return;
+    }
+
+    // SAFETY:
+    //  The kernel never executes the instructions restricted by UMIP from userspace.
+    unsafe { instr::set_cr4(instr::cr4() | Cr4::UMIP.bits()) };
+
+    crate::log::trace!("User-mode instruction prevention is enabled.");
+}
+
+/// Returns the ID of the current CPU.
+///
+/// This is the initial APIC ID reported by the **CPUID** instruction, which is available even
+/// before the local APIC is reachable.
+#[inline]
+pub fn current_id() -> u32 {
+    super::instr::cpuid(1, 0)[1] >> 24
+}
@@ -1,50 +1,230 @@
+use core::fmt;
 use core::mem::MaybeUninit;
+use core::ops::Range;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
 
+use fabric_sys::x86_64::FaultPoint;
+
+use crate::kassert::kensure;
 use crate::log;
 
-use crate::x86_64::mem::{BootAllocator, OutOfMemory, HHDM_OFFSET, PAGE_SIZE};
-use crate::x86_64::raw::PageFlags;
+use crate::x86_64::config::{
+    LOWER_HALF_TOP_4_LEVEL, LOWER_HALF_TOP_5_LEVEL, USER_FIXED_TOP, USER_TOP,
+};
+use crate::x86_64::instr;
+use crate::x86_64::mem::{
+    BootAllocator, Frame, OutOfMemory, Page, PageSize, PhysAddr, Size1GiB, Size2MiB, Size4KiB,
+    VirtAddr, HHDM_OFFSET, PAGE_SIZE,
+};
+use crate::x86_64::raw::{Cr4, PageFlags};
 
-const ONE_GIB: usize = 1024 * 1024 * 1024;
-const TWO_MIB: usize = 1024 * 1024 * 2;
-const FOUR_KIB: usize = 1024 * 4;
+const ONE_GIB: usize = Size1GiB::SIZE;
 
-/// Fuses the provided flags.
+/// The flags of the L4 entries reserved by [`reserve_upper_half`].
 ///
-/// This function preserves the `ACCESSED` flag and the user-defined flags of `a`.
-fn fuse_flags(a: u64, b: u64) -> u64 {
-    debug_assert!(
-        b & PRESERVED_BITS == 0,
-        "potential loss of data ({:#x})",
-        b & PRESERVED_BITS
-    );
-    debug_assert!(a & PageFlags::HUGE.bits() == 0);
-    debug_assert!(b & PageFlags::HUGE.bits() == 0);
+/// Merging the flags used by the kernel mappings into them never modifies them. See
+/// [`UpperHalfAddressSpaceTok`].
+const UPPER_HALF_FLAGS: PageFlags = PageFlags::PRESENT.union(PageFlags::WRITABLE);
+
+/// The last address that may be part of the address space of userland processes, once
+/// [`USER_TOP`] has been clamped to the active paging mode by [`init_user_top`].
+static ACTIVE_USER_TOP: AtomicUsize = AtomicUsize::new(USER_FIXED_TOP);
+
+/// The last address of the lower half of the address space with the active paging mode.
+static ACTIVE_LOWER_HALF_TOP: AtomicUsize = AtomicUsize::new(LOWER_HALF_TOP_4_LEVEL);
+
+/// The size of the guard region at the top of the address space of processes, below
+/// [`user_top`].
+///
+/// Processes never map memory there. A `syscall` instruction at the very end of the lower half
+/// would make `sysretq` return to a non-canonical address, which faults in the kernel on Intel
+/// CPUs.
+pub const USER_GUARD_SIZE: usize = PAGE_SIZE;
+
+/// Clamps [`USER_TOP`] to the lower half of the active paging mode.
+///
+/// # Safety
+///
+/// This function must be called during boot, before any process is created.
+pub unsafe fn init_user_top() {
+    let lower_half_top = match Cr4::from_bits_retain(instr::cr4()).contains(Cr4::LA57) {
+        true => LOWER_HALF_TOP_5_LEVEL,
+        false => LOWER_HALF_TOP_4_LEVEL,
+    };
+
+    let user_top = USER_TOP.min(lower_half_top);
+    ACTIVE_USER_TOP.store(user_top, Relaxed);
+    ACTIVE_LOWER_HALF_TOP.store(lower_half_top, Relaxed);
+
+    log::trace!("The address space of processes ends at {:#x}.", user_top);
+}
+
+/// Returns the last address that may be part of the address space of userland processes.
+///
+/// This is [`USER_TOP`], clamped to the lower half of the active paging mode.
+#[inline(always)]
+pub fn user_top() -> usize {
+    ACTIVE_USER_TOP.load(Relaxed)
+}
+
+/// Returns the end of the part of the address space where processes may map memory.
+///
+/// This is the start of the guard region that ends at [`user_top`]. See [`USER_GUARD_SIZE`].
+#[inline(always)]
+pub fn user_limit() -> usize {
+    user_top() + 1 - USER_GUARD_SIZE
+}
+
+/// Returns whether `address` is canonical with the active paging mode.
+///
+/// Canonical addresses are either part of the lower half of the address space, or part of the
+/// upper half, whose addresses are the sign extension of the most significant implemented bit.
+#[inline]
+pub fn is_canonical(address: usize) -> bool {
+    let lower_half_top = ACTIVE_LOWER_HALF_TOP.load(Relaxed);
+    address <= lower_half_top || address >= !lower_half_top
+}
+
+/// An error that occurs when new flags cannot be merged into a page table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagMergeError {
+    /// The entry maps a huge page, which cannot be the parent of other entries.
+    HugePage,
+    /// The new flags contain bits that cannot be merged, and would be lost.
+    DataLoss(u64),
+}
+
+/// An entry of a page table.
+///
+/// An entry is made of the physical address of the frame or of the page table it references,
+/// and of [`PageFlags`]. Entries whose `PRESENT` flag is not set are ignored by the CPU.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PageTableEntry(u64);
 
+impl PageTableEntry {
+    /// An entry that references nothing.
+    pub const UNUSED: Self = Self(0);
+
+    /// The bits of an entry that hold the physical address it references.
+    const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+    /// The flags that are active in a merged entry when they are active in either of the
+    /// merged flags.
     const OR_FLAGS: PageFlags = PageFlags::DIRTY
         .union(PageFlags::PRESENT)
         .union(PageFlags::WRITABLE)
         .union(PageFlags::USER);
+
+    /// The flags that are only active in a merged entry when they are active in both of the
+    /// merged flags.
     const AND_FLAGS: PageFlags = PageFlags::DISABLE_CACHE
         .union(PageFlags::GLOBAL)
         .union(PageFlags::NO_EXECUTE)
         .union(PageFlags::WRITE_THROUGH);
-    const PRESERVED_BITS: u64 = 0x3FF0_FFFF_FFFF_FE00 | PageFlags::ACCESSED.bits();
 
-    let a_and = a & AND_FLAGS.bits();
-    let b_and = b & AND_FLAGS.bits();
-    let a_or = a & OR_FLAGS.bits();
-    let b_or = b & OR_FLAGS.bits();
-    let a_kept = a & PRESERVED_BITS;
+    /// The bits of an entry that are kept as-is when new flags are merged into it: the
+    /// address, the `ACCESSED` flag and the bits available to software.
+    const PRESERVED_BITS: u64 = 0x3FFF_FFFF_FFFF_FE00 | PageFlags::ACCESSED.bits();
+
+    /// Creates a new entry that references `addr` with the provided flags.
+    #[inline(always)]
+    pub const fn new(addr: PhysAddr, flags: PageFlags) -> Self {
+        Self(addr.get() as u64 & Self::ADDRESS_MASK | flags.bits())
+    }
+
+    /// Returns whether the entry is [`UNUSED`](Self::UNUSED).
+    #[inline(always)]
+    pub const fn is_unused(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns whether the `PRESENT` flag of the entry is set.
+    #[inline(always)]
+    pub const fn is_present(self) -> bool {
+        self.0 & PageFlags::PRESENT.bits() != 0
+    }
+
+    /// Returns whether the entry maps a huge page rather than referencing a page table.
+    #[inline(always)]
+    pub const fn is_huge(self) -> bool {
+        self.0 & PageFlags::HUGE.bits() != 0
+    }
 
-    // The flags that are part of the `OR_FLAGS` are simply fused together, while the flags that
-    // are part of the `AND_FLAGS` should only remain active if they are active in both flags.
-    a_kept | a_or | b_or | (a_and & b_and)
+    /// Returns the physical address referenced by the entry.
+    #[inline(always)]
+    pub const fn addr(self) -> PhysAddr {
+        PhysAddr::new((self.0 & Self::ADDRESS_MASK) as usize)
+    }
+
+    /// Returns the flags of the entry.
+    #[inline(always)]
+    pub const fn flags(self) -> PageFlags {
+        PageFlags::from_bits_truncate(self.0)
+    }
+
+    /// Makes the entry reference `addr` with the provided flags.
+    #[inline(always)]
+    pub fn set(&mut self, addr: PhysAddr, flags: PageFlags) {
+        *self = Self::new(addr, flags);
+    }
+
+    /// Sets the entry to [`UNUSED`](Self::UNUSED).
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        *self = Self::UNUSED;
+    }
+
+    /// Merges `flags` into the flags of the entry, so that it may be the parent of an entry that
+    /// uses them.
+    ///
+    /// Flags that grant permissions (such as `WRITABLE` or `USER`) are kept when either the
+    /// entry or `flags` has them. Flags that restrict permissions (such as `NO_EXECUTE`) are only
+    /// kept when both have them. The address, the `ACCESSED` flag and the bits available to
+    /// software are preserved.
+    ///
+    /// # Errors
+    ///
+    /// - [`FlagMergeError::HugePage`] is returned if the entry maps a huge page.
+    ///
+    /// - [`FlagMergeError::DataLoss`] is returned if `flags` contains bits that would be lost by
+    ///   the merge, such as address bits or the `HUGE` flag.
+    ///
+    /// The entry is left unchanged on error.
+    pub fn fuse_flags(&mut self, flags: PageFlags) -> Result<(), FlagMergeError> {
+        if self.is_huge() {
+            return Err(FlagMergeError::HugePage);
+        }
+
+        let lost = flags.bits() & (Self::PRESERVED_BITS | PageFlags::HUGE.bits());
+        if lost != 0 {
+            return Err(FlagMergeError::DataLoss(lost));
+        }
+
+        let a = self.0;
+        let b = flags.bits();
+
+        let a_and = a & Self::AND_FLAGS.bits();
+        let b_and = b & Self::AND_FLAGS.bits();
+        let a_or = a & Self::OR_FLAGS.bits();
+        let b_or = b & Self::OR_FLAGS.bits();
+        let a_kept = a & Self::PRESERVED_BITS;
+
+        self.0 = a_kept | a_or | b_or | (a_and & b_and);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PageTableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PageTableEntry({:#x}, {:?})", self.addr(), self.flags())
+    }
 }
 
 /// A page table.
 #[repr(align(4096))]
-pub struct PageTable(pub [u64; 512]);
+pub struct PageTable(pub [PageTableEntry; 512]);
 
 impl PageTable {
     /// Returns a mutable reference to the page table entry at the given index.
@@ -53,7 +233,7 @@ impl PageTable {
     ///
     /// `index` must be less than 512.
     #[inline(always)]
-    unsafe fn entry_mut(&mut self, index: usize) -> &mut u64 {
+    unsafe fn entry_mut(&mut self, index: usize) -> &mut PageTableEntry {
         debug_assert!(index < 512);
         unsafe { self.0.get_unchecked_mut(index) }
     }
@@ -73,26 +253,35 @@ impl PageTable {
     unsafe fn directory_entry_mut(
         &mut self,
         direct_map: usize,
-        alloc_page: &mut dyn FnMut() -> Result<usize, OutOfMemory>,
+        alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
         index: usize,
         parent_flags: PageFlags,
     ) -> Result<&mut PageTable, OutOfMemory> {
         let entry = unsafe { self.entry_mut(index) };
 
-        let page;
-        if *entry == 0 {
+        if entry.is_unused() {
             // The directory entry is not present. We have to allocate a page table for this.
-            page = alloc_page()?;
+            let page = alloc_page()?;
 
-            unsafe { core::ptr::write_bytes((page + direct_map) as *mut PageTable, 0x00, 1) };
+            unsafe { core::ptr::write_bytes((page.get() + direct_map) as *mut PageTable, 0x00, 1) };
 
-            *entry = page as u64 | (PageFlags::PRESENT | parent_flags).bits();
+            entry.set(page, PageFlags::PRESENT | parent_flags);
         } else {
-            // The directory is already present. We need to extract the address.
-            *entry = fuse_flags(*entry, parent_flags.bits());
-            page = (*entry & 0x0fffffff_fffff000) as usize;
+            // A huge page would be interpreted as a page table, corrupting the memory it maps, and
+            // lost flags would silently change the permissions of the entries below. Callers
+            // already undo their changes when they run out of memory.
+            let merged = entry.fuse_flags(parent_flags);
+            kensure!(
+                merged.is_ok(),
+                Err(OutOfMemory),
+                "cannot merge {:?} into {:?}: {:?}",
+                parent_flags,
+                entry,
+                merged,
+            );
         }
 
+        let page = entry.addr().get();
         debug_assert!((page + direct_map) % PAGE_SIZE == 0);
         Ok(unsafe { &mut *((page + direct_map) as *mut PageTable) })
     }
@@ -109,24 +298,17 @@ impl PageTable {
     ) -> Option<&mut PageTable> {
         let entry = unsafe { *self.entry_mut(index) };
 
-        if entry & PageFlags::PRESENT.bits() == 0 {
+        if !entry.is_present() || entry.is_huge() {
             return None;
         }
 
-        if entry & PageFlags::HUGE.bits() != 0 {
-            return None;
-        }
-
-        let phys_addr = entry & 0x0FFFFFFF_FFFFF000;
-        let virt_addr = phys_addr as usize + direct_map;
+        let virt_addr = entry.addr().get() + direct_map;
 
         Some(unsafe { &mut *(virt_addr as *mut PageTable) })
     }
 }
 
-/// Maps a 4k page.
-///
-/// This function assumes that `phys` and `virt` are aligned to 4 KiB.
+/// Maps a 4 KiB page to a frame of physical memory.
 ///
 /// # Safety
 ///
@@ -134,32 +316,40 @@ impl PageTable {
 pub unsafe fn map_4kib(
     l4: &mut PageTable,
     direct_map: usize,
-    alloc_page: &mut dyn FnMut() -> Result<usize, OutOfMemory>,
-    virt: usize,
-    phys: usize,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    page: Page,
+    frame: Frame,
     flags: PageFlags,
 ) -> Result<(), OutOfMemory> {
-    debug_assert!(phys % FOUR_KIB == 0);
-    debug_assert!(virt % FOUR_KIB == 0);
+    if crate::x86_64::fault_injection::should_fail(FaultPoint::Map4KiB) {
+        return Err(OutOfMemory);
+    }
+
+    let virt = page.start();
+    let phys = frame.start();
 
-    let l4_idx = (virt >> 39) & 0o777;
-    let l3_idx = (virt >> 30) & 0o777;
-    let l2_idx = (virt >> 21) & 0o777;
-    let l1_idx = (virt >> 12) & 0o777;
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
+    let l2_idx = virt.table_index(2);
+    let l1_idx = virt.table_index(1);
 
     let l3 = unsafe { l4.directory_entry_mut(direct_map, alloc_page, l4_idx, flags)? };
     let l2 = unsafe { l3.directory_entry_mut(direct_map, alloc_page, l3_idx, flags)? };
     let l1 = unsafe { l2.directory_entry_mut(direct_map, alloc_page, l2_idx, flags)? };
 
-    let entry = unsafe { l1.entry_mut(l1_idx) };
-    *entry = phys as u64 | (PageFlags::PRESENT | flags).bits();
+    unsafe { l1.entry_mut(l1_idx) }.set(phys, PageFlags::PRESENT | flags);
+
+    crate::paranoid::check!(
+        unsafe { translate_4kib(l4, direct_map, page) } == Some(frame),
+        "{:#x} is not mapped to {:#x} after being mapped",
+        virt,
+        phys,
+    );
 
     Ok(())
 }
 
-/// Maps a 2 MiB page.
-///
-/// This function assumes that `phys` and `virt` are aligned to 2 MiB.
+/// Maps a 2 MiB page to a frame of physical memory.
 ///
 /// # Safety
 ///
@@ -167,30 +357,27 @@ pub unsafe fn map_4kib(
 pub unsafe fn map_2mib(
     l4: &mut PageTable,
     direct_map: usize,
-    alloc_page: &mut dyn FnMut() -> Result<usize, OutOfMemory>,
-    virt: usize,
-    phys: usize,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    page: Page<Size2MiB>,
+    frame: Frame<Size2MiB>,
     flags: PageFlags,
 ) -> Result<(), OutOfMemory> {
-    debug_assert!(phys % TWO_MIB == 0);
-    debug_assert!(virt % TWO_MIB == 0);
+    let virt = page.start();
+    let phys = frame.start();
 
-    let l4_idx = (virt >> 39) & 0o777;
-    let l3_idx = (virt >> 30) & 0o777;
-    let l2_idx = (virt >> 21) & 0o777;
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
+    let l2_idx = virt.table_index(2);
 
     let l3 = unsafe { l4.directory_entry_mut(direct_map, alloc_page, l4_idx, flags)? };
     let l2 = unsafe { l3.directory_entry_mut(direct_map, alloc_page, l3_idx, flags)? };
 
-    let entry = unsafe { l2.entry_mut(l2_idx) };
-    *entry = phys as u64 | (PageFlags::PRESENT | PageFlags::HUGE | flags).bits();
+    unsafe { l2.entry_mut(l2_idx) }.set(phys, PageFlags::PRESENT | PageFlags::HUGE | flags);
 
     Ok(())
 }
 
-/// Maps a 1 GiB page.
-///
-/// This function assumes that `phys` and `virt` are aligned to 1 GiB.
+/// Maps a 1 GiB page to a frame of physical memory.
 ///
 /// # Safety
 ///
@@ -198,21 +385,20 @@ pub unsafe fn map_2mib(
 pub unsafe fn map_1gib(
     l4: &mut PageTable,
     direct_map: usize,
-    alloc_page: &mut dyn FnMut() -> Result<usize, OutOfMemory>,
-    virt: usize,
-    phys: usize,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    page: Page<Size1GiB>,
+    frame: Frame<Size1GiB>,
     flags: PageFlags,
 ) -> Result<(), OutOfMemory> {
-    debug_assert!(phys % ONE_GIB == 0);
-    debug_assert!(virt % ONE_GIB == 0);
+    let virt = page.start();
+    let phys = frame.start();
 
-    let l4_idx = (virt >> 39) & 0o777;
-    let l3_idx = (virt >> 30) & 0o777;
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
 
     let l3 = unsafe { l4.directory_entry_mut(direct_map, alloc_page, l4_idx, flags)? };
 
-    let entry = unsafe { l3.entry_mut(l3_idx) };
-    *entry = phys as u64 | (PageFlags::PRESENT | PageFlags::HUGE | flags).bits();
+    unsafe { l3.entry_mut(l3_idx) }.set(phys, PageFlags::PRESENT | PageFlags::HUGE | flags);
 
     Ok(())
 }
@@ -221,35 +407,453 @@ pub unsafe fn map_1gib(
 ///
 /// # Returns
 ///
-/// If the page was not mapped, `Err(())` is returned. Otherwise, `Ok(())` is returned.
+/// If the page was not mapped, `Err(())` is returned. Otherwise, the frame that the page was
+/// mapped to is returned. The frame itself is not freed.
+///
+/// Pages that are part of a huge page are not considered mapped: the huge page must first be
+/// split with [`split_huge_page`].
 ///
 /// # Safety
 ///
 /// `direct_map` can be used to compute the virtual address of a given physical address.
-pub unsafe fn unmap_4kib(l4: &mut PageTable, direct_map: usize, virt: usize) -> Result<(), ()> {
-    debug_assert!(virt % FOUR_KIB == 0);
+pub unsafe fn unmap_4kib(l4: &mut PageTable, direct_map: usize, page: Page) -> Result<Frame, ()> {
+    let virt = page.start();
 
-    let l4_idx = (virt >> 39) & 0o777;
-    let l3_idx = (virt >> 30) & 0o777;
-    let l2_idx = (virt >> 21) & 0o777;
-    let l1_idx = (virt >> 12) & 0o777;
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
+    let l2_idx = virt.table_index(2);
+    let l1_idx = virt.table_index(1);
 
-    unsafe {
+    let frame = unsafe {
         let l3 = l4.try_directory_entry_mut(direct_map, l4_idx).ok_or(())?;
         let l2 = l3.try_directory_entry_mut(direct_map, l3_idx).ok_or(())?;
         let l1 = l2.try_directory_entry_mut(direct_map, l2_idx).ok_or(())?;
 
         let entry = l1.entry_mut(l1_idx);
-        if *entry & PageFlags::PRESENT.bits() == 0 {
+        if !entry.is_present() {
+            return Err(());
+        }
+
+        let frame = Frame::containing(entry.addr());
+        entry.clear();
+        frame
+    };
+
+    crate::paranoid::check!(
+        unsafe { translate_4kib(l4, direct_map, page) }.is_none(),
+        "{:#x} is still mapped after being unmapped",
+        virt,
+    );
+
+    Ok(frame)
+}
+
+/// Unmaps a page of size 2 MiB.
+///
+/// # Returns
+///
+/// If the page was not mapped as a single 2 MiB page, `Err(())` is returned. Otherwise, `Ok(())`
+/// is returned.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+// Only the kernel maps huge pages for now, and it never unmaps them.
+#[allow(dead_code)]
+pub unsafe fn unmap_2mib(
+    l4: &mut PageTable,
+    direct_map: usize,
+    page: Page<Size2MiB>,
+) -> Result<(), ()> {
+    let virt = page.start();
+
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
+    let l2_idx = virt.table_index(2);
+
+    unsafe {
+        let l3 = l4.try_directory_entry_mut(direct_map, l4_idx).ok_or(())?;
+        let l2 = l3.try_directory_entry_mut(direct_map, l3_idx).ok_or(())?;
+
+        let entry = l2.entry_mut(l2_idx);
+        if !entry.is_present() || !entry.is_huge() {
             return Err(());
         }
 
-        *entry = 0;
+        entry.clear();
     }
 
     Ok(())
 }
 
+/// Unmaps a page of size 1 GiB.
+///
+/// # Returns
+///
+/// If the page was not mapped as a single 1 GiB page, `Err(())` is returned. Otherwise, `Ok(())`
+/// is returned.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+// Only the kernel maps huge pages for now, and it never unmaps them.
+#[allow(dead_code)]
+pub unsafe fn unmap_1gib(
+    l4: &mut PageTable,
+    direct_map: usize,
+    page: Page<Size1GiB>,
+) -> Result<(), ()> {
+    let virt = page.start();
+
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
+
+    unsafe {
+        let l3 = l4.try_directory_entry_mut(direct_map, l4_idx).ok_or(())?;
+
+        let entry = l3.entry_mut(l3_idx);
+        if !entry.is_present() || !entry.is_huge() {
+            return Err(());
+        }
+
+        entry.clear();
+    }
+
+    Ok(())
+}
+
+/// Replaces the huge page mapped by `entry` with a page table that maps the same memory with
+/// pages of `child_size` bytes.
+///
+/// # Safety
+///
+/// `entry` must map a huge page whose size is 512 times `child_size`.
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+unsafe fn split_entry(
+    entry: &mut PageTableEntry,
+    direct_map: usize,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    child_size: usize,
+) -> Result<(), OutOfMemory> {
+    debug_assert!(entry.is_present() && entry.is_huge());
+
+    let table = alloc_page()?;
+    let children = unsafe { &mut *((table.get() + direct_map) as *mut PageTable) };
+
+    // The low bits of the address of a huge page hold its PAT bit, which is never set.
+    let base = PhysAddr::new(entry.addr().get() & !(512 * child_size - 1));
+
+    // The `HUGE` bit of a level 1 entry is its PAT bit instead.
+    let mut flags = entry.flags();
+    if child_size == Size4KiB::SIZE {
+        flags.remove(PageFlags::HUGE);
+    }
+
+    for (i, child) in children.0.iter_mut().enumerate() {
+        child.set(base + i * child_size, flags);
+    }
+
+    // The permissions of the parent entry are the same as the ones of the huge page, so the
+    // effective permissions of the memory are unchanged.
+    let mut parent_flags = entry.flags();
+    parent_flags.remove(PageFlags::HUGE | PageFlags::DIRTY | PageFlags::GLOBAL);
+    entry.set(table, parent_flags);
+
+    Ok(())
+}
+
+/// Splits the huge pages that contain the provided 4 KiB page, so that it is mapped by a level 1
+/// page table.
+///
+/// A 1 GiB page is split into 2 MiB pages, and the 2 MiB page that contains `page` is then split
+/// into 4 KiB pages. The memory remains mapped to the same frames with the same flags, which
+/// allows [`unmap_4kib`] to unmap only part of a huge page.
+///
+/// Nothing is done if `page` is not mapped, or if it is already mapped by a 4 KiB page.
+///
+/// # Returns
+///
+/// The number of page tables that were allocated.
+///
+/// # Errors
+///
+/// If a page table cannot be allocated, [`OutOfMemory`] is returned. The mapping may have been
+/// partially split, but the memory remains mapped to the same frames.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+///
+/// The caller must invalidate the TLB entries of the split pages before relying on the new
+/// mappings, as the CPU may still hold translations for the huge pages.
+// Only the kernel maps huge pages for now, and it never unmaps them.
+#[allow(dead_code)]
+pub unsafe fn split_huge_page(
+    l4: &mut PageTable,
+    direct_map: usize,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    page: Page,
+) -> Result<usize, OutOfMemory> {
+    let virt = page.start();
+
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
+    let l2_idx = virt.table_index(2);
+
+    let mut allocated = 0;
+
+    unsafe {
+        let Some(l3) = l4.try_directory_entry_mut(direct_map, l4_idx) else {
+            return Ok(0);
+        };
+
+        let entry = l3.entry_mut(l3_idx);
+        if entry.is_present() && entry.is_huge() {
+            split_entry(entry, direct_map, alloc_page, Size2MiB::SIZE)?;
+            allocated += 1;
+        }
+
+        let Some(l2) = l3.try_directory_entry_mut(direct_map, l3_idx) else {
+            return Ok(allocated);
+        };
+
+        let entry = l2.entry_mut(l2_idx);
+        if entry.is_present() && entry.is_huge() {
+            split_entry(entry, direct_map, alloc_page, Size4KiB::SIZE)?;
+            allocated += 1;
+        }
+    }
+
+    crate::paranoid::check!(
+        unsafe { translate_4kib(l4, direct_map, page) }.is_some()
+            || unsafe { translate(l4, direct_map, virt) }.is_none(),
+        "{:#x} is still part of a huge page after being split",
+        virt,
+    );
+
+    Ok(allocated)
+}
+
+/// Returns the frame that the provided 4 KiB page is mapped to.
+///
+/// # Returns
+///
+/// If the page is not mapped, or if it is part of a huge page, `None` is returned.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+pub unsafe fn translate_4kib(l4: &mut PageTable, direct_map: usize, page: Page) -> Option<Frame> {
+    let virt = page.start();
+
+    let l4_idx = virt.table_index(4);
+    let l3_idx = virt.table_index(3);
+    let l2_idx = virt.table_index(2);
+    let l1_idx = virt.table_index(1);
+
+    unsafe {
+        let l3 = l4.try_directory_entry_mut(direct_map, l4_idx)?;
+        let l2 = l3.try_directory_entry_mut(direct_map, l3_idx)?;
+        let l1 = l2.try_directory_entry_mut(direct_map, l2_idx)?;
+
+        let entry = *l1.entry_mut(l1_idx);
+        if !entry.is_present() {
+            return None;
+        }
+
+        Some(Frame::containing(entry.addr()))
+    }
+}
+
+/// Returns the physical address that the provided virtual address is mapped to, regardless of
+/// the size of the page that contains it.
+///
+/// # Returns
+///
+/// If the address is not mapped, `None` is returned.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+pub unsafe fn translate(l4: &mut PageTable, direct_map: usize, virt: VirtAddr) -> Option<PhysAddr> {
+    let virt = virt.get();
+    let mut table = l4;
+
+    for shift in [39, 30, 21, 12] {
+        let entry = unsafe { *table.entry_mut((virt >> shift) & 0o777) };
+        if !entry.is_present() {
+            return None;
+        }
+
+        let phys = entry.addr().get();
+        if shift == 12 || entry.is_huge() {
+            let mask = (1 << shift) - 1;
+            return Some(PhysAddr::new((phys & !mask) | (virt & mask)));
+        }
+
+        table = unsafe { &mut *((phys + direct_map) as *mut PageTable) };
+    }
+
+    None
+}
+
+/// Calls `f` with the virtual address, the physical address, the size and the flags of every
+/// page mapped by the provided entries of an L4 table.
+///
+/// # Returns
+///
+/// The number of page tables visited, including the L4 table.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+unsafe fn walk(
+    l4: &mut PageTable,
+    direct_map: usize,
+    entries: Range<usize>,
+    f: &mut dyn FnMut(usize, usize, usize, PageFlags),
+) -> usize {
+    /// Walks the entries of `table`, which maps `1 << shift` bytes per entry starting at `base`.
+    unsafe fn walk_table(
+        table: &mut PageTable,
+        direct_map: usize,
+        base: usize,
+        shift: u32,
+        entries: Range<usize>,
+        f: &mut dyn FnMut(usize, usize, usize, PageFlags),
+    ) -> usize {
+        let mut tables = 1;
+
+        for index in entries {
+            let entry = unsafe { *table.entry_mut(index) };
+            if !entry.is_present() {
+                continue;
+            }
+
+            // Addresses of the higher half must be sign-extended to be canonical.
+            let virt = ((((base + (index << shift)) << 16) as isize) >> 16) as usize;
+            let flags = entry.flags();
+
+            if shift == 12 || entry.is_huge() {
+                let phys = entry.addr().get() & !((1 << shift) - 1);
+                f(virt, phys, 1 << shift, flags);
+            } else if let Some(next) = unsafe { table.try_directory_entry_mut(direct_map, index) } {
+                tables += unsafe { walk_table(next, direct_map, virt, shift - 9, 0..512, f) };
+            }
+        }
+
+        tables
+    }
+
+    unsafe { walk_table(l4, direct_map, 0, 39, entries, f) }
+}
+
+/// Calls `f` with the virtual address, the size and the flags of every page mapped in the lower
+/// half of the provided address space.
+///
+/// # Returns
+///
+/// The number of page tables that map the lower half, including the root table.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+pub unsafe fn walk_lower_half(
+    l4: &mut PageTable,
+    direct_map: usize,
+    f: &mut dyn FnMut(usize, usize, PageFlags),
+) -> usize {
+    // The lower half is mapped by the first 256 entries of the L4 table.
+    unsafe {
+        walk(l4, direct_map, 0..256, &mut |virt, _, size, flags| {
+            f(virt, size, flags)
+        })
+    }
+}
+
+/// Memory released by [`destroy_lower_half`].
+#[derive(Debug, Clone, Copy)]
+pub enum Released {
+    /// A page of `size` bytes mapped at `virt`, backed by the memory starting at `phys`.
+    Page {
+        virt: VirtAddr,
+        phys: PhysAddr,
+        size: usize,
+    },
+    /// A page table that is no longer referenced.
+    Table(PhysAddr),
+}
+
+/// Unmaps everything mapped in the lower half of the provided address space, and releases the
+/// page tables that mapped it.
+///
+/// `release` is called once for every page that was mapped, and once for every page table that
+/// is no longer referenced. The L4 table itself is left to the caller, and the entries that
+/// mapped the lower half are cleared.
+///
+/// # Returns
+///
+/// The number of page tables that were released.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+///
+/// The address space must not be loaded, as the CPU may otherwise keep walking the released
+/// page tables.
+pub unsafe fn destroy_lower_half(
+    l4: &mut PageTable,
+    direct_map: usize,
+    release: &mut dyn FnMut(Released),
+) -> usize {
+    /// Destroys the entries of `table`, which maps `1 << shift` bytes per entry starting at
+    /// `base`.
+    unsafe fn destroy_table(
+        table: &mut PageTable,
+        direct_map: usize,
+        base: usize,
+        shift: u32,
+        entries: Range<usize>,
+        release: &mut dyn FnMut(Released),
+    ) -> usize {
+        let mut tables = 0;
+
+        for index in entries {
+            let virt = base + (index << shift);
+
+            if let Some(next) = unsafe { table.try_directory_entry_mut(direct_map, index) } {
+                tables +=
+                    unsafe { destroy_table(next, direct_map, virt, shift - 9, 0..512, release) };
+            }
+
+            let entry = unsafe { table.entry_mut(index) };
+            if !entry.is_present() {
+                entry.clear();
+                continue;
+            }
+
+            if shift == 12 || entry.is_huge() {
+                release(Released::Page {
+                    virt: VirtAddr::new(virt),
+                    phys: PhysAddr::new(entry.addr().get() & !((1 << shift) - 1)),
+                    size: 1 << shift,
+                });
+            } else {
+                release(Released::Table(entry.addr()));
+                tables += 1;
+            }
+
+            entry.clear();
+        }
+
+        tables
+    }
+
+    // The lower half is mapped by the first 256 entries of the L4 table.
+    unsafe { destroy_table(l4, direct_map, 0, 39, 0..256, release) }
+}
+
 /// Creates a direct mapping for the given physical address.
 ///
 /// Both `phys` and `virt` must be aligned to the page size. The size may or may not be aligned as
@@ -262,46 +866,91 @@ pub unsafe fn unmap_4kib(l4: &mut PageTable, direct_map: usize, virt: usize) ->
 pub unsafe fn create_direct_map(
     l4: &mut PageTable,
     direct_map: usize,
-    alloc_page: &mut dyn FnMut() -> Result<usize, OutOfMemory>,
-    mut phys: usize,
-    mut virt: usize,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    mut phys: PhysAddr,
+    mut virt: VirtAddr,
     mut size: usize,
     flags: PageFlags,
 ) -> Result<(), OutOfMemory> {
-    debug_assert!(phys % PAGE_SIZE == 0);
-    debug_assert!(virt % PAGE_SIZE == 0);
+    debug_assert!(phys.is_page_aligned());
+    debug_assert!(virt.is_page_aligned());
 
-    if size == 0 {
-        return Ok(());
-    }
+    while size != 0 {
+        macro_rules! try_map {
+            ($size:ty, $map:ident) => {
+                if size >= <$size>::SIZE {
+                    if let (Some(page), Some(frame)) = (
+                        Page::<$size>::from_start(virt),
+                        Frame::<$size>::from_start(phys),
+                    ) {
+                        unsafe { $map(l4, direct_map, alloc_page, page, frame, flags)? };
 
-    loop {
-        if size >= ONE_GIB && phys % ONE_GIB == 0 && virt % ONE_GIB == 0 {
-            unsafe { map_1gib(l4, direct_map, alloc_page, virt, phys, flags)? };
+                        size -= <$size>::SIZE;
+                        virt += <$size>::SIZE;
+                        phys += <$size>::SIZE;
+                        continue;
+                    }
+                }
+            };
+        }
 
-            size -= ONE_GIB;
-            virt += ONE_GIB;
-            phys += ONE_GIB;
-        } else if size >= TWO_MIB && phys % TWO_MIB == 0 && virt % TWO_MIB == 0 {
-            unsafe { map_2mib(l4, direct_map, alloc_page, virt, phys, flags)? };
+        try_map!(Size1GiB, map_1gib);
+        try_map!(Size2MiB, map_2mib);
 
-            size -= TWO_MIB;
-            virt += TWO_MIB;
-            phys += TWO_MIB;
-        } else {
-            unsafe { map_4kib(l4, direct_map, alloc_page, virt, phys, flags)? };
+        // The last page may be partially used.
+        let page = Page::<Size4KiB>::containing(virt);
+        unsafe {
+            map_4kib(
+                l4,
+                direct_map,
+                alloc_page,
+                page,
+                Frame::containing(phys),
+                flags,
+            )?
+        };
 
-            if size <= FOUR_KIB {
-                break;
-            }
+        size = size.saturating_sub(Size4KiB::SIZE);
+        virt += Size4KiB::SIZE;
+        phys += Size4KiB::SIZE;
+    }
+
+    Ok(())
+}
+
+/// Allocates an L3 table for every unused entry of the upper half of the provided L4 table.
+///
+/// Once this is done, mapping memory in the upper half never modifies the L4 table. See
+/// [`UpperHalfAddressSpaceTok`].
+///
+/// # Returns
+///
+/// The number of page tables that were allocated.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+unsafe fn reserve_upper_half(
+    l4: &mut PageTable,
+    direct_map: usize,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+) -> Result<usize, OutOfMemory> {
+    let mut reserved = 0;
 
-            size -= FOUR_KIB;
-            virt += FOUR_KIB;
-            phys += FOUR_KIB;
+    // The upper half is mapped by the last 256 entries of the L4 table.
+    for entry in &mut l4.0[256..] {
+        if !entry.is_unused() {
+            continue;
         }
+
+        let page = alloc_page()?;
+        unsafe { core::ptr::write_bytes((page.get() + direct_map) as *mut PageTable, 0x00, 1) };
+
+        entry.set(page, UPPER_HALF_FLAGS);
+        reserved += 1;
     }
 
-    Ok(())
+    Ok(reserved)
 }
 
 /// Initialize the page table to be used by the kernel.
@@ -319,6 +968,9 @@ pub unsafe fn create_direct_map(
 /// will take precedence and overwrite the direct map. This is checked in debug mode and will
 /// panic if it happens.
 ///
+/// Every entry of the upper half of the created L4 table references an L3 table, even when
+/// nothing is mapped there yet. See [`UpperHalfAddressSpaceTok`].
+///
 /// # Arguments
 ///
 /// This function assumes a direct mapping between physical and virtual memory, and will use
@@ -344,17 +996,17 @@ pub unsafe fn create_kernel_address_space(
     direct_map: usize,
     boot_allocator: &mut BootAllocator,
     mut direct_map_size: usize,
-    mut kernel_start: usize,
-    public_data_phys: usize,
+    mut kernel_start: PhysAddr,
+    public_data_phys: PhysAddr,
     public_data_size: usize,
-) -> Result<usize, OutOfMemory> {
+) -> Result<PhysAddr, OutOfMemory> {
     log::trace!("Creating the kernel address space...");
 
     // If the kernel is not page aligned, we need to round down the start address.
     // Because that address is being rounded down, we need to add the difference to the size.
     let mut kernel_size = crate::x86_64::image_end() - crate::x86_64::image_begin();
-    kernel_size += kernel_start & !0xFFF;
-    kernel_start = crate::utility::align_page_down(kernel_start);
+    kernel_size += kernel_start.page_offset();
+    kernel_start = kernel_start.page_align_down();
 
     // The direct map must be at least four gigabytes large as some I/O devices are mapped there.
     if direct_map_size < ONE_GIB * 4 {
@@ -366,30 +1018,31 @@ pub unsafe fn create_kernel_address_space(
         "the kernel and the higher half direct map overlap"
     );
 
-    let l4 = boot_allocator.allocate(PAGE_SIZE, PAGE_SIZE)?;
-    unsafe { core::ptr::write_bytes((l4 + direct_map) as *mut PageTable, 0x00, 1) };
+    let l4 = boot_allocator.allocate_page_table()?;
+    let l4_table = (l4.get() + direct_map) as *mut PageTable;
+    unsafe { core::ptr::write_bytes(l4_table, 0x00, 1) };
 
-    let mut alloc_page = || boot_allocator.allocate(PAGE_SIZE, PAGE_SIZE);
+    let mut alloc_page = || boot_allocator.allocate_page_table();
 
     unsafe {
         // Create a direct mapping between physical memory and the higher half.
         create_direct_map(
-            &mut *((l4 + direct_map) as *mut PageTable),
+            &mut *l4_table,
             direct_map,
             &mut alloc_page,
-            0,
-            HHDM_OFFSET,
+            PhysAddr::ZERO,
+            VirtAddr::new(HHDM_OFFSET),
             direct_map_size,
             PageFlags::WRITABLE | PageFlags::GLOBAL,
         )?;
 
         // Map the kernel.
         create_direct_map(
-            &mut *((l4 + direct_map) as *mut PageTable),
+            &mut *l4_table,
             direct_map,
             &mut alloc_page,
             kernel_start,
-            crate::x86_64::image_begin(),
+            VirtAddr::new(crate::x86_64::image_begin()),
             kernel_size,
             PageFlags::WRITABLE | PageFlags::GLOBAL,
         )?;
@@ -400,23 +1053,147 @@ pub unsafe fn create_kernel_address_space(
             crate::x86_64::public_data_address()
         );
         create_direct_map(
-            &mut *((l4 + direct_map) as *mut PageTable),
+            &mut *l4_table,
             direct_map,
             &mut alloc_page,
             public_data_phys,
-            crate::x86_64::public_data_address(),
+            VirtAddr::new(crate::x86_64::public_data_address()),
             public_data_size,
             PageFlags::WRITABLE | PageFlags::GLOBAL | PageFlags::USER,
         )?;
+
+        let reserved = reserve_upper_half(&mut *l4_table, direct_map, &mut alloc_page)?;
+        log::trace!("Reserved {} L3 tables for the upper half.", reserved);
+    }
+
+    #[cfg(debug_assertions)]
+    unsafe {
+        verify_kernel_address_space(
+            &mut *l4_table,
+            direct_map,
+            direct_map_size,
+            kernel_start,
+            kernel_size,
+            public_data_size,
+        );
     }
 
     Ok(l4)
 }
 
+/// Walks the page tables created by [`create_kernel_address_space`], checking that:
+///
+/// - The direct map covers the first `direct_map_size` bytes of physical memory, with the
+///   expected flags.
+///
+/// - The kernel image is mapped at [`crate::x86_64::image_begin`], backed by the physical memory
+///   starting at `kernel_start`.
+///
+/// - The public data is the only part of the higher half that userland processes can access.
+///
+/// This catches bugs in the paging code before the new address space is loaded, where they would
+/// be much harder to diagnose.
+///
+/// # Panics
+///
+/// This function panics if any of those checks fails.
+///
+/// # Safety
+///
+/// `direct_map` can be used to compute the virtual address of a given physical address.
+#[cfg(debug_assertions)]
+unsafe fn verify_kernel_address_space(
+    l4: &mut PageTable,
+    direct_map: usize,
+    direct_map_size: usize,
+    kernel_start: PhysAddr,
+    kernel_size: usize,
+    public_data_size: usize,
+) {
+    let hhdm = HHDM_OFFSET..HHDM_OFFSET + direct_map_size;
+    let image = crate::x86_64::image_begin()..crate::x86_64::image_begin() + kernel_size;
+    let public_data = crate::x86_64::public_data_address()
+        ..crate::x86_64::public_data_address() + public_data_size;
+
+    let mut hhdm_covered = 0;
+    let mut image_covered = 0;
+
+    let tables = unsafe {
+        walk(l4, direct_map, 256..512, &mut |virt, phys, size, flags| {
+            let end = virt + (size - 1);
+
+            if flags.contains(PageFlags::USER) {
+                assert!(
+                    public_data.contains(&virt) && end < public_data.end.next_multiple_of(size),
+                    "{:#x}..={:#x} is accessible from userland",
+                    virt,
+                    end,
+                );
+            }
+
+            if hhdm.contains(&virt) {
+                assert_eq!(
+                    phys,
+                    virt - HHDM_OFFSET,
+                    "the direct map is inconsistent at {:#x}",
+                    virt,
+                );
+                assert!(
+                    flags.contains(PageFlags::WRITABLE | PageFlags::GLOBAL),
+                    "the direct map has the wrong flags at {:#x} ({:?})",
+                    virt,
+                    flags,
+                );
+                hhdm_covered += size;
+            } else if image.contains(&virt) {
+                assert_eq!(
+                    phys,
+                    kernel_start.get() + (virt - image.start),
+                    "the kernel image is mapped to the wrong memory at {:#x}",
+                    virt,
+                );
+                image_covered += size;
+            }
+        })
+    };
+
+    assert!(
+        hhdm_covered >= direct_map_size,
+        "the direct map only covers {:#x} bytes out of {:#x}",
+        hhdm_covered,
+        direct_map_size,
+    );
+    assert!(
+        image_covered >= kernel_size,
+        "the kernel image is only mapped up to {:#x} bytes out of {:#x}",
+        image_covered,
+        kernel_size,
+    );
+
+    log::trace!(
+        "The kernel address space is consistent ({} page tables).",
+        tables
+    );
+}
+
 /// The physical address of the L4 page table that contains the kernel address space.
-static mut L4_TABLE: MaybeUninit<usize> = MaybeUninit::uninit();
+static mut L4_TABLE: MaybeUninit<PhysAddr> = MaybeUninit::uninit();
 
 /// A "token" type that proves the global address space has been initialized.
+///
+/// # Shared Upper Half
+///
+/// The upper half of every address space is the upper half of the kernel address space. Rather
+/// than copying the whole upper half, an address space only copies the last 256 entries of the
+/// kernel L4 table (see [`share_with`](Self::share_with)), and thus references the same L3
+/// tables. Mappings created in the upper half of the kernel address space after a process has
+/// been created are visible to that process.
+///
+/// This only holds as long as the L4 entries of the upper half never change. They all reference
+/// an L3 table from the moment the kernel address space is created, and their flags are
+/// permissive enough that mapping kernel memory never needs to modify them. The only L4 entries
+/// that allow userland accesses are the ones that map the public data, and no other
+/// user-accessible memory may be mapped in the upper half.
 #[derive(Clone, Copy)]
 pub struct UpperHalfAddressSpaceTok(());
 
@@ -437,7 +1214,7 @@ impl UpperHalfAddressSpaceTok {
     ///
     /// This function is unsafe because it can only be called once.
     #[inline(always)]
-    pub unsafe fn init(l4_table: usize) -> Self {
+    pub unsafe fn init(l4_table: PhysAddr) -> Self {
         unsafe {
             L4_TABLE = MaybeUninit::new(l4_table);
             Self::unchecked()
@@ -446,7 +1223,284 @@ impl UpperHalfAddressSpaceTok {
 
     /// Returns the physical address of the L4 page table that contains the kernel address space.
     #[inline(always)]
-    pub fn get(self) -> usize {
+    pub fn get(self) -> PhysAddr {
         unsafe { L4_TABLE.assume_init() }
     }
+
+    /// Makes the provided L4 table share the upper half of the kernel address space, and leaves
+    /// its lower half empty.
+    ///
+    /// See the [type-level documentation](Self) for more information.
+    pub fn share_with(self, l4: &mut PageTable) {
+        // SAFETY:
+        //  The kernel address space has been initialized, and its L4 table is never modified.
+        let kernel = unsafe { &*self.get().hhdm_ptr::<PageTable>() };
+
+        l4.0[..256].fill(PageTableEntry::UNUSED);
+        l4.0[256..].copy_from_slice(&kernel.0[256..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An address that sets every bit of [`PageTableEntry::ADDRESS_MASK`] at least once across
+    /// the tests.
+    const ADDR: PhysAddr = PhysAddr::new(0x000F_EDCB_A987_6000);
+
+    /// The bits available to software, both below and above the address.
+    const SOFTWARE_BITS: u64 = 0x0E00 | 0x3FF0_0000_0000_0000;
+
+    /// Merges `b` into an entry whose flags are `a`, and returns the resulting flags.
+    fn merge(a: PageFlags, b: PageFlags) -> PageFlags {
+        let mut entry = PageTableEntry::new(ADDR, a);
+        assert_eq!(entry.fuse_flags(b), Ok(()));
+        assert_eq!(entry.addr(), ADDR);
+        entry.flags()
+    }
+
+    #[test]
+    fn new_masks_the_address() {
+        let entry = PageTableEntry::new(PhysAddr::new(0xFFF0_0000_0000_1FFF), PageFlags::PRESENT);
+
+        assert_eq!(entry.addr().get(), 0x1000);
+        assert_eq!(entry.flags().bits(), PageFlags::PRESENT.bits());
+        assert!(entry.is_present());
+        assert!(!entry.is_huge());
+        assert!(!entry.is_unused());
+        assert!(PageTableEntry::UNUSED.is_unused());
+    }
+
+    #[test]
+    fn or_flags() {
+        for flag in PageTableEntry::OR_FLAGS.iter() {
+            for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+                let a_flags = if a { flag } else { PageFlags::empty() };
+                let b_flags = if b { flag } else { PageFlags::empty() };
+
+                let merged = merge(a_flags, b_flags);
+                assert_eq!(merged.contains(flag), a || b, "{flag:?} ({a}, {b})");
+            }
+        }
+    }
+
+    #[test]
+    fn and_flags() {
+        for flag in PageTableEntry::AND_FLAGS.iter() {
+            for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+                let a_flags = if a { flag } else { PageFlags::empty() };
+                let b_flags = if b { flag } else { PageFlags::empty() };
+
+                let merged = merge(a_flags, b_flags);
+                assert_eq!(merged.contains(flag), a && b, "{flag:?} ({a}, {b})");
+            }
+        }
+    }
+
+    #[test]
+    fn or_and_flags_are_independent() {
+        let merged = merge(
+            PageFlags::PRESENT | PageFlags::NO_EXECUTE | PageFlags::GLOBAL,
+            PageFlags::WRITABLE | PageFlags::USER | PageFlags::NO_EXECUTE,
+        );
+
+        assert_eq!(
+            merged.bits(),
+            (PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER | PageFlags::NO_EXECUTE)
+                .bits(),
+        );
+    }
+
+    #[test]
+    fn preserved_bits() {
+        let original =
+            PageTableEntry::new(ADDR, PageFlags::PRESENT | PageFlags::ACCESSED).0 | SOFTWARE_BITS;
+
+        for flags in [
+            PageFlags::empty(),
+            PageTableEntry::OR_FLAGS,
+            PageTableEntry::AND_FLAGS,
+            PageTableEntry::OR_FLAGS | PageTableEntry::AND_FLAGS,
+        ] {
+            let mut entry = PageTableEntry(original);
+            assert_eq!(entry.fuse_flags(flags), Ok(()));
+
+            let preserved = PageTableEntry::PRESERVED_BITS;
+            assert_eq!(entry.0 & preserved, original & preserved, "{flags:?}");
+            assert_eq!(entry.addr(), ADDR);
+            assert!(entry.flags().contains(PageFlags::ACCESSED));
+        }
+    }
+
+    #[test]
+    fn data_loss() {
+        let original = PageTableEntry::new(ADDR, PageFlags::PRESENT | PageFlags::WRITABLE);
+
+        for (flags, lost) in [
+            (PageFlags::HUGE, PageFlags::HUGE.bits()),
+            (PageFlags::ACCESSED, PageFlags::ACCESSED.bits()),
+            (
+                PageFlags::from_bits_retain(0x1000 | PageFlags::USER.bits()),
+                0x1000,
+            ),
+            (PageFlags::from_bits_retain(SOFTWARE_BITS), SOFTWARE_BITS),
+        ] {
+            let mut entry = original;
+            assert_eq!(entry.fuse_flags(flags), Err(FlagMergeError::DataLoss(lost)));
+            assert_eq!(entry, original);
+        }
+    }
+
+    #[test]
+    fn huge_page() {
+        let original = PageTableEntry::new(ADDR, PageFlags::PRESENT | PageFlags::HUGE);
+
+        let mut entry = original;
+        assert_eq!(
+            entry.fuse_flags(PageFlags::WRITABLE),
+            Err(FlagMergeError::HugePage)
+        );
+        assert_eq!(entry, original);
+    }
+
+    /// A host-side address space, whose page tables are allocated on the heap.
+    ///
+    /// The heap is identity-mapped from the point of view of the paging functions: the direct
+    /// map is at offset 0, and the "physical" address of a page table is its address on the
+    /// host. The frames that pages are mapped to are never accessed.
+    struct TestAddressSpace {
+        l4: Box<PageTable>,
+        tables: Vec<Box<PageTable>>,
+    }
+
+    impl TestAddressSpace {
+        /// The first frame that pages are mapped to by [`map_range`](Self::map_range). It is
+        /// far from the virtual addresses used by the tests, so that a frame is never confused
+        /// with the page it backs.
+        const FIRST_FRAME: usize = 0x7_0000_0000;
+
+        fn new() -> Self {
+            Self {
+                l4: Box::new(PageTable([PageTableEntry::UNUSED; 512])),
+                tables: Vec::new(),
+            }
+        }
+
+        /// Returns the frame that [`map_range`](Self::map_range) maps `page` to.
+        fn frame_of(page: Page) -> Frame {
+            Frame::containing(PhysAddr::new(Self::FIRST_FRAME + page.start().get()))
+        }
+
+        /// Maps every page of the `start..start + length` range.
+        fn map_range(&mut self, start: usize, length: usize) {
+            let tables = &mut self.tables;
+            let mut alloc_page = || {
+                let table = Box::new(PageTable([PageTableEntry::UNUSED; 512]));
+                let addr = PhysAddr::new(&*table as *const PageTable as usize);
+                tables.push(table);
+                Ok(addr)
+            };
+
+            for page in Page::range_of(VirtAddr::new(start), length) {
+                let frame = Self::frame_of(page);
+                let flags = PageFlags::USER | PageFlags::WRITABLE;
+                let mapped =
+                    unsafe { map_4kib(&mut self.l4, 0, &mut alloc_page, page, frame, flags) };
+                assert!(mapped.is_ok(), "failed to map {:#x}", page.start());
+            }
+        }
+
+        /// Unmaps every page of the `start..start + length` range, and returns the frames that
+        /// were released.
+        fn unmap_range(&mut self, start: usize, length: usize) -> Vec<Frame> {
+            Page::range_of(VirtAddr::new(start), length)
+                .filter_map(|page| unsafe { unmap_4kib(&mut self.l4, 0, page) }.ok())
+                .collect()
+        }
+
+        /// Returns the frame that `address` is mapped to.
+        fn translate(&mut self, address: usize) -> Option<Frame> {
+            let page = Page::containing(VirtAddr::new(address));
+            unsafe { translate_4kib(&mut self.l4, 0, page) }
+        }
+
+        /// Returns the frames that [`map_range`](Self::map_range) maps the `start..start +
+        /// length` range to.
+        fn expected_frames(start: usize, length: usize) -> Vec<Frame> {
+            Page::range_of(VirtAddr::new(start), length)
+                .map(Self::frame_of)
+                .collect()
+        }
+    }
+
+    /// The start of the range mapped by the unmap tests, right below a 2 MiB boundary so that it
+    /// spans two L1 tables.
+    const BASE: usize = 0x40_0000 - 4 * PAGE_SIZE;
+
+    #[test]
+    fn unmap_returns_the_frame() {
+        let mut space = TestAddressSpace::new();
+        space.map_range(BASE, PAGE_SIZE);
+
+        let page = Page::containing(VirtAddr::new(BASE));
+        let frame = unsafe { unmap_4kib(&mut space.l4, 0, page) };
+
+        // The frame that backed the page is returned, not the page itself.
+        assert_eq!(frame, Ok(TestAddressSpace::frame_of(page)));
+        assert_ne!(frame.unwrap().start().get(), BASE);
+
+        assert_eq!(space.translate(BASE), None);
+        assert_eq!(unsafe { unmap_4kib(&mut space.l4, 0, page) }, Err(()));
+    }
+
+    #[test]
+    fn partial_unmap() {
+        let mut space = TestAddressSpace::new();
+        space.map_range(BASE, 4 * PAGE_SIZE);
+
+        let released = space.unmap_range(BASE + PAGE_SIZE, 2 * PAGE_SIZE);
+        assert_eq!(
+            released,
+            TestAddressSpace::expected_frames(BASE + PAGE_SIZE, 2 * PAGE_SIZE),
+        );
+
+        for (offset, mapped) in [(0, true), (1, false), (2, false), (3, true)] {
+            let address = BASE + offset * PAGE_SIZE;
+            let expected = mapped
+                .then(|| TestAddressSpace::frame_of(Page::containing(VirtAddr::new(address))));
+            assert_eq!(space.translate(address), expected, "{address:#x}");
+        }
+    }
+
+    #[test]
+    fn unmap_across_tables() {
+        let mut space = TestAddressSpace::new();
+        space.map_range(BASE, 8 * PAGE_SIZE);
+        assert_eq!(space.tables.len(), 4);
+
+        // The range spans the last pages of an L1 table and the first pages of the next one.
+        let released = space.unmap_range(BASE + 2 * PAGE_SIZE, 4 * PAGE_SIZE);
+        assert_eq!(
+            released,
+            TestAddressSpace::expected_frames(BASE + 2 * PAGE_SIZE, 4 * PAGE_SIZE),
+        );
+
+        assert!(space.translate(BASE + PAGE_SIZE).is_some());
+        assert!(space.translate(BASE + 6 * PAGE_SIZE).is_some());
+    }
+
+    #[test]
+    fn unmap_unmapped_pages() {
+        let mut space = TestAddressSpace::new();
+        space.map_range(BASE, 2 * PAGE_SIZE);
+
+        // Pages that are not mapped, including the ones whose page tables do not exist, are
+        // skipped.
+        let released = space.unmap_range(BASE - PAGE_SIZE, 0x40_0000);
+        assert_eq!(
+            released,
+            TestAddressSpace::expected_frames(BASE, 2 * PAGE_SIZE)
+        );
+    }
 }
@@ -0,0 +1,120 @@
+//! A driver for the legacy 8259 programmable interrupt controllers (PICs).
+//!
+//! The kernel routes external interrupts through the I/O APICs. The PICs are only initialized to
+//! get them out of the way: by default, they deliver their interrupts on vectors 8-15, where they
+//! would be mistaken for CPU exceptions.
+//!
+//! The PICs are remapped to [`PIC_VECTOR_BASE`] and all of their lines are masked. They may
+//! still raise spurious interrupts on their last line, which are handled by
+//! [`spurious_master`] and [`spurious_slave`].
+
+use crate::x86_64::cpu::idt::PIC_VECTOR_BASE;
+use crate::x86_64::instr::{inb, outb};
+use crate::x86_64::raw::StackFrame;
+
+/// The command port of the master PIC.
+const MASTER_COMMAND: u16 = 0x20;
+/// The data port of the master PIC.
+const MASTER_DATA: u16 = 0x21;
+/// The command port of the slave PIC.
+const SLAVE_COMMAND: u16 = 0xA0;
+/// The data port of the slave PIC.
+const SLAVE_DATA: u16 = 0xA1;
+
+/// Starts the initialization sequence, announcing that a fourth initialization word follows.
+const ICW1_INIT: u8 = 0x11;
+/// Informs the master PIC that a slave PIC is connected to its line 2.
+const ICW3_MASTER: u8 = 1 << 2;
+/// Informs the slave PIC of its cascade identity.
+const ICW3_SLAVE: u8 = 2;
+/// Puts the PICs in 8086 mode.
+const ICW4_8086: u8 = 0x01;
+
+/// The end-of-interrupt command.
+const EOI: u8 = 0x20;
+/// Requests the in-service register on the next read of the command port.
+const READ_ISR: u8 = 0x0B;
+
+/// The line on which spurious interrupts are reported.
+const SPURIOUS_LINE: u8 = 7;
+
+/// Writes to an unused port, giving the PICs time to process the previous command on old
+/// hardware.
+#[inline(always)]
+fn io_wait() {
+    // SAFETY:
+    //  Port 0x80 is used for POST codes. Writing to it has no side effects.
+    unsafe { outb(0x80, 0) };
+}
+
+/// Remaps the PICs to [`PIC_VECTOR_BASE`] and masks all of their lines.
+///
+/// # Safety
+///
+/// This function must only be called once, before interrupts are enabled.
+pub unsafe fn init() {
+    unsafe {
+        outb(MASTER_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(SLAVE_COMMAND, ICW1_INIT);
+        io_wait();
+
+        outb(MASTER_DATA, PIC_VECTOR_BASE as u8);
+        io_wait();
+        outb(SLAVE_DATA, PIC_VECTOR_BASE as u8 + 8);
+        io_wait();
+
+        outb(MASTER_DATA, ICW3_MASTER);
+        io_wait();
+        outb(SLAVE_DATA, ICW3_SLAVE);
+        io_wait();
+
+        outb(MASTER_DATA, ICW4_8086);
+        io_wait();
+        outb(SLAVE_DATA, ICW4_8086);
+        io_wait();
+
+        outb(MASTER_DATA, 0xFF);
+        outb(SLAVE_DATA, 0xFF);
+    }
+}
+
+/// Returns whether the PIC with the provided command port is actually servicing an interrupt on
+/// its spurious line.
+///
+/// # Safety
+///
+/// `command` must be the command port of one of the PICs.
+unsafe fn is_in_service(command: u16) -> bool {
+    unsafe {
+        outb(command, READ_ISR);
+        inb(command) & (1 << SPURIOUS_LINE) != 0
+    }
+}
+
+/// The handler of the interrupts raised on the last line of the master PIC.
+///
+/// Because all the lines are masked, those are always spurious and must not be acknowledged.
+pub extern "x86-interrupt" fn spurious_master(_: StackFrame) {
+    // SAFETY:
+    //  We are using the command port of the master PIC.
+    if unsafe { is_in_service(MASTER_COMMAND) } {
+        unsafe { outb(MASTER_COMMAND, EOI) };
+    }
+}
+
+/// The handler of the interrupts raised on the last line of the slave PIC.
+///
+/// The master PIC is not aware that the interrupt is spurious, and it must still be
+/// acknowledged.
+pub extern "x86-interrupt" fn spurious_slave(_: StackFrame) {
+    // SAFETY:
+    //  We are using the command ports of the PICs.
+    unsafe {
+        if is_in_service(SLAVE_COMMAND) {
+            outb(SLAVE_COMMAND, EOI);
+        }
+
+        outb(MASTER_COMMAND, EOI);
+    }
+}
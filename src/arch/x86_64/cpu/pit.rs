@@ -0,0 +1,97 @@
+//! A driver for the 8254 programmable interval timer (PIT).
+//!
+//! The PIT runs at a fixed, well-known frequency. The kernel uses it in two ways:
+//!
+//! - Channel 2, whose output can be polled, is used as a reference to measure the frequency of
+//!   other timers. See [`busy_wait`].
+//!
+//! - Channel 0, which is connected to ISA IRQ 0, is used as the source of the scheduler tick
+//!   when the local APIC timer cannot be trusted. See [`start_periodic`].
+
+use crate::x86_64::instr::{inb, outb};
+
+/// The frequency of the oscillator driving the PIT, in hertz.
+pub const FREQUENCY: u32 = 1_193_182;
+
+/// The data port of channel 0.
+const CHANNEL0_DATA: u16 = 0x40;
+/// The data port of channel 2.
+const CHANNEL2_DATA: u16 = 0x42;
+/// The mode/command register.
+const COMMAND: u16 = 0x43;
+/// The port that controls the gate of channel 2 and exposes its output.
+const CHANNEL2_GATE: u16 = 0x61;
+
+/// Selects channel 0, with the reload value written low byte first.
+const SELECT_CHANNEL0: u8 = 0x30;
+/// Selects channel 2, with the reload value written low byte first.
+const SELECT_CHANNEL2: u8 = 0xB0;
+/// Mode 0: the output goes high once the counter reaches zero.
+const MODE_INTERRUPT_ON_TERMINAL_COUNT: u8 = 0b000 << 1;
+/// Mode 2: the output pulses every time the counter reaches zero, and the counter is reloaded.
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+
+/// Enables the gate of channel 2.
+const GATE_ENABLE: u8 = 1 << 0;
+/// Connects channel 2 to the PC speaker.
+const GATE_SPEAKER: u8 = 1 << 1;
+/// Set when the output of channel 2 is high.
+const GATE_OUTPUT: u8 = 1 << 5;
+
+/// The longest duration that [`busy_wait`] can measure, in microseconds.
+pub const MAX_BUSY_WAIT_US: u32 = (u16::MAX as u64 * 1_000_000 / FREQUENCY as u64) as u32;
+
+/// Returns the reload value that makes the PIT count for `duration_us` microseconds.
+#[inline]
+fn reload_value(duration_us: u32) -> u16 {
+    let count = FREQUENCY as u64 * duration_us as u64 / 1_000_000;
+    count.clamp(1, u16::MAX as u64) as u16
+}
+
+/// Spins until `duration_us` microseconds have elapsed, as measured by channel 2 of the PIT.
+///
+/// Durations longer than [`MAX_BUSY_WAIT_US`] are truncated.
+///
+/// # Safety
+///
+/// Channel 2 of the PIT must not be used concurrently.
+pub unsafe fn busy_wait(duration_us: u32) {
+    let reload = reload_value(duration_us);
+
+    unsafe {
+        // Disable the speaker and the gate while the channel is being programmed.
+        let gate = inb(CHANNEL2_GATE) & !(GATE_SPEAKER | GATE_ENABLE);
+        outb(CHANNEL2_GATE, gate);
+
+        outb(COMMAND, SELECT_CHANNEL2 | MODE_INTERRUPT_ON_TERMINAL_COUNT);
+        outb(CHANNEL2_DATA, reload as u8);
+        outb(CHANNEL2_DATA, (reload >> 8) as u8);
+
+        // Raising the gate starts the countdown.
+        outb(CHANNEL2_GATE, gate | GATE_ENABLE);
+
+        while inb(CHANNEL2_GATE) & GATE_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+
+        outb(CHANNEL2_GATE, gate);
+    }
+}
+
+/// Programs channel 0 of the PIT to raise ISA IRQ 0 at the provided frequency.
+///
+/// The actual frequency may be slightly different, as the PIT can only divide its own frequency
+/// by an integer.
+///
+/// # Safety
+///
+/// Channel 0 of the PIT must not be used concurrently.
+pub unsafe fn start_periodic(frequency: u32) {
+    let divisor = (FREQUENCY / frequency.max(1)).clamp(1, u16::MAX as u32) as u16;
+
+    unsafe {
+        outb(COMMAND, SELECT_CHANNEL0 | MODE_RATE_GENERATOR);
+        outb(CHANNEL0_DATA, divisor as u8);
+        outb(CHANNEL0_DATA, (divisor >> 8) as u8);
+    }
+}
@@ -0,0 +1,385 @@
+//! Kernel page table isolation.
+//!
+//! CPUs affected by rogue data cache loads (Meltdown) let userspace speculatively read any memory
+//! mapped in the current address space, including the pages that are only accessible to the
+//! kernel. Page table isolation defends against this by giving each process a second L4 table,
+//! loaded while the process runs userspace code. That table maps the lower half of the address
+//! space of the process, and only the parts of the kernel needed to enter it:
+//!
+//! - The `.entry.text` section, which holds the entry points of the kernel and their trampolines.
+//! - The `.entry.data` section, which holds the GDT, the IDT and the TSS, the per-CPU blocks,
+//!   along with the variables used by the entry points.
+//! - The topmost page of the kernel stack, where the CPU pushes the interrupted context.
+//! - The stack of the double fault handler.
+//!
+//! # Entering the Kernel
+//!
+//! Every entry of the IDT points to a trampoline that loads the complete address space of the
+//! current process, [`KERNEL_CR3`], before jumping to the actual handler. All gates become
+//! interrupt gates, so that no interrupt is taken before the switch. The system call entry point
+//! switches address spaces on its own.
+//!
+//! # Returning to Userspace
+//!
+//! The entry points that return to userspace call [`exit_to_user`] while the registers of the
+//! process are saved on the kernel stack, and load the returned table right before restoring
+//! them. Page tables below the L4 table are shared between both tables of a process, so only the
+//! entries of the L4 table may be out of date.
+//!
+//! The handlers written with the `x86-interrupt` calling convention return with `iretq` on
+//! their own. When they interrupted userspace, their trampoline pushes a second interrupt frame
+//! so that they return to the trampoline instead, which then switches address spaces.
+//!
+//! # Limitations
+//!
+//! - Page table isolation is only enabled with the `pti=on` command line option, as it is
+//!   expensive. It cannot be used along with shadow stacks, which are only supported by CPUs
+//!   that are not affected by Meltdown anyway.
+//! - Global pages are disabled, and every switch between the two tables flushes the TLB.
+//! - The topmost page of the kernel stack remains readable while userspace runs.
+
+use core::arch::asm;
+
+use crate::log;
+use crate::x86_64::acpi;
+use crate::x86_64::instr;
+use crate::x86_64::irq;
+use crate::x86_64::kernel_stack::KERNEL_STACK_TOP;
+use crate::x86_64::keyboard;
+use crate::x86_64::mem::{
+    BootAllocator, Frame, MemoryTracker, OutOfMemory, Page, PhysAddr, VirtAddr, HHDM_OFFSET,
+    PAGE_SIZE,
+};
+use crate::x86_64::raw::{Cr4, PageFlags};
+
+use super::gdt::{self, KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR};
+use super::paging::{self, PageTable, PageTableEntry, UpperHalfAddressSpaceTok};
+use super::{apic, exceptions, idt, pic};
+
+/// Whether page table isolation is enabled.
+///
+/// This is read by the entry points of the kernel, and only modified during boot.
+#[link_section = ".entry.data"]
+pub static mut ENABLED: bool = false;
+
+/// The physical address of the complete address space of the current process, which the entry
+/// points load when the CPU enters the kernel.
+#[link_section = ".entry.data"]
+pub static mut KERNEL_CR3: u64 = 0;
+
+/// The physical address of the table loaded while the current process runs userspace code.
+#[link_section = ".entry.data"]
+pub static mut USER_CR3: u64 = 0;
+
+/// The physical address of an L4 table whose upper half is copied to the user tables of the
+/// processes.
+static mut TEMPLATE: PhysAddr = PhysAddr::ZERO;
+
+/// Returns whether page table isolation is enabled.
+#[inline(always)]
+pub fn enabled() -> bool {
+    // SAFETY:
+    //  This is only modified during boot.
+    unsafe { ENABLED }
+}
+
+/// Defines a trampoline that loads the complete address space of the current process, and jumps
+/// to `$target`.
+///
+/// This is used for the entry points that never return to the interrupted code, and for the ones
+/// that switch back to the user table themselves.
+macro_rules! trampoline {
+    ($name:ident => $target:path) => {
+        #[naked]
+        #[link_section = ".entry.text"]
+        unsafe extern "C" fn $name() {
+            unsafe {
+                asm!(
+                    r#"
+                    push rax
+                    mov rax, [rip + {kernel_cr3}]
+                    mov cr3, rax
+                    pop rax
+                    jmp {target}
+                    "#,
+                    kernel_cr3 = sym KERNEL_CR3,
+                    target = sym $target,
+                    options(noreturn),
+                );
+            }
+        }
+    };
+}
+
+/// Defines a trampoline for an `x86-interrupt` handler that returns to the interrupted code, and
+/// whose vector does not push an error code.
+///
+/// When userspace was interrupted, the trampoline loads the complete address space of the current
+/// process, and pushes an interrupt frame that makes the handler return to the trampoline. The
+/// user table is then loaded again before returning to userspace.
+macro_rules! returning_trampoline {
+    ($name:ident => $target:path) => {
+        #[naked]
+        #[link_section = ".entry.text"]
+        unsafe extern "C" fn $name() {
+            unsafe {
+                asm!(
+                    r#"
+                    test byte ptr [rsp + 8], 3
+                    jz {target}
+
+                    push rax
+                    mov rax, [rip + {kernel_cr3}]
+                    mov cr3, rax
+
+                    lea rax, [rsp + 8]
+                    push {kernel_data_selector}
+                    push rax
+                    pushfq
+                    push {kernel_code_selector}
+                    lea rax, [rip + 2f]
+                    push rax
+                    mov rax, [rsp + 40]
+                    jmp {target}
+
+                2:
+                    push rax
+                    mov rax, [rip + {user_cr3}]
+                    mov cr3, rax
+                    pop rax
+                    iretq
+                    "#,
+                    kernel_cr3 = sym KERNEL_CR3,
+                    user_cr3 = sym USER_CR3,
+                    kernel_data_selector = const KERNEL_DATA_SELECTOR,
+                    kernel_code_selector = const KERNEL_CODE_SELECTOR,
+                    target = sym $target,
+                    options(noreturn),
+                );
+            }
+        }
+    };
+}
+
+trampoline!(division_error => exceptions::division_error);
+trampoline!(debug => exceptions::debug);
+trampoline!(non_maskable_interrupt => exceptions::non_maskable_interrupt);
+returning_trampoline!(breakpoint => exceptions::breakpoint);
+trampoline!(overflow => exceptions::overflow);
+trampoline!(bound_range_exceeded => exceptions::bound_range_exceeded);
+trampoline!(invalid_opcode => exceptions::invalid_opcode);
+trampoline!(device_not_available => exceptions::device_not_available);
+trampoline!(double_fault => exceptions::double_fault);
+trampoline!(invalid_tss => exceptions::invalid_tss);
+trampoline!(segment_not_present => exceptions::segment_not_present);
+trampoline!(stack_segment_fault => exceptions::stack_segment_fault);
+trampoline!(general_protection_fault => exceptions::general_protection_fault);
+trampoline!(page_fault => exceptions::page_fault);
+trampoline!(x87_floating_point => exceptions::x87_floating_point);
+trampoline!(alignment_check => exceptions::alignment_check);
+trampoline!(machine_check => exceptions::machine_check);
+trampoline!(simd_floating_point => exceptions::simd_floating_point);
+trampoline!(virtualization => exceptions::virtualization);
+trampoline!(control_protection => exceptions::control_protection);
+trampoline!(hypervisor_injection => exceptions::hypervisor_injection);
+trampoline!(vmm_communication => exceptions::vmm_communication);
+trampoline!(security => exceptions::security);
+returning_trampoline!(pic_spurious_master => pic::spurious_master);
+returning_trampoline!(pic_spurious_slave => pic::spurious_slave);
+returning_trampoline!(lapic_spurious_interrupt => apic::spurious_interrupt);
+trampoline!(timer => apic::timer);
+returning_trampoline!(sci_interrupt => acpi::sci_interrupt);
+returning_trampoline!(keyboard_interrupt => keyboard::interrupt);
+returning_trampoline!(irq_line_0 => irq::line_0);
+returning_trampoline!(irq_line_1 => irq::line_1);
+returning_trampoline!(irq_line_2 => irq::line_2);
+returning_trampoline!(irq_line_3 => irq::line_3);
+returning_trampoline!(irq_line_4 => irq::line_4);
+returning_trampoline!(irq_line_5 => irq::line_5);
+returning_trampoline!(irq_line_6 => irq::line_6);
+returning_trampoline!(irq_line_7 => irq::line_7);
+
+/// The trampolines of the entries of the IDT.
+const TRAMPOLINES: [(usize, unsafe extern "C" fn()); 38] = [
+    (idt::DIVISION_ERROR, division_error),
+    (idt::DEBUG, debug),
+    (idt::NON_MASKABLE_INTERRUPT, non_maskable_interrupt),
+    (idt::BREAKPOINT, breakpoint),
+    (idt::OVERFLOW, overflow),
+    (idt::BOUND_RANGE_EXCEEDED, bound_range_exceeded),
+    (idt::INVALID_OPCODE, invalid_opcode),
+    (idt::DEVICE_NOT_AVAILABLE, device_not_available),
+    (idt::DOUBLE_FAULT, double_fault),
+    (idt::INVALID_TSS, invalid_tss),
+    (idt::SEGMENT_NOT_PRESENT, segment_not_present),
+    (idt::STACK_SEGMENT_FAULT, stack_segment_fault),
+    (idt::GENERAL_PROTECTION_FAULT, general_protection_fault),
+    (idt::PAGE_FAULT, page_fault),
+    (idt::X87_FLOATING_POINT, x87_floating_point),
+    (idt::ALIGNMENT_CHECK, alignment_check),
+    (idt::MACHINE_CHECK, machine_check),
+    (idt::SIMD_FLOATING_POINT, simd_floating_point),
+    (idt::VIRTUALIZATION, virtualization),
+    (idt::CONTROL_PROTECTION, control_protection),
+    (idt::HYPERVISOR_INJECTION, hypervisor_injection),
+    (idt::VMM_COMMUNICATION, vmm_communication),
+    (idt::SECURITY, security),
+    (idt::PIC_MASTER_SPURIOUS_VECTOR, pic_spurious_master),
+    (idt::PIC_SLAVE_SPURIOUS_VECTOR, pic_spurious_slave),
+    (idt::LAPIC_SPURIOUS_VECTOR, lapic_spurious_interrupt),
+    (idt::LAPIC_TIMER_VECTOR, timer),
+    (idt::ACPI_SCI_VECTOR, sci_interrupt),
+    (idt::PIT_TIMER_VECTOR, timer),
+    (idt::KEYBOARD_VECTOR, keyboard_interrupt),
+    (idt::USER_IRQ_VECTOR_BASE, irq_line_0),
+    (idt::USER_IRQ_VECTOR_BASE + 1, irq_line_1),
+    (idt::USER_IRQ_VECTOR_BASE + 2, irq_line_2),
+    (idt::USER_IRQ_VECTOR_BASE + 3, irq_line_3),
+    (idt::USER_IRQ_VECTOR_BASE + 4, irq_line_4),
+    (idt::USER_IRQ_VECTOR_BASE + 5, irq_line_5),
+    (idt::USER_IRQ_VECTOR_BASE + 6, irq_line_6),
+    (idt::USER_IRQ_VECTOR_BASE + 7, irq_line_7),
+];
+
+/// Builds the upper half of the user tables, and redirects the entries of the IDT to their
+/// trampolines.
+///
+/// This does nothing if page table isolation is disabled.
+///
+/// # Safety
+///
+/// This function must be called once, after the GDT and the IDT have been initialized, while the
+/// kernel address space is loaded.
+pub unsafe fn init(
+    boot_allocator: &mut BootAllocator,
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<(), OutOfMemory> {
+    if !enabled() {
+        return Ok(());
+    }
+
+    let kernel_l4 = unsafe { &mut *upper_half.get().hhdm_ptr::<PageTable>() };
+
+    let template = boot_allocator.allocate_page_table()?;
+    unsafe { core::ptr::write_bytes(template.hhdm_ptr::<PageTable>(), 0x00, 1) };
+    let template_l4 = unsafe { &mut *template.hhdm_ptr::<PageTable>() };
+
+    let regions = [
+        (crate::x86_64::entry_text(), PageFlags::empty()),
+        (
+            crate::x86_64::entry_data(),
+            PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
+        ),
+        (
+            KERNEL_STACK_TOP - PAGE_SIZE..KERNEL_STACK_TOP,
+            PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
+        ),
+        (
+            gdt::double_fault_stack(),
+            PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
+        ),
+    ];
+
+    for (range, flags) in regions {
+        for page in Page::range_of(VirtAddr::new(range.start), range.end - range.start) {
+            let phys = unsafe { paging::translate(kernel_l4, HHDM_OFFSET, page.start()) }
+                .expect("the entry points of the kernel are not mapped");
+
+            unsafe {
+                paging::map_4kib(
+                    template_l4,
+                    HHDM_OFFSET,
+                    &mut || boot_allocator.allocate_page_table(),
+                    page,
+                    Frame::containing(phys),
+                    flags,
+                )?;
+            }
+        }
+    }
+
+    // SAFETY:
+    //  This function is only called once, during boot. Interrupts are still disabled.
+    unsafe {
+        TEMPLATE = template;
+        KERNEL_CR3 = upper_half.get().get() as u64;
+
+        // Global pages would keep the translations of the kernel in the TLB while userspace runs.
+        instr::set_cr4(instr::cr4() & !Cr4::PAGE_GLOBAL.bits());
+
+        for (vector, trampoline) in TRAMPOLINES {
+            idt::redirect(vector, trampoline as usize);
+        }
+    }
+
+    log::trace!("Kernel page table isolation is enabled.");
+
+    Ok(())
+}
+
+/// Creates the table loaded while a process runs userspace code.
+///
+/// The lower half of the table is filled by [`exit_to_user`].
+///
+/// # Returns
+///
+/// The physical address of the table, or `None` if page table isolation is disabled.
+pub fn create_user_table(
+    memory_tracker: &mut MemoryTracker,
+) -> Result<Option<PhysAddr>, OutOfMemory> {
+    if !enabled() {
+        return Ok(None);
+    }
+
+    let table = memory_tracker.allocate_page_table()?;
+
+    // SAFETY:
+    //  The template has been built by `init`, and the table has just been allocated.
+    unsafe {
+        let template = &*TEMPLATE.hhdm_ptr::<PageTable>();
+        let table = &mut *table.hhdm_ptr::<PageTable>();
+
+        table.0[..256].fill(PageTableEntry::UNUSED);
+        table.0[256..].copy_from_slice(&template.0[256..]);
+    }
+
+    Ok(Some(table))
+}
+
+/// Records the tables of the process that is about to run.
+///
+/// `user_table` is the table created by [`create_user_table`] for the process.
+///
+/// # Safety
+///
+/// `address_space` must be the currently loaded address space.
+#[inline]
+pub unsafe fn set_current(address_space: PhysAddr, user_table: Option<PhysAddr>) {
+    if !enabled() {
+        return;
+    }
+
+    unsafe {
+        KERNEL_CR3 = address_space.get() as u64;
+        USER_CR3 = user_table.unwrap_or(address_space).get() as u64;
+    }
+}
+
+/// Copies the lower half of the address space of the current process to its user table, and
+/// returns the physical address of that table.
+///
+/// This is called by the entry points that return to userspace, right before they load the
+/// returned table.
+pub extern "C" fn exit_to_user() -> u64 {
+    // SAFETY:
+    //  Both tables are owned by the current process, which is never modified concurrently.
+    unsafe {
+        let kernel = &*((KERNEL_CR3 as usize + HHDM_OFFSET) as *const PageTable);
+        let user = &mut *((USER_CR3 as usize + HHDM_OFFSET) as *mut PageTable);
+
+        // The lower half is mapped by the first 256 entries of the L4 table.
+        user.0[..256].copy_from_slice(&kernel.0[..256]);
+
+        USER_CR3
+    }
+}
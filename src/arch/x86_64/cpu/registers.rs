@@ -0,0 +1,141 @@
+//! Snapshots of the state of the current CPU that is not saved in exception frames.
+//!
+//! Crash reports include a [`RegisterSnapshot`] so that the control registers, the segment
+//! selectors and the segment bases are always reported the same way, whatever the path that
+//! led to the crash.
+
+use core::arch::asm;
+use core::fmt;
+
+use crate::log;
+use crate::x86_64::instr;
+use crate::x86_64::raw;
+
+/// The segment selectors loaded in the current CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub cs: u16,
+    pub ss: u16,
+    pub ds: u16,
+    pub es: u16,
+    pub fs: u16,
+    pub gs: u16,
+}
+
+impl Selectors {
+    /// Reads the segment selectors of the current CPU.
+    pub fn capture() -> Self {
+        let (cs, ss, ds, es, fs, gs): (u16, u16, u16, u16, u16, u16);
+
+        // SAFETY:
+        //  Reading segment registers has no side effect.
+        unsafe {
+            asm!(
+                "mov {0:x}, cs",
+                "mov {1:x}, ss",
+                "mov {2:x}, ds",
+                "mov {3:x}, es",
+                "mov {4:x}, fs",
+                "mov {5:x}, gs",
+                out(reg) cs,
+                out(reg) ss,
+                out(reg) ds,
+                out(reg) es,
+                out(reg) fs,
+                out(reg) gs,
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+
+        Self {
+            cs,
+            ss,
+            ds,
+            es,
+            fs,
+            gs,
+        }
+    }
+}
+
+/// The state of the current CPU at the time [`RegisterSnapshot::capture`] was called.
+///
+/// It is displayed over several lines, each of them indented by two spaces.
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterSnapshot {
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub rflags: u64,
+    pub efer: u64,
+    pub fs_base: u64,
+    pub gs_base: u64,
+    pub kernel_gs_base: u64,
+    pub selectors: Selectors,
+}
+
+impl RegisterSnapshot {
+    /// Captures the state of the current CPU.
+    pub fn capture() -> Self {
+        let rflags: u64;
+
+        // SAFETY:
+        //  Reading the flags has no side effect.
+        unsafe {
+            asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+        }
+
+        // SAFETY:
+        //  Those model-specific registers are architectural on every x86_64 CPU, and reading
+        //  them has no side effect.
+        let (efer, fs_base, gs_base, kernel_gs_base) = unsafe {
+            (
+                instr::rdmsr(raw::IA32_EFER),
+                instr::rdmsr(raw::IA32_FS_BASE),
+                instr::rdmsr(raw::IA32_GS_BASE),
+                instr::rdmsr(raw::IA32_KERNEL_GS_BASE),
+            )
+        };
+
+        Self {
+            cr0: instr::cr0(),
+            cr2: instr::cr2(),
+            cr3: instr::cr3(),
+            cr4: instr::cr4(),
+            rflags,
+            efer,
+            fs_base,
+            gs_base,
+            kernel_gs_base,
+            selectors: Selectors::capture(),
+        }
+    }
+}
+
+impl fmt::Display for RegisterSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "  CR0 = {:#018x}  CR2 = {:#018x}  CR3 = {:#018x}  CR4 = {:#018x}",
+            self.cr0, self.cr2, self.cr3, self.cr4,
+        )?;
+        writeln!(
+            f,
+            "  EFER = {:#x}  RFLAGS = {:#x}  FS_BASE = {:#x}  GS_BASE = {:#x}  KERNEL_GS_BASE = {:#x}",
+            self.efer, self.rflags, self.fs_base, self.gs_base, self.kernel_gs_base,
+        )?;
+
+        let s = &self.selectors;
+        write!(
+            f,
+            "  CS = {:#06x}  SS = {:#06x}  DS = {:#06x}  ES = {:#06x}  FS = {:#06x}  GS = {:#06x}",
+            s.cs, s.ss, s.ds, s.es, s.fs, s.gs,
+        )
+    }
+}
+
+/// Captures the state of the current CPU and writes it to the log.
+pub fn print() {
+    log::error!("{}", RegisterSnapshot::capture());
+}
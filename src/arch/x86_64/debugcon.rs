@@ -0,0 +1,108 @@
+//! The debug console of QEMU and Bochs.
+//!
+//! Both emulators expose an I/O port, `0xE9`, whose bytes are written directly to the output of
+//! the emulator. Unlike the emulated UART, there is no status register to poll between bytes,
+//! which makes it much faster. It is selected as the sink of the kernel log with the `log=e9`
+//! option of the kernel command line.
+//!
+//! On real hardware, the port is usually not connected to anything.
+//!
+//! # Crash Records
+//!
+//! When the kernel panics under an emulator, [`report_panic`] writes a crash record to the debug
+//! console and emits the magic breakpoint of Bochs, so that automated harnesses can capture the
+//! state of the machine right at the point of failure. The record is independent of the sink
+//! of the log, and is easy to find in the output of the emulator:
+//!
+//! ```text
+//! --- FABRIC CRASH ---
+//! message: <the panic message>
+//! location: <file>:<line>:<column>
+//! uptime_us: <microseconds since boot>
+//! build: <the build ID of the kernel>
+//! --- END CRASH ---
+//! ```
+
+use core::fmt;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use super::instr::{self, inb, outb};
+use crate::log::LogFn;
+
+/// The I/O port of the debug console.
+pub const PORT: u16 = 0xE9;
+
+/// Returns whether the debug console is present.
+///
+/// When it is, reading from its port returns the number of the port itself.
+pub fn is_present() -> bool {
+    // SAFETY:
+    //  Reading from the port has no side effects, whether the debug console is present or not.
+    unsafe { inb(PORT) == PORT as u8 }
+}
+
+/// Writes bytes to the debug console.
+#[derive(Debug, Clone, Copy)]
+pub struct Debugcon;
+
+impl Debugcon {
+    /// Writes a byte to the debug console.
+    #[inline(always)]
+    pub fn write_byte(self, byte: u8) {
+        // SAFETY:
+        //  Writing to the port has no side effects besides the output of the emulator.
+        unsafe { outb(PORT, byte) };
+    }
+
+    /// Writes some bytes to the debug console.
+    pub fn write_bytes(self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    /// Returns a [`LogFn`] that writes to the debug console.
+    pub fn log_fn(self) -> LogFn {
+        |lvl, module, msg| super::serial::write_record(&mut Debugcon, lvl, module, msg)
+    }
+}
+
+impl Write for Debugcon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Reports a kernel panic to the emulator, if the kernel is running under one.
+///
+/// See the [module-level documentation](self) for more information.
+pub fn report_panic(info: &PanicInfo) {
+    if !is_present() {
+        return;
+    }
+
+    let mut out = Debugcon;
+
+    let _ = out.write_str("\n--- FABRIC CRASH ---\n");
+    let _ = match info.message() {
+        Some(msg) => writeln!(out, "message: {msg}"),
+        None => out.write_str("message: <no message>\n"),
+    };
+    let _ = match info.location() {
+        Some(loc) => writeln!(
+            out,
+            "location: {}:{}:{}",
+            loc.file(),
+            loc.line(),
+            loc.column()
+        ),
+        None => out.write_str("location: <no location>\n"),
+    };
+    let _ = writeln!(out, "uptime_us: {}", super::timer::uptime_us());
+    let _ = writeln!(out, "build: {}", super::build_id::BUILD_ID);
+    let _ = out.write_str("--- END CRASH ---\n");
+
+    instr::magic_breakpoint();
+}
@@ -0,0 +1,116 @@
+//! A boot mode that removes the sources of nondeterminism of the kernel, for testing.
+//!
+//! Integration tests compare the serial output of the kernel against golden files. Some parts of
+//! that output change from one boot to the next even when nothing else does. The `deterministic`
+//! option of the kernel command line removes them:
+//!
+//! - The layout of processes is randomized from a fixed seed instead of **RDRAND** and the
+//!   time-stamp counter (see [`aslr`](super::aslr)). The seed can be chosen with
+//!   `deterministic=<seed>`.
+//!
+//! - The application processors are started one after the other, each one being waited for
+//!   until it is online (see [`smp`](super::smp)).
+//!
+//! - The frequency of the TSC is fixed rather than measured, and the PIT is the default tick
+//!   source (see [`timer`](super::timer)).
+//!
+//! - Log records are written with interrupts disabled, so that a record logged by an interrupt
+//!   handler never cuts another one in half. Unless the `log.prefix` option is passed, records
+//!   are prefixed with the CPU that emitted them, but not with a timestamp.
+//!
+//! The mode is reported to processes through [`BootFlags::DETERMINISTIC`].
+//!
+//! [`BootFlags::DETERMINISTIC`]: fabric_sys::x86_64::public::BootFlags::DETERMINISTIC
+
+use core::fmt::Arguments;
+use core::sync::atomic::Ordering::Relaxed;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64};
+
+use crate::log::{self, Level};
+use crate::utility::Cmdline;
+
+use super::config::parse_usize;
+use super::{instr, timer};
+
+/// The seed used when the `deterministic` option does not provide one.
+pub const DEFAULT_SEED: u64 = 0x0FAB_21C0_0000_0001;
+
+/// Whether the deterministic boot mode is enabled.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The seed of the random numbers generated in the deterministic boot mode.
+static SEED: AtomicU64 = AtomicU64::new(DEFAULT_SEED);
+
+/// The log function that [`ordered_log`] forwards records to.
+static INNER_LOG_FN: AtomicPtr<()> = AtomicPtr::new(log::no_op as *mut ());
+
+/// Reads the `deterministic` option of the kernel command line, and enables the mode if it is
+/// present.
+///
+/// # Safety
+///
+/// This function must only be called once during boot, after the log sink, its prefix and the
+/// monotonic clock have been initialized, and before the options that depend on the mode are
+/// read.
+pub unsafe fn init(cmdline: Cmdline) {
+    let seed = match cmdline.get(b"deterministic") {
+        None => return,
+        Some(b"") => DEFAULT_SEED,
+        Some(value) => match core::str::from_utf8(value).ok().and_then(parse_usize) {
+            Some(seed) => seed as u64,
+            None => {
+                log::warn!(
+                    "Invalid `deterministic` seed: `{}`.",
+                    core::str::from_utf8(value).unwrap_or("<invalid UTF-8>")
+                );
+                DEFAULT_SEED
+            }
+        },
+    };
+
+    SEED.store(seed, Relaxed);
+    ENABLED.store(true, Relaxed);
+
+    if cmdline.get(b"log.prefix").is_none() {
+        log::set_prefix(log::Prefix::CPU);
+    }
+
+    INNER_LOG_FN.store(log::get_global_log_fn() as *mut (), Relaxed);
+    log::set_global_log_fn(ordered_log);
+
+    // SAFETY:
+    //  The caller guarantees that the clock has been initialized, and that this function is
+    //  called during boot.
+    unsafe { timer::fix_clock() };
+
+    log::info!("Deterministic boot mode enabled (seed = {:#x}).", seed);
+}
+
+/// Returns whether the deterministic boot mode is enabled.
+#[inline]
+pub fn enabled() -> bool {
+    ENABLED.load(Relaxed)
+}
+
+/// Returns the seed of the random numbers generated in the deterministic boot mode.
+#[inline]
+pub fn seed() -> u64 {
+    SEED.load(Relaxed)
+}
+
+/// Writes a log record to the sink that was selected before the mode was enabled, with
+/// interrupts disabled.
+fn ordered_log(lvl: Level, module: &str, msg: Arguments) {
+    // SAFETY:
+    //  `INNER_LOG_FN` always points to a function pointer of type `LogFn`.
+    let inner: log::LogFn = unsafe { core::mem::transmute(INNER_LOG_FN.load(Relaxed)) };
+
+    if !instr::interrupts_enabled() {
+        inner(lvl, module, msg);
+        return;
+    }
+
+    instr::cli();
+    inner(lvl, module, msg);
+    instr::sti();
+}
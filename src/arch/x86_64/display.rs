@@ -0,0 +1,219 @@
+//! Rectangles of framebuffers delegated by their owner to other processes.
+//!
+//! A display server that owns a framebuffer may create regions of it: rectangles that it grants
+//! to a single client process. The client maps the memory of its region directly in its address
+//! space, and draws into it without the server having to copy its pixels.
+//!
+//! # Granularity
+//!
+//! Memory is mapped with the granularity of a page, and the pages of a framebuffer hold whole
+//! rows of pixels. When a region is created, the kernel ensures that the memory of its rows
+//! starts and ends on page boundaries: the pages mapped by the client never cover the rows above
+//! or below the rectangle.
+//!
+//! Within those rows, the client may access every column. The horizontal bounds of a region are
+//! only advisory.
+//!
+//! # Lifetime
+//!
+//! A region is destroyed, and unmapped from the address space of its grantee, when:
+//!
+//! - Its owner destroys it.
+//! - Its owner releases the framebuffer.
+//! - Its owner or its grantee exits.
+//!
+//! # Synchronization
+//!
+//! Just like the process table, the region table is only accessed by system call handlers, and
+//! never concurrently.
+
+use core::sync::atomic::Ordering::Acquire;
+
+use fabric_sys::x86_64::public::{Framebuffer, PublicData};
+use fabric_sys::ProcessId;
+
+use super::mem::{PhysAddr, PAGE_SIZE};
+use super::process::{self, Backing};
+
+/// The maximum number of regions that may exist at the same time.
+pub const MAX_REGIONS: usize = 32;
+
+/// A rectangle of a framebuffer, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// An error that might occur when creating a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateError {
+    /// The framebuffer does not exist.
+    InvalidFramebuffer,
+    /// The framebuffer is not owned by the caller.
+    NotOwned,
+    /// The rectangle is empty, exceeds the framebuffer, or its rows do not start and end on
+    /// page boundaries.
+    InvalidRect,
+    /// The maximum number of regions has been reached.
+    TooManyRegions,
+}
+
+/// A rectangle of a framebuffer, granted to a process.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    /// The index of the framebuffer.
+    framebuffer: usize,
+    /// The owner of the framebuffer, which created the region.
+    owner: ProcessId,
+    /// The process that may map the region.
+    grantee: ProcessId,
+    /// The physical address of the first row of the region.
+    physical_address: PhysAddr,
+    /// The size of the rows of the region, in bytes. This is always a multiple of the page size.
+    length: usize,
+}
+
+/// The global region table.
+static mut REGIONS: [Option<Region>; MAX_REGIONS] = [None; MAX_REGIONS];
+
+/// Returns the framebuffer with the provided index.
+fn framebuffer(index: usize) -> Option<&'static Framebuffer> {
+    let public = unsafe { &*(super::public_data_address() as *const PublicData) };
+    public.framebuffers().get(index)
+}
+
+/// Creates a region of the provided framebuffer, owned by `owner`, and grants it to `grantee`.
+///
+/// # Returns
+///
+/// The ID of the new region.
+pub fn create(
+    owner: ProcessId,
+    index: usize,
+    rect: Rect,
+    grantee: ProcessId,
+) -> Result<usize, CreateError> {
+    let framebuffer = framebuffer(index).ok_or(CreateError::InvalidFramebuffer)?;
+
+    if framebuffer.owned_by.load(Acquire) != owner.get() as u64 {
+        return Err(CreateError::NotOwned);
+    }
+
+    let within = |start: usize, length: usize, bound: u64| {
+        length != 0 && start.checked_add(length).is_some_and(|end| end as u64 <= bound)
+    };
+
+    if !within(rect.x, rect.width, framebuffer.width)
+        || !within(rect.y, rect.height, framebuffer.height)
+    {
+        return Err(CreateError::InvalidRect);
+    }
+
+    // The rectangle fits in the framebuffer: those cannot overflow.
+    let pitch = framebuffer.pitch as usize;
+    let offset = rect.y * pitch;
+    let length = rect.height * pitch;
+
+    if offset % PAGE_SIZE != 0 || length % PAGE_SIZE != 0 {
+        return Err(CreateError::InvalidRect);
+    }
+
+    // SAFETY:
+    //  The region table is never accessed concurrently.
+    let table = unsafe { &mut REGIONS };
+
+    let slot = table
+        .iter()
+        .position(Option::is_none)
+        .ok_or(CreateError::TooManyRegions)?;
+
+    table[slot] = Some(Region {
+        framebuffer: index,
+        owner,
+        grantee,
+        physical_address: PhysAddr::new(framebuffer.physical_address as usize + offset),
+        length,
+    });
+
+    Ok(slot + 1)
+}
+
+/// Returns the physical address and the size of the memory of the region with the provided ID,
+/// if it exists and is granted to `grantee`.
+pub fn granted(grantee: ProcessId, id: usize) -> Option<(PhysAddr, usize)> {
+    // SAFETY:
+    //  The region table is never accessed concurrently.
+    let region = unsafe { REGIONS.get(id.checked_sub(1)?)?.as_ref()? };
+
+    if region.grantee != grantee {
+        return None;
+    }
+
+    Some((region.physical_address, region.length))
+}
+
+/// Destroys the region at the provided index of the table, and unmaps it from the address space
+/// of its grantee.
+fn remove(index: usize) {
+    // SAFETY:
+    //  The region table is never accessed concurrently.
+    let Some(region) = (unsafe { REGIONS[index].take() }) else {
+        return;
+    };
+
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    if let Some(grantee) = unsafe { process::get(region.grantee) } {
+        grantee.unmap_backing(Backing::FramebufferRegion { id: index + 1 });
+    }
+}
+
+/// Destroys the region with the provided ID, which must be owned by `owner`.
+///
+/// # Returns
+///
+/// Whether the region existed.
+pub fn destroy(owner: ProcessId, id: usize) -> bool {
+    let Some(index) = id.checked_sub(1) else {
+        return false;
+    };
+
+    // SAFETY:
+    //  The region table is never accessed concurrently.
+    match unsafe { REGIONS.get(index) } {
+        Some(Some(region)) if region.owner == owner => {
+            remove(index);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Destroys the regions of the framebuffer with the provided index.
+///
+/// This function is called when the framebuffer is released.
+pub fn release_framebuffer(framebuffer: usize) {
+    for index in 0..MAX_REGIONS {
+        // SAFETY:
+        //  The region table is never accessed concurrently.
+        if unsafe { REGIONS[index] }.is_some_and(|r| r.framebuffer == framebuffer) {
+            remove(index);
+        }
+    }
+}
+
+/// Destroys the regions owned by or granted to the provided process.
+///
+/// This function is called when the process exits, before its resources are released.
+pub fn release_process(id: ProcessId) {
+    for index in 0..MAX_REGIONS {
+        // SAFETY:
+        //  The region table is never accessed concurrently.
+        if unsafe { REGIONS[index] }.is_some_and(|r| r.owner == id || r.grantee == id) {
+            remove(index);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Escrow of the resources owned by crashed processes.
+//!
+//! A process may declare the processes it started with the `spawn` system call as restartable,
+//! along with a port of its own. When a restartable process crashes, the framebuffers and PCI
+//! devices it owned are not released. Instead:
+//!
+//! 1. They are placed in an escrow held on behalf of the supervisor: their `owned_by` field is
+//!    set to [`OWNED_BY_ESCROW`] combined with the index of the escrow, which prevents other
+//!    processes from acquiring them.
+//!
+//! 2. A [`CrashNotice`] message is sent to the port of the supervisor. It holds the ID of the
+//!    escrow.
+//!
+//! 3. The supervisor starts a replacement process, and hands the resources over to it with
+//!    [`reclaim`]. If the supervisor exits first, the escrows it holds are released.
+//!
+//! A process that terminates itself does not crash: its resources are released right away.
+//!
+//! # Synchronization
+//!
+//! Just like the process table, the escrow table is only accessed by system call handlers and
+//! by the scheduler, and never concurrently.
+
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::*;
+
+use fabric_sys::x86_64::public::{PublicData, OWNED_BY_ESCROW};
+use fabric_sys::{CrashNotice, CrashReason, PortId, ProcessId};
+
+use crate::log;
+
+use super::ipc::{self, Message};
+use super::process::ExitReason;
+
+/// The maximum number of escrows that may exist at the same time.
+pub const MAX_ESCROWS: usize = 16;
+
+/// The resources of a crashed process, held on behalf of its supervisor.
+#[derive(Clone, Copy)]
+struct Escrow {
+    /// The process that may reclaim the resources.
+    supervisor: ProcessId,
+}
+
+/// The global escrow table.
+static mut ESCROWS: [Option<Escrow>; MAX_ESCROWS] = [None; MAX_ESCROWS];
+
+/// Returns the value of the `owned_by` field of the resources held in the escrow with the
+/// provided index.
+#[inline(always)]
+fn tag(index: usize) -> u64 {
+    OWNED_BY_ESCROW | index as u64
+}
+
+/// Returns the `owned_by` fields of all the resources that may be owned by a process.
+fn owners() -> impl Iterator<Item = &'static AtomicU64> {
+    let public = unsafe { &*(super::public_data_address() as *const PublicData) };
+
+    let framebuffers = public.framebuffers().iter().map(|f| &f.owned_by);
+    let devices = public.pci_devices().iter().map(|d| &d.owned_by);
+    framebuffers.chain(devices)
+}
+
+/// Transfers the resources owned by `from` to `to`.
+///
+/// # Returns
+///
+/// The number of resources that have been transferred.
+fn transfer(from: u64, to: u64) -> usize {
+    owners()
+        .filter(|owned_by| owned_by.compare_exchange(from, to, AcqRel, Relaxed).is_ok())
+        .count()
+}
+
+/// Releases the resources owned by a process that is being terminated.
+///
+/// When the process crashed and `restart` designates the port of its supervisor, its resources
+/// are placed in an escrow and the supervisor is notified. Otherwise, they are simply released.
+pub fn on_exit(id: ProcessId, restart: Option<PortId>, reason: ExitReason) {
+    let (crash, vector) = match reason {
+        ExitReason::Terminated => (None, 0),
+        ExitReason::Exception { vector } => (Some(CrashReason::Exception), vector as usize),
+        ExitReason::OutOfMemory => (Some(CrashReason::OutOfMemory), 0),
+        ExitReason::Killed => (Some(CrashReason::Killed), 0),
+    };
+
+    // SAFETY:
+    //  The port table is never accessed concurrently.
+    let supervisor = restart.and_then(|port| unsafe { ipc::get(port) }.map(|p| p.owner));
+
+    let (Some(crash), Some(port), Some(supervisor)) = (crash, restart, supervisor) else {
+        transfer(id.get() as u64, 0);
+        return;
+    };
+
+    // SAFETY:
+    //  The escrow table is never accessed concurrently.
+    let table = unsafe { &mut ESCROWS };
+
+    let mut escrow = table.iter().position(Option::is_none);
+    match escrow {
+        Some(index) => {
+            if transfer(id.get() as u64, tag(index)) == 0 {
+                escrow = None;
+            } else {
+                table[index] = Some(Escrow { supervisor });
+            }
+        }
+        None => {
+            log::warn!(
+                "The escrow table is full: the resources of process {} are released.",
+                id
+            );
+            transfer(id.get() as u64, 0);
+        }
+    }
+
+    let notice = CrashNotice {
+        process: id.get(),
+        reason: crash as usize,
+        vector,
+        escrow: escrow.map_or(0, |index| index + 1),
+    };
+
+    if ipc::post(port, Message::from_kernel(&notice)).is_err() {
+        log::warn!(
+            "The crash of process {} could not be reported to process {}.",
+            id,
+            supervisor,
+        );
+
+        // The supervisor will never learn about the escrow.
+        if let Some(index) = escrow {
+            transfer(tag(index), 0);
+            table[index] = None;
+        }
+    }
+}
+
+/// Hands the resources held in the escrow with the provided ID over to `to`.
+///
+/// # Returns
+///
+/// The number of resources that have been handed over, or `None` if the escrow does not exist
+/// or is not held on behalf of `supervisor`.
+pub fn reclaim(supervisor: ProcessId, escrow: usize, to: ProcessId) -> Option<usize> {
+    let index = escrow.checked_sub(1)?;
+
+    // SAFETY:
+    //  The escrow table is never accessed concurrently.
+    let slot = unsafe { ESCROWS.get_mut(index)? };
+
+    if slot.map(|e| e.supervisor) != Some(supervisor) {
+        return None;
+    }
+
+    *slot = None;
+    Some(transfer(tag(index), to.get() as u64))
+}
+
+/// Releases the escrows held on behalf of the provided process.
+///
+/// This function is called when the process exits.
+pub fn release_held_by(supervisor: ProcessId) {
+    // SAFETY:
+    //  The escrow table is never accessed concurrently.
+    for (index, slot) in unsafe { ESCROWS.iter_mut() }.enumerate() {
+        if slot.is_some_and(|e| e.supervisor == supervisor) {
+            *slot = None;
+            let count = transfer(tag(index), 0);
+            log::trace!(
+                "Released {} escrowed resources of process {}.",
+                count,
+                supervisor
+            );
+        }
+    }
+}
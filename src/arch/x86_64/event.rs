@@ -0,0 +1,108 @@
+//! Delivery of kernel events to userspace processes.
+//!
+//! Each kind of event may have a single subscriber: a port that receives an [`Event`] message
+//! every time the event occurs. See [`fabric_sys::event`] for more information.
+
+use fabric_sys::event::{Event, EventKind};
+use fabric_sys::{PortId, ProcessId};
+
+use super::ipc::{self, Message};
+
+/// An error that might occur when subscribing to an event.
+#[derive(Debug, Clone, Copy)]
+pub struct AlreadySubscribed;
+
+/// An error that might occur when delivering an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliverError {
+    /// No port is subscribed to the event.
+    NoSubscriber,
+    /// The subscribed port cannot hold any more messages.
+    PortFull,
+}
+
+/// The port subscribed to each kind of event, indexed by [`EventKind`].
+static mut SUBSCRIBERS: [Option<PortId>; EventKind::COUNT] = [None; EventKind::COUNT];
+
+/// The number of events of each kind that occurred so far, indexed by [`EventKind`].
+///
+/// See [`Event::sequence`].
+static mut SEQUENCES: [u64; EventKind::COUNT] = [0; EventKind::COUNT];
+
+/// Returns the port subscribed to the provided kind of event, if it still exists.
+fn subscriber(kind: EventKind) -> Option<PortId> {
+    // SAFETY:
+    //  The subscriber table is never accessed concurrently.
+    let slot = unsafe { &mut SUBSCRIBERS[kind as usize] };
+
+    // The port may have been destroyed since the subscription has been made.
+    if slot.is_some_and(|port| unsafe { ipc::get(port).is_none() }) {
+        *slot = None;
+    }
+
+    *slot
+}
+
+/// Returns the process that owns the port subscribed to the provided kind of event, if any.
+pub fn subscriber_owner(kind: EventKind) -> Option<ProcessId> {
+    let port = subscriber(kind)?;
+    unsafe { ipc::get(port) }.map(|p| p.owner)
+}
+
+/// Subscribes `port` to the events of the provided kind on behalf of `owner`.
+///
+/// When `port` is `None`, the subscription of `owner` is removed, if any.
+///
+/// # Errors
+///
+/// If a port owned by another process is already subscribed to the events of that kind, an
+/// error is returned.
+pub fn subscribe(
+    owner: ProcessId,
+    kind: EventKind,
+    port: Option<PortId>,
+) -> Result<(), AlreadySubscribed> {
+    if let Some(current) = subscriber(kind) {
+        if unsafe { ipc::get(current) }.is_some_and(|p| p.owner != owner) {
+            return Err(AlreadySubscribed);
+        }
+    }
+
+    // SAFETY:
+    //  The subscriber table is never accessed concurrently.
+    unsafe { SUBSCRIBERS[kind as usize] = port };
+
+    Ok(())
+}
+
+/// Delivers an event of the provided kind to its subscriber.
+///
+/// `data` is the additional information carried by the event. See [`Event::data`].
+///
+/// # Errors
+///
+/// If no port is subscribed to the event, or if the subscribed port is full, an error is
+/// returned and the event is dropped. The event still consumes a sequence number.
+pub fn deliver(kind: EventKind, data: usize) -> Result<(), DeliverError> {
+    // SAFETY:
+    //  The sequence numbers are never accessed concurrently.
+    let sequence = unsafe {
+        let next = &mut SEQUENCES[kind as usize];
+        *next += 1;
+        *next - 1
+    };
+
+    let port = subscriber(kind).ok_or(DeliverError::NoSubscriber)?;
+
+    let event = Event {
+        kind: kind as usize,
+        data,
+        timestamp: super::timer::uptime_ns(),
+        sequence,
+    };
+
+    ipc::post(port, Message::from_kernel(&event)).map_err(|err| match err {
+        ipc::PostError::NoSuchPort => DeliverError::NoSubscriber,
+        ipc::PostError::PortFull => DeliverError::PortFull,
+    })
+}
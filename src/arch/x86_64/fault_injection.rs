@@ -0,0 +1,120 @@
+//! Fault injection, enabled by the `fault-injection` feature.
+//!
+//! The error paths of the kernel are rarely taken on a real system, which makes it easy for them
+//! to leak frames or to leave partial state behind. This module allows tests to force some
+//! operations to fail on their Nth call, as if the system had run out of memory:
+//!
+//! - [`FaultPoint::Allocate`] fails `MemoryTracker::allocate`.
+//! - [`FaultPoint::Map4KiB`] fails `paging::map_4kib`.
+//!
+//! Faults are armed with the `inject_fault` system call, or from boot with the
+//! `fault.allocate=<n>` and `fault.map=<n>` options of the kernel command line, which makes it
+//! possible to test the creation of the first processes. Only the Nth call fails: the fault is
+//! disarmed afterwards.
+//!
+//! The self-tests of the kernel (see `ktest.rs`) arm each fault point at every call made by
+//! process creation, `map_memory` and the setup of the inbox, and check that nothing is leaked
+//! when they fail.
+//!
+//! When the feature is disabled, [`should_fail`] always returns `false` and compiles to nothing.
+
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+
+use fabric_sys::x86_64::FaultPoint;
+
+use crate::log;
+use crate::utility::Cmdline;
+
+/// Whether the `fault-injection` feature is enabled.
+pub const ENABLED: bool = cfg!(feature = "fault-injection");
+
+/// The number of calls left before each fault point fails, or 0 if it is disarmed.
+///
+/// Indexed by [`FaultPoint`].
+static COUNTDOWNS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// Converts the raw value used by the `inject_fault` system call into a [`FaultPoint`].
+pub fn point_from_raw(raw: usize) -> Option<FaultPoint> {
+    match raw {
+        0 => Some(FaultPoint::Allocate),
+        1 => Some(FaultPoint::Map4KiB),
+        _ => None,
+    }
+}
+
+/// Arms the provided fault point so that its `nth` next call fails.
+///
+/// `nth` starts at 1 for the next call. 0 disarms the fault point.
+pub fn arm(point: FaultPoint, nth: usize) {
+    if !ENABLED {
+        return;
+    }
+
+    if nth != 0 {
+        log::info!("Injecting a fault in the call #{} to {:?}.", nth, point);
+    }
+    COUNTDOWNS[point as usize].store(nth, Relaxed);
+}
+
+/// Arms the fault points requested on the provided command line.
+///
+/// Invalid values are reported and ignored.
+pub fn init(cmdline: Cmdline) {
+    if !ENABLED {
+        return;
+    }
+
+    for (name, point) in [
+        (&b"fault.allocate"[..], FaultPoint::Allocate),
+        (&b"fault.map"[..], FaultPoint::Map4KiB),
+    ] {
+        let Some(value) = cmdline.get(name) else {
+            continue;
+        };
+
+        match core::str::from_utf8(value)
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            Some(nth) => arm(point, nth),
+            None => log::warn!(
+                "Invalid `{}` value: `{}`.",
+                core::str::from_utf8(name).unwrap_or("<invalid UTF-8>"),
+                core::str::from_utf8(value).unwrap_or("<invalid UTF-8>"),
+            ),
+        }
+    }
+}
+
+/// Returns whether the current call to the provided fault point should fail.
+///
+/// This must be called exactly once per call to the operation.
+#[inline(always)]
+pub fn should_fail(point: FaultPoint) -> bool {
+    if !ENABLED {
+        return false;
+    }
+
+    should_fail_slow(point)
+}
+
+#[inline(never)]
+fn should_fail_slow(point: FaultPoint) -> bool {
+    let countdown = &COUNTDOWNS[point as usize];
+
+    // The kernel is not reentrant: nothing can modify the countdown between the load and the
+    // store.
+    match countdown.load(Relaxed) {
+        0 => false,
+        n => {
+            countdown.store(n - 1, Relaxed);
+
+            if n == 1 {
+                log::warn!("Injected a fault in {:?}.", point);
+            }
+
+            n == 1
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! A minimal driver for the High Precision Event Timer (HPET).
+//!
+//! The kernel only uses the main counter of the HPET, as a reference to measure the frequency of
+//! the TSC (see [`timer::refine_clock`]). Its comparators are left disabled, and the HPET never
+//! raises interrupts.
+//!
+//! [`timer::refine_clock`]: super::timer::refine_clock
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Release};
+
+use crate::log;
+use crate::utility::{MmioRegion, Register};
+
+use super::acpi;
+use super::cpu::paging::UpperHalfAddressSpaceTok;
+use super::mem::{vmalloc, MemoryTrackerTok, PhysAddr};
+use super::raw::PageFlags;
+
+/// The general capabilities and ID register.
+const CAPABILITIES: Register<u64> = Register::new(0x000);
+/// The general configuration register.
+const CONFIGURATION: Register<u64> = Register::new(0x010);
+/// The main counter value register.
+const MAIN_COUNTER: Register<u64> = Register::new(0x0F0);
+
+/// The size of the registers of the HPET that the kernel uses.
+const REGISTERS_SIZE: usize = 0x400;
+
+/// Set in [`CAPABILITIES`] when the main counter is 64 bits wide.
+const CAPABILITIES_COUNTER_64BIT: u64 = 1 << 13;
+/// The shift of the period of the main counter, in femtoseconds, in [`CAPABILITIES`].
+const CAPABILITIES_PERIOD_SHIFT: u32 = 32;
+
+/// Set in [`CONFIGURATION`] to make the main counter run.
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+/// Set in [`CONFIGURATION`] to route the first comparators to the legacy ISA interrupts.
+const CONFIGURATION_LEGACY_ROUTE: u64 = 1 << 1;
+
+/// The largest period of the main counter allowed by the specification, in femtoseconds.
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// The number of femtoseconds in a second.
+const FS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// The initialized HPET.
+#[derive(Clone, Copy)]
+struct Hpet {
+    /// The registers of the HPET.
+    registers: MmioRegion,
+    /// The period of the main counter, in femtoseconds.
+    period_fs: u64,
+    /// Whether the main counter is 64 bits wide.
+    counter_64bit: bool,
+}
+
+/// The HPET, once [`init`] has found one.
+///
+/// Only written by [`init`], before [`PRESENT`] is set.
+static mut HPET: Option<Hpet> = None;
+
+/// Whether the HPET has been initialized.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Returns the HPET, if it has been initialized.
+fn hpet() -> Option<Hpet> {
+    if !PRESENT.load(Acquire) {
+        return None;
+    }
+
+    // SAFETY:
+    //  The HPET is never modified once `PRESENT` is set.
+    unsafe { HPET }
+}
+
+/// Maps the registers of the HPET described by the ACPI tables, and starts its main counter.
+///
+/// # Safety
+///
+/// This function must only be called once, after the ACPI tables have been parsed and the
+/// memory tracker has been initialized.
+pub unsafe fn init(upper_half: UpperHalfAddressSpaceTok) {
+    let Some(info) = acpi::info().hpet else {
+        log::trace!("No HPET found.");
+        return;
+    };
+
+    // SAFETY:
+    //  The caller guarantees that the memory tracker is initialized.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    // The registers of the HPET are not covered by the direct map.
+    let base = unsafe {
+        vmalloc::map_physical(
+            upper_half,
+            &mut || memory_tracker.allocate_page_table(),
+            PhysAddr::new(info.address),
+            REGISTERS_SIZE,
+            PageFlags::WRITABLE
+                | PageFlags::DISABLE_CACHE
+                | PageFlags::GLOBAL
+                | PageFlags::NO_EXECUTE,
+        )
+    };
+
+    let Ok(base) = base else {
+        log::error!("Failed to map the registers of the HPET.");
+        return;
+    };
+
+    // SAFETY:
+    //  The registers have just been mapped, and are never unmapped.
+    let registers = unsafe { MmioRegion::new(base.as_ptr(), REGISTERS_SIZE) };
+
+    let capabilities = registers.read(CAPABILITIES);
+    let period_fs = capabilities >> CAPABILITIES_PERIOD_SHIFT;
+
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        log::warn!(
+            "The HPET reports an invalid period ({} fs). It will be ignored.",
+            period_fs
+        );
+        return;
+    }
+
+    // The comparators are not used, and must not take over the interrupts of the PIT and the
+    // RTC.
+    registers.modify(CONFIGURATION, |config| {
+        (config | CONFIGURATION_ENABLE) & !CONFIGURATION_LEGACY_ROUTE
+    });
+
+    // SAFETY:
+    //  This function is only called once, before `PRESENT` is set.
+    unsafe {
+        HPET = Some(Hpet {
+            registers,
+            period_fs,
+            counter_64bit: capabilities & CAPABILITIES_COUNTER_64BIT != 0,
+        });
+    }
+    PRESENT.store(true, Release);
+
+    log::trace!(
+        "HPET enabled ({} Hz main counter).",
+        FS_PER_SECOND / period_fs
+    );
+}
+
+/// Returns the frequency of the main counter, in hertz, if the HPET has been initialized.
+pub fn frequency() -> Option<u64> {
+    hpet().map(|hpet| FS_PER_SECOND / hpet.period_fs)
+}
+
+/// Reads the main counter, if the HPET has been initialized.
+///
+/// When the counter is only 32 bits wide, the upper bits of the returned value are always zero.
+pub fn counter() -> Option<u64> {
+    let hpet = hpet()?;
+    let value = hpet.registers.read(MAIN_COUNTER);
+
+    match hpet.counter_64bit {
+        true => Some(value),
+        false => Some(value & u32::MAX as u64),
+    }
+}
+
+/// Waits until the main counter has advanced by at least `ticks`.
+///
+/// # Returns
+///
+/// The number of ticks that actually elapsed is returned, or `None` if the HPET has not been
+/// initialized.
+pub fn busy_wait(ticks: u64) -> Option<u64> {
+    let mask = match hpet()?.counter_64bit {
+        true => u64::MAX,
+        false => u32::MAX as u64,
+    };
+
+    let start = counter()?;
+    loop {
+        let elapsed = counter()?.wrapping_sub(start) & mask;
+        if elapsed >= ticks {
+            return Some(elapsed);
+        }
+        core::hint::spin_loop();
+    }
+}
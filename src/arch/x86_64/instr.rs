@@ -36,6 +36,78 @@ pub unsafe fn inb(port: u16) -> u8 {
     ret
 }
 
+/// Writes a 16-bit word to the given I/O port.
+///
+/// # Safety
+///
+/// Setting arbitrary ports can violate memory safety.
+#[inline(always)]
+pub unsafe fn outw(port: u16, value: u16) {
+    unsafe {
+        asm!(
+            "out dx, ax",
+            in("dx") port,
+            in("ax") value,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Reads a 16-bit word from the given I/O port.
+///
+/// # Safety
+///
+/// Reading from arbitrary ports can violate memory safety.
+#[inline(always)]
+pub unsafe fn inw(port: u16) -> u16 {
+    let ret;
+    unsafe {
+        asm!(
+            "in ax, dx",
+            in("dx") port,
+            out("ax") ret,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    ret
+}
+
+/// Writes a 32-bit double word to the given I/O port.
+///
+/// # Safety
+///
+/// Setting arbitrary ports can violate memory safety.
+#[inline(always)]
+pub unsafe fn outl(port: u16, value: u32) {
+    unsafe {
+        asm!(
+            "out dx, eax",
+            in("dx") port,
+            in("eax") value,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Reads a 32-bit double word from the given I/O port.
+///
+/// # Safety
+///
+/// Reading from arbitrary ports can violate memory safety.
+#[inline(always)]
+pub unsafe fn inl(port: u16) -> u32 {
+    let ret;
+    unsafe {
+        asm!(
+            "in eax, dx",
+            in("dx") port,
+            out("eax") ret,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    ret
+}
+
 /// Writes to the specified model-specific register.
 ///
 /// # Safety
@@ -77,6 +149,56 @@ pub unsafe fn rdmsr(port: u32) -> u64 {
     ((high as u64) << 32) | (low as u64)
 }
 
+/// Reads the time-stamp counter of the current CPU.
+#[inline(always)]
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!(
+            "rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    ((high as u64) << 32) | (low as u64)
+}
+
+/// Reads a random number from the hardware generator of the current CPU.
+///
+/// `None` is returned when the generator did not have enough entropy available.
+///
+/// # Safety
+///
+/// The CPU must support the **RDRAND** instruction.
+#[inline(always)]
+pub unsafe fn rdrand() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    unsafe {
+        asm!(
+            "rdrand {}",
+            "setc {}",
+            out(reg) value,
+            out(reg_byte) ok,
+            options(nomem, nostack)
+        );
+    }
+    (ok != 0).then_some(value)
+}
+
+/// Executes the **CPUID** instruction with the provided leaf and sub-leaf.
+///
+/// The values of the `eax`, `ebx`, `ecx` and `edx` registers are returned, in that order.
+#[inline(always)]
+pub fn cpuid(leaf: u32, subleaf: u32) -> [u32; 4] {
+    // SAFETY:
+    //  The CPUID instruction is available on every x86_64 CPU.
+    let result = unsafe { core::arch::x86_64::__cpuid_count(leaf, subleaf) };
+    [result.eax, result.ebx, result.ecx, result.edx]
+}
+
 /// Halts the CPU until the next interrupt arrives.
 #[inline(always)]
 pub fn hlt() {
@@ -85,6 +207,17 @@ pub fn hlt() {
     }
 }
 
+/// Executes the `xchg bx, bx` instruction, which Bochs treats as a breakpoint when its
+/// `magic_break` option is enabled.
+///
+/// Elsewhere, this instruction does nothing.
+#[inline(always)]
+pub fn magic_breakpoint() {
+    unsafe {
+        asm!("xchg bx, bx", options(nostack, nomem, preserves_flags));
+    }
+}
+
 /// Disables interrupts.
 pub fn cli() {
     unsafe {
@@ -99,6 +232,18 @@ pub fn sti() {
     }
 }
 
+/// Returns whether maskable interrupts are enabled on the current CPU.
+#[inline(always)]
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+
+    rflags & super::raw::RFlags::INTERRUPT_ENABLE.bits() != 0
+}
+
 /// Invalidates the TLB entry for the given virtual address.
 #[inline(always)]
 pub fn invlpg(addr: usize) {
@@ -106,3 +251,99 @@ pub fn invlpg(addr: usize) {
         asm!("invlpg [{}]", in(reg) addr, options(nostack, readonly, preserves_flags));
     }
 }
+
+/// Enables interrupts and halts the CPU until the next interrupt arrives, then disables
+/// interrupts again.
+///
+/// Because **STI** only takes effect after the following instruction, no interrupt can be
+/// delivered between the two instructions and be missed by the **HLT** instruction.
+///
+/// Interrupt handlers are expected to modify memory while the CPU is halted, which is why this
+/// function does not use the `nomem` option.
+#[inline(always)]
+pub fn sti_hlt_cli() {
+    unsafe {
+        asm!("sti", "hlt", "cli", options(nostack, preserves_flags));
+    }
+}
+
+/// Loads the provided physical address into the **CR3** register, switching the current address
+/// space.
+///
+/// # Safety
+///
+/// `l4_table` must be the physical address of a valid L4 page table which maps the kernel.
+#[inline(always)]
+pub unsafe fn set_cr3(l4_table: usize) {
+    unsafe {
+        asm!("mov cr3, {}", in(reg) l4_table, options(nostack, preserves_flags));
+    }
+}
+
+/// Reads the value of the **CR2** register, which holds the address that caused the last page
+/// fault.
+#[inline(always)]
+pub fn cr2() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Reads the value of the **CR3** register, which holds the physical address of the L4 page table
+/// of the current address space.
+#[inline(always)]
+pub fn cr3() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Reads the value of the **CR0** register.
+#[inline(always)]
+pub fn cr0() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Writes the provided value to the **CR0** register.
+///
+/// # Safety
+///
+/// Changing the control registers may change the behavior of the CPU in ways that violate
+/// memory safety.
+#[inline(always)]
+pub unsafe fn set_cr0(value: u64) {
+    unsafe {
+        asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+/// Reads the value of the **CR4** register.
+#[inline(always)]
+pub fn cr4() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr4", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Writes the provided value to the **CR4** register.
+///
+/// # Safety
+///
+/// Changing the control registers may change the behavior of the CPU in ways that violate
+/// memory safety.
+#[inline(always)]
+pub unsafe fn set_cr4(value: u64) {
+    unsafe {
+        asm!("mov cr4, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
@@ -0,0 +1,242 @@
+//! Message-based communication between processes.
+//!
+//! # Ports
+//!
+//! A port is a bounded queue of small messages. It is owned by the process that created it, which
+//! is the only one allowed to receive messages from it. Any process that knows the ID of a port
+//! may send messages to it.
+//!
+//! Messages may carry the ID of another port, which is typically used by the receiver to send a
+//! reply.
+//!
+//! # Synchronization
+//!
+//! Just like the process table, the port table is only accessed by system call handlers and
+//! never concurrently.
+
+use core::mem::size_of;
+
+use fabric_sys::ipc::Pod;
+use fabric_sys::x86_64::MAX_MESSAGE_SIZE;
+use fabric_sys::{PortId, ProcessId};
+
+use super::process::{self, ProcessState};
+use super::scheduler;
+use crate::utility::{IdAllocator, OverflowPolicy, RingBuffer};
+
+/// The maximum number of ports that may exist at the same time.
+pub const MAX_PORTS: usize = 64;
+
+/// The maximum number of messages that may be queued in a single port.
+pub const PORT_CAPACITY: usize = 16;
+
+/// A message queued in a [`Port`].
+#[derive(Clone, Copy)]
+pub struct Message {
+    /// The ID of the process that sent the message.
+    pub sender: usize,
+    /// The ID of the port attached to the message, or 0 if no port is attached.
+    pub attachment: usize,
+    /// The number of bytes of `data` that are part of the message.
+    pub length: usize,
+    /// The content of the message.
+    pub data: [u8; MAX_MESSAGE_SIZE],
+}
+
+impl Message {
+    /// Creates a message sent by the kernel, whose content is `value`.
+    pub fn from_kernel<T: Pod>(value: &T) -> Self {
+        // The condition is known at compile time: the check is optimized away.
+        assert!(size_of::<T>() <= MAX_MESSAGE_SIZE);
+
+        let mut message = Self {
+            sender: 0,
+            attachment: 0,
+            length: size_of::<T>(),
+            data: [0; MAX_MESSAGE_SIZE],
+        };
+
+        // SAFETY:
+        //  `T` is a plain-old-data type that fits in a message.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                value as *const T as *const u8,
+                message.data.as_mut_ptr(),
+                size_of::<T>(),
+            );
+        }
+
+        message
+    }
+}
+
+/// Indicates that a [`Port`] cannot hold any more messages.
+#[derive(Debug, Clone, Copy)]
+pub struct PortFull;
+
+/// A queue of messages owned by a process.
+pub struct Port {
+    /// The process that may receive messages from the port.
+    pub owner: ProcessId,
+    /// The pending messages.
+    messages: RingBuffer<Message, PORT_CAPACITY>,
+}
+
+impl Port {
+    /// Creates a new empty [`Port`].
+    pub const fn new(owner: ProcessId) -> Self {
+        Self {
+            owner,
+            messages: RingBuffer::new(),
+        }
+    }
+
+    /// Returns the number of pending messages.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns whether the port has no pending message.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Returns the oldest message of the queue without removing it.
+    #[inline]
+    pub fn peek(&self) -> Option<&Message> {
+        self.messages.peek()
+    }
+
+    /// Pushes a message at the back of the queue.
+    #[inline]
+    pub fn push(&mut self, message: Message) -> Result<(), PortFull> {
+        self.messages
+            .push(message, OverflowPolicy::DropNewest)
+            .map_err(|_| PortFull)
+    }
+
+    /// Removes the oldest message of the queue.
+    #[inline]
+    pub fn pop(&mut self) -> Option<Message> {
+        self.messages.pop()
+    }
+}
+
+/// The global port table.
+static mut PORTS: [Option<Port>; MAX_PORTS] = {
+    const NONE: Option<Port> = None;
+    [NONE; MAX_PORTS]
+};
+
+/// The IDs of the slots of the port table.
+///
+/// The ID of a destroyed port never designates another port: messages sent with a stale ID are
+/// rejected instead of reaching an unrelated process.
+static mut PORT_IDS: IdAllocator<MAX_PORTS> = IdAllocator::new();
+
+/// Creates a new port owned by the provided process.
+///
+/// The ID of the new port is returned, or `None` if the port table is full.
+pub fn create(owner: ProcessId) -> Option<PortId> {
+    // SAFETY:
+    //  The port table is never accessed concurrently.
+    let (table, ids) = unsafe { (&mut PORTS, &mut PORT_IDS) };
+
+    let (id, index) = ids.allocate()?;
+    table[index] = Some(Port::new(owner));
+
+    Some(id)
+}
+
+/// Returns the port with the provided ID, if it exists.
+///
+/// # Safety
+///
+/// No other reference to the requested port may be alive while the returned reference is used.
+pub unsafe fn get(id: PortId) -> Option<&'static mut Port> {
+    let index = unsafe { PORT_IDS.index_of(id)? };
+    unsafe { PORTS[index].as_mut() }
+}
+
+/// Destroys the port with the provided ID, discarding its pending messages.
+pub fn destroy(id: PortId) {
+    // SAFETY:
+    //  The port table is never accessed concurrently.
+    let Some(index) = (unsafe { PORT_IDS.release(id) }) else {
+        return;
+    };
+
+    // SAFETY:
+    //  The port table is never accessed concurrently.
+    if let Some(port) = unsafe { PORTS[index].take() } {
+        publish_pending(port.owner);
+    }
+}
+
+/// Updates the number of pending messages reported in the inbox of the provided process.
+///
+/// This must be called whenever a message is pushed to or removed from one of its ports.
+pub fn publish_pending(owner: ProcessId) {
+    // SAFETY:
+    //  The port table is never accessed concurrently.
+    let pending: usize = unsafe { PORTS.iter() }
+        .flatten()
+        .filter(|port| port.owner == owner)
+        .map(Port::len)
+        .sum();
+
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    if let Some(inbox) = unsafe { process::get(owner) }.and_then(|p| p.inbox()) {
+        inbox.set_pending_messages(pending as u32);
+    }
+}
+
+/// An error that might occur when posting a message to a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostError {
+    /// The port does not exist.
+    NoSuchPort,
+    /// The port cannot hold any more messages.
+    PortFull,
+}
+
+/// Pushes a message to the provided port, waking up its owner if it was waiting for a message.
+pub fn post(id: PortId, message: Message) -> Result<(), PostError> {
+    // SAFETY:
+    //  The port table is never accessed concurrently.
+    let port = unsafe { get(id) }.ok_or(PostError::NoSuchPort)?;
+
+    port.push(message).map_err(|_| PostError::PortFull)?;
+    publish_pending(port.owner);
+
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    if let Some(owner) = unsafe { process::get(port.owner) } {
+        if owner.state == (ProcessState::Receiving { port: id }) {
+            owner.state = ProcessState::Runnable;
+            owner.cancel_timeout();
+            scheduler::enqueue(port.owner);
+        }
+    }
+
+    Ok(())
+}
+
+/// Destroys all the ports owned by the provided process.
+pub fn destroy_owned_by(owner: ProcessId) {
+    // SAFETY:
+    //  The port table is never accessed concurrently.
+    let (table, ids) = unsafe { (&mut PORTS, &mut PORT_IDS) };
+
+    for (index, slot) in table.iter_mut().enumerate() {
+        if slot.as_ref().is_some_and(|port| port.owner == owner) {
+            if let Some(id) = ids.id_at(index) {
+                ids.release(id);
+            }
+            *slot = None;
+        }
+    }
+}
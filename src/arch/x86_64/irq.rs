@@ -0,0 +1,641 @@
+//! Delivery of device interrupts to userspace drivers.
+//!
+//! The owner of a PCI device may bind the legacy interrupt line of the device to one of its
+//! ports. Each bound line is routed by the I/O APIC to its own interrupt vector, starting at
+//! [`USER_IRQ_VECTOR_BASE`].
+//!
+//! # Mask on Delivery
+//!
+//! The kernel cannot acknowledge an interrupt at the level of the device: only its driver knows
+//! how to. When a bound line fires, the kernel:
+//!
+//! 1. Masks the line in the I/O APIC, and signals the end of the interrupt to the local APIC.
+//!    A level-triggered line that remains asserted does not fire again.
+//!
+//! 2. Sends an [`IrqNotice`] to the port of the driver.
+//!
+//! The line is only unmasked once the driver has serviced the device and performed the
+//! `ack_irq` system call.
+//!
+//! # Budgets
+//!
+//! A driver that never acknowledges its interrupts, or that monopolizes the CPU while handling
+//! them, degrades the whole system. Each line has two budgets, measured in ticks from the
+//! delivery of an interrupt to its acknowledgment:
+//!
+//! - The ack deadline, selected with the `irq.ack_deadline` option of the kernel command line
+//!   (in milliseconds, [`DEFAULT_ACK_DEADLINE_MS`] by default).
+//! - The CPU budget: the number of ticks during which the driver may run in the meantime,
+//!   selected with the `irq.cpu_budget` option ([`DEFAULT_CPU_BUDGET_MS`] by default).
+//!
+//! Both may be adjusted per line by the supervisor of the driver. When a driver exceeds one of
+//! them, the kernel escalates, waiting for one more ack deadline between each step:
+//!
+//! 1. The device is masked in its PCI command register.
+//! 2. An [`IrqViolationNotice`] is sent to the supervisor of the driver, if it has one.
+//! 3. The line is unbound and the device is revoked.
+//!
+//! Acknowledging the interrupt in the meantime stops the escalation.
+//!
+//! # Limitations
+//!
+//! The kernel does not interpret the ACPI namespace, and has no access to the routing of the
+//! PCI interrupt pins. The interrupt line written by the firmware in the configuration space of
+//! the device is interpreted as an ISA IRQ instead, which matches the routing of the legacy
+//! chipsets emulated by most hypervisors. Lines shared by several devices are not supported.
+//!
+//! Message signaled interrupts are not supported yet. They would be masked through the per-vector
+//! mask bits of the MSI capability of the device rather than through the I/O APIC.
+
+use core::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use fabric_sys::x86_64::public::{PciDevice, PublicData};
+use fabric_sys::x86_64::{IrqNotice, IrqViolation, IrqViolationNotice};
+use fabric_sys::{PortId, ProcessId};
+
+use crate::log;
+use crate::utility::Cmdline;
+
+use super::acpi;
+use super::cpu::apic;
+use super::cpu::idt::USER_IRQ_VECTOR_BASE;
+use super::cpu::ioapic::{self, Polarity, TriggerMode};
+use super::ipc::{self, Message};
+use super::pci::{self, ConfigAddress};
+use super::percpu;
+use super::process;
+use super::raw::StackFrame;
+use super::timeout::{self, Target};
+use super::timer::TICK_FREQUENCY;
+
+/// The maximum number of interrupt lines that may be bound at the same time.
+pub const MAX_IRQ_LINES: usize = 8;
+
+/// The default ack deadline of the lines, in milliseconds.
+pub const DEFAULT_ACK_DEADLINE_MS: u64 = 100;
+
+/// The default CPU budget of the lines, in milliseconds.
+pub const DEFAULT_CPU_BUDGET_MS: u64 = 50;
+
+/// The `interrupt_line` of a PCI device that has not been assigned a line.
+const NO_INTERRUPT_LINE: u8 = 0xFF;
+
+/// An error that might occur when binding an interrupt line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindError {
+    /// The device is not owned by the process, or does not use legacy interrupts.
+    InvalidDevice,
+    /// The interrupt line is already used by the kernel or by another binding.
+    LineInUse,
+    /// The maximum number of bound lines has been reached.
+    TooManyLines,
+}
+
+/// An error that might occur when setting the budgets of a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetError {
+    /// The line is not bound.
+    NoSuchLine,
+    /// The caller may not set the budgets of the line to the requested values.
+    PermissionDenied,
+}
+
+/// The budgets of a line, in ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    /// The number of ticks after which an unacknowledged interrupt is overdue.
+    pub ack_deadline: u64,
+    /// The number of ticks during which the driver may run before acknowledging an interrupt.
+    pub cpu: u64,
+}
+
+/// The step of the escalation reached by an overdue interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The driver is within its budgets.
+    Handling,
+    /// The driver exceeded one of its budgets, and the device has been masked.
+    Masked(IrqViolation),
+    /// The supervisor of the driver has been notified.
+    Reported,
+}
+
+/// An interrupt that has been delivered to the driver, but not acknowledged yet.
+#[derive(Clone, Copy)]
+struct Pending {
+    /// The number of ticks during which the driver ran since the interrupt was delivered.
+    cpu: u64,
+    /// The step of the escalation reached so far.
+    stage: Stage,
+    /// The timeout that moves the escalation to its next step.
+    ///
+    /// It expires once the ack deadline of the line has elapsed since the interrupt was
+    /// delivered, or since the last step of the escalation.
+    timeout: Option<timeout::Handle>,
+}
+
+/// An interrupt line bound to the port of a driver.
+#[derive(Clone, Copy)]
+struct Line {
+    /// The global system interrupt of the line.
+    gsi: u32,
+    /// The index of the device that raises the interrupt.
+    device: usize,
+    /// The process that owns the device.
+    owner: ProcessId,
+    /// The port that receives the notices.
+    port: PortId,
+    /// The budgets of the driver.
+    budget: Budget,
+    /// The interrupt currently handled by the driver, if any.
+    pending: Option<Pending>,
+    /// The number of interrupts raised on the line since it has been bound.
+    sequence: u64,
+}
+
+/// The global table of bound interrupt lines, indexed by line ID.
+///
+/// Interrupts only occur while userspace is running, so the table is never accessed
+/// concurrently.
+static mut LINES: [Option<Line>; MAX_IRQ_LINES] = [None; MAX_IRQ_LINES];
+
+/// The budgets given to newly bound lines.
+static mut DEFAULT_BUDGET: Budget = Budget {
+    ack_deadline: ms_to_ticks(DEFAULT_ACK_DEADLINE_MS),
+    cpu: ms_to_ticks(DEFAULT_CPU_BUDGET_MS),
+};
+
+/// Converts a duration in milliseconds to a number of ticks, rounding up.
+///
+/// Budgets last at least one tick.
+const fn ms_to_ticks(ms: u64) -> u64 {
+    let ticks = ms.saturating_mul(TICK_FREQUENCY as u64).div_ceil(1000);
+    if ticks == 0 {
+        1
+    } else {
+        ticks
+    }
+}
+
+/// Reads the duration, in milliseconds, of the provided option of the command line.
+///
+/// Invalid values are reported and replaced by `default`.
+fn read_ms(cmdline: Cmdline, name: &str, default: u64) -> u64 {
+    let Some(value) = cmdline.get(name.as_bytes()) else {
+        return default;
+    };
+
+    match core::str::from_utf8(value)
+        .ok()
+        .and_then(|s| s.parse().ok())
+    {
+        Some(ms) => ms,
+        None => {
+            log::warn!(
+                "Invalid `{}` value: `{}`.",
+                name,
+                core::str::from_utf8(value).unwrap_or("<invalid UTF-8>")
+            );
+            default
+        }
+    }
+}
+
+/// Reads the default budgets of the lines from the `irq.ack_deadline` and `irq.cpu_budget`
+/// options of the provided command line.
+///
+/// # Safety
+///
+/// This function must be called once, during boot.
+pub unsafe fn init(cmdline: Cmdline) {
+    let ack_deadline_ms = read_ms(cmdline, "irq.ack_deadline", DEFAULT_ACK_DEADLINE_MS);
+    let cpu_budget_ms = read_ms(cmdline, "irq.cpu_budget", DEFAULT_CPU_BUDGET_MS);
+
+    // SAFETY:
+    //  The caller guarantees that the budget is not accessed concurrently.
+    unsafe {
+        DEFAULT_BUDGET = Budget {
+            ack_deadline: ms_to_ticks(ack_deadline_ms),
+            cpu: ms_to_ticks(cpu_budget_ms),
+        };
+    }
+
+    log::trace!(
+        "IRQ ack deadline of {} ms, CPU budget of {} ms.",
+        ack_deadline_ms,
+        cpu_budget_ms,
+    );
+}
+
+/// Returns the public description of the PCI device with the provided index.
+fn public_device(index: usize) -> Option<&'static PciDevice> {
+    let public = unsafe { &*(super::public_data_address() as *const PublicData) };
+    public.pci_devices().get(index)
+}
+
+/// Returns the location of the PCI device with the provided index.
+fn config_address(index: usize) -> Option<ConfigAddress> {
+    pci::devices().nth(index).map(|d| d.address)
+}
+
+/// Binds the interrupt line of the provided PCI device to `port`.
+///
+/// # Returns
+///
+/// The ID of the bound line.
+pub fn bind(owner: ProcessId, device: usize, port: PortId) -> Result<usize, BindError> {
+    let info = public_device(device)
+        .filter(|d| d.owned_by.load(Acquire) == owner.get() as u64)
+        .ok_or(BindError::InvalidDevice)?;
+
+    if info.interrupt_pin == 0 || info.interrupt_line == NO_INTERRUPT_LINE {
+        return Err(BindError::InvalidDevice);
+    }
+
+    // PCI interrupts are level-triggered and active-low, unless the MADT says otherwise.
+    let (gsi, polarity, trigger) =
+        acpi::resolve_isa_irq(info.interrupt_line, Polarity::ActiveLow, TriggerMode::Level);
+
+    // SAFETY:
+    //  The line table is never accessed concurrently.
+    let table = unsafe { &mut LINES };
+
+    // The lines routed by the kernel for its own use are never masked.
+    if table.iter().flatten().any(|l| l.gsi == gsi) || !ioapic::is_masked(gsi).unwrap_or(false) {
+        return Err(BindError::LineInUse);
+    }
+
+    let index = table
+        .iter()
+        .position(Option::is_none)
+        .ok_or(BindError::TooManyLines)?;
+
+    table[index] = Some(Line {
+        gsi,
+        device,
+        owner,
+        port,
+        // SAFETY:
+        //  The default budget is only modified during boot.
+        budget: unsafe { DEFAULT_BUDGET },
+        pending: None,
+        sequence: 0,
+    });
+
+    // The device may have been masked when it was revoked from its previous owner.
+    if let Some(address) = config_address(device) {
+        // SAFETY:
+        //  The configuration space is never accessed concurrently.
+        unsafe { pci::set_interrupts_disabled(address, false) };
+    }
+
+    let routed = ioapic::route(
+        gsi,
+        (USER_IRQ_VECTOR_BASE + index) as u8,
+        polarity,
+        trigger,
+        apic::local_apic_id(),
+    );
+
+    if routed.is_err() {
+        table[index] = None;
+        return Err(BindError::InvalidDevice);
+    }
+
+    log::trace!(
+        "Bound GSI {} of device {} to port {} of process {}.",
+        gsi,
+        device,
+        port,
+        owner,
+    );
+
+    Ok(index)
+}
+
+/// Acknowledges the last interrupt delivered on the provided line, and unmasks it.
+///
+/// # Returns
+///
+/// `false` if the line is not bound by `owner`.
+pub fn ack(owner: ProcessId, line: usize) -> bool {
+    // SAFETY:
+    //  The line table is never accessed concurrently.
+    let Some(Some(line)) = (unsafe { LINES.get_mut(line) }) else {
+        return false;
+    };
+
+    if line.owner != owner {
+        return false;
+    }
+
+    let pending = line.pending.take();
+    if let Some(handle) = pending.and_then(|p| p.timeout) {
+        timeout::cancel(handle);
+    }
+    if pending.is_some_and(|p| p.stage != Stage::Handling) {
+        if let Some(address) = config_address(line.device) {
+            // SAFETY:
+            //  The configuration space is never accessed concurrently.
+            unsafe { pci::set_interrupts_disabled(address, false) };
+        }
+    }
+
+    // The line was checked to be handled by an I/O APIC when it was bound.
+    let _ = ioapic::set_masked(line.gsi, false);
+    true
+}
+
+/// Sets the budgets of the provided line on behalf of `caller`.
+///
+/// The supervisor of the driver, which is the process that started it, may set any budget. The
+/// driver itself may only lower its own budgets.
+pub fn set_budget(caller: ProcessId, line: usize, budget: Budget) -> Result<(), BudgetError> {
+    // SAFETY:
+    //  The line table is never accessed concurrently.
+    let Some(Some(line)) = (unsafe { LINES.get_mut(line) }) else {
+        return Err(BudgetError::NoSuchLine);
+    };
+
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    let parent = unsafe { process::get(line.owner) }.and_then(|p| p.parent);
+
+    let allowed = if parent == Some(caller) {
+        true
+    } else if line.owner == caller {
+        budget.ack_deadline <= line.budget.ack_deadline && budget.cpu <= line.budget.cpu
+    } else {
+        false
+    };
+
+    if !allowed {
+        return Err(BudgetError::PermissionDenied);
+    }
+
+    line.budget = budget;
+    Ok(())
+}
+
+/// Converts budgets expressed in milliseconds to a [`Budget`].
+///
+/// A value of 0 selects the default budget.
+pub fn budget_from_ms(ack_deadline_ms: u64, cpu_ms: u64) -> Budget {
+    // SAFETY:
+    //  The default budget is only modified during boot.
+    let default = unsafe { DEFAULT_BUDGET };
+
+    Budget {
+        ack_deadline: match ack_deadline_ms {
+            0 => default.ack_deadline,
+            ms => ms_to_ticks(ms),
+        },
+        cpu: match cpu_ms {
+            0 => default.cpu,
+            ms => ms_to_ticks(ms),
+        },
+    }
+}
+
+/// Cancels the timeout of the pending interrupt of the provided line, if any.
+fn cancel_timeout(line: &mut Line) {
+    if let Some(handle) = line.pending.as_mut().and_then(|p| p.timeout.take()) {
+        timeout::cancel(handle);
+    }
+}
+
+/// Arms the timeout of the pending interrupt of the line with the provided ID, so that it
+/// expires once the ack deadline of the line has elapsed since `now`.
+fn arm_timeout(index: usize, line: &mut Line, now: u64) {
+    cancel_timeout(line);
+
+    if let Some(pending) = &mut line.pending {
+        let deadline = now.saturating_add(line.budget.ack_deadline);
+        pending.timeout = timeout::arm(Target::IrqLine(index), deadline);
+    }
+}
+
+/// Masks and removes the bindings for which `f` returns `true`.
+fn unbind_where(mut f: impl FnMut(&Line) -> bool) {
+    // SAFETY:
+    //  The line table is never accessed concurrently.
+    for slot in unsafe { LINES.iter_mut() } {
+        if slot.as_ref().is_some_and(&mut f) {
+            let mut line = slot.take().unwrap();
+            cancel_timeout(&mut line);
+            let _ = ioapic::set_masked(line.gsi, true);
+        }
+    }
+}
+
+/// Removes the binding of the provided device, if any.
+///
+/// This function is called when the device is released.
+pub fn unbind_device(device: usize) {
+    unbind_where(|line| line.device == device);
+}
+
+/// Removes the bindings of the provided process.
+///
+/// This function is called when the process exits.
+pub fn release_owned_by(owner: ProcessId) {
+    unbind_where(|line| line.owner == owner);
+}
+
+/// Sends an [`IrqViolationNotice`] to the supervisor of the driver that owns the provided line.
+fn report(index: usize, line: &Line, violation: IrqViolation) {
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    let Some(port) = (unsafe { process::get(line.owner) }).and_then(|p| p.restart_port) else {
+        log::warn!("Process {} has no supervisor to report to.", line.owner);
+        return;
+    };
+
+    let notice = IrqViolationNotice {
+        process: line.owner.get(),
+        device: line.device,
+        violation: violation as u32,
+        line: index as u32,
+    };
+
+    if ipc::post(port, Message::from_kernel(&notice)).is_err() {
+        log::warn!(
+            "The budget violation of process {} could not be reported to its supervisor.",
+            line.owner,
+        );
+    }
+}
+
+/// Unbinds the provided line, and revokes its device from the driver.
+fn revoke(mut line: Line) {
+    cancel_timeout(&mut line);
+    let _ = ioapic::set_masked(line.gsi, true);
+
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    if let Some(process) = unsafe { process::get(line.owner) } {
+        process.unmap_device(line.device);
+    }
+
+    // The device remains masked in its command register until it is bound again.
+    if let Some(device) = public_device(line.device) {
+        let _ = device
+            .owned_by
+            .compare_exchange(line.owner.get() as u64, 0, AcqRel, Relaxed);
+    }
+
+    log::warn!(
+        "Revoked device {} from process {}, which exceeded its interrupt budget.",
+        line.device,
+        line.owner,
+    );
+}
+
+/// Masks the device of the line with the provided ID, whose driver exceeded one of its budgets,
+/// and starts the escalation.
+fn escalate(index: usize, line: &mut Line, violation: IrqViolation, now: u64) {
+    let Some(pending) = &mut line.pending else {
+        return;
+    };
+
+    log::warn!(
+        "Process {} exceeded its budget for device {} ({:?}).",
+        line.owner,
+        line.device,
+        violation,
+    );
+
+    pending.stage = Stage::Masked(violation);
+
+    if let Some(address) = config_address(line.device) {
+        // SAFETY:
+        //  The configuration space is never accessed concurrently.
+        unsafe { pci::set_interrupts_disabled(address, true) };
+    }
+
+    arm_timeout(index, line, now);
+}
+
+/// Accounts for the CPU time consumed by the drivers, and escalates when they exceed their CPU
+/// budget.
+///
+/// This function is called on every tick by the scheduler.
+pub fn tick(now: u64) {
+    let current = percpu::current_process();
+
+    // SAFETY:
+    //  The line table is never accessed concurrently.
+    for (index, slot) in unsafe { LINES.iter_mut() }.enumerate() {
+        let Some(line) = slot else { continue };
+        let Some(pending) = &mut line.pending else {
+            continue;
+        };
+
+        if current == Some(line.owner) {
+            pending.cpu += 1;
+        }
+
+        if pending.stage == Stage::Handling && pending.cpu >= line.budget.cpu {
+            escalate(index, line, IrqViolation::CpuBudget, now);
+        }
+    }
+}
+
+/// Moves the escalation of the pending interrupt of the line with the provided ID to its next
+/// step.
+///
+/// This function is called by the scheduler when the timeout of the line expires: the driver
+/// has not acknowledged the interrupt within its ack deadline.
+pub fn ack_overdue(index: usize, now: u64) {
+    // SAFETY:
+    //  The line table is never accessed concurrently.
+    let Some(slot) = (unsafe { LINES.get_mut(index) }) else {
+        return;
+    };
+    let Some(line) = slot else { return };
+    let Some(pending) = &mut line.pending else {
+        return;
+    };
+
+    pending.timeout = None;
+
+    match pending.stage {
+        Stage::Handling => escalate(index, line, IrqViolation::AckDeadline, now),
+        Stage::Masked(violation) => {
+            pending.stage = Stage::Reported;
+            report(index, line, violation);
+            arm_timeout(index, line, now);
+        }
+        Stage::Reported => {
+            if let Some(line) = slot.take() {
+                revoke(line);
+            }
+        }
+    }
+}
+
+/// Handles an interrupt raised on the line with the provided ID.
+fn interrupt(index: usize) {
+    // SAFETY:
+    //  The line table is never accessed concurrently.
+    let Some(line) = (unsafe { LINES[index].as_mut() }) else {
+        apic::send_eoi();
+        return;
+    };
+
+    // The line must be masked before the end of the interrupt is signaled, or a level-triggered
+    // line would fire again right away.
+    let _ = ioapic::set_masked(line.gsi, true);
+    apic::send_eoi();
+
+    cancel_timeout(line);
+    line.pending = Some(Pending {
+        cpu: 0,
+        stage: Stage::Handling,
+        timeout: None,
+    });
+    arm_timeout(index, line, super::scheduler::ticks());
+
+    let notice = IrqNotice {
+        line: index,
+        device: line.device,
+        timestamp: super::timer::uptime_ns(),
+        sequence: line.sequence,
+    };
+
+    line.sequence += 1;
+
+    if ipc::post(line.port, Message::from_kernel(&notice)).is_err() {
+        log::warn_ratelimited!(
+            "The interrupt of device {} could not be delivered to process {}.",
+            line.device,
+            line.owner,
+        );
+    }
+}
+
+/// Defines the handlers of the interrupt vectors of the lines.
+macro_rules! line_handlers {
+    ($($name:ident => $index:literal,)*) => {
+        $(
+            #[doc = concat!("The handler of the interrupt vector of line ", stringify!($index), ".")]
+            pub extern "x86-interrupt" fn $name(_: StackFrame) {
+                interrupt($index);
+            }
+        )*
+
+        /// The handlers of the interrupt vectors of the lines, indexed by line ID.
+        pub const HANDLERS: [extern "x86-interrupt" fn(StackFrame); MAX_IRQ_LINES] = [$($name),*];
+    };
+}
+
+line_handlers! {
+    line_0 => 0,
+    line_1 => 1,
+    line_2 => 2,
+    line_3 => 3,
+    line_4 => 4,
+    line_5 => 5,
+    line_6 => 6,
+    line_7 => 7,
+}
@@ -1,23 +1,193 @@
-use crate::x86_64::mem::{BootAllocator, OutOfMemory, PAGE_SIZE};
+//! The kernel stack.
+//!
+//! The kernel stack is mapped at a fixed virtual address, rather than accessed through the
+//! higher half direct map. The page right below it is never mapped: overflowing the stack
+//! triggers a page fault instead of silently overwriting the memory that follows. That page fault
+//! cannot be handled on the overflowed stack, and turns into a double fault, which runs on its
+//! own stack and reports the overflow.
+//!
+//! # Usage
+//!
+//! With the `stack-usage` feature, the kernel stack is filled with a poison value when it is
+//! allocated. The deepest word that no longer holds the poison is the high-water mark of the
+//! stack. It is measured every second and whenever a process exits, and a warning is logged when
+//! it exceeds [`USAGE_WARNING_PERCENT`] of the stack. This provides the data needed to tune
+//! [`KERNEL_STACK_SIZE`].
+//!
+//! The stacks of the application processors are not measured, as they never run system calls.
 
-/// The size of the kernel stack.
-pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE * 16;
+use crate::log;
+use crate::x86_64::config::KERNEL_STACK_SIZE;
+use crate::x86_64::cpu::paging::{self, PageTable};
+use crate::x86_64::mem::usage::{self, Category};
+use crate::x86_64::mem::{BootAllocator, OutOfMemory, PhysAddr, VirtAddr, PAGE_SIZE};
+use crate::x86_64::raw::PageFlags;
+use crate::x86_64::timer::TICK_FREQUENCY;
 
-/// The kernel stack top physical address.
-pub static mut KERNEL_STACK_TOP: usize = 0;
+/// The virtual address of the guard page right below the kernel stack.
+///
+/// This page is never mapped.
+pub const KERNEL_STACK_GUARD: usize = 0xFFFFFF00_00000000;
+
+/// The virtual address of the bottom of the kernel stack (its lowest address).
+pub const KERNEL_STACK_BOTTOM: usize = KERNEL_STACK_GUARD + PAGE_SIZE;
+
+/// The virtual address of the top of the kernel stack.
+pub const KERNEL_STACK_TOP: usize = KERNEL_STACK_BOTTOM + KERNEL_STACK_SIZE;
+
+/// The value written at the bottom of the kernel stack when the `paranoid` feature is enabled.
+const CANARY: u64 = 0xCA4A_12E5_DEAD_57AC;
+
+/// The number of words of the bottom of the kernel stack that hold the [`CANARY`].
+const CANARY_WORDS: usize = 16;
+
+/// Whether the `stack-usage` feature is enabled.
+const MEASURE_USAGE: bool = cfg!(feature = "stack-usage");
+
+/// The value written to the kernel stack when the `stack-usage` feature is enabled.
+const POISON: u64 = 0x5A5A_5A5A_5A5A_5A5A;
+
+/// The usage of the kernel stack, in percent of its size, above which warnings are logged.
+pub const USAGE_WARNING_PERCENT: usize = 75;
+
+/// The highest usage of the kernel stack reported so far, in bytes.
+static mut REPORTED_USAGE: usize = 0;
+
+/// The tick at which the usage of the kernel stack was last measured by [`tick`].
+static mut LAST_MEASUREMENT: u64 = 0;
+
+/// Checks that the canary at the bottom of the kernel stack is intact.
+///
+/// This does nothing unless the `paranoid` feature is enabled.
+pub fn check_canary() {
+    if !crate::paranoid::ENABLED {
+        return;
+    }
+
+    // SAFETY:
+    //  The kernel stack is mapped in the kernel address space.
+    let words =
+        unsafe { core::slice::from_raw_parts(KERNEL_STACK_BOTTOM as *const u64, CANARY_WORDS) };
+
+    crate::paranoid::check!(
+        words.iter().all(|&word| word == CANARY),
+        "the canary of the kernel stack has been overwritten",
+    );
+}
+
+/// Returns the number of bytes of the kernel stack that have been used since boot.
+///
+/// This is only meaningful when the `stack-usage` feature is enabled.
+fn high_water_mark() -> usize {
+    // SAFETY:
+    //  The kernel stack is mapped in the kernel address space. The words below the stack
+    //  pointer are only read.
+    let words = unsafe {
+        core::slice::from_raw_parts(
+            KERNEL_STACK_BOTTOM as *const u64,
+            KERNEL_STACK_SIZE / core::mem::size_of::<u64>(),
+        )
+    };
+
+    let untouched = words[CANARY_WORDS..]
+        .iter()
+        .position(|&word| word != POISON)
+        .map_or(words.len(), |index| CANARY_WORDS + index);
+
+    KERNEL_STACK_SIZE - untouched * core::mem::size_of::<u64>()
+}
+
+/// Measures the high-water mark of the kernel stack, and logs a warning when it exceeds
+/// [`USAGE_WARNING_PERCENT`] of the stack for the first time, or grows past the last reported
+/// value.
+///
+/// This does nothing unless the `stack-usage` feature is enabled.
+pub fn check_usage() {
+    if !MEASURE_USAGE {
+        return;
+    }
+
+    let used = high_water_mark();
+
+    // SAFETY:
+    //  This is only accessed with interrupts disabled, on the bootstrap CPU.
+    let reported = unsafe { &mut *core::ptr::addr_of_mut!(REPORTED_USAGE) };
+
+    if used <= *reported || used * 100 < KERNEL_STACK_SIZE * USAGE_WARNING_PERCENT {
+        return;
+    }
+
+    *reported = used;
+
+    log::warn!(
+        "The kernel stack is {}% used ({} of {} bytes).",
+        used * 100 / KERNEL_STACK_SIZE,
+        used,
+        KERNEL_STACK_SIZE,
+    );
+}
+
+/// Measures the usage of the kernel stack once per second.
+///
+/// This function is called on every tick by the scheduler.
+pub fn tick(now: u64) {
+    if !MEASURE_USAGE {
+        return;
+    }
+
+    // SAFETY:
+    //  This is only accessed with interrupts disabled, on the bootstrap CPU.
+    let last = unsafe { &mut *core::ptr::addr_of_mut!(LAST_MEASUREMENT) };
+
+    // The timer may account for several ticks at once (see `timer::set_mode`).
+    if now - *last >= TICK_FREQUENCY as u64 {
+        *last = now;
+        check_usage();
+    }
+}
 
-/// Initializes the kernel stack.
+/// Allocates the kernel stack and maps it in the provided address space.
+///
+/// # Returns
+///
+/// The physical address of the top of the kernel stack.
 ///
 /// # Safety
 ///
 /// This function must be called once.
-pub unsafe fn init(boot_allocator: &mut BootAllocator) -> Result<usize, OutOfMemory> {
-    let base = boot_allocator.allocate(KERNEL_STACK_SIZE, 1)?;
-    let top = base + KERNEL_STACK_SIZE;
+///
+/// `l4_table` must be the physical address of the kernel address space, and `direct_map` must
+/// be the offset of the currently loaded direct map.
+pub unsafe fn init(
+    boot_allocator: &mut BootAllocator,
+    l4_table: PhysAddr,
+    direct_map: usize,
+) -> Result<PhysAddr, OutOfMemory> {
+    let base = boot_allocator.allocate(KERNEL_STACK_SIZE, PAGE_SIZE)?;
+    usage::record(Category::Stacks, KERNEL_STACK_SIZE);
 
     unsafe {
-        KERNEL_STACK_TOP = top;
+        paging::create_direct_map(
+            &mut *((l4_table.get() + direct_map) as *mut PageTable),
+            direct_map,
+            &mut || boot_allocator.allocate_page_table(),
+            base,
+            VirtAddr::new(KERNEL_STACK_BOTTOM),
+            KERNEL_STACK_SIZE,
+            PageFlags::WRITABLE | PageFlags::GLOBAL | PageFlags::NO_EXECUTE,
+        )?;
+
+        let bottom = (base.get() + direct_map) as *mut u64;
+
+        if MEASURE_USAGE {
+            let words = KERNEL_STACK_SIZE / core::mem::size_of::<u64>();
+            core::slice::from_raw_parts_mut(bottom, words).fill(POISON);
+        }
+
+        if crate::paranoid::ENABLED {
+            core::slice::from_raw_parts_mut(bottom, CANARY_WORDS).fill(CANARY);
+        }
     }
 
-    Ok(top)
+    Ok(base + KERNEL_STACK_SIZE)
 }
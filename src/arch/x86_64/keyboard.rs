@@ -0,0 +1,336 @@
+//! A driver for the PS/2 keyboard.
+//!
+//! The 8042 controller is configured to translate the scancodes of the keyboard to scancode set
+//! 1, and to raise ISA IRQ 1 when a byte is available. The interrupt handler decodes the
+//! scancodes into [`KeyEvent`]s and pushes them to a queue, from which processes read them with
+//! the `read_input_events` system call.
+//!
+//! The queue is shared by every reader. When it is full, the oldest events are dropped: their
+//! loss is visible as a gap in the sequence numbers of the events.
+//!
+//! # Limitations
+//!
+//! - Only the first port of the controller is used. The second one, usually connected to a
+//!   mouse, is disabled.
+//! - The keyboard is used as configured by the firmware. Its LEDs are never updated.
+//! - The pause key, which has no release code, is ignored.
+
+use core::ptr::addr_of_mut;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Release};
+
+use fabric_sys::x86_64::{KeyEvent, KeyFlags};
+
+use crate::log;
+use crate::utility::{OverflowPolicy, RingBuffer};
+
+use super::acpi;
+use super::cpu::apic;
+use super::cpu::idt::KEYBOARD_VECTOR;
+use super::cpu::ioapic::{self, Polarity, TriggerMode};
+use super::instr::{inb, outb};
+use super::process::{self, ProcessState};
+use super::raw::StackFrame;
+use super::scheduler;
+use super::timer;
+
+/// The data port of the controller.
+const DATA: u16 = 0x60;
+/// The status register of the controller, when read.
+const STATUS: u16 = 0x64;
+/// The command register of the controller, when written.
+const COMMAND: u16 = 0x64;
+
+/// Set in [`STATUS`] when a byte can be read from [`DATA`].
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Set in [`STATUS`] while the controller has not consumed the last byte written to it.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+/// Reads the configuration byte of the controller.
+const COMMAND_READ_CONFIG: u8 = 0x20;
+/// Writes the configuration byte of the controller.
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+/// Disables the second port.
+const COMMAND_DISABLE_PORT2: u8 = 0xA7;
+/// Disables the first port.
+const COMMAND_DISABLE_PORT1: u8 = 0xAD;
+/// Enables the first port.
+const COMMAND_ENABLE_PORT1: u8 = 0xAE;
+
+/// Set in the configuration byte when the first port raises IRQ 1.
+const CONFIG_PORT1_INTERRUPT: u8 = 1 << 0;
+/// Set in the configuration byte when the second port raises IRQ 12.
+const CONFIG_PORT2_INTERRUPT: u8 = 1 << 1;
+/// Set in the configuration byte when the clock of the first port is disabled.
+const CONFIG_PORT1_CLOCK_DISABLED: u8 = 1 << 4;
+/// Set in the configuration byte when the scancodes of the first port are translated to
+/// scancode set 1.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// The number of times the status register is polled before the controller is considered
+/// unresponsive.
+const POLL_COUNT: usize = 100_000;
+
+/// The maximum number of bytes flushed from the controller during initialization.
+const MAX_STALE_BYTES: usize = 16;
+
+/// The number of events that the queue can hold.
+const QUEUE_CAPACITY: usize = 64;
+
+/// The prefix of the scancodes of the extended keys.
+const PREFIX_EXTENDED: u8 = 0xE0;
+/// The prefix of the sequence sent by the pause key.
+const PREFIX_PAUSE: u8 = 0xE1;
+/// The number of bytes that follow [`PREFIX_PAUSE`].
+const PAUSE_LENGTH: u8 = 5;
+/// Set in a scancode when the key is released.
+const RELEASE_BIT: u8 = 0x80;
+
+/// Whether a PS/2 keyboard has been initialized.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// The state of the decoder of the scancodes.
+struct Decoder {
+    /// Whether the last byte was [`PREFIX_EXTENDED`].
+    extended: bool,
+    /// The number of bytes of the current pause sequence that remain to be ignored.
+    skip: u8,
+    /// The sequence number of the next event.
+    sequence: u32,
+}
+
+/// The decoder of the scancodes.
+///
+/// Only accessed by the interrupt handler, which is never reentered.
+static mut DECODER: Decoder = Decoder {
+    extended: false,
+    skip: 0,
+    sequence: 0,
+};
+
+/// The events that have not been read yet.
+///
+/// Interrupts are disabled while system calls are handled, so the queue is never accessed
+/// concurrently.
+static mut QUEUE: RingBuffer<KeyEvent, QUEUE_CAPACITY> = RingBuffer::new();
+
+/// Waits until the controller is ready to accept a byte, then writes it to `port`.
+///
+/// # Returns
+///
+/// `false` is returned if the controller never became ready.
+///
+/// # Safety
+///
+/// The controller must not be accessed concurrently.
+unsafe fn write(port: u16, value: u8) -> bool {
+    for _ in 0..POLL_COUNT {
+        if unsafe { inb(STATUS) } & STATUS_INPUT_FULL == 0 {
+            unsafe { outb(port, value) };
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+
+    false
+}
+
+/// Waits until the controller has a byte available, then reads it.
+///
+/// # Safety
+///
+/// The controller must not be accessed concurrently.
+unsafe fn read() -> Option<u8> {
+    for _ in 0..POLL_COUNT {
+        if unsafe { inb(STATUS) } & STATUS_OUTPUT_FULL != 0 {
+            return Some(unsafe { inb(DATA) });
+        }
+        core::hint::spin_loop();
+    }
+
+    None
+}
+
+/// Configures the controller so that the first port raises interrupts with translated
+/// scancodes.
+///
+/// # Safety
+///
+/// The controller must not be accessed concurrently.
+unsafe fn configure() -> Option<()> {
+    unsafe {
+        // The ports are disabled while the controller is configured, so that the keyboard does
+        // not interfere with the responses of the controller.
+        write(COMMAND, COMMAND_DISABLE_PORT1).then_some(())?;
+        write(COMMAND, COMMAND_DISABLE_PORT2).then_some(())?;
+
+        for _ in 0..MAX_STALE_BYTES {
+            if inb(STATUS) & STATUS_OUTPUT_FULL == 0 {
+                break;
+            }
+            inb(DATA);
+        }
+
+        write(COMMAND, COMMAND_READ_CONFIG).then_some(())?;
+        let mut config = read()?;
+
+        config |= CONFIG_PORT1_INTERRUPT | CONFIG_TRANSLATION;
+        config &= !(CONFIG_PORT2_INTERRUPT | CONFIG_PORT1_CLOCK_DISABLED);
+
+        write(COMMAND, COMMAND_WRITE_CONFIG).then_some(())?;
+        write(DATA, config).then_some(())?;
+
+        write(COMMAND, COMMAND_ENABLE_PORT1).then_some(())
+    }
+}
+
+/// Initializes the PS/2 keyboard, if the system has one.
+///
+/// # Safety
+///
+/// This function must only be called once, after the I/O APICs have been initialized.
+pub unsafe fn init() {
+    // Reading a port that no device decodes returns all ones.
+    if unsafe { inb(STATUS) } == 0xFF {
+        log::trace!("No PS/2 controller found.");
+        return;
+    }
+
+    if unsafe { configure() }.is_none() {
+        log::warn!("The PS/2 controller is unresponsive. The keyboard will not be available.");
+        return;
+    }
+
+    let (gsi, polarity, trigger) =
+        acpi::resolve_isa_irq(1, Polarity::ActiveHigh, TriggerMode::Edge);
+
+    let routed = ioapic::route(
+        gsi,
+        KEYBOARD_VECTOR as u8,
+        polarity,
+        trigger,
+        apic::local_apic_id(),
+    );
+
+    if routed.is_err() {
+        log::warn!(
+            "The PS/2 keyboard (GSI {}) is not handled by any I/O APIC.",
+            gsi
+        );
+        return;
+    }
+
+    PRESENT.store(true, Release);
+
+    log::trace!("PS/2 keyboard enabled (GSI {}).", gsi);
+}
+
+/// Returns whether a PS/2 keyboard has been initialized.
+#[inline]
+pub fn is_present() -> bool {
+    PRESENT.load(Acquire)
+}
+
+/// Decodes the provided byte, received from the keyboard.
+///
+/// `None` is returned when the byte does not complete a key event.
+fn decode(decoder: &mut Decoder, byte: u8) -> Option<KeyEvent> {
+    if decoder.skip != 0 {
+        decoder.skip -= 1;
+        return None;
+    }
+
+    match byte {
+        PREFIX_EXTENDED => {
+            decoder.extended = true;
+            return None;
+        }
+        PREFIX_PAUSE => {
+            decoder.skip = PAUSE_LENGTH;
+            return None;
+        }
+        // Errors, and the responses of the keyboard to commands.
+        0x00 | 0xEE | 0xFA | 0xFE | 0xFF => return None,
+        _ => (),
+    }
+
+    let extended = core::mem::take(&mut decoder.extended);
+    let scancode = byte & !RELEASE_BIT;
+
+    // Some keys are sent along with fake presses of the shift keys, which are not actual keys.
+    if extended && matches!(scancode, 0x2A | 0x36) {
+        return None;
+    }
+
+    let mut flags = KeyFlags::empty();
+    flags.set(KeyFlags::RELEASED, byte & RELEASE_BIT != 0);
+    flags.set(KeyFlags::EXTENDED, extended);
+
+    let event = KeyEvent {
+        timestamp: timer::uptime_ns(),
+        sequence: decoder.sequence,
+        scancode,
+        reserved: 0,
+        flags,
+    };
+
+    decoder.sequence = decoder.sequence.wrapping_add(1);
+
+    Some(event)
+}
+
+/// Removes the oldest event from the queue.
+pub fn pop() -> Option<KeyEvent> {
+    // SAFETY:
+    //  The queue is never accessed concurrently.
+    unsafe { (*addr_of_mut!(QUEUE)).pop() }
+}
+
+/// Returns whether the queue has no event.
+pub fn is_empty() -> bool {
+    // SAFETY:
+    //  The queue is never accessed concurrently.
+    unsafe { (*addr_of_mut!(QUEUE)).is_empty() }
+}
+
+/// Wakes up the processes waiting for an input event.
+fn wake_readers() {
+    // SAFETY:
+    //  Interrupts only occur when no reference into the process table is alive.
+    for (id, process) in unsafe { process::iter() } {
+        if process.state == ProcessState::ReadingInput {
+            process.state = ProcessState::Runnable;
+            process.cancel_timeout();
+            scheduler::enqueue(id);
+        }
+    }
+}
+
+/// The handler of the [`KEYBOARD_VECTOR`] interrupt.
+pub extern "x86-interrupt" fn interrupt(_: StackFrame) {
+    // SAFETY:
+    //  The interrupt handler is never reentered.
+    let decoder = unsafe { &mut *addr_of_mut!(DECODER) };
+    // SAFETY:
+    //  The queue is never accessed concurrently.
+    let queue = unsafe { &mut *addr_of_mut!(QUEUE) };
+
+    let mut received = false;
+
+    // SAFETY:
+    //  The controller is only accessed by this handler once it has been initialized.
+    while unsafe { inb(STATUS) } & STATUS_OUTPUT_FULL != 0 {
+        let byte = unsafe { inb(DATA) };
+
+        if let Some(event) = decode(decoder, byte) {
+            let _ = queue.push(event, OverflowPolicy::DropOldest);
+            received = true;
+        }
+    }
+
+    apic::send_eoi();
+
+    if received {
+        wake_readers();
+    }
+}
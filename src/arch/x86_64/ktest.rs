@@ -0,0 +1,700 @@
+//! Self-tests of the kernel, enabled by the `ktest` feature.
+//!
+//! Some properties of the kernel can only be checked on a running system. When the feature is
+//! enabled, the tests listed in [`TESTS`] run at the end of the boot process, once the init
+//! process and the modules have been loaded, but before any of them has been scheduled.
+//!
+//! A test is a function that panics when it fails, which takes the whole system down. The result
+//! of each test is logged on its own line, which external tooling looks for in the serial output:
+//!
+//! ```text
+//! ktest: layout_checksum ... ok
+//! ktest: umip ... ok
+//! ktest: address_space_teardown ... ok
+//! ktest: fault_injection_load ... ok
+//! ktest: fault_injection_map_memory ... ok
+//! ktest: fault_injection_inbox ... ok
+//! ktest: serial_throughput: 4096 bytes in <t1> us (<t2> us unbatched)
+//! ktest: serial_throughput ... ok
+//! ktest: syscall_latency ... ok
+//! ktest: umip_sgdt ... ok
+//! ktest: umip_sidt ... ok
+//! ktest: syscall_latency: 10000 calls in <t3> us (<t4> ns per call)
+//! ktest: syscall_latency_probe ... ok
+//! ktest: done (11 passed)
+//! ```
+//!
+//! # Probes
+//!
+//! Tests that check how the kernel treats userspace start small processes, called probes, with
+//! [`spawn_probe`]. Such a test only completes when its probes exit, which happens once the boot
+//! process is over and the scheduler runs them. The exit reason of each probe is checked by
+//! [`on_exit`], and `ktest: done` is logged once the last one has exited.
+//!
+//! A probe may leave a result for the kernel to report: the 8 bytes at its initial stack pointer
+//! are read when it exits, before its memory is freed.
+
+use core::mem::size_of;
+
+use fabric_sys::x86_64::{FaultPoint, MapFlags, Syscall};
+use fabric_sys::{InitHeader, ProcessId};
+
+use crate::log;
+
+use super::cpu::paging::{self, UpperHalfAddressSpaceTok};
+use super::fault_injection;
+use super::instr;
+use super::mem::{MemoryTrackerTok, Page, VirtAddr, HHDM_OFFSET, PAGE_SIZE};
+use super::process::{self, Backing, ExitReason, MemoryMap, Process, Region, INBOX_ADDRESS};
+use super::raw::Cr4;
+use super::serial::SerialTok;
+use super::timer;
+
+/// A self-test of the kernel.
+struct Test {
+    /// The name of the test, as it appears in the log.
+    name: &'static str,
+    /// Runs the test, panicking if it fails.
+    run: fn(),
+}
+
+/// The self-tests of the kernel, in the order in which they run.
+static TESTS: &[Test] = &[
+    Test {
+        name: "layout_checksum",
+        run: layout_checksum,
+    },
+    Test {
+        name: "umip",
+        run: umip,
+    },
+    Test {
+        name: "address_space_teardown",
+        run: address_space_teardown,
+    },
+    Test {
+        name: "fault_injection_load",
+        run: fault_injection_load,
+    },
+    Test {
+        name: "fault_injection_map_memory",
+        run: fault_injection_map_memory,
+    },
+    Test {
+        name: "fault_injection_inbox",
+        run: fault_injection_inbox,
+    },
+    Test {
+        name: "serial_throughput",
+        run: serial_throughput,
+    },
+    Test {
+        name: "syscall_latency",
+        run: syscall_latency,
+    },
+];
+
+/// The maximum number of probes that may be running at the same time.
+const MAX_PROBES: usize = 4;
+
+/// A process started by a test, whose exit completes it.
+struct Probe {
+    /// The name of the probe, as it appears in the log.
+    name: &'static str,
+    /// The ID of the process.
+    id: ProcessId,
+    /// The reason why the process is expected to exit.
+    expected: ExitReason,
+    /// The initial stack pointer of the process, where it may leave a result.
+    stack_pointer: usize,
+    /// The function that reports the result of the probe, if it leaves one.
+    report: Option<fn(u64)>,
+}
+
+/// The state of the self-tests.
+struct State {
+    /// The probes that have not exited yet.
+    probes: [Option<Probe>; MAX_PROBES],
+    /// The number of tests and probes that have passed.
+    passed: usize,
+    /// Whether every test listed in [`TESTS`] has run.
+    finished: bool,
+}
+
+/// The global state of the self-tests.
+static mut STATE: State = State {
+    probes: [const { None }; MAX_PROBES],
+    passed: 0,
+    finished: false,
+};
+
+/// Returns the global state of the self-tests.
+fn state() -> &'static mut State {
+    // SAFETY:
+    //  The kernel is not re-entrant, and the state is never accessed concurrently.
+    unsafe { &mut *core::ptr::addr_of_mut!(STATE) }
+}
+
+/// Runs the self-tests of the kernel.
+///
+/// See the [module-level documentation](self) for more information.
+pub fn run() {
+    log::info!("ktest: running {} tests", TESTS.len());
+
+    for test in TESTS {
+        (test.run)();
+        log::info!("ktest: {} ... ok", test.name);
+        state().passed += 1;
+    }
+
+    state().finished = true;
+    report_if_done();
+}
+
+/// Checks the exit reason of the provided process if it is a probe, and reports its result.
+///
+/// This function is called by [`process::terminate`] right before the address space of the
+/// process is destroyed.
+///
+/// # Panics
+///
+/// This function panics if the process is a probe that exited for another reason than expected.
+pub fn on_exit(id: ProcessId, process: &Process, reason: ExitReason) {
+    let state = state();
+
+    let Some(probe) = state
+        .probes
+        .iter_mut()
+        .find(|probe| probe.as_ref().is_some_and(|probe| probe.id == id))
+        .and_then(Option::take)
+    else {
+        return;
+    };
+
+    assert!(
+        reason == probe.expected,
+        "probe `{}` exited with {:?} instead of {:?}",
+        probe.name,
+        reason,
+        probe.expected,
+    );
+
+    if let Some(report) = probe.report {
+        let page = Page::containing(VirtAddr::new(probe.stack_pointer));
+
+        // SAFETY:
+        //  The address space of the process has not been destroyed yet, and the probe has left
+        //  its initial stack pointer aligned.
+        let result = unsafe {
+            let frame = paging::translate_4kib(process.l4_table(), HHDM_OFFSET, page)
+                .expect("the result of the probe is not mapped");
+            frame
+                .start()
+                .hhdm_ptr::<u8>()
+                .add(probe.stack_pointer % PAGE_SIZE)
+                .cast::<u64>()
+                .read()
+        };
+
+        report(result);
+    }
+
+    log::info!("ktest: {} ... ok", probe.name);
+    state.passed += 1;
+    report_if_done();
+}
+
+/// Logs the end of the self-tests if every test has run and every probe has exited.
+fn report_if_done() {
+    let state = state();
+
+    if state.finished && state.probes.iter().all(Option::is_none) {
+        log::info!("ktest: done ({} passed)", state.passed);
+    }
+}
+
+/// The address at which probes are loaded.
+const PROBE_ADDRESS: usize = 0x40_0000;
+
+/// The maximum size of the image of a probe, header included.
+const MAX_PROBE_SIZE: usize = 128;
+
+/// Writes to `image` a flat image whose code segment is made of `code` alone, and returns the
+/// part of `image` that holds it.
+///
+/// The entry point of the image is the first byte of `code`.
+///
+/// # Panics
+///
+/// This function panics if `code` does not fit in the image.
+fn probe_image<'a>(code: &[u8], image: &'a mut [u8; MAX_PROBE_SIZE]) -> &'a [u8] {
+    const HEADER_SIZE: usize = size_of::<InitHeader>();
+
+    let header = InitHeader {
+        magic: InitHeader::MAGIC,
+        image_start: PROBE_ADDRESS as *const (),
+        text_end: (PROBE_ADDRESS + PAGE_SIZE) as *const (),
+        data_start: (PROBE_ADDRESS + PAGE_SIZE) as *const (),
+        entry_point: (PROBE_ADDRESS + HEADER_SIZE) as *const (),
+    };
+
+    assert!(
+        HEADER_SIZE + code.len() <= MAX_PROBE_SIZE,
+        "probe too large"
+    );
+
+    // SAFETY:
+    //  The image is large enough to hold the header, and `read_unaligned` is used by the loader.
+    unsafe {
+        image
+            .as_mut_ptr()
+            .cast::<InitHeader>()
+            .write_unaligned(header)
+    };
+    image[HEADER_SIZE..HEADER_SIZE + code.len()].copy_from_slice(code);
+
+    &image[..HEADER_SIZE + code.len()]
+}
+
+/// Starts a probe running `code`, which is expected to exit for the provided reason.
+///
+/// When `report` is provided, it is called with the result left by the probe once it exits.
+///
+/// See [`probe_image`].
+///
+/// # Panics
+///
+/// This function panics if the probe cannot be started.
+fn spawn_probe(name: &'static str, code: &[u8], expected: ExitReason, report: Option<fn(u64)>) {
+    let mut image = [0; MAX_PROBE_SIZE];
+
+    // SAFETY:
+    //  The kernel address space is initialized before the self-tests run.
+    let upper_half = unsafe { UpperHalfAddressSpaceTok::unchecked() };
+
+    let process = match process::load_from(probe_image(code, &mut image), b"", upper_half) {
+        Ok(process) => process,
+        Err(err) => panic!("failed to load probe `{}`: {}", name, err.message()),
+    };
+    let stack_pointer = process.context.rsp as usize;
+
+    let id = match process::start(process) {
+        Ok(id) => id,
+        Err(err) => panic!("failed to start probe `{}`: {}", name, err.message()),
+    };
+
+    let slot = state()
+        .probes
+        .iter_mut()
+        .find(|probe| probe.is_none())
+        .expect("too many probes are running");
+
+    *slot = Some(Probe {
+        name,
+        id,
+        expected,
+        stack_pointer,
+        report,
+    });
+}
+
+/// The expected value of [`fabric_sys::layout::checksum`].
+///
+/// Changing the layout of a structure shared with userspace is a breaking change of the ABI of
+/// the kernel. This value must be updated along with it.
+const LAYOUT_CHECKSUM: u64 = 0x76c6_7507_4d13_4535;
+
+/// Checks that the layout of the structures shared with userspace has not changed.
+fn layout_checksum() {
+    let checksum = fabric_sys::layout::checksum();
+
+    assert!(
+        checksum == LAYOUT_CHECKSUM,
+        "the layout of the structures shared with userspace has changed (checksum = {:#x})",
+        checksum,
+    );
+}
+
+/// The vector of general protection faults.
+const GENERAL_PROTECTION_FAULT: u8 = 13;
+
+/// Checks that the instructions restricted by UMIP fault when they are executed by a process.
+///
+/// Each instruction stores its table descriptor below the stack pointer, and is followed by
+/// `ud2`. A probe that was not stopped exits with an invalid opcode exception instead, which
+/// [`on_exit`] reports as a failure.
+fn umip() {
+    if instr::cr4() & Cr4::UMIP.bits() == 0 {
+        log::info!("ktest: umip skipped (not supported by the CPU)");
+        return;
+    }
+
+    let expected = ExitReason::Exception {
+        vector: GENERAL_PROTECTION_FAULT,
+    };
+
+    // sgdt [rsp - 16]
+    // ud2
+    spawn_probe(
+        "umip_sgdt",
+        &[0x0f, 0x01, 0x44, 0x24, 0xf0, 0x0f, 0x0b],
+        expected,
+        None,
+    );
+
+    // sidt [rsp - 16]
+    // ud2
+    spawn_probe(
+        "umip_sidt",
+        &[0x0f, 0x01, 0x4c, 0x24, 0xf0, 0x0f, 0x0b],
+        expected,
+        None,
+    );
+}
+
+/// Checks that destroying the address space of a process gives back every frame that it used,
+/// including the frames of the page tables.
+///
+/// The process is loaded but never started. Pages of its stack are populated and unmapped
+/// before its address space is destroyed, so that the frames released by both paths are
+/// accounted for.
+fn address_space_teardown() {
+    // SAFETY:
+    //  The memory tracker is initialized before the self-tests run.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+
+    let before = memory_tracker.lock().free_page_count();
+
+    let mut process = load_idle_process();
+
+    let stack = *process
+        .memory_map
+        .regions()
+        .iter()
+        .find(|region| region.backing == Backing::Stack)
+        .expect("the process has no stack");
+
+    // The pages right above the guard page are far from the arguments at the top of the stack,
+    // and are not mapped yet.
+    let bottom = stack.start + PAGE_SIZE;
+    for address in [bottom, bottom + PAGE_SIZE, bottom + 2 * PAGE_SIZE] {
+        // SAFETY:
+        //  The process has never been started.
+        assert!(
+            unsafe { process.populate(address) },
+            "failed to populate {:#x}",
+            address
+        );
+    }
+
+    assert!(memory_tracker.lock().free_page_count() < before);
+
+    process
+        .unmap_range(&mut memory_tracker.lock(), bottom + PAGE_SIZE, PAGE_SIZE)
+        .expect("failed to unmap a page of the stack");
+
+    // SAFETY:
+    //  The address space of the process has never been loaded.
+    unsafe { process.destroy_address_space(&mut memory_tracker.lock()) };
+
+    assert_free_pages(before);
+}
+
+/// Checks that the number of free frames is back to `before`.
+///
+/// # Panics
+///
+/// This function panics if frames were leaked or freed twice.
+fn assert_free_pages(before: usize) {
+    // SAFETY:
+    //  The memory tracker is initialized before the self-tests run.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+
+    let after = memory_tracker.lock().free_page_count();
+    assert!(
+        after == before,
+        "{} frames were leaked",
+        before as isize - after as isize,
+    );
+}
+
+/// Checks that the memory map of `process` holds the same regions as `expected`.
+fn assert_same_regions(process: &Process, expected: &MemoryMap) {
+    let (a, b) = (process.memory_map.regions(), expected.regions());
+
+    let same = a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.start == b.start
+                && a.length == b.length
+                && a.flags.bits() == b.flags.bits()
+                && a.backing == b.backing
+        });
+
+    assert!(same, "the memory map was left in a partial state");
+}
+
+/// The maximum number of calls to a fault point that an operation tested by [`sweep`] may make.
+const MAX_SWEEP: usize = 64;
+
+/// Runs `attempt` with each of the fault points armed at its first call, then at its second
+/// call, and so on, until it succeeds.
+///
+/// `attempt` returns whether the operation succeeded, and checks that nothing was leaked in both
+/// cases.
+///
+/// # Panics
+///
+/// This function panics if the operation still fails after [`MAX_SWEEP`] attempts.
+fn sweep(mut attempt: impl FnMut() -> bool) {
+    for point in [FaultPoint::Allocate, FaultPoint::Map4KiB] {
+        let succeeded = (1..=MAX_SWEEP).any(|nth| {
+            fault_injection::arm(point, nth);
+            let succeeded = attempt();
+            fault_injection::arm(point, 0);
+            succeeded
+        });
+
+        assert!(succeeded, "the operation never succeeded with {:?}", point);
+    }
+}
+
+/// Loads a process that is never started, with the image used by probes.
+///
+/// # Panics
+///
+/// This function panics if the process cannot be loaded.
+fn load_idle_process() -> Process {
+    // SAFETY:
+    //  The kernel address space is initialized before the self-tests run.
+    let upper_half = unsafe { UpperHalfAddressSpaceTok::unchecked() };
+
+    // ud2
+    let mut image = [0; MAX_PROBE_SIZE];
+    match process::load_from(probe_image(&[0x0f, 0x0b], &mut image), b"", upper_half) {
+        Ok(process) => process,
+        Err(err) => panic!("failed to load the process: {}", err.message()),
+    }
+}
+
+/// Checks that a process whose creation fails because of an allocation or mapping failure does
+/// not leak any frame.
+fn fault_injection_load() {
+    // SAFETY:
+    //  The memory tracker and the kernel address space are initialized before the self-tests
+    //  run.
+    let (memory_tracker, upper_half) = unsafe {
+        (
+            MemoryTrackerTok::unchecked(),
+            UpperHalfAddressSpaceTok::unchecked(),
+        )
+    };
+
+    let before = memory_tracker.lock().free_page_count();
+
+    let mut image = [0; MAX_PROBE_SIZE];
+    let image = probe_image(&[0x0f, 0x0b], &mut image);
+
+    sweep(|| {
+        let loaded = match process::load_from(image, b"", upper_half) {
+            Ok(mut process) => {
+                // SAFETY:
+                //  The address space of the process has never been loaded.
+                unsafe { process.destroy_address_space(&mut memory_tracker.lock()) };
+                true
+            }
+            Err(_) => false,
+        };
+
+        assert_free_pages(before);
+        loaded
+    });
+}
+
+/// Checks that a `map_memory` call that fails because of an allocation or mapping failure frees
+/// the pages it had already mapped, and removes its region from the memory map.
+///
+/// The page tables that cover the region are created beforehand, by mapping it once, so that
+/// every frame used by the call is accounted for.
+fn fault_injection_map_memory() {
+    // SAFETY:
+    //  The memory tracker is initialized before the self-tests run.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+
+    let mut process = load_idle_process();
+
+    let region = Region {
+        start: process.map_base,
+        length: 4 * PAGE_SIZE,
+        flags: MapFlags::WRITABLE,
+        backing: Backing::Anonymous,
+    };
+
+    process
+        .map_zeroed(&mut memory_tracker.lock(), region)
+        .expect("failed to map the region");
+    process
+        .unmap_range(&mut memory_tracker.lock(), region.start, region.length)
+        .expect("failed to unmap the region");
+
+    let before = memory_tracker.lock().free_page_count();
+    let regions = process.memory_map.clone();
+
+    sweep(|| {
+        let mapped = process
+            .map_zeroed(&mut memory_tracker.lock(), region)
+            .is_ok();
+
+        if mapped {
+            process
+                .unmap_range(&mut memory_tracker.lock(), region.start, region.length)
+                .expect("failed to unmap the region");
+        }
+
+        assert_same_regions(&process, &regions);
+        assert_free_pages(before);
+        mapped
+    });
+
+    // SAFETY:
+    //  The address space of the process has never been loaded.
+    unsafe { process.destroy_address_space(&mut memory_tracker.lock()) };
+}
+
+/// Checks that mapping the inbox of a process, through which the kernel delivers its
+/// announcements, leaves nothing behind when it fails.
+///
+/// The inbox of the process is unmapped beforehand, keeping the page table that covers it.
+fn fault_injection_inbox() {
+    // SAFETY:
+    //  The memory tracker is initialized before the self-tests run.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+
+    let mut process = load_idle_process();
+
+    process
+        .unmap_range(&mut memory_tracker.lock(), INBOX_ADDRESS, PAGE_SIZE)
+        .expect("failed to unmap the inbox");
+
+    let before = memory_tracker.lock().free_page_count();
+    let regions = process.memory_map.clone();
+
+    sweep(|| {
+        let mapped = process.map_inbox(&mut memory_tracker.lock()).is_ok();
+
+        if mapped {
+            process
+                .unmap_range(&mut memory_tracker.lock(), INBOX_ADDRESS, PAGE_SIZE)
+                .expect("failed to unmap the inbox");
+        }
+
+        assert_same_regions(&process, &regions);
+        assert_free_pages(before);
+        mapped
+    });
+
+    // SAFETY:
+    //  The address space of the process has never been loaded.
+    unsafe { process.destroy_address_space(&mut memory_tracker.lock()) };
+}
+
+/// Measures the time it takes to write to the serial port, with and without filling its
+/// transmit FIFO at once.
+///
+/// The measurements depend on the machine and on the emulator, and are only reported: this test
+/// never fails.
+fn serial_throughput() {
+    const LINES: usize = 64;
+    const LINE_LENGTH: usize = 64;
+
+    // SAFETY:
+    //  The serial port is initialized at the very beginning of the boot process.
+    let serial = unsafe { SerialTok::unchecked() };
+
+    let mut line = [b'.'; LINE_LENGTH];
+    line[LINE_LENGTH - 1] = b'\n';
+
+    let measure = |write: fn(SerialTok, &[u8])| {
+        let start = timer::uptime_ns();
+        for _ in 0..LINES {
+            write(serial, &line);
+        }
+        (timer::uptime_ns() - start) / 1000
+    };
+
+    let unbatched = measure(SerialTok::write_bytes_unbatched);
+    let batched = measure(SerialTok::write_bytes);
+
+    log::info!(
+        "ktest: serial_throughput: {} bytes in {} us ({} us unbatched)",
+        LINES * LINE_LENGTH,
+        batched,
+        unbatched,
+    );
+}
+
+/// The number of system calls made by the probe of [`syscall_latency`].
+const SYSCALL_LATENCY_CALLS: u32 = 10_000;
+
+/// Measures the time it takes for a process to perform a system call that does nothing, the
+/// `get_time` system call.
+///
+/// The probe reads the time before and after its loop with the same system call, and leaves
+/// the elapsed time, in nanoseconds, as its result. Like [`serial_throughput`], this test is
+/// only meant to compare builds (for example with and without the `quiet-syscalls` feature) and
+/// never fails.
+fn syscall_latency() {
+    let get_time = (Syscall::GetTime as u32).to_le_bytes();
+    let terminate = (Syscall::Terminate as u32).to_le_bytes();
+    let calls = SYSCALL_LATENCY_CALLS.to_le_bytes();
+
+    #[rustfmt::skip]
+    let code = [
+        // mov eax, GetTime
+        // syscall
+        // mov r12, rax
+        0xb8, get_time[0], get_time[1], get_time[2], get_time[3],
+        0x0f, 0x05,
+        0x49, 0x89, 0xc4,
+        // mov r13d, SYSCALL_LATENCY_CALLS
+        0x41, 0xbd, calls[0], calls[1], calls[2], calls[3],
+        // 2:
+        // mov eax, GetTime
+        // syscall
+        // dec r13d
+        // jnz 2b
+        0xb8, get_time[0], get_time[1], get_time[2], get_time[3],
+        0x0f, 0x05,
+        0x41, 0xff, 0xcd,
+        0x75, 0xf4,
+        // mov eax, GetTime
+        // syscall
+        // sub rax, r12
+        // mov [rsp], rax
+        0xb8, get_time[0], get_time[1], get_time[2], get_time[3],
+        0x0f, 0x05,
+        0x4c, 0x29, 0xe0,
+        0x48, 0x89, 0x04, 0x24,
+        // xor edi, edi
+        // mov eax, Terminate
+        // syscall
+        // ud2
+        0x31, 0xff,
+        0xb8, terminate[0], terminate[1], terminate[2], terminate[3],
+        0x0f, 0x05,
+        0x0f, 0x0b,
+    ];
+
+    spawn_probe(
+        "syscall_latency_probe",
+        &code,
+        ExitReason::Terminated,
+        Some(|elapsed_ns| {
+            log::info!(
+                "ktest: syscall_latency: {} calls in {} us ({} ns per call)",
+                SYSCALL_LATENCY_CALLS,
+                elapsed_ns / 1000,
+                elapsed_ns / SYSCALL_LATENCY_CALLS as u64,
+            );
+        }),
+    );
+}
@@ -0,0 +1,297 @@
+//! A lock dependency checker, enabled in debug builds.
+//!
+//! Every lock of the kernel belongs to a [`LockClass`]. Whenever a lock is acquired while other
+//! locks are held, the checker records that the class of the new lock *depends* on the classes
+//! of the held ones, along with a backtrace of the acquisition.
+//!
+//! Before a lock is acquired, the checker makes sure that none of the held classes depends,
+//! directly or not, on the class of the new lock. If one does, the two locks may be acquired in
+//! opposite orders by two execution paths, which would eventually deadlock the kernel. The
+//! kernel panics immediately, printing the backtraces of both paths, even if the deadlock did
+//! not actually happen this time.
+//!
+//! # Execution Contexts
+//!
+//! The kernel runs on a single CPU, and interrupts are disabled while it executes kernel code.
+//! There is thus a single execution context, and the locks held by it can be tracked globally.
+//!
+//! # Release Builds
+//!
+//! When debug assertions are disabled, all the functions of this module compile to nothing.
+
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+
+use crate::log;
+
+use super::backtrace;
+
+/// The maximum number of lock classes that the checker can track.
+const MAX_CLASSES: usize = 16;
+
+/// The maximum number of locks that may be held at the same time.
+const MAX_HELD: usize = 8;
+
+/// The maximum number of frames saved for each acquisition.
+const TRACE_DEPTH: usize = 8;
+
+/// A class of locks, whose acquisition order is checked.
+///
+/// Locks that protect the same kind of data should share the same class, usually stored in a
+/// `static` next to the lock type.
+pub struct LockClass {
+    /// A name for the class, printed when an issue is detected.
+    name: &'static str,
+    /// The index of the class in the dependency graph, plus one. 0 if the class has not been
+    /// registered yet.
+    index: AtomicUsize,
+}
+
+impl LockClass {
+    /// Creates a new [`LockClass`] with the provided name.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            index: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the index of the class, registering it if needed.
+    fn index(&'static self) -> usize {
+        match self.index.load(Relaxed) {
+            0 => {
+                // SAFETY:
+                //  The checker is only used by a single execution context.
+                let index = unsafe { STATE.classes_len };
+                assert!(index < MAX_CLASSES, "too many lock classes");
+
+                unsafe {
+                    STATE.classes[index] = Some(self);
+                    STATE.classes_len += 1;
+                }
+
+                self.index.store(index + 1, Relaxed);
+                index
+            }
+            index => index - 1,
+        }
+    }
+}
+
+/// A backtrace saved when a lock has been acquired.
+#[derive(Clone, Copy)]
+struct Trace {
+    frames: [usize; TRACE_DEPTH],
+    len: usize,
+}
+
+impl Trace {
+    /// Saves the backtrace of the current call stack.
+    #[inline(always)]
+    fn capture() -> Self {
+        let mut frames = [0; TRACE_DEPTH];
+        let len = backtrace::capture(&mut frames);
+        Self { frames, len }
+    }
+
+    /// Writes the backtrace to the log.
+    fn print(&self) {
+        backtrace::print_captured(&self.frames[..self.len]);
+    }
+}
+
+/// A lock currently held by the execution context.
+#[derive(Clone, Copy)]
+struct Held {
+    /// The index of the class of the lock.
+    class: usize,
+    /// Where the lock has been acquired.
+    trace: Trace,
+}
+
+/// The state of the checker.
+struct State {
+    /// The registered classes.
+    classes: [Option<&'static LockClass>; MAX_CLASSES],
+    /// The number of registered classes.
+    classes_len: usize,
+    /// `dependencies[a][b]` is set when a lock of class `b` has been acquired while a lock of
+    /// class `a` was held. It holds the backtrace of the first such acquisition.
+    dependencies: [[Option<Trace>; MAX_CLASSES]; MAX_CLASSES],
+    /// The locks currently held, in acquisition order.
+    held: [Option<Held>; MAX_HELD],
+    /// The number of locks currently held.
+    held_len: usize,
+}
+
+static mut STATE: State = State {
+    classes: [None; MAX_CLASSES],
+    classes_len: 0,
+    dependencies: [[None; MAX_CLASSES]; MAX_CLASSES],
+    held: [None; MAX_HELD],
+    held_len: 0,
+};
+
+/// Returns the name of the class with the provided index.
+fn name_of(index: usize) -> &'static str {
+    // SAFETY:
+    //  The checker is only used by a single execution context.
+    unsafe { STATE.classes[index].map_or("<unknown>", |class| class.name) }
+}
+
+/// Looks for a chain of dependencies going from `from` to `to`, writing the classes along it
+/// into `path`.
+///
+/// # Returns
+///
+/// The length of the chain, including both ends, or `None` if there is no such chain.
+fn find_path(
+    state: &State,
+    from: usize,
+    to: usize,
+    path: &mut [usize; MAX_CLASSES],
+) -> Option<usize> {
+    fn visit(
+        state: &State,
+        node: usize,
+        to: usize,
+        depth: usize,
+        visited: &mut [bool; MAX_CLASSES],
+        path: &mut [usize; MAX_CLASSES],
+    ) -> Option<usize> {
+        visited[node] = true;
+        path[depth] = node;
+
+        if node == to {
+            return Some(depth + 1);
+        }
+
+        (0..state.classes_len)
+            .filter(|&next| state.dependencies[node][next].is_some())
+            .find_map(|next| {
+                if visited[next] {
+                    None
+                } else {
+                    visit(state, next, to, depth + 1, visited, path)
+                }
+            })
+    }
+
+    let mut visited = [false; MAX_CLASSES];
+    visit(state, from, to, 0, &mut visited, path)
+}
+
+/// Reports an issue with the acquisition of a lock, along with the backtrace of the current
+/// acquisition.
+fn report(class: usize, message: &str) {
+    log::error!(
+        "Lock dependency error while acquiring `{}`: {}.",
+        name_of(class),
+        message
+    );
+    log::error!("   > Current acquisition:");
+    Trace::capture().print();
+}
+
+/// Records that a lock of the provided class is about to be acquired.
+///
+/// This must be called *before* spinning on the lock, so that issues are reported rather than
+/// deadlocking.
+///
+/// # Panics
+///
+/// This function panics if acquiring the lock may deadlock the kernel.
+#[inline(always)]
+pub fn acquire(class: &'static LockClass) {
+    if cfg!(debug_assertions) {
+        acquire_slow(class);
+    }
+}
+
+#[inline(never)]
+fn acquire_slow(class: &'static LockClass) {
+    let index = class.index();
+
+    // SAFETY:
+    //  The checker is only used by a single execution context.
+    let state = unsafe { &mut *core::ptr::addr_of_mut!(STATE) };
+
+    let held_len = state.held_len;
+    for held in state.held[..held_len].iter().flatten() {
+        if held.class == index {
+            report(index, "the lock is already held");
+            log::error!("   > Previous acquisition:");
+            held.trace.print();
+            panic!("recursive locking of `{}`", class.name);
+        }
+
+        let mut path = [0; MAX_CLASSES];
+        if let Some(len) = find_path(state, index, held.class, &mut path) {
+            report(index, "the locks are acquired in an inconsistent order");
+            log::error!("   > `{}` was held, acquired at:", name_of(held.class));
+            held.trace.print();
+
+            for pair in path[..len].windows(2) {
+                log::error!(
+                    "   > `{}` was acquired while holding `{}`, at:",
+                    name_of(pair[1]),
+                    name_of(pair[0]),
+                );
+                if let Some(trace) = &state.dependencies[pair[0]][pair[1]] {
+                    trace.print();
+                }
+            }
+
+            panic!(
+                "lock order inversion between `{}` and `{}`",
+                class.name,
+                name_of(held.class),
+            );
+        }
+    }
+
+    let trace = Trace::capture();
+
+    for held in state.held[..held_len].iter().flatten() {
+        state.dependencies[held.class][index].get_or_insert(trace);
+    }
+
+    assert!(held_len < MAX_HELD, "too many locks held at the same time");
+    state.held[held_len] = Some(Held {
+        class: index,
+        trace,
+    });
+    state.held_len += 1;
+}
+
+/// Records that a lock of the provided class has been released.
+#[inline(always)]
+pub fn release(class: &'static LockClass) {
+    if cfg!(debug_assertions) {
+        release_slow(class);
+    }
+}
+
+#[inline(never)]
+fn release_slow(class: &'static LockClass) {
+    let index = class.index();
+
+    // SAFETY:
+    //  The checker is only used by a single execution context.
+    let state = unsafe { &mut *core::ptr::addr_of_mut!(STATE) };
+
+    // Locks are usually released in the reverse order of their acquisition, but this is not
+    // required.
+    let Some(position) = state.held[..state.held_len]
+        .iter()
+        .rposition(|held| held.is_some_and(|held| held.class == index))
+    else {
+        panic!("released `{}`, which was not held", class.name);
+    };
+
+    state
+        .held
+        .copy_within(position + 1..state.held_len, position);
+    state.held_len -= 1;
+    state.held[state.held_len] = None;
+}
@@ -0,0 +1,149 @@
+//! Physical and virtual addresses.
+//!
+//! Both kinds of addresses are represented by `usize` values, which makes it easy to pass one
+//! where the other is expected. [`PhysAddr`] and [`VirtAddr`] are distinct types, and converting
+//! between them is always explicit, through the higher half direct map.
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+
+use super::{HHDM_OFFSET, PAGE_SIZE};
+use crate::utility::num;
+
+macro_rules! address_type {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(usize);
+
+        // Not every method is used by both types.
+        #[allow(dead_code)]
+        impl $name {
+            /// The address zero.
+            pub const ZERO: Self = Self(0);
+
+            /// Creates a new address from its raw value.
+            #[inline(always)]
+            pub const fn new(addr: usize) -> Self {
+                Self(addr)
+            }
+
+            /// Returns the raw value of the address.
+            #[inline(always)]
+            pub const fn get(self) -> usize {
+                self.0
+            }
+
+            /// Returns whether the address is aligned to a page boundary.
+            #[inline(always)]
+            pub const fn is_page_aligned(self) -> bool {
+                num::is_page_aligned(self.0)
+            }
+
+            /// Aligns the address to the previous page boundary.
+            #[inline(always)]
+            pub const fn page_align_down(self) -> Self {
+                Self(num::page_align_down(self.0))
+            }
+
+            /// Returns the offset of the address within its page.
+            #[inline(always)]
+            pub const fn page_offset(self) -> usize {
+                num::page_offset(self.0)
+            }
+        }
+
+        impl Add<usize> for $name {
+            type Output = Self;
+
+            #[inline(always)]
+            fn add(self, offset: usize) -> Self {
+                Self(self.0 + offset)
+            }
+        }
+
+        impl AddAssign<usize> for $name {
+            #[inline(always)]
+            fn add_assign(&mut self, offset: usize) {
+                self.0 += offset;
+            }
+        }
+
+        impl Sub for $name {
+            type Output = usize;
+
+            #[inline(always)]
+            fn sub(self, other: Self) -> usize {
+                self.0 - other.0
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, concat!(stringify!($name), "({:#x})"), self.0)
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+address_type! {
+    /// A physical address.
+    PhysAddr
+}
+
+address_type! {
+    /// A virtual address.
+    VirtAddr
+}
+
+impl PhysAddr {
+    /// Returns the virtual address at which this physical address is mapped in the higher half
+    /// direct map.
+    #[inline(always)]
+    pub const fn to_hhdm(self) -> VirtAddr {
+        VirtAddr(self.0 + HHDM_OFFSET)
+    }
+
+    /// Returns a pointer to the memory at this physical address, through the higher half direct
+    /// map.
+    #[inline(always)]
+    pub const fn hhdm_ptr<T>(self) -> *mut T {
+        self.to_hhdm().as_ptr()
+    }
+
+    /// Returns the physical address of the page frame with the provided index.
+    #[inline(always)]
+    pub const fn from_frame_index(index: usize) -> Self {
+        Self(index * PAGE_SIZE)
+    }
+
+    /// Returns the index of the page frame that contains this physical address.
+    #[inline(always)]
+    pub const fn frame_index(self) -> usize {
+        self.0 / PAGE_SIZE
+    }
+}
+
+impl VirtAddr {
+    /// Returns a pointer to the memory at this virtual address.
+    #[inline(always)]
+    pub const fn as_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    /// Returns the index of this address in the page table of the provided level.
+    ///
+    /// Level 1 is the table that maps 4 KiB pages, and level 4 is the root table.
+    #[inline(always)]
+    pub const fn table_index(self, level: u32) -> usize {
+        (self.0 >> (12 + 9 * (level - 1))) & 0o777
+    }
+}
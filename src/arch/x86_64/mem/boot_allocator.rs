@@ -1,4 +1,5 @@
-use super::OutOfMemory;
+use super::usage::{self, Category};
+use super::{OutOfMemory, PhysAddr, PAGE_SIZE};
 
 /// The "boot allocator" is responsible for allocating pages during the boot process. Pages
 /// allocated by this provider cannot be trivially deallocated (this is a bump allocator).
@@ -26,10 +27,10 @@ impl BootAllocator {
     /// This function will automatically align `base` and `length` to the page size. If an invalid
     /// value is passed, the function returns an allocator that cannot allocate anything (i.e. of
     /// length 0).
-    pub fn new(base: usize, length: usize) -> Self {
+    pub fn new(base: PhysAddr, length: usize) -> Self {
         Self {
-            start: base,
-            stop: base + length,
+            start: base.get(),
+            stop: base.get() + length,
         }
     }
 
@@ -41,8 +42,8 @@ impl BootAllocator {
 
     /// Returns the physical address of the next page that will be allocated.
     #[inline(always)]
-    pub fn peek(&self) -> usize {
-        self.start
+    pub fn peek(&self) -> PhysAddr {
+        PhysAddr::new(self.start)
     }
 
     /// Allocates zero or more bytes of physical memory.
@@ -56,9 +57,9 @@ impl BootAllocator {
     /// # Returns
     ///
     /// This function returns [`Err(_)`] if the allocation failed (i.e. there is not enough memory
-    /// in the managed block). Otherwise, it returns the address of the first allocated page in
-    /// the higher half direct map.
-    pub fn allocate(&mut self, size: usize, align: usize) -> Result<usize, OutOfMemory> {
+    /// in the managed block). Otherwise, it returns the physical address of the first allocated
+    /// byte.
+    pub fn allocate(&mut self, size: usize, align: usize) -> Result<PhysAddr, OutOfMemory> {
         debug_assert!(align.is_power_of_two());
 
         let align_mask = align - 1;
@@ -70,6 +71,13 @@ impl BootAllocator {
 
         self.start = ret + size;
 
-        Ok(ret)
+        Ok(PhysAddr::new(ret))
+    }
+
+    /// Allocates a page for a page table, and records it in the memory usage of the kernel.
+    pub fn allocate_page_table(&mut self) -> Result<PhysAddr, OutOfMemory> {
+        let page = self.allocate(PAGE_SIZE, PAGE_SIZE)?;
+        usage::record(Category::PageTables, PAGE_SIZE);
+        Ok(page)
     }
 }
@@ -2,8 +2,15 @@ use core::cell::UnsafeCell;
 use core::mem::{align_of, size_of, MaybeUninit};
 use core::ops::{Deref, DerefMut};
 
-use super::{BootAllocator, OutOfMemory, HHDM_OFFSET, PAGE_SIZE};
+use fabric_sys::x86_64::FaultPoint;
+
+use super::usage::{self, Category};
+use super::{BootAllocator, Frame, OutOfMemory, PhysAddr, PAGE_SIZE};
+use crate::kassert::{kassert, kensure};
+use crate::paranoid::LockLevel;
 use crate::utility::RawEpochMutex;
+use crate::x86_64::fault_injection;
+use crate::x86_64::lockdep::{self, LockClass};
 
 /// Stores metadata about a page.
 #[repr(C)]
@@ -25,6 +32,17 @@ pub struct MemoryTracker {
     free_pages: *mut usize,
     /// The total number of free pages referenced by `free_pages`.
     free_pages_len: usize,
+    /// The number of free pages below which the kernel starts reclaiming memory.
+    low_watermark: usize,
+    /// The number of free pages that the kernel tries to reach when reclaiming memory.
+    high_watermark: usize,
+    /// Whether the number of free pages fell below `low_watermark` since the last call to
+    /// [`MemoryTracker::take_pressure`].
+    under_pressure: bool,
+    /// A bitmap of the pages referenced by `free_pages`, used to check the accounting of the
+    /// tracker.
+    #[cfg(feature = "paranoid")]
+    free_bitmap: *mut u64,
 }
 
 unsafe impl Sync for MemoryTracker {}
@@ -42,62 +60,188 @@ impl MemoryTracker {
         // We will allocate two arrayso: one for the page metadata, and the other for the list of
         // free pages.
 
-        let free_pages = (boot_allocator
+        let free_pages = boot_allocator
             .allocate(page_count * size_of::<usize>(), align_of::<usize>())?
-            + HHDM_OFFSET) as *mut usize;
+            .hhdm_ptr::<usize>();
+        usage::record(Category::Metadata, page_count * size_of::<usize>());
+
+        #[cfg(feature = "paranoid")]
+        let free_bitmap = {
+            let words = page_count.div_ceil(64);
+            let bitmap = boot_allocator
+                .allocate(words * size_of::<u64>(), align_of::<u64>())?
+                .hhdm_ptr::<u64>();
+            usage::record(Category::Metadata, words * size_of::<u64>());
+            unsafe { core::ptr::write_bytes(bitmap, 0x00, words) };
+            bitmap
+        };
 
         Ok(Self {
             free_pages,
             free_pages_len: 0,
+            low_watermark: 0,
+            high_watermark: 0,
+            under_pressure: false,
             page_count,
+            #[cfg(feature = "paranoid")]
+            free_bitmap,
         })
     }
 
+    /// Flips the bit of the provided page index in the bitmap of free pages, returning whether
+    /// the page was free before.
+    #[cfg(feature = "paranoid")]
+    fn toggle_free_bit(&mut self, index: usize) -> bool {
+        // SAFETY:
+        //  The bitmap has one bit per page managed by the tracker.
+        unsafe {
+            let word = self.free_bitmap.add(index / 64);
+            let mask = 1 << (index % 64);
+            let was_free = *word & mask != 0;
+            *word ^= mask;
+            was_free
+        }
+    }
+
     /// Registers a new free page in the tracker.
     ///
     /// # Panics
     ///
-    /// In debug builds, this function checks that its invariants are respected and panics if any
-    /// of them are violated. It won't check whether the page is already registered as free,
-    /// however, as this would be too expensive.
+    /// In debug builds, this function panics if `frame` is not managed by the tracker. In release
+    /// builds, an error is logged and the frame is ignored. It won't check whether the page is
+    /// already registered as free, however, as this would be too expensive.
     ///
     /// # Safety
     ///
-    /// - `page` must be a valid physical address (aligned to the page size).
-    /// - `page` must not already be registered.
-    /// - `page` must be within the range of pages managed by the tracker (i.e. less than the value
-    ///   passed to [`MemoryTracker::new`]).
+    /// - `frame` must not already be registered.
+    /// - `frame` must be within the range of frames managed by the tracker (i.e. its index must be
+    ///   less than the value passed to [`MemoryTracker::new`]).
     #[inline]
-    pub fn mark_as_unused(&mut self, page: usize) {
-        #[cfg(debug_assertions)]
+    pub fn mark_as_unused(&mut self, frame: Frame) {
+        let page = frame.start();
+        let index = page.frame_index();
+
+        kensure!(
+            index < self.page_count,
+            (),
+            "page {:#x} is not managed by the memory tracker",
+            page,
+        );
+
+        #[cfg(feature = "paranoid")]
         {
-            assert!(page % PAGE_SIZE == 0, "page is {:#x}", page);
-            let index = page / PAGE_SIZE;
-            assert!(index < self.page_count);
+            crate::paranoid::check!(
+                index < self.page_count,
+                "page {:#x} is not managed by the memory tracker",
+                page,
+            );
+            crate::paranoid::check!(
+                !self.toggle_free_bit(index),
+                "page {:#x} has been freed twice",
+                page,
+            );
         }
 
         // SAFETY:
         //  The caller must ensure that the page is valid and not already registered as free.
         //  If the page is not already registered, `free_pages` is large enough to store it.
-        unsafe {
-            self.free_pages
-                .add(self.free_pages_len)
-                .write(page / PAGE_SIZE)
-        };
+        unsafe { self.free_pages.add(self.free_pages_len).write(index) };
 
         self.free_pages_len += 1;
     }
 
+    /// Returns the number of free pages.
+    #[inline(always)]
+    pub fn free_page_count(&self) -> usize {
+        self.free_pages_len
+    }
+
+    /// Sets the watermarks of the tracker.
+    ///
+    /// See [`reclaim`](crate::x86_64::reclaim) for more information.
+    pub fn set_watermarks(&mut self, low: usize, high: usize) {
+        kassert!(low <= high, "invalid watermarks ({} > {})", low, high);
+
+        self.low_watermark = low;
+        self.high_watermark = high;
+    }
+
+    /// Returns the number of free pages below which the kernel starts reclaiming memory.
+    #[inline(always)]
+    pub fn low_watermark(&self) -> usize {
+        self.low_watermark
+    }
+
+    /// Returns the number of free pages that the kernel tries to reach when reclaiming memory.
+    #[inline(always)]
+    pub fn high_watermark(&self) -> usize {
+        self.high_watermark
+    }
+
+    /// Returns whether the number of free pages fell below the low watermark since the last call
+    /// to this function.
+    #[inline]
+    pub fn take_pressure(&mut self) -> bool {
+        core::mem::take(&mut self.under_pressure)
+    }
+
     /// Allocates a physical memory page.
     #[inline]
-    pub fn allocate(&mut self) -> Result<usize, OutOfMemory> {
+    pub fn allocate(&mut self) -> Result<Frame, OutOfMemory> {
+        if fault_injection::should_fail(FaultPoint::Allocate) {
+            return Err(OutOfMemory);
+        }
+
         if self.free_pages_len == 0 {
+            self.under_pressure = true;
             return Err(OutOfMemory);
         }
 
         self.free_pages_len -= 1;
-        let ret = unsafe { self.free_pages.add(self.free_pages_len).read() * PAGE_SIZE };
-        Ok(ret)
+        if self.free_pages_len < self.low_watermark {
+            self.under_pressure = true;
+        }
+        let index = unsafe { self.free_pages.add(self.free_pages_len).read() };
+        let ret = PhysAddr::from_frame_index(index);
+
+        #[cfg(feature = "paranoid")]
+        crate::paranoid::check!(
+            self.toggle_free_bit(index),
+            "page {:#x} was allocated while not free",
+            ret,
+        );
+
+        Ok(Frame::containing(ret))
+    }
+
+    /// Allocates a physical memory page and fills it with zeros.
+    ///
+    /// Freed frames are handed out again without being cleared: every frame that ends up mapped
+    /// in the address space of a process must be allocated with this function, so that it does
+    /// not expose the memory of another process.
+    pub fn allocate_zeroed(&mut self) -> Result<Frame, OutOfMemory> {
+        let frame = self.allocate()?;
+
+        // SAFETY:
+        //  The frame has just been allocated, and the HHDM maps all physical memory.
+        unsafe { core::ptr::write_bytes(frame.start().hhdm_ptr::<u8>(), 0x00, PAGE_SIZE) };
+
+        Ok(frame)
+    }
+
+    /// Allocates a page for a page table, and records it in the memory usage of the kernel.
+    #[inline]
+    pub fn allocate_page_table(&mut self) -> Result<PhysAddr, OutOfMemory> {
+        let frame = self.allocate()?;
+        usage::record(Category::PageTables, PAGE_SIZE);
+        Ok(frame.start())
+    }
+
+    /// Frees a page allocated with [`MemoryTracker::allocate_page_table`].
+    #[inline]
+    pub fn free_page_table(&mut self, page: PhysAddr) {
+        usage::release(Category::PageTables, PAGE_SIZE);
+        self.mark_as_unused(Frame::containing(page));
     }
 }
 
@@ -114,6 +258,9 @@ pub struct LockedMemoryTracker {
     epoch: RawEpochMutex,
 }
 
+/// The lock class of [`LockedMemoryTracker`].
+static LOCK_CLASS: LockClass = LockClass::new("memory tracker");
+
 unsafe impl Sync for LockedMemoryTracker {}
 unsafe impl Send for LockedMemoryTracker {}
 
@@ -132,6 +279,8 @@ impl LockedMemoryTracker {
     /// When the guard is dropped, the [`LockedMemoryTracker`] is unlocked automatically.
     #[inline(always)]
     pub fn lock(&self) -> MemoryTrackerGuard {
+        crate::paranoid::before_lock(LockLevel::MemoryTracker);
+        lockdep::acquire(&LOCK_CLASS);
         self.epoch.lock();
         MemoryTrackerGuard {
             page_list: unsafe { &mut *self.inner.get() },
@@ -168,6 +317,8 @@ impl<'a> Drop for MemoryTrackerGuard<'a> {
         // SAFETY:
         //  The existence of the guard ensures that we hold the lock.
         unsafe { self.lock.unlock() };
+        lockdep::release(&LOCK_CLASS);
+        crate::paranoid::after_unlock(LockLevel::MemoryTracker);
     }
 }
 
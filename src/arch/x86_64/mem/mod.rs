@@ -12,12 +12,13 @@
 //!   the kernel. Userspace pointers will never be able to point to this region of memory, and
 //!   system calls should always check that pointers passed by users are part of the lower half.
 //!
-//! Note that the page table is set up in the [`crate::x86_64::cpu::paging`] module.
-
-/// The maximum amount of physical memory supported by the kernel.
-///
-/// This is currently (and kinda arbitrarily) set to 1 TiB.
-pub const MAX_PHYSICAL_MEMORY: usize = 1024 * 1024 * 1024 * 1024;
+//! Besides the direct map, the higher half holds the image of the kernel, its stack, and the
+//! [`vmalloc`] region, where the kernel maps the memory that the direct map cannot provide.
+//!
+//! Note that the page table is set up in the [`crate::x86_64::cpu::paging`] module. The end of
+//! the lower half available to processes is configured by
+//! [`USER_TOP`](crate::x86_64::config::USER_TOP), and clamped to the active paging mode by
+//! [`user_top`](crate::x86_64::cpu::paging::user_top).
 
 /// The size of a physical page.
 pub const PAGE_SIZE: usize = 4096;
@@ -30,15 +31,25 @@ pub const PAGE_SIZE: usize = 4096;
 /// The offset between physical addresses and virtual addresses in the higher half.
 pub const HHDM_OFFSET: usize = 0xFFFF8000_00000000;
 
-/// The first value that is not part of the virtual address space of userland processes.
-pub const USER_TOP: usize = 0x00007FFF_FFFFFFFF;
+/// The lowest address that the kernel picks when it chooses where to map memory on behalf of a
+/// userland process.
+///
+/// Keeping the bottom of the address space unused leaves room for the image of the process and
+/// for the mappings it places itself.
+pub const USER_MAP_BASE: usize = 0x00001000_00000000;
 
 /// Indicates that the allocator cannot allocate for the requested amount of memory.
 #[derive(Debug, Clone, Copy)]
 pub struct OutOfMemory;
 
+mod addr;
 mod boot_allocator;
 mod memory_tracker;
+mod page;
+pub mod usage;
+pub mod vmalloc;
 
+pub use self::addr::*;
 pub use self::boot_allocator::*;
 pub use self::memory_tracker::*;
+pub use self::page::*;
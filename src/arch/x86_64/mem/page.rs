@@ -0,0 +1,148 @@
+//! Pages of virtual memory and frames of physical memory.
+//!
+//! A [`Page`] is an aligned block of virtual memory, and a [`Frame`] is an aligned block of
+//! physical memory. Both are generic over their size, which is one of the sizes supported by the
+//! paging hardware: [`Size4KiB`] (the default), [`Size2MiB`] or [`Size1GiB`].
+//!
+//! Ranges of pages and frames can be iterated over with [`Page::range`] and [`Frame::range`].
+
+use core::marker::PhantomData;
+
+use super::{PhysAddr, VirtAddr};
+
+/// A size of page supported by the paging hardware.
+pub trait PageSize: Copy + Ord {
+    /// The size of a page, in bytes.
+    const SIZE: usize;
+}
+
+/// The size of regular pages (4 KiB).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size4KiB {}
+
+/// The size of pages mapped by an L2 entry (2 MiB).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size2MiB {}
+
+/// The size of pages mapped by an L3 entry (1 GiB).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size1GiB {}
+
+impl PageSize for Size4KiB {
+    const SIZE: usize = 4 * 1024;
+}
+
+impl PageSize for Size2MiB {
+    const SIZE: usize = 2 * 1024 * 1024;
+}
+
+impl PageSize for Size1GiB {
+    const SIZE: usize = 1024 * 1024 * 1024;
+}
+
+macro_rules! block_type {
+    ($(#[$attr:meta])* $name:ident, $iter:ident, $addr:ident) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+        pub struct $name<S: PageSize = Size4KiB> {
+            start: $addr,
+            size: PhantomData<S>,
+        }
+
+        // Not every method is used for every size.
+        #[allow(dead_code)]
+        impl<S: PageSize> $name<S> {
+            /// The size of the block, in bytes.
+            pub const SIZE: usize = S::SIZE;
+
+            /// Returns the block that contains the provided address.
+            #[inline(always)]
+            pub const fn containing(addr: $addr) -> Self {
+                Self {
+                    start: $addr::new(addr.get() & !(S::SIZE - 1)),
+                    size: PhantomData,
+                }
+            }
+
+            /// Returns the block that starts at the provided address.
+            ///
+            /// `None` is returned if the address is not aligned to the size of the block.
+            #[inline(always)]
+            pub const fn from_start(addr: $addr) -> Option<Self> {
+                if addr.get() & (S::SIZE - 1) == 0 {
+                    Some(Self {
+                        start: addr,
+                        size: PhantomData,
+                    })
+                } else {
+                    None
+                }
+            }
+
+            /// Returns the first address of the block.
+            #[inline(always)]
+            pub const fn start(self) -> $addr {
+                self.start
+            }
+
+            /// Returns an iterator over the blocks from `start` (inclusive) to `end` (exclusive).
+            #[inline(always)]
+            pub const fn range(start: Self, end: Self) -> $iter<S> {
+                $iter { next: start, end }
+            }
+
+            /// Returns an iterator over the blocks that overlap the `length` bytes starting at
+            /// `start`.
+            #[inline(always)]
+            pub const fn range_of(start: $addr, length: usize) -> $iter<S> {
+                let end = (start.get() + length + (S::SIZE - 1)) & !(S::SIZE - 1);
+                Self::range(Self::containing(start), Self::containing($addr::new(end)))
+            }
+        }
+
+        #[doc = concat!("An iterator over a range of [`", stringify!($name), "`]s, created by [`", stringify!($name), "::range`].")]
+        #[derive(Debug, Clone)]
+        pub struct $iter<S: PageSize = Size4KiB> {
+            next: $name<S>,
+            end: $name<S>,
+        }
+
+        impl<S: PageSize> Iterator for $iter<S> {
+            type Item = $name<S>;
+
+            #[inline]
+            fn next(&mut self) -> Option<$name<S>> {
+                if self.next >= self.end {
+                    return None;
+                }
+
+                let ret = self.next;
+                self.next.start += S::SIZE;
+                Some(ret)
+            }
+
+            #[inline]
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                let len = self.len();
+                (len, Some(len))
+            }
+        }
+
+        impl<S: PageSize> ExactSizeIterator for $iter<S> {
+            #[inline]
+            fn len(&self) -> usize {
+                self.end.start.get().saturating_sub(self.next.start.get()) / S::SIZE
+            }
+        }
+    };
+}
+
+block_type! {
+    /// An aligned block of virtual memory.
+    Page, PageIter, VirtAddr
+}
+
+block_type! {
+    /// An aligned block of physical memory.
+    Frame, FrameIter, PhysAddr
+}
@@ -0,0 +1,81 @@
+//! Accounting of the memory used by the kernel for its own purposes.
+//!
+//! Every allocation made by the kernel for itself, either with the boot allocator or with the
+//! memory tracker, is recorded here under a [`Category`]. The counters are exposed through the
+//! `query_kernel_stats` system call and written to the log when the system runs out of memory,
+//! so that memory leaked by the kernel is visible.
+//!
+//! The kernel has no heap, and its log is not buffered: none of its memory is used for those.
+
+use fabric_sys::x86_64::KernelMemoryUsage;
+
+use crate::kassert::kassert;
+use crate::log;
+use crate::utility::HumanByteCount;
+
+/// A category of memory used by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// The page tables of the kernel and of the processes.
+    PageTables,
+    /// The stacks of the kernel.
+    Stacks,
+    /// The bookkeeping structures of the kernel.
+    Metadata,
+}
+
+/// The memory used by the kernel, in bytes.
+static mut USAGE: KernelMemoryUsage = KernelMemoryUsage {
+    page_tables: 0,
+    stacks: 0,
+    metadata: 0,
+};
+
+/// Returns the counter of the provided category.
+fn counter(category: Category) -> &'static mut u64 {
+    // SAFETY:
+    //  The counters are never accessed concurrently.
+    let usage = unsafe { &mut USAGE };
+
+    match category {
+        Category::PageTables => &mut usage.page_tables,
+        Category::Stacks => &mut usage.stacks,
+        Category::Metadata => &mut usage.metadata,
+    }
+}
+
+/// Records that `bytes` bytes have been allocated for the provided category.
+#[inline]
+pub fn record(category: Category, bytes: usize) {
+    *counter(category) += bytes as u64;
+}
+
+/// Records that `bytes` bytes allocated for the provided category have been freed.
+#[inline]
+pub fn release(category: Category, bytes: usize) {
+    let counter = counter(category);
+    kassert!(
+        *counter >= bytes as u64,
+        "{:?} memory freed twice",
+        category
+    );
+    *counter = counter.saturating_sub(bytes as u64);
+}
+
+/// Returns the memory currently used by the kernel.
+#[inline]
+pub fn get() -> KernelMemoryUsage {
+    // SAFETY:
+    //  The counters are never accessed concurrently.
+    unsafe { USAGE }
+}
+
+/// Writes the memory currently used by the kernel to the log.
+pub fn dump() {
+    let usage = get();
+
+    log::warn!("Memory used by the kernel:");
+    log::warn!("  page tables: {}", HumanByteCount(usage.page_tables));
+    log::warn!("  stacks:      {}", HumanByteCount(usage.stacks));
+    log::warn!("  metadata:    {}", HumanByteCount(usage.metadata));
+}
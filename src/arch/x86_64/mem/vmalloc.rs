@@ -0,0 +1,263 @@
+//! Virtually contiguous mappings of the kernel, outside of the higher half direct map.
+//!
+//! The direct map only covers part of the physical address space, and the memory it maps cannot
+//! be surrounded by unmapped pages. The [`VMALLOC_START`]..[`VMALLOC_END`] range of the kernel
+//! address space is reserved for the mappings that the direct map cannot provide:
+//!
+//! - Stacks, which are backed by arbitrary frames and preceded by a guard page. Overflowing one
+//!   of them triggers a page fault instead of overwriting the memory below.
+//!
+//! - MMIO windows, wherever they are located in physical memory.
+//!
+//! Every mapping of the region is preceded by an unmapped guard page. Virtual memory is never
+//! reused: the region is large enough for the kernel never to run out of it.
+//!
+//! The kernel stack itself is not part of the region, as its address is a constant of the entry
+//! points of the kernel. See [`kernel_stack`](crate::x86_64::kernel_stack).
+
+use crate::log;
+use crate::utility::num;
+use crate::x86_64::cpu::paging::{self, PageTable, UpperHalfAddressSpaceTok};
+use crate::x86_64::mem::{
+    Frame, MemoryTracker, OutOfMemory, Page, PhysAddr, VirtAddr, HHDM_OFFSET, PAGE_SIZE,
+};
+use crate::x86_64::raw::PageFlags;
+
+/// The first address of the region.
+pub const VMALLOC_START: usize = 0xFFFFFE80_00000000;
+
+/// The end of the region (exclusive).
+///
+/// The kernel stack follows the region.
+pub const VMALLOC_END: usize = crate::x86_64::kernel_stack::KERNEL_STACK_GUARD;
+
+/// The address of the next guard page of the region.
+static mut NEXT: usize = VMALLOC_START;
+
+/// Reserves `length` bytes of the region, preceded by a guard page.
+///
+/// # Returns
+///
+/// The first address of the reserved range, or `None` if the region is exhausted.
+fn reserve(length: usize) -> Option<VirtAddr> {
+    let length = num::page_align_up(length)?;
+
+    // SAFETY:
+    //  The kernel is not reentrant.
+    let next = unsafe { &mut NEXT };
+
+    let start = *next + PAGE_SIZE;
+    let end = start
+        .checked_add(length)
+        .filter(|&end| end <= VMALLOC_END)?;
+
+    *next = end;
+    Some(VirtAddr::new(start))
+}
+
+/// Maps the provided frames at consecutive pages, starting at `start`.
+///
+/// If a page table cannot be allocated, the pages that were already mapped are unmapped.
+///
+/// # Safety
+///
+/// The kernel must run in its own address space.
+unsafe fn map_at(
+    upper_half: UpperHalfAddressSpaceTok,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    start: VirtAddr,
+    frames: &mut dyn Iterator<Item = Frame>,
+    flags: PageFlags,
+) -> Result<(), OutOfMemory> {
+    // SAFETY:
+    //  The kernel address space has been initialized.
+    let l4 = unsafe { &mut *upper_half.get().hhdm_ptr::<PageTable>() };
+
+    let mut page = Page::containing(start);
+
+    for frame in frames {
+        let mapped = unsafe { paging::map_4kib(l4, HHDM_OFFSET, alloc_page, page, frame, flags) };
+
+        if mapped.is_err() {
+            // The virtual memory reserved for the mapping is lost.
+            for page in Page::range(Page::containing(start), page) {
+                unsafe {
+                    let _ = paging::unmap_4kib(l4, HHDM_OFFSET, page);
+                    crate::x86_64::instr::invlpg(page.start().get());
+                }
+            }
+
+            return Err(OutOfMemory);
+        }
+
+        page = Page::containing(page.start() + PAGE_SIZE);
+    }
+
+    Ok(())
+}
+
+/// Maps the provided frames at consecutive addresses of the region.
+///
+/// The frames do not have to be contiguous in physical memory.
+///
+/// # Returns
+///
+/// The virtual address of the first frame.
+///
+/// # Errors
+///
+/// If the region is exhausted, or if a page table cannot be allocated, [`OutOfMemory`] is
+/// returned and nothing remains mapped.
+///
+/// # Safety
+///
+/// The kernel must run in its own address space. The frames must not be used for anything else
+/// while they are mapped.
+pub unsafe fn map_frames(
+    upper_half: UpperHalfAddressSpaceTok,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    frames: &[Frame],
+    flags: PageFlags,
+) -> Result<VirtAddr, OutOfMemory> {
+    let start = reserve(frames.len() * PAGE_SIZE).ok_or(OutOfMemory)?;
+
+    unsafe {
+        map_at(
+            upper_half,
+            alloc_page,
+            start,
+            &mut frames.iter().copied(),
+            flags,
+        )?
+    };
+
+    log::trace!(
+        "Mapped {} frames at {:#x} with {:?}.",
+        frames.len(),
+        start,
+        flags,
+    );
+
+    Ok(start)
+}
+
+/// Allocates `length` bytes of memory with the memory tracker, and maps them at consecutive
+/// addresses of the region.
+///
+/// This is used for the stacks allocated once the memory tracker is initialized.
+///
+/// # Returns
+///
+/// The virtual address of the first allocated page.
+///
+/// # Errors
+///
+/// If the region is exhausted, or if the system runs out of memory, [`OutOfMemory`] is returned
+/// and nothing remains allocated.
+///
+/// # Safety
+///
+/// The kernel must run in its own address space.
+pub unsafe fn allocate(
+    upper_half: UpperHalfAddressSpaceTok,
+    memory_tracker: &mut MemoryTracker,
+    length: usize,
+    flags: PageFlags,
+) -> Result<VirtAddr, OutOfMemory> {
+    let start = reserve(length).ok_or(OutOfMemory)?;
+
+    // SAFETY:
+    //  The kernel address space has been initialized.
+    let l4 = unsafe { &mut *upper_half.get().hhdm_ptr::<PageTable>() };
+
+    for page in Page::range_of(start, length) {
+        let mapped = memory_tracker.allocate().and_then(|frame| {
+            let mapped = unsafe {
+                paging::map_4kib(
+                    l4,
+                    HHDM_OFFSET,
+                    &mut || memory_tracker.allocate_page_table(),
+                    page,
+                    frame,
+                    flags,
+                )
+            };
+
+            if mapped.is_err() {
+                memory_tracker.mark_as_unused(frame);
+            }
+
+            mapped
+        });
+
+        if mapped.is_err() {
+            // The virtual memory reserved for the mapping is lost.
+            for page in Page::range(Page::containing(start), page) {
+                unsafe {
+                    if let Ok(frame) = paging::unmap_4kib(l4, HHDM_OFFSET, page) {
+                        memory_tracker.mark_as_unused(frame);
+                    }
+                    crate::x86_64::instr::invlpg(page.start().get());
+                }
+            }
+
+            return Err(OutOfMemory);
+        }
+    }
+
+    log::trace!(
+        "Allocated {:#x} bytes at {:#x} with {:?}.",
+        length,
+        start,
+        flags
+    );
+
+    Ok(start)
+}
+
+/// Maps the `phys..phys + length` range of physical memory in the region.
+///
+/// This is used for MMIO windows, which may be located beyond the end of the direct map.
+///
+/// # Returns
+///
+/// The virtual address at which `phys` is mapped. It has the same offset within its page as
+/// `phys`.
+///
+/// # Errors
+///
+/// If the region is exhausted, or if a page table cannot be allocated, [`OutOfMemory`] is
+/// returned and nothing remains mapped.
+///
+/// # Safety
+///
+/// The kernel must run in its own address space.
+pub unsafe fn map_physical(
+    upper_half: UpperHalfAddressSpaceTok,
+    alloc_page: &mut dyn FnMut() -> Result<PhysAddr, OutOfMemory>,
+    phys: PhysAddr,
+    length: usize,
+    flags: PageFlags,
+) -> Result<VirtAddr, OutOfMemory> {
+    let start = reserve(phys.page_offset() + length).ok_or(OutOfMemory)?;
+
+    unsafe {
+        map_at(
+            upper_half,
+            alloc_page,
+            start,
+            &mut Frame::range_of(phys, length),
+            flags,
+        )?
+    };
+
+    log::trace!(
+        "Mapped {:#x}..{:#x} at {:#x} with {:?}.",
+        phys,
+        phys + length,
+        start,
+        flags,
+    );
+
+    Ok(start + phys.page_offset())
+}
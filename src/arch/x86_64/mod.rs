@@ -5,25 +5,78 @@
 //! The following modules are defined, providing documentation for the various relevant parts of
 //! the code base for the **x86_64** architecture:
 //!
+//! - [`acpi`]: Parsing of the ACPI tables and fixed power management features.
+//! - [`aslr`]: Randomization of the layout of the address space of processes.
+//! - [`build_id`]: The identity of the build of the kernel, embedded in its image.
+//! - [`boot_stage`]: Reports of the progress of the boot process to external tooling.
+//! - [`config`]: The fundamental constants of the kernel, and how to override them.
 //! - [`cpu`]: CPU-specific code. Not including scheduling.
+//! - [`deterministic`]: A boot mode that makes the output of the kernel reproducible.
+//! - [`fault_injection`]: Forced failures used to test the error paths of the kernel.
+//! - [`hpet`]: The main counter of the HPET, used to measure the frequency of the TSC.
+//! - [`keyboard`]: A driver for the PS/2 keyboard.
+//! - [`lockdep`]: Checks of the order in which locks are acquired.
 //! - [`mem`]: Physical memory management.
+//! - [`pci`]: Enumeration of the PCI devices.
+//! - [`percpu`]: Data that is local to each CPU, reached through the `gs` segment.
 //! - [`serial`]: Serial port driver.
+//! - [`smp`]: Bring-up of the application processors.
+//! - [`supervisor`]: Supervision of the init process.
+//! - [`symbols`]: The symbol table of the kernel, used to symbolize crash dumps.
+//! - [`timeout`]: The timeouts of the kernel, kept in a timer wheel.
+//! - [`timer`]: Selection of the source of the scheduler tick, and the monotonic clock.
+//! - [`tlb`]: Invalidation of the translations cached by the other CPUs.
+
+use core::ops::Range;
 
 use fabric_sys::x86_64::public::PublicData;
 
 #[path = "boot/limine/mod.rs"]
 mod limine;
 
+mod acpi;
+mod aslr;
+mod backtrace;
+mod boot_stage;
+mod build_id;
+mod config;
 mod cpu;
+mod debugcon;
+mod deterministic;
+mod display;
+mod escrow;
+mod event;
+mod fault_injection;
+mod hpet;
 mod instr;
+mod ipc;
+mod irq;
 mod kernel_stack;
+mod keyboard;
+#[cfg(feature = "ktest")]
+mod ktest;
+mod lockdep;
 mod mem;
+mod oom;
+mod pci;
+mod percpu;
 mod process;
 mod public;
 mod raw;
+mod reclaim;
 mod scheduler;
 mod serial;
+mod smp;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+mod supervisor;
+mod symbols;
 mod syscall;
+mod timeout;
+mod timer;
+mod tlb;
+
+pub use self::mem::PAGE_SIZE;
 
 /// Disables interrupts and halts the CPU forever.
 pub fn die() -> ! {
@@ -36,6 +89,74 @@ pub fn die() -> ! {
     }
 }
 
+/// Returns the number of microseconds elapsed since boot.
+///
+/// See [`timer::uptime_us`].
+#[inline(always)]
+pub fn uptime_us() -> u64 {
+    timer::uptime_us()
+}
+
+/// Writes a backtrace of the kernel stack to the log.
+///
+/// See [`backtrace::print`].
+#[inline(always)]
+pub fn print_backtrace() {
+    backtrace::print();
+}
+
+/// Writes the state of the current CPU to the log.
+///
+/// See [`cpu::registers::print`].
+#[inline(always)]
+pub fn print_machine_state() {
+    cpu::registers::print();
+}
+
+/// Returns the build ID of the kernel.
+///
+/// See [`build_id`].
+#[inline]
+pub fn build_id() -> &'static impl core::fmt::Display {
+    &build_id::BUILD_ID
+}
+
+/// Reports a kernel panic to the emulator the kernel is running under, if any.
+///
+/// See [`debugcon::report_panic`].
+#[inline(always)]
+pub fn report_panic_to_emulator(info: &core::panic::PanicInfo) {
+    debugcon::report_panic(info);
+}
+
+/// Resets the machine.
+///
+/// The reset line of the PS/2 controller is pulsed first. If that does not work, an empty IDT is
+/// loaded and an exception is raised, causing a triple fault.
+pub fn reboot() -> ! {
+    instr::cli();
+
+    // SAFETY:
+    //  We are about to reset the machine. Nothing that happens to memory matters anymore.
+    unsafe {
+        instr::outb(0x64, 0xFE);
+
+        let idt_desc = raw::TableDesc {
+            base: core::ptr::null(),
+            limit: 0,
+        };
+
+        core::arch::asm!(
+            r#"
+            lidt [{idt_desc}]
+            int3
+            "#,
+            idt_desc = in(reg) &idt_desc,
+            options(noreturn),
+        );
+    }
+}
+
 /// Returns the address of the beginning of the kernel image.
 ///
 /// This value is computed by the linker.
@@ -66,6 +187,46 @@ pub fn image_end() -> usize {
     unsafe { core::ptr::addr_of!(__fabric_image_end) as usize }
 }
 
+/// Returns the range of virtual addresses of the `.entry.text` section of the kernel image.
+///
+/// This section holds the entry points that must remain mapped while userspace runs. See
+/// [`cpu::pti`].
+#[inline(always)]
+pub fn entry_text() -> Range<usize> {
+    extern "C" {
+        static __fabric_entry_text_begin: u8;
+        static __fabric_entry_text_end: u8;
+    }
+
+    // SAFETY:
+    //  We just taking the address of the symbols, without creating a reference to them. This is
+    //  always safe.
+    unsafe {
+        core::ptr::addr_of!(__fabric_entry_text_begin) as usize
+            ..core::ptr::addr_of!(__fabric_entry_text_end) as usize
+    }
+}
+
+/// Returns the range of virtual addresses of the `.entry.data` section of the kernel image.
+///
+/// This section holds the data that must remain mapped while userspace runs. See
+/// [`cpu::pti`].
+#[inline(always)]
+pub fn entry_data() -> Range<usize> {
+    extern "C" {
+        static __fabric_entry_data_begin: u8;
+        static __fabric_entry_data_end: u8;
+    }
+
+    // SAFETY:
+    //  We just taking the address of the symbols, without creating a reference to them. This is
+    //  always safe.
+    unsafe {
+        core::ptr::addr_of!(__fabric_entry_data_begin) as usize
+            ..core::ptr::addr_of!(__fabric_entry_data_end) as usize
+    }
+}
+
 /// Returns the virtual address of the global [`PublicData`] instance.
 #[inline(always)]
 pub fn public_data_address() -> usize {
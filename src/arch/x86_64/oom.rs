@@ -0,0 +1,319 @@
+//! Handling of out-of-memory conditions.
+//!
+//! When a request for physical memory cannot be satisfied, the kernel first reclaims the
+//! purgeable memory of the processes (see [`process::purge`]). If that is not enough:
+//!
+//! 1. The request fails, and an [`EventKind::OutOfMemory`] event is delivered to its
+//!    subscriber, the memory manager of the system. The event holds the number of pages that
+//!    were missing.
+//!
+//! 2. The memory manager is given a grace period to give frames back, selected with the
+//!    `oom.grace` option of the kernel command line (in milliseconds, [`DEFAULT_GRACE_MS`] by
+//!    default). It may revoke memory by asking the processes it manages to release it, or by
+//!    terminating them.
+//!
+//! 3. If the shortage has not been resolved once the grace period is over, the last-resort
+//!    policy selected with the `oom.policy` option is applied:
+//!
+//!    - `kill` (the default): the process that owns the most memory is terminated. The init
+//!      process and the memory manager are never selected. When the grace period starts, the
+//!      process that would be terminated at that point is warned through its inbox with
+//!      [`Announcements::TERMINATION_WARNING`], giving it a chance to release memory itself.
+//!    - `notify`: nothing more is done.
+
+use fabric_sys::event::EventKind;
+use fabric_sys::x86_64::public::Announcements;
+use fabric_sys::ProcessId;
+
+use crate::log;
+use crate::utility::Cmdline;
+
+use super::event::{self, DeliverError};
+use super::mem::{usage, MemoryTracker, MemoryTrackerTok};
+use super::process::{self, ExitReason};
+use super::timer::{self, TICK_FREQUENCY};
+use super::{scheduler, supervisor};
+
+/// The default length of the grace period, in milliseconds.
+pub const DEFAULT_GRACE_MS: u64 = 100;
+
+/// What the kernel does when a memory shortage outlives its grace period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OomPolicy {
+    /// Terminate the process that owns the most memory.
+    #[default]
+    Kill,
+    /// Do nothing besides notifying the memory manager.
+    Notify,
+}
+
+/// A memory shortage that has not been resolved yet.
+#[derive(Debug, Clone, Copy)]
+struct Shortage {
+    /// The number of free pages that the failed request needed.
+    wanted: usize,
+    /// The tick at which the grace period ends.
+    deadline: u64,
+    /// Whether the process selected to be terminated has been warned.
+    warned: bool,
+    /// The process that has been warned that it would be terminated, if any.
+    victim: Option<ProcessId>,
+}
+
+/// The state of the out-of-memory handler.
+struct Oom {
+    /// The policy applied once the grace period is over.
+    policy: OomPolicy,
+    /// The length of the grace period, in ticks.
+    grace: u64,
+    /// The current shortage, if any.
+    shortage: Option<Shortage>,
+}
+
+/// The global state of the out-of-memory handler.
+static mut OOM: Oom = Oom {
+    policy: OomPolicy::Kill,
+    grace: DEFAULT_GRACE_MS * TICK_FREQUENCY as u64 / 1000,
+    shortage: None,
+};
+
+/// Returns the global state of the out-of-memory handler.
+fn oom() -> &'static mut Oom {
+    // SAFETY:
+    //  The state is never accessed concurrently.
+    unsafe { &mut OOM }
+}
+
+/// Reads the configuration of the out-of-memory handler from the `oom.policy` and `oom.grace`
+/// options of the provided command line.
+///
+/// Invalid values are reported and replaced by the default ones.
+///
+/// # Safety
+///
+/// This function must be called once, during boot.
+pub unsafe fn init(cmdline: Cmdline) {
+    let oom = oom();
+
+    oom.policy = match cmdline.get(b"oom.policy") {
+        None | Some(b"kill") => OomPolicy::Kill,
+        Some(b"notify") => OomPolicy::Notify,
+        Some(other) => {
+            log::warn!(
+                "Unknown `oom.policy` value: `{}`.",
+                core::str::from_utf8(other).unwrap_or("<invalid UTF-8>")
+            );
+            log::warn!("Falling back to `kill`.");
+            OomPolicy::default()
+        }
+    };
+
+    let grace_ms = match cmdline.get(b"oom.grace") {
+        None => DEFAULT_GRACE_MS,
+        Some(value) => match core::str::from_utf8(value)
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            Some(ms) => ms,
+            None => {
+                log::warn!(
+                    "Invalid `oom.grace` value: `{}`.",
+                    core::str::from_utf8(value).unwrap_or("<invalid UTF-8>")
+                );
+                DEFAULT_GRACE_MS
+            }
+        },
+    };
+
+    // The grace period lasts at least one tick, so that the memory manager gets a chance to
+    // run.
+    oom.grace = (grace_ms.saturating_mul(TICK_FREQUENCY as u64) / 1000).max(1);
+
+    log::trace!(
+        "OOM policy: {:?}, grace period of {} ms.",
+        oom.policy,
+        grace_ms
+    );
+}
+
+/// Makes sure that at least `wanted` pages are free, reclaiming purgeable memory if needed.
+///
+/// The process with the ID `except` keeps its purgeable memory.
+///
+/// # Returns
+///
+/// This function returns whether `wanted` pages are free. When they are not, the shortage is
+/// reported with [`report`].
+///
+/// # Safety
+///
+/// No reference to a process other than `except` may be alive.
+pub unsafe fn reclaim(
+    memory_tracker: &mut MemoryTracker,
+    wanted: usize,
+    except: Option<ProcessId>,
+) -> bool {
+    if memory_tracker.free_page_count() < wanted {
+        unsafe { process::purge(memory_tracker, wanted, except) };
+    }
+
+    let free = memory_tracker.free_page_count();
+    if free >= wanted {
+        return true;
+    }
+
+    report(wanted, free);
+    false
+}
+
+/// Reports that a request for `wanted` pages could not be satisfied while only `free` pages
+/// were available.
+///
+/// Unless a shortage is already going on, the memory manager is notified and the grace period
+/// starts.
+pub fn report(wanted: usize, free: usize) {
+    let oom = oom();
+
+    if let Some(shortage) = &mut oom.shortage {
+        shortage.wanted = shortage.wanted.max(wanted);
+        return;
+    }
+
+    log::warn!(
+        "Out of memory: {} pages were requested, but only {} are free.",
+        wanted,
+        free,
+    );
+    usage::dump();
+
+    oom.shortage = Some(Shortage {
+        wanted,
+        deadline: scheduler::ticks().saturating_add(oom.grace),
+        warned: false,
+        victim: None,
+    });
+
+    match event::deliver(EventKind::OutOfMemory, wanted - free) {
+        Ok(()) | Err(DeliverError::NoSubscriber) => (),
+        Err(DeliverError::PortFull) => {
+            log::warn_ratelimited!(
+                "The out-of-memory event could not be delivered: the port is full."
+            );
+        }
+    }
+}
+
+/// Checks whether the current shortage has been resolved, applying the last-resort policy once
+/// its grace period is over.
+///
+/// This function is called on every tick by the scheduler.
+pub fn tick(now: u64) {
+    let oom = oom();
+
+    let Some(shortage) = &mut oom.shortage else {
+        return;
+    };
+
+    // SAFETY:
+    //  The memory tracker is initialized before the scheduler starts.
+    let free = unsafe { MemoryTrackerTok::unchecked() }
+        .lock()
+        .free_page_count();
+
+    if free >= shortage.wanted {
+        log::info!(
+            "The memory shortage has been resolved ({} pages are free).",
+            free
+        );
+        withdraw_warning(shortage.victim);
+        oom.shortage = None;
+        return;
+    }
+
+    if oom.policy == OomPolicy::Kill && !shortage.warned {
+        shortage.warned = true;
+        shortage.victim = warn_largest(shortage.deadline.saturating_sub(now));
+    }
+
+    if now < shortage.deadline {
+        return;
+    }
+
+    let victim = shortage.victim;
+    oom.shortage = None;
+
+    match oom.policy {
+        OomPolicy::Kill => kill_largest(),
+        OomPolicy::Notify => {
+            log::warn!("The memory shortage has not been resolved during the grace period.");
+        }
+    }
+
+    // The process that has been killed may not be the one that was warned.
+    withdraw_warning(victim);
+}
+
+/// Returns the process that owns the most memory, excluding the init process and the memory
+/// manager, along with the number of pages it owns.
+///
+/// `None` is returned if no such process owns any memory.
+fn largest() -> Option<(ProcessId, usize)> {
+    let manager = event::subscriber_owner(EventKind::OutOfMemory);
+
+    // SAFETY:
+    //  The references do not outlive the iteration.
+    unsafe { process::iter() }
+        .filter(|&(id, _)| !supervisor::is_init(id) && Some(id) != manager)
+        .map(|(id, process)| (id, process.resident_pages()))
+        .max_by_key(|&(_, pages)| pages)
+        .filter(|&(_, pages)| pages != 0)
+}
+
+/// Warns the process that owns the most memory that it will be terminated in `ticks` ticks.
+///
+/// # Returns
+///
+/// The ID of the process that has been warned, if any.
+fn warn_largest(ticks: u64) -> Option<ProcessId> {
+    let (id, _) = largest()?;
+
+    let deadline_ns = timer::uptime_us()
+        .saturating_mul(1000)
+        .saturating_add(ticks.saturating_mul(1_000_000_000 / TICK_FREQUENCY as u64));
+
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    let inbox = unsafe { process::get(id) }.and_then(|p| p.inbox())?;
+    inbox.warn_termination(deadline_ns);
+
+    Some(id)
+}
+
+/// Withdraws the termination warning posted to the provided process, if it still exists.
+fn withdraw_warning(victim: Option<ProcessId>) {
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    let inbox = victim
+        .and_then(|id| unsafe { process::get(id) })
+        .and_then(|p| p.inbox());
+
+    if let Some(inbox) = inbox {
+        inbox.post(Announcements::empty(), Announcements::TERMINATION_WARNING);
+    }
+}
+
+/// Terminates the process that owns the most memory, excluding the init process and the memory
+/// manager.
+fn kill_largest() {
+    match largest() {
+        Some((id, pages)) => {
+            log::warn!(
+                "Terminating process {} to reclaim {} pages of memory.",
+                id,
+                pages,
+            );
+            process::terminate(id, ExitReason::OutOfMemory);
+        }
+        _ => log::warn!("No process can be terminated to reclaim memory."),
+    }
+}
@@ -0,0 +1,166 @@
+//! Decoding of the base address registers (BARs) of PCI devices.
+//!
+//! The size of the resource decoded by a register is found by writing all ones to it and reading
+//! it back: the bits that the device hardwires to zero give the alignment, and thus the size, of
+//! the resource.
+
+use fabric_sys::x86_64::public::{PciBar, PciBarFlags, PCI_BAR_COUNT};
+
+use super::{read_config, write_config, ConfigAddress};
+
+/// The offset of the first base address register in the configuration space.
+const BAR0: u8 = 0x10;
+
+/// The offset of the command register in the configuration space.
+const COMMAND: u8 = 0x04;
+/// Set in the command register when the device responds to I/O space accesses.
+const COMMAND_IO_SPACE: u32 = 1 << 0;
+/// Set in the command register when the device responds to memory space accesses.
+const COMMAND_MEMORY_SPACE: u32 = 1 << 1;
+
+/// Set in a base address register that refers to I/O ports.
+const BAR_IO: u32 = 1 << 0;
+/// The mask of the type field of a memory base address register.
+const BAR_MEMORY_TYPE_MASK: u32 = 0b11 << 1;
+/// The value of the type field of a 64-bit memory base address register.
+const BAR_MEMORY_TYPE_64: u32 = 0b10 << 1;
+/// Set in a memory base address register whose memory is prefetchable.
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// A base address register that is not implemented.
+pub const EMPTY_BAR: PciBar = PciBar {
+    address: 0,
+    size: 0,
+    flags: PciBarFlags::empty(),
+    _reserved: [0; 4],
+};
+
+/// Writes all ones to the register at `offset` and returns the value read back. The original
+/// value of the register is restored.
+///
+/// # Safety
+///
+/// The decoding of the device must be disabled.
+unsafe fn probe_register(address: ConfigAddress, offset: u8) -> u32 {
+    unsafe {
+        let original = read_config(address, offset);
+        write_config(address, offset, u32::MAX);
+        let probed = read_config(address, offset);
+        write_config(address, offset, original);
+        probed
+    }
+}
+
+/// Decodes the first `count` base address registers of the provided device.
+///
+/// The decoding of the device is disabled while the registers are probed, preventing it from
+/// responding to accesses at the temporary addresses written to the registers.
+///
+/// # Safety
+///
+/// `address` must refer to an existing device function, and the function must not be used
+/// concurrently.
+pub unsafe fn decode(address: ConfigAddress, count: usize) -> [PciBar; PCI_BAR_COUNT] {
+    let mut bars = [EMPTY_BAR; PCI_BAR_COUNT];
+
+    unsafe {
+        let command = read_config(address, COMMAND);
+        write_config(
+            address,
+            COMMAND,
+            command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE),
+        );
+
+        let mut index = 0;
+        while index < count {
+            let offset = BAR0 + index as u8 * 4;
+            let raw = read_config(address, offset);
+
+            if raw & BAR_IO != 0 {
+                bars[index] = decode_io(raw, probe_register(address, offset));
+                index += 1;
+                continue;
+            }
+
+            let is_64bit = raw & BAR_MEMORY_TYPE_MASK == BAR_MEMORY_TYPE_64;
+
+            if is_64bit && index + 1 >= count {
+                // The upper half of the register would be outside of the header. The device is
+                // misbehaving.
+                break;
+            }
+
+            let (raw_high, probed_high) = if is_64bit {
+                (
+                    read_config(address, offset + 4),
+                    probe_register(address, offset + 4),
+                )
+            } else {
+                (0, u32::MAX)
+            };
+
+            let probed = probe_register(address, offset);
+
+            bars[index] = decode_memory(
+                (raw_high as u64) << 32 | raw as u64,
+                (probed_high as u64) << 32 | probed as u64,
+                is_64bit,
+                raw & BAR_PREFETCHABLE != 0,
+            );
+
+            // The upper half of a 64-bit register does not describe a resource on its own.
+            index += if is_64bit { 2 } else { 1 };
+        }
+
+        write_config(address, COMMAND, command);
+    }
+
+    bars
+}
+
+/// Decodes an I/O base address register.
+fn decode_io(raw: u32, probed: u32) -> PciBar {
+    let mut mask = probed & !0b11;
+
+    if mask == 0 {
+        return EMPTY_BAR;
+    }
+
+    // Some devices only implement the lower 16 bits of I/O registers.
+    if mask & 0xFFFF_0000 == 0 {
+        mask |= 0xFFFF_0000;
+    }
+
+    PciBar {
+        address: (raw & !0b11) as u64,
+        size: (!mask).wrapping_add(1) as u64,
+        flags: PciBarFlags::PRESENT | PciBarFlags::IO,
+        _reserved: [0; 4],
+    }
+}
+
+/// Decodes a memory base address register.
+///
+/// For 32-bit registers, the upper half of `probed` must be all ones.
+fn decode_memory(raw: u64, probed: u64, is_64bit: bool, prefetchable: bool) -> PciBar {
+    let mask = probed & !0b1111;
+
+    if mask == 0 || (!is_64bit && mask == 0xFFFF_FFFF_0000_0000) {
+        return EMPTY_BAR;
+    }
+
+    let mut flags = PciBarFlags::PRESENT;
+    if is_64bit {
+        flags.insert(PciBarFlags::IS_64BIT);
+    }
+    if prefetchable {
+        flags.insert(PciBarFlags::PREFETCHABLE);
+    }
+
+    PciBar {
+        address: raw & !0b1111,
+        size: (!mask).wrapping_add(1),
+        flags,
+        _reserved: [0; 4],
+    }
+}
@@ -0,0 +1,328 @@
+//! Enumeration of the PCI devices connected to the system.
+//!
+//! The kernel does not drive PCI devices itself. Instead, it decodes their base address registers
+//! at boot and exposes the devices to userspace through the
+//! [`PublicData`](fabric_sys::x86_64::public::PublicData). A driver acquires a device, and may
+//! then map the memory of its registers in its address space.
+//!
+//! # Resource Checks
+//!
+//! The memory of a register is mapped with page granularity. A register whose memory does not
+//! start on a page boundary, or that shares a page with the register of another device, would
+//! give its owner access to memory it does not own. Such registers are never marked as
+//! [`PciBarFlags::MAPPABLE`].
+
+use fabric_sys::x86_64::public::{PciBar, PciBarFlags, PciDevice, PCI_BAR_COUNT};
+
+use crate::log;
+use crate::x86_64::instr::{inl, outl};
+use crate::x86_64::mem::PAGE_SIZE;
+
+mod bar;
+
+/// The maximum number of PCI device functions that the kernel keeps track of.
+pub const MAX_PCI_DEVICES: usize = 64;
+
+/// The I/O port used to select a register of the configuration space.
+const CONFIG_ADDRESS: u16 = 0xCF8;
+/// The I/O port used to access the selected register of the configuration space.
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// The vendor ID read from a function that does not exist.
+const INVALID_VENDOR: u16 = 0xFFFF;
+
+/// Set in the header type when the device implements multiple functions.
+const HEADER_MULTIFUNCTION: u8 = 1 << 7;
+/// The header type of general devices.
+const HEADER_GENERAL: u8 = 0x00;
+/// The header type of PCI-to-PCI bridges.
+const HEADER_BRIDGE: u8 = 0x01;
+
+/// The location of a device function on the PCI buses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+/// Selects the register at `offset` in the configuration space of the provided function.
+///
+/// # Safety
+///
+/// The configuration space must not be accessed concurrently.
+#[inline]
+unsafe fn select(address: ConfigAddress, offset: u8) {
+    let value = 1 << 31
+        | (address.bus as u32) << 16
+        | (address.device as u32) << 11
+        | (address.function as u32) << 8
+        | (offset & 0xFC) as u32;
+
+    unsafe { outl(CONFIG_ADDRESS, value) };
+}
+
+/// Reads the 32-bit register at `offset` in the configuration space of the provided function.
+///
+/// # Safety
+///
+/// The configuration space must not be accessed concurrently.
+pub unsafe fn read_config(address: ConfigAddress, offset: u8) -> u32 {
+    unsafe {
+        select(address, offset);
+        inl(CONFIG_DATA)
+    }
+}
+
+/// Writes the 32-bit register at `offset` in the configuration space of the provided function.
+///
+/// # Safety
+///
+/// The configuration space must not be accessed concurrently. Writing arbitrary registers can
+/// violate memory safety.
+pub unsafe fn write_config(address: ConfigAddress, offset: u8, value: u32) {
+    unsafe {
+        select(address, offset);
+        outl(CONFIG_DATA, value);
+    }
+}
+
+/// Set in the command register of a function to prevent it from asserting its interrupt pin.
+const COMMAND_INTERRUPT_DISABLE: u32 = 1 << 10;
+
+/// Prevents the provided function from asserting its interrupt pin, or allows it to again.
+///
+/// # Safety
+///
+/// The configuration space must not be accessed concurrently.
+pub unsafe fn set_interrupts_disabled(address: ConfigAddress, disabled: bool) {
+    unsafe {
+        // The upper half of the register is the status register, whose bits are cleared by
+        // writing a one to them.
+        let command = read_config(address, 0x04) & 0xFFFF;
+        let command = if disabled {
+            command | COMMAND_INTERRUPT_DISABLE
+        } else {
+            command & !COMMAND_INTERRUPT_DISABLE
+        };
+        write_config(address, 0x04, command);
+    }
+}
+
+/// A PCI device function known to the kernel.
+#[derive(Clone, Copy)]
+pub struct Device {
+    pub address: ConfigAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub interrupt_line: u8,
+    pub interrupt_pin: u8,
+    pub bars: [PciBar; PCI_BAR_COUNT],
+}
+
+impl Device {
+    /// Converts this device into the representation exposed to userspace.
+    pub fn to_public(self) -> PciDevice {
+        PciDevice {
+            bus: self.address.bus,
+            device: self.address.device,
+            function: self.address.function,
+            class: self.class,
+            subclass: self.subclass,
+            prog_if: self.prog_if,
+            vendor_id: self.vendor_id,
+            device_id: self.device_id,
+            interrupt_line: self.interrupt_line,
+            interrupt_pin: self.interrupt_pin,
+            _reserved: [0; 4],
+            bars: self.bars,
+            owned_by: Default::default(),
+        }
+    }
+}
+
+/// The PCI device functions found during enumeration.
+static mut DEVICES: [Option<Device>; MAX_PCI_DEVICES] = [None; MAX_PCI_DEVICES];
+
+/// Returns an iterator over the PCI device functions found during enumeration.
+pub fn devices() -> impl Iterator<Item = &'static Device> {
+    // SAFETY:
+    //  The device table is only modified by `init`.
+    unsafe { DEVICES.iter() }.flatten()
+}
+
+/// Reads the identification of the provided function, if it exists.
+///
+/// # Safety
+///
+/// The configuration space must not be accessed concurrently.
+unsafe fn probe_function(address: ConfigAddress) -> Option<Device> {
+    let id = unsafe { read_config(address, 0x00) };
+    let vendor_id = id as u16;
+
+    if vendor_id == INVALID_VENDOR {
+        return None;
+    }
+
+    let class = unsafe { read_config(address, 0x08) };
+    let header_type = unsafe { (read_config(address, 0x0C) >> 16) as u8 } & !HEADER_MULTIFUNCTION;
+
+    let bar_count = match header_type {
+        HEADER_GENERAL => 6,
+        HEADER_BRIDGE => 2,
+        _ => 0,
+    };
+
+    // Both general devices and bridges hold their interrupt configuration at this offset.
+    let interrupt = unsafe { read_config(address, 0x3C) };
+
+    Some(Device {
+        address,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        interrupt_line: interrupt as u8,
+        interrupt_pin: (interrupt >> 8) as u8,
+        bars: unsafe { bar::decode(address, bar_count) },
+    })
+}
+
+/// Enumerates the PCI device functions connected to the system and decodes their base address
+/// registers.
+///
+/// # Safety
+///
+/// This function must only be called once, before any other access to the configuration space.
+pub unsafe fn init() {
+    // SAFETY:
+    //  This function is only called once, before any other access to the device table.
+    let table = unsafe { &mut DEVICES };
+    let mut count = 0;
+
+    'scan: for bus in 0..=255 {
+        for device in 0..32 {
+            let first = ConfigAddress {
+                bus,
+                device,
+                function: 0,
+            };
+
+            let header_type = match unsafe { read_config(first, 0x00) } as u16 {
+                INVALID_VENDOR => continue,
+                _ => unsafe { (read_config(first, 0x0C) >> 16) as u8 },
+            };
+
+            let functions = if header_type & HEADER_MULTIFUNCTION != 0 {
+                8
+            } else {
+                1
+            };
+
+            for function in 0..functions {
+                let address = ConfigAddress { function, ..first };
+                let Some(found) = (unsafe { probe_function(address) }) else {
+                    continue;
+                };
+
+                if count >= MAX_PCI_DEVICES {
+                    log::warn!("Too many PCI devices.");
+                    log::warn!("Only the first {} will be available.", MAX_PCI_DEVICES);
+                    break 'scan;
+                }
+
+                table[count] = Some(found);
+                count += 1;
+            }
+        }
+    }
+
+    check_resources(table);
+
+    for device in devices() {
+        log::trace!(
+            "PCI {:02x}:{:02x}.{} {:04x}:{:04x} (class {:02x}:{:02x})",
+            device.address.bus,
+            device.address.device,
+            device.address.function,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+        );
+    }
+}
+
+/// Returns the range of pages covered by the memory of the provided register.
+fn page_range(bar: &PciBar) -> (u64, u64) {
+    let start = bar.address & !(PAGE_SIZE as u64 - 1);
+    let end = bar
+        .address
+        .saturating_add(bar.size)
+        .saturating_add(PAGE_SIZE as u64 - 1)
+        & !(PAGE_SIZE as u64 - 1);
+    (start, end)
+}
+
+/// Returns whether the provided register refers to memory.
+#[inline(always)]
+fn is_memory(bar: &PciBar) -> bool {
+    bar.flags.contains(PciBarFlags::PRESENT) && !bar.flags.contains(PciBarFlags::IO)
+}
+
+/// Marks the memory registers that may safely be mapped by the owner of their device as
+/// [`PciBarFlags::MAPPABLE`].
+fn check_resources(table: &mut [Option<Device>]) {
+    for i in 0..table.len() {
+        let Some(device) = table[i] else {
+            continue;
+        };
+
+        for (b, bar) in device.bars.iter().enumerate() {
+            if !is_memory(bar) {
+                continue;
+            }
+
+            let (start, end) = page_range(bar);
+
+            let unassigned = bar.address == 0;
+            let misaligned = bar.address % PAGE_SIZE as u64 != 0;
+            let overflows = bar.address.checked_add(bar.size).is_none();
+
+            // Look for another register whose memory shares one of the pages of this one.
+            let shared = table.iter().enumerate().any(|(j, other)| {
+                other.iter().any(|other| {
+                    other.bars.iter().enumerate().any(|(c, other)| {
+                        let (other_start, other_end) = page_range(other);
+                        (j, c) != (i, b)
+                            && is_memory(other)
+                            && other.address != 0
+                            && other_start < end
+                            && start < other_end
+                    })
+                })
+            });
+
+            if unassigned || misaligned || overflows || shared {
+                log::warn!(
+                    "PCI {:02x}:{:02x}.{} BAR{} ({:#x}, {:#x} bytes) cannot be mapped.",
+                    device.address.bus,
+                    device.address.device,
+                    device.address.function,
+                    b,
+                    bar.address,
+                    bar.size,
+                );
+                continue;
+            }
+
+            if let Some(device) = &mut table[i] {
+                device.bars[b].flags.insert(PciBarFlags::MAPPABLE);
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Data that is local to each CPU.
+//!
+//! Every CPU owns a [`PerCpu`] block, whose address is loaded in its `gs` base while the kernel
+//! runs. The fields of the block are read and written through `gs`-relative accesses, which
+//! never touch the block of another CPU.
+//!
+//! # The `gs` Base
+//!
+//! Userspace can change its own `gs` base by loading a segment selector in `gs`. The kernel
+//! therefore swaps the base with the one of userspace when it enters and leaves userspace:
+//!
+//! - While the kernel runs, **IA32_GS_BASE** points to the block of the CPU, and
+//!   **IA32_KERNEL_GS_BASE** holds the base of userspace.
+//! - While userspace runs, the two are exchanged.
+//!
+//! The entry points that may be reached from userspace and access the block execute `swapgs`
+//! when the interrupted context belongs to userspace, and again before returning to it. Those are
+//! the system call entry point, the timer interrupt, the entry points of exceptions that save an
+//! [`ExceptionFrame`](super::raw::ExceptionFrame), and [`restore_trap_frame`].
+//!
+//! The handlers written with the `x86-interrupt` calling convention run with the `gs` base of
+//! the interrupted context, and must not access per-CPU data.
+//!
+//! [`restore_trap_frame`]: super::scheduler::restore_trap_frame
+//!
+//! # Limitations
+//!
+//! Non-maskable interrupts and machine checks may be taken between the `syscall` instruction and
+//! the first `swapgs` of the system call entry point. Their handlers never return, and do not
+//! access per-CPU data.
+
+use core::arch::asm;
+use core::mem::offset_of;
+
+use fabric_sys::ProcessId;
+
+use super::instr;
+use super::raw;
+use super::smp::MAX_CPUS;
+
+/// The data that is local to a CPU.
+///
+/// The blocks are part of the `.entry.data` section, as the system call entry point accesses
+/// them before loading the complete address space of the current process when page table
+/// isolation is enabled.
+#[repr(C)]
+#[allow(dead_code)] // some fields are only read through `gs`
+pub struct PerCpu {
+    /// The address of this structure, so that it can be read through `gs:0`.
+    this: usize,
+    /// The index of the CPU in the CPU table. The bootstrap CPU has index 0.
+    index: usize,
+    /// The ID of the local APIC of the CPU.
+    lapic_id: u32,
+    /// The stack used when the CPU enters the kernel.
+    kernel_stack_top: usize,
+    /// The stack pointer of userspace, saved by the system call entry point while it switches to
+    /// the kernel stack.
+    user_stack_pointer: usize,
+    /// The ID of the process that's currently running on the CPU, or 0.
+    current_process: usize,
+}
+
+impl PerCpu {
+    /// The block of a CPU that has not been initialized.
+    const EMPTY: Self = Self {
+        this: 0,
+        index: 0,
+        lapic_id: 0,
+        kernel_stack_top: 0,
+        user_stack_pointer: 0,
+        current_process: 0,
+    };
+}
+
+/// The offset of the user stack pointer within [`PerCpu`], used by the system call entry point.
+pub const USER_STACK_POINTER_OFFSET: usize = offset_of!(PerCpu, user_stack_pointer);
+
+/// The offset of the kernel stack top within [`PerCpu`], used by the system call entry point.
+pub const KERNEL_STACK_TOP_OFFSET: usize = offset_of!(PerCpu, kernel_stack_top);
+
+/// The blocks of the CPUs, indexed like the CPU table of [`smp`](super::smp).
+#[link_section = ".entry.data"]
+static mut BLOCKS: [PerCpu; MAX_CPUS] = [PerCpu::EMPTY; MAX_CPUS];
+
+/// Initializes the block of the CPU with the provided index, and installs it on the current CPU.
+///
+/// # Safety
+///
+/// This function must be called once per CPU, on that CPU, after its GDT has been loaded:
+/// loading a segment selector in `gs` overwrites its base. The kernel must not have entered
+/// userspace on the CPU yet.
+pub unsafe fn init(index: usize, lapic_id: u32, kernel_stack_top: usize) {
+    // SAFETY:
+    //  The block of a CPU is only accessed by that CPU once it has been initialized.
+    unsafe {
+        let block = &mut BLOCKS[index];
+        block.this = block as *mut PerCpu as usize;
+        block.index = index;
+        block.lapic_id = lapic_id;
+        block.kernel_stack_top = kernel_stack_top;
+
+        instr::wrmsr(raw::IA32_GS_BASE, block.this as u64);
+        instr::wrmsr(raw::IA32_KERNEL_GS_BASE, 0);
+    }
+}
+
+/// Reads the `usize` field at `offset` in the block of the current CPU.
+#[inline(always)]
+fn read(offset: usize) -> usize {
+    let value: usize;
+
+    // SAFETY:
+    //  The `gs` base points to the block of the current CPU while the kernel runs.
+    unsafe {
+        asm!(
+            "mov {}, gs:[{}]",
+            out(reg) value,
+            in(reg) offset,
+            options(nostack, readonly, preserves_flags),
+        );
+    }
+
+    value
+}
+
+/// Writes the `usize` field at `offset` in the block of the current CPU.
+#[inline(always)]
+fn write(offset: usize, value: usize) {
+    // SAFETY:
+    //  The `gs` base points to the block of the current CPU while the kernel runs.
+    unsafe {
+        asm!(
+            "mov gs:[{}], {}",
+            in(reg) offset,
+            in(reg) value,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// Returns the index of the current CPU in the CPU table.
+#[inline]
+pub fn index() -> usize {
+    read(offset_of!(PerCpu, index))
+}
+
+/// Returns the top of the stack used when the current CPU enters the kernel.
+#[inline]
+pub fn kernel_stack_top() -> usize {
+    read(KERNEL_STACK_TOP_OFFSET)
+}
+
+/// Returns the ID of the process that's currently running on the current CPU, if any.
+#[inline]
+pub fn current_process() -> Option<ProcessId> {
+    ProcessId::new(read(offset_of!(PerCpu, current_process)))
+}
+
+/// Sets the ID of the process that's currently running on the current CPU.
+///
+/// # Safety
+///
+/// The address space of the process must be loaded, or `id` must be `None`.
+#[inline]
+pub unsafe fn set_current_process(id: Option<ProcessId>) {
+    write(
+        offset_of!(PerCpu, current_process),
+        id.map_or(0, ProcessId::get),
+    );
+}
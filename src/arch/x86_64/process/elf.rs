@@ -0,0 +1,207 @@
+//! Parsing of ELF64 executables.
+//!
+//! Only the program headers are used: the kernel does not care about sections. The executable
+//! must be statically linked and position-dependent (`ET_EXEC`), as the kernel performs no
+//! relocation and has no dynamic linker.
+//!
+//! # Segments
+//!
+//! Each `PT_LOAD` program header becomes a [`LoadSegment`]. Its pages are mapped with the access
+//! rights requested by the header: code is read-only, and data is never executable. A segment
+//! may not be both writable and executable, and two segments may not share a page, otherwise the
+//! rights of one of them would leak into the other.
+//!
+//! The part of a segment that is not backed by the file (its `.bss`) is zeroed.
+
+use core::mem::size_of;
+
+use fabric_sys::x86_64::MapFlags;
+
+use crate::utility::num;
+use crate::x86_64::mem::PAGE_SIZE;
+
+use super::{LoadError, LoadSegment, INBOX_ADDRESS, MAX_SEGMENTS};
+
+/// The magic number at the start of every ELF file.
+pub const MAGIC: [u8; 4] = *b"\x7FELF";
+
+/// The size of the ELF header of a 64-bit file.
+const HEADER_SIZE: usize = 64;
+/// The size of a program header of a 64-bit file.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// `e_ident[EI_CLASS]` for 64-bit files.
+const ELFCLASS64: u8 = 2;
+/// `e_ident[EI_DATA]` for little-endian files.
+const ELFDATA2LSB: u8 = 1;
+/// `e_ident[EI_DATA]` for big-endian files.
+const ELFDATA2MSB: u8 = 2;
+/// The only version of the ELF format.
+const EV_CURRENT: u8 = 1;
+
+/// `e_type` for executables that are loaded at a fixed address.
+const ET_EXEC: u16 = 2;
+/// `e_machine` for x86_64.
+const EM_X86_64: u16 = 62;
+
+/// A segment that is loaded in memory.
+const PT_LOAD: u32 = 1;
+/// A segment that requests a dynamic linker.
+const PT_INTERP: u32 = 3;
+
+/// The segment is executable.
+const PF_X: u32 = 1 << 0;
+/// The segment is writable.
+const PF_W: u32 = 1 << 1;
+
+/// Reads a value of type `T` at `offset` in `data`.
+fn read<T: Copy>(data: &[u8], offset: usize) -> Option<T> {
+    let bytes = data.get(offset..offset.checked_add(size_of::<T>())?)?;
+
+    // SAFETY:
+    //  We checked that `data` is large enough, and `T` is only instantiated with integers.
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Returns whether `bytes` starts like an ELF file.
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// The segments and the entry point of an ELF executable.
+pub struct Executable<'a> {
+    /// The address of the first instruction of the process.
+    pub entry_point: usize,
+    /// The segments of the executable, sorted by address. Only the first `segment_count` are
+    /// valid.
+    segments: [LoadSegment<'a>; MAX_SEGMENTS],
+    /// The number of segments of the executable.
+    segment_count: usize,
+}
+
+impl<'a> Executable<'a> {
+    /// Returns the segments of the executable, sorted by address.
+    pub fn segments(&self) -> &[LoadSegment<'a>] {
+        &self.segments[..self.segment_count]
+    }
+}
+
+/// Parses the ELF executable stored in `bytes`.
+pub fn parse(bytes: &[u8]) -> Result<Executable, LoadError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(LoadError::TooSmall);
+    }
+
+    if !is_elf(bytes) {
+        return Err(LoadError::InvalidMagic);
+    }
+
+    match bytes[5] {
+        ELFDATA2LSB => (),
+        ELFDATA2MSB => return Err(LoadError::WrongEndianness),
+        _ => return Err(LoadError::UnsupportedElf),
+    }
+
+    let header = |offset| read::<u16>(bytes, offset).ok_or(LoadError::TooSmall);
+
+    if bytes[4] != ELFCLASS64
+        || bytes[6] != EV_CURRENT
+        || header(0x10)? != ET_EXEC
+        || header(0x12)? != EM_X86_64
+    {
+        return Err(LoadError::UnsupportedElf);
+    }
+
+    let entry_point = read::<u64>(bytes, 0x18).ok_or(LoadError::TooSmall)? as usize;
+    let phoff = read::<u64>(bytes, 0x20).ok_or(LoadError::TooSmall)? as usize;
+    let phentsize = header(0x36)? as usize;
+    let phnum = header(0x38)? as usize;
+
+    if phentsize < PROGRAM_HEADER_SIZE {
+        return Err(LoadError::UnsupportedElf);
+    }
+
+    let mut executable = Executable {
+        entry_point,
+        segments: [LoadSegment::EMPTY; MAX_SEGMENTS],
+        segment_count: 0,
+    };
+
+    for index in 0..phnum {
+        let offset = index
+            .checked_mul(phentsize)
+            .and_then(|offset| offset.checked_add(phoff))
+            .ok_or(LoadError::TooSmall)?;
+        let program_header = bytes
+            .get(offset..)
+            .filter(|header| header.len() >= PROGRAM_HEADER_SIZE)
+            .ok_or(LoadError::TooSmall)?;
+
+        // The reads cannot fail, as the program header is large enough.
+        let field = |offset| read::<u64>(program_header, offset).unwrap_or_default() as usize;
+        let kind = read::<u32>(program_header, 0x00).unwrap_or_default();
+        let flags = read::<u32>(program_header, 0x04).unwrap_or_default();
+
+        match kind {
+            PT_LOAD => (),
+            PT_INTERP => return Err(LoadError::UnsupportedElf),
+            _ => continue,
+        }
+
+        let file_offset = field(0x08);
+        let address = field(0x10);
+        let file_size = field(0x20);
+        let memory_size = field(0x28);
+
+        if memory_size == 0 {
+            continue;
+        }
+
+        let data = num::range_end(file_offset, file_size)
+            .and_then(|end| bytes.get(file_offset..end))
+            .ok_or(LoadError::InvalidSegments)?;
+
+        let start = num::page_align_down(address);
+        let end = num::range_end(address, memory_size)
+            .and_then(num::page_align_up)
+            .ok_or(LoadError::InvalidSegments)?;
+
+        // Segments must be sorted, must not share a page, and must end before the inbox of the
+        // process. The first page is never mapped, so that null pointers always fault.
+        let previous_end = executable.segments().last().map_or(PAGE_SIZE, |s| s.end);
+        if file_size > memory_size || start < previous_end || end > INBOX_ADDRESS {
+            return Err(LoadError::InvalidSegments);
+        }
+
+        let flags = match (flags & PF_W != 0, flags & PF_X != 0) {
+            (false, false) => MapFlags::empty(),
+            (true, false) => MapFlags::WRITABLE,
+            (false, true) => MapFlags::EXECUTABLE,
+            (true, true) => return Err(LoadError::InvalidSegments),
+        };
+
+        let slot = executable
+            .segments
+            .get_mut(executable.segment_count)
+            .ok_or(LoadError::InvalidSegments)?;
+        *slot = LoadSegment {
+            start,
+            end,
+            data_address: address,
+            data,
+            flags,
+        };
+        executable.segment_count += 1;
+    }
+
+    // Perform some sanity checks on the entry point, like for flat images.
+    let executes_entry_point = executable.segments().iter().any(|segment| {
+        segment.flags.contains(MapFlags::EXECUTABLE)
+            && (segment.start..segment.end).contains(&entry_point)
+    });
+    if !executes_entry_point {
+        return Err(LoadError::InvalidEntryPoint);
+    }
+
+    Ok(executable)
+}
@@ -0,0 +1,512 @@
+use core::mem::size_of;
+use fabric_sys::x86_64::MapFlags;
+
+use fabric_sys::{Arg, InitArgs, InitHeader, ProcessId, INIT_STACK_SIZE};
+
+use crate::utility::num;
+use crate::x86_64::cpu::paging::{self, PageTable, UpperHalfAddressSpaceTok};
+use crate::x86_64::cpu::pti;
+use crate::x86_64::mem::{
+    MemoryTrackerTok, OutOfMemory, Page, PhysAddr, VirtAddr, HHDM_OFFSET, PAGE_SIZE,
+};
+
+use crate::x86_64::{aslr, scheduler};
+
+use super::{elf, page_flags_of, Backing, Process, Region, INBOX_ADDRESS};
+
+/// The maximum number of segments of a process image.
+pub const MAX_SEGMENTS: usize = 8;
+
+/// The image of a process, stored in physical memory.
+///
+/// Two formats are supported:
+///
+/// - Statically linked ELF64 executables, whose `PT_LOAD` segments are mapped with the access
+///   rights requested by their program headers. See [`elf`].
+///
+/// - Flat images, which start with an [`InitHeader`] and are copied as-is at the address
+///   specified in that header. The header splits the image in three segments: code, read-only
+///   data, and writable data. Each segment is mapped with its own access rights.
+///
+/// The image itself is never modified.
+#[derive(Debug, Clone, Copy)]
+pub struct Image {
+    /// The physical address of the first byte of the image.
+    pub physical_address: PhysAddr,
+    /// The size of the image, in bytes.
+    pub size: usize,
+    /// The physical address of the command line passed to the process.
+    pub cmdline_address: PhysAddr,
+    /// The length of the command line passed to the process, in bytes.
+    pub cmdline_length: usize,
+}
+
+/// An error that might occur when loading a process [`Image`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The image is too small to hold an [`InitHeader`].
+    TooSmall,
+    /// The magic number of the header is invalid.
+    InvalidMagic,
+    /// The magic number of the header is reversed, indicating that the image has been compiled
+    /// for a different endianness.
+    WrongEndianness,
+    /// The entry point is not part of the code segment of the image.
+    InvalidEntryPoint,
+    /// The segments described by the header are not ordered, not aligned to a page boundary, or
+    /// not part of the image.
+    InvalidSegments,
+    /// The image is an ELF file, but not a statically linked x86_64 executable.
+    UnsupportedElf,
+    /// The arguments of the process do not fit in the topmost page of its stack.
+    ArgumentsTooLarge,
+    /// The system ran out of memory while creating the address space of the process.
+    OutOfMemory,
+    /// The process table is full.
+    TooManyProcesses,
+}
+
+impl LoadError {
+    /// Returns a human-readable description of the error.
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::TooSmall => "the image is too small to hold the necessary header",
+            Self::InvalidMagic => "the image does not have a valid header",
+            Self::WrongEndianness => "the image has been compiled for a different endianness",
+            Self::InvalidEntryPoint => "the image does not have a valid entry point",
+            Self::InvalidSegments => "the image does not have valid segment bounds",
+            Self::UnsupportedElf => "the image is not a static x86_64 executable",
+            Self::ArgumentsTooLarge => "the command line of the image is too large",
+            Self::OutOfMemory => "not enough memory to create the address space",
+            Self::TooManyProcesses => "too many processes are running",
+        }
+    }
+}
+
+/// Creates a new [`Process`] running the provided image.
+///
+/// See [`load_from`].
+///
+/// # Safety
+///
+/// The provided image must reference valid memory.
+pub unsafe fn load(
+    image: Image,
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<Process, LoadError> {
+    // SAFETY:
+    //  The caller guarantees that the image references valid memory.
+    let (bytes, cmdline) = unsafe {
+        (
+            core::slice::from_raw_parts(image.physical_address.hhdm_ptr::<u8>(), image.size),
+            core::slice::from_raw_parts(
+                image.cmdline_address.hhdm_ptr::<u8>(),
+                image.cmdline_length,
+            ),
+        )
+    };
+
+    load_from(bytes, cmdline, upper_half)
+}
+
+/// A range of the address space of a process that is initialized from its image.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadSegment<'a> {
+    /// The first address of the segment, aligned to a page boundary.
+    pub start: usize,
+    /// The end of the segment, aligned to a page boundary.
+    pub end: usize,
+    /// The address at which [`LoadSegment::data`] is copied.
+    pub data_address: usize,
+    /// The bytes of the image that initialize the segment. The rest of the segment is zeroed.
+    pub data: &'a [u8],
+    /// The access rights of the segment.
+    pub flags: MapFlags,
+}
+
+impl LoadSegment<'_> {
+    /// A segment that covers no memory.
+    pub const EMPTY: Self = Self {
+        start: 0,
+        end: 0,
+        data_address: 0,
+        data: &[],
+        flags: MapFlags::empty(),
+    };
+
+    /// Returns the bytes of the segment that are stored in the page starting at `page`, along
+    /// with their offset within that page.
+    fn data_in_page(&self, page: usize) -> (usize, &[u8]) {
+        let data_end = self.data_address + self.data.len();
+        let start = page.clamp(self.data_address, data_end);
+        let end = (page + PAGE_SIZE).clamp(self.data_address, data_end);
+
+        (
+            start - page,
+            &self.data[start - self.data_address..end - self.data_address],
+        )
+    }
+}
+
+/// Creates a new [`Process`] running the image stored in `bytes`, with the provided command
+/// line.
+///
+/// The image does not have to be aligned in memory, nor to be stored in physical memory. It is
+/// copied before this function returns.
+///
+/// The address space of the process contains the upper half of the kernel address space, a
+/// private copy of the segments of the image, the inbox of the process, and a stack holding the
+/// arguments of the process, as described in [`InitArgs`].
+pub fn load_from(
+    bytes: &[u8],
+    cmdline: &[u8],
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<Process, LoadError> {
+    if elf::is_elf(bytes) {
+        let executable = elf::parse(bytes)?;
+        load_segments(
+            executable.entry_point,
+            executable.segments(),
+            cmdline,
+            upper_half,
+        )
+    } else {
+        let (entry_point, segments) = flat_segments(bytes)?;
+        load_segments(entry_point, &segments, cmdline, upper_half)
+    }
+}
+
+/// Splits a flat image, which starts with an [`InitHeader`], in its segments.
+///
+/// # Returns
+///
+/// The entry point of the process is returned, along with the code, read-only data and writable
+/// data segments of the image. Some of them may be empty.
+fn flat_segments(bytes: &[u8]) -> Result<(usize, [LoadSegment; 3]), LoadError> {
+    // We converting numbers using the native endianness, as the kernel is not supposed to run
+    // a process that was compiled for a different endianness.
+    // If a process is compiled for a different endianness, the magic number will be reversed and
+    // we will be able to detect it.
+    if bytes.len() < core::mem::size_of::<InitHeader>() {
+        return Err(LoadError::TooSmall);
+    }
+
+    // SAFETY:
+    //  The image is large enough to hold the header.
+    let header = unsafe { bytes.as_ptr().cast::<InitHeader>().read_unaligned() };
+
+    if InitHeader::MAGIC != header.magic {
+        if header.magic == InitHeader::MAGIC.swap_bytes() {
+            return Err(LoadError::WrongEndianness);
+        }
+        return Err(LoadError::InvalidMagic);
+    }
+
+    let image_start = header.image_start as usize;
+    let text_end = header.text_end as usize;
+    let data_start = header.data_start as usize;
+    let Some(image_end) =
+        num::page_align_up(bytes.len()).and_then(|len| num::range_end(image_start, len))
+    else {
+        return Err(LoadError::InvalidSegments);
+    };
+
+    // The segments must be ordered, page-aligned, and part of the image. Otherwise, a page could
+    // end up being both writable and executable. The image must also end before the inbox of
+    // the process.
+    if !num::is_page_aligned(image_start)
+        || !num::is_page_aligned(text_end)
+        || !num::is_page_aligned(data_start)
+        || text_end < image_start
+        || data_start < text_end
+        || data_start > image_end
+        || image_end > INBOX_ADDRESS
+    {
+        return Err(LoadError::InvalidSegments);
+    }
+
+    // Perform some sanity checks on the entry point.
+    //
+    // This won't prevent all possible issues (far from it), but it should catch some errors early
+    // on.
+    if header.entry_point.is_null()
+        || (header.entry_point as usize) < image_start
+        || (header.entry_point as usize) >= text_end
+    {
+        return Err(LoadError::InvalidEntryPoint);
+    }
+
+    // Code is mapped read-only and executable, read-only data is neither writable nor executable,
+    // and the rest of the image is writable but not executable.
+    let segment = |start: usize, end: usize, flags| {
+        let offset = (start - image_start).min(bytes.len());
+        let data_end = (end - image_start).min(bytes.len());
+
+        LoadSegment {
+            start,
+            end,
+            data_address: start,
+            data: &bytes[offset..data_end],
+            flags,
+        }
+    };
+
+    Ok((
+        header.entry_point as usize,
+        [
+            segment(image_start, text_end, MapFlags::EXECUTABLE),
+            segment(text_end, data_start, MapFlags::empty()),
+            segment(data_start, image_end, MapFlags::WRITABLE),
+        ],
+    ))
+}
+
+/// Creates a new [`Process`] whose address space is initialized with the provided segments.
+///
+/// The segments must be sorted by address, must not overlap, and must end before the inbox of
+/// the process.
+fn load_segments(
+    entry_point: usize,
+    segments: &[LoadSegment],
+    cmdline: &[u8],
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<Process, LoadError> {
+    let image_end = segments.last().map_or(0, |segment| segment.end);
+
+    // We need to map the kernel in the upper half of the address space. The L3 tables of the
+    // kernel address space are shared by all processes.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+
+    let l4_table = memory_tracker
+        .lock()
+        .allocate_page_table()
+        .map_err(|_| LoadError::OutOfMemory)?;
+
+    // SAFETY:
+    //  The table has just been allocated.
+    upper_half.share_with(unsafe { &mut *l4_table.hhdm_ptr::<PageTable>() });
+
+    let layout = aslr::Layout::pick(image_end);
+
+    let mut process = Process::new(l4_table, entry_point);
+    process.map_base = layout.map_base;
+
+    if map_segments(&mut process, memory_tracker, segments).is_err() {
+        discard(process);
+        return Err(LoadError::OutOfMemory);
+    }
+
+    let rsp = match push_args(&mut process, layout.stack_end, cmdline) {
+        Ok(rsp) => rsp,
+        Err(error) => {
+            discard(process);
+            return Err(error);
+        }
+    };
+    process.context.rsp = rsp as u64;
+    process.context.rdi = rsp as u64;
+
+    Ok(process)
+}
+
+/// Creates the user table of the process, copies the provided segments into its address space,
+/// and maps its inbox.
+///
+/// # Errors
+///
+/// If the system runs out of memory, an error is returned. The memory that was already mapped
+/// is part of the memory map of the process, and is freed along with its address space.
+///
+/// # Locking
+///
+/// The segments may refer to the memory of the calling process (see the `spawn` system call),
+/// whose pages are populated on first access. Populating a page requires the memory tracker, so
+/// it is never locked while the segments are read.
+fn map_segments(
+    process: &mut Process,
+    memory_tracker: MemoryTrackerTok,
+    segments: &[LoadSegment],
+) -> Result<(), OutOfMemory> {
+    process.user_table = pti::create_user_table(&mut memory_tracker.lock())?;
+
+    // The image is copied into frames owned by the process rather than mapped directly. This
+    // way, the original image is never modified and can be used to start other instances of the
+    // process later on.
+    for segment in segments {
+        if segment.start == segment.end {
+            continue;
+        }
+
+        // The region is recorded first, so that the pages mapped so far are released with the
+        // rest of the address space if a later allocation fails.
+        process
+            .memory_map
+            .insert(Region {
+                start: segment.start,
+                length: segment.end - segment.start,
+                flags: segment.flags,
+                backing: Backing::Image,
+            })
+            .map_err(|_| OutOfMemory)?;
+
+        let page_flags = page_flags_of(segment.flags);
+
+        for page in Page::range_of(VirtAddr::new(segment.start), segment.end - segment.start) {
+            let (offset, data) = segment.data_in_page(page.start().get());
+
+            let frame = memory_tracker.lock().allocate()?;
+
+            // SAFETY:
+            //  The frame has just been allocated, and the HHDM maps all physical memory.
+            unsafe {
+                let dst = frame.start().hhdm_ptr::<u8>();
+                core::ptr::write_bytes(dst, 0, PAGE_SIZE);
+                core::ptr::copy_nonoverlapping(data.as_ptr(), dst.add(offset), data.len());
+            }
+
+            let mut memory_tracker = memory_tracker.lock();
+            let mapped = unsafe {
+                paging::map_4kib(
+                    process.l4_table(),
+                    HHDM_OFFSET,
+                    &mut || memory_tracker.allocate_page_table(),
+                    page,
+                    frame,
+                    page_flags,
+                )
+            };
+
+            if mapped.is_err() {
+                memory_tracker.mark_as_unused(frame);
+                return Err(OutOfMemory);
+            }
+        }
+    }
+
+    process.map_inbox(&mut memory_tracker.lock())
+}
+
+/// Maps the initial stack of the process, ending at `stack_end`, and writes its arguments at the
+/// top of it.
+///
+/// # Returns
+///
+/// This function returns the initial stack pointer of the process.
+///
+/// # Errors
+///
+/// On failure, the stack may already be part of the memory map of the process. It is freed
+/// along with the address space of the process.
+fn push_args(process: &mut Process, stack_end: usize, cmdline: &[u8]) -> Result<usize, LoadError> {
+    let args = || {
+        cmdline
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+    };
+    let argc = args().count();
+
+    let stack_start = stack_end - INIT_STACK_SIZE;
+
+    // Compute the layout of the arguments, from the top of the stack downwards.
+    let bytes_start = stack_end - cmdline.len();
+    let argv_start = (bytes_start & !15) - argc * size_of::<Arg>();
+    let rsp = argv_start - size_of::<InitArgs>() - 8;
+
+    if stack_end - rsp > PAGE_SIZE {
+        return Err(LoadError::ArgumentsTooLarge);
+    }
+
+    process
+        .memory_map
+        .insert(Region {
+            start: stack_start,
+            length: INIT_STACK_SIZE,
+            flags: MapFlags::WRITABLE,
+            backing: Backing::Stack,
+        })
+        .map_err(|_| LoadError::OutOfMemory)?;
+
+    // The address space of the process is not the current one. The topmost page of the stack
+    // is written through the direct map.
+    let top_page = stack_end - PAGE_SIZE;
+    if !unsafe { process.populate(top_page) } {
+        return Err(LoadError::OutOfMemory);
+    }
+    let page = Page::containing(VirtAddr::new(top_page));
+    let frame = unsafe { paging::translate_4kib(process.l4_table(), HHDM_OFFSET, page) }
+        .ok_or(LoadError::OutOfMemory)?;
+    let to_kernel = |virt: usize| (frame.start().to_hhdm() + (virt - top_page)).get();
+
+    unsafe {
+        let bytes = to_kernel(bytes_start) as *mut u8;
+        core::ptr::copy_nonoverlapping(cmdline.as_ptr(), bytes, cmdline.len());
+
+        let argv = to_kernel(argv_start) as *mut Arg;
+        for (i, arg) in args().enumerate() {
+            let offset = arg.as_ptr() as usize - cmdline.as_ptr() as usize;
+            argv.add(i).write(Arg {
+                data: (bytes_start + offset) as *const u8,
+                len: arg.len(),
+            });
+        }
+
+        (to_kernel(rsp) as *mut InitArgs).write(InitArgs {
+            argc,
+            argv: argv_start as *const Arg,
+        });
+    }
+
+    Ok(rsp)
+}
+
+/// Loads the provided image in a new process, and adds it to the run queue.
+///
+/// # Safety
+///
+/// The provided image must reference valid memory.
+pub unsafe fn spawn(
+    image: Image,
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<ProcessId, LoadError> {
+    let process = unsafe { load(image, upper_half)? };
+    start(process)
+}
+
+/// Loads the image stored in `bytes` in a new process, and adds it to the run queue.
+///
+/// See [`load_from`].
+pub fn spawn_from(
+    bytes: &[u8],
+    cmdline: &[u8],
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<ProcessId, LoadError> {
+    let process = load_from(bytes, cmdline, upper_half)?;
+    start(process)
+}
+
+/// Inserts a newly loaded process in the process table, and adds it to the run queue.
+///
+/// If the process table is full, the address space of the process is freed.
+pub fn start(process: Process) -> Result<ProcessId, LoadError> {
+    match super::insert(process) {
+        Ok(id) => {
+            scheduler::enqueue(id);
+            Ok(id)
+        }
+        Err(process) => {
+            discard(process);
+            Err(LoadError::TooManyProcesses)
+        }
+    }
+}
+
+/// Frees the address space of a process that has been loaded but never started.
+fn discard(mut process: Process) {
+    // SAFETY:
+    //  The memory tracker is initialized before any process is loaded.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+
+    // SAFETY:
+    //  The process has never run, so its address space has never been loaded.
+    unsafe { process.destroy_address_space(&mut memory_tracker.lock()) };
+}
@@ -0,0 +1,97 @@
+//! The inboxes of the processes.
+//!
+//! Each process has a page of memory, mapped read-only at [`INBOX_ADDRESS`], where the kernel
+//! posts announcements that concern it. See [`Inbox`] for the layout and the protocol used to
+//! read it.
+//!
+//! The kernel accesses the inbox of a process through the direct map, by translating
+//! [`INBOX_ADDRESS`] in its address space. A process that unmaps its inbox, or maps something
+//! else over it, simply stops receiving announcements.
+
+use fabric_sys::x86_64::public::{Announcements, Inbox};
+use fabric_sys::x86_64::MapFlags;
+use fabric_sys::INIT_STACK_SIZE;
+
+use crate::x86_64::config::USER_FIXED_TOP;
+use crate::x86_64::cpu::paging;
+use crate::x86_64::mem::{MemoryTracker, OutOfMemory, Page, VirtAddr, HHDM_OFFSET, PAGE_SIZE};
+
+use super::{page_flags_of, Backing, Process, Region};
+
+/// The address at which the inbox of every process is mapped.
+///
+/// This is the page right below the default location of the initial stack of the process, whose
+/// guard page separates the two. See [`aslr`](crate::x86_64::aslr).
+pub const INBOX_ADDRESS: usize = USER_FIXED_TOP + 1 - INIT_STACK_SIZE - PAGE_SIZE;
+
+impl Process {
+    /// Maps a new, empty inbox at [`INBOX_ADDRESS`].
+    ///
+    /// # Errors
+    ///
+    /// If the system runs out of memory, [`OutOfMemory`] is returned and the process is left as
+    /// it was: the inbox is neither mapped nor part of the memory map of the process.
+    pub fn map_inbox(&mut self, memory_tracker: &mut MemoryTracker) -> Result<(), OutOfMemory> {
+        let frame = memory_tracker.allocate_zeroed()?;
+
+        let region = Region {
+            start: INBOX_ADDRESS,
+            length: PAGE_SIZE,
+            flags: MapFlags::empty(),
+            backing: Backing::Inbox,
+        };
+
+        if self.memory_map.insert(region).is_err() {
+            memory_tracker.mark_as_unused(frame);
+            return Err(OutOfMemory);
+        }
+
+        let mapped = unsafe {
+            paging::map_4kib(
+                self.l4_table(),
+                HHDM_OFFSET,
+                &mut || memory_tracker.allocate_page_table(),
+                Page::containing(VirtAddr::new(INBOX_ADDRESS)),
+                frame,
+                page_flags_of(MapFlags::empty()),
+            )
+        };
+
+        if mapped.is_err() {
+            memory_tracker.mark_as_unused(frame);
+            // The inbox is never merged with another region.
+            let _ = self.memory_map.remove(INBOX_ADDRESS, PAGE_SIZE);
+        }
+
+        mapped
+    }
+
+    /// Returns the inbox of the process, or `None` if it is no longer mapped.
+    pub fn inbox(&self) -> Option<&Inbox> {
+        let region = self.memory_map.find(INBOX_ADDRESS)?;
+        if region.backing != Backing::Inbox {
+            return None;
+        }
+
+        let page = Page::containing(VirtAddr::new(INBOX_ADDRESS));
+
+        // SAFETY:
+        //  The page tables of the process are not modified while the inbox is used, and the
+        //  frame that backs the region holds an `Inbox`.
+        unsafe {
+            let frame = paging::translate_4kib(self.l4_table(), HHDM_OFFSET, page)?;
+            Some(&*frame.start().hhdm_ptr::<Inbox>())
+        }
+    }
+}
+
+/// Posts `set` and withdraws `clear` from the inbox of every process.
+pub fn announce_all(set: Announcements, clear: Announcements) {
+    // SAFETY:
+    //  The references do not outlive the iteration.
+    for (_, process) in unsafe { super::iter() } {
+        if let Some(inbox) = process.inbox() {
+            inbox.post(set, clear);
+        }
+    }
+}
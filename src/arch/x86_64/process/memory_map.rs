@@ -0,0 +1,454 @@
+use fabric_sys::x86_64::{MapFlags, MappingKind};
+
+use crate::kassert::kensure;
+use crate::x86_64::mem::PAGE_SIZE;
+use crate::x86_64::raw::PageFlags;
+
+/// The maximum number of distinct regions that a process may have mapped in its address space.
+pub const MAX_REGIONS: usize = 64;
+
+/// The kind memory that backs a [`Region`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Memory allocated by the kernel on behalf of the process.
+    Anonymous,
+    /// The memory of the framebuffer with the provided index.
+    Framebuffer { index: usize },
+    /// A private copy of the image of the process.
+    Image,
+    /// A stack whose pages are allocated when they are first accessed.
+    ///
+    /// The lowest page of the region is a guard page and is never mapped.
+    Stack,
+    /// The memory of the PCI device with the provided index.
+    Device { index: usize },
+    /// Memory allocated by the kernel on behalf of the process, which the kernel may reclaim
+    /// when the system runs low on memory.
+    ///
+    /// `purged` is set once the memory has been reclaimed. The pages of the region are then
+    /// mapped again, zeroed, when they are accessed.
+    Purgeable { purged: bool },
+    /// The inbox of the process, where the kernel posts its announcements.
+    ///
+    /// See [`INBOX_ADDRESS`](super::INBOX_ADDRESS).
+    Inbox,
+    /// The rows of a framebuffer granted to the process by its owner.
+    ///
+    /// See [`display`](crate::x86_64::display).
+    FramebufferRegion { id: usize },
+}
+
+impl Backing {
+    /// Returns the userspace-visible [`MappingKind`] of this backing.
+    pub fn kind(self) -> MappingKind {
+        match self {
+            Self::Anonymous => MappingKind::Anonymous,
+            Self::Framebuffer { .. } => MappingKind::Framebuffer,
+            Self::Image => MappingKind::Image,
+            Self::Stack => MappingKind::Stack,
+            Self::Device { .. } => MappingKind::Device,
+            Self::Purgeable { .. } => MappingKind::Purgeable,
+            Self::Inbox => MappingKind::Inbox,
+            Self::FramebufferRegion { .. } => MappingKind::FramebufferRegion,
+        }
+    }
+
+    /// Returns whether the memory of this backing is allocated by the kernel on behalf of the
+    /// process, and must be freed when it is unmapped.
+    ///
+    /// The memory of framebuffers, of their regions and of devices is never freed.
+    pub fn is_owned(self) -> bool {
+        match self {
+            Self::Anonymous | Self::Stack | Self::Image | Self::Purgeable { .. } | Self::Inbox => {
+                true
+            }
+            Self::Framebuffer { .. } | Self::Device { .. } | Self::FramebufferRegion { .. } => {
+                false
+            }
+        }
+    }
+}
+
+/// Converts the provided [`MapFlags`] into the page flags used to map user memory.
+pub fn page_flags_of(flags: MapFlags) -> PageFlags {
+    let mut page_flags = PageFlags::USER;
+
+    if flags.contains(MapFlags::WRITABLE) {
+        page_flags.insert(PageFlags::WRITABLE);
+    }
+
+    if !flags.contains(MapFlags::EXECUTABLE) {
+        page_flags.insert(PageFlags::NO_EXECUTE);
+    }
+
+    page_flags
+}
+
+/// A contiguous region of the virtual address space of a process.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    /// The first address of the region. This is always aligned to a page boundary.
+    pub start: usize,
+    /// The size of the region, in bytes. This is always a non-zero multiple of the page size.
+    pub length: usize,
+    /// The access rights of the process on the region.
+    pub flags: MapFlags,
+    /// The memory that backs the region.
+    pub backing: Backing,
+}
+
+impl Region {
+    /// An empty region, used to initialize unused slots.
+    const EMPTY: Self = Self {
+        start: 0,
+        length: 0,
+        flags: MapFlags::empty(),
+        backing: Backing::Anonymous,
+    };
+
+    /// Returns the first address past the end of the region.
+    #[inline(always)]
+    pub fn end(&self) -> usize {
+        self.start + self.length
+    }
+
+    /// Returns whether the provided address is part of the region.
+    #[inline(always)]
+    pub fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end()
+    }
+}
+
+/// Indicates that a [`MemoryMap`] cannot hold any more regions.
+#[derive(Debug, Clone, Copy)]
+pub struct TooManyRegions;
+
+/// Keeps track of the regions mapped in the address space of a process.
+///
+/// # Representation
+///
+/// Regions are stored in a fixed-size array, sorted by start address. Regions never overlap.
+#[derive(Clone)]
+pub struct MemoryMap {
+    /// The regions. Only the first `len` elements are initialized.
+    regions: [Region; MAX_REGIONS],
+    /// The number of regions in the map.
+    len: usize,
+}
+
+impl MemoryMap {
+    /// Creates a new empty [`MemoryMap`].
+    pub const fn new() -> Self {
+        Self {
+            regions: [Region::EMPTY; MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    /// Returns the regions of the map, sorted by start address.
+    #[inline(always)]
+    pub fn regions(&self) -> &[Region] {
+        &self.regions[..self.len]
+    }
+
+    /// Returns the region that contains the provided address, if any.
+    pub fn find(&self, address: usize) -> Option<&Region> {
+        let index = self.regions().partition_point(|r| r.end() <= address);
+        self.regions().get(index).filter(|r| r.contains(address))
+    }
+
+    /// Returns whether the whole `start..start + length` range is mapped with at least the
+    /// provided flags.
+    ///
+    /// The guard page of a stack is never considered mapped, as it cannot be accessed.
+    pub fn is_mapped(&self, start: usize, length: usize, flags: MapFlags) -> bool {
+        let Some(end) = start.checked_add(length) else {
+            return false;
+        };
+
+        let mut cur = start;
+        let mut index = self.regions().partition_point(|r| r.end() <= start);
+        while cur < end {
+            match self.regions().get(index) {
+                Some(r) if r.start <= cur && r.flags.contains(flags) => {
+                    if r.backing == Backing::Stack && cur < r.start + PAGE_SIZE {
+                        return false;
+                    }
+                    cur = r.end();
+                }
+                _ => return false,
+            }
+            index += 1;
+        }
+
+        true
+    }
+
+    /// Returns the number of regions that can still be added to the map.
+    #[inline(always)]
+    pub fn free_slots(&self) -> usize {
+        MAX_REGIONS - self.len
+    }
+
+    /// Returns whether no region overlaps with the `start..start + length` range.
+    pub fn is_free(&self, start: usize, length: usize) -> bool {
+        let Some(end) = start.checked_add(length) else {
+            return false;
+        };
+
+        let index = self.regions().partition_point(|r| r.end() <= start);
+        !self.regions().get(index).is_some_and(|r| r.start < end)
+    }
+
+    /// Returns the lowest address of a free range of `length` bytes that lies entirely within
+    /// `low..high`.
+    ///
+    /// `low` must be aligned to a page boundary.
+    pub fn find_free(&self, length: usize, low: usize, high: usize) -> Option<usize> {
+        let mut candidate = low;
+
+        for r in self.regions() {
+            if r.end() <= candidate {
+                continue;
+            }
+
+            if r.start >= candidate.checked_add(length)? {
+                break;
+            }
+
+            candidate = r.end();
+        }
+
+        (candidate.checked_add(length)? <= high).then_some(candidate)
+    }
+
+    /// Inserts a new region in the map.
+    ///
+    /// Any existing region overlapping with the new one is truncated, split or removed, the same
+    /// way the page tables are overwritten by new mappings. The new region is merged with the
+    /// regions directly adjacent to it if they share the same flags and backing.
+    ///
+    /// # Errors
+    ///
+    /// If the map does not have enough space to hold the new region, an error is returned and the
+    /// map is left unchanged.
+    pub fn insert(&mut self, region: Region) -> Result<(), TooManyRegions> {
+        kensure!(
+            region.length != 0,
+            Ok(()),
+            "empty region at {:#x}",
+            region.start
+        );
+
+        if self.len_after_remove(region.start, region.end()) >= MAX_REGIONS {
+            return Err(TooManyRegions);
+        }
+
+        self.remove(region.start, region.length)?;
+
+        let index = self.regions().partition_point(|r| r.start < region.start);
+        self.insert_at(index, region);
+
+        if index + 1 < self.len && self.can_merge(index, index + 1) {
+            self.regions[index].length += self.regions[index + 1].length;
+            self.remove_at(index + 1);
+        }
+
+        if index > 0 && self.can_merge(index - 1, index) {
+            self.regions[index - 1].length += self.regions[index].length;
+            self.remove_at(index);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the regions at index `a` and `b` can be merged into a single region.
+    ///
+    /// Stacks are never merged, as each of them has its own guard page.
+    fn can_merge(&self, a: usize, b: usize) -> bool {
+        let a = &self.regions[a];
+        let b = &self.regions[b];
+        a.backing != Backing::Stack
+            && a.end() == b.start
+            && a.flags.bits() == b.flags.bits()
+            && a.backing == b.backing
+    }
+
+    /// Removes the `start..start + length` range from the map.
+    ///
+    /// Regions that partially overlap with the range are truncated or split.
+    ///
+    /// # Errors
+    ///
+    /// Splitting a region requires an additional slot in the map. If no slot is available, an
+    /// error is returned and the map is left unchanged.
+    pub fn remove(&mut self, start: usize, length: usize) -> Result<(), TooManyRegions> {
+        let end = start + length;
+
+        if !self.can_remove(start, length) {
+            return Err(TooManyRegions);
+        }
+
+        let mut index = self.regions().partition_point(|r| r.end() <= start);
+        while index < self.len && self.regions[index].start < end {
+            let r = self.regions[index];
+
+            match (r.start < start, r.end() > end) {
+                (false, false) => {
+                    self.remove_at(index);
+                    continue;
+                }
+                (true, false) => self.regions[index].length = start - r.start,
+                (false, true) => {
+                    self.regions[index].start = end;
+                    self.regions[index].length = r.end() - end;
+                }
+                (true, true) => {
+                    self.regions[index].length = start - r.start;
+                    index += 1;
+                    self.insert_at(
+                        index,
+                        Region {
+                            start: end,
+                            length: r.end() - end,
+                            ..r
+                        },
+                    );
+                }
+            }
+
+            index += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether [`remove`](Self::remove) would succeed for the `start..start + length`
+    /// range.
+    #[inline]
+    pub fn can_remove(&self, start: usize, length: usize) -> bool {
+        self.len_after_remove(start, start + length) <= MAX_REGIONS
+    }
+
+    /// Returns the number of regions that the map would have after removing the
+    /// `start..end` range.
+    fn len_after_remove(&self, start: usize, end: usize) -> usize {
+        let mut len = self.len;
+        for r in self.regions() {
+            if r.end() <= start || r.start >= end {
+                continue;
+            }
+
+            match (r.start < start, r.end() > end) {
+                (false, false) => len -= 1,
+                (true, true) => len += 1,
+                _ => (),
+            }
+        }
+        len
+    }
+
+    /// Inserts a region at the provided index, shifting the following regions.
+    fn insert_at(&mut self, index: usize, region: Region) {
+        debug_assert!(self.len < MAX_REGIONS);
+        self.regions.copy_within(index..self.len, index + 1);
+        self.regions[index] = region;
+        self.len += 1;
+    }
+
+    /// Removes the region at the provided index, shifting the following regions.
+    fn remove_at(&mut self, index: usize) {
+        self.regions.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an anonymous, writable region.
+    fn region(start: usize, pages: usize) -> Region {
+        Region {
+            start,
+            length: pages * PAGE_SIZE,
+            flags: MapFlags::WRITABLE,
+            backing: Backing::Anonymous,
+        }
+    }
+
+    /// Returns the bounds of the regions of `map`, in pages.
+    fn bounds(map: &MemoryMap) -> Vec<(usize, usize)> {
+        map.regions()
+            .iter()
+            .map(|r| (r.start / PAGE_SIZE, r.end() / PAGE_SIZE))
+            .collect()
+    }
+
+    #[test]
+    fn partial_unmap() {
+        let mut map = MemoryMap::new();
+        map.insert(region(0x10 * PAGE_SIZE, 8)).unwrap();
+
+        map.remove(0x10 * PAGE_SIZE, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(bounds(&map), [(0x12, 0x18)]);
+
+        map.remove(0x16 * PAGE_SIZE, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(bounds(&map), [(0x12, 0x16)]);
+    }
+
+    #[test]
+    fn unmap_splits_region() {
+        let mut map = MemoryMap::new();
+        map.insert(region(0x10 * PAGE_SIZE, 8)).unwrap();
+
+        map.remove(0x13 * PAGE_SIZE, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(bounds(&map), [(0x10, 0x13), (0x15, 0x18)]);
+
+        // Both halves keep the flags and the backing of the original region.
+        for r in map.regions() {
+            assert_eq!(r.flags.bits(), MapFlags::WRITABLE.bits());
+            assert_eq!(r.backing, Backing::Anonymous);
+        }
+    }
+
+    #[test]
+    fn unmap_split_needs_a_free_slot() {
+        let mut map = MemoryMap::new();
+        for i in 0..MAX_REGIONS {
+            // Leave a gap between regions, so that they are not merged.
+            map.insert(region(4 * i * PAGE_SIZE, 3)).unwrap();
+        }
+
+        assert!(!map.can_remove(PAGE_SIZE, PAGE_SIZE));
+        assert!(map.remove(PAGE_SIZE, PAGE_SIZE).is_err());
+        assert_eq!(map.regions().len(), MAX_REGIONS);
+        assert_eq!(map.regions()[0].length, 3 * PAGE_SIZE);
+
+        // Removing a whole region does not need one.
+        map.remove(0, 3 * PAGE_SIZE).unwrap();
+        assert_eq!(map.regions().len(), MAX_REGIONS - 1);
+    }
+
+    #[test]
+    fn unmap_across_regions() {
+        let mut map = MemoryMap::new();
+        map.insert(region(0x10 * PAGE_SIZE, 4)).unwrap();
+        map.insert(Region {
+            flags: MapFlags::empty(),
+            ..region(0x14 * PAGE_SIZE, 4)
+        })
+        .unwrap();
+        map.insert(region(0x1A * PAGE_SIZE, 2)).unwrap();
+        map.insert(region(0x20 * PAGE_SIZE, 4)).unwrap();
+        assert_eq!(
+            bounds(&map),
+            [(0x10, 0x14), (0x14, 0x18), (0x1A, 0x1C), (0x20, 0x24)]
+        );
+
+        // The range truncates the first region, removes the second and the third ones, skips
+        // the gaps, and truncates the last one.
+        map.remove(0x12 * PAGE_SIZE, 0x10 * PAGE_SIZE).unwrap();
+        assert_eq!(bounds(&map), [(0x10, 0x12), (0x22, 0x24)]);
+    }
+}
@@ -0,0 +1,709 @@
+//! Keeps track of the processes running on the system.
+//!
+//! # Process Table
+//!
+//! Processes are stored in a fixed-size table. The ID of a process is its index within that table,
+//! plus one (process IDs can never be zero).
+//!
+//! # Synchronization
+//!
+//! The kernel is not reentrant: interrupts are disabled while a system call is being handled, and
+//! interrupt handlers that inspect the process table only do so when they interrupted userspace.
+//! For this reason, the process table can be accessed without locking.
+
+use fabric_sys::{PortId, ProcessId};
+
+mod elf;
+mod image;
+mod inbox;
+mod memory_map;
+
+pub use self::image::*;
+pub use self::inbox::*;
+pub use self::memory_map::*;
+
+use crate::kassert::kassert;
+use crate::log;
+use crate::utility::IdAllocator;
+
+use super::cpu::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use super::cpu::paging::{self, PageTable, Released, UpperHalfAddressSpaceTok};
+use super::cpu::pti;
+use super::mem::{
+    Frame, MemoryTracker, MemoryTrackerTok, OutOfMemory, Page, PhysAddr, VirtAddr, HHDM_OFFSET,
+    PAGE_SIZE, USER_MAP_BASE,
+};
+use super::raw::{PageFlags, RFlags, TrapFrame};
+use super::tlb::{self, Asid};
+use super::{display, escrow, ipc, irq, kernel_stack, percpu, scheduler, supervisor, timeout};
+
+/// The maximum number of processes that may exist at the same time.
+pub const MAX_PROCESSES: usize = 64;
+
+/// The state of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// The process is either running, or waiting in the run queue of the scheduler.
+    Runnable,
+    /// The process has been suspended and won't be scheduled until it is resumed.
+    ///
+    /// Because the kernel never blocks in the middle of a system call, the complete userspace
+    /// context of a suspended process is always saved in [`Process::context`]. Suspending a
+    /// process never requires interrupting the kernel.
+    Suspended,
+    /// The process is waiting for another process to wake it up using the `wake` system call.
+    ///
+    /// `key` is the physical address of the word the process is waiting on.
+    Waiting { key: usize },
+    /// The process is waiting for a message to be sent to one of its ports.
+    Receiving { port: PortId },
+    /// The process is waiting for the keyboard to produce an input event.
+    ///
+    /// See [`keyboard`](super::keyboard).
+    ReadingInput,
+    /// The process sleeps until the monotonic clock reaches `until`, in nanoseconds since boot.
+    ///
+    /// The timeout of the process is armed for the first tick at which `until` may have been
+    /// reached. See [`timer::uptime_ns`](super::timer::uptime_ns).
+    Sleeping { until: u64 },
+}
+
+/// The reason why a process stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The process terminated itself using the `terminate` system call.
+    Terminated,
+    /// The process caused a CPU exception and was terminated by the kernel.
+    Exception { vector: u8 },
+    /// The process was terminated by the kernel to reclaim memory.
+    ///
+    /// See [`oom`](super::oom).
+    OutOfMemory,
+    /// The process was terminated by another process using the `terminate` system call.
+    Killed,
+}
+
+/// Stores information about a running process.
+pub struct Process {
+    /// The physical address of the process's l4 page table.
+    pub address_space: PhysAddr,
+    /// The L4 page table loaded while the process runs userspace code, when page table isolation
+    /// is enabled.
+    ///
+    /// See [`pti`](crate::x86_64::cpu::pti).
+    pub user_table: Option<PhysAddr>,
+    /// The current state of the process.
+    pub state: ProcessState,
+    /// The regions mapped in the lower half of the address space of the process.
+    pub memory_map: MemoryMap,
+    /// The lowest address that the kernel picks when it chooses where to map memory on behalf of
+    /// the process.
+    ///
+    /// See [`aslr`](super::aslr).
+    pub map_base: usize,
+    /// The timeout that wakes the process up if it is still waiting for a message, on a futex, or
+    /// for an input event when it expires.
+    ///
+    /// See [`timeout`](super::timeout).
+    pub timeout: Option<timeout::Handle>,
+    /// The process that started this one with the `spawn` system call, if it is still running.
+    pub parent: Option<ProcessId>,
+    /// The port of the parent that is notified when the process crashes, if the process is
+    /// restartable.
+    ///
+    /// See [`escrow`](super::escrow).
+    pub restart_port: Option<PortId>,
+    /// The userspace context of the process.
+    ///
+    /// This is only up to date when the process is not currently running on the CPU. Otherwise,
+    /// the context lives in the trap frame built on the kernel stack.
+    pub context: TrapFrame,
+}
+
+impl Process {
+    /// Creates a new [`Process`] that will start executing at `entry_point` once scheduled.
+    ///
+    /// The process starts in the [`ProcessState::Runnable`] state, but is not yet part of the run
+    /// queue.
+    pub fn new(address_space: PhysAddr, entry_point: usize) -> Self {
+        Self {
+            address_space,
+            user_table: None,
+            state: ProcessState::Runnable,
+            memory_map: MemoryMap::new(),
+            map_base: USER_MAP_BASE,
+            timeout: None,
+            parent: None,
+            restart_port: None,
+            context: TrapFrame {
+                rip: entry_point as u64,
+                cs: USER_CODE_SELECTOR as u64,
+                rflags: (RFlags::RESERVED_1 | RFlags::INTERRUPT_ENABLE).bits(),
+                ss: USER_DATA_SELECTOR as u64,
+                ..TrapFrame::default()
+            },
+        }
+    }
+
+    /// Returns the L4 page table of the process.
+    ///
+    /// # Safety
+    ///
+    /// The returned reference must not outlive the process, and must not be used concurrently.
+    #[inline(always)]
+    pub unsafe fn l4_table(&self) -> &'static mut PageTable {
+        unsafe { &mut *self.address_space.hhdm_ptr::<PageTable>() }
+    }
+
+    /// Cancels the timeout of the process, if it has one.
+    pub fn cancel_timeout(&mut self) {
+        if let Some(handle) = self.timeout.take() {
+            timeout::cancel(handle);
+        }
+    }
+
+    /// Returns the number of pages allocated by the kernel on behalf of the process that are
+    /// currently mapped in its address space.
+    pub fn resident_pages(&self) -> usize {
+        self.memory_map
+            .regions()
+            .iter()
+            .filter(|region| region.backing.is_owned())
+            .flat_map(|region| Page::range_of(VirtAddr::new(region.start), region.length))
+            // SAFETY:
+            //  The page tables of the process are not modified during the iteration.
+            .filter(|&page| unsafe {
+                paging::translate_4kib(self.l4_table(), HHDM_OFFSET, page).is_some()
+            })
+            .count()
+    }
+
+    /// Unmaps the `start..start + length` range of the address space of the process.
+    ///
+    /// When `memory_tracker` is provided, the physical pages that were mapped in the range are
+    /// marked as unused.
+    pub fn unmap_pages(
+        &self,
+        mut memory_tracker: Option<&mut MemoryTracker>,
+        start: usize,
+        length: usize,
+    ) {
+        let l4 = unsafe { self.l4_table() };
+
+        for page in Page::range_of(VirtAddr::new(start), length) {
+            // Only the frame that backed the page is released, never the virtual address of the
+            // page itself.
+            let unmapped = unsafe { paging::unmap_4kib(l4, HHDM_OFFSET, page) };
+
+            if let (Ok(frame), Some(tracker)) = (unmapped, memory_tracker.as_deref_mut()) {
+                tracker.mark_as_unused(frame);
+            }
+        }
+
+        // The frames must not be reused while another CPU may still access them.
+        tlb::flush_range(Asid::User(self.address_space), start, length);
+    }
+
+    /// Allocates new zeroed physical pages and maps them at `start..start + length` in the address
+    /// space of the process.
+    ///
+    /// # Errors
+    ///
+    /// If the system runs out of memory, the pages that were already mapped are unmapped and
+    /// freed, and an error is returned.
+    pub fn map_new_pages(
+        &self,
+        memory_tracker: &mut MemoryTracker,
+        start: usize,
+        length: usize,
+        page_flags: PageFlags,
+    ) -> Result<(), OutOfMemory> {
+        let l4 = unsafe { self.l4_table() };
+
+        for page in Page::range_of(VirtAddr::new(start), length) {
+            let mapped = match memory_tracker.allocate_zeroed() {
+                Ok(phys) => unsafe {
+                    paging::map_4kib(
+                        l4,
+                        HHDM_OFFSET,
+                        &mut || memory_tracker.allocate_page_table(),
+                        page,
+                        phys,
+                        page_flags,
+                    )
+                    .map_err(|err| {
+                        memory_tracker.mark_as_unused(phys);
+                        err
+                    })
+                },
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = mapped {
+                self.unmap_pages(Some(memory_tracker), start, page.start().get() - start);
+                return Err(err);
+            }
+
+            crate::x86_64::instr::invlpg(page.start().get());
+        }
+
+        Ok(())
+    }
+
+    /// Records `region` in the memory map of the process, and backs all of it with new zeroed
+    /// physical pages.
+    ///
+    /// # Errors
+    ///
+    /// If the memory map is full or the system runs out of memory, an error is returned and the
+    /// process is left as it was: the pages that were already mapped are freed, and the region
+    /// is removed from the memory map.
+    pub fn map_zeroed(
+        &mut self,
+        memory_tracker: &mut MemoryTracker,
+        region: Region,
+    ) -> Result<(), OutOfMemory> {
+        self.memory_map.insert(region).map_err(|_| OutOfMemory)?;
+
+        let mapped = self.map_new_pages(
+            memory_tracker,
+            region.start,
+            region.length,
+            page_flags_of(region.flags),
+        );
+
+        if mapped.is_err() {
+            // The region may have been merged with its neighbours, which then have enough room
+            // to be split apart again.
+            let _ = self.memory_map.remove(region.start, region.length);
+        }
+
+        mapped
+    }
+
+    /// Unmaps the `start..start + length` range of the address space of the process, and removes
+    /// it from its memory map.
+    ///
+    /// The frames that back the parts of the range mapped to memory owned by the process are
+    /// freed (see [`Backing::is_owned`]). Pages that are not part of any region are left alone.
+    ///
+    /// # Errors
+    ///
+    /// If the memory map cannot hold the regions that would result from splitting a region in
+    /// two, an error is returned and nothing is unmapped.
+    pub fn unmap_range(
+        &mut self,
+        memory_tracker: &mut MemoryTracker,
+        start: usize,
+        length: usize,
+    ) -> Result<(), TooManyRegions> {
+        if !self.memory_map.can_remove(start, length) {
+            return Err(TooManyRegions);
+        }
+
+        let end = start + length;
+
+        for region in self.memory_map.regions() {
+            let from = region.start.max(start);
+            let to = region.end().min(end);
+
+            if from >= to {
+                continue;
+            }
+
+            if region.backing.is_owned() {
+                self.unmap_pages(Some(memory_tracker), from, to - from);
+            } else {
+                self.unmap_pages(None, from, to - from);
+            }
+        }
+
+        self.memory_map.remove(start, length)
+    }
+
+    /// Unmaps the regions of the process that refer to the memory of the PCI device with the
+    /// provided index.
+    ///
+    /// The memory itself belongs to the device and is not freed.
+    pub fn unmap_device(&mut self, index: usize) {
+        self.unmap_backing(Backing::Device { index });
+    }
+
+    /// Unmaps the regions of the process that are backed by `backing`.
+    ///
+    /// The backing must not be owned by the process: its memory is not freed.
+    pub fn unmap_backing(&mut self, backing: Backing) {
+        debug_assert!(!backing.is_owned());
+
+        while let Some(region) = self
+            .memory_map
+            .regions()
+            .iter()
+            .find(|r| r.backing == backing)
+            .copied()
+        {
+            // Removing a whole region never requires splitting another region.
+            let _ = self.memory_map.remove(region.start, region.length);
+            self.unmap_pages(None, region.start, region.length);
+        }
+    }
+
+    /// Unmaps the whole lower half of the address space of the process, and frees the page
+    /// tables that mapped it.
+    ///
+    /// The frames allocated by the kernel on behalf of the process are marked as unused. Frames
+    /// that do not belong to the process, such as the ones of framebuffers and devices, are
+    /// simply unmapped.
+    ///
+    /// # Safety
+    ///
+    /// The address space of the process must not be loaded.
+    pub unsafe fn destroy_lower_half(&mut self, memory_tracker: &mut MemoryTracker) {
+        let memory_map = &self.memory_map;
+        let l4 = unsafe { self.l4_table() };
+
+        let tables = unsafe {
+            paging::destroy_lower_half(l4, HHDM_OFFSET, &mut |released| match released {
+                Released::Page { virt, phys, size } => {
+                    let region = memory_map.find(virt.get());
+
+                    kassert!(
+                        region.is_some(),
+                        "{:#x} is mapped outside of the memory map of the process",
+                        virt,
+                    );
+
+                    if region.is_some_and(|region| region.backing.is_owned()) {
+                        Frame::range_of(phys, size).for_each(|f| memory_tracker.mark_as_unused(f));
+                    }
+                }
+                Released::Table(table) => memory_tracker.free_page_table(table),
+            })
+        };
+
+        log::trace!("Freed {} page tables of the lower half.", tables);
+    }
+
+    /// Frees the whole address space of the process: the lower half (see
+    /// [`destroy_lower_half`](Self::destroy_lower_half)), and the L4 tables of the process.
+    ///
+    /// # Safety
+    ///
+    /// The address space of the process must not be loaded, and must not be used afterwards.
+    pub unsafe fn destroy_address_space(&mut self, memory_tracker: &mut MemoryTracker) {
+        // SAFETY:
+        //  The caller guarantees that the address space is not loaded.
+        unsafe { self.destroy_lower_half(memory_tracker) };
+
+        // The user table shares the lower half of the address space of the process, which has
+        // just been freed.
+        if let Some(table) = self.user_table.take() {
+            memory_tracker.free_page_table(table);
+        }
+        memory_tracker.free_page_table(self.address_space);
+    }
+
+    /// Attempts to resolve a page fault caused by the process accessing `address`, assuming
+    /// that the page containing that address is not mapped.
+    ///
+    /// If the address is part of a stack region (and not its guard page), a new zeroed page is
+    /// allocated and mapped there. The same goes for memory allocated by the kernel whose pages
+    /// have been purged or that is allocated on demand (see [`MapFlags::LAZY`]).
+    ///
+    /// [`MapFlags::LAZY`]: fabric_sys::x86_64::MapFlags::LAZY
+    ///
+    /// # Returns
+    ///
+    /// This function returns whether the fault has been resolved.
+    ///
+    /// # Safety
+    ///
+    /// The address space of the process must not be accessed concurrently.
+    pub unsafe fn populate(&mut self, address: usize) -> bool {
+        let Some(region) = self.memory_map.find(address) else {
+            return false;
+        };
+
+        let on_demand = match region.backing {
+            // The first page of a stack is its guard page.
+            Backing::Stack => address >= region.start + PAGE_SIZE,
+            Backing::Anonymous | Backing::Purgeable { .. } => true,
+            _ => false,
+        };
+
+        if !on_demand {
+            return false;
+        }
+
+        // SAFETY:
+        //  The memory tracker is initialized before any process is started.
+        let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+        let mut memory_tracker = memory_tracker.lock();
+
+        let Ok(phys) = memory_tracker.allocate_zeroed() else {
+            super::oom::report(1, 0);
+            return false;
+        };
+
+        let mapped = unsafe {
+            paging::map_4kib(
+                self.l4_table(),
+                HHDM_OFFSET,
+                &mut || memory_tracker.allocate_page_table(),
+                Page::containing(VirtAddr::new(address)),
+                phys,
+                page_flags_of(region.flags),
+            )
+        };
+
+        if mapped.is_err() {
+            memory_tracker.mark_as_unused(phys);
+            return false;
+        }
+
+        true
+    }
+}
+
+/// The global process table.
+static mut PROCESSES: [Option<Process>; MAX_PROCESSES] = {
+    const NONE: Option<Process> = None;
+    [NONE; MAX_PROCESSES]
+};
+
+/// The IDs of the slots of the process table.
+///
+/// The ID of a process that has exited never designates another process.
+static mut PROCESS_IDS: IdAllocator<MAX_PROCESSES> = IdAllocator::new();
+
+/// Inserts a new process in the process table.
+///
+/// # Errors
+///
+/// If the process table is full, the process is given back so that its resources can be
+/// released.
+pub fn insert(process: Process) -> Result<ProcessId, Process> {
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    let (table, ids) = unsafe { (&mut PROCESSES, &mut PROCESS_IDS) };
+
+    let Some((id, index)) = ids.allocate() else {
+        return Err(process);
+    };
+    table[index] = Some(process);
+
+    Ok(id)
+}
+
+/// Returns the process with the provided ID, if it exists.
+///
+/// # Safety
+///
+/// No other reference to the requested process may be alive while the returned reference is used.
+pub unsafe fn get(id: ProcessId) -> Option<&'static mut Process> {
+    let index = unsafe { PROCESS_IDS.index_of(id)? };
+    unsafe { PROCESSES[index].as_mut() }
+}
+
+/// Returns an iterator over all the processes of the table, along with their IDs.
+///
+/// # Safety
+///
+/// No other reference to a process may be alive while the returned iterator is used.
+pub unsafe fn iter() -> impl Iterator<Item = (ProcessId, &'static mut Process)> {
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    let (table, ids) = unsafe { (&mut PROCESSES, &PROCESS_IDS) };
+
+    table.iter_mut().enumerate().filter_map(|(index, slot)| {
+        let id = ids.id_at(index)?;
+        slot.as_mut().map(|process| (id, process))
+    })
+}
+
+/// Reclaims the memory of the purgeable regions of the processes until at least `wanted`
+/// pages are free, or until no purgeable memory is left.
+///
+/// The process with the ID `except` is left untouched.
+///
+/// # Returns
+///
+/// This function returns the number of pages that have been reclaimed.
+///
+/// # Safety
+///
+/// No reference to a process other than `except` may be alive.
+pub unsafe fn purge(
+    memory_tracker: &mut MemoryTracker,
+    wanted: usize,
+    except: Option<ProcessId>,
+) -> usize {
+    let before = memory_tracker.free_page_count();
+
+    for (id, process) in unsafe { iter() } {
+        if Some(id) == except {
+            continue;
+        }
+
+        let mut index = 0;
+        while index < process.memory_map.regions().len() {
+            if memory_tracker.free_page_count() >= wanted {
+                return memory_tracker.free_page_count() - before;
+            }
+
+            let region = process.memory_map.regions()[index];
+            index += 1;
+
+            if region.backing != (Backing::Purgeable { purged: false }) {
+                continue;
+            }
+
+            process.unmap_pages(Some(memory_tracker), region.start, region.length);
+
+            // Replacing a whole region never requires an additional slot. The region may be
+            // merged with its neighbours though, which shifts the following ones.
+            let _ = process.memory_map.insert(Region {
+                backing: Backing::Purgeable { purged: true },
+                ..region
+            });
+            index = process
+                .memory_map
+                .regions()
+                .partition_point(|r| r.start < region.end());
+        }
+    }
+
+    memory_tracker.free_page_count() - before
+}
+
+/// Returns the ID of the process that's currently running.
+///
+/// # Panics
+///
+/// This function panics if no process is currently running. This cannot happen when called from
+/// a system call handler.
+#[inline]
+#[track_caller]
+pub fn current_id() -> ProcessId {
+    percpu::current_process().expect("no process is currently running")
+}
+
+/// Resolves a process ID provided by userspace.
+///
+/// The ID `0` refers to the current process.
+///
+/// # Safety
+///
+/// No other reference to the requested process may be alive while the returned reference is used.
+pub unsafe fn resolve(process_id: usize) -> Option<(ProcessId, &'static mut Process)> {
+    let id = match ProcessId::new(process_id) {
+        Some(id) => id,
+        None => current_id(),
+    };
+
+    unsafe { get(id).map(|process| (id, process)) }
+}
+
+/// Returns whether the process `ancestor` started the process `id`, either directly or through
+/// processes that are still running.
+///
+/// # Safety
+///
+/// No reference to a process may be alive.
+pub unsafe fn is_ancestor(ancestor: ProcessId, id: ProcessId) -> bool {
+    let mut current = id;
+
+    // Every process of the chain is in the process table, which bounds its length.
+    for _ in 0..MAX_PROCESSES {
+        match unsafe { get(current) }.and_then(|p| p.parent) {
+            Some(parent) if parent == ancestor => return true,
+            Some(parent) => current = parent,
+            None => return false,
+        }
+    }
+
+    false
+}
+
+/// Terminates the provided process, releasing all the resources it owns.
+///
+/// If the process is the current one, it is descheduled before the kernel returns to userspace.
+pub fn terminate(id: ProcessId, reason: ExitReason) {
+    // The regions granted to the process are unmapped from its address space, which must not
+    // have been destroyed yet.
+    display::release_process(id);
+
+    let Some(process) = (unsafe { get(id) }) else {
+        return;
+    };
+
+    scheduler::dequeue(id);
+
+    process.cancel_timeout();
+
+    // SAFETY:
+    //  The memory tracker is initialized before any process is started.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    // The page tables of the process are about to be freed. The kernel must not keep running
+    // on them.
+    //
+    // SAFETY:
+    //  The kernel address space is initialized before any process is started.
+    if percpu::current_process() == Some(id) {
+        unsafe {
+            let kernel = UpperHalfAddressSpaceTok::unchecked().get();
+            crate::x86_64::instr::set_cr3(kernel.get());
+            pti::set_current(kernel, None);
+        }
+        tlb::set_active(None);
+    }
+
+    tlb::flush_address_space(process.address_space);
+
+    #[cfg(feature = "ktest")]
+    super::ktest::on_exit(id, process, reason);
+
+    // SAFETY:
+    //  The address space of the process is no longer loaded.
+    unsafe { process.destroy_address_space(&mut memory_tracker) };
+
+    irq::release_owned_by(id);
+    escrow::on_exit(id, process.restart_port, reason);
+    escrow::release_held_by(id);
+
+    ipc::destroy_owned_by(id);
+
+    // SAFETY:
+    //  The process table is never accessed concurrently.
+    unsafe {
+        if let Some(index) = PROCESS_IDS.release(id) {
+            PROCESSES[index] = None;
+        }
+    }
+
+    if percpu::current_process() == Some(id) {
+        // SAFETY:
+        //  The kernel address space has been loaded above.
+        unsafe { percpu::set_current_process(None) };
+        scheduler::request_reschedule();
+    }
+
+    // The children of the process are no longer supervised.
+    //
+    // SAFETY:
+    //  The references do not outlive the iteration.
+    for (_, child) in unsafe { iter() } {
+        if child.parent == Some(id) {
+            child.parent = None;
+            child.restart_port = None;
+        }
+    }
+
+    log::trace!("Process {} exited ({:?}).", id, reason);
+
+    kernel_stack::check_usage();
+
+    supervisor::on_exit(id, reason);
+}
@@ -1,6 +1,6 @@
 use core::mem::size_of;
 
-use fabric_sys::x86_64::public::{Framebuffer, PublicData};
+use fabric_sys::x86_64::public::{Framebuffer, PciDevice, PublicData};
 
 /// Stores the layout of the public data area mapped in every userspace program.
 ///
@@ -12,6 +12,8 @@ use fabric_sys::x86_64::public::{Framebuffer, PublicData};
 /// |-|
 /// | An instance of [`PublicData`]                    |
 /// | `framebuffer_count` instances of [`Framebuffer`] |
+/// | `pci_device_count` instances of [`PciDevice`]    |
+/// | The `cmdline_length` bytes of the command line   |
 pub struct PublicDataLayout {
     /// The total size of the public data area.
     pub size: usize,
@@ -19,11 +21,19 @@ pub struct PublicDataLayout {
     pub root: usize,
     /// The virtual address at which the framebuffers should be written.
     pub framebuffers: usize,
+    /// The virtual address at which the PCI devices should be written.
+    pub pci_devices: usize,
+    /// The virtual address at which the command line should be written.
+    pub cmdline: usize,
 }
 
 impl PublicDataLayout {
     /// Creates a new [`PublicDataLayout`] for the provided parameters.
-    pub fn compute(framebuffer_count: usize) -> Self {
+    pub fn compute(
+        framebuffer_count: usize,
+        pci_device_count: usize,
+        cmdline_length: usize,
+    ) -> Self {
         let mut offset = 0;
 
         let root = offset;
@@ -32,10 +42,19 @@ impl PublicDataLayout {
         let framebuffers = offset;
         offset += size_of::<Framebuffer>() * framebuffer_count;
 
+        // `PciDevice` has the same alignment as `Framebuffer`. No padding is needed.
+        let pci_devices = offset;
+        offset += size_of::<PciDevice>() * pci_device_count;
+
+        let cmdline = offset;
+        offset += cmdline_length;
+
         Self {
             size: offset,
             root,
             framebuffers,
+            pci_devices,
+            cmdline,
         }
     }
 }
@@ -2,6 +2,8 @@
 
 use bitflags::bitflags;
 
+use crate::utility::Register;
+
 bitflags! {
     /// Describes the flags which can be set on a segment descriptor.
     #[derive(Debug, Clone, Copy)]
@@ -119,6 +121,28 @@ pub const STAR: u32 = 0xC000_0081;
 ///
 /// It stores the address of the system call handler.
 pub const LSTAR: u32 = 0xC000_0082;
+/// The **SFMASK** model-specific register.
+///
+/// The bits set in this register are cleared from **RFLAGS** when the **SYSCALL** instruction is
+/// executed.
+pub const SFMASK: u32 = 0xC000_0084;
+
+bitflags! {
+    /// Some of the flags of the **RFLAGS** register.
+    #[derive(Debug, Clone, Copy)]
+    pub struct RFlags: u64 {
+        /// This bit is reserved and must always be set.
+        const RESERVED_1 = 1 << 1;
+        /// When set, the CPU generates a debug exception after each instruction.
+        const TRAP = 1 << 8;
+        /// When set, maskable hardware interrupts are delivered to the CPU.
+        const INTERRUPT_ENABLE = 1 << 9;
+        /// When set, string instructions decrement their index registers.
+        const DIRECTION = 1 << 10;
+        /// When set, alignment checking is enabled for user-mode accesses.
+        const ALIGNMENT_CHECK = 1 << 18;
+    }
+}
 
 bitflags! {
     /// The flags allowed in the **IA32_EFER** model-specific register.
@@ -126,20 +150,144 @@ bitflags! {
         /// Enables the **SYSCALL** and **SYSRET** instructions, for compatibility with AMD
         /// processors.
         const SYSCALL_ENABLE = 1 << 0;
+        /// Enables the `NO_EXECUTE` flag of page table entries.
+        const NO_EXECUTE_ENABLE = 1 << 11;
+    }
+}
+
+bitflags! {
+    /// The flags of the **CR0** control register used by the kernel.
+    pub struct Cr0: u64 {
+        /// Prevents the kernel from writing to read-only pages.
+        const WRITE_PROTECT = 1 << 16;
+    }
+}
+
+bitflags! {
+    /// The flags of the **CR4** control register used by the kernel.
+    pub struct Cr4: u64 {
+        /// Enables global pages, whose translations are kept in the TLB when **CR3** is written.
+        const PAGE_GLOBAL = 1 << 7;
+        /// Prevents userspace from executing the `sgdt`, `sidt`, `sldt`, `smsw` and `str`
+        /// instructions, which would leak the addresses of kernel structures.
+        const UMIP = 1 << 11;
+        /// Enables 5-level paging, where virtual addresses are 57 bits wide.
+        const LA57 = 1 << 12;
+        /// Enables the `rdfsbase`, `rdgsbase`, `wrfsbase` and `wrgsbase` instructions.
+        const FSGSBASE = 1 << 16;
+        /// Enables the control-flow enforcement technology (CET).
+        const CET = 1 << 23;
+    }
+}
+
+bitflags! {
+    /// The flags allowed in the **IA32_S_CET** model-specific register.
+    pub struct SCet: u64 {
+        /// Enables supervisor shadow stacks.
+        const SH_STK_EN = 1 << 0;
+    }
+}
+
+/// The **IA32_S_CET** model-specific register, which configures CET for the kernel.
+pub const IA32_S_CET: u32 = 0x6A2;
+
+/// The **IA32_PL0_SSP** model-specific register, loaded into the shadow stack pointer when the
+/// CPU switches to ring 0.
+pub const IA32_PL0_SSP: u32 = 0x6A4;
+
+/// The **IA32_INTERRUPT_SSP_TABLE_ADDR** model-specific register, which contains the address of
+/// the shadow stack pointers used along with the *Interrupt Stack Table*.
+pub const IA32_INTERRUPT_SSP_TABLE_ADDR: u32 = 0x6A8;
+
+bitflags! {
+    /// The flags allowed in the **IA32_SPEC_CTRL** model-specific register.
+    pub struct SpecCtrl: u64 {
+        /// *Indirect Branch Restricted Speculation*. When the CPU supports enhanced IBRS, setting
+        /// this flag once protects the kernel from branch target injection.
+        const IBRS = 1 << 0;
+        /// *Single Thread Indirect Branch Predictors*.
+        const STIBP = 1 << 1;
+        /// *Speculative Store Bypass Disable*.
+        const SSBD = 1 << 2;
+    }
+}
+
+/// The **IA32_SPEC_CTRL** model-specific register, which controls speculative execution.
+pub const IA32_SPEC_CTRL: u32 = 0x48;
+
+/// The **IA32_PRED_CMD** model-specific register. Writing [`PRED_CMD_IBPB`] to it issues an
+/// *Indirect Branch Prediction Barrier*.
+pub const IA32_PRED_CMD: u32 = 0x49;
+
+/// Prevents the indirect branches executed before the barrier from influencing the predictions of
+/// the ones executed after it.
+pub const PRED_CMD_IBPB: u64 = 1 << 0;
+
+bitflags! {
+    /// The flags of the **IA32_ARCH_CAPABILITIES** model-specific register.
+    pub struct ArchCapabilities: u64 {
+        /// The CPU is not vulnerable to rogue data cache loads (Meltdown).
+        const RDCL_NO = 1 << 0;
+        /// The CPU supports enhanced IBRS.
+        const IBRS_ALL = 1 << 1;
+        /// The CPU may use alternate predictors when the return stack buffer underflows.
+        const RSBA = 1 << 2;
+        /// The CPU is not vulnerable to speculative store bypass.
+        const SSB_NO = 1 << 4;
+        /// The CPU is not vulnerable to microarchitectural data sampling.
+        const MDS_NO = 1 << 5;
     }
 }
 
+/// The **IA32_ARCH_CAPABILITIES** model-specific register, which enumerates the speculative
+/// execution vulnerabilities the CPU is not affected by.
+pub const IA32_ARCH_CAPABILITIES: u32 = 0x10A;
+
+/// The **IA32_BIOS_SIGN_ID** model-specific register, which contains the revision of the
+/// microcode loaded on the CPU.
+///
+/// On Intel CPUs, the revision is in the upper 32 bits, and is only written to the register by
+/// the `cpuid` instruction. On AMD CPUs, it is in the lower 32 bits.
+pub const IA32_BIOS_SIGN_ID: u32 = 0x8B;
+
+/// The **IA32_MCG_STATUS** model-specific register.
+///
+/// It describes the state of the processor after a machine-check exception has been raised.
+pub const IA32_MCG_STATUS: u32 = 0x17A;
+
 /// The **IA32_APIC_BASE** model-specific register.
 ///
 /// This register contains the base physical address of the local APIC.
 pub const IA32_APIC_BASE: u32 = 0x1B;
 
-pub const LAPIC_EOI: usize = 0x0B0;
-pub const LAPIC_TIMER_INTERRUPT_VECTOR: usize = 0x320;
-pub const LAPIC_SPURIOUS_INTERRUPT_VECTOR: usize = 0x0F0;
-pub const LAPIC_INITIAL_COUNT: usize = 0x380;
-pub const LAPIC_CURRENT_COUNT: usize = 0x390;
-pub const LAPIC_DIVIDE_CONFIG: usize = 0x3E0;
+/// The **IA32_FS_BASE** model-specific register.
+///
+/// This register contains the base address of the `fs` segment.
+pub const IA32_FS_BASE: u32 = 0xC000_0100;
+
+/// The **IA32_GS_BASE** model-specific register.
+///
+/// This register contains the base address of the `gs` segment.
+pub const IA32_GS_BASE: u32 = 0xC000_0101;
+
+/// The **IA32_KERNEL_GS_BASE** model-specific register.
+///
+/// This register contains the base address that is exchanged with the one of the `gs` segment by
+/// the `swapgs` instruction.
+pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+/// The size of the registers of the local APIC.
+pub const LAPIC_REGISTERS_SIZE: usize = 0x400;
+
+pub const LAPIC_ID: Register<u32> = Register::new(0x020);
+pub const LAPIC_EOI: Register<u32> = Register::new(0x0B0);
+pub const LAPIC_TIMER_INTERRUPT_VECTOR: Register<u32> = Register::new(0x320);
+pub const LAPIC_SPURIOUS_INTERRUPT_VECTOR: Register<u32> = Register::new(0x0F0);
+pub const LAPIC_INITIAL_COUNT: Register<u32> = Register::new(0x380);
+pub const LAPIC_CURRENT_COUNT: Register<u32> = Register::new(0x390);
+pub const LAPIC_DIVIDE_CONFIG: Register<u32> = Register::new(0x3E0);
+pub const LAPIC_ICR_LOW: Register<u32> = Register::new(0x300);
+pub const LAPIC_ICR_HIGH: Register<u32> = Register::new(0x310);
 
 #[repr(C)]
 pub struct StackFrame {
@@ -150,11 +298,135 @@ pub struct StackFrame {
     pub ss: u64,
 }
 
+/// The complete state of the general purpose registers of an interrupted execution context.
+///
+/// This structure is built on the kernel stack by the system call and interrupt entry points. The
+/// last five fields have the same layout as the [`StackFrame`] pushed by the CPU when an interrupt
+/// occurs, which allows leaving the kernel with the **IRETQ** instruction.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// The state of the general purpose registers when an exception that pushes an error code
+/// occurs.
+///
+/// This structure is built on the stack by the exception entry points. It has the same layout as
+/// a [`TrapFrame`], except for the error code pushed by the CPU before the [`StackFrame`].
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl ExceptionFrame {
+    /// Returns the registers of the interrupted context, without the error code.
+    pub fn trap_frame(&self) -> TrapFrame {
+        TrapFrame {
+            rax: self.rax,
+            rbx: self.rbx,
+            rcx: self.rcx,
+            rdx: self.rdx,
+            rsi: self.rsi,
+            rdi: self.rdi,
+            rbp: self.rbp,
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rip: self.rip,
+            cs: self.cs,
+            rflags: self.rflags,
+            rsp: self.rsp,
+            ss: self.ss,
+        }
+    }
+
+    /// Replaces the registers of the interrupted context with the ones of `frame`.
+    ///
+    /// The error code is left untouched.
+    pub fn set_trap_frame(&mut self, frame: &TrapFrame) {
+        *self = Self {
+            error_code: self.error_code,
+            rax: frame.rax,
+            rbx: frame.rbx,
+            rcx: frame.rcx,
+            rdx: frame.rdx,
+            rsi: frame.rsi,
+            rdi: frame.rdi,
+            rbp: frame.rbp,
+            r8: frame.r8,
+            r9: frame.r9,
+            r10: frame.r10,
+            r11: frame.r11,
+            r12: frame.r12,
+            r13: frame.r13,
+            r14: frame.r14,
+            r15: frame.r15,
+            rip: frame.rip,
+            cs: frame.cs,
+            rflags: frame.rflags,
+            rsp: frame.rsp,
+            ss: frame.ss,
+        };
+    }
+}
+
 // LAPIC timer configurations.
 
 pub const LAPIC_TIMER_ONE_SHOT: u32 = 0 << 17;
 pub const LAPIC_TIMER_PERIODIC: u32 = 1 << 17;
 pub const LAPIC_TIMER_TSC_DEADLINE: u32 = 2 << 17;
+pub const LAPIC_TIMER_MASKED: u32 = 1 << 16;
+
+// LAPIC interrupt command flags.
+
+pub const LAPIC_ICR_SEND_PENDING: u32 = 1 << 12;
+pub const LAPIC_ICR_ASSERT: u32 = 1 << 14;
 
 // LAPIC divide configurations.
 
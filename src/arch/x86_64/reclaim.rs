@@ -0,0 +1,111 @@
+//! Background reclaim of physical memory.
+//!
+//! The memory tracker has two watermarks on its number of free pages, computed during boot by
+//! [`init`]. When an allocation makes the number of free pages fall below the low watermark, the
+//! tracker raises a flag that is checked on the next tick by [`tick`]:
+//!
+//! 1. The purgeable memory of the processes is reclaimed until the high watermark is reached
+//!    (see [`process::purge`]).
+//!
+//! 2. If the number of free pages is still below the low watermark, an
+//!    [`EventKind::LowMemory`] event is delivered to its subscriber, which may revoke memory
+//!    from the processes it manages. The event is not delivered again until the number of free
+//!    pages has climbed back above the high watermark.
+//!
+//! While the number of free pages remains below the high watermark after the event has been
+//! delivered, [`Announcements::RELEASE_MEMORY`] is also posted to the inbox of every process.
+//!
+//! This spreads the cost of reclaiming memory over time, instead of paying it all at once when
+//! an allocation fails. The latter is handled by the [`oom`](super::oom) module.
+
+use fabric_sys::event::EventKind;
+use fabric_sys::x86_64::public::Announcements;
+
+use crate::log;
+
+use super::event::{self, DeliverError};
+use super::mem::{MemoryTracker, MemoryTrackerTok};
+use super::process;
+
+/// The fraction of the free memory available at boot used as the low watermark.
+const LOW_WATERMARK_DIVISOR: usize = 64;
+
+/// The minimum value of the low watermark, in pages.
+const MIN_LOW_WATERMARK: usize = 64;
+
+/// The maximum value of the low watermark, in pages.
+const MAX_LOW_WATERMARK: usize = 16384;
+
+/// Whether the [`EventKind::LowMemory`] event has been delivered since the number of free pages
+/// was last above the high watermark.
+static mut NOTIFIED: bool = false;
+
+/// Computes the watermarks of the provided memory tracker from the number of pages that are
+/// free at boot.
+pub fn init(memory_tracker: &mut MemoryTracker) {
+    let total = memory_tracker.free_page_count();
+
+    let low = (total / LOW_WATERMARK_DIVISOR)
+        .clamp(MIN_LOW_WATERMARK, MAX_LOW_WATERMARK)
+        .min(total / 4);
+    let high = (low * 2).min(total / 2);
+
+    memory_tracker.set_watermarks(low, high);
+
+    log::trace!(
+        "Memory watermarks: low = {} pages, high = {} pages.",
+        low,
+        high
+    );
+}
+
+/// Reclaims memory in the background when the number of free pages has fallen below the low
+/// watermark.
+///
+/// This function is called on every tick by the scheduler.
+pub fn tick() {
+    // SAFETY:
+    //  The memory tracker is initialized before the scheduler starts.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    // SAFETY:
+    //  This flag is never accessed concurrently.
+    let notified = unsafe { &mut NOTIFIED };
+
+    if *notified && memory_tracker.free_page_count() >= memory_tracker.high_watermark() {
+        *notified = false;
+        process::announce_all(Announcements::empty(), Announcements::RELEASE_MEMORY);
+    }
+
+    if !memory_tracker.take_pressure() {
+        return;
+    }
+
+    let high = memory_tracker.high_watermark();
+
+    // SAFETY:
+    //  Timer interrupts are only handled when no reference into the process table is alive.
+    let reclaimed = unsafe { process::purge(&mut memory_tracker, high, None) };
+    if reclaimed != 0 {
+        log::trace!("Reclaimed {} pages of purgeable memory.", reclaimed);
+    }
+
+    let free = memory_tracker.free_page_count();
+    if free >= memory_tracker.low_watermark() || *notified {
+        return;
+    }
+
+    drop(memory_tracker);
+    *notified = true;
+    process::announce_all(Announcements::RELEASE_MEMORY, Announcements::empty());
+
+    match event::deliver(EventKind::LowMemory, free) {
+        Ok(()) | Err(DeliverError::NoSubscriber) => (),
+        Err(DeliverError::PortFull) => {
+            log::warn_ratelimited!(
+                "The low memory event could not be delivered: the port is full."
+            );
+        }
+    }
+}
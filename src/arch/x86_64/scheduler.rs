@@ -1 +1,402 @@
+//! A simple round-robin scheduler.
+//!
+//! # Context Switches
+//!
+//! The kernel only has a single kernel stack. Every time the CPU enters the kernel from
+//! userspace (through a system call or an interrupt), the entry point saves the registers of the
+//! running process into a [`TrapFrame`] at the top of that stack. When the kernel returns to
+//! userspace, the registers are restored from that same frame.
+//!
+//! Switching to another process is therefore simply a matter of saving the content of the trap
+//! frame into the [`Process`] structure of the previous process and overwriting it with the
+//! context of the next one.
+//!
+//! [`Process`]: super::process::Process
 
+use core::arch::asm;
+
+use fabric_sys::ProcessId;
+
+use crate::kassert::{kassert, kensure};
+
+use super::cpu::{cet, mitigations, pti};
+use super::instr;
+use super::percpu;
+use super::process::{self, ProcessState, MAX_PROCESSES};
+use super::raw::{self, TrapFrame};
+use super::timeout::{self, Target};
+use super::tlb;
+
+/// A queue of processes waiting to be scheduled.
+struct RunQueue {
+    /// The process IDs in the queue. Only the `len` elements starting at `head` (wrapping around)
+    /// are initialized.
+    slots: [Option<ProcessId>; MAX_PROCESSES],
+    /// The index of the first element of the queue.
+    head: usize,
+    /// The number of elements in the queue.
+    len: usize,
+}
+
+impl RunQueue {
+    /// Pushes a process at the back of the queue.
+    fn push(&mut self, id: ProcessId) {
+        // The queue cannot hold the same process twice, so it will never hold more than
+        // `MAX_PROCESSES` elements.
+        kensure!(self.len < MAX_PROCESSES, (), "the run queue is full");
+        kensure!(
+            !self.contains(id),
+            (),
+            "process {} is already in the run queue",
+            id,
+        );
+
+        self.slots[(self.head + self.len) % MAX_PROCESSES] = Some(id);
+        self.len += 1;
+    }
+
+    /// Pushes a process at the front of the queue.
+    fn push_front(&mut self, id: ProcessId) {
+        kensure!(self.len < MAX_PROCESSES, (), "the run queue is full");
+        kensure!(
+            !self.contains(id),
+            (),
+            "process {} is already in the run queue",
+            id,
+        );
+
+        self.head = (self.head + MAX_PROCESSES - 1) % MAX_PROCESSES;
+        self.slots[self.head] = Some(id);
+        self.len += 1;
+    }
+
+    /// Pops the process at the front of the queue.
+    fn pop(&mut self) -> Option<ProcessId> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let id = self.slots[self.head].take();
+        self.head = (self.head + 1) % MAX_PROCESSES;
+        self.len -= 1;
+        id
+    }
+
+    /// Returns whether the provided process is part of the queue.
+    fn contains(&self, id: ProcessId) -> bool {
+        (0..self.len).any(|i| self.slots[(self.head + i) % MAX_PROCESSES] == Some(id))
+    }
+
+    /// Removes the provided process from the queue, preserving the order of the other processes.
+    fn remove(&mut self, id: ProcessId) {
+        for _ in 0..self.len {
+            let Some(cur) = self.pop() else { break };
+            if cur != id {
+                self.push(cur);
+            }
+        }
+    }
+}
+
+/// The processes that are ready to run, not including the current one.
+static mut RUN_QUEUE: RunQueue = RunQueue {
+    slots: [None; MAX_PROCESSES],
+    head: 0,
+    len: 0,
+};
+
+/// Whether the current process should give the CPU to another process before returning to
+/// userspace.
+///
+/// This flag is checked by the system call entry point.
+pub static mut NEED_RESCHEDULE: bool = false;
+
+/// The number of timer ticks elapsed since the scheduler has started.
+static mut TICKS: u64 = 0;
+
+/// Returns the number of timer ticks elapsed since the scheduler has started.
+///
+/// The counter advances at [`TICK_FREQUENCY`](super::timer::TICK_FREQUENCY), even when the
+/// timer is programmed to interrupt the CPU less often (see [`timer::set_mode`]).
+///
+/// [`timer::set_mode`]: super::timer::set_mode
+#[inline(always)]
+pub fn ticks() -> u64 {
+    // SAFETY:
+    //  This counter is never modified concurrently.
+    unsafe { TICKS }
+}
+
+/// Advances the tick counter by `elapsed` ticks, expiring the timeouts whose deadline has been
+/// reached.
+///
+/// This function is called by the timer interrupt handler.
+pub fn tick(elapsed: u64) {
+    super::kernel_stack::check_canary();
+
+    // SAFETY:
+    //  This counter is never accessed concurrently.
+    let now = unsafe {
+        TICKS += elapsed;
+        TICKS
+    };
+
+    timeout::advance(now, |target| match target {
+        Target::Process(id) => wake_up(id),
+        Target::IrqLine(index) => super::irq::ack_overdue(index, now),
+    });
+
+    super::reclaim::tick();
+    super::oom::tick(now);
+    super::irq::tick(now);
+    super::kernel_stack::tick(now);
+}
+
+/// Wakes up the process with the provided ID, whose timeout has expired, if it is still waiting
+/// for a message, on a futex, for an input event, or for the end of its sleep.
+fn wake_up(id: ProcessId) {
+    // SAFETY:
+    //  Timer interrupts are only handled when no reference into the process table is alive.
+    let Some(process) = (unsafe { process::get(id) }) else {
+        return;
+    };
+
+    process.timeout = None;
+
+    // The tick counter only approximates the monotonic clock. A process is never woken up before
+    // the end of its sleep: it waits for one more tick instead.
+    if let ProcessState::Sleeping { until } = process.state {
+        if super::timer::uptime_ns() < until {
+            process.timeout = timeout::arm(Target::Process(id), ticks() + 1);
+            if process.timeout.is_some() {
+                return;
+            }
+        }
+    }
+
+    if matches!(
+        process.state,
+        ProcessState::Receiving { .. }
+            | ProcessState::Waiting { .. }
+            | ProcessState::ReadingInput
+            | ProcessState::Sleeping { .. }
+    ) {
+        process.state = ProcessState::Runnable;
+        enqueue(id);
+    }
+}
+
+/// Returns a tick at which the earliest timeout may expire, if any timeout is armed.
+///
+/// See [`timeout::next_expiry`].
+pub fn next_deadline() -> Option<u64> {
+    timeout::next_expiry()
+}
+
+/// Returns whether a process is waiting in the run queue for the CPU.
+#[inline]
+pub fn has_waiting() -> bool {
+    // SAFETY:
+    //  The run queue is never accessed concurrently.
+    unsafe { RUN_QUEUE.len != 0 }
+}
+
+/// Adds a process to the run queue.
+///
+/// The process must be in the [`ProcessState::Runnable`] state, and must not already be part of
+/// the run queue.
+pub fn enqueue(id: ProcessId) {
+    // SAFETY:
+    //  The run queue is never accessed concurrently.
+    unsafe { RUN_QUEUE.push(id) };
+
+    // Make sure the process gets a chance to preempt the current one.
+    super::timer::wake_before(ticks() + 1);
+}
+
+/// Removes a process from the run queue, if it was part of it.
+pub fn dequeue(id: ProcessId) {
+    // SAFETY:
+    //  The run queue is never accessed concurrently.
+    unsafe { RUN_QUEUE.remove(id) };
+}
+
+/// Moves a process of the run queue to its front, so that it is the next one to run.
+///
+/// # Returns
+///
+/// `false` is returned if the process is not part of the run queue.
+pub fn promote(id: ProcessId) -> bool {
+    // SAFETY:
+    //  The run queue is never accessed concurrently.
+    let queue = unsafe { &mut *core::ptr::addr_of_mut!(RUN_QUEUE) };
+
+    if !queue.contains(id) {
+        return false;
+    }
+
+    queue.remove(id);
+    queue.push_front(id);
+    true
+}
+
+/// Requests the current process to be descheduled before the kernel returns to userspace.
+#[inline(always)]
+pub fn request_reschedule() {
+    // SAFETY:
+    //  This flag is never accessed concurrently.
+    unsafe { NEED_RESCHEDULE = true };
+}
+
+/// Saves `frame` in the current process and replaces it with the context of the next process to
+/// run.
+///
+/// If no process is ready to run, the CPU goes idle until an interrupt makes one runnable.
+///
+/// # Safety
+///
+/// `frame` must be the frame that will be restored when returning to userspace. Interrupts must
+/// be disabled.
+pub unsafe extern "C" fn schedule(frame: &mut TrapFrame) {
+    unsafe { NEED_RESCHEDULE = false };
+
+    let current = percpu::current_process();
+
+    if let Some(id) = current {
+        // The current process may have been removed from the table while handling the system
+        // call.
+        if let Some(process) = unsafe { process::get(id) } {
+            process.context = *frame;
+
+            if process.state == ProcessState::Runnable {
+                enqueue(id);
+            }
+        }
+    }
+
+    let next = loop {
+        // SAFETY:
+        //  The run queue is never accessed concurrently.
+        if let Some(next) = unsafe { RUN_QUEUE.pop() } {
+            break next;
+        }
+
+        // No process is ready to run. Wait for an interrupt to make one runnable.
+        instr::sti_hlt_cli();
+    };
+
+    let process = unsafe { process::get(next).expect("scheduled a process that does not exist") };
+    *frame = process.context;
+
+    if current != Some(next) {
+        // Processes do not trust each other. The branches executed by the previous process must
+        // not influence the predictions made while running the next one.
+        mitigations::indirect_branch_barrier();
+
+        unsafe {
+            instr::set_cr3(process.address_space.get());
+            pti::set_current(process.address_space, process.user_table);
+            percpu::set_current_process(Some(next));
+        }
+        tlb::set_active(Some(process.address_space));
+    }
+}
+
+/// Starts running the first process of the run queue.
+///
+/// # Safety
+///
+/// This function must only be called once, when no process is running yet.
+pub unsafe fn start() -> ! {
+    instr::cli();
+
+    // The run queue and the current process are not shared between CPUs yet.
+    kassert!(
+        percpu::index() == 0,
+        "the scheduler must run on the bootstrap CPU"
+    );
+
+    // Like the ones built by the entry points, the frame lives at the top of the kernel stack. It
+    // remains mapped while returning to userspace when page table isolation is enabled. The
+    // memory that it overwrites belongs to callers that never run again.
+    let frame = (percpu::kernel_stack_top() - core::mem::size_of::<TrapFrame>()) as *mut TrapFrame;
+    unsafe {
+        frame.write(TrapFrame::default());
+        schedule(&mut *frame);
+    }
+
+    unsafe { cet::prepare() };
+
+    // Shadow stacks are enabled here, as this code never returns. The shadow stack token of the
+    // kernel is taken while entering userspace, and released by the `iretq` instruction.
+    unsafe {
+        asm!(
+            r#"
+            cmp byte ptr [rip + {shadow_stacks}], 0
+            je 2f
+            mov ecx, {s_cet}
+            mov eax, {sh_stk_en}
+            xor edx, edx
+            wrmsr
+            setssbsy
+        2:
+            mov rsp, rdi
+            jmp {restore}
+            "#,
+            in("rdi") frame,
+            shadow_stacks = sym cet::SHADOW_STACKS,
+            s_cet = const raw::IA32_S_CET,
+            sh_stk_en = const raw::SCet::SH_STK_EN.bits(),
+            restore = sym restore_trap_frame,
+            options(noreturn),
+        );
+    }
+}
+
+/// Restores the [`TrapFrame`] pointed to by the stack pointer and returns from the trap using the
+/// **IRETQ** instruction.
+///
+/// The `jmp` instruction must be used to enter this function. When the frame belongs to
+/// userspace, the `gs` base of userspace is restored right before returning (see
+/// [`percpu`](super::percpu)), and when page table isolation is enabled, the user table of the
+/// current process is loaded first.
+#[naked]
+#[link_section = ".entry.text"]
+pub unsafe extern "C" fn restore_trap_frame() -> ! {
+    unsafe {
+        asm!(
+            r#"
+            cmp byte ptr [rip + {pti}], 0
+            je 2f
+            test byte ptr [rsp + 16 * 8], 3
+            jz 2f
+            call {exit_to_user}
+            mov cr3, rax
+        2:
+            pop rax
+            pop rbx
+            pop rcx
+            pop rdx
+            pop rsi
+            pop rdi
+            pop rbp
+            pop r8
+            pop r9
+            pop r10
+            pop r11
+            pop r12
+            pop r13
+            pop r14
+            pop r15
+            test byte ptr [rsp + 8], 3
+            jz 3f
+            swapgs
+        3:
+            iretq
+            "#,
+            pti = sym pti::ENABLED,
+            exit_to_user = sym pti::exit_to_user,
+            options(noreturn),
+        );
+    }
+}
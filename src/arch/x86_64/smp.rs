@@ -0,0 +1,321 @@
+//! Bring-up of the application processors.
+//!
+//! The bootloader starts the application processors (APs) on its own, and parks each of them in
+//! a loop that waits for an address to jump to. They are brought up in two steps:
+//!
+//! 1. Before the bootstrap CPU leaves the address space of the bootloader, every AP is
+//!    [`register`]ed and sent to [`ap_entry`]. The AP switches to the kernel address space right
+//!    away, and waits for a stack to be published. It does not touch the memory of the
+//!    bootloader past that point, which can be reclaimed safely.
+//!
+//! 2. Once the memory tracker is initialized, [`start`] allocates a kernel stack and a double
+//!    fault stack for each AP, one at a time, and waits for it to come online. The AP loads its
+//!    own GDT and TSS, the IDT shared by all CPUs, initializes its local APIC, and installs its
+//!    [`percpu`](super::percpu) block.
+//!
+//! An AP that does not come online in time is given up on, and the next one is started. In the
+//! [deterministic boot mode](super::deterministic), the bootstrap CPU waits for each AP for as
+//! long as it takes, so that the APs always come online in the same order.
+//!
+//! The scheduler is not ready to run on more than one CPU. Until it is, the APs are parked with
+//! interrupts disabled once they are online. They run without CET or UMIP, and never enter
+//! userspace.
+//!
+//! # Synchronization
+//!
+//! The tables of this module are written by the bootstrap CPU before the AP they describe is
+//! released, and read by that AP afterwards. The two CPUs synchronize through [`AP_STACKS`] and
+//! [`ONLINE`].
+
+use core::arch::asm;
+use core::sync::atomic::Ordering::*;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+
+use crate::log;
+use crate::x86_64::config::KERNEL_STACK_SIZE;
+use crate::x86_64::cpu::gdt::{self, CpuTables};
+use crate::x86_64::cpu::paging::UpperHalfAddressSpaceTok;
+use crate::x86_64::cpu::{apic, idt};
+use crate::x86_64::mem::usage::{self, Category};
+use crate::x86_64::mem::{vmalloc, MemoryTrackerTok, OutOfMemory, PhysAddr};
+use crate::x86_64::raw::{self, PageFlags};
+use crate::x86_64::{deterministic, percpu, timer};
+
+/// The maximum number of CPUs supported by the kernel, including the bootstrap CPU.
+pub const MAX_CPUS: usize = 16;
+
+/// The time given to an application processor to come online, in microseconds.
+const ONLINE_TIMEOUT_US: u64 = 100_000;
+
+/// The CPU table, which holds the ID of the local APIC of each CPU.
+///
+/// Only the first [`CPU_COUNT`] entries are used. The index of a CPU in this table is also the
+/// index of its [`percpu`] block.
+static mut CPUS: [u32; MAX_CPUS] = [0; MAX_CPUS];
+
+/// The number of CPUs in the CPU table.
+static mut CPU_COUNT: usize = 0;
+
+/// The descriptor tables of the application processors, indexed like [`CPUS`].
+///
+/// The entry of the bootstrap CPU is unused: it uses the tables of [`gdt`].
+#[link_section = ".entry.data"]
+static mut AP_TABLES: [CpuTables; MAX_CPUS] = [CpuTables::EMPTY; MAX_CPUS];
+
+/// The value loaded in **CR3** by the application processors in [`ap_entry`].
+static mut AP_CR3: usize = 0;
+
+/// The top of the kernel stack of each application processor, or 0 while it has not been
+/// allocated.
+///
+/// An AP waits in [`ap_entry`] until its entry is set.
+static AP_STACKS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// The top of the double fault stack of each application processor.
+///
+/// An entry is written before the matching entry of [`AP_STACKS`].
+static mut AP_DOUBLE_FAULT_STACKS: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Whether each CPU has completed its initialization.
+static ONLINE: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Records the bootstrap CPU as the first entry of the CPU table, along with the address space
+/// that application processors switch to.
+///
+/// # Safety
+///
+/// This function must be called once, before [`register`], and `kernel_l4_table` must be the
+/// address space of the kernel.
+pub unsafe fn init(bsp_lapic_id: u32, kernel_l4_table: PhysAddr) {
+    // SAFETY:
+    //  No application processor has been released yet.
+    unsafe {
+        AP_CR3 = kernel_l4_table.get();
+        CPUS[0] = bsp_lapic_id;
+        CPU_COUNT = 1;
+    }
+
+    ONLINE[0].store(true, Release);
+}
+
+/// Registers an application processor in the CPU table.
+///
+/// # Returns
+///
+/// The index of the CPU, which must be passed to [`ap_entry`], or `None` if the CPU table is
+/// full.
+///
+/// # Safety
+///
+/// The CPU must not have been released yet.
+pub unsafe fn register(lapic_id: u32) -> Option<usize> {
+    // SAFETY:
+    //  No application processor reads the table before it is released.
+    unsafe {
+        let index = CPU_COUNT;
+        *CPUS.get_mut(index)? = lapic_id;
+        CPU_COUNT += 1;
+        Some(index)
+    }
+}
+
+/// The entry point of the application processors.
+///
+/// The bootloader jumps here with the address of its description of the CPU in `rdi`. Its
+/// `extra_argument` field holds the index returned by [`register`].
+///
+/// The function switches to the kernel address space, waits until a stack is published in
+/// [`AP_STACKS`], and calls [`ap_main`] with the index of the CPU.
+#[naked]
+pub extern "C" fn ap_entry() {
+    unsafe {
+        asm!(
+            r#"
+            mov rsi, [rdi + 24]
+
+            mov ecx, {efer}
+            rdmsr
+            or eax, {nxe}
+            wrmsr
+
+            mov rax, [rip + {cr3}]
+            mov cr3, rax
+
+        2:
+            mov rax, [{stacks} + 8 * rsi]
+            test rax, rax
+            jnz 3f
+            pause
+            jmp 2b
+        3:
+            mov rsp, rax
+            mov rdi, rsi
+            xor ebp, ebp
+            call {main}
+            ud2
+            "#,
+            efer = const raw::IA32_EFER,
+            nxe = const raw::Efer::NO_EXECUTE_ENABLE.bits(),
+            cr3 = sym AP_CR3,
+            stacks = sym AP_STACKS,
+            main = sym ap_main,
+            options(noreturn),
+        );
+    }
+}
+
+/// The entry point of the application processors that do not fit in the CPU table.
+///
+/// The CPU is halted forever, without touching memory.
+#[naked]
+pub extern "C" fn ap_park() {
+    unsafe {
+        asm!(
+            r#"
+            cli
+        2:
+            hlt
+            jmp 2b
+            "#,
+            options(noreturn),
+        );
+    }
+}
+
+/// Initializes the application processor with the provided index, and parks it.
+///
+/// Nothing is logged here: the logger may be used by the bootstrap CPU concurrently.
+extern "C" fn ap_main(index: usize) -> ! {
+    let kernel_stack_top = AP_STACKS[index].load(Acquire);
+
+    // SAFETY:
+    //  The entries of the CPU are only accessed by this CPU once it has been released.
+    unsafe {
+        gdt::init_ap(
+            &mut AP_TABLES[index],
+            kernel_stack_top,
+            AP_DOUBLE_FAULT_STACKS[index],
+        );
+        idt::load();
+        apic::init_local_apic();
+
+        percpu::init(index, CPUS[index], kernel_stack_top);
+    }
+
+    ONLINE[index].store(true, Release);
+
+    crate::x86_64::die();
+}
+
+/// Allocates the stacks of the registered application processors, and waits for each of them
+/// to come online.
+///
+/// # Safety
+///
+/// This function must be called once, after the memory tracker has been initialized, and the
+/// kernel must run in its own address space.
+pub unsafe fn start(upper_half: UpperHalfAddressSpaceTok) {
+    // SAFETY:
+    //  The table is no longer modified once the APs have been registered.
+    let count = unsafe { CPU_COUNT };
+
+    for index in 1..count {
+        // SAFETY:
+        //  The CPU has not been released yet.
+        let lapic_id = unsafe { CPUS[index] };
+
+        // SAFETY:
+        //  The memory tracker is initialized.
+        let stacks = unsafe { allocate_stacks(upper_half) };
+        let (kernel_stack_top, double_fault_stack_top) = match stacks {
+            Ok(stacks) => stacks,
+            Err(OutOfMemory) => {
+                log::warn!("Out of memory while starting CPU {}.", lapic_id);
+                break;
+            }
+        };
+
+        // SAFETY:
+        //  The AP does not read this entry before its kernel stack is published.
+        unsafe { AP_DOUBLE_FAULT_STACKS[index] = double_fault_stack_top };
+        AP_STACKS[index].store(kernel_stack_top, Release);
+
+        let deadline = match deterministic::enabled() {
+            true => u64::MAX,
+            false => timer::uptime_us() + ONLINE_TIMEOUT_US,
+        };
+        while !ONLINE[index].load(Acquire) && timer::uptime_us() < deadline {
+            core::hint::spin_loop();
+        }
+
+        if ONLINE[index].load(Acquire) {
+            log::trace!("CPU {} is online.", lapic_id);
+        } else {
+            log::warn!("CPU {} did not come online.", lapic_id);
+        }
+    }
+
+    log::info!("{} CPU(s) online.", online_count());
+}
+
+/// Allocates the kernel stack and the double fault stack of an application processor.
+///
+/// # Returns
+///
+/// The tops of the two stacks.
+///
+/// # Safety
+///
+/// The memory tracker must be initialized, and the kernel must run in its own address space.
+unsafe fn allocate_stacks(
+    upper_half: UpperHalfAddressSpaceTok,
+) -> Result<(usize, usize), OutOfMemory> {
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    let kernel_stack = unsafe {
+        vmalloc::allocate(
+            upper_half,
+            &mut memory_tracker,
+            KERNEL_STACK_SIZE,
+            PageFlags::WRITABLE | PageFlags::GLOBAL | PageFlags::NO_EXECUTE,
+        )?
+    };
+    usage::record(Category::Stacks, KERNEL_STACK_SIZE);
+
+    // The kernel stack is lost on failure, just like the virtual memory that backs it: this
+    // only happens during boot.
+    let double_fault_stack =
+        unsafe { gdt::allocate_ap_double_fault_stack(upper_half, &mut memory_tracker)? };
+
+    Ok((kernel_stack.get() + KERNEL_STACK_SIZE, double_fault_stack))
+}
+
+/// Returns the number of CPUs in the CPU table, including those that did not come online.
+pub fn count() -> usize {
+    // SAFETY:
+    //  The table is no longer modified once the APs have been registered.
+    unsafe { CPU_COUNT }
+}
+
+/// Returns the ID of the local APIC of the CPU with the provided index.
+pub fn lapic_id(index: usize) -> u32 {
+    // SAFETY:
+    //  The table is no longer modified once the APs have been registered.
+    unsafe { CPUS[index] }
+}
+
+/// Returns the index of the CPU whose local APIC has the provided ID.
+///
+/// Unlike [`percpu::index`], this can be used by interrupt handlers that run with the `gs` base
+/// of userspace.
+pub fn index_of(lapic_id: u32) -> Option<usize> {
+    // SAFETY:
+    //  The table is no longer modified once the APs have been registered.
+    unsafe { CPUS[..CPU_COUNT].iter().position(|&id| id == lapic_id) }
+}
+
+/// Returns the number of CPUs that are online, including the bootstrap CPU.
+pub fn online_count() -> usize {
+    ONLINE.iter().filter(|online| online.load(Acquire)).count()
+}
@@ -0,0 +1,203 @@
+//! Binary snapshots of the state of the kernel, enabled by the `snapshot` feature.
+//!
+//! When a process performs the `debug_snapshot` system call, the kernel serializes its process
+//! table, the memory map of every process and a summary of the memory tracker, and writes the
+//! result to the serial port. This allows analysing hangs offline when no debugger is attached.
+//!
+//! # Format
+//!
+//! The snapshot is written as lines of hexadecimal digits, between a
+//! `-----BEGIN FABRIC SNAPSHOT-----` line and a `-----END FABRIC SNAPSHOT-----` line, so that it
+//! survives being mixed with the regular output of the kernel log.
+//!
+//! Once decoded, the snapshot starts with the [`MAGIC`] bytes followed by the [`VERSION`] of the
+//! format as a `u32`. Then comes a list of records, each made of a kind (`u32`), the length of
+//! its payload in bytes (`u32`) and the payload itself. The list ends with a record of kind
+//! [`END`] with an empty payload. Integers are little-endian.
+//!
+//! - [`MEMORY`]: the number of ticks since boot, the number of free pages, the low and high
+//!   watermarks, and the memory used by the kernel for page tables, stacks and metadata (all
+//!   `u64`).
+//!
+//! - [`PROCESS`]: the ID of the process (`u64`), its state (`u32`, see [`state`]) and the data
+//!   attached to it (`u32`), the physical address of its L4 table (`u64`), its deadline
+//!   (`u64`, `u64::MAX` for none), its number of regions (`u64`), and for each region its start,
+//!   length and flags (`u64`), the kind of its backing (`u32`, see
+//!   [`MappingKind`](fabric_sys::x86_64::MappingKind)) and the data attached to it (`u32`).
+//!
+//! Readers must skip records of unknown kinds.
+
+use crate::x86_64::mem::{usage, MemoryTrackerTok};
+use crate::x86_64::process::{self, Backing, ProcessState};
+use crate::x86_64::scheduler;
+use crate::x86_64::serial::SerialTok;
+use crate::x86_64::timeout;
+
+/// The first bytes of a snapshot.
+pub const MAGIC: [u8; 8] = *b"FABRSNAP";
+
+/// The version of the format.
+pub const VERSION: u32 = 1;
+
+/// The kind of the record that ends a snapshot.
+pub const END: u32 = 0;
+/// The kind of the record that summarizes the state of the memory tracker.
+pub const MEMORY: u32 = 1;
+/// The kind of the record that describes a process.
+pub const PROCESS: u32 = 2;
+
+/// The size of the payload of a [`MEMORY`] record.
+const MEMORY_SIZE: usize = 7 * 8;
+/// The size of the payload of a [`PROCESS`] record, without its regions.
+const PROCESS_SIZE: usize = 8 + 4 + 4 + 8 + 8 + 8;
+/// The size of a region in the payload of a [`PROCESS`] record.
+const REGION_SIZE: usize = 8 + 8 + 8 + 4 + 4;
+
+/// The number of bytes written on each line.
+const BYTES_PER_LINE: usize = 32;
+
+/// The values of the state of a process in [`PROCESS`] records.
+pub mod state {
+    /// [`ProcessState::Runnable`](crate::x86_64::process::ProcessState::Runnable).
+    pub const RUNNABLE: u32 = 0;
+    /// [`ProcessState::Suspended`](crate::x86_64::process::ProcessState::Suspended).
+    pub const SUSPENDED: u32 = 1;
+    /// [`ProcessState::Waiting`](crate::x86_64::process::ProcessState::Waiting), with the low
+    /// bits of the key as data.
+    pub const WAITING: u32 = 2;
+    /// [`ProcessState::Receiving`](crate::x86_64::process::ProcessState::Receiving), with the
+    /// ID of the port as data.
+    pub const RECEIVING: u32 = 3;
+    /// [`ProcessState::ReadingInput`](crate::x86_64::process::ProcessState::ReadingInput).
+    pub const READING_INPUT: u32 = 4;
+    /// [`ProcessState::Sleeping`](crate::x86_64::process::ProcessState::Sleeping). The end of
+    /// the sleep is not recorded, but the deadline of the timeout of the process is.
+    pub const SLEEPING: u32 = 5;
+}
+
+/// Writes bytes to the serial port as lines of hexadecimal digits.
+struct HexWriter {
+    serial: SerialTok,
+    column: usize,
+}
+
+impl HexWriter {
+    /// Writes the provided bytes.
+    fn bytes(&mut self, bytes: &[u8]) {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+        for &byte in bytes {
+            self.serial.write_byte(DIGITS[(byte >> 4) as usize]);
+            self.serial.write_byte(DIGITS[(byte & 0xF) as usize]);
+
+            self.column += 1;
+            if self.column == BYTES_PER_LINE {
+                self.serial.write_byte(b'\n');
+                self.column = 0;
+            }
+        }
+    }
+
+    #[inline]
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    #[inline]
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Writes the header of a record.
+    #[inline]
+    fn record(&mut self, kind: u32, length: usize) {
+        self.u32(kind);
+        self.u32(length as u32);
+    }
+
+    /// Terminates the current line.
+    fn finish(self) {
+        if self.column != 0 {
+            self.serial.write_byte(b'\n');
+        }
+    }
+}
+
+/// Writes a snapshot of the state of the kernel to the serial port.
+///
+/// # Safety
+///
+/// No reference into the process table may be alive.
+pub unsafe fn write() {
+    // SAFETY:
+    //  The serial port is initialized at the very beginning of the boot process.
+    let serial = unsafe { SerialTok::unchecked() };
+
+    serial.write_bytes(b"\n-----BEGIN FABRIC SNAPSHOT-----\n");
+
+    let mut w = HexWriter { serial, column: 0 };
+    w.bytes(&MAGIC);
+    w.u32(VERSION);
+
+    // SAFETY:
+    //  The memory tracker is initialized before any process is started.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let memory_tracker = memory_tracker.lock();
+    let usage = usage::get();
+
+    w.record(MEMORY, MEMORY_SIZE);
+    w.u64(scheduler::ticks());
+    w.u64(memory_tracker.free_page_count() as u64);
+    w.u64(memory_tracker.low_watermark() as u64);
+    w.u64(memory_tracker.high_watermark() as u64);
+    w.u64(usage.page_tables);
+    w.u64(usage.stacks);
+    w.u64(usage.metadata);
+
+    drop(memory_tracker);
+
+    // SAFETY:
+    //  The caller makes sure that no reference into the process table is alive.
+    for (id, process) in unsafe { process::iter() } {
+        let regions = process.memory_map.regions();
+
+        let (state, data) = match process.state {
+            ProcessState::Runnable => (state::RUNNABLE, 0),
+            ProcessState::Suspended => (state::SUSPENDED, 0),
+            ProcessState::Waiting { key } => (state::WAITING, key as u32),
+            ProcessState::Receiving { port } => (state::RECEIVING, port.get() as u32),
+            ProcessState::ReadingInput => (state::READING_INPUT, 0),
+            ProcessState::Sleeping { .. } => (state::SLEEPING, 0),
+        };
+
+        let deadline = process.timeout.and_then(timeout::deadline);
+
+        w.record(PROCESS, PROCESS_SIZE + regions.len() * REGION_SIZE);
+        w.u64(id.get() as u64);
+        w.u32(state);
+        w.u32(data);
+        w.u64(process.address_space.get() as u64);
+        w.u64(deadline.unwrap_or(u64::MAX));
+        w.u64(regions.len() as u64);
+
+        for region in regions {
+            let data = match region.backing {
+                Backing::Framebuffer { index } | Backing::Device { index } => index as u32,
+                Backing::FramebufferRegion { id } => id as u32,
+                Backing::Purgeable { purged } => purged as u32,
+                Backing::Anonymous | Backing::Image | Backing::Stack | Backing::Inbox => 0,
+            };
+
+            w.u64(region.start as u64);
+            w.u64(region.length as u64);
+            w.u64(region.flags.bits() as u64);
+            w.u32(region.backing.kind() as u32);
+            w.u32(data);
+        }
+    }
+
+    w.record(END, 0);
+    w.finish();
+
+    serial.write_bytes(b"-----END FABRIC SNAPSHOT-----\n");
+}
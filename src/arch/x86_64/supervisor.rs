@@ -0,0 +1,194 @@
+//! Supervision of the init process.
+//!
+//! The init process is the only process started by the kernel itself. When it exits, nothing is
+//! left to drive the system, so the kernel applies a policy selected on the kernel command line
+//! with the `init.on_exit` option:
+//!
+//! - `halt` (the default): the machine is halted.
+//! - `reboot`: the machine is reset.
+//! - `restart`: a new init process is started from the image provided by the bootloader. After
+//!   [`MAX_RESTARTS`] restarts, the machine is halted.
+
+use fabric_sys::ProcessId;
+
+use crate::log;
+use crate::utility::Cmdline;
+
+use super::cpu::paging::UpperHalfAddressSpaceTok;
+use super::process::{self, ExitReason, Image};
+
+/// The maximum number of times the init process may be restarted before the machine is halted.
+pub const MAX_RESTARTS: usize = 8;
+
+/// What the kernel does when the init process exits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InitExitPolicy {
+    /// Halt the machine.
+    #[default]
+    Halt,
+    /// Reset the machine.
+    Reboot,
+    /// Start a new init process from the same image.
+    Restart,
+}
+
+impl InitExitPolicy {
+    /// Reads the policy from the `init.on_exit` option of the provided command line.
+    ///
+    /// Unknown values are reported and replaced by the default policy.
+    pub fn from_cmdline(cmdline: Cmdline) -> Self {
+        match cmdline.get(b"init.on_exit") {
+            None => Self::default(),
+            Some(b"halt") => Self::Halt,
+            Some(b"reboot") => Self::Reboot,
+            Some(b"restart") => Self::Restart,
+            Some(other) => {
+                log::warn!(
+                    "Unknown `init.on_exit` policy: `{}`.",
+                    core::str::from_utf8(other).unwrap_or("<invalid UTF-8>")
+                );
+                log::warn!("Falling back to `halt`.");
+                Self::default()
+            }
+        }
+    }
+
+    /// Converts this policy to its representation in the public data area.
+    pub fn to_public(self) -> fabric_sys::x86_64::public::InitExitPolicy {
+        use fabric_sys::x86_64::public::InitExitPolicy as Public;
+
+        match self {
+            Self::Halt => Public::Halt,
+            Self::Reboot => Public::Reboot,
+            Self::Restart => Public::Restart,
+        }
+    }
+}
+
+/// The state of the supervisor.
+struct Supervisor {
+    /// The policy applied when the init process exits.
+    policy: InitExitPolicy,
+    /// The image of the init process.
+    image: Image,
+    /// Proves that the upper half of the kernel address space has been initialized.
+    upper_half: UpperHalfAddressSpaceTok,
+    /// The ID of the running init process, if any.
+    init: Option<ProcessId>,
+    /// The number of times the init process has been restarted.
+    restarts: usize,
+}
+
+/// The global supervisor state, initialized by [`init`].
+static mut SUPERVISOR: Option<Supervisor> = None;
+
+/// Initializes the supervisor.
+///
+/// # Safety
+///
+/// This function must only be called once, before [`spawn_init`]. The image must remain valid
+/// until the machine is reset.
+pub unsafe fn init(policy: InitExitPolicy, image: Image, upper_half: UpperHalfAddressSpaceTok) {
+    log::trace!("Init exit policy: {:?}.", policy);
+
+    // SAFETY:
+    //  This function is called once, before the supervisor state is accessed anywhere else.
+    unsafe {
+        SUPERVISOR = Some(Supervisor {
+            policy,
+            image,
+            upper_half,
+            init: None,
+            restarts: 0,
+        });
+    }
+}
+
+/// Returns the global supervisor state.
+fn supervisor() -> &'static mut Supervisor {
+    // SAFETY:
+    //  The supervisor state is never accessed concurrently.
+    unsafe {
+        SUPERVISOR
+            .as_mut()
+            .expect("the supervisor is not initialized")
+    }
+}
+
+/// Starts a new init process and adds it to the run queue.
+///
+/// # Errors
+///
+/// Errors are logged, and `None` is returned.
+pub fn spawn_init() -> Option<ProcessId> {
+    let supervisor = supervisor();
+
+    // SAFETY:
+    //  The image remains valid until the machine is reset, as required by `init`.
+    let id = match unsafe { process::spawn(supervisor.image, supervisor.upper_half) } {
+        Ok(id) => id,
+        Err(err) => {
+            log::error!(
+                "Failed to start the `fabric_init` process: {}.",
+                err.message()
+            );
+            return None;
+        }
+    };
+
+    supervisor.init = Some(id);
+
+    Some(id)
+}
+
+/// Returns whether the provided process is the running init process.
+pub fn is_init(id: ProcessId) -> bool {
+    supervisor().init == Some(id)
+}
+
+/// Notifies the supervisor that a process has exited.
+///
+/// This function is called by [`process::terminate`] once the process has been removed from the
+/// process table.
+pub fn on_exit(id: ProcessId, reason: ExitReason) {
+    let supervisor = supervisor();
+
+    if supervisor.init != Some(id) {
+        return;
+    }
+
+    supervisor.init = None;
+    log::warn!("The `fabric_init` process has exited ({:?}).", reason);
+
+    match supervisor.policy {
+        InitExitPolicy::Halt => {
+            log::info!("Halting the machine.");
+            crate::die();
+        }
+        InitExitPolicy::Reboot => {
+            log::info!("Rebooting the machine.");
+            super::reboot();
+        }
+        InitExitPolicy::Restart => {
+            if supervisor.restarts >= MAX_RESTARTS {
+                log::error!(
+                    "The `fabric_init` process has been restarted {} times.",
+                    supervisor.restarts
+                );
+                log::error!("Halting the machine.");
+                crate::die();
+            }
+
+            supervisor.restarts += 1;
+            log::info!(
+                "Restarting the `fabric_init` process ({}/{}).",
+                supervisor.restarts,
+                MAX_RESTARTS
+            );
+
+            if spawn_init().is_none() {
+                crate::die();
+            }
+        }
+    }
+}
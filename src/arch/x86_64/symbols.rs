@@ -0,0 +1,317 @@
+//! The symbol table of the kernel, used to show function names in crash dumps.
+//!
+//! # Loading
+//!
+//! The bootloader provides the ELF file of the kernel, which includes its `.symtab` section
+//! unless the kernel has been stripped. During boot, the function symbols of that section are
+//! copied, along with their names, to memory allocated by the boot allocator and sorted by
+//! address. See [`build`].
+//!
+//! # Names
+//!
+//! Names are stored mangled. The legacy Rust mangling scheme is decoded when a symbol is
+//! displayed, and the hash that ends mangled names is omitted.
+
+use core::fmt;
+use core::mem::size_of;
+
+use crate::x86_64::mem::usage::{self, Category};
+use crate::x86_64::mem::{BootAllocator, PhysAddr};
+
+/// The type of the section that holds the symbol table.
+const SHT_SYMTAB: u32 = 2;
+/// The type of a symbol that refers to a function.
+const STT_FUNC: u8 = 2;
+
+/// A function symbol of the kernel.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Symbol {
+    /// The address of the first instruction of the function.
+    address: usize,
+    /// The size of the function, in bytes. This may be 0 when the size is unknown.
+    size: usize,
+    /// The offset of the name of the symbol, relative to the start of the table.
+    name: u32,
+    /// The length of the name of the symbol, in bytes.
+    name_len: u32,
+}
+
+/// A symbol table built by [`build`].
+///
+/// The table is made of `count` instances of [`Symbol`] sorted by address, followed by their
+/// names.
+#[derive(Clone, Copy)]
+pub struct SymbolTable {
+    /// The physical address of the table.
+    address: PhysAddr,
+    /// The number of symbols in the table.
+    count: usize,
+}
+
+/// The symbol table of the kernel, once it has been registered with [`init`].
+static mut SYMBOLS: Option<SymbolTable> = None;
+
+/// Reads a value of type `T` at `offset` in `data`.
+fn read<T: Copy>(data: &[u8], offset: usize) -> Option<T> {
+    let bytes = data.get(offset..offset.checked_add(size_of::<T>())?)?;
+
+    // SAFETY:
+    //  We checked that `data` is large enough, and `T` is only instantiated with integers.
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Returns the content of the section with the provided index, along with its `sh_link` field.
+fn section(elf: &[u8], index: usize) -> Option<(u32, &[u8], u32)> {
+    let shoff = read::<u64>(elf, 0x28)? as usize;
+    let shentsize = read::<u16>(elf, 0x3A)? as usize;
+    let header = shoff.checked_add(index.checked_mul(shentsize)?)?;
+
+    let kind = read::<u32>(elf, header + 0x04)?;
+    let offset = read::<u64>(elf, header + 0x18)? as usize;
+    let size = read::<u64>(elf, header + 0x20)? as usize;
+    let link = read::<u32>(elf, header + 0x28)?;
+
+    Some((kind, elf.get(offset..offset.checked_add(size)?)?, link))
+}
+
+/// Returns the name starting at `offset` in the provided string table.
+fn name_at(strtab: &[u8], offset: usize) -> &[u8] {
+    let name = strtab.get(offset..).unwrap_or_default();
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    &name[..len]
+}
+
+/// Builds the symbol table of the function symbols of the provided ELF file.
+///
+/// # Returns
+///
+/// `None` is returned if the file is not a valid ELF file, if it has no symbol table, or if the
+/// boot allocator cannot hold the table.
+pub fn build(
+    elf: &[u8],
+    boot_allocator: &mut BootAllocator,
+    current_hhdm: usize,
+) -> Option<SymbolTable> {
+    if elf.get(0..5)? != b"\x7FELF\x02" {
+        return None;
+    }
+
+    let shnum = read::<u16>(elf, 0x3C)? as usize;
+    let (symtab, strtab) = (0..shnum).find_map(|index| {
+        let (kind, symtab, link) = section(elf, index)?;
+        if kind != SHT_SYMTAB {
+            return None;
+        }
+        let (_, strtab, _) = section(elf, link as usize)?;
+        Some((symtab, strtab))
+    })?;
+
+    // Each entry of the symbol table is 24 bytes long.
+    let functions = || {
+        symtab.chunks_exact(24).filter_map(|entry| {
+            let name = name_at(strtab, read::<u32>(entry, 0)? as usize);
+            let info = read::<u8>(entry, 4)?;
+            let address = read::<u64>(entry, 8)? as usize;
+            let size = read::<u64>(entry, 16)? as usize;
+            (info & 0xF == STT_FUNC && address != 0 && !name.is_empty())
+                .then_some((address, size, name))
+        })
+    };
+
+    let count = functions().count();
+    let names_length: usize = functions().map(|(_, _, name)| name.len()).sum();
+    let names_offset = count * size_of::<Symbol>();
+
+    let address = boot_allocator
+        .allocate(names_offset + names_length, 8)
+        .ok()?;
+    usage::record(Category::Metadata, names_offset + names_length);
+
+    // SAFETY:
+    //  The boot allocator gave us enough memory for the symbols and their names.
+    let (symbols, names) = unsafe {
+        let base = (address.get() + current_hhdm) as *mut u8;
+        (
+            core::slice::from_raw_parts_mut(base as *mut Symbol, count),
+            core::slice::from_raw_parts_mut(base.add(names_offset), names_length),
+        )
+    };
+
+    let mut name_cursor = 0;
+    for (slot, (address, size, name)) in symbols.iter_mut().zip(functions()) {
+        names[name_cursor..name_cursor + name.len()].copy_from_slice(name);
+
+        *slot = Symbol {
+            address,
+            size,
+            name: (names_offset + name_cursor) as u32,
+            name_len: name.len() as u32,
+        };
+
+        name_cursor += name.len();
+    }
+
+    symbols.sort_unstable_by_key(|symbol| symbol.address);
+
+    Some(SymbolTable { address, count })
+}
+
+/// Registers the symbol table of the kernel.
+///
+/// # Safety
+///
+/// The table must have been built by [`build`], and the kernel address space must be loaded.
+pub unsafe fn init(table: SymbolTable) {
+    unsafe { SYMBOLS = Some(table) };
+}
+
+/// Returns the symbols of the registered table, and the address of the table.
+fn symbols() -> (&'static [Symbol], usize) {
+    // SAFETY:
+    //  The symbol table is only modified once, during boot.
+    let Some(table) = (unsafe { SYMBOLS }) else {
+        return (&[], 0);
+    };
+
+    let base = table.address.to_hhdm().get();
+
+    // SAFETY:
+    //  The table has been built by `build`. It lives in memory that is never reclaimed.
+    let symbols = unsafe { core::slice::from_raw_parts(base as *const Symbol, table.count) };
+
+    (symbols, base)
+}
+
+/// Returns the name of the function that contains the provided address, along with the offset
+/// of the address within it.
+pub fn resolve(address: usize) -> Option<(&'static str, usize)> {
+    let (symbols, base) = symbols();
+
+    let index = symbols.partition_point(|s| s.address <= address);
+    let symbol = symbols.get(index.checked_sub(1)?)?;
+    let offset = address - symbol.address;
+
+    if symbol.size != 0 && offset >= symbol.size {
+        return None;
+    }
+
+    // SAFETY:
+    //  The name has been copied by `build` right after the symbols.
+    let name = unsafe {
+        core::slice::from_raw_parts(
+            (base + symbol.name as usize) as *const u8,
+            symbol.name_len as usize,
+        )
+    };
+
+    Some((core::str::from_utf8(name).ok()?, offset))
+}
+
+/// Displays an address along with the function that contains it, if known.
+pub struct Symbolized(pub usize);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#018x}", self.0)?;
+
+        match resolve(self.0) {
+            Some((name, offset)) => write!(f, " {}+{:#x}", Demangled(name), offset),
+            None => f.write_str(" <unknown>"),
+        }
+    }
+}
+
+/// The escape sequences of the legacy Rust mangling scheme.
+const ESCAPES: &[(&str, &str)] = &[
+    ("$LT$", "<"),
+    ("$GT$", ">"),
+    ("$RF$", "&"),
+    ("$BP$", "*"),
+    ("$C$", ","),
+    ("$SP$", "@"),
+    ("$u20$", " "),
+    ("$u27$", "'"),
+    ("$u5b$", "["),
+    ("$u5d$", "]"),
+    ("$u7b$", "{"),
+    ("$u7d$", "}"),
+    ("$u7e$", "~"),
+    ("..", "::"),
+];
+
+/// Displays a symbol name, decoding the legacy Rust mangling scheme.
+///
+/// Names that are not mangled, or that cannot be decoded, are displayed as-is.
+struct Demangled<'a>(&'a str);
+
+impl Demangled<'_> {
+    /// Returns the path segments of the mangled name, if it uses the legacy Rust mangling
+    /// scheme.
+    fn segments(&self) -> Option<impl Iterator<Item = &str>> {
+        let mut rest = self.0.strip_prefix("_ZN")?.strip_suffix('E')?;
+
+        // Make sure that the whole name can be decoded before writing anything.
+        let mut check = rest;
+        while !check.is_empty() {
+            let digits = check.bytes().take_while(u8::is_ascii_digit).count();
+            let len: usize = check[..digits].parse().ok()?;
+            check = check.get(digits + len..)?;
+        }
+
+        Some(core::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None;
+            }
+
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let len: usize = rest[..digits].parse().ok()?;
+            let segment = &rest[digits..digits + len];
+            rest = &rest[digits + len..];
+
+            // The last segment is a hash that disambiguates the symbol.
+            let is_hash = rest.is_empty()
+                && segment.len() == 17
+                && segment.starts_with('h')
+                && segment[1..].bytes().all(|b| b.is_ascii_hexdigit());
+
+            (!is_hash).then_some(segment)
+        }))
+    }
+}
+
+impl fmt::Display for Demangled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(segments) = self.segments() else {
+            return f.write_str(self.0);
+        };
+
+        for (index, segment) in segments.enumerate() {
+            if index != 0 {
+                f.write_str("::")?;
+            }
+
+            // Segments that would start with an escape sequence are prefixed with `_`.
+            let mut rest = segment
+                .strip_prefix('_')
+                .filter(|s| s.starts_with('$'))
+                .unwrap_or(segment);
+
+            while !rest.is_empty() {
+                match ESCAPES.iter().find(|(from, _)| rest.starts_with(from)) {
+                    Some((from, to)) => {
+                        f.write_str(to)?;
+                        rest = &rest[from.len()..];
+                    }
+                    None => {
+                        let len = rest.chars().next().map_or(1, char::len_utf8);
+                        f.write_str(&rest[..len])?;
+                        rest = &rest[len..];
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+//! Validation of the arguments of system calls.
+//!
+//! System call handlers receive raw `usize` values. Instead of validating them by hand, handlers
+//! declare the type of each argument with the [`audit!`] macro, which checks the raw values and
+//! converts them into typed values, returning early from the handler when an argument is invalid.
+//!
+//! ```ignore
+//! audit! {
+//!     process: Pid = process_id;
+//!     info: UserMut<MappingInfo> = info;
+//!     message: UserSlice<MAX_DEBUG_LOG_LENGTH> = (data, length);
+//! }
+//! ```
+//!
+//! Each type implements [`Audit`], which decides which error is returned for an invalid value.
+//!
+//! # User Ranges
+//!
+//! Every address and every range received from userspace goes through [`validate_user_range`]
+//! before anything else, including the memory map of the caller, is looked at. Handlers that
+//! validate a range by hand must call it as well.
+//!
+//! # User Memory
+//!
+//! The kernel must never fault while it accesses the memory of the caller: a page fault raised
+//! by the kernel is a bug, and takes the whole system down. The buffers declared with
+//! [`UserMut`], [`UserSlice`] and [`UserSliceMut`] are populated when they are audited. Pages
+//! that are allocated on demand, such as the ones of stacks and of lazy or purged mappings, are
+//! allocated there, and the system call fails with [`SysResult::OUT_OF_MEMORY`] if they cannot
+//! be. The guard page of a stack is never accepted.
+
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+
+use bitflags::Flags;
+use fabric_sys::x86_64::MapFlags;
+use fabric_sys::{PortId, ProcessId, SysResult};
+
+use crate::utility::num;
+use crate::x86_64::cpu::paging;
+use crate::x86_64::ipc;
+use crate::x86_64::mem::{Page, VirtAddr, HHDM_OFFSET};
+use crate::x86_64::process::{self, Process};
+
+/// A type that can be built from the raw arguments of a system call.
+pub trait Audit: Sized {
+    /// The raw arguments the value is built from. This is usually `usize`, or `(usize, usize)` for
+    /// values that are made of an address and a length.
+    type Raw;
+
+    /// Validates the raw arguments and converts them.
+    ///
+    /// # Errors
+    ///
+    /// The error that the system call must return when the arguments are invalid.
+    fn audit(raw: Self::Raw) -> Result<Self, SysResult>;
+}
+
+/// Validates the arguments of a system call, returning early from the handler with the error
+/// picked by [`Audit::audit`] if any of them is invalid.
+///
+/// Arguments are validated in the order in which they are declared. Invalid arguments are
+/// expected to be rare, and the early returns are marked as cold.
+pub macro audit($($name:ident: $ty:ty = $raw:expr);* $(;)?) {
+    $(
+        let $name = match <$ty as $crate::x86_64::syscall::audit::Audit>::audit($raw) {
+            Ok(value) => value,
+            Err(error) => {
+                $crate::utility::cold_path();
+                return error;
+            }
+        };
+    )*
+}
+
+/// Returns whether the `address..address + length` range may refer to the memory of a process.
+///
+/// - `address` must be canonical with the active paging mode.
+/// - The end of the range must not overflow.
+/// - The range must end at or before [`paging::user_limit`]. It may not touch the guard region
+///   below the top of the address space of processes, nor the upper half.
+///
+/// Empty ranges follow the same rules, even though no memory is accessed through them: their
+/// address must be canonical and at or below the start of the guard region.
+pub fn validate_user_range(address: usize, length: usize) -> bool {
+    paging::is_canonical(address) && num::range_within(address, length, paging::user_limit())
+}
+
+/// Checks that the process that performed the current system call has mapped the
+/// `address..address + length` range of its address space with the provided flags, and that
+/// every page of the range is backed by memory.
+///
+/// Pages that are allocated on demand are populated.
+///
+/// # Errors
+///
+/// [`SysResult::INVALID_VALUE`] is returned if the range is not mapped, and
+/// [`SysResult::OUT_OF_MEMORY`] if one of its pages could not be populated.
+fn audit_caller_memory(address: usize, length: usize, flags: MapFlags) -> Result<(), SysResult> {
+    if !validate_user_range(address, length) {
+        return Err(SysResult::INVALID_VALUE);
+    }
+
+    let Some(caller) = (unsafe { process::get(process::current_id()) }) else {
+        return Err(SysResult::INVALID_VALUE);
+    };
+
+    if !caller.memory_map.is_mapped(address, length, flags) {
+        return Err(SysResult::INVALID_VALUE);
+    }
+
+    if length == 0 {
+        return Ok(());
+    }
+
+    for page in Page::range_of(VirtAddr::new(address), length) {
+        let present = unsafe { paging::translate_4kib(caller.l4_table(), HHDM_OFFSET, page) };
+
+        // SAFETY:
+        //  The address space of the caller is not accessed concurrently.
+        if present.is_none() && !unsafe { caller.populate(page.start().get()) } {
+            return Err(SysResult::OUT_OF_MEMORY);
+        }
+    }
+
+    Ok(())
+}
+
+/// A process identifier, where 0 designates the calling process.
+///
+/// Invalid identifiers are rejected with [`SysResult::INVALID_PROCESS_ID`].
+pub struct Pid {
+    pub id: ProcessId,
+    pub process: &'static mut Process,
+}
+
+impl Audit for Pid {
+    type Raw = usize;
+
+    fn audit(raw: usize) -> Result<Self, SysResult> {
+        match unsafe { process::resolve(raw) } {
+            Some((id, process)) => Ok(Self { id, process }),
+            None => Err(SysResult::INVALID_PROCESS_ID),
+        }
+    }
+}
+
+/// A value of type `F`, whose undefined bits must be cleared.
+pub struct Bits<F>(pub F);
+
+impl<F: Flags<Bits = usize>> Audit for Bits<F> {
+    type Raw = usize;
+
+    fn audit(raw: usize) -> Result<Self, SysResult> {
+        F::from_bits(raw).map(Self).ok_or(SysResult::INVALID_VALUE)
+    }
+}
+
+/// A range of the lower half, whose start and length are multiples of the page size.
+///
+/// The range may be empty.
+#[derive(Clone, Copy)]
+pub struct PageRange {
+    pub start: usize,
+    pub length: usize,
+}
+
+impl Audit for PageRange {
+    type Raw = (usize, usize);
+
+    fn audit((start, length): (usize, usize)) -> Result<Self, SysResult> {
+        if !num::is_page_aligned(start) || !num::is_page_aligned(length) {
+            return Err(SysResult::INVALID_VALUE);
+        }
+
+        if !validate_user_range(start, length) {
+            return Err(SysResult::INVALID_VALUE);
+        }
+
+        Ok(Self { start, length })
+    }
+}
+
+/// A pointer to a properly aligned instance of `T` that the caller may write to.
+pub struct UserMut<T> {
+    address: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> UserMut<T> {
+    /// Writes `value` to the memory of the caller.
+    pub fn write(&self, value: T) {
+        // SAFETY:
+        //  We checked that the memory is mapped, populated and writable in the address space of
+        //  the caller, which is the current address space.
+        unsafe { core::ptr::write(self.address as *mut T, value) };
+    }
+}
+
+impl<T> Audit for UserMut<T> {
+    type Raw = usize;
+
+    fn audit(address: usize) -> Result<Self, SysResult> {
+        if address % align_of::<T>() != 0 {
+            return Err(SysResult::INVALID_VALUE);
+        }
+
+        audit_caller_memory(address, size_of::<T>(), MapFlags::WRITABLE)?;
+
+        Ok(Self {
+            address,
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// An optional pointer, where 0 is the null pointer.
+impl<T> Audit for Option<UserMut<T>> {
+    type Raw = usize;
+
+    fn audit(address: usize) -> Result<Self, SysResult> {
+        match address {
+            0 => Ok(None),
+            _ => UserMut::audit(address).map(Some),
+        }
+    }
+}
+
+/// A buffer of at most `MAX` bytes that the caller may read from.
+pub struct UserSlice<const MAX: usize> {
+    address: usize,
+    length: usize,
+}
+
+impl<const MAX: usize> UserSlice<MAX> {
+    /// Returns the content of the buffer.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY:
+        //  We checked that the memory is mapped, populated and readable in the address space of
+        //  the caller, which is the current address space.
+        unsafe { core::slice::from_raw_parts(self.address as *const u8, self.length) }
+    }
+}
+
+impl<const MAX: usize> Audit for UserSlice<MAX> {
+    type Raw = (usize, usize);
+
+    fn audit((address, length): (usize, usize)) -> Result<Self, SysResult> {
+        if length > MAX {
+            return Err(SysResult::INVALID_VALUE);
+        }
+
+        audit_caller_memory(address, length, MapFlags::empty())?;
+
+        Ok(Self { address, length })
+    }
+}
+
+/// A buffer that the caller may write to.
+pub struct UserSliceMut {
+    address: usize,
+    length: usize,
+}
+
+impl UserSliceMut {
+    /// Returns the length of the buffer, in bytes.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Copies `data` to the start of the buffer.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `data` is larger than the buffer.
+    #[inline]
+    pub fn write(&self, data: &[u8]) {
+        self.write_at(0, data);
+    }
+
+    /// Copies `data` to the buffer, `offset` bytes after its start.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `data` does not fit in the buffer at that offset.
+    pub fn write_at(&self, offset: usize, data: &[u8]) {
+        assert!(offset <= self.length && data.len() <= self.length - offset);
+
+        // SAFETY:
+        //  We checked that the memory is mapped, populated and writable in the address space of
+        //  the caller, which is the current address space.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                (self.address + offset) as *mut u8,
+                data.len(),
+            );
+        }
+    }
+}
+
+impl Audit for UserSliceMut {
+    type Raw = (usize, usize);
+
+    fn audit((address, length): (usize, usize)) -> Result<Self, SysResult> {
+        audit_caller_memory(address, length, MapFlags::WRITABLE)?;
+
+        Ok(Self { address, length })
+    }
+}
+
+/// A port owned by the calling process.
+pub struct OwnedPort {
+    pub id: PortId,
+    pub port: &'static mut ipc::Port,
+}
+
+impl Audit for OwnedPort {
+    type Raw = usize;
+
+    fn audit(raw: usize) -> Result<Self, SysResult> {
+        let id = PortId::new(raw).ok_or(SysResult::INVALID_VALUE)?;
+        let port = unsafe { ipc::get(id) }.ok_or(SysResult::INVALID_VALUE)?;
+
+        if port.owner != process::current_id() {
+            return Err(SysResult::INVALID_VALUE);
+        }
+
+        Ok(Self { id, port })
+    }
+}
+
+/// An optional port owned by the calling process, where 0 designates no port.
+impl Audit for Option<OwnedPort> {
+    type Raw = usize;
+
+    fn audit(raw: usize) -> Result<Self, SysResult> {
+        match raw {
+            0 => Ok(None),
+            _ => OwnedPort::audit(raw).map(Some),
+        }
+    }
+}
@@ -1,14 +1,171 @@
 //! This module contains the implementation of all system calls!
 
+use core::mem::{align_of, size_of};
+use core::sync::atomic::AtomicU32;
 use core::sync::atomic::Ordering::*;
 
-use fabric_sys::x86_64::public::PublicData;
-use fabric_sys::x86_64::MapFlags;
-use fabric_sys::SysResult;
+use fabric_sys::event::EventKind;
+use fabric_sys::x86_64::public::{PciBarFlags, PublicData};
+use fabric_sys::x86_64::{
+    AddressSpaceStats, CacheOp, KernelInfo, KernelStats, KeyEvent, MapFlags, MappingInfo,
+    MessageInfo, QueryAddressSpaceFlags, RemapFlags, Syscall, TickMode, MAX_DEBUG_LOG_LENGTH,
+    MAX_LOG_FILTER_NAME_LENGTH, MAX_MESSAGE_SIZE,
+};
+use fabric_sys::{PortId, ProcessId, SysResult};
 
-use crate::x86_64::mem::{MemoryTrackerTok, HHDM_OFFSET, PAGE_SIZE, USER_TOP};
-use crate::x86_64::process::CURRENT_PROCESS;
-use crate::x86_64::raw::{self, PageFlags};
+use crate::log::{self, LevelFilter, SetFilterError};
+use crate::utility::num;
+use crate::x86_64::build_id::BUILD_ID;
+use crate::x86_64::config::VERSION;
+use crate::x86_64::cpu::paging::{self, UpperHalfAddressSpaceTok};
+use crate::x86_64::cpu::{cache, info};
+use crate::x86_64::display::{self, CreateError, Rect};
+use crate::x86_64::escrow;
+use crate::x86_64::event;
+use crate::x86_64::fault_injection;
+use crate::x86_64::ipc::{self, Message, PostError};
+use crate::x86_64::irq::{self, BindError, BudgetError};
+use crate::x86_64::keyboard;
+use crate::x86_64::mem::usage;
+use crate::x86_64::mem::{
+    Frame, MemoryTracker, MemoryTrackerTok, Page, PhysAddr, VirtAddr, HHDM_OFFSET, PAGE_SIZE,
+};
+use crate::x86_64::process::{
+    self, page_flags_of, Backing, ExitReason, LoadError, Process, ProcessState, Region,
+};
+use crate::x86_64::raw::PageFlags;
+use crate::x86_64::timeout::{self, Target};
+use crate::x86_64::timer::{self, SetModeError};
+use crate::x86_64::tlb::{self, Asid};
+use crate::x86_64::{oom, scheduler, supervisor};
+
+use super::audit::{
+    audit, validate_user_range, Bits, OwnedPort, PageRange, Pid, UserMut, UserSlice, UserSliceMut,
+};
+use super::diag;
+
+/// Removes the part of a region that could not be mapped from the memory map of a process.
+fn forget_unmapped_tail(process: &mut Process, virtual_address: usize, length: usize) {
+    // Truncating the end of a region never requires splitting it.
+    let _ = process.memory_map.remove(virtual_address, length);
+}
+
+/// Maps the physical pages currently backing the `from..from + length` range of the address space
+/// of `process` at `to`, without unmapping them from their original location.
+///
+/// # Errors
+///
+/// If the system runs out of memory while creating the page tables, the pages that were already
+/// mapped at `to` are unmapped and an error is returned.
+fn alias_pages(
+    process: &Process,
+    memory_tracker: &mut MemoryTracker,
+    from: usize,
+    to: usize,
+    length: usize,
+    page_flags: PageFlags,
+) -> Result<(), ()> {
+    let l4 = unsafe { process.l4_table() };
+
+    let sources = Page::range_of(VirtAddr::new(from), length);
+    let destinations = Page::range_of(VirtAddr::new(to), length);
+
+    for (src, dst) in sources.zip(destinations) {
+        let phys = unsafe { paging::translate_4kib(l4, HHDM_OFFSET, src) };
+
+        if let Some(phys) = phys {
+            let mapped = unsafe {
+                paging::map_4kib(
+                    l4,
+                    HHDM_OFFSET,
+                    &mut || memory_tracker.allocate_page_table(),
+                    dst,
+                    phys,
+                    page_flags,
+                )
+            };
+
+            if mapped.is_err() {
+                process.unmap_pages(None, to, dst.start().get() - to);
+                return Err(());
+            }
+
+            crate::x86_64::instr::invlpg(dst.start().get());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the key used to identify the word at `address` in the address space of `process` in
+/// the `wait` and `wake` system calls.
+///
+/// The key is the physical address of the word, making it possible for processes to synchronize
+/// through shared memory. If the page is part of a stack that has not been accessed yet, it is
+/// allocated.
+///
+/// `None` is returned if the memory could not be allocated.
+fn futex_key(process: &mut Process, address: usize) -> Option<usize> {
+    let page = Page::containing(VirtAddr::new(address));
+
+    let phys = match unsafe { paging::translate_4kib(process.l4_table(), HHDM_OFFSET, page) } {
+        Some(phys) => phys,
+        None if unsafe { process.populate(address) } => unsafe {
+            paging::translate_4kib(process.l4_table(), HHDM_OFFSET, page)?
+        },
+        None => return None,
+    };
+
+    Some(phys.start().get() + num::page_offset(address))
+}
+
+/// Puts the current process in the provided waiting state until it is woken up, or until
+/// `timeout` ticks have elapsed.
+///
+/// `usize::MAX` waits forever. `None` is returned if the timeout could not be armed.
+fn block(process: &mut Process, state: ProcessState, timeout: usize) -> Option<()> {
+    process.cancel_timeout();
+
+    if timeout != usize::MAX {
+        let deadline = scheduler::ticks().saturating_add(timeout as u64);
+        process.timeout = Some(timeout::arm(
+            Target::Process(process::current_id()),
+            deadline,
+        )?);
+    }
+
+    // The process is not part of the run queue while it is running, so it won't be scheduled
+    // again until someone wakes it up.
+    process.state = state;
+    scheduler::request_reschedule();
+
+    Some(())
+}
+
+/// Returns whether the process `caller` may terminate `target`, another process.
+///
+/// - The init process is never terminated by another process.
+/// - The init process may terminate any other process, and so may the memory manager, which is
+///   subscribed to the out-of-memory event, to reclaim memory.
+/// - Other processes may terminate the processes they started, directly or not.
+fn may_terminate(caller: ProcessId, target: ProcessId) -> bool {
+    if supervisor::is_init(target) {
+        return false;
+    }
+
+    supervisor::is_init(caller)
+        || event::subscriber_owner(EventKind::OutOfMemory) == Some(caller)
+        || unsafe { process::is_ancestor(caller, target) }
+}
+
+/// Returns whether the process `caller` may act on the memory, the devices or the execution of
+/// `target`.
+///
+/// A process may always act on itself. Other processes are subject to the same rules as when
+/// they are terminated (see [`may_terminate`]).
+fn may_control(caller: ProcessId, target: ProcessId) -> bool {
+    caller == target || may_terminate(caller, target)
+}
 
 /// Handles the `terminate` system call.
 pub extern "C" fn terminate(
@@ -19,14 +176,31 @@ pub extern "C" fn terminate(
     _: usize,
     _: usize,
 ) -> SysResult {
-    todo!("terminate({})", process_id);
+    let Some((process_id, _)) = (unsafe { process::resolve(process_id) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    let current = process::current_id();
+    if process_id != current && !may_terminate(current, process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    let reason = if process_id == current {
+        ExitReason::Terminated
+    } else {
+        ExitReason::Killed
+    };
+
+    process::terminate(process_id, reason);
+
+    SysResult::success(0)
 }
 
 /// Handles the `map_memory` system call.
 pub extern "C" fn map_memory(
     process_id: usize,
-    mut virtual_address: usize,
-    mut length: usize,
+    virtual_address: usize,
+    length: usize,
     flags: usize,
     _: usize,
     _: usize,
@@ -34,33 +208,74 @@ pub extern "C" fn map_memory(
     //
     // Validate the arguments.
     //
-    let process = if process_id != 0 {
-        return SysResult::INVALID_PROCESS_ID;
-    } else {
-        unsafe { &mut CURRENT_PROCESS }
-    };
+    audit! {
+        process: Pid = process_id;
+        range: PageRange = (virtual_address, length);
+        flags: Bits<MapFlags> = flags;
+    }
 
-    if virtual_address % PAGE_SIZE != 0 || length % PAGE_SIZE != 0 {
-        return SysResult::INVALID_VALUE;
+    let Pid {
+        id: process_id,
+        process,
+    } = process;
+    let PageRange {
+        start: mut virtual_address,
+        length,
+    } = range;
+    let Bits(flags) = flags;
+
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
     }
 
-    if virtual_address.saturating_add(length) > USER_TOP {
+    let is_stack = flags.contains(MapFlags::STACK);
+
+    if is_stack && length < 2 * PAGE_SIZE {
         return SysResult::INVALID_VALUE;
     }
 
-    let Some(flags) = MapFlags::from_bits(flags) else { return SysResult::INVALID_VALUE };
-
     //
-    // Convert the flags into the format used by the CPU.
+    // Pick an address if the caller let the kernel choose.
     //
-    let mut page_flags = raw::PageFlags::USER;
+    if virtual_address == 0 && length != 0 {
+        match process
+            .memory_map
+            .find_free(length, process.map_base, paging::user_limit())
+        {
+            Some(addr) => virtual_address = addr,
+            None => return SysResult::OUT_OF_MEMORY,
+        }
+    }
+
+    let start = virtual_address;
 
-    if flags.contains(MapFlags::WRITABLE) {
-        page_flags.insert(raw::PageFlags::WRITABLE);
+    if !process.memory_map.is_free(start, length) {
+        return SysResult::CONFLICT;
+    }
+
+    if length == 0 {
+        return SysResult::success(start);
     }
 
-    if !flags.contains(MapFlags::EXECUTABLE) {
-        page_flags.insert(raw::PageFlags::NO_EXECUTE);
+    let region = Region {
+        start,
+        length,
+        flags,
+        backing: if is_stack {
+            Backing::Stack
+        } else {
+            Backing::Anonymous
+        },
+    };
+
+    // Stacks and lazy mappings are only backed by memory once they are accessed. See
+    // `Process::populate`.
+    if is_stack || flags.contains(MapFlags::LAZY) {
+        if process.memory_map.insert(region).is_err() {
+            return SysResult::OUT_OF_MEMORY;
+        }
+
+        return SysResult::success(start);
     }
 
     // SAFETY:
@@ -69,40 +284,38 @@ pub extern "C" fn map_memory(
     let mut memory_tracker = memory_tracker.lock();
 
     //
-    // Allocate memory until we have mapped the entire requested region.
+    // Reclaim purgeable memory if the request cannot be satisfied otherwise. The pages needed
+    // by the page tables are not accounted for.
     //
-    while length != 0 {
-        let Ok(phys) =  memory_tracker.allocate() else { return SysResult::OUT_OF_MEMORY };
-
-        // Map the page.
-        if unsafe {
-            crate::x86_64::cpu::paging::map_4kib(
-                &mut *((process.address_space + HHDM_OFFSET) as *mut _),
-                HHDM_OFFSET,
-                &mut || memory_tracker.allocate(),
-                virtual_address,
-                phys,
-                page_flags,
-            )
-            .is_err()
-        } {
-            return SysResult::OUT_OF_MEMORY;
-        }
-
-        crate::x86_64::instr::invlpg(virtual_address);
+    // SAFETY:
+    //  The only process referenced here is the target of the system call, which is left
+    //  untouched.
+    if !unsafe {
+        oom::reclaim(
+            &mut memory_tracker,
+            num::page_count(length),
+            Some(process_id),
+        )
+    } {
+        return SysResult::OUT_OF_MEMORY;
+    }
 
-        length -= PAGE_SIZE;
-        virtual_address += PAGE_SIZE;
+    //
+    // Record the region in the memory map of the process, and map the entire region. Nothing is
+    // left of it on failure.
+    //
+    if process.map_zeroed(&mut memory_tracker, region).is_err() {
+        return SysResult::OUT_OF_MEMORY;
     }
 
-    SysResult::success(0)
+    SysResult::success(start)
 }
 
 /// Handles the `unmap_memory` system call.
 pub extern "C" fn unmap_memory(
     process_id: usize,
-    mut virtual_address: usize,
-    mut length: usize,
+    virtual_address: usize,
+    length: usize,
     _: usize,
     _: usize,
     _: usize,
@@ -110,49 +323,35 @@ pub extern "C" fn unmap_memory(
     //
     // Validate the arguments.
     //
-    let process = if process_id != 0 {
-        return SysResult::INVALID_PROCESS_ID;
-    } else {
-        unsafe { &mut CURRENT_PROCESS }
-    };
-
-    if virtual_address % PAGE_SIZE != 0 || length % PAGE_SIZE != 0 {
-        return SysResult::INVALID_VALUE;
+    audit! {
+        process: Pid = process_id;
+        range: PageRange = (virtual_address, length);
     }
 
-    if virtual_address.saturating_add(length) > USER_TOP {
-        return SysResult::INVALID_VALUE;
-    }
+    let Pid {
+        id: process_id,
+        process,
+    } = process;
+    let PageRange {
+        start: virtual_address,
+        length,
+    } = range;
 
-    //
-    // Mark the memory as free.
-    //
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
 
     // SAFETY:
     //  The memory tracker is known to be initialized before system calls are enabled.
     let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
 
-    while length != 0 {
-        unsafe {
-            // The documentation (that we wrote) indicates that attempting to unmap a page that's
-            // not currently mapped has unspecified behavior. In our case, we'll just ignore the
-            // error and only mark the page as free if it was actually previously mapped.
-            let was_used = crate::x86_64::cpu::paging::unmap_4kib(
-                &mut *((process.address_space + HHDM_OFFSET) as *mut _),
-                HHDM_OFFSET,
-                virtual_address,
-            )
-            .is_ok();
-
-            if was_used {
-                memory_tracker.lock().mark_as_unused(virtual_address);
-            }
-        }
-
-        crate::x86_64::instr::invlpg(virtual_address);
-
-        virtual_address += PAGE_SIZE;
-        length -= PAGE_SIZE;
+    // Only the pages that are actually mapped are unmapped, and only the memory that the process
+    // owns is marked as free.
+    if process
+        .unmap_range(&mut memory_tracker.lock(), virtual_address, length)
+        .is_err()
+    {
+        return SysResult::OUT_OF_MEMORY;
     }
 
     SysResult::success(0)
@@ -161,58 +360,83 @@ pub extern "C" fn unmap_memory(
 pub extern "C" fn acquire_framebuffer(
     process_id: usize,
     index: usize,
-    mut at: usize,
+    at: usize,
     _: usize,
     _: usize,
     _: usize,
 ) -> SysResult {
-    let process = if process_id != 0 {
+    let Some((process_id, process)) = (unsafe { process::resolve(process_id) }) else {
         return SysResult::INVALID_PROCESS_ID;
-    } else {
-        unsafe { &mut CURRENT_PROCESS }
     };
 
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
     let public = unsafe { &*(crate::x86_64::public_data_address() as *mut PublicData) };
 
     let Some(framebuffer) = public.framebuffers().get(index) else {
         return SysResult::INVALID_VALUE;
     };
 
+    let Some(size) = num::page_align_up(framebuffer.size_in_bytes() as usize) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if !num::is_page_aligned(at) || !validate_user_range(at, size) {
+        return SysResult::INVALID_VALUE;
+    }
+
+    if !process.memory_map.is_free(at, size) {
+        return SysResult::CONFLICT;
+    }
+
     if framebuffer
         .owned_by
-        .compare_exchange(0, process_id, AcqRel, Relaxed)
+        .compare_exchange(0, process_id.get() as u64, AcqRel, Relaxed)
         .is_err()
     {
         return SysResult::CONFLICT;
     }
 
+    let region = Region {
+        start: at,
+        length: size,
+        flags: MapFlags::WRITABLE,
+        backing: Backing::Framebuffer { index },
+    };
+
+    if process.memory_map.insert(region).is_err() {
+        framebuffer.owned_by.store(0, Release);
+        return SysResult::OUT_OF_MEMORY;
+    }
+
     let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
     let mut memory_tracker = memory_tracker.lock();
 
     // Map the framebuffer into the process's address space at the address they requested.
-    let mut size = framebuffer.size_in_bytes();
-    let mut addr = framebuffer.physical_address;
-    while size != 0 {
+    let pages = Page::range_of(VirtAddr::new(at), size);
+    let frames = Frame::range_of(PhysAddr::new(framebuffer.physical_address as usize), size);
+
+    for (page, frame) in pages.zip(frames) {
         // Map the page.
         if unsafe {
             crate::x86_64::cpu::paging::map_4kib(
-                &mut *((process.address_space + HHDM_OFFSET) as *mut _),
+                process.l4_table(),
                 HHDM_OFFSET,
-                &mut || memory_tracker.allocate(),
-                at,
-                addr,
+                &mut || memory_tracker.allocate_page_table(),
+                page,
+                frame,
                 PageFlags::USER | PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
             )
             .is_err()
         } {
+            let mapped = page.start().get() - at;
+            forget_unmapped_tail(process, page.start().get(), size - mapped);
             return SysResult::OUT_OF_MEMORY;
         }
 
-        crate::x86_64::instr::invlpg(at);
-
-        size -= PAGE_SIZE;
-        at += PAGE_SIZE;
-        addr += PAGE_SIZE;
+        crate::x86_64::instr::invlpg(page.start().get());
     }
 
     SysResult::success(0)
@@ -226,23 +450,57 @@ pub extern "C" fn release_framebuffer(
     _: usize,
     _: usize,
 ) -> SysResult {
-    let _process = if process_id != 0 {
+    let Some((process_id, process)) = (unsafe { process::resolve(process_id) }) else {
         return SysResult::INVALID_PROCESS_ID;
-    } else {
-        unsafe { &mut CURRENT_PROCESS }
     };
 
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
     let public = unsafe { &*(crate::x86_64::public_data_address() as *mut PublicData) };
 
     let Some(framebuffer) = public.framebuffers().get(index) else {
         return SysResult::INVALID_VALUE;
     };
 
-    // TODO: the memory should be unmapped.
+    if framebuffer.owned_by.load(Acquire) != process_id.get() as u64 {
+        return SysResult::CONFLICT;
+    }
+
+    // The regions delegated to other processes must not outlive the ownership of the
+    // framebuffer.
+    display::release_framebuffer(index);
+
+    // Unmap the regions of the process that refer to the framebuffer. The memory itself belongs
+    // to the framebuffer and must not be freed.
+    while let Some(region) = process
+        .memory_map
+        .regions()
+        .iter()
+        .find(|r| r.backing == Backing::Framebuffer { index })
+        .copied()
+    {
+        // Removing a whole region never requires splitting another region.
+        let _ = process.memory_map.remove(region.start, region.length);
+
+        for page in Page::range_of(VirtAddr::new(region.start), region.length) {
+            unsafe {
+                let _ =
+                    crate::x86_64::cpu::paging::unmap_4kib(process.l4_table(), HHDM_OFFSET, page);
+            }
+        }
+
+        tlb::flush_range(
+            Asid::User(process.address_space),
+            region.start,
+            region.length,
+        );
+    }
 
     if framebuffer
         .owned_by
-        .compare_exchange(process_id, 0, AcqRel, Relaxed)
+        .compare_exchange(process_id.get() as u64, 0, AcqRel, Relaxed)
         .is_err()
     {
         return SysResult::CONFLICT;
@@ -250,3 +508,1594 @@ pub extern "C" fn release_framebuffer(
 
     SysResult::success(0)
 }
+
+/// Handles the `suspend` system call.
+pub extern "C" fn suspend(
+    process_id: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        process: Pid = process_id;
+    }
+
+    let Pid {
+        id: process_id,
+        process,
+    } = process;
+
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    if process.state == ProcessState::Suspended {
+        return SysResult::success(0);
+    }
+
+    process.state = ProcessState::Suspended;
+
+    if process_id == process::current_id() {
+        // The current process is not part of the run queue. It needs to give the CPU to
+        // another process before returning to userspace.
+        scheduler::request_reschedule();
+    } else {
+        scheduler::dequeue(process_id);
+    }
+
+    SysResult::success(0)
+}
+
+/// Handles the `resume` system call.
+pub extern "C" fn resume(
+    process_id: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        process: Pid = process_id;
+    }
+
+    let Pid {
+        id: process_id,
+        process,
+    } = process;
+
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    if process.state != ProcessState::Suspended {
+        return SysResult::success(0);
+    }
+
+    process.state = ProcessState::Runnable;
+    scheduler::enqueue(process_id);
+
+    SysResult::success(0)
+}
+
+/// Handles the `query_mapping` system call.
+pub extern "C" fn query_mapping(
+    process_id: usize,
+    address: usize,
+    info: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        info: UserMut<MappingInfo> = info;
+        process: Pid = process_id;
+    }
+
+    let Pid {
+        id: process_id,
+        process,
+    } = process;
+
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    let Some(region) = process.memory_map.find(address) else {
+        return SysResult::success(0);
+    };
+
+    info.write(MappingInfo {
+        start: region.start,
+        length: region.length,
+        flags: region.flags,
+        kind: region.backing.kind(),
+    });
+
+    SysResult::success(1)
+}
+
+/// Handles the `remap_memory` system call.
+pub extern "C" fn remap_memory(
+    old_address: usize,
+    old_length: usize,
+    new_length: usize,
+    flags: usize,
+    new_address_hint: usize,
+    _: usize,
+) -> SysResult {
+    //
+    // Validate the arguments.
+    //
+    if !num::is_page_aligned(old_address)
+        || !num::is_page_aligned(old_length)
+        || !num::is_page_aligned(new_length)
+    {
+        return SysResult::INVALID_VALUE;
+    }
+
+    if old_length == 0 || new_length == 0 {
+        return SysResult::INVALID_VALUE;
+    }
+
+    audit! {
+        flags: Bits<RemapFlags> = flags;
+    }
+
+    let Bits(flags) = flags;
+
+    let Some(process) = (unsafe { process::get(process::current_id()) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    // The old range must be part of a single region of anonymous memory. Other kinds of memory
+    // are owned by someone else and cannot be resized.
+    let Some(region) = process.memory_map.find(old_address).copied() else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if region.backing != Backing::Anonymous || old_address.saturating_add(old_length) > region.end()
+    {
+        return SysResult::INVALID_VALUE;
+    }
+
+    let page_flags = page_flags_of(region.flags);
+
+    // The pages of lazy mappings are allocated when they are first accessed, including the ones
+    // that the mapping grows by.
+    let is_lazy = region.flags.contains(MapFlags::LAZY);
+
+    // SAFETY:
+    //  The memory tracker is initialized before system calls are enabled.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    //
+    // Shrink the mapping.
+    //
+    if new_length <= old_length {
+        let tail = old_address + new_length;
+        let tail_length = old_length - new_length;
+
+        if tail_length != 0 {
+            if process.memory_map.remove(tail, tail_length).is_err() {
+                return SysResult::OUT_OF_MEMORY;
+            }
+
+            process.unmap_pages(Some(&mut memory_tracker), tail, tail_length);
+        }
+
+        return SysResult::success(old_address);
+    }
+
+    //
+    // Attempt to grow the mapping in place.
+    //
+    let grow_start = old_address + old_length;
+    let grow_length = new_length - old_length;
+
+    if validate_user_range(grow_start, grow_length)
+        && process.memory_map.is_free(grow_start, grow_length)
+    {
+        let grown = Region {
+            start: grow_start,
+            length: grow_length,
+            ..region
+        };
+
+        if process.memory_map.insert(grown).is_err() {
+            return SysResult::OUT_OF_MEMORY;
+        }
+
+        if !is_lazy
+            && process
+                .map_new_pages(&mut memory_tracker, grow_start, grow_length, page_flags)
+                .is_err()
+        {
+            forget_unmapped_tail(process, grow_start, grow_length);
+            return SysResult::OUT_OF_MEMORY;
+        }
+
+        return SysResult::success(old_address);
+    }
+
+    //
+    // Move the mapping to the provided address.
+    //
+    if !flags.contains(RemapFlags::MAY_MOVE) {
+        return SysResult::CONFLICT;
+    }
+
+    let new_address = if new_address_hint == 0 {
+        match process
+            .memory_map
+            .find_free(new_length, process.map_base, paging::user_limit())
+        {
+            Some(addr) => addr,
+            None => return SysResult::OUT_OF_MEMORY,
+        }
+    } else {
+        new_address_hint
+    };
+
+    if !num::is_page_aligned(new_address) || !validate_user_range(new_address, new_length) {
+        return SysResult::INVALID_VALUE;
+    }
+
+    if !process.memory_map.is_free(new_address, new_length) {
+        return SysResult::CONFLICT;
+    }
+
+    // Inserting the new region takes at most one slot, and removing the old range may split its
+    // region in two. Making sure that two slots are available ensures that updating the memory
+    // map cannot fail once the page tables have been modified.
+    if process.memory_map.free_slots() < 2 {
+        return SysResult::OUT_OF_MEMORY;
+    }
+
+    if alias_pages(
+        process,
+        &mut memory_tracker,
+        old_address,
+        new_address,
+        old_length,
+        page_flags,
+    )
+    .is_err()
+    {
+        return SysResult::OUT_OF_MEMORY;
+    }
+
+    if !is_lazy
+        && process
+            .map_new_pages(
+                &mut memory_tracker,
+                new_address + old_length,
+                grow_length,
+                page_flags,
+            )
+            .is_err()
+    {
+        process.unmap_pages(None, new_address, old_length);
+        return SysResult::OUT_OF_MEMORY;
+    }
+
+    // The physical pages are now mapped at their new location. They must not be freed.
+    process.unmap_pages(None, old_address, old_length);
+
+    let _ = process.memory_map.remove(old_address, old_length);
+    let _ = process.memory_map.insert(Region {
+        start: new_address,
+        length: new_length,
+        ..region
+    });
+
+    SysResult::success(new_address)
+}
+
+/// Handles the `wait` system call.
+pub extern "C" fn wait(
+    address: usize,
+    expected: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    wait_timeout(address, expected, usize::MAX, 0, 0, 0)
+}
+
+/// Handles the `wait_timeout` system call.
+pub extern "C" fn wait_timeout(
+    address: usize,
+    expected: usize,
+    timeout: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some(process) = (unsafe { process::get(process::current_id()) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    if address % align_of::<AtomicU32>() != 0
+        || !validate_user_range(address, size_of::<AtomicU32>())
+        || !process
+            .memory_map
+            .is_mapped(address, size_of::<AtomicU32>(), MapFlags::empty())
+    {
+        return SysResult::INVALID_VALUE;
+    }
+
+    let Some(key) = futex_key(process, address) else {
+        return SysResult::OUT_OF_MEMORY;
+    };
+
+    // SAFETY:
+    //  We checked that the word is mapped in the address space of the current process, and
+    //  `futex_key` made sure it is backed by memory.
+    let value = unsafe { (*(address as *const AtomicU32)).load(SeqCst) };
+
+    if value != expected as u32 || timeout == 0 {
+        return SysResult::success(0);
+    }
+
+    match block(process, ProcessState::Waiting { key }, timeout) {
+        Some(()) => SysResult::success(0),
+        None => SysResult::OUT_OF_MEMORY,
+    }
+}
+
+/// Handles the `wake` system call.
+pub extern "C" fn wake(
+    address: usize,
+    count: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some(process) = (unsafe { process::get(process::current_id()) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    if address % align_of::<AtomicU32>() != 0
+        || !validate_user_range(address, size_of::<AtomicU32>())
+        || !process
+            .memory_map
+            .is_mapped(address, size_of::<AtomicU32>(), MapFlags::empty())
+    {
+        return SysResult::INVALID_VALUE;
+    }
+
+    let Some(key) = futex_key(process, address) else {
+        return SysResult::OUT_OF_MEMORY;
+    };
+
+    let mut woken = 0;
+    for (id, process) in unsafe { process::iter() } {
+        if woken == count {
+            break;
+        }
+
+        if process.state == (ProcessState::Waiting { key }) {
+            process.state = ProcessState::Runnable;
+            process.cancel_timeout();
+            scheduler::enqueue(id);
+            woken += 1;
+        }
+    }
+
+    SysResult::success(woken)
+}
+
+/// Handles the `create_port` system call.
+pub extern "C" fn create_port(
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    match ipc::create(process::current_id()) {
+        Some(id) => SysResult::success(id.get()),
+        None => SysResult::OUT_OF_MEMORY,
+    }
+}
+
+/// Handles the `destroy_port` system call.
+pub extern "C" fn destroy_port(
+    port: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        port: OwnedPort = port;
+    }
+
+    ipc::destroy(port.id);
+
+    SysResult::success(0)
+}
+
+/// Handles the `send` system call.
+pub extern "C" fn send(
+    port: usize,
+    data: usize,
+    length: usize,
+    attachment: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let sender = process::current_id();
+
+    // Only the ports owned by the sender can be attached, so that the receiver knows that the
+    // attached port was handed to it by its owner.
+    audit! {
+        data: UserSlice<MAX_MESSAGE_SIZE> = (data, length);
+        attachment: Option<OwnedPort> = attachment;
+    }
+
+    let Some(id) = PortId::new(port) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    let mut message = Message {
+        sender: sender.get(),
+        attachment: attachment.map_or(0, |port| port.id.get()),
+        length,
+        data: [0; MAX_MESSAGE_SIZE],
+    };
+
+    message.data[..length].copy_from_slice(data.as_bytes());
+
+    match ipc::post(id, message) {
+        Ok(()) => SysResult::success(0),
+        Err(PostError::NoSuchPort) => SysResult::INVALID_VALUE,
+        Err(PostError::PortFull) => SysResult::WOULD_BLOCK,
+    }
+}
+
+/// Handles the `receive` system call.
+pub extern "C" fn receive(
+    port: usize,
+    buffer: usize,
+    length: usize,
+    info: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        port: OwnedPort = port;
+        buffer: UserSliceMut = (buffer, length);
+        info: Option<UserMut<MessageInfo>> = info;
+    }
+
+    let OwnedPort { port, .. } = port;
+
+    let Some(message) = port.peek() else {
+        return SysResult::WOULD_BLOCK;
+    };
+
+    // The message is left in the queue when it does not fit in the buffer, allowing the caller to
+    // try again with a larger one.
+    if message.length > buffer.len() {
+        return SysResult::INVALID_VALUE;
+    }
+
+    let message = port.pop().unwrap();
+    ipc::publish_pending(port.owner);
+
+    buffer.write(&message.data[..message.length]);
+
+    if let Some(info) = info {
+        info.write(MessageInfo {
+            sender: message.sender,
+            attachment: message.attachment,
+        });
+    }
+
+    SysResult::success(message.length)
+}
+
+/// Handles the `wait_port` system call.
+pub extern "C" fn wait_port(
+    port: usize,
+    timeout: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        port: OwnedPort = port;
+    }
+
+    let OwnedPort { id, port } = port;
+
+    if !port.is_empty() || timeout == 0 {
+        return SysResult::success(0);
+    }
+
+    let Some(process) = (unsafe { process::get(process::current_id()) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    match block(process, ProcessState::Receiving { port: id }, timeout) {
+        Some(()) => SysResult::success(0),
+        None => SysResult::OUT_OF_MEMORY,
+    }
+}
+
+/// Handles the `debug_log` system call.
+pub extern "C" fn debug_log(
+    data: usize,
+    length: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let id = process::current_id();
+
+    audit! {
+        data: UserSlice<MAX_DEBUG_LOG_LENGTH> = (data, length);
+    }
+
+    let Ok(message) = core::str::from_utf8(data.as_bytes()) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    log::info!("[process {}] {}", id, message);
+
+    SysResult::success(0)
+}
+
+/// Handles the `subscribe_event` system call.
+pub extern "C" fn subscribe_event(
+    kind: usize,
+    port: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some(kind) = EventKind::from_raw(kind) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    audit! {
+        port: Option<OwnedPort> = port;
+    }
+
+    match event::subscribe(process::current_id(), kind, port.map(|p| p.id)) {
+        Ok(()) => SysResult::success(0),
+        Err(_) => SysResult::CONFLICT,
+    }
+}
+
+/// Handles the `acquire_pci_device` system call.
+pub extern "C" fn acquire_pci_device(
+    process_id: usize,
+    index: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some((process_id, _)) = (unsafe { process::resolve(process_id) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    let public = unsafe { &*(crate::x86_64::public_data_address() as *mut PublicData) };
+
+    let Some(device) = public.pci_devices().get(index) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if device
+        .owned_by
+        .compare_exchange(0, process_id.get() as u64, AcqRel, Relaxed)
+        .is_err()
+    {
+        return SysResult::CONFLICT;
+    }
+
+    SysResult::success(0)
+}
+
+/// Handles the `release_pci_device` system call.
+pub extern "C" fn release_pci_device(
+    process_id: usize,
+    index: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some((process_id, process)) = (unsafe { process::resolve(process_id) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    let public = unsafe { &*(crate::x86_64::public_data_address() as *mut PublicData) };
+
+    let Some(device) = public.pci_devices().get(index) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if device.owned_by.load(Acquire) != process_id.get() as u64 {
+        return SysResult::CONFLICT;
+    }
+
+    process.unmap_device(index);
+
+    if device
+        .owned_by
+        .compare_exchange(process_id.get() as u64, 0, AcqRel, Relaxed)
+        .is_err()
+    {
+        return SysResult::CONFLICT;
+    }
+
+    irq::unbind_device(index);
+
+    SysResult::success(0)
+}
+
+/// Handles the `map_device_memory` system call.
+pub extern "C" fn map_device_memory(
+    process_id: usize,
+    physical_address: usize,
+    length: usize,
+    mut at: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    //
+    // Validate the arguments.
+    //
+    let Some((process_id, process)) = (unsafe { process::resolve(process_id) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    if !may_control(process::current_id(), process_id) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    let public = unsafe { &*(crate::x86_64::public_data_address() as *mut PublicData) };
+
+    // The requested range must be exactly the memory of a register of a device owned by the
+    // process. Registers that share pages with other resources are never mappable, ensuring
+    // that a driver cannot reach the registers of a neighboring device.
+    let owned_bar = public
+        .pci_devices()
+        .iter()
+        .enumerate()
+        .find_map(|(index, device)| {
+            if device.owned_by.load(Acquire) != process_id.get() as u64 {
+                return None;
+            }
+
+            device
+                .bars
+                .iter()
+                .any(|bar| {
+                    bar.flags.contains(PciBarFlags::MAPPABLE)
+                        && bar.address == physical_address as u64
+                        && num::page_align_up(bar.size as usize) == Some(length)
+                })
+                .then_some(index)
+        });
+
+    let Some(index) = owned_bar else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if !num::is_page_aligned(at) || !validate_user_range(at, length) {
+        return SysResult::INVALID_VALUE;
+    }
+
+    //
+    // Pick an address if the caller let the kernel choose.
+    //
+    if at == 0 {
+        match process
+            .memory_map
+            .find_free(length, process.map_base, paging::user_limit())
+        {
+            Some(addr) => at = addr,
+            None => return SysResult::OUT_OF_MEMORY,
+        }
+    }
+
+    if !process.memory_map.is_free(at, length) {
+        return SysResult::CONFLICT;
+    }
+
+    let region = Region {
+        start: at,
+        length,
+        flags: MapFlags::WRITABLE,
+        backing: Backing::Device { index },
+    };
+
+    if process.memory_map.insert(region).is_err() {
+        return SysResult::OUT_OF_MEMORY;
+    }
+
+    // SAFETY:
+    //  The memory tracker is initialized before system calls are enabled.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    let l4 = unsafe { process.l4_table() };
+
+    // Device memory must never be cached: reads and writes have side effects.
+    let page_flags = PageFlags::USER
+        | PageFlags::WRITABLE
+        | PageFlags::NO_EXECUTE
+        | PageFlags::DISABLE_CACHE
+        | PageFlags::WRITE_THROUGH;
+
+    let pages = Page::range_of(VirtAddr::new(at), length);
+    let frames = Frame::range_of(PhysAddr::new(physical_address), length);
+
+    for (page, frame) in pages.zip(frames) {
+        let mapped = unsafe {
+            paging::map_4kib(
+                l4,
+                HHDM_OFFSET,
+                &mut || memory_tracker.allocate_page_table(),
+                page,
+                frame,
+                page_flags,
+            )
+        };
+
+        if mapped.is_err() {
+            process.unmap_pages(None, at, page.start().get() - at);
+            let _ = process.memory_map.remove(at, length);
+            return SysResult::OUT_OF_MEMORY;
+        }
+
+        crate::x86_64::instr::invlpg(page.start().get());
+    }
+
+    SysResult::success(at)
+}
+
+/// Handles the `query_address_space` system call.
+pub extern "C" fn query_address_space(
+    process_id: usize,
+    stats: usize,
+    flags: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        stats: UserMut<AddressSpaceStats> = stats;
+        flags: Bits<QueryAddressSpaceFlags> = flags;
+        process: Pid = process_id;
+    }
+
+    let caller = process::current_id();
+    if process.id != caller && !supervisor::is_init(caller) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    let regions = process.process.memory_map.regions();
+
+    let mut mapped_pages = [0; 4];
+    let l4 = unsafe { process.process.l4_table() };
+    let page_table_count = unsafe {
+        paging::walk_lower_half(l4, HHDM_OFFSET, &mut |_, size, page_flags| {
+            let mut index = 0;
+            if page_flags.contains(PageFlags::WRITABLE) {
+                index |= MapFlags::WRITABLE.bits();
+            }
+            if !page_flags.contains(PageFlags::NO_EXECUTE) {
+                index |= MapFlags::EXECUTABLE.bits();
+            }
+            mapped_pages[index] += num::page_count(size);
+        })
+    };
+
+    // Regions are sorted and never overlap, so the holes are the gaps between them.
+    let user_limit = paging::user_limit();
+    let mut largest_hole = (0, 0);
+    let mut cursor = 0;
+    for (start, end) in regions
+        .iter()
+        .map(|region| (region.start, region.end()))
+        .chain(core::iter::once((user_limit, user_limit)))
+    {
+        if start - cursor > largest_hole.1 {
+            largest_hole = (cursor, start - cursor);
+        }
+        cursor = end;
+    }
+
+    if flags.0.contains(QueryAddressSpaceFlags::LOG_REGIONS) {
+        log::info!("Address space of process {}:", process.id);
+        for region in regions {
+            log::info!(
+                "  {:#018x}..{:#018x} {:?} {:?}",
+                region.start,
+                region.end(),
+                region.backing.kind(),
+                region.flags,
+            );
+        }
+    }
+
+    stats.write(AddressSpaceStats {
+        region_count: regions.len(),
+        reserved_bytes: regions.iter().map(|region| region.length).sum(),
+        mapped_pages,
+        page_table_count,
+        largest_hole_start: largest_hole.0,
+        largest_hole_length: largest_hole.1,
+    });
+
+    SysResult::success(0)
+}
+
+/// Handles the `query_kernel_stats` system call.
+pub extern "C" fn query_kernel_stats(
+    stats: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        stats: UserMut<KernelStats> = stats;
+    }
+
+    stats.write(KernelStats {
+        cpu: info::get(),
+        memory: usage::get(),
+    });
+
+    SysResult::success(0)
+}
+
+/// Handles the `set_purgeable` system call.
+pub extern "C" fn set_purgeable(
+    address: usize,
+    length: usize,
+    purgeable: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        range: PageRange = (address, length);
+    }
+
+    let purgeable = match purgeable {
+        0 => false,
+        1 => true,
+        _ => return SysResult::INVALID_VALUE,
+    };
+
+    if range.length == 0 {
+        return SysResult::INVALID_VALUE;
+    }
+
+    let Some(process) = (unsafe { process::get(process::current_id()) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    // The range must be part of a single region of memory allocated by the kernel.
+    let Some(region) = process.memory_map.find(range.start).copied() else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    let purged = match region.backing {
+        Backing::Anonymous => false,
+        Backing::Purgeable { purged } => purged,
+        _ => return SysResult::INVALID_VALUE,
+    };
+
+    if range.start + range.length > region.end() {
+        return SysResult::INVALID_VALUE;
+    }
+
+    // Only the memory map changes: the pages that are still mapped keep their content, and the
+    // ones that have been purged are mapped again when they are accessed.
+    let backing = match purgeable {
+        true => Backing::Purgeable { purged: false },
+        false => Backing::Anonymous,
+    };
+
+    let inserted = process.memory_map.insert(Region {
+        start: range.start,
+        length: range.length,
+        flags: region.flags,
+        backing,
+    });
+
+    if inserted.is_err() {
+        return SysResult::OUT_OF_MEMORY;
+    }
+
+    SysResult::success(purged as usize)
+}
+
+/// Handles the `debug_snapshot` system call.
+pub extern "C" fn debug_snapshot(
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    #[cfg(feature = "snapshot")]
+    {
+        log::info!(
+            "Process {} requested a snapshot of the kernel.",
+            process::current_id()
+        );
+
+        // SAFETY:
+        //  No reference into the process table is alive.
+        unsafe { crate::x86_64::snapshot::write() };
+
+        SysResult::success(0)
+    }
+
+    #[cfg(not(feature = "snapshot"))]
+    SysResult::NOT_SUPPORTED
+}
+
+/// Handles the `set_log_filter` system call.
+pub extern "C" fn set_log_filter(
+    module: usize,
+    module_length: usize,
+    level: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        module: UserSlice<MAX_LOG_FILTER_NAME_LENGTH> = (module, module_length);
+    }
+
+    let Some(level) = LevelFilter::from_raw(level) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if !supervisor::is_init(process::current_id()) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    match log::set_filter(module.as_bytes(), level) {
+        Ok(()) => SysResult::success(0),
+        Err(SetFilterError::NameTooLong) => SysResult::INVALID_VALUE,
+        Err(SetFilterError::TableFull) => SysResult::OUT_OF_MEMORY,
+    }
+}
+
+/// Handles the `inject_fault` system call.
+pub extern "C" fn inject_fault(
+    point: usize,
+    nth: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    if !fault_injection::ENABLED {
+        return SysResult::NOT_SUPPORTED;
+    }
+
+    if !supervisor::is_init(process::current_id()) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    let Some(point) = fault_injection::point_from_raw(point) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    fault_injection::arm(point, nth);
+    SysResult::success(0)
+}
+
+/// Handles the `kernel_info` system call.
+pub extern "C" fn kernel_info(
+    info: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        info: UserMut<KernelInfo> = info;
+    }
+
+    let mut syscalls = [0u64; 2];
+    for index in 0..super::SYSTEM_CALL_COUNT {
+        syscalls[index / 64] |= 1 << (index % 64);
+    }
+
+    // Those system calls always fail when the kernel is built without their feature.
+    let disabled = [
+        (Syscall::DebugSnapshot, cfg!(feature = "snapshot")),
+        (Syscall::InjectFault, fault_injection::ENABLED),
+    ];
+    for (syscall, enabled) in disabled {
+        if !enabled {
+            syscalls[syscall as usize / 64] &= !(1 << (syscall as usize % 64));
+        }
+    }
+
+    info.write(KernelInfo {
+        version_major: VERSION[0],
+        version_minor: VERSION[1],
+        version_patch: VERSION[2],
+        syscall_count: super::SYSTEM_CALL_COUNT as u32,
+        syscalls,
+        features: info::features(),
+        page_sizes: info::page_sizes(),
+        user_top: paging::user_top() as u64,
+        build_timestamp: BUILD_ID.timestamp,
+        build_commit: BUILD_ID.commit,
+    });
+
+    SysResult::success(0)
+}
+
+/// Handles the `spawn` system call.
+pub extern "C" fn spawn(
+    image: usize,
+    image_length: usize,
+    cmdline: usize,
+    cmdline_length: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        image: UserSlice<{ usize::MAX }> = (image, image_length);
+        cmdline: UserSlice<PAGE_SIZE> = (cmdline, cmdline_length);
+    }
+
+    // SAFETY:
+    //  The kernel address space is initialized before system calls are enabled.
+    let upper_half = unsafe { UpperHalfAddressSpaceTok::unchecked() };
+
+    let current = process::current_id();
+
+    match process::spawn_from(image.as_bytes(), cmdline.as_bytes(), upper_half) {
+        Ok(id) => {
+            // SAFETY:
+            //  The process table is never accessed concurrently.
+            if let Some(child) = unsafe { process::get(id) } {
+                child.parent = Some(current);
+            }
+
+            diag!(trace!("Process {} spawned process {}.", current, id));
+            SysResult::success(id.get())
+        }
+        Err(LoadError::OutOfMemory | LoadError::TooManyProcesses) => SysResult::OUT_OF_MEMORY,
+        Err(_) => SysResult::INVALID_VALUE,
+    }
+}
+
+/// Handles the `set_restartable` system call.
+pub extern "C" fn set_restartable(
+    process_id: usize,
+    port: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        process: Pid = process_id;
+        port: Option<OwnedPort> = port;
+    }
+
+    let Pid { process, .. } = process;
+
+    if process.parent != Some(process::current_id()) {
+        return SysResult::INVALID_PROCESS_ID;
+    }
+
+    process.restart_port = port.map(|p| p.id);
+
+    SysResult::success(0)
+}
+
+/// Handles the `reclaim_escrow` system call.
+pub extern "C" fn reclaim_escrow(
+    escrow: usize,
+    to: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        to: Pid = to;
+    }
+
+    match escrow::reclaim(process::current_id(), escrow, to.id) {
+        Some(count) => SysResult::success(count),
+        None => SysResult::INVALID_VALUE,
+    }
+}
+
+/// Handles the `bind_irq` system call.
+pub extern "C" fn bind_irq(
+    device: usize,
+    port: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        port: OwnedPort = port;
+    }
+
+    match irq::bind(process::current_id(), device, port.id) {
+        Ok(line) => SysResult::success(line),
+        Err(BindError::InvalidDevice) => SysResult::INVALID_VALUE,
+        Err(BindError::LineInUse) => SysResult::CONFLICT,
+        Err(BindError::TooManyLines) => SysResult::OUT_OF_MEMORY,
+    }
+}
+
+/// Handles the `ack_irq` system call.
+pub extern "C" fn ack_irq(
+    line: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    if !irq::ack(process::current_id(), line) {
+        return SysResult::INVALID_VALUE;
+    }
+
+    SysResult::success(0)
+}
+
+/// Handles the `set_irq_budget` system call.
+pub extern "C" fn set_irq_budget(
+    line: usize,
+    ack_deadline_ms: usize,
+    cpu_budget_ms: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let budget = irq::budget_from_ms(ack_deadline_ms as u64, cpu_budget_ms as u64);
+
+    match irq::set_budget(process::current_id(), line, budget) {
+        Ok(()) => SysResult::success(0),
+        Err(BudgetError::NoSuchLine) => SysResult::INVALID_VALUE,
+        Err(BudgetError::PermissionDenied) => SysResult::PERMISSION_DENIED,
+    }
+}
+
+/// Handles the `create_framebuffer_region` system call.
+pub extern "C" fn create_framebuffer_region(
+    index: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    grantee: usize,
+) -> SysResult {
+    audit! {
+        grantee: Pid = grantee;
+    }
+
+    let rect = Rect {
+        x,
+        y,
+        width,
+        height,
+    };
+
+    match display::create(process::current_id(), index, rect, grantee.id) {
+        Ok(id) => SysResult::success(id),
+        Err(CreateError::InvalidFramebuffer | CreateError::InvalidRect) => SysResult::INVALID_VALUE,
+        Err(CreateError::NotOwned) => SysResult::CONFLICT,
+        Err(CreateError::TooManyRegions) => SysResult::OUT_OF_MEMORY,
+    }
+}
+
+/// Handles the `map_framebuffer_region` system call.
+pub extern "C" fn map_framebuffer_region(
+    id: usize,
+    mut at: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    //
+    // Validate the arguments.
+    //
+    let process_id = process::current_id();
+
+    let Some((physical_address, length)) = display::granted(process_id, id) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if !num::is_page_aligned(at) || !validate_user_range(at, length) {
+        return SysResult::INVALID_VALUE;
+    }
+
+    let Some(process) = (unsafe { process::get(process_id) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    //
+    // Pick an address if the caller let the kernel choose.
+    //
+    if at == 0 {
+        match process
+            .memory_map
+            .find_free(length, process.map_base, paging::user_limit())
+        {
+            Some(addr) => at = addr,
+            None => return SysResult::OUT_OF_MEMORY,
+        }
+    }
+
+    if !process.memory_map.is_free(at, length) {
+        return SysResult::CONFLICT;
+    }
+
+    let region = Region {
+        start: at,
+        length,
+        flags: MapFlags::WRITABLE,
+        backing: Backing::FramebufferRegion { id },
+    };
+
+    if process.memory_map.insert(region).is_err() {
+        return SysResult::OUT_OF_MEMORY;
+    }
+
+    // SAFETY:
+    //  The memory tracker is initialized before system calls are enabled.
+    let memory_tracker = unsafe { MemoryTrackerTok::unchecked() };
+    let mut memory_tracker = memory_tracker.lock();
+
+    let l4 = unsafe { process.l4_table() };
+
+    // Only the rows of the region are mapped: `display::create` ensured that they start and end
+    // on page boundaries.
+    let pages = Page::range_of(VirtAddr::new(at), length);
+    let frames = Frame::range_of(physical_address, length);
+
+    for (page, frame) in pages.zip(frames) {
+        let mapped = unsafe {
+            paging::map_4kib(
+                l4,
+                HHDM_OFFSET,
+                &mut || memory_tracker.allocate_page_table(),
+                page,
+                frame,
+                PageFlags::USER | PageFlags::WRITABLE | PageFlags::NO_EXECUTE,
+            )
+        };
+
+        if mapped.is_err() {
+            process.unmap_pages(None, at, page.start().get() - at);
+            let _ = process.memory_map.remove(at, length);
+            return SysResult::OUT_OF_MEMORY;
+        }
+
+        crate::x86_64::instr::invlpg(page.start().get());
+    }
+
+    SysResult::success(at)
+}
+
+/// Handles the `destroy_framebuffer_region` system call.
+pub extern "C" fn destroy_framebuffer_region(
+    id: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    if !display::destroy(process::current_id(), id) {
+        return SysResult::INVALID_VALUE;
+    }
+
+    SysResult::success(0)
+}
+
+/// Handles the `set_tick_mode` system call.
+pub extern "C" fn set_tick_mode(
+    mode: usize,
+    period: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some(mode) = TickMode::from_raw(mode) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    if !supervisor::is_init(process::current_id()) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    match timer::set_mode(mode, period) {
+        Ok(()) => SysResult::success(0),
+        Err(SetModeError::InvalidPeriod) => SysResult::INVALID_VALUE,
+        Err(SetModeError::NotSupported) => SysResult::NOT_SUPPORTED,
+    }
+}
+
+/// Returns whether the process with the provided ID owns at least one PCI device.
+fn owns_pci_device(id: ProcessId) -> bool {
+    let public = unsafe { &*(crate::x86_64::public_data_address() as *mut PublicData) };
+
+    public
+        .pci_devices()
+        .iter()
+        .any(|device| device.owned_by.load(Acquire) == id.get() as u64)
+}
+
+/// Handles the `cache_control` system call.
+pub extern "C" fn cache_control(
+    op: usize,
+    address: usize,
+    length: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some(op) = CacheOp::from_raw(op) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    let caller = process::current_id();
+
+    if op == CacheOp::FlushAll {
+        if !supervisor::is_init(caller) && !owns_pci_device(caller) {
+            return SysResult::PERMISSION_DENIED;
+        }
+
+        cache::writeback_all();
+        return SysResult::success(0);
+    }
+
+    let Some(process) = (unsafe { process::get(caller) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    if !validate_user_range(address, length)
+        || !process
+            .memory_map
+            .is_mapped(address, length, MapFlags::empty())
+    {
+        return SysResult::INVALID_VALUE;
+    }
+
+    if op == CacheOp::SyncInstructions {
+        // The caches of the CPU are coherent with its instruction stream: serializing it is
+        // enough for the instructions that were already fetched to be discarded.
+        cache::serialize();
+        return SysResult::success(0);
+    }
+
+    let l4 = unsafe { process.l4_table() };
+    let end = address + length;
+    let mut page = address & !(PAGE_SIZE - 1);
+
+    while page < end {
+        let start = page.max(address);
+        let next = page + PAGE_SIZE;
+
+        // Pages that have not been accessed yet have nothing in the caches, and flushing them
+        // would allocate them.
+        let present = unsafe {
+            paging::translate_4kib(l4, HHDM_OFFSET, Page::containing(VirtAddr::new(page)))
+        };
+
+        if present.is_some()
+            && !unsafe {
+                cache::writeback_range(start, next.min(end) - start, op == CacheOp::Writeback)
+            }
+        {
+            return SysResult::NOT_SUPPORTED;
+        }
+
+        page = next;
+    }
+
+    SysResult::success(0)
+}
+
+/// Returns whether the process with the provided ID owns at least one framebuffer.
+fn owns_framebuffer(id: ProcessId) -> bool {
+    let public = unsafe { &*(crate::x86_64::public_data_address() as *mut PublicData) };
+
+    public
+        .framebuffers()
+        .iter()
+        .any(|framebuffer| framebuffer.owned_by.load(Acquire) == id.get() as u64)
+}
+
+/// Handles the `read_input_events` system call.
+pub extern "C" fn read_input_events(
+    buffer: usize,
+    count: usize,
+    timeout: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let caller = process::current_id();
+
+    if !supervisor::is_init(caller) && !owns_framebuffer(caller) {
+        return SysResult::PERMISSION_DENIED;
+    }
+
+    if !keyboard::is_present() {
+        return SysResult::NOT_SUPPORTED;
+    }
+
+    let Some(length) = count.checked_mul(size_of::<KeyEvent>()) else {
+        return SysResult::INVALID_VALUE;
+    };
+
+    audit! {
+        events: UserSliceMut = (buffer, length);
+    }
+
+    if keyboard::is_empty() {
+        if timeout == 0 {
+            return SysResult::WOULD_BLOCK;
+        }
+
+        let Some(process) = (unsafe { process::get(caller) }) else {
+            return SysResult::INVALID_PROCESS_ID;
+        };
+
+        return match block(process, ProcessState::ReadingInput, timeout) {
+            Some(()) => SysResult::success(0),
+            None => SysResult::OUT_OF_MEMORY,
+        };
+    }
+
+    let mut read = 0;
+    while read < count {
+        let Some(event) = keyboard::pop() else {
+            break;
+        };
+
+        // SAFETY:
+        //  `KeyEvent` has no padding, so all its bytes are initialized.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                &event as *const KeyEvent as *const u8,
+                size_of::<KeyEvent>(),
+            )
+        };
+
+        events.write_at(read * size_of::<KeyEvent>(), bytes);
+        read += 1;
+    }
+
+    SysResult::success(read)
+}
+
+/// Handles the `get_time` system call.
+pub extern "C" fn get_time(
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    SysResult::success(timer::uptime_ns() as usize)
+}
+
+/// Handles the `yield_to` system call.
+pub extern "C" fn yield_to(
+    process_id: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    audit! {
+        process: Pid = process_id;
+    }
+
+    let Pid {
+        id: process_id,
+        process,
+    } = process;
+
+    // A runnable process other than the current one is always part of the run queue.
+    if process_id == process::current_id()
+        || process.state != ProcessState::Runnable
+        || !scheduler::promote(process_id)
+    {
+        return SysResult::success(0);
+    }
+
+    scheduler::request_reschedule();
+
+    SysResult::success(1)
+}
+
+/// Handles the `yield` system call.
+pub extern "C" fn yield_now(
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    // The current process is moved to the back of the run queue by the scheduler.
+    scheduler::request_reschedule();
+
+    SysResult::success(0)
+}
+
+/// Handles the `sleep_ns` system call.
+pub extern "C" fn sleep_ns(
+    duration: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> SysResult {
+    let Some(process) = (unsafe { process::get(process::current_id()) }) else {
+        return SysResult::INVALID_PROCESS_ID;
+    };
+
+    if duration == 0 {
+        scheduler::request_reschedule();
+        return SysResult::success(0);
+    }
+
+    let duration = duration as u64;
+    let until = timer::uptime_ns().saturating_add(duration);
+
+    // The timeout is a lower bound: the scheduler checks the clock again when it expires.
+    let ticks = duration.div_ceil(timer::NS_PER_TICK) as usize;
+
+    match block(process, ProcessState::Sleeping { until }, ticks) {
+        Some(()) => SysResult::success(0),
+        None => SysResult::OUT_OF_MEMORY,
+    }
+}
@@ -1,23 +1,39 @@
 //! This module wraps the functions required to handle the `syscall` instruction.
+//!
+//! # Hot Path
+//!
+//! System calls are frequent, and their handlers are kept small. Invalid arguments are expected
+//! to be rare: the paths that reject them are marked as cold (see [`audit!`](audit::audit)), so
+//! that the compiler keeps them out of the straight-line code.
+//!
+//! Diagnostics logged by the handlers go through [`diag!`]. The `quiet-syscalls` feature strips
+//! them entirely, including the formatting of their arguments.
+//!
+//! The round trip of a system call that does nothing is measured by the kernel self-tests (see
+//! `ktest`), which makes it possible to compare builds.
 
 use core::arch::asm;
 
 use crate::log;
+use crate::utility::InitGuard;
 
 use fabric_sys::SysResult;
 
+mod audit;
 mod handlers;
 
+use super::cpu::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use super::cpu::{cet, mitigations, pti};
 use super::instr::{rdmsr, wrmsr};
-use super::kernel_stack::KERNEL_STACK_TOP;
-use super::mem::HHDM_OFFSET;
+use super::percpu;
 use super::raw;
+use super::scheduler::{restore_trap_frame, schedule, NEED_RESCHEDULE};
 
 /// The type of a system call handler.
 type SystemCallFn = extern "C" fn(usize, usize, usize, usize, usize, usize) -> SysResult;
 
 /// The total number of system calls.
-const SYSTEM_CALL_COUNT: usize = 5;
+const SYSTEM_CALL_COUNT: usize = 45;
 
 /// A lookup table of system call handlers.
 ///
@@ -30,8 +46,80 @@ static SYSTEM_CALLS: [SystemCallFn; SYSTEM_CALL_COUNT] = [
     handlers::unmap_memory,
     handlers::acquire_framebuffer,
     handlers::release_framebuffer,
+    handlers::suspend,
+    handlers::resume,
+    handlers::query_mapping,
+    handlers::remap_memory,
+    handlers::wait,
+    handlers::wake,
+    handlers::create_port,
+    handlers::destroy_port,
+    handlers::send,
+    handlers::receive,
+    handlers::wait_port,
+    handlers::debug_log,
+    handlers::subscribe_event,
+    handlers::acquire_pci_device,
+    handlers::release_pci_device,
+    handlers::map_device_memory,
+    handlers::query_address_space,
+    handlers::query_kernel_stats,
+    handlers::set_purgeable,
+    handlers::debug_snapshot,
+    handlers::set_log_filter,
+    handlers::inject_fault,
+    handlers::kernel_info,
+    handlers::spawn,
+    handlers::set_restartable,
+    handlers::reclaim_escrow,
+    handlers::bind_irq,
+    handlers::ack_irq,
+    handlers::set_irq_budget,
+    handlers::create_framebuffer_region,
+    handlers::map_framebuffer_region,
+    handlers::destroy_framebuffer_region,
+    handlers::set_tick_mode,
+    handlers::cache_control,
+    handlers::wait_timeout,
+    handlers::read_input_events,
+    handlers::get_time,
+    handlers::yield_to,
+    handlers::yield_now,
+    handlers::sleep_ns,
 ];
 
+/// Whether the diagnostics of the system call path are logged.
+///
+/// This is `false` when the `quiet-syscalls` feature is enabled.
+pub const DIAGNOSTICS: bool = !cfg!(feature = "quiet-syscalls");
+
+/// Logs a diagnostic of the system call path with the provided logging macro, unless the
+/// `quiet-syscalls` feature is enabled.
+///
+/// ```ignore
+/// diag!(trace!("Process {} spawned process {}.", current, id));
+/// ```
+///
+/// This is not meant for the output of the system calls whose purpose is to log, such as
+/// `debug_log`.
+pub macro diag($lvl:ident!($($arg:tt)*)) {
+    if $crate::x86_64::syscall::DIAGNOSTICS {
+        $crate::log::$lvl!($($arg)*);
+    }
+}
+
+/// Handles a system call whose number is not part of [`SYSTEM_CALLS`].
+#[cold]
+extern "C" fn invalid_system_call(number: usize) -> SysResult {
+    diag!(warn_ratelimited!(
+        "Process {} performed an unknown system call ({}).",
+        super::process::current_id(),
+        number,
+    ));
+
+    SysResult::INVALID_VALUE
+}
+
 /// The function that is called when a userspace program executes the `syscall` instruction.
 ///
 /// # Arguments
@@ -65,54 +153,163 @@ static SYSTEM_CALLS: [SystemCallFn; SYSTEM_CALL_COUNT] = [
 ///
 /// The same rules as for the C calling convention apply.
 #[naked]
+#[link_section = ".entry.text"]
 extern "C" fn system_call() {
     unsafe {
-        // The `syscall` instruction invoked by the userland program puts the return address in
-        // the `rcx` register. We need to save it on the stack before clobbering all the registers
-        // by calling the system call handler.
-        //
         // Note that system calls must not touch the stack of the caller, as it might be invalid
         // or broken. Instead, we need to use our own stack. The stack pointer of the caller is
-        // saved on the kernel stack, and will be restored before returning with `sysretq`.
+        // saved in the per-CPU block while we're loading the kernel stack of the CPU. The block
+        // is reached through `gs`, whose base is exchanged with the one of userspace on entry and
+        // right before `sysretq`. See `percpu`.
+        //
+        // The complete state of the caller is then saved on the kernel stack as a `TrapFrame`.
+        // The `syscall` instruction invoked by the userland program put the return address in
+        // the `rcx` register, and its flags in the `r11` register.
+        //
+        // We're calling a C function, which writes the return value in the `rax` register. That
+        // value is saved in the trap frame, and is restored along with the other registers.
+        //
+        // If the system call requested the current process to be descheduled, the trap frame is
+        // handed to the scheduler, which might replace it with the context of another process.
+        // In that case, we need to use the `iretq` instruction to restore the context, as the
+        // `sysretq` instruction would clobber the `rcx` and `r11` registers of the new process.
         //
-        // We're calling a C function, which writes the return value in the `rax` register. Our
-        // system calls also return the value in `rax`, so we don't need to do anything more than
-        // calling the function.
+        // When shadow stacks are used, the shadow stack of the kernel is taken before calling
+        // any function, and released before `sysretq`. The `iretq` instruction releases it
+        // automatically.
+        //
+        // When page table isolation is enabled, the complete address space of the process is
+        // loaded right after switching stacks, and the user table is loaded again before the
+        // registers are restored. See `cpu::pti`.
+        //
+        // The system call number is masked once it has been checked, so that a mispredicted
+        // bounds check cannot load a handler from outside of the table, and a speculation
+        // barrier may follow. Handlers are called through a retpoline when the CPU does not
+        // prevent userspace from steering indirect branches. See `cpu::mitigations`.
         asm!(
             r#"
-            cmp rax, {syscall_count}
-            jae 2f
+            swapgs
+            mov gs:[{user_stack_pointer}], rsp
+            mov rsp, gs:[{kernel_stack_top}]
+
+            cmp byte ptr [rip + {pti}], 0
+            je 7f
+            push rax
+            mov rax, [rip + {kernel_cr3}]
+            mov cr3, rax
+            pop rax
+        7:
 
-            mov r12, {stack_offset}
-            add r12, [{kernel_stack_top}]
-            mov [r12], rsp
-            mov rsp, r12
+            cmp byte ptr [rip + {shadow_stacks}], 0
+            je 5f
+            setssbsy
+        5:
 
+            push {user_data_selector}
+            push qword ptr gs:[{user_stack_pointer}]
+            push r11
+            push {user_code_selector}
+            push rcx
+            push r15
+            push r14
+            push r13
+            push r12
+            push r11
+            push r10
+            push r9
+            push r8
             push rbp
-            mov rbp, rsp
+            push rdi
+            push rsi
+            push rdx
             push rcx
+            push rbx
+            push rax
+
+            cmp rax, {syscall_count}
+            jae 3f
+            sbb r11, r11
+            and rax, r11
+            cmp byte ptr [rip + {dispatch_fence}], 0
+            je 11f
+            lfence
+        11:
+
+            mov r11, [{system_calls} + 8 * rax]
             mov rcx, r10
+            cmp byte ptr [rip + {retpoline}], 0
+            je 8f
+            call {call_r11}
+            jmp 9f
+        8:
+            call r11
+        9:
+            mov [rsp], rax
 
-            call [{system_calls} + 8 * rax]
+        2:
+            cmp byte ptr [rip + {need_reschedule}], 0
+            jne 4f
 
+            cmp byte ptr [rip + {shadow_stacks}], 0
+            je 6f
+            mov rax, {shadow_stack_token}
+            clrssbsy [rax]
+        6:
+            cmp byte ptr [rip + {pti}], 0
+            je 10f
+            call {exit_to_user}
+            mov cr3, rax
+        10:
+            pop rax
+            pop rbx
             pop rcx
+            pop rdx
+            pop rsi
+            pop rdi
             pop rbp
-            pop rsp
+            pop r8
+            pop r9
+            pop r10
+            pop r11
+            pop r12
+            pop r13
+            pop r14
+            pop r15
+            mov rcx, [rsp]
+            mov r11, [rsp + 16]
+            mov rsp, [rsp + 24]
+            swapgs
             sysretq
 
-        2:
-            mov rax, {invalid_syscall_number}
-            sysretq
+        3:
+            mov rdi, rax
+            call {invalid_system_call}
+            mov [rsp], rax
+            jmp 2b
+
+        4:
+            mov rdi, rsp
+            call {schedule}
+            jmp {restore_trap_frame}
             "#,
-            kernel_stack_top = sym KERNEL_STACK_TOP,
-            // The `kernel_stack_top` symbol is the physical address of the top of the kernel stack.
-            // We need to offset it by `HHDM_OFFSET` to access it through virtual memory. However,
-            // the current stack pointer must be pushed on the stack before we can update it, so we
-            // need to subtract 8 bytes from the offset, which is the size of a pointer.
-            stack_offset = const HHDM_OFFSET - 8,
+            user_stack_pointer = const percpu::USER_STACK_POINTER_OFFSET,
+            kernel_stack_top = const percpu::KERNEL_STACK_TOP_OFFSET,
+            shadow_stacks = sym cet::SHADOW_STACKS,
+            shadow_stack_token = const cet::KERNEL_SHADOW_STACK_TOKEN,
+            user_data_selector = const USER_DATA_SELECTOR,
+            user_code_selector = const USER_CODE_SELECTOR,
             syscall_count = const SYSTEM_CALL_COUNT,
             system_calls = sym SYSTEM_CALLS,
-            invalid_syscall_number = const SysResult::INVALID_VALUE.0,
+            retpoline = sym mitigations::RETPOLINE,
+            dispatch_fence = sym mitigations::DISPATCH_FENCE,
+            call_r11 = sym mitigations::call_r11,
+            pti = sym pti::ENABLED,
+            kernel_cr3 = sym pti::KERNEL_CR3,
+            exit_to_user = sym pti::exit_to_user,
+            invalid_system_call = sym invalid_system_call,
+            need_reschedule = sym NEED_RESCHEDULE,
+            schedule = sym schedule,
+            restore_trap_frame = sym restore_trap_frame,
             options(noreturn),
         )
     }
@@ -126,6 +323,11 @@ extern "C" fn system_call() {
 #[inline] // only called once
 #[allow(clippy::assertions_on_constants)] // must remain true
 pub unsafe fn init() {
+    static GUARD: InitGuard = InitGuard::new();
+    if !GUARD.enter("syscall::init") {
+        return;
+    }
+
     log::trace!("Initializing the system call handler...");
 
     #[cfg(debug_assertions)]
@@ -139,6 +341,55 @@ pub unsafe fn init() {
         assert_eq!(TAB[UnmapMemory as usize], unmap_memory as _);
         assert_eq!(TAB[AcquireFramebuffer as usize], acquire_framebuffer as _);
         assert_eq!(TAB[ReleaseFramebuffer as usize], release_framebuffer as _);
+        assert_eq!(TAB[Suspend as usize], suspend as _);
+        assert_eq!(TAB[Resume as usize], resume as _);
+        assert_eq!(TAB[QueryMapping as usize], query_mapping as _);
+        assert_eq!(TAB[RemapMemory as usize], remap_memory as _);
+        assert_eq!(TAB[Wait as usize], wait as _);
+        assert_eq!(TAB[Wake as usize], wake as _);
+        assert_eq!(TAB[CreatePort as usize], create_port as _);
+        assert_eq!(TAB[DestroyPort as usize], destroy_port as _);
+        assert_eq!(TAB[Send as usize], send as _);
+        assert_eq!(TAB[Receive as usize], receive as _);
+        assert_eq!(TAB[WaitPort as usize], wait_port as _);
+        assert_eq!(TAB[DebugLog as usize], debug_log as _);
+        assert_eq!(TAB[SubscribeEvent as usize], subscribe_event as _);
+        assert_eq!(TAB[AcquirePciDevice as usize], acquire_pci_device as _);
+        assert_eq!(TAB[ReleasePciDevice as usize], release_pci_device as _);
+        assert_eq!(TAB[MapDeviceMemory as usize], map_device_memory as _);
+        assert_eq!(TAB[QueryAddressSpace as usize], query_address_space as _);
+        assert_eq!(TAB[QueryKernelStats as usize], query_kernel_stats as _);
+        assert_eq!(TAB[SetPurgeable as usize], set_purgeable as _);
+        assert_eq!(TAB[DebugSnapshot as usize], debug_snapshot as _);
+        assert_eq!(TAB[SetLogFilter as usize], set_log_filter as _);
+        assert_eq!(TAB[InjectFault as usize], inject_fault as _);
+        assert_eq!(TAB[KernelInfo as usize], kernel_info as _);
+        assert_eq!(TAB[Spawn as usize], spawn as _);
+        assert_eq!(TAB[SetRestartable as usize], set_restartable as _);
+        assert_eq!(TAB[ReclaimEscrow as usize], reclaim_escrow as _);
+        assert_eq!(TAB[BindIrq as usize], bind_irq as _);
+        assert_eq!(TAB[AckIrq as usize], ack_irq as _);
+        assert_eq!(TAB[SetIrqBudget as usize], set_irq_budget as _);
+        assert_eq!(
+            TAB[CreateFramebufferRegion as usize],
+            create_framebuffer_region as _
+        );
+        assert_eq!(
+            TAB[MapFramebufferRegion as usize],
+            map_framebuffer_region as _
+        );
+        assert_eq!(
+            TAB[DestroyFramebufferRegion as usize],
+            destroy_framebuffer_region as _
+        );
+        assert_eq!(TAB[SetTickMode as usize], set_tick_mode as _);
+        assert_eq!(TAB[CacheControl as usize], cache_control as _);
+        assert_eq!(TAB[WaitTimeout as usize], wait_timeout as _);
+        assert_eq!(TAB[ReadInputEvents as usize], read_input_events as _);
+        assert_eq!(TAB[GetTime as usize], get_time as _);
+        assert_eq!(TAB[YieldTo as usize], yield_to as _);
+        assert_eq!(TAB[Yield as usize], yield_now as _);
+        assert_eq!(TAB[SleepNs as usize], sleep_ns as _);
     }
 
     // Intel processors normally use **SYSENTER** and **SYSEXIT** instructions to perform system
@@ -155,10 +406,21 @@ pub unsafe fn init() {
     // The process will jump to this virtual address when the **SYSCALL** instruction is executed.
     unsafe { wrmsr(raw::LSTAR, system_call as usize as u64) };
 
+    // Interrupts are disabled while a system call is being handled. The kernel is not reentrant.
+    unsafe {
+        wrmsr(
+            raw::SFMASK,
+            (raw::RFlags::INTERRUPT_ENABLE
+                | raw::RFlags::TRAP
+                | raw::RFlags::DIRECTION
+                | raw::RFlags::ALIGNMENT_CHECK)
+                .bits(),
+        );
+    }
+
     // Specify the code segment and data segment to use when executing the **SYSCALL** and
     // **SYSRET** instructions.
     use super::cpu::gdt::{KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR};
-    use super::cpu::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
 
     // This constant specifies the segment selectors that will be loaded when the **SYSRET**
     // instruction is loaded.
@@ -0,0 +1,302 @@
+//! The timeouts of the kernel, kept in a hierarchical timer wheel.
+//!
+//! Every blocking operation that may time out (waiting for a message, waiting on a futex,
+//! sleeping, and the ack deadlines of the interrupt lines) arms a timeout here instead of being
+//! checked on every tick. Deadlines are expressed in scheduler ticks (see [`scheduler::ticks`]).
+//!
+//! # The Wheel
+//!
+//! The wheel is made of [`LEVELS`] levels of [`SLOTS`] slots. A slot of level `l` holds the
+//! timeouts whose deadline falls within a span of `SLOTS^l` ticks:
+//!
+//! - Level 0 holds the timeouts that expire within the next [`SLOTS`] ticks, one slot per tick.
+//! - When the tick counter reaches the start of the span of a slot of a higher level, its
+//!   timeouts are *cascaded*: they are moved to the lower levels, which now cover their deadline
+//!   with a finer granularity.
+//!
+//! Timeouts are nodes of doubly linked lists, one per slot, which makes arming and cancelling a
+//! timeout O(1). Deadlines beyond the span of the last level are kept in its farthest slot, and
+//! re-inserted when they are cascaded.
+//!
+//! [`scheduler::ticks`]: super::scheduler::ticks
+
+use core::num::NonZeroUsize;
+use core::ptr::addr_of_mut;
+
+use fabric_sys::ProcessId;
+
+use crate::utility::IdAllocator;
+
+use super::irq::MAX_IRQ_LINES;
+use super::process::MAX_PROCESSES;
+
+/// The number of levels of the wheel.
+pub const LEVELS: usize = 4;
+
+/// The number of bits of a deadline that select a slot within a level.
+const SLOT_BITS: u32 = 6;
+
+/// The number of slots of each level.
+pub const SLOTS: usize = 1 << SLOT_BITS;
+
+/// The maximum number of timeouts armed at the same time.
+///
+/// Each process waits for at most one thing at a time, and each line has at most one pending
+/// interrupt.
+const MAX_TIMEOUTS: usize = MAX_PROCESSES + MAX_IRQ_LINES;
+
+/// Marks the end of a list.
+const NIL: u16 = u16::MAX;
+
+/// The list, in [`Wheel::heads`], of the timeouts that are expiring on the current tick.
+const EXPIRING: u16 = (LEVELS * SLOTS) as u16;
+
+/// What happens when a timeout expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The process with the provided ID stops waiting.
+    Process(ProcessId),
+    /// The pending interrupt of the line with the provided ID is overdue.
+    IrqLine(usize),
+}
+
+/// The handle of an armed timeout, used to cancel it.
+///
+/// The handle of a timeout that has expired or has been cancelled is never reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle(NonZeroUsize);
+
+/// An armed timeout.
+#[derive(Clone, Copy)]
+struct Node {
+    target: Target,
+    deadline: u64,
+    /// The index of the list the node is part of, in [`Wheel::heads`].
+    list: u16,
+    prev: u16,
+    next: u16,
+}
+
+/// The timer wheel. See the [module documentation](self).
+struct Wheel {
+    /// The tick up to which the timeouts have been processed.
+    now: u64,
+    /// The first node of each slot, indexed by `level * SLOTS + slot`, followed by the first node
+    /// of the [`EXPIRING`] list.
+    heads: [u16; LEVELS * SLOTS + 1],
+    nodes: [Node; MAX_TIMEOUTS],
+    ids: IdAllocator<MAX_TIMEOUTS>,
+}
+
+/// The timer wheel of the kernel.
+static mut WHEEL: Wheel = Wheel {
+    now: 0,
+    heads: [NIL; LEVELS * SLOTS + 1],
+    nodes: [Node {
+        target: Target::IrqLine(0),
+        deadline: 0,
+        list: 0,
+        prev: NIL,
+        next: NIL,
+    }; MAX_TIMEOUTS],
+    ids: IdAllocator::new(),
+};
+
+/// Returns the timer wheel.
+fn wheel() -> &'static mut Wheel {
+    // SAFETY:
+    //  The wheel is only accessed with interrupts disabled, and the references returned by this
+    //  function never outlive the function that requested them.
+    unsafe { &mut *addr_of_mut!(WHEEL) }
+}
+
+/// Returns the list, as an index in [`Wheel::heads`], that a timeout expiring at `deadline`
+/// belongs to when the wheel is at `now`.
+fn list_of(now: u64, deadline: u64) -> u16 {
+    // The farthest slot of the last level holds every deadline beyond its span, and deadlines in
+    // the past expire on the next tick.
+    let span = 1u64 << (SLOT_BITS as usize * LEVELS);
+    let deadline = deadline.clamp(now + 1, now + span);
+
+    // The number of ticks between the next one and the deadline.
+    let delta = deadline - (now + 1);
+
+    let mut level = 0;
+    while level + 1 < LEVELS && delta >= 1 << (SLOT_BITS as usize * (level + 1)) {
+        level += 1;
+    }
+
+    let slot = (deadline >> (SLOT_BITS as usize * level)) as usize % SLOTS;
+    (level * SLOTS + slot) as u16
+}
+
+impl Wheel {
+    /// Adds the node with the provided index to the list it belongs to.
+    fn link(&mut self, index: u16) {
+        let list = list_of(self.now, self.nodes[index as usize].deadline);
+        self.link_to(index, list);
+    }
+
+    /// Adds the node with the provided index to the provided list.
+    fn link_to(&mut self, index: u16, list: u16) {
+        let head = self.heads[list as usize];
+
+        let node = &mut self.nodes[index as usize];
+        node.list = list;
+        node.prev = NIL;
+        node.next = head;
+
+        if head != NIL {
+            self.nodes[head as usize].prev = index;
+        }
+        self.heads[list as usize] = index;
+    }
+
+    /// Removes the node with the provided index from its list.
+    fn unlink(&mut self, index: u16) {
+        let Node {
+            list, prev, next, ..
+        } = self.nodes[index as usize];
+
+        match prev {
+            NIL => self.heads[list as usize] = next,
+            prev => self.nodes[prev as usize].next = next,
+        }
+        if next != NIL {
+            self.nodes[next as usize].prev = prev;
+        }
+    }
+
+    /// Removes every node of the provided list, returning the first one. The nodes remain
+    /// chained through their `next` field.
+    fn take_list(&mut self, list: usize) -> u16 {
+        core::mem::replace(&mut self.heads[list], NIL)
+    }
+
+    /// Moves the timeouts of the slots whose span starts at `tick` down to the lower levels,
+    /// and the ones that expire at `tick` to the [`EXPIRING`] list.
+    ///
+    /// The wheel must be at the tick that precedes `tick`.
+    fn collect(&mut self, tick: u64) {
+        // The highest levels go first, so that their timeouts reach the lower levels in time.
+        for level in (1..LEVELS).rev() {
+            let shift = SLOT_BITS as usize * level;
+            if tick & ((1 << shift) - 1) != 0 {
+                continue;
+            }
+
+            let slot = (tick >> shift) as usize % SLOTS;
+            let mut index = self.take_list(level * SLOTS + slot);
+            while index != NIL {
+                let next = self.nodes[index as usize].next;
+                self.link(index);
+                index = next;
+            }
+        }
+
+        let mut index = self.take_list(tick as usize % SLOTS);
+        while index != NIL {
+            let next = self.nodes[index as usize].next;
+
+            // The deadline may have been beyond the span of the wheel when the timeout was armed.
+            if self.nodes[index as usize].deadline > tick {
+                self.link(index);
+            } else {
+                self.link_to(index, EXPIRING);
+            }
+
+            index = next;
+        }
+    }
+}
+
+/// Arms a timeout that expires at the tick `deadline`.
+///
+/// # Returns
+///
+/// The handle of the timeout is returned, or `None` if too many timeouts are armed.
+pub fn arm(target: Target, deadline: u64) -> Option<Handle> {
+    let wheel = wheel();
+
+    let (id, index) = wheel.ids.allocate()?;
+    wheel.nodes[index] = Node {
+        target,
+        deadline,
+        list: 0,
+        prev: NIL,
+        next: NIL,
+    };
+    wheel.link(index as u16);
+
+    super::timer::wake_before(deadline);
+
+    Some(Handle(id))
+}
+
+/// Cancels the provided timeout.
+///
+/// Nothing happens if the timeout has already expired.
+pub fn cancel(handle: Handle) {
+    let wheel = wheel();
+
+    if let Some(index) = wheel.ids.release(handle.0) {
+        wheel.unlink(index as u16);
+    }
+}
+
+/// Returns the deadline of the provided timeout, if it has not expired yet.
+pub fn deadline(handle: Handle) -> Option<u64> {
+    let wheel = wheel();
+    let index = wheel.ids.index_of(handle.0)?;
+    Some(wheel.nodes[index].deadline)
+}
+
+/// Returns a tick at which the earliest armed timeout may expire, if any timeout is armed.
+///
+/// The returned tick is never later than the actual deadline, but it may be earlier when that
+/// deadline is only known with the granularity of a higher level.
+pub fn next_expiry() -> Option<u64> {
+    let wheel = wheel();
+
+    for level in 0..LEVELS {
+        let shift = SLOT_BITS as usize * level;
+        let base = wheel.now >> shift;
+
+        // The slots of the level are visited in the order in which they are reached.
+        for step in 1..=SLOTS as u64 {
+            let slot = (base + step) as usize % SLOTS;
+            if wheel.heads[level * SLOTS + slot] != NIL {
+                return Some((base + step) << shift);
+            }
+        }
+    }
+
+    None
+}
+
+/// Advances the wheel up to the tick `now`, calling `expire` for every timeout whose deadline
+/// has been reached.
+///
+/// The timeouts are removed from the wheel before `expire` is called, which may arm and cancel
+/// timeouts.
+pub fn advance(now: u64, mut expire: impl FnMut(Target)) {
+    while wheel().now < now {
+        let tick = wheel().now + 1;
+
+        wheel().collect(tick);
+        wheel().now = tick;
+
+        loop {
+            let wheel = wheel();
+
+            let index = wheel.heads[EXPIRING as usize];
+            let Some(id) = wheel.ids.id_at(index as usize) else {
+                break;
+            };
+
+            wheel.unlink(index);
+            wheel.ids.release(id);
+            expire(wheel.nodes[index as usize].target);
+        }
+    }
+}
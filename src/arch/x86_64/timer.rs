@@ -0,0 +1,581 @@
+//! Selection of the source of the scheduler tick, and the monotonic clock of the kernel.
+//!
+//! # Tick Source
+//!
+//! The local APIC timer is preferred, as it does not need to go through the I/O APIC. Its
+//! frequency is unknown though, and must be measured against the PIT. When the measurement
+//! fails or is inconsistent, the PIT itself is used as the tick source.
+//!
+//! The source can be forced with the `timer` option of the kernel command line, which accepts
+//! `auto` (the default), `lapic` and `pit`. In the [deterministic boot mode](super::deterministic),
+//! the default is `pit`, as the calibration of the local APIC timer varies from one boot to the
+//! next.
+//!
+//! # Tick Modes
+//!
+//! By default, the timer interrupts the CPU on every tick. When the local APIC timer is the tick
+//! source, the init process may change this at runtime with [`set_mode`]:
+//!
+//! - In [`TickMode::Periodic`], the timer interrupts the CPU once every `period` ticks.
+//!
+//! - In [`TickMode::Deadline`], the timer is re-armed after every interrupt in one-shot mode,
+//!   for the next tick if a process is waiting for the CPU, and for the earliest deadline of the
+//!   sleeping processes otherwise. A process that goes to sleep with an earlier deadline, or that
+//!   becomes ready to run, shortens the current period (see [`wake_before`]).
+//!
+//! In both modes, every interrupt accounts for the number of ticks its period covered, so that
+//! the tick counter of the scheduler keeps advancing at [`TICK_FREQUENCY`]. When the period
+//! cannot be counted with the divider used during calibration, a larger divider is selected.
+//!
+//! # Monotonic Clock
+//!
+//! The time elapsed since boot is measured with the time-stamp counter (TSC), whose frequency is
+//! measured against the PIT as early as possible during boot. See [`uptime_us`].
+//!
+//! That first measurement is short, as the PIT can only count a few tens of milliseconds. When
+//! the system has an HPET, the frequency is measured again against it once it is available, over
+//! a longer period (see [`refine_clock`]).
+//!
+//! In the deterministic boot mode, the measured frequency is replaced by the one reported by the
+//! hypervisor or the CPU, or rounded to the nearest megahertz, and is never refined (see
+//! [`fix_clock`]).
+//!
+//! The parameters used to convert TSC readings are published in the public data area, so that
+//! processes can read the clock without performing a system call (see [`clock_params`]).
+
+use fabric_sys::x86_64::public::{ClockParams, PublicData};
+use fabric_sys::x86_64::{TickMode, MAX_TICK_PERIOD};
+
+use crate::log;
+use crate::utility::Cmdline;
+use crate::x86_64::cpu::ioapic::{self, Polarity, TriggerMode};
+use crate::x86_64::cpu::{apic, idt, pit};
+use crate::x86_64::{instr, raw, scheduler};
+
+use super::cpu::info;
+use super::{acpi, deterministic, hpet, public_data_address};
+
+/// The frequency of the scheduler tick, in hertz.
+pub const TICK_FREQUENCY: u32 = 100;
+
+/// The duration of a scheduler tick, in nanoseconds.
+pub const NS_PER_TICK: u64 = 1_000_000_000 / TICK_FREQUENCY as u64;
+
+/// The duration of a single measurement of the local APIC timer, in microseconds.
+const CALIBRATION_US: u32 = 10_000;
+
+const _: () = assert!(CALIBRATION_US <= pit::MAX_BUSY_WAIT_US);
+
+/// The duration of the measurement of the TSC against the HPET, in microseconds.
+const REFINE_US: u64 = 50_000;
+
+/// The number of measurements of the local APIC timer that must agree with each other.
+const CALIBRATION_ROUNDS: usize = 3;
+
+/// The maximum relative difference between two measurements, expressed as a fraction of the
+/// first one (`1 / CALIBRATION_TOLERANCE`).
+const CALIBRATION_TOLERANCE: u32 = 16;
+
+/// Set in the `edx` register of the `0x8000_0007` **CPUID** leaf when the TSC runs at a constant
+/// rate, regardless of the power state of the CPU.
+const CPUID_INVARIANT_TSC: u32 = 1 << 8;
+
+/// The **CPUID** leaf that reports the ratio of the TSC to the core crystal clock.
+const CPUID_TSC_LEAF: u32 = 0x15;
+
+/// The **CPUID** leaf that reports the highest leaf of the hypervisor.
+const CPUID_HYPERVISOR_LEAF: u32 = 0x4000_0000;
+
+/// The **CPUID** leaf in which hypervisors such as QEMU and VMware report the frequency of the
+/// TSC, in kilohertz.
+const CPUID_HYPERVISOR_TIMING_LEAF: u32 = 0x4000_0010;
+
+/// The divide configurations of the local APIC timer that may be used to count long periods,
+/// along with the power of two by which they divide the configuration used during calibration.
+const DIVIDERS: [(u32, u32); 5] = [
+    (raw::LAPIC_DIVIDE_BY_16, 0),
+    (raw::LAPIC_DIVIDE_BY_32, 1),
+    (raw::LAPIC_DIVIDE_BY_64, 2),
+    (raw::LAPIC_DIVIDE_BY_128, 3),
+    (raw::LAPIC_DIVIDE_BY_256, 4),
+];
+
+/// The parameters of the monotonic clock.
+static mut CLOCK: ClockParams = ClockParams::UNCALIBRATED;
+
+/// The state of the local APIC timer, when it is the tick source.
+struct LapicTimer {
+    /// The number of timer counts in a tick, with the divider used during calibration.
+    per_tick: u32,
+    /// The current mode of the timer.
+    mode: TickMode,
+    /// The period requested with [`set_mode`], in ticks.
+    period: u32,
+    /// The number of ticks covered by the current period of the timer.
+    armed_ticks: u32,
+    /// The initial count of the current period of the timer.
+    armed_count: u32,
+    /// The divide configuration of the current period of the timer, as an index in
+    /// [`DIVIDERS`].
+    divider: usize,
+}
+
+impl LapicTimer {
+    /// Returns the initial count and the index of the divider to use to count `ticks` ticks.
+    fn count_for(&self, ticks: u32) -> Option<(u32, usize)> {
+        let counts = self.per_tick as u64 * ticks as u64;
+
+        DIVIDERS
+            .iter()
+            .position(|&(_, shift)| counts >> shift <= u32::MAX as u64)
+            .map(|index| ((counts >> DIVIDERS[index].1).max(1) as u32, index))
+    }
+
+    /// Starts a new period of the timer, covering `ticks` ticks.
+    ///
+    /// The initial count must have been computed with [`count_for`](Self::count_for).
+    fn arm(&mut self, ticks: u32, count: u32, divider: usize) {
+        self.armed_ticks = ticks;
+        self.armed_count = count;
+        self.divider = divider;
+
+        // SAFETY:
+        //  The local APIC has been initialized before the timer was selected as the tick source.
+        unsafe { apic::arm_timer(count, DIVIDERS[divider].0, self.mode == TickMode::Periodic) };
+    }
+}
+
+/// The state of the local APIC timer, or `None` when it is not the tick source.
+static mut LAPIC_TIMER: Option<LapicTimer> = None;
+
+/// Returns the state of the local APIC timer, if it is the tick source.
+fn lapic_timer() -> Option<&'static mut LapicTimer> {
+    // SAFETY:
+    //  The state is only accessed with interrupts disabled, on the bootstrap CPU.
+    unsafe { (*core::ptr::addr_of_mut!(LAPIC_TIMER)).as_mut() }
+}
+
+/// Returns whether the TSC runs at a constant rate, regardless of the power state of the CPU.
+pub fn tsc_is_invariant() -> bool {
+    instr::cpuid(0x8000_0000, 0)[0] >= 0x8000_0007
+        && instr::cpuid(0x8000_0007, 0)[3] & CPUID_INVARIANT_TSC != 0
+}
+
+/// Initializes the monotonic clock by measuring the frequency of the TSC.
+///
+/// # Safety
+///
+/// This function must only be called once, and the PIT must not be used concurrently.
+pub unsafe fn init_clock() {
+    let start = instr::rdtsc();
+    unsafe { pit::busy_wait(CALIBRATION_US) };
+    let elapsed = instr::rdtsc().wrapping_sub(start);
+
+    // SAFETY:
+    //  This function is only called once, before the clock is read concurrently.
+    unsafe { CLOCK = ClockParams::new(start, elapsed * 1_000_000 / CALIBRATION_US as u64, 0) };
+
+    if !tsc_is_invariant() {
+        log::warn!("The TSC is not invariant. Timestamps may drift.");
+    }
+}
+
+/// Returns the frequency of the TSC reported by the hypervisor or by the CPU, in hertz, if any.
+fn reported_tsc_frequency() -> Option<u64> {
+    if info::hypervisor_present()
+        && instr::cpuid(CPUID_HYPERVISOR_LEAF, 0)[0] >= CPUID_HYPERVISOR_TIMING_LEAF
+    {
+        let khz = instr::cpuid(CPUID_HYPERVISOR_TIMING_LEAF, 0)[0];
+        if khz != 0 {
+            return Some(khz as u64 * 1000);
+        }
+    }
+
+    if instr::cpuid(0, 0)[0] >= CPUID_TSC_LEAF {
+        let [denominator, numerator, crystal_hz, _] = instr::cpuid(CPUID_TSC_LEAF, 0);
+        if denominator != 0 && numerator != 0 && crystal_hz != 0 {
+            return Some(crystal_hz as u64 * numerator as u64 / denominator as u64);
+        }
+    }
+
+    None
+}
+
+/// Replaces the measured frequency of the TSC with a fixed one, so that the monotonic clock does
+/// not depend on the accuracy of a measurement.
+///
+/// The frequency reported by the hypervisor or by the CPU is used when available. Otherwise, the
+/// measured frequency is rounded to the nearest megahertz. The clock remains continuous.
+///
+/// This is used by the [deterministic boot mode](super::deterministic). [`refine_clock`] leaves
+/// the fixed frequency untouched.
+///
+/// # Safety
+///
+/// This function must only be called once, during boot, after [`init_clock`] and before the
+/// public data area is initialized.
+pub unsafe fn fix_clock() {
+    let clock = clock_params();
+    if !clock.is_calibrated() {
+        return;
+    }
+
+    let tsc_frequency = reported_tsc_frequency().unwrap_or_else(|| {
+        let mhz = (clock.tsc_frequency + 500_000) / 1_000_000;
+        mhz.max(1) * 1_000_000
+    });
+
+    let now = instr::rdtsc();
+
+    // SAFETY:
+    //  This function is only called during boot, before the clock is read concurrently.
+    unsafe { CLOCK = ClockParams::new(now, tsc_frequency, clock.tsc_to_ns(now)) };
+
+    log::trace!("TSC frequency fixed to {} Hz.", tsc_frequency);
+}
+
+/// Measures the frequency of the TSC against the HPET, if the system has one, and updates the
+/// parameters of the monotonic clock accordingly.
+///
+/// The clock remains continuous: the time it reports does not jump when the parameters change.
+///
+/// # Safety
+///
+/// This function must only be called once, during boot, after the HPET and the public data area
+/// have been initialized.
+pub unsafe fn refine_clock() {
+    if deterministic::enabled() {
+        return;
+    }
+
+    let Some(hpet_frequency) = hpet::frequency() else {
+        return;
+    };
+
+    let start = instr::rdtsc();
+    let Some(elapsed_ticks) = hpet::busy_wait(hpet_frequency * REFINE_US / 1_000_000) else {
+        return;
+    };
+    let elapsed = instr::rdtsc().wrapping_sub(start);
+
+    let tsc_frequency = (elapsed as u128 * hpet_frequency as u128 / elapsed_ticks as u128) as u64;
+
+    let now = instr::rdtsc();
+    let params = ClockParams::new(now, tsc_frequency, clock_params().tsc_to_ns(now));
+
+    // SAFETY:
+    //  This function is only called during boot, before the clock is read concurrently.
+    unsafe { CLOCK = params };
+
+    // SAFETY:
+    //  The public data area has been initialized.
+    let public = unsafe { &*(public_data_address() as *const PublicData) };
+    public.clock.store(params);
+
+    log::trace!(
+        "TSC frequency measured against the HPET: {} Hz.",
+        tsc_frequency
+    );
+}
+
+/// Returns the number of microseconds elapsed since the monotonic clock has been initialized.
+///
+/// When the TSC could not be calibrated, the time is derived from the scheduler tick instead.
+pub fn uptime_us() -> u64 {
+    uptime_ns() / 1000
+}
+
+/// Returns the number of nanoseconds elapsed since the monotonic clock has been initialized.
+///
+/// This is the clock that processes read from the public data area. When the TSC could not be
+/// calibrated, the time is derived from the scheduler tick instead.
+pub fn uptime_ns() -> u64 {
+    let clock = clock_params();
+
+    if !clock.is_calibrated() {
+        return scheduler::ticks() * NS_PER_TICK;
+    }
+
+    clock.tsc_to_ns(instr::rdtsc())
+}
+
+/// Returns the parameters used by the monotonic clock to convert TSC readings.
+#[inline(always)]
+pub fn clock_params() -> ClockParams {
+    // SAFETY:
+    //  This is only modified by `init_clock` and `refine_clock`, during boot.
+    unsafe { CLOCK }
+}
+
+/// The tick source requested on the kernel command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimerPreference {
+    /// Use the local APIC timer when it is reliable, and the PIT otherwise.
+    #[default]
+    Auto,
+    /// Use the local APIC timer unless it does not work at all.
+    LocalApic,
+    /// Always use the PIT.
+    Pit,
+}
+
+impl TimerPreference {
+    /// Reads the preference from the `timer` option of the provided command line.
+    ///
+    /// Unknown values are reported and replaced by the default preference.
+    pub fn from_cmdline(cmdline: Cmdline) -> Self {
+        match cmdline.get(b"timer") {
+            // The outcome of the calibration of the local APIC timer varies from one boot to the
+            // next.
+            None if deterministic::enabled() => Self::Pit,
+            None | Some(b"auto") => Self::Auto,
+            Some(b"lapic") => Self::LocalApic,
+            Some(b"pit") => Self::Pit,
+            Some(other) => {
+                log::warn!(
+                    "Unknown `timer` source: `{}`.",
+                    core::str::from_utf8(other).unwrap_or("<invalid UTF-8>")
+                );
+                log::warn!("Falling back to `auto`.");
+                Self::default()
+            }
+        }
+    }
+
+    /// Converts this preference to its representation in the public data area.
+    pub fn to_public(self) -> fabric_sys::x86_64::public::TimerPreference {
+        use fabric_sys::x86_64::public::TimerPreference as Public;
+
+        match self {
+            Self::Auto => Public::Auto,
+            Self::LocalApic => Public::LocalApic,
+            Self::Pit => Public::Pit,
+        }
+    }
+}
+
+/// Measures the number of local APIC timer ticks in a scheduler tick.
+///
+/// When `strict` is set, the measurement is repeated and `None` is returned if the results do
+/// not agree with each other.
+///
+/// # Safety
+///
+/// The local APIC must have been initialized, and the PIT must not be used concurrently.
+unsafe fn calibrate_local_apic(strict: bool) -> Option<u32> {
+    let rounds = if strict { CALIBRATION_ROUNDS } else { 1 };
+
+    let first = unsafe { apic::calibrate_timer(CALIBRATION_US)? };
+
+    for _ in 1..rounds {
+        let other = unsafe { apic::calibrate_timer(CALIBRATION_US)? };
+        if first.abs_diff(other) > first / CALIBRATION_TOLERANCE {
+            log::warn!(
+                "Inconsistent local APIC timer measurements ({} and {} ticks).",
+                first,
+                other
+            );
+            return None;
+        }
+    }
+
+    let per_second = first as u64 * (1_000_000 / CALIBRATION_US) as u64;
+    let per_tick = per_second / TICK_FREQUENCY as u64;
+
+    u32::try_from(per_tick).ok().filter(|&count| count != 0)
+}
+
+/// Starts the PIT as the source of the scheduler tick.
+///
+/// # Safety
+///
+/// The I/O APICs must have been initialized, and the PIT must not be used concurrently.
+unsafe fn start_pit() -> bool {
+    let (gsi, polarity, trigger) =
+        acpi::resolve_isa_irq(0, Polarity::ActiveHigh, TriggerMode::Edge);
+
+    let routed = ioapic::route(
+        gsi,
+        idt::PIT_TIMER_VECTOR as u8,
+        polarity,
+        trigger,
+        apic::local_apic_id(),
+    );
+
+    if routed.is_err() {
+        log::error!("The PIT (GSI {}) is not handled by any I/O APIC.", gsi);
+        return false;
+    }
+
+    unsafe { pit::start_periodic(TICK_FREQUENCY) };
+
+    log::trace!("Using the PIT as the tick source (GSI {}).", gsi);
+
+    true
+}
+
+/// Starts the scheduler tick, selecting its source according to the provided preference.
+///
+/// When no source can be started, an error is logged and processes are never preempted.
+///
+/// # Safety
+///
+/// This function must only be called once, after the local APIC and the I/O APICs have been
+/// initialized.
+pub unsafe fn init(preference: TimerPreference) {
+    if preference != TimerPreference::Pit {
+        let strict = preference == TimerPreference::Auto;
+
+        match unsafe { calibrate_local_apic(strict) } {
+            Some(count) => {
+                unsafe { apic::start_timer(count) };
+                // SAFETY:
+                //  This function is only called once, before the timer interrupt is handled.
+                unsafe {
+                    LAPIC_TIMER = Some(LapicTimer {
+                        per_tick: count,
+                        mode: TickMode::Periodic,
+                        period: 1,
+                        armed_ticks: 1,
+                        armed_count: count,
+                        divider: 0,
+                    });
+                }
+                log::trace!(
+                    "Using the local APIC timer as the tick source ({} ticks per period).",
+                    count
+                );
+                return;
+            }
+            None => log::warn!("The local APIC timer is unreliable. Falling back to the PIT."),
+        }
+    }
+
+    if !unsafe { start_pit() } {
+        log::error!("No timer is available. Processes will not be preempted.");
+    }
+}
+
+/// An error that can occur when changing the mode of the timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetModeError {
+    /// The period is out of bounds, or cannot be counted by the timer.
+    InvalidPeriod,
+    /// The tick source cannot be reconfigured.
+    NotSupported,
+}
+
+/// Changes how the timer interrupts the CPU. See the [module-level documentation](self).
+///
+/// `period` is the number of ticks between two interrupts, or the maximum number of ticks between
+/// two interrupts in [`TickMode::Deadline`].
+///
+/// When the tick source is not the local APIC timer, only the default configuration (a periodic
+/// timer with a period of 1 tick) is accepted.
+pub fn set_mode(mode: TickMode, period: usize) -> Result<(), SetModeError> {
+    if !(1..=MAX_TICK_PERIOD).contains(&period) {
+        return Err(SetModeError::InvalidPeriod);
+    }
+
+    let period = period as u32;
+
+    let Some(timer) = lapic_timer() else {
+        return match (mode, period) {
+            (TickMode::Periodic, 1) => Ok(()),
+            _ => Err(SetModeError::NotSupported),
+        };
+    };
+
+    let (count, divider) = timer.count_for(period).ok_or(SetModeError::InvalidPeriod)?;
+
+    // The current period is abandoned. Account for the ticks it already covered so that the
+    // tick counter does not fall behind.
+    let remaining = apic::timer_remaining();
+    if remaining != 0 {
+        let elapsed = timer.armed_count - remaining.min(timer.armed_count);
+        let per_tick = timer.per_tick >> DIVIDERS[timer.divider].1;
+        scheduler::tick((elapsed / per_tick.max(1)) as u64);
+    }
+
+    timer.mode = mode;
+    timer.period = period;
+    timer.arm(period, count, divider);
+
+    log::info!(
+        "The timer is now in {:?} mode, with a period of {} ticks.",
+        mode,
+        period
+    );
+
+    Ok(())
+}
+
+/// Returns the number of ticks covered by the period of the timer that just ended.
+///
+/// This function is called by the timer interrupt handler.
+pub fn elapsed_ticks() -> u64 {
+    lapic_timer().map_or(1, |timer| timer.armed_ticks as u64)
+}
+
+/// Starts the next period of the timer when it is in [`TickMode::Deadline`].
+///
+/// This function is called by the timer interrupt handler, once the scheduler has accounted for
+/// the period that just ended.
+pub fn rearm() {
+    let Some(timer) = lapic_timer() else { return };
+
+    if timer.mode != TickMode::Deadline {
+        return;
+    }
+
+    let ticks = if scheduler::has_waiting() {
+        1
+    } else {
+        let now = scheduler::ticks();
+        scheduler::next_deadline().map_or(timer.period, |deadline| {
+            deadline.saturating_sub(now).clamp(1, timer.period as u64) as u32
+        })
+    };
+
+    // The period has been validated by `set_mode`, and shorter periods need smaller counts.
+    if let Some((count, divider)) = timer.count_for(ticks) {
+        timer.arm(ticks, count, divider);
+    }
+}
+
+/// Shortens the current period of the timer so that it ends no later than the tick `deadline`,
+/// when the timer is in [`TickMode::Deadline`].
+///
+/// The ticks that already elapsed during the current period are preserved, so the next interrupt
+/// still accounts for them.
+pub fn wake_before(deadline: u64) {
+    let Some(timer) = lapic_timer() else { return };
+
+    if timer.mode != TickMode::Deadline {
+        return;
+    }
+
+    let now = scheduler::ticks();
+    if deadline >= now + timer.armed_ticks as u64 {
+        return;
+    }
+
+    let remaining = apic::timer_remaining();
+    if remaining == 0 {
+        // The interrupt that ends the current period is already pending.
+        return;
+    }
+
+    let ticks = deadline.saturating_sub(now).max(1) as u32;
+    let per_tick = timer.per_tick >> DIVIDERS[timer.divider].1;
+    let elapsed = timer.armed_count - remaining.min(timer.armed_count);
+    let count = ticks
+        .saturating_mul(per_tick)
+        .saturating_sub(elapsed)
+        .max(1);
+
+    // The divider is kept, so that the counts of the elapsed and of the remaining parts of the
+    // period can be added together.
+    timer.arm(ticks, count, timer.divider);
+    timer.armed_count = count + elapsed;
+}
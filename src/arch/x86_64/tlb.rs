@@ -0,0 +1,214 @@
+//! Invalidation of the translations cached by the CPUs.
+//!
+//! The `invlpg` instruction only invalidates the translations cached by the TLB of the CPU that
+//! executes it. When a mapping is removed, the other CPUs that may have cached it must be told to
+//! invalidate it as well before the memory it referred to is reused: this is a *TLB shootdown*.
+//!
+//! # Address Spaces
+//!
+//! The kernel does not use PCIDs, and the TLB of a CPU only holds the translations of the address
+//! space it has currently loaded, along with the global translations of the upper half. An
+//! [`Asid`] is therefore either the upper half, which every CPU shares, or the lower half of a
+//! process, identified by the physical address of its L4 table.
+//!
+//! Every CPU records the address space it has loaded (see [`set_active`]). A range of a process is
+//! only invalidated on the CPUs that have loaded its address space: the other CPUs flush their
+//! TLB when they switch to it.
+//!
+//! # Protocol
+//!
+//! [`flush_range`] invalidates the range on the current CPU, then pushes it to the queue of
+//! pending invalidations of each other CPU involved, and sends it a [`TLB_SHOOTDOWN_VECTOR`]
+//! interrupt. The interrupt handler drains the queue of its CPU and acknowledges the requests it
+//! has seen by publishing their generation. [`flush_range`] returns once every CPU has
+//! acknowledged its request.
+//!
+//! When a queue is full, its CPU is asked to flush its whole TLB instead.
+//!
+//! While it waits, the current CPU services its own queue: two CPUs that shoot each other down
+//! with interrupts disabled do not deadlock.
+//!
+//! # Limitations
+//!
+//! The application processors are parked with interrupts disabled, and never touch memory again
+//! (see [`smp`](super::smp)). They never load an address space and are never involved in a
+//! shootdown.
+//!
+//! [`TLB_SHOOTDOWN_VECTOR`]: super::cpu::idt::TLB_SHOOTDOWN_VECTOR
+
+use core::sync::atomic::Ordering::*;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+
+use crate::utility::{MpscRingBuffer, OverflowPolicy};
+
+use super::cpu::{apic, paging};
+use super::cpu::idt::TLB_SHOOTDOWN_VECTOR;
+use super::instr;
+use super::mem::{PhysAddr, PAGE_SIZE};
+use super::raw::{Cr4, StackFrame};
+use super::smp::{self, MAX_CPUS};
+
+/// The number of pending invalidations that each CPU can hold.
+const QUEUE_CAPACITY: usize = 16;
+
+/// The number of pages above which a range is invalidated by flushing the whole TLB.
+const FULL_FLUSH_PAGES: usize = 32;
+
+/// An address space whose translations may be cached by the CPUs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Asid {
+    /// The upper half, shared by every address space.
+    Kernel,
+    /// The lower half of the process whose L4 table is at the provided physical address.
+    User(PhysAddr),
+}
+
+/// A range of pages whose translations must be invalidated.
+#[derive(Clone, Copy)]
+struct Invalidation {
+    asid: Asid,
+    start: usize,
+    length: usize,
+}
+
+/// The pending invalidations of each CPU, indexed like the CPU table of [`smp`].
+static QUEUES: [MpscRingBuffer<Invalidation, QUEUE_CAPACITY>; MAX_CPUS] =
+    [const { MpscRingBuffer::new(OverflowPolicy::DropNewest) }; MAX_CPUS];
+
+/// Whether each CPU must flush its whole TLB because its queue overflowed.
+static OVERFLOWED: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// The generation of the last request made to each CPU.
+static REQUESTED: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// The generation of the last request handled by each CPU.
+static COMPLETED: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// The physical address of the L4 table of the process loaded by each CPU, or 0 when the CPU
+/// does not run any process.
+static ACTIVE: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// Returns the index of the current CPU.
+///
+/// This does not rely on the `gs` base, which is the one of userspace in the interrupt handler.
+fn current_index() -> usize {
+    smp::index_of(apic::local_apic_id() as u32).unwrap_or(0)
+}
+
+/// Records the address space loaded by the current CPU.
+///
+/// `None` indicates that the CPU only runs the kernel.
+pub fn set_active(l4_table: Option<PhysAddr>) {
+    ACTIVE[current_index()].store(l4_table.map_or(0, PhysAddr::get), Release);
+}
+
+/// Returns whether the CPU with the provided index may have cached translations of `asid`.
+fn is_involved(index: usize, asid: Asid) -> bool {
+    let active = ACTIVE[index].load(Acquire);
+
+    match asid {
+        Asid::Kernel => active != 0,
+        Asid::User(l4_table) => active == l4_table.get(),
+    }
+}
+
+/// Invalidates the translations of the `start..start + length` range of `asid` on the current
+/// CPU.
+fn invalidate_local(asid: Asid, start: usize, length: usize) {
+    if length / PAGE_SIZE > FULL_FLUSH_PAGES {
+        flush_local(asid == Asid::Kernel);
+        return;
+    }
+
+    let mut page = start & !(PAGE_SIZE - 1);
+    while page < start + length {
+        instr::invlpg(page);
+        page += PAGE_SIZE;
+    }
+}
+
+/// Flushes the TLB of the current CPU.
+///
+/// When `global` is set, the global translations of the upper half are flushed as well.
+fn flush_local(global: bool) {
+    // SAFETY:
+    //  Reloading the current address space, or toggling the global pages, only invalidates the
+    //  translations cached by the CPU.
+    unsafe {
+        if global {
+            let cr4 = instr::cr4();
+            instr::set_cr4(cr4 & !Cr4::PAGE_GLOBAL.bits());
+            instr::set_cr4(cr4);
+        } else {
+            instr::set_cr3(instr::cr3() as usize);
+        }
+    }
+}
+
+/// Handles the pending invalidations of the CPU with the provided index.
+fn service(index: usize) {
+    let generation = REQUESTED[index].load(Acquire);
+
+    if OVERFLOWED[index].swap(false, AcqRel) {
+        while QUEUES[index].pop().is_some() {}
+        flush_local(true);
+    }
+
+    while let Some(invalidation) = QUEUES[index].pop() {
+        if is_involved(index, invalidation.asid) {
+            invalidate_local(invalidation.asid, invalidation.start, invalidation.length);
+        }
+    }
+
+    COMPLETED[index].fetch_max(generation, Release);
+}
+
+/// Invalidates the translations of the `start..start + length` range of `asid` on every CPU that
+/// may have cached them.
+///
+/// The function returns once every CPU involved has invalidated the range.
+pub fn flush_range(asid: Asid, start: usize, length: usize) {
+    let current = current_index();
+
+    if asid == Asid::Kernel || is_involved(current, asid) {
+        invalidate_local(asid, start, length);
+    }
+
+    let mut pending = [0; MAX_CPUS];
+
+    for index in (0..smp::count()).filter(|&i| i != current && is_involved(i, asid)) {
+        let invalidation = Invalidation {
+            asid,
+            start,
+            length,
+        };
+
+        if !QUEUES[index].push(invalidation) {
+            OVERFLOWED[index].store(true, Release);
+        }
+
+        pending[index] = REQUESTED[index].fetch_add(1, AcqRel) + 1;
+        apic::send_ipi(smp::lapic_id(index), TLB_SHOOTDOWN_VECTOR as u8);
+    }
+
+    for (index, &generation) in pending.iter().enumerate().filter(|(_, &g)| g != 0) {
+        while COMPLETED[index].load(Acquire) < generation {
+            service(current);
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Invalidates every translation of the lower half of a process on the CPUs that have loaded
+/// its address space.
+///
+/// This must be called before the page tables of the process are freed.
+pub fn flush_address_space(l4_table: PhysAddr) {
+    flush_range(Asid::User(l4_table), 0, paging::user_top());
+}
+
+/// The handler of the [`TLB_SHOOTDOWN_VECTOR`] interrupt.
+pub extern "x86-interrupt" fn shootdown_interrupt(_: StackFrame) {
+    service(current_index());
+    apic::send_eoi();
+}
@@ -0,0 +1,69 @@
+//! Invariant checks that degrade gracefully in release builds.
+//!
+//! A `debug_assert!` that does not hold in a release build is silently ignored, and the kernel
+//! proceeds with a broken invariant. The macros of this module are meant for the checks whose
+//! failure must not go unnoticed:
+//!
+//! - [`kassert!`] panics in debug builds, and logs an error in release builds before
+//!   proceeding.
+//!
+//! - [`kensure!`] panics in debug builds, and logs an error in release builds before returning
+//!   the provided value from the current function. The value is usually an error code, or the
+//!   result of terminating the process that caused the failure.
+//!
+//! Checks that are too expensive for release builds belong to [`paranoid`](crate::paranoid)
+//! instead.
+
+use core::fmt::Arguments;
+use core::panic::Location;
+
+use crate::log::{self, Level};
+
+/// Reports that the condition `cond` of a check emitted by the provided module does not hold.
+///
+/// This is used by the [`kassert!`] and [`kensure!`] macros.
+#[cold]
+#[inline(never)]
+#[track_caller]
+pub fn failed(module: &'static str, cond: &str, msg: Arguments) {
+    if cfg!(debug_assertions) {
+        panic!("assertion failed: {}: {}", cond, msg);
+    }
+
+    let location = Location::caller();
+
+    log::log(
+        Level::Error,
+        module,
+        format_args!(
+            "Assertion failed at {}:{}: {}: {}",
+            location.file(),
+            location.line(),
+            cond,
+            msg,
+        ),
+    );
+}
+
+/// Checks that a condition holds.
+///
+/// In debug builds, the kernel panics if it does not. In release builds, an error is logged and
+/// the execution continues.
+pub macro kassert($cond:expr, $($arg:tt)+) {
+    if !$cond {
+        $crate::kassert::failed(module_path!(), stringify!($cond), format_args!($($arg)+));
+    }
+}
+
+/// Checks that a condition holds, returning `$ret` from the current function if it does not.
+///
+/// In debug builds, the kernel panics if the condition does not hold. In release builds, an
+/// error is logged before returning.
+pub macro kensure($cond:expr, $ret:expr, $($arg:tt)+) {
+    if !$cond {
+        $crate::kassert::failed(module_path!(), stringify!($cond), format_args!($($arg)+));
+        // Functions that return nothing pass `()`.
+        #[allow(clippy::unused_unit)]
+        return $ret;
+    }
+}
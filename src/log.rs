@@ -1,6 +1,10 @@
 use core::fmt::Arguments;
-use core::sync::atomic::AtomicPtr;
 use core::sync::atomic::Ordering::Relaxed;
+use core::sync::atomic::{AtomicPtr, AtomicU8};
+
+use bitflags::bitflags;
+
+use crate::utility::Cmdline;
 
 /// A log level supported by the kernel.
 ///
@@ -14,10 +18,13 @@ pub enum Level {
 }
 
 /// A function that can be used to log messages.
-pub type LogFn = fn(lvl: Level, msg: Arguments);
+///
+/// `module` is the path of the module that emitted the message, without the name of the crate
+/// and of the architecture module (see [`short_module_path`]).
+pub type LogFn = fn(lvl: Level, module: &str, msg: Arguments);
 
 /// A [`LogFn`] that does nothing.
-pub fn no_op(_: Level, _: Arguments) {}
+pub fn no_op(_: Level, _: &str, _: Arguments) {}
 
 /// The global log function.
 static GLOBAL_LOG_FN: AtomicPtr<()> = AtomicPtr::new(no_op as *mut ());
@@ -36,30 +43,344 @@ pub fn get_global_log_fn() -> LogFn {
     unsafe { core::mem::transmute(GLOBAL_LOG_FN.load(Relaxed)) }
 }
 
+bitflags! {
+    /// The information written before every log record.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Prefix: u8 {
+        /// The time elapsed since boot, formatted as `[secs.micros]`.
+        const TIMESTAMP = 1 << 0;
+        /// The ID of the CPU that emitted the record, formatted as `[cpuN]`.
+        const CPU = 1 << 1;
+    }
+}
+
+impl Prefix {
+    /// Reads the prefix from the `log.prefix` option of the provided command line.
+    ///
+    /// The option is a comma-separated list of `time` and `cpu`, or `none`. Unknown items are
+    /// reported and ignored. When the option is absent, both the timestamp and the CPU are
+    /// written.
+    pub fn from_cmdline(cmdline: Cmdline) -> Self {
+        let Some(value) = cmdline.get(b"log.prefix") else {
+            return Self::all();
+        };
+
+        let mut prefix = Self::empty();
+
+        for item in value.split(|&b| b == b',').filter(|item| !item.is_empty()) {
+            match item {
+                b"none" => (),
+                b"time" => prefix.insert(Self::TIMESTAMP),
+                b"cpu" => prefix.insert(Self::CPU),
+                other => warn!(
+                    "Unknown `log.prefix` item: `{}`.",
+                    core::str::from_utf8(other).unwrap_or("<invalid UTF-8>")
+                ),
+            }
+        }
+
+        prefix
+    }
+}
+
+/// The prefix written before every log record.
+static PREFIX: AtomicU8 = AtomicU8::new(Prefix::all().bits());
+
+/// Sets the prefix written before every log record.
+#[inline(always)]
+pub fn set_prefix(prefix: Prefix) {
+    PREFIX.store(prefix.bits(), Relaxed);
+}
+
+/// Returns the prefix written before every log record.
+#[inline(always)]
+pub fn prefix() -> Prefix {
+    Prefix::from_bits_truncate(PREFIX.load(Relaxed))
+}
+
+/// The minimum level of the messages that get through a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LevelFilter {
+    Trace,
+    Info,
+    Warn,
+    Error,
+    /// No message gets through.
+    Off,
+}
+
+impl LevelFilter {
+    /// Parses a level filter from its name (`trace`, `info`, `warn`, `error` or `off`).
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"trace" => Some(Self::Trace),
+            b"info" => Some(Self::Info),
+            b"warn" => Some(Self::Warn),
+            b"error" => Some(Self::Error),
+            b"off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+
+    /// Converts the raw value used by the `set_log_filter` system call into a [`LevelFilter`].
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Trace),
+            1 => Some(Self::Info),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Error),
+            4 => Some(Self::Off),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a message of the provided level gets through this filter.
+    #[inline(always)]
+    pub fn allows(self, lvl: Level) -> bool {
+        lvl as u8 >= self as u8
+    }
+}
+
+/// The maximum number of per-module filters.
+pub const MAX_FILTERS: usize = 16;
+
+/// The maximum length of the name of a module in a filter.
+pub const MAX_FILTER_NAME_LENGTH: usize = 32;
+
+/// A filter that applies to the messages of a module and of its submodules.
+#[derive(Clone, Copy)]
+struct Filter {
+    name: [u8; MAX_FILTER_NAME_LENGTH],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl Filter {
+    /// An unused filter.
+    const EMPTY: Self = Self {
+        name: [0; MAX_FILTER_NAME_LENGTH],
+        len: 0,
+        level: LevelFilter::Trace,
+    };
+
+    /// Returns the name of the module the filter applies to.
+    #[inline(always)]
+    fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+}
+
+/// The per-module filters. Only the first `FILTER_COUNT` are used.
+static mut FILTERS: [Filter; MAX_FILTERS] = [Filter::EMPTY; MAX_FILTERS];
+
+/// The number of per-module filters in use.
+static mut FILTER_COUNT: usize = 0;
+
+/// The filter applied to the modules that have no filter of their own.
+static mut DEFAULT_FILTER: LevelFilter = LevelFilter::Trace;
+
+/// An error that might occur when setting a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetFilterError {
+    /// [`MAX_FILTERS`] filters are already in use.
+    TableFull,
+    /// The name of the module is longer than [`MAX_FILTER_NAME_LENGTH`].
+    NameTooLong,
+}
+
+/// Sets the filter applied to the messages of `module` and of its submodules.
+///
+/// An empty `module` sets the filter applied to the modules that have no filter of their own.
+/// Module names are relative paths, such as `cpu::paging` or `paging`, and match any module
+/// whose path contains them (see [`short_module_path`]).
+///
+/// # Errors
+///
+/// If [`MAX_FILTERS`] filters are already in use, or if the name of the module is longer than
+/// [`MAX_FILTER_NAME_LENGTH`], an error is returned.
+pub fn set_filter(module: &[u8], level: LevelFilter) -> Result<(), SetFilterError> {
+    // SAFETY:
+    //  The filters are never modified concurrently: this function is only called during boot,
+    //  and by system calls, which run with interrupts disabled.
+    unsafe {
+        if module.is_empty() {
+            DEFAULT_FILTER = level;
+            return Ok(());
+        }
+
+        if module.len() > MAX_FILTER_NAME_LENGTH {
+            return Err(SetFilterError::NameTooLong);
+        }
+
+        let filters = &mut FILTERS[..FILTER_COUNT];
+        if let Some(filter) = filters.iter_mut().find(|f| f.name() == module) {
+            filter.level = level;
+            return Ok(());
+        }
+
+        if FILTER_COUNT == MAX_FILTERS {
+            return Err(SetFilterError::TableFull);
+        }
+
+        let filter = &mut FILTERS[FILTER_COUNT];
+        filter.name[..module.len()].copy_from_slice(module);
+        filter.len = module.len();
+        filter.level = level;
+        FILTER_COUNT += 1;
+    }
+
+    Ok(())
+}
+
+/// Returns whether `path` is `name`, or a submodule of a module named `name`.
+///
+/// `name` may start anywhere in `path`, as long as it starts and ends on a `::` boundary.
+fn module_matches(path: &[u8], name: &[u8]) -> bool {
+    let mut start = 0;
+
+    loop {
+        let rest = &path[start..];
+        if rest.starts_with(name)
+            && (rest.len() == name.len() || rest[name.len()..].starts_with(b"::"))
+        {
+            return true;
+        }
+
+        match rest.windows(2).position(|w| w == b"::") {
+            Some(pos) => start += pos + 2,
+            None => return false,
+        }
+    }
+}
+
+/// Returns whether a message of the provided level, emitted by the provided module, gets through
+/// the filters.
+///
+/// The filter with the longest matching name applies.
+pub fn enabled(lvl: Level, module: &str) -> bool {
+    // SAFETY:
+    //  The filters are never modified concurrently.
+    let (filters, default) = unsafe { (&FILTERS[..FILTER_COUNT], DEFAULT_FILTER) };
+
+    let level = filters
+        .iter()
+        .filter(|f| module_matches(module.as_bytes(), f.name()))
+        .max_by_key(|f| f.len)
+        .map_or(default, |f| f.level);
+
+    level.allows(lvl)
+}
+
+/// Reads the filters from the `log.filter` option of the provided command line.
+///
+/// The option is a comma-separated list of `module=level` items, where `level` is one of the
+/// names accepted by [`LevelFilter::from_name`]. An item without a module sets the filter of
+/// the modules that have no filter of their own. For example, `log.filter=info,paging=warn`
+/// hides the trace messages of the kernel, and the info messages of the paging module. Invalid
+/// items are reported and ignored.
+pub fn set_filters_from_cmdline(cmdline: Cmdline) {
+    let Some(value) = cmdline.get(b"log.filter") else {
+        return;
+    };
+
+    for item in value.split(|&b| b == b',').filter(|item| !item.is_empty()) {
+        let (module, level) = match item.iter().position(|&b| b == b'=') {
+            Some(pos) => (&item[..pos], &item[pos + 1..]),
+            None => (&b""[..], item),
+        };
+
+        let result = match LevelFilter::from_name(level) {
+            Some(level) => set_filter(module, level).map_err(|err| match err {
+                SetFilterError::TableFull => "too many filters",
+                SetFilterError::NameTooLong => "module name too long",
+            }),
+            None => Err("unknown level"),
+        };
+
+        if let Err(reason) = result {
+            warn!(
+                "Invalid `log.filter` item: `{}` ({}).",
+                core::str::from_utf8(item).unwrap_or("<invalid UTF-8>"),
+                reason,
+            );
+        }
+    }
+}
+
+/// Strips the name of the crate and of the architecture module from a module path.
+///
+/// For example, `fabric::x86_64::cpu::paging` becomes `cpu::paging`.
+pub fn short_module_path(path: &str) -> &str {
+    let path = path.strip_prefix("fabric").unwrap_or(path);
+    let path = path.strip_prefix("::").unwrap_or(path);
+    let path = path.strip_prefix("x86_64").unwrap_or(path);
+    path.strip_prefix("::").unwrap_or(path)
+}
+
+/// Logs a message emitted by the provided module, if it gets through the filters.
+///
+/// This is used by the logging macros.
+#[inline]
+pub fn log(lvl: Level, module: &'static str, msg: Arguments) {
+    let module = short_module_path(module);
+    if enabled(lvl, module) {
+        get_global_log_fn()(lvl, module, msg);
+    }
+}
+
 /// Logs a message with the [`Level::Trace`] log level.
 pub macro trace {
     ($($arg:tt)*) => {
-        $crate::log::get_global_log_fn()($crate::log::Level::Trace, format_args!($($arg)*))
+        $crate::log::log($crate::log::Level::Trace, module_path!(), format_args!($($arg)*))
     }
 }
 
 /// Logs a message with the [`Level::Info`] log level.
 pub macro info {
     ($($arg:tt)*) => {
-        $crate::log::get_global_log_fn()($crate::log::Level::Info, format_args!($($arg)*))
+        $crate::log::log($crate::log::Level::Info, module_path!(), format_args!($($arg)*))
     }
 }
 
 /// Logs a message with the [`Level::Warn`] log level.
 pub macro warn {
     ($($arg:tt)*) => {
-        $crate::log::get_global_log_fn()($crate::log::Level::Warn, format_args!($($arg)*))
+        $crate::log::log($crate::log::Level::Warn, module_path!(), format_args!($($arg)*))
     }
 }
 
 /// Logs a message with the [`Level::Error`] log level.
 pub macro error {
     ($($arg:tt)*) => {
-        $crate::log::get_global_log_fn()($crate::log::Level::Error, format_args!($($arg)*))
+        $crate::log::log($crate::log::Level::Error, module_path!(), format_args!($($arg)*))
     }
 }
+
+/// The number of messages that a call site of [`warn_ratelimited!`] may log in a burst.
+pub const RATELIMIT_BURST: u32 = 10;
+
+/// The number of microseconds after which a call site of [`warn_ratelimited!`] may log one more
+/// message.
+pub const RATELIMIT_INTERVAL_US: u64 = 1_000_000;
+
+/// Logs a message with the [`Level::Warn`] log level, unless the call site has logged too many
+/// messages recently.
+///
+/// Each call site has its own [`RateLimiter`](crate::utility::RateLimiter). When messages have
+/// been suppressed, their number is reported before the next message that gets through.
+pub macro warn_ratelimited {
+    ($($arg:tt)*) => {{
+        static LIMITER: $crate::utility::RateLimiter = $crate::utility::RateLimiter::new(
+            $crate::log::RATELIMIT_BURST,
+            $crate::log::RATELIMIT_INTERVAL_US,
+        );
+
+        if let Some(suppressed) = LIMITER.check($crate::uptime_us()) {
+            if suppressed != 0 {
+                $crate::log::warn!("{} similar message(s) suppressed.", suppressed);
+            }
+
+            $crate::log::warn!($($arg)*);
+        }
+    }}
+}
@@ -24,10 +24,16 @@
 //! Fabric currently only have support for the **x86_64** architecture. Documentation specific for
 //! this architecture can be found in the [`x86_64`] module.
 //!
+//! ## Tests
+//!
+//! Unit tests do not touch the hardware, and run on the host with `cargo test`. The properties
+//! that can only be checked on a running system are covered by the self-tests of the kernel,
+//! enabled by the `ktest` feature.
+//!
 //! [1]: https://en.wikipedia.org/wiki/Exokernel
 
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 //
 #![deny(unsafe_op_in_unsafe_fn)]
 //
@@ -39,7 +45,9 @@
 #![feature(pointer_byte_offsets)]
 
 mod builtins;
+mod kassert;
 mod log;
+mod paranoid;
 mod utility;
 
 #[path = "arch/x86_64/mod.rs"]
@@ -53,6 +61,41 @@ fn die() -> ! {
     self::x86_64::die();
 }
 
+/// Returns the number of microseconds elapsed since boot.
+#[inline(always)]
+fn uptime_us() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    self::x86_64::uptime_us()
+}
+
+/// Writes a backtrace of the current call stack to the log.
+#[inline(always)]
+fn print_backtrace() {
+    #[cfg(target_arch = "x86_64")]
+    self::x86_64::print_backtrace();
+}
+
+/// Writes the state of the current CPU to the log.
+#[inline(always)]
+fn print_machine_state() {
+    #[cfg(target_arch = "x86_64")]
+    self::x86_64::print_machine_state();
+}
+
+/// Returns the build ID of the kernel.
+#[inline(always)]
+fn build_id() -> &'static impl core::fmt::Display {
+    #[cfg(target_arch = "x86_64")]
+    self::x86_64::build_id()
+}
+
+/// Reports a kernel panic to the emulator the kernel is running under, if any.
+#[inline(always)]
+fn report_panic_to_emulator(info: &core::panic::PanicInfo) {
+    #[cfg(target_arch = "x86_64")]
+    self::x86_64::report_panic_to_emulator(info);
+}
+
 /// This function is called when something goes wrong in the kernel.
 ///
 /// This should *never* happen, and is if the control flow ever goes through this function, it
@@ -60,7 +103,8 @@ fn die() -> ! {
 ///
 /// When an expected error occurs, but the kernel cannot recover from it, the kernel should *not*
 /// panic, and instead hang or reboot the machine.
-#[panic_handler]
+#[cfg_attr(not(test), panic_handler)]
+#[cfg_attr(test, allow(dead_code))]
 fn bug(info: &core::panic::PanicInfo) -> ! {
     log::error!("KERNEL PANIC!");
     log::error!("");
@@ -83,6 +127,11 @@ fn bug(info: &core::panic::PanicInfo) -> ! {
         ),
         None => log::error!("   > Location = <no location>"),
     }
+    log::error!("   > Build    = {}", build_id());
+    log::error!("   > Machine State:");
+    print_machine_state();
+    print_backtrace();
+    report_panic_to_emulator(info);
 
     die();
 }
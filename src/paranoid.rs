@@ -0,0 +1,82 @@
+//! Expensive invariant checks, enabled by the `paranoid` feature.
+//!
+//! Those checks are too slow to be part of regular builds, even debug ones, but are useful in CI
+//! and when hunting bugs. When the feature is disabled, they compile to nothing.
+//!
+//! # Checks
+//!
+//! - Page tables are walked after every mapping and unmapping of a 4 KiB page, to make sure that
+//!   the change is visible.
+//!
+//! - The memory tracker keeps a bitmap of the pages it considers free, catching double frees and
+//!   allocations of pages that are not free.
+//!
+//! - The bottom of the kernel stack is filled with a canary, which is verified on every
+//!   scheduler tick.
+//!
+//! - Locks have a level, and may only be acquired while all the locks held by the current
+//!   execution context have a lower level. This catches lock-order inversions, as well as
+//!   recursive locking, which would otherwise silently deadlock the kernel.
+
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::Relaxed;
+
+/// Whether the `paranoid` feature is enabled.
+pub const ENABLED: bool = cfg!(feature = "paranoid");
+
+/// Panics with the provided message if the `paranoid` feature is enabled and the condition does
+/// not hold.
+///
+/// When the feature is disabled, the condition is not evaluated.
+pub macro check($cond:expr, $($arg:tt)+) {
+    if $crate::paranoid::ENABLED && !$cond {
+        panic!("paranoid check failed: {}", format_args!($($arg)+));
+    }
+}
+
+/// The level of a lock.
+///
+/// A lock may only be acquired while all the locks held by the current execution context have a
+/// strictly lower level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum LockLevel {
+    /// The lock of the global memory tracker.
+    MemoryTracker,
+}
+
+/// The set of the levels of the locks currently held, one bit per level.
+///
+/// The kernel runs on a single CPU, so a global is enough to track the locks of the current
+/// execution context.
+static HELD_LOCKS: AtomicU32 = AtomicU32::new(0);
+
+/// Records that a lock of the provided level is about to be acquired.
+///
+/// This must be called *before* spinning on the lock, so that recursive locking is reported
+/// rather than deadlocking.
+#[inline(always)]
+pub fn before_lock(level: LockLevel) {
+    if !ENABLED {
+        return;
+    }
+
+    let held = HELD_LOCKS.fetch_or(1 << level as u32, Relaxed);
+
+    check!(
+        held >> level as u32 == 0,
+        "lock {:?} acquired while holding locks {:#b}",
+        level,
+        held,
+    );
+}
+
+/// Records that a lock of the provided level has been released.
+#[inline(always)]
+pub fn after_unlock(level: LockLevel) {
+    if !ENABLED {
+        return;
+    }
+
+    HELD_LOCKS.fetch_and(!(1 << level as u32), Relaxed);
+}
@@ -0,0 +1,43 @@
+/// A command line passed to the kernel by the bootloader.
+///
+/// The command line is a list of options separated by whitespaces. Each option is either a
+/// simple flag (`name`) or a key-value pair (`name=value`).
+#[derive(Clone, Copy)]
+pub struct Cmdline<'a>(&'a [u8]);
+
+impl<'a> Cmdline<'a> {
+    /// Creates a new [`Cmdline`] from the provided raw bytes.
+    #[inline(always)]
+    pub const fn new(raw: &'a [u8]) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the raw bytes of the command line.
+    #[inline(always)]
+    pub const fn as_bytes(self) -> &'a [u8] {
+        self.0
+    }
+
+    /// Returns an iterator over the options of the command line.
+    ///
+    /// Each option is returned as a `(name, value)` pair. Flags have no value.
+    pub fn options(self) -> impl Iterator<Item = (&'a [u8], Option<&'a [u8]>)> {
+        self.0
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|opt| !opt.is_empty())
+            .map(|opt| match opt.iter().position(|&b| b == b'=') {
+                Some(eq) => (&opt[..eq], Some(&opt[eq + 1..])),
+                None => (opt, None),
+            })
+    }
+
+    /// Returns the value of the last option with the provided name.
+    ///
+    /// Flags are reported as an empty value.
+    pub fn get(self, name: &[u8]) -> Option<&'a [u8]> {
+        self.options()
+            .filter(|(n, _)| *n == name)
+            .last()
+            .map(|(_, value)| value.unwrap_or(b""))
+    }
+}
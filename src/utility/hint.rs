@@ -0,0 +1,10 @@
+//! Hints given to the compiler about the branches that are unlikely to be taken.
+//!
+//! The intrinsics of the standard library that serve this purpose are unstable. Instead, the
+//! unlikely side of a branch calls a function marked as `#[cold]`, which makes the compiler move
+//! it out of the straight-line code.
+
+/// Marks the code path that calls this function as unlikely to be executed.
+#[cold]
+#[inline(always)]
+pub fn cold_path() {}
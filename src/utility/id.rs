@@ -0,0 +1,151 @@
+//! Allocation of identifiers that are not reused right away.
+//!
+//! Objects such as processes and ports live in fixed-size tables, and are designated by an ID
+//! that userspace keeps around. Deriving the ID from the index of the slot alone would make a
+//! stale ID designate whatever object reuses the slot next. [`IdAllocator`] embeds a generation
+//! counter in the IDs it hands out:
+//!
+//! ```text
+//! 63                              16 15              0
+//! +---------------------------------+----------------+
+//! |           generation            |   index + 1    |
+//! +---------------------------------+----------------+
+//! ```
+//!
+//! The generation of a slot is incremented every time its ID is released, which invalidates every
+//! copy of the previous ID. The first ID handed out for a slot has a generation of 0, so the
+//! first IDs are the same as if they were derived from the index alone.
+//!
+//! Released slots are reused in the order in which they were released, which maximizes the time
+//! before a slot, and thus its next generation, is reused. A slot whose generation would
+//! overflow is retired for good.
+
+use core::num::NonZeroUsize;
+
+/// The number of bits of an ID that hold the index of its slot.
+const INDEX_BITS: u32 = 16;
+
+/// The mask of the bits of an ID that hold the index of its slot.
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// The largest generation that fits in an ID.
+const MAX_GENERATION: usize = usize::MAX >> INDEX_BITS;
+
+/// The state of a slot of an [`IdAllocator`].
+#[derive(Clone, Copy)]
+struct Slot {
+    /// The generation of the current, or next, ID of the slot.
+    generation: usize,
+    /// Whether the ID of the slot is currently allocated.
+    allocated: bool,
+}
+
+/// Allocates IDs for the `N` slots of a table. See the [module documentation](self).
+pub struct IdAllocator<const N: usize> {
+    slots: [Slot; N],
+    /// The indices of the free slots, in the order in which they were released. Only the `len`
+    /// elements starting at `head` (wrapping around) are used.
+    free: [u16; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> IdAllocator<N> {
+    /// Checks that `N` is non-zero, and that the index of every slot fits in an ID.
+    const VALID_CAPACITY: () = assert!(
+        N != 0 && N < INDEX_MASK,
+        "the capacity of an ID allocator must be non-zero and fit in the index bits of an ID"
+    );
+
+    /// Creates a new [`IdAllocator`] whose slots are all free.
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_CAPACITY;
+
+        let mut free = [0; N];
+        let mut index = 0;
+        while index < N {
+            free[index] = index as u16;
+            index += 1;
+        }
+
+        Self {
+            slots: [Slot {
+                generation: 0,
+                allocated: false,
+            }; N],
+            free,
+            head: 0,
+            len: N,
+        }
+    }
+
+    /// Builds the ID of the slot with the provided index.
+    fn make_id(&self, index: usize) -> NonZeroUsize {
+        let id = (self.slots[index].generation << INDEX_BITS) | (index + 1);
+
+        // SAFETY:
+        //  `index + 1` is never zero.
+        unsafe { NonZeroUsize::new_unchecked(id) }
+    }
+
+    /// Allocates a new ID.
+    ///
+    /// # Returns
+    ///
+    /// The ID is returned along with the index of its slot, or `None` if every slot is in use or
+    /// retired.
+    pub fn allocate(&mut self) -> Option<(NonZeroUsize, usize)> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let index = self.free[self.head] as usize;
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+
+        self.slots[index].allocated = true;
+        Some((self.make_id(index), index))
+    }
+
+    /// Returns the index of the slot of the provided ID.
+    ///
+    /// `None` is returned if the ID is not currently allocated, including when it has been
+    /// released and its slot reused since.
+    pub fn index_of(&self, id: NonZeroUsize) -> Option<usize> {
+        let index = (id.get() & INDEX_MASK).checked_sub(1)?;
+        let slot = self.slots.get(index)?;
+
+        (slot.allocated && slot.generation == id.get() >> INDEX_BITS).then_some(index)
+    }
+
+    /// Returns the ID currently allocated for the slot with the provided index, if any.
+    pub fn id_at(&self, index: usize) -> Option<NonZeroUsize> {
+        let slot = self.slots.get(index)?;
+        slot.allocated.then(|| self.make_id(index))
+    }
+
+    /// Releases the provided ID, making its slot available to a future ID.
+    ///
+    /// # Returns
+    ///
+    /// The index of the slot of the ID is returned, or `None` if the ID was not allocated.
+    pub fn release(&mut self, id: NonZeroUsize) -> Option<usize> {
+        let index = self.index_of(id)?;
+        let slot = &mut self.slots[index];
+
+        slot.allocated = false;
+
+        // A slot whose generation cannot be incremented anymore is never reused: its next ID
+        // would be one that has already been handed out.
+        if slot.generation == MAX_GENERATION {
+            return Some(index);
+        }
+        slot.generation += 1;
+
+        self.free[(self.head + self.len) % N] = index as u16;
+        self.len += 1;
+
+        Some(index)
+    }
+}
@@ -0,0 +1,118 @@
+//! Access to memory-mapped registers.
+//!
+//! Device registers must be accessed with volatile operations: the compiler may otherwise merge,
+//! elide or reorder the accesses, which all have side effects on the device. An [`MmioRegion`]
+//! is the mapping of a block of registers, and a [`Register`] is the typed offset of one of them
+//! within the block. Drivers declare their registers as constants and never handle raw pointers.
+//!
+//! Volatile accesses are never reordered with respect to each other. The memory that holds the
+//! registers is expected to be mapped as uncacheable, which makes the CPU perform them in
+//! program order as well.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+/// A register of type `T` at a fixed offset of an [`MmioRegion`].
+pub struct Register<T> {
+    /// The offset of the register from the start of the region, in bytes.
+    offset: usize,
+    ty: PhantomData<fn() -> T>,
+}
+
+impl<T> Register<T> {
+    /// Creates a new [`Register`] at the provided offset.
+    ///
+    /// The offset must be aligned to the size of `T`. This is checked at compile time when the
+    /// register is a constant.
+    #[inline(always)]
+    pub const fn new(offset: usize) -> Self {
+        assert!(
+            offset % size_of::<T>() == 0,
+            "misaligned memory-mapped register"
+        );
+
+        Self {
+            offset,
+            ty: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Register<T> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Register<T> {}
+
+impl<T> fmt::Debug for Register<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Register({:#x})", self.offset)
+    }
+}
+
+/// A block of memory-mapped registers.
+#[derive(Debug, Clone, Copy)]
+pub struct MmioRegion {
+    /// The virtual address of the first register of the region.
+    base: *mut u8,
+    /// The size of the region, in bytes.
+    length: usize,
+}
+
+impl MmioRegion {
+    /// Creates a new [`MmioRegion`] of `length` bytes starting at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base..base + length` must remain mapped to the registers of a device for as long as the
+    /// region is used. Accessing any register of the device must not break the invariants of
+    /// the kernel (for example, by making the device write to arbitrary memory): reading and
+    /// writing registers is safe.
+    #[inline(always)]
+    pub const unsafe fn new(base: *mut u8, length: usize) -> Self {
+        Self { base, length }
+    }
+
+    /// Returns a pointer to the provided register.
+    #[inline(always)]
+    fn ptr<T>(self, register: Register<T>) -> *mut T {
+        debug_assert!(
+            register.offset + size_of::<T>() <= self.length,
+            "{:?} is outside of the region ({:#x} bytes)",
+            register,
+            self.length,
+        );
+
+        // SAFETY:
+        //  The register is part of the region, which is mapped.
+        unsafe { self.base.add(register.offset).cast() }
+    }
+
+    /// Reads the provided register.
+    #[inline(always)]
+    pub fn read<T: Copy>(self, register: Register<T>) -> T {
+        // SAFETY:
+        //  The creator of the region guarantees that the registers are mapped.
+        unsafe { self.ptr(register).read_volatile() }
+    }
+
+    /// Writes `value` to the provided register.
+    #[inline(always)]
+    pub fn write<T: Copy>(self, register: Register<T>, value: T) {
+        // SAFETY:
+        //  The creator of the region guarantees that the registers are mapped.
+        unsafe { self.ptr(register).write_volatile(value) }
+    }
+
+    /// Reads the provided register, and writes back the value returned by `f`.
+    ///
+    /// This is not atomic: the device may modify the register between the two accesses.
+    #[inline(always)]
+    pub fn modify<T: Copy>(self, register: Register<T>, f: impl FnOnce(T) -> T) {
+        self.write(register, f(self.read(register)));
+    }
+}
@@ -1,25 +1,23 @@
 //! Provides miscellaneous utility functions and types.
 
+mod cmdline;
 mod epoch_mutex;
 mod fmt;
+mod hint;
+mod id;
+mod mmio;
+mod once;
+mod rate_limit;
+mod ring;
 
+pub mod num;
+
+pub use self::cmdline::*;
 pub use self::epoch_mutex::*;
 pub use self::fmt::*;
-
-/// Aligns the given value to the next page boundary (4 KiB).
-#[inline(always)]
-pub fn align_page_up(x: usize) -> usize {
-    #[cfg(target_arch = "x86_64")]
-    {
-        (x + 0xfff) & !0xfff
-    }
-}
-
-/// Aligns the given value to the previous page boundary (4 KiB).
-#[inline(always)]
-pub fn align_page_down(x: usize) -> usize {
-    #[cfg(target_arch = "x86_64")]
-    {
-        x & !0xfff
-    }
-}
+pub use self::hint::*;
+pub use self::id::*;
+pub use self::mmio::*;
+pub use self::once::*;
+pub use self::rate_limit::*;
+pub use self::ring::*;
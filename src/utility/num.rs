@@ -0,0 +1,98 @@
+//! Checked integer arithmetic for addresses, lengths and alignments.
+//!
+//! Most of the values manipulated by system call handlers come from userland and may be
+//! anything. The functions of this module never overflow silently: when the result cannot be
+//! represented, `None` is returned instead.
+
+#[cfg(target_arch = "x86_64")]
+use crate::x86_64::PAGE_SIZE;
+
+/// Returns whether `x` is a multiple of `align`.
+///
+/// `align` must be a power of two.
+#[inline(always)]
+pub const fn is_aligned(x: usize, align: usize) -> bool {
+    debug_assert!(align.is_power_of_two());
+    x & (align - 1) == 0
+}
+
+/// Aligns `x` to the next multiple of `align`.
+///
+/// `align` must be a power of two.
+///
+/// # Returns
+///
+/// `None` is returned if the result does not fit in a `usize`.
+#[inline(always)]
+pub const fn align_up(x: usize, align: usize) -> Option<usize> {
+    debug_assert!(align.is_power_of_two());
+    match x.checked_add(align - 1) {
+        Some(x) => Some(x & !(align - 1)),
+        None => None,
+    }
+}
+
+/// Aligns `x` to the previous multiple of `align`.
+///
+/// `align` must be a power of two. This function cannot overflow.
+#[inline(always)]
+pub const fn align_down(x: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two());
+    x & !(align - 1)
+}
+
+/// Returns the end of the range that starts at `base` and is `length` bytes long.
+///
+/// # Returns
+///
+/// `None` is returned if the end of the range does not fit in a `usize`.
+#[inline(always)]
+pub const fn range_end(base: usize, length: usize) -> Option<usize> {
+    base.checked_add(length)
+}
+
+/// Returns whether the range that starts at `base` and is `length` bytes long ends at or before
+/// `limit`.
+///
+/// A range whose end does not fit in a `usize` is never within `limit`.
+#[inline(always)]
+pub const fn range_within(base: usize, length: usize, limit: usize) -> bool {
+    match range_end(base, length) {
+        Some(end) => end <= limit,
+        None => false,
+    }
+}
+
+/// Returns whether `x` is aligned to a page boundary.
+#[inline(always)]
+pub const fn is_page_aligned(x: usize) -> bool {
+    is_aligned(x, PAGE_SIZE)
+}
+
+/// Aligns `x` to the next page boundary.
+///
+/// # Returns
+///
+/// `None` is returned if the result does not fit in a `usize`.
+#[inline(always)]
+pub const fn page_align_up(x: usize) -> Option<usize> {
+    align_up(x, PAGE_SIZE)
+}
+
+/// Aligns `x` to the previous page boundary.
+#[inline(always)]
+pub const fn page_align_down(x: usize) -> usize {
+    align_down(x, PAGE_SIZE)
+}
+
+/// Returns the offset of `x` within its page.
+#[inline(always)]
+pub const fn page_offset(x: usize) -> usize {
+    x & (PAGE_SIZE - 1)
+}
+
+/// Returns the number of pages needed to hold `bytes` bytes.
+#[inline(always)]
+pub const fn page_count(bytes: usize) -> usize {
+    bytes.div_ceil(PAGE_SIZE)
+}
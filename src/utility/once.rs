@@ -0,0 +1,81 @@
+//! Guards against initialization functions being called more than once.
+
+use core::sync::atomic::Ordering::AcqRel;
+use core::sync::atomic::{AtomicBool, AtomicU64};
+
+use crate::log;
+
+/// Enforces that an initialization function only runs once.
+///
+/// Initialization functions are `unsafe` and document that they must only be called once, but
+/// nothing prevents a second call, which would silently reset the state they set up. The guard
+/// catches such a call: it panics in debug builds, and logs an error and asks the function to do
+/// nothing in release builds.
+pub struct InitGuard(AtomicBool);
+
+impl InitGuard {
+    /// Creates a new [`InitGuard`] for a function that has not run yet.
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Records that the initialization function `name` is running.
+    ///
+    /// # Returns
+    ///
+    /// This function returns whether the initialization function may proceed. `false` is only
+    /// returned in release builds, when the function has already run.
+    #[track_caller]
+    pub fn enter(&self, name: &str) -> bool {
+        if !self.0.swap(true, AcqRel) {
+            return true;
+        }
+
+        reentered(name, None)
+    }
+}
+
+/// Enforces that an initialization function only runs once per CPU.
+///
+/// CPUs are identified by a number below 256, such as the ID of their local APIC. See
+/// [`InitGuard`].
+pub struct PerCpuInitGuard([AtomicU64; 4]);
+
+impl PerCpuInitGuard {
+    /// Creates a new [`PerCpuInitGuard`] for a function that has not run on any CPU yet.
+    pub const fn new() -> Self {
+        Self([const { AtomicU64::new(0) }; 4])
+    }
+
+    /// Records that the initialization function `name` is running on the CPU identified by
+    /// `cpu`.
+    ///
+    /// # Returns
+    ///
+    /// This function returns whether the initialization function may proceed. `false` is only
+    /// returned in release builds, when the function has already run on that CPU.
+    #[track_caller]
+    pub fn enter(&self, cpu: u8, name: &str) -> bool {
+        let bit = 1 << (cpu % 64);
+
+        if self.0[cpu as usize / 64].fetch_or(bit, AcqRel) & bit == 0 {
+            return true;
+        }
+
+        reentered(name, Some(cpu))
+    }
+}
+
+/// Reports that the initialization function `name` has been called again.
+#[cold]
+#[track_caller]
+fn reentered(name: &str, cpu: Option<u8>) -> bool {
+    match cpu {
+        Some(cpu) if cfg!(debug_assertions) => panic!("`{}` called twice on CPU {}", name, cpu),
+        None if cfg!(debug_assertions) => panic!("`{}` called twice", name),
+        Some(cpu) => log::error!("`{}` called twice on CPU {}. Ignoring.", name, cpu),
+        None => log::error!("`{}` called twice. Ignoring.", name),
+    }
+
+    false
+}
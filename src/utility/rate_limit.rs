@@ -0,0 +1,62 @@
+use core::sync::atomic::Ordering::Relaxed;
+use core::sync::atomic::{AtomicU32, AtomicU64};
+
+/// A token bucket limiting how often an event may happen.
+///
+/// The bucket holds up to `burst` tokens, and is refilled with one token every `interval_us`
+/// microseconds. Every time the event happens, a token is consumed. When the bucket is empty,
+/// the event is suppressed.
+///
+/// This is used by [`warn_ratelimited!`](crate::log::warn_ratelimited) to prevent a misbehaving
+/// process from flooding the log.
+pub struct RateLimiter {
+    /// The maximum number of tokens in the bucket.
+    burst: u32,
+    /// The number of microseconds it takes to refill a single token.
+    interval_us: u64,
+    /// The number of tokens currently in the bucket.
+    tokens: AtomicU32,
+    /// The time at which the bucket was last refilled, in microseconds.
+    last_refill: AtomicU64,
+    /// The number of events suppressed since the last one that was allowed.
+    suppressed: AtomicU32,
+}
+
+impl RateLimiter {
+    /// Creates a new [`RateLimiter`] with a full bucket.
+    pub const fn new(burst: u32, interval_us: u64) -> Self {
+        Self {
+            burst,
+            interval_us,
+            tokens: AtomicU32::new(burst),
+            last_refill: AtomicU64::new(0),
+            suppressed: AtomicU32::new(0),
+        }
+    }
+
+    /// Records an event that happened at `now_us`.
+    ///
+    /// # Returns
+    ///
+    /// If the event is allowed, the number of events that were suppressed since the last
+    /// allowed one is returned. Otherwise, `None` is returned.
+    pub fn check(&self, now_us: u64) -> Option<u32> {
+        let elapsed = now_us.saturating_sub(self.last_refill.load(Relaxed));
+        let refill = elapsed / self.interval_us;
+
+        if refill != 0 {
+            let tokens = (self.tokens.load(Relaxed) as u64 + refill).min(self.burst as u64);
+            self.tokens.store(tokens as u32, Relaxed);
+            self.last_refill.store(now_us, Relaxed);
+        }
+
+        let tokens = self.tokens.load(Relaxed);
+        if tokens == 0 {
+            self.suppressed.fetch_add(1, Relaxed);
+            return None;
+        }
+
+        self.tokens.store(tokens - 1, Relaxed);
+        Some(self.suppressed.swap(0, Relaxed))
+    }
+}
@@ -0,0 +1,546 @@
+//! Fixed-capacity ring buffers.
+//!
+//! - [`RingBuffer`] is a plain ring buffer, accessed through exclusive references.
+//!
+//! - [`SpscRingBuffer`] is a lock-free ring buffer with a single producer and a single consumer,
+//!   such as an interrupt handler feeding a system call.
+//!
+//! - [`MpscRingBuffer`] is a [`RingBuffer`] protected by a lock, which any number of producers
+//!   may push to.
+//!
+//! The capacity of the buffers must be a power of two, which allows indices to be wrapped with a
+//! mask. This is checked at compile time.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::*;
+
+use super::RawEpochMutex;
+
+/// What a ring buffer does when an element is pushed while it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The new element is rejected.
+    DropNewest,
+    /// The oldest element is removed to make room for the new one.
+    DropOldest,
+}
+
+/// Uninitialized storage for `N` elements of type `T`.
+struct Slots<T, const N: usize>(UnsafeCell<MaybeUninit<[T; N]>>);
+
+impl<T, const N: usize> Slots<T, N> {
+    /// Checks that `N` is a non-zero power of two.
+    const VALID_CAPACITY: () = assert!(
+        N.is_power_of_two(),
+        "the capacity of a ring buffer must be a power of two"
+    );
+
+    #[inline(always)]
+    const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_CAPACITY;
+        Self(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+
+    /// Returns a pointer to the slot that the provided free-running index refers to.
+    #[inline(always)]
+    fn slot(&self, index: usize) -> *mut T {
+        // SAFETY:
+        //  The masked index is always in bounds.
+        unsafe { (self.0.get() as *mut T).add(index & (N - 1)) }
+    }
+}
+
+/// A fixed-capacity ring buffer.
+///
+/// The buffer holds up to `N` elements. When it is full, [`push`](Self::push) applies the
+/// provided [`OverflowPolicy`].
+pub struct RingBuffer<T, const N: usize> {
+    slots: Slots<T, N>,
+    /// The free-running index of the oldest element.
+    head: usize,
+    /// The number of elements in the buffer.
+    len: usize,
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// Creates a new empty [`RingBuffer`].
+    pub const fn new() -> Self {
+        Self {
+            slots: Slots::new(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of elements in the buffer.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the buffer is empty.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether the buffer is full.
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Pushes an element at the end of the buffer.
+    ///
+    /// # Returns
+    ///
+    /// When the buffer is full, the element that has been dropped to apply `policy` is returned:
+    /// either `value` itself, or the oldest element of the buffer.
+    pub fn push(&mut self, value: T, policy: OverflowPolicy) -> Result<(), T> {
+        let evicted = match (self.is_full(), policy) {
+            (false, _) => None,
+            (true, OverflowPolicy::DropNewest) => return Err(value),
+            (true, OverflowPolicy::DropOldest) => self.pop(),
+        };
+
+        // SAFETY:
+        //  The buffer is not full, so the slot after the last element is free.
+        unsafe {
+            self.slots
+                .slot(self.head.wrapping_add(self.len))
+                .write(value)
+        };
+        self.len += 1;
+
+        match evicted {
+            Some(evicted) => Err(evicted),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the oldest element of the buffer.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // SAFETY:
+        //  The buffer is not empty, so its oldest slot is initialized. It is considered free
+        //  once the head has moved past it.
+        let value = unsafe { self.slots.slot(self.head).read() };
+        self.head = self.head.wrapping_add(1);
+        self.len -= 1;
+
+        Some(value)
+    }
+
+    /// Returns the oldest element of the buffer without removing it.
+    #[inline]
+    pub fn peek(&self) -> Option<&T> {
+        // SAFETY:
+        //  The buffer is not empty, so its oldest slot is initialized.
+        (!self.is_empty()).then(|| unsafe { &*self.slots.slot(self.head) })
+    }
+
+    /// Removes all the elements of the buffer.
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T, const N: usize> Default for RingBuffer<T, N> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for RingBuffer<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// A lock-free ring buffer with a single producer and a single consumer.
+///
+/// Elements that are pushed while the buffer is full are rejected: dropping the oldest element
+/// would require the producer to modify the state of the consumer.
+///
+/// No subsystem uses it yet. It is meant for the ones whose producer runs in an interrupt
+/// handler, such as tracing.
+#[allow(dead_code)]
+pub struct SpscRingBuffer<T, const N: usize> {
+    slots: Slots<T, N>,
+    /// The free-running index of the oldest element. Only modified by the consumer.
+    head: AtomicUsize,
+    /// The free-running index of the slot after the newest element. Only modified by the
+    /// producer.
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for SpscRingBuffer<T, N> {}
+unsafe impl<T: Send, const N: usize> Send for SpscRingBuffer<T, N> {}
+
+#[allow(dead_code)]
+impl<T, const N: usize> SpscRingBuffer<T, N> {
+    /// Creates a new empty [`SpscRingBuffer`].
+    pub const fn new() -> Self {
+        Self {
+            slots: Slots::new(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the maximum number of elements that the buffer can hold.
+    #[inline(always)]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of elements in the buffer.
+    ///
+    /// The result may be outdated as soon as it is returned.
+    #[inline]
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Acquire);
+        let head = self.head.load(Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    /// Returns whether the buffer is empty.
+    ///
+    /// The result may be outdated as soon as it is returned.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pushes an element at the end of the buffer.
+    ///
+    /// # Errors
+    ///
+    /// If the buffer is full, `value` is returned.
+    ///
+    /// # Safety
+    ///
+    /// This function must not be called concurrently with itself: there must be a single
+    /// producer.
+    pub unsafe fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Relaxed);
+        let head = self.head.load(Acquire);
+
+        if tail.wrapping_sub(head) == N {
+            return Err(value);
+        }
+
+        // SAFETY:
+        //  The slot is free, and the consumer does not read it until the tail moves past it.
+        unsafe { self.slots.slot(tail).write(value) };
+        self.tail.store(tail.wrapping_add(1), Release);
+
+        Ok(())
+    }
+
+    /// Removes the oldest element of the buffer.
+    ///
+    /// # Safety
+    ///
+    /// This function must not be called concurrently with itself: there must be a single
+    /// consumer.
+    pub unsafe fn pop(&self) -> Option<T> {
+        let head = self.head.load(Relaxed);
+        let tail = self.tail.load(Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        // SAFETY:
+        //  The slot has been initialized by the producer, which does not reuse it until the head
+        //  moves past it.
+        let value = unsafe { self.slots.slot(head).read() };
+        self.head.store(head.wrapping_add(1), Release);
+
+        Some(value)
+    }
+}
+
+impl<T, const N: usize> Default for SpscRingBuffer<T, N> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for SpscRingBuffer<T, N> {
+    fn drop(&mut self) {
+        // SAFETY:
+        //  We have exclusive access to the buffer.
+        while unsafe { self.pop() }.is_some() {}
+    }
+}
+
+/// A [`RingBuffer`] protected by a lock, which any number of producers may push to.
+///
+/// The lock is a spinlock: an execution context that holds it must not be interrupted by
+/// another one that uses the same buffer.
+pub struct MpscRingBuffer<T, const N: usize> {
+    lock: RawEpochMutex,
+    ring: UnsafeCell<RingBuffer<T, N>>,
+    policy: OverflowPolicy,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for MpscRingBuffer<T, N> {}
+unsafe impl<T: Send, const N: usize> Send for MpscRingBuffer<T, N> {}
+
+impl<T, const N: usize> MpscRingBuffer<T, N> {
+    /// Creates a new empty [`MpscRingBuffer`] that applies `policy` when it is full.
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            lock: RawEpochMutex::UNLOCKED,
+            ring: UnsafeCell::new(RingBuffer::new()),
+            policy,
+        }
+    }
+
+    /// Calls `f` with exclusive access to the inner [`RingBuffer`].
+    fn with<R>(&self, f: impl FnOnce(&mut RingBuffer<T, N>) -> R) -> R {
+        self.lock.lock();
+        // SAFETY:
+        //  We hold the lock.
+        let ret = f(unsafe { &mut *self.ring.get() });
+        // SAFETY:
+        //  We locked the mutex above.
+        unsafe { self.lock.unlock() };
+        ret
+    }
+
+    /// Pushes an element at the end of the buffer, applying the overflow policy of the buffer
+    /// if it is full.
+    ///
+    /// # Returns
+    ///
+    /// This function returns whether `value` has been added to the buffer.
+    pub fn push(&self, value: T) -> bool {
+        let policy = self.policy;
+        let dropped = self.with(|ring| ring.push(value, policy).err());
+
+        if dropped.is_none() {
+            return true;
+        }
+
+        // The dropped element is released outside of the lock.
+        drop(dropped);
+        policy == OverflowPolicy::DropOldest
+    }
+
+    /// Removes the oldest element of the buffer.
+    #[inline]
+    pub fn pop(&self) -> Option<T> {
+        self.with(|ring| ring.pop())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::thread;
+
+    use super::*;
+
+    /// Increments a counter when it is dropped.
+    struct DropCounter<'a>(&'a Cell<usize>);
+
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut ring = RingBuffer::<u32, 4>::new();
+
+        assert!(ring.is_empty());
+        assert_eq!(ring.len(), 0);
+        assert_eq!(ring.peek(), None);
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut ring = RingBuffer::<u32, 4>::new();
+
+        // Start close to the end of the free-running indices, so that both the slots and the
+        // indices wrap around.
+        ring.head = usize::MAX - 1;
+
+        for round in 0..3 {
+            for i in 0..3 {
+                assert_eq!(
+                    ring.push(round * 10 + i, OverflowPolicy::DropNewest),
+                    Ok(())
+                );
+            }
+            assert_eq!(ring.peek(), Some(&(round * 10)));
+            for i in 0..3 {
+                assert_eq!(ring.pop(), Some(round * 10 + i));
+            }
+            assert!(ring.is_empty());
+        }
+    }
+
+    #[test]
+    fn full_drop_newest() {
+        let mut ring = RingBuffer::<u32, 4>::new();
+
+        for i in 0..4 {
+            assert_eq!(ring.push(i, OverflowPolicy::DropNewest), Ok(()));
+        }
+        assert!(ring.is_full());
+        assert_eq!(ring.push(4, OverflowPolicy::DropNewest), Err(4));
+        assert_eq!(ring.len(), 4);
+
+        for i in 0..4 {
+            assert_eq!(ring.pop(), Some(i));
+        }
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn full_drop_oldest() {
+        let mut ring = RingBuffer::<u32, 4>::new();
+
+        for i in 0..4 {
+            assert_eq!(ring.push(i, OverflowPolicy::DropOldest), Ok(()));
+        }
+        assert_eq!(ring.push(4, OverflowPolicy::DropOldest), Err(0));
+        assert_eq!(ring.push(5, OverflowPolicy::DropOldest), Err(1));
+        assert_eq!(ring.len(), 4);
+
+        for i in 2..6 {
+            assert_eq!(ring.pop(), Some(i));
+        }
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn drop_remaining_elements() {
+        let dropped = Cell::new(0);
+
+        let mut ring = RingBuffer::<DropCounter, 4>::new();
+        for _ in 0..3 {
+            assert!(ring
+                .push(DropCounter(&dropped), OverflowPolicy::DropNewest)
+                .is_ok());
+        }
+        drop(ring.pop());
+        assert_eq!(dropped.get(), 1);
+
+        drop(ring);
+        assert_eq!(dropped.get(), 3);
+    }
+
+    #[test]
+    fn spsc_empty_and_full() {
+        let ring = SpscRingBuffer::<u32, 4>::new();
+
+        // SAFETY:
+        //  The test is both the only producer and the only consumer.
+        unsafe {
+            assert!(ring.is_empty());
+            assert_eq!(ring.pop(), None);
+
+            for i in 0..4 {
+                assert_eq!(ring.push(i), Ok(()));
+            }
+            assert_eq!(ring.len(), 4);
+            assert_eq!(ring.push(4), Err(4));
+
+            for i in 0..4 {
+                assert_eq!(ring.pop(), Some(i));
+            }
+            assert!(ring.is_empty());
+            assert_eq!(ring.pop(), None);
+        }
+    }
+
+    #[test]
+    fn spsc_wraparound() {
+        let ring = SpscRingBuffer::<u32, 4>::new();
+        ring.head.store(usize::MAX - 1, Relaxed);
+        ring.tail.store(usize::MAX - 1, Relaxed);
+
+        // SAFETY:
+        //  The test is both the only producer and the only consumer.
+        unsafe {
+            for i in 0..4 {
+                assert_eq!(ring.push(i), Ok(()));
+            }
+            assert_eq!(ring.push(4), Err(4));
+            for i in 0..4 {
+                assert_eq!(ring.pop(), Some(i));
+            }
+            assert_eq!(ring.pop(), None);
+        }
+    }
+
+    #[test]
+    fn spsc_ordering() {
+        const COUNT: usize = 10_000;
+
+        let ring = SpscRingBuffer::<usize, 8>::new();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..COUNT {
+                    // SAFETY:
+                    //  This thread is the only producer.
+                    while unsafe { ring.push(i) }.is_err() {
+                        thread::yield_now();
+                    }
+                }
+            });
+
+            s.spawn(|| {
+                let mut expected = 0;
+                while expected < COUNT {
+                    // SAFETY:
+                    //  This thread is the only consumer.
+                    match unsafe { ring.pop() } {
+                        Some(value) => {
+                            assert_eq!(value, expected);
+                            expected += 1;
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+            });
+        });
+
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn mpsc_overflow_policies() {
+        let newest = MpscRingBuffer::<u32, 2>::new(OverflowPolicy::DropNewest);
+        assert!(newest.push(0));
+        assert!(newest.push(1));
+        assert!(!newest.push(2));
+        assert_eq!(newest.pop(), Some(0));
+        assert_eq!(newest.pop(), Some(1));
+        assert_eq!(newest.pop(), None);
+
+        let oldest = MpscRingBuffer::<u32, 2>::new(OverflowPolicy::DropOldest);
+        assert!(oldest.push(0));
+        assert!(oldest.push(1));
+        assert!(oldest.push(2));
+        assert_eq!(oldest.pop(), Some(1));
+        assert_eq!(oldest.pop(), Some(2));
+        assert_eq!(oldest.pop(), None);
+    }
+}